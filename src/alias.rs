@@ -0,0 +1,133 @@
+// Expands the aliases defined as alias.<name> in the config. An alias given in place of a command
+// is replaced by the words of its value, eg. with `co = switch -c` `gitrs co topic` runs
+// `gitrs switch -c topic`, and the expansion may itself start with another alias. An alias whose
+// value starts with `!` runs the rest as a shell command from the top of the worktree instead, with
+// the arguments after it available as "$@". Like git, aliases can't shadow gitrs' own commands.
+use std::env;
+use std::process::Command;
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::config::Config;
+use crate::repository::Repository;
+use crate::trace::{self, trace};
+
+pub enum Expansion {
+    /// The arguments to run gitrs with, with every alias expanded
+    Args(Vec<String>),
+    /// A shell command to run with the given arguments
+    Shell(String, Vec<String>),
+}
+
+/// Expands the alias `args` (the arguments after the program name) start with, where
+/// `is_command` tells the names of real commands
+pub fn expand(
+    config: &Config,
+    mut args: Vec<String>,
+    is_command: impl Fn(&str) -> bool,
+) -> anyhow::Result<Expansion> {
+    // Options to gitrs itself come before the command
+    let Some(idx) = args.iter().position(|arg| !arg.starts_with('-')) else {
+        return Ok(Expansion::Args(args));
+    };
+
+    let mut expanded: Vec<String> = Vec::new();
+    while !is_command(&args[idx]) {
+        let name = args[idx].clone();
+        let Some(value) = config.get(&format!("alias.{}", name)) else {
+            break;
+        };
+        if let Some(start) = expanded.iter().position(|alias| *alias == name) {
+            let chain: String = expanded
+                .iter()
+                .enumerate()
+                .map(|(i, alias)| match i {
+                    _ if i == start => format!("\n  {} <==", alias),
+                    _ if i == expanded.len() - 1 => format!("\n  {} ==>", alias),
+                    _ => format!("\n  {}", alias),
+                })
+                .collect();
+            bail!(
+                "alias loop detected: expansion of '{}' does not terminate:{}",
+                expanded[0],
+                chain
+            );
+        }
+        expanded.push(name.clone());
+
+        if let Some(command) = value.strip_prefix('!') {
+            return Ok(Expansion::Shell(
+                command.to_string(),
+                args.split_off(idx + 1),
+            ));
+        }
+        let words = split_words(value).map_err(|e| anyhow!("bad alias.{} string: {}", name, e))?;
+        if words.is_empty() {
+            bail!("empty alias for {}", name);
+        }
+        trace!("alias expansion: {} => {}", name, trace::quote(&words));
+        args.splice(idx..=idx, words);
+    }
+    Ok(Expansion::Args(args))
+}
+
+/// Runs the shell alias `command` with `args`, from the top of the worktree if there is one,
+/// returning its exit status. GIT_PREFIX is set to where it was run from, relative to the top.
+pub fn run_shell(command: &str, args: &[String]) -> anyhow::Result<i32> {
+    // The arguments are passed as positional parameters, so they aren't parsed by the shell
+    let script = match args.is_empty() {
+        true => command.to_string(),
+        false => format!("{} \"$@\"", command),
+    };
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(&script).arg(command).args(args);
+    if let Some(repository) = Repository::find_repository_at(&env::current_dir()?) {
+        let prefix = repository.relative_to_worktree(".".as_ref())?;
+        let prefix = match prefix.as_str() {
+            "" | "." => String::new(),
+            prefix => format!("{}/", prefix),
+        };
+        shell
+            .current_dir(&repository.worktree)
+            .env("GIT_PREFIX", prefix);
+    }
+
+    trace!(
+        "run_command: {}",
+        trace::quote(&[vec![script], args.to_vec()].concat())
+    );
+    let status = shell
+        .status()
+        .with_context(|| format!("Couldn't run alias '{}'", command))?;
+    // There is no status when the shell was killed by a signal
+    Ok(status.code().unwrap_or(128))
+}
+
+// Splits an alias into words the way a shell would, honoring quotes and backslashes but nothing
+// else
+fn split_words(value: &str) -> anyhow::Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (Some(open), c) if c == open => quote = None,
+            (None | Some('"'), '\\') => {
+                let escaped = chars.next().context("cmdline ends with \\")?;
+                word.get_or_insert_default().push(escaped);
+            }
+            (_, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        bail!("unclosed quote");
+    }
+    words.extend(word);
+    Ok(words)
+}
@@ -0,0 +1,847 @@
+// Applies unified diffs, as produced by `git diff` or `diff -u`, to files in the current
+// directory. Hunks are located at the line numbers given in their header, or the nearest place
+// the surrounding context matches exactly. Unless rejects are allowed, a patch is only written if
+// every hunk in it applies. Binary files are patched by git diff --binary's `GIT binary patch`
+// sections, which are only applied to the content whose hash the patch names.
+//
+// The extended header lines of git diffs are followed too: a file can be renamed, change its mode
+// or become a symlink, whose target is patched as its content, with or without changes to it. With
+// --3way, a patch is applied to the version of the file its index line names, which then is merged
+// into the file like merge does, leaving conflict markers where they overlap. As gitrs apply only
+// works on the worktree, the conflicts are only in the files, not in the index as well like git.
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+
+use crate::binary_patch::{self, BinaryPatch};
+use crate::config::Config;
+use crate::line_diff::Whitespace;
+use crate::merge;
+use crate::merge_file::{self, ConflictStyle, Labels};
+use crate::object::GitrsObject;
+use crate::object::blob::Blob;
+use crate::object::tree::{self, SYMLINK_MODE};
+use crate::refs::ZERO_HASH;
+use crate::repository::Repository;
+
+#[derive(Clone, Copy, PartialEq)]
+enum LineKind {
+    Context,
+    Removed,
+    Added,
+}
+
+struct HunkLine {
+    kind: LineKind,
+    // Includes the line terminator, unless the line is marked as lacking one
+    text: String,
+}
+
+struct Hunk {
+    old_start: usize,
+    old_count: usize,
+    new_start: usize,
+    new_count: usize,
+    lines: Vec<HunkLine>,
+}
+
+/// The changes a patch makes to a single file. A missing path is a file being created or deleted.
+/// When both paths are present, the new one names the file being patched, since diffs between
+/// unrelated files (eg. `diff -u f.orig f`) are common, unless the patch renames the old one.
+#[derive(Default)]
+pub struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    /// Whether the old path is renamed to the new one
+    rename: bool,
+    /// The modes of both versions, where a git diff gives them
+    old_mode: Option<String>,
+    new_mode: Option<String>,
+    /// The blob hashes of both versions, as the index line has them
+    hashes: Option<(String, String)>,
+    hunks: Vec<Hunk>,
+    /// Patches binary files instead of the hunks
+    binary: Option<Binary>,
+}
+
+// A binary file's patch, which can't be applied without the full hashes of both versions
+struct Binary {
+    // None for a patch only saying that the files differ
+    patch: Option<BinaryPatch>,
+}
+
+// What the header of a git diff says about the file it is for, until its patch is parsed
+#[derive(Default)]
+struct GitHeader {
+    // Whether a `diff --git` line started a header
+    started: bool,
+    patch: FilePatch,
+}
+
+pub struct ApplyOptions {
+    /// Only check that the patches apply
+    pub check: bool,
+    /// Apply the patches in reverse
+    pub reverse: bool,
+    /// Apply the hunks that can be applied, writing the others to `<file>.rej`
+    pub reject: bool,
+    /// Report each file as it is checked or applied
+    pub verbose: bool,
+    /// Merge the patch into the files it doesn't apply to, from the versions it was made from
+    pub three_way: bool,
+}
+
+// The outcome of applying a file patch in memory
+struct Applied {
+    path: PathBuf,
+    // None if the file is deleted
+    content: Option<Vec<u8>>,
+    // The mode the file is written with
+    mode: String,
+    // The file the patch renames, which is deleted
+    renamed: Option<PathBuf>,
+    rejected: Vec<usize>,
+    // Whether merging the patch left conflicts
+    conflicts: bool,
+}
+
+/// Parses a unified diff that may touch several files
+pub fn parse(patch: &str, strip: usize) -> anyhow::Result<Vec<FilePatch>> {
+    let mut lines = patch.split_inclusive('\n').peekable();
+    let mut patches = Vec::new();
+    let mut header = GitHeader::default();
+
+    while let Some(line) = lines.next() {
+        // A header not followed by changes to the content, eg. only changing the mode
+        if line.starts_with("diff --git ") && header.started {
+            patches.push(std::mem::take(&mut header).patch);
+        }
+        if let Some(binary) = header.parse_line(line, strip) {
+            let patch = match binary {
+                true => Some(binary_patch::parse(&mut lines)?),
+                false => None,
+            };
+            let mut file_patch = std::mem::take(&mut header).patch;
+            file_patch.binary = Some(Binary { patch });
+            patches.push(file_patch);
+            continue;
+        }
+        let Some(old) = line.strip_prefix("--- ") else {
+            continue;
+        };
+        let Some(new) = lines.next_if(|line| line.starts_with("+++ ")) else {
+            continue;
+        };
+
+        let mut file_patch = std::mem::take(&mut header).patch;
+        file_patch.old_path = parse_path(old, strip);
+        file_patch.new_path = parse_path(&new[4..], strip);
+
+        while let Some(header) = lines.next_if(|line| line.starts_with("@@ ")) {
+            let mut hunk = parse_hunk_header(header)
+                .with_context(|| format!("Malformed hunk header: {}", header.trim_end()))?;
+
+            let (mut old_left, mut new_left) = (hunk.old_count, hunk.new_count);
+            while old_left > 0 || new_left > 0 {
+                let Some(line) = lines.next() else {
+                    bail!("Patch ends in the middle of a hunk");
+                };
+                // Some tools drop the space of empty context lines
+                let (kind, text) = match line.split_at_checked(1) {
+                    Some((" ", text)) => (LineKind::Context, text),
+                    Some(("-", text)) => (LineKind::Removed, text),
+                    Some(("+", text)) => (LineKind::Added, text),
+                    _ if line.trim_end_matches(['\r', '\n']).is_empty() => {
+                        (LineKind::Context, line)
+                    }
+                    _ => bail!("Malformed hunk line: {}", line.trim_end()),
+                };
+                if kind != LineKind::Added {
+                    old_left = old_left
+                        .checked_sub(1)
+                        .context("Hunk is longer than its header")?;
+                }
+                if kind != LineKind::Removed {
+                    new_left = new_left
+                        .checked_sub(1)
+                        .context("Hunk is longer than its header")?;
+                }
+                hunk.lines.push(HunkLine {
+                    kind,
+                    text: text.to_string(),
+                });
+
+                // `\ No newline at end of file` applies to the line before it
+                if lines.next_if(|line| line.starts_with('\\')).is_some()
+                    && let Some(last) = hunk.lines.last_mut()
+                {
+                    last.text.truncate(last.text.trim_end_matches('\n').len());
+                }
+            }
+
+            file_patch.hunks.push(hunk);
+        }
+
+        patches.push(file_patch);
+    }
+    if header.started {
+        patches.push(header.patch);
+    }
+
+    if patches.is_empty() {
+        bail!("No valid patches in input");
+    }
+    Ok(patches)
+}
+
+/// Applies the patches, returning false if any of them (or any of their hunks) didn't apply, or
+/// merging one left conflicts. Merging with `three_way` takes blobs from `repository`.
+pub fn apply(
+    repository: Option<&Repository>,
+    patches: Vec<FilePatch>,
+    options: &ApplyOptions,
+) -> anyhow::Result<bool> {
+    let style = match repository {
+        Some(repository) => merge::conflict_style(&Config::load(repository)?),
+        None => ConflictStyle::Merge,
+    };
+    let mut results = Vec::new();
+    let mut clean = true;
+
+    for mut patch in patches {
+        if options.reverse {
+            patch.reverse();
+        }
+        let display = patch.display_path().to_string();
+        if options.verbose {
+            eprintln!("Checking patch {}...", patch.describe());
+        }
+
+        let merged = match repository.filter(|_| options.three_way) {
+            Some(repository) => patch.merge_in_memory(repository, style)?,
+            None => None,
+        };
+        let applied = match (&patch.binary, merged) {
+            (_, Some(merged)) => {
+                match merged.conflicts {
+                    true => eprintln!("Applied patch to '{}' with conflicts.", display),
+                    false => eprintln!("Applied patch to '{}' cleanly.", display),
+                }
+                merged
+            }
+            (Some(binary), None) => match patch.apply_binary(binary) {
+                Ok(applied) => applied,
+                Err(e) => {
+                    clean = false;
+                    eprintln!("error: {}", e);
+                    eprintln!("error: {}: patch does not apply", display);
+                    continue;
+                }
+            },
+            (None, None) => match patch.apply_in_memory() {
+                Ok(applied) => applied,
+                Err(e) => {
+                    clean = false;
+                    eprintln!("error: {}", e);
+                    continue;
+                }
+            },
+        };
+        if !applied.rejected.is_empty() {
+            clean = false;
+            let first = &patch.hunks[applied.rejected[0]];
+            eprintln!("error: patch failed: {}:{}", display, first.old_start);
+            if !options.reject {
+                eprintln!("error: {}: patch does not apply", display);
+            }
+        }
+        results.push((patch, applied));
+    }
+
+    if options.check || (!clean && !options.reject) {
+        return Ok(clean);
+    }
+
+    let mut conflicts = Vec::new();
+    for (patch, applied) in results {
+        let display = patch.display_path();
+        if options.verbose || !applied.rejected.is_empty() {
+            match applied.rejected.len() {
+                0 => eprintln!("Applied patch {} cleanly.", patch.describe()),
+                1 => eprintln!("Applying patch {} with 1 reject...", display),
+                n => eprintln!("Applying patch {} with {} rejects...", display, n),
+            }
+        }
+
+        if let Some(renamed) = &applied.renamed {
+            fs::remove_file(renamed)
+                .with_context(|| format!("Failed to delete {}", renamed.display()))?;
+        }
+        match &applied.content {
+            Some(content) => {
+                if let Some(parent) = applied.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                tree::replace_worktree_file(content, &applied.path, &applied.mode)
+                    .with_context(|| format!("Failed to write {}", applied.path.display()))?;
+            }
+            None => fs::remove_file(&applied.path)
+                .with_context(|| format!("Failed to delete {}", applied.path.display()))?,
+        }
+        if applied.conflicts {
+            conflicts.push(display.to_string());
+        }
+
+        if !applied.rejected.is_empty() {
+            let mut rej = format!(
+                "diff a/{} b/{}\t(rejected hunks)\n",
+                patch.old_path.as_deref().unwrap_or(display),
+                patch.new_path.as_deref().unwrap_or(display)
+            );
+            for &idx in &applied.rejected {
+                eprintln!("Rejected hunk #{}.", idx + 1);
+                rej.push_str(&patch.hunks[idx].to_string());
+            }
+            fs::write(format!("{}.rej", display), rej)?;
+        }
+    }
+    for path in &conflicts {
+        eprintln!("U {}", path);
+    }
+
+    Ok(clean && conflicts.is_empty())
+}
+
+impl FilePatch {
+    fn display_path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("")
+    }
+
+    // Names the file in progress messages, as `old => new` for a rename
+    fn describe(&self) -> String {
+        match (self.rename, &self.old_path) {
+            (true, Some(old)) => format!("{} => {}", old, self.display_path()),
+            _ => self.display_path().to_string(),
+        }
+    }
+
+    fn reverse(&mut self) {
+        std::mem::swap(&mut self.old_path, &mut self.new_path);
+        std::mem::swap(&mut self.old_mode, &mut self.new_mode);
+        if let Some((old, new)) = &mut self.hashes {
+            std::mem::swap(old, new);
+        }
+        if let Some(patch) = self
+            .binary
+            .as_mut()
+            .and_then(|binary| binary.patch.as_mut())
+        {
+            std::mem::swap(&mut patch.forward, &mut patch.reverse);
+        }
+        for hunk in &mut self.hunks {
+            std::mem::swap(&mut hunk.old_start, &mut hunk.new_start);
+            std::mem::swap(&mut hunk.old_count, &mut hunk.new_count);
+            for line in &mut hunk.lines {
+                line.kind = match line.kind {
+                    LineKind::Removed => LineKind::Added,
+                    LineKind::Added => LineKind::Removed,
+                    LineKind::Context => LineKind::Context,
+                };
+            }
+        }
+    }
+
+    // The file the patch reads, which is the one it renames if it renames one
+    fn source_path(&self) -> PathBuf {
+        match (self.rename, &self.old_path) {
+            (true, Some(old)) => PathBuf::from(old),
+            _ => PathBuf::from(self.display_path()),
+        }
+    }
+
+    // Reads the file the patch applies to, as its content or a symlink's target, checking that
+    // it is of the type the patch expects, and returns it with the mode to write the result with
+    fn read_current(&self) -> anyhow::Result<(Vec<u8>, String)> {
+        let source = self.source_path();
+        let display = source.display();
+        let check_new = || -> anyhow::Result<()> {
+            let path = PathBuf::from(self.display_path());
+            if tree::worktree_mode(&path)?.is_some() {
+                bail!("{}: already exists in working directory", path.display());
+            }
+            Ok(())
+        };
+        let Some(_) = &self.old_path else {
+            check_new()?;
+            let mode = self.new_mode.as_deref().unwrap_or("100644");
+            return Ok((Vec::new(), mode.to_string()));
+        };
+
+        let mode =
+            tree::worktree_mode(&source)?.with_context(|| format!("{}: No such file", display))?;
+        if self.rename {
+            check_new()?;
+        }
+        if let Some(expected) = &self.old_mode {
+            if (expected == SYMLINK_MODE) != (mode == SYMLINK_MODE) {
+                bail!("{}: wrong type", display);
+            }
+            if expected != mode {
+                eprintln!(
+                    "warning: {} has type {}, expected {}",
+                    display, mode, expected
+                );
+            }
+        }
+        let content = tree::read_worktree_file(&source, mode)?
+            .with_context(|| format!("{}: No such file", display))?;
+        let mode = self.new_mode.as_deref().unwrap_or(mode);
+        Ok((content, mode.to_string()))
+    }
+
+    fn apply_in_memory(&self) -> anyhow::Result<Applied> {
+        let (current, mode) = self.read_current()?;
+        let current = String::from_utf8(current)
+            .with_context(|| format!("{}: is not a text file", self.source_path().display()))?;
+        let (result, rejected) = self.apply_hunks(&current);
+        self.applied(result.into_bytes(), mode, rejected, false)
+    }
+
+    // Applies the patch to the version of the file it was made from, and merges that into the
+    // file, returning None if the repository doesn't have that version or the patch doesn't apply
+    // to it. Patches creating files or patching binary ones aren't merged.
+    fn merge_in_memory(
+        &self,
+        repository: &Repository,
+        style: ConflictStyle,
+    ) -> anyhow::Result<Option<Applied>> {
+        let Some((old_hash, _)) = self.hashes.as_ref().filter(|_| self.binary.is_none()) else {
+            return Ok(None);
+        };
+        if self.old_path.is_none() || self.hunks.is_empty() {
+            return Ok(None);
+        }
+        let Some(base) = GitrsObject::find(repository, old_hash)
+            .and_then(|hash| Blob::read(repository, &hash))
+            .ok()
+        else {
+            eprintln!("error: repository lacks the necessary blob to perform 3-way merge.");
+            eprintln!("Falling back to direct application...");
+            return Ok(None);
+        };
+        let Ok(base) = std::str::from_utf8(base.data()) else {
+            return Ok(None);
+        };
+        let (theirs, rejected) = self.apply_hunks(base);
+        if !rejected.is_empty() {
+            return Ok(None);
+        }
+
+        let (current, mode) = self.read_current()?;
+        let labels = Labels {
+            base: "base",
+            ours: "ours",
+            theirs: "theirs",
+        };
+        let contents = [base.as_bytes(), &current, theirs.as_bytes()];
+        let Some(merged) = merge_file::merge(contents, &labels, style, Whitespace::Exact) else {
+            return Ok(None);
+        };
+        self.applied(merged.content, mode, Vec::new(), merged.conflicts > 0)
+            .map(Some)
+    }
+
+    // Applies the hunks that apply to `current`, returning the result and which hunks didn't
+    fn apply_hunks(&self, current: &str) -> (String, Vec<usize>) {
+        let lines: Vec<&str> = current.split_inclusive('\n').collect();
+        let mut result = String::new();
+        let mut rejected = Vec::new();
+        // Position in `lines` up to which the file has been copied to `result`
+        let mut pos = 0;
+        // How far hunks have been found from where their header says they are
+        let mut offset: isize = 0;
+
+        for (idx, hunk) in self.hunks.iter().enumerate() {
+            let preimage = hunk.preimage();
+            // An empty preimage is inserted after line `old_start`
+            let expected = if hunk.old_count == 0 {
+                hunk.old_start
+            } else {
+                hunk.old_start.saturating_sub(1)
+            };
+            let expected = expected.saturating_add_signed(offset);
+
+            match find_preimage(&lines, &preimage, pos, expected) {
+                Some(found) => {
+                    lines[pos..found]
+                        .iter()
+                        .for_each(|line| result.push_str(line));
+                    hunk.postimage()
+                        .iter()
+                        .for_each(|line| result.push_str(line));
+                    pos = found + preimage.len();
+                    offset += found as isize - expected as isize;
+                }
+                None => rejected.push(idx),
+            }
+        }
+        lines[pos..].iter().for_each(|line| result.push_str(line));
+        (result, rejected)
+    }
+
+    // What applying the patch leaves, given the content it results in
+    fn applied(
+        &self,
+        result: Vec<u8>,
+        mode: String,
+        rejected: Vec<usize>,
+        conflicts: bool,
+    ) -> anyhow::Result<Applied> {
+        let path = PathBuf::from(self.display_path());
+        let content = match &self.new_path {
+            Some(_) => Some(result),
+            None if result.is_empty() => None,
+            None => bail!("{}: removal patch leaves file contents", path.display()),
+        };
+        Ok(Applied {
+            path,
+            content,
+            mode,
+            renamed: self.rename.then(|| self.source_path()),
+            rejected,
+            conflicts,
+        })
+    }
+
+    // Applies a binary patch, failing unless the file has the content the patch is for, so that
+    // the result is known to be what the patch names too
+    fn apply_binary(&self, binary: &Binary) -> anyhow::Result<Applied> {
+        let display = self.display_path();
+        let full = |hash: &str| hash.len() == ZERO_HASH.len();
+        let (Some(patch), Some((old_hash, new_hash))) = (
+            binary.patch.as_ref(),
+            self.hashes
+                .as_ref()
+                .filter(|(old, new)| full(old) && full(new)),
+        ) else {
+            bail!(
+                "cannot apply binary patch to '{}' without full index line",
+                display
+            );
+        };
+        let Some(hunk) = &patch.forward else {
+            bail!(
+                "cannot reverse-apply a binary patch without the reverse hunk to '{}'",
+                display
+            );
+        };
+
+        let (current, mode) = self.read_current()?;
+        let hash = blob_hash(&current);
+        if self.old_path.is_some() && hash != *old_hash {
+            bail!(
+                "the patch applies to '{}' ({}), which does not match the current contents.",
+                display,
+                hash
+            );
+        }
+        let result = hunk
+            .apply(&current)
+            .with_context(|| format!("binary patch does not apply to '{}'", display))?;
+        if self.new_path.is_some() && blob_hash(&result) != *new_hash {
+            bail!(
+                "binary patch to '{}' creates incorrect result (expecting {}, got {})",
+                display,
+                new_hash,
+                blob_hash(&result)
+            );
+        }
+        self.applied(result, mode, Vec::new(), false)
+    }
+}
+
+impl GitHeader {
+    // Notes what a line of the header says, returning Some if it starts a binary file's patch:
+    // true for a `GIT binary patch`, and false for a line only saying that the files differ
+    fn parse_line(&mut self, line: &str, strip: usize) -> Option<bool> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let patch = &mut self.patch;
+        // The paths of renames are given without the a/ and b/ prefixes
+        let rename_path = |path: &str| parse_path(path, strip.saturating_sub(1));
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            *self = Self {
+                started: true,
+                ..Self::default()
+            };
+            if let Some((old, new)) = paths.split_once(" b/") {
+                self.patch.old_path = parse_path(old, strip);
+                self.patch.new_path = parse_path(&format!("b/{}", new), strip);
+            }
+        } else if let Some(mode) = line.strip_prefix("new file mode ") {
+            patch.old_path = None;
+            patch.new_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            patch.new_path = None;
+            patch.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            patch.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            patch.new_mode = Some(mode.to_string());
+        } else if let Some(path) = line.strip_prefix("rename from ") {
+            patch.old_path = rename_path(path);
+            patch.rename = true;
+        } else if let Some(path) = line.strip_prefix("rename to ") {
+            patch.new_path = rename_path(path);
+            patch.rename = true;
+        } else if let Some(index) = line.strip_prefix("index ") {
+            // The mode is only given here when it didn't change
+            let (hashes, mode) = index.split_once(' ').unwrap_or((index, ""));
+            patch.hashes = hashes
+                .split_once("..")
+                .map(|(old, new)| (old.to_string(), new.to_string()));
+            if !mode.is_empty() {
+                patch.old_mode.get_or_insert_with(|| mode.to_string());
+                patch.new_mode.get_or_insert_with(|| mode.to_string());
+            }
+        } else if line == "GIT binary patch" {
+            return Some(true);
+        } else if line.starts_with("Binary files ") && line.ends_with(" differ") {
+            return Some(false);
+        }
+        None
+    }
+}
+
+// The hash of a blob with `content`
+fn blob_hash(content: &[u8]) -> String {
+    GitrsObject::BlobObject(Blob::new(content.to_vec())).hash()
+}
+
+impl Hunk {
+    // The lines the hunk expects to find
+    fn preimage(&self) -> Vec<&str> {
+        self.image_without(LineKind::Added)
+    }
+
+    // The lines the hunk leaves in their place
+    fn postimage(&self) -> Vec<&str> {
+        self.image_without(LineKind::Removed)
+    }
+
+    fn image_without(&self, excluded: LineKind) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| line.kind != excluded)
+            .map(|line| line.text.as_str())
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Hunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_count, self.new_start, self.new_count
+        )?;
+        for line in &self.lines {
+            let prefix = match line.kind {
+                LineKind::Context => ' ',
+                LineKind::Removed => '-',
+                LineKind::Added => '+',
+            };
+            write!(f, "{}{}", prefix, line.text)?;
+            if !line.text.ends_with('\n') {
+                writeln!(f, "\n\\ No newline at end of file")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds where `preimage` occurs in `lines` at or after `min`, as close to `expected` as possible
+fn find_preimage(lines: &[&str], preimage: &[&str], min: usize, expected: usize) -> Option<usize> {
+    let max = lines.len().checked_sub(preimage.len())?;
+    let matches_at = |start: usize| lines[start..start + preimage.len()] == *preimage;
+    let expected = expected.clamp(min, max.max(min));
+
+    (0..=lines.len()).find_map(|distance| {
+        [
+            expected.checked_add(distance),
+            expected.checked_sub(distance),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|&start| start >= min && start <= max && matches_at(start))
+    })
+}
+
+// Parses the path from a `---` or `+++` line, dropping any timestamp and the leading components
+fn parse_path(line: &str, strip: usize) -> Option<String> {
+    let path = line.trim_end_matches(['\r', '\n']);
+    let path = path.split('\t').next().unwrap_or(path);
+    if path == "/dev/null" {
+        return None;
+    }
+
+    let components: Vec<&str> = path.split('/').collect();
+    Some(components[strip.min(components.len() - 1)..].join("/"))
+}
+
+// Parses `@@ -old_start[,old_count] +new_start[,new_count] @@`
+fn parse_hunk_header(header: &str) -> Option<Hunk> {
+    let ranges = header.strip_prefix("@@ -")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(" +")?;
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = parse_range(old)?;
+    let (new_start, new_count) = parse_range(new)?;
+
+    Some(Hunk {
+        old_start,
+        old_count,
+        new_start,
+        new_count,
+        lines: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    const OPTIONS: ApplyOptions = ApplyOptions {
+        check: false,
+        reverse: false,
+        reject: false,
+        verbose: false,
+        three_way: false,
+    };
+
+    // A directory for the files a test patches, as patches name files in it by absolute path
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("gitrs-apply-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // What git diff -M prints for renaming f to g, retargeting the symlink l from f to m, and
+    // making m executable, with the paths under `dir`
+    fn extended_patch(dir: &str) -> String {
+        format!(
+            "diff --git a/{dir}/f b/{dir}/g\n\
+             similarity index 100%\n\
+             rename from {dir}/f\n\
+             rename to {dir}/g\n\
+             diff --git a/{dir}/l b/{dir}/l\n\
+             index 4d1ae35..08b9811 120000\n\
+             --- a/{dir}/l\n\
+             +++ b/{dir}/l\n\
+             @@ -1 +1 @@\n\
+             -f\n\
+             \\ No newline at end of file\n\
+             +m\n\
+             \\ No newline at end of file\n\
+             diff --git a/{dir}/m b/{dir}/m\n\
+             old mode 100644\n\
+             new mode 100755\n"
+        )
+    }
+
+    #[test]
+    fn parses_extended_headers() {
+        let patches = parse(&extended_patch("d"), 1).unwrap();
+        let [rename, symlink, mode] = patches.as_slice() else {
+            panic!("Expected three patches");
+        };
+        assert!(rename.rename && rename.hunks.is_empty());
+        assert_eq!(rename.old_path.as_deref(), Some("d/f"));
+        assert_eq!(rename.new_path.as_deref(), Some("d/g"));
+        assert_eq!(symlink.old_mode.as_deref(), Some(SYMLINK_MODE));
+        assert_eq!(symlink.new_mode.as_deref(), Some(SYMLINK_MODE));
+        assert_eq!(symlink.hunks.len(), 1);
+        assert!(!mode.rename && mode.hunks.is_empty());
+        assert_eq!(mode.old_mode.as_deref(), Some("100644"));
+        assert_eq!(mode.new_mode.as_deref(), Some("100755"));
+    }
+
+    #[test]
+    fn applies_extended_headers() {
+        let dir = directory("extended");
+        fs::write(dir.join("f"), "1\n2\n").unwrap();
+        fs::write(dir.join("m"), "x\n").unwrap();
+        std::os::unix::fs::symlink("f", dir.join("l")).unwrap();
+        let patch = extended_patch(&dir.to_string_lossy());
+
+        assert!(apply(None, parse(&patch, 1).unwrap(), &OPTIONS).unwrap());
+        assert!(!dir.join("f").exists());
+        assert_eq!(fs::read_to_string(dir.join("g")).unwrap(), "1\n2\n");
+        assert_eq!(fs::read_link(dir.join("l")).unwrap(), PathBuf::from("m"));
+        let mode = fs::metadata(dir.join("m")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o100, 0o100);
+
+        // Only a symlink takes a symlink's patch, and the file a rename is from must exist
+        let reverse = ApplyOptions {
+            reverse: true,
+            ..OPTIONS
+        };
+        fs::remove_file(dir.join("l")).unwrap();
+        fs::write(dir.join("l"), "m").unwrap();
+        assert!(!apply(None, parse(&patch, 1).unwrap(), &reverse).unwrap());
+        fs::remove_file(dir.join("g")).unwrap();
+        assert!(!apply(None, parse(&patch, 1).unwrap(), &reverse).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merges_with_three_way() {
+        let worktree = directory("three-way");
+        let repository = Repository::init(&worktree).unwrap();
+        let base = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let hash = GitrsObject::BlobObject(Blob::new(base.as_bytes().to_vec()))
+            .write(&repository)
+            .unwrap();
+        let path = worktree.join("f").to_string_lossy().into_owned();
+        // Changing 2 to two, as made from the blob above
+        let patch = format!(
+            "diff --git a/{path} b/{path}\n\
+             index {}..0123456 100644\n\
+             --- a/{path}\n\
+             +++ b/{path}\n\
+             @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n",
+            &hash[..7]
+        );
+        let three_way = ApplyOptions {
+            three_way: true,
+            ..OPTIONS
+        };
+
+        // What git apply --3way leaves where the context changed, and where the same lines did
+        fs::write(&path, base.replace('8', "eight").replace('5', "five")).unwrap();
+        assert!(apply(Some(&repository), parse(&patch, 1).unwrap(), &three_way).unwrap());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1\ntwo\n3\n4\nfive\n6\n7\neight\n9\n"
+        );
+        fs::write(&path, base.replace('2', "TWO")).unwrap();
+        assert!(!apply(Some(&repository), parse(&patch, 1).unwrap(), &three_way).unwrap());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1\n<<<<<<< ours\nTWO\n=======\ntwo\n>>>>>>> theirs\n3\n4\n5\n6\n7\n8\n9\n"
+        );
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+// Resolves gitattributes for worktree paths. Attributes are read from the `.gitattributes` file in
+// each directory leading up to a path, and from `info/attributes` in the repository. Files closer
+// to the path take precedence, and `info/attributes` overrides everything.
+use std::collections::HashMap;
+use std::fs;
+
+use indexmap::{IndexMap, IndexSet};
+
+use crate::repository::Repository;
+use crate::wildmatch::path_matches;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttrValue {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+impl std::fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttrValue::Set => write!(f, "set"),
+            AttrValue::Unset => write!(f, "unset"),
+            AttrValue::Value(value) => write!(f, "{value}"),
+            AttrValue::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+struct AttrRule {
+    pattern: String,
+    // Directory (relative to the worktree) of the file the rule was read from, empty for the root
+    // and for `info/attributes`
+    base: String,
+    attrs: Vec<(String, AttrValue)>,
+}
+
+pub struct Attributes<'a> {
+    repository: &'a Repository,
+    // Rules read from each directory's `.gitattributes`, keyed by directory
+    dir_rules: HashMap<String, Vec<AttrRule>>,
+    info_rules: Vec<AttrRule>,
+    macros: HashMap<String, Vec<(String, AttrValue)>>,
+    // Attribute names in the order they were first seen, which is the order git reports them in
+    names: IndexSet<String>,
+}
+
+impl<'a> Attributes<'a> {
+    pub fn new(repository: &'a Repository) -> Self {
+        let mut attributes = Self {
+            repository,
+            dir_rules: HashMap::new(),
+            info_rules: Vec::new(),
+            macros: HashMap::from([(
+                "binary".to_string(),
+                vec![
+                    ("diff".to_string(), AttrValue::Unset),
+                    ("merge".to_string(), AttrValue::Unset),
+                    ("text".to_string(), AttrValue::Unset),
+                ],
+            )]),
+            names: ["binary", "diff", "merge", "text"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        };
+
+        // Macros may only be defined at the top level, so load the root file eagerly
+        attributes.rules_for_dir("");
+
+        if let Some(content) = repository
+            .get_path_to_file(&["info", "attributes"])
+            .and_then(|path| fs::read_to_string(path).ok())
+        {
+            attributes.info_rules = attributes.parse(&content, "");
+        }
+
+        attributes
+    }
+
+    /// Resolves every attribute specified for `path` (relative to the worktree, `/` separated)
+    pub fn check(&mut self, path: &str) -> IndexMap<String, AttrValue> {
+        let mut dirs = vec![String::new()];
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for i in 1..=components.len() {
+            dirs.push(components[..i].join("/"));
+        }
+
+        dirs.iter().for_each(|dir| self.rules_for_dir(dir));
+        let rules = dirs
+            .iter()
+            .flat_map(|dir| self.dir_rules[dir].iter())
+            .chain(self.info_rules.iter());
+
+        // Rules are visited in increasing order of precedence, so later matches simply overwrite
+        // earlier ones
+        let mut result = IndexMap::new();
+        for rule in rules.filter(|rule| Self::rule_matches(rule, path)) {
+            for (name, value) in &rule.attrs {
+                result.insert(name.clone(), value.clone());
+                if value == &AttrValue::Set
+                    && let Some(expansion) = self.macros.get(name)
+                {
+                    result.extend(expansion.iter().cloned());
+                }
+            }
+        }
+
+        result.sort_by(|a, _, b, _| self.names.get_index_of(a).cmp(&self.names.get_index_of(b)));
+        result
+    }
+
+    // Loads and caches the rules from `dir/.gitattributes`
+    fn rules_for_dir(&mut self, dir: &str) {
+        if self.dir_rules.contains_key(dir) {
+            return;
+        }
+
+        let path = self.repository.worktree.join(dir).join(".gitattributes");
+        let rules = fs::read_to_string(path)
+            .map(|content| self.parse(&content, dir))
+            .unwrap_or_default();
+        self.dir_rules.insert(dir.to_string(), rules);
+    }
+
+    fn parse(&mut self, content: &str, base: &str) -> Vec<AttrRule> {
+        let mut rules = Vec::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (pattern, rest) = Self::split_pattern(line);
+            let attrs: Vec<_> = rest.split_whitespace().map(Self::parse_attr).collect();
+
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                self.names.insert(name.to_string());
+            }
+            self.names
+                .extend(attrs.iter().map(|(attr_name, _)| attr_name.clone()));
+
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                if base.is_empty() {
+                    self.macros.insert(name.to_string(), attrs);
+                } else {
+                    eprintln!(
+                        "warning: [attr]{} not allowed in {}/.gitattributes",
+                        name, base
+                    );
+                }
+            } else if pattern.starts_with('!') {
+                eprintln!(
+                    "warning: Negative patterns are ignored in git attributes: {}",
+                    pattern
+                );
+            } else {
+                rules.push(AttrRule {
+                    pattern,
+                    base: base.to_string(),
+                    attrs,
+                });
+            }
+        }
+
+        rules
+    }
+
+    // Splits a line into its (possibly quoted) pattern and the attribute list following it
+    fn split_pattern(line: &str) -> (String, &str) {
+        if let Some(quoted) = line.strip_prefix('"')
+            && let Some(end) = quoted.find('"')
+        {
+            return (quoted[..end].to_string(), &quoted[end + 1..]);
+        }
+
+        match line.split_once(char::is_whitespace) {
+            Some((pattern, rest)) => (pattern.to_string(), rest),
+            None => (line.to_string(), ""),
+        }
+    }
+
+    fn parse_attr(token: &str) -> (String, AttrValue) {
+        if let Some(name) = token.strip_prefix('-') {
+            (name.to_string(), AttrValue::Unset)
+        } else if let Some(name) = token.strip_prefix('!') {
+            (name.to_string(), AttrValue::Unspecified)
+        } else if let Some((name, value)) = token.split_once('=') {
+            (name.to_string(), AttrValue::Value(value.to_string()))
+        } else {
+            (token.to_string(), AttrValue::Set)
+        }
+    }
+
+    fn rule_matches(rule: &AttrRule, path: &str) -> bool {
+        // Directory patterns never apply to the files within them
+        !rule.pattern.ends_with('/') && path_matches(&rule.pattern, &rule.base, path)
+    }
+}
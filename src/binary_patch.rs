@@ -0,0 +1,246 @@
+// Writes and reads the `GIT binary patch` sections git diff --binary puts in place of hunks for
+// binary files. A section has a hunk making the new content from the old and one making the old
+// content back from the new. Each hunk is either the content itself (`literal <size>`) or a delta
+// from the other side (`delta <size>`), whichever is smaller once deflated, then encoded in
+// base85 on lines of up to 52 bytes, each starting with a letter giving how many.
+use std::io::{Read, Write};
+use std::iter::Peekable;
+
+use anyhow::{Context, bail, ensure};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::delta;
+
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+// The most bytes on a line
+const LINE_BYTES: usize = 52;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Method {
+    Literal,
+    Delta,
+}
+
+pub struct BinaryHunk {
+    pub method: Method,
+    /// The content, or the delta making it, inflated
+    pub data: Vec<u8>,
+}
+
+/// The hunks making the new content from the old and the old back from the new, either of which
+/// may be missing once the patch is reversed
+pub struct BinaryPatch {
+    pub forward: Option<BinaryHunk>,
+    pub reverse: Option<BinaryHunk>,
+}
+
+/// Formats the section turning `old` into `new`, compressing its hunks at `level`
+pub fn format(old: &[u8], new: &[u8], level: Compression) -> anyhow::Result<Vec<u8>> {
+    let mut patch = b"GIT binary patch\n".to_vec();
+    write_hunk(&mut patch, old, new, level)?;
+    write_hunk(&mut patch, new, old, level)?;
+    Ok(patch)
+}
+
+// Writes the hunk making `new` from `old`
+fn write_hunk(
+    patch: &mut Vec<u8>,
+    old: &[u8],
+    new: &[u8],
+    level: Compression,
+) -> anyhow::Result<()> {
+    let literal = deflate(new, level)?;
+    let delta = match delta::create(old, new, Some(literal.len())) {
+        Some(delta) => Some((delta.len(), deflate(&delta, level)?)),
+        None => None,
+    };
+    let (header, data) = match delta {
+        Some((size, delta)) if delta.len() < literal.len() => (format!("delta {}\n", size), delta),
+        _ => (format!("literal {}\n", new.len()), literal),
+    };
+
+    patch.extend(header.as_bytes());
+    for line in data.chunks(LINE_BYTES) {
+        patch.push(match line.len() as u8 {
+            length @ 1..=26 => b'A' + length - 1,
+            length => b'a' + length - 27,
+        });
+        encode_85(patch, line);
+        patch.push(b'\n');
+    }
+    patch.push(b'\n');
+    Ok(())
+}
+
+/// Parses the hunks after a `GIT binary patch` line
+pub fn parse<'a>(
+    lines: &mut Peekable<impl Iterator<Item = &'a str>>,
+) -> anyhow::Result<BinaryPatch> {
+    let forward = parse_hunk(lines)?.context("unrecognized binary patch")?;
+    Ok(BinaryPatch {
+        forward: Some(forward),
+        reverse: parse_hunk(lines)?,
+    })
+}
+
+// Parses a hunk up to the empty line ending it, if a hunk starts here
+fn parse_hunk<'a>(
+    lines: &mut Peekable<impl Iterator<Item = &'a str>>,
+) -> anyhow::Result<Option<BinaryHunk>> {
+    let Some(header) =
+        lines.next_if(|line| line.starts_with("literal ") || line.starts_with("delta "))
+    else {
+        return Ok(None);
+    };
+    let corrupt = |line: &str| format!("corrupt binary patch: {}", line.trim_end());
+    let (method, size) = header
+        .trim_end()
+        .split_once(' ')
+        .expect("Checked for a space");
+    let method = match method {
+        "literal" => Method::Literal,
+        _ => Method::Delta,
+    };
+    let size: usize = size.parse().with_context(|| corrupt(header))?;
+
+    let mut deflated = Vec::new();
+    for line in lines.by_ref() {
+        let encoded = line.trim_end_matches(['\r', '\n']).as_bytes();
+        let Some((&length, encoded)) = encoded.split_first() else {
+            break;
+        };
+        let length = match length {
+            b'A'..=b'Z' => length - b'A' + 1,
+            b'a'..=b'z' => length - b'a' + 27,
+            _ => bail!(corrupt(line)),
+        } as usize;
+        let most = encoded.len() / 5 * 4;
+        ensure!(length <= most && length + 4 > most, corrupt(line));
+        decode_85(&mut deflated, encoded, length).with_context(|| corrupt(line))?;
+    }
+
+    let mut data = Vec::new();
+    ZlibDecoder::new(&deflated[..])
+        .read_to_end(&mut data)
+        .with_context(|| corrupt(header))?;
+    ensure!(data.len() == size, corrupt(header));
+    Ok(Some(BinaryHunk { method, data }))
+}
+
+impl BinaryHunk {
+    /// Makes the content the hunk is for from the content on the other side
+    pub fn apply(&self, old: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self.method {
+            Method::Literal => Ok(self.data.clone()),
+            Method::Delta => delta::apply(old, &self.data),
+        }
+    }
+}
+
+fn deflate(data: &[u8], level: Compression) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+// Each group of 4 bytes, the last one padded with zeros, is written as 5 base85 digits
+fn encode_85(out: &mut Vec<u8>, data: &[u8]) {
+    for group in data.chunks(4) {
+        let mut value = group
+            .iter()
+            .chain([0; 4].iter())
+            .take(4)
+            .fold(0u32, |value, &byte| value << 8 | byte as u32);
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85[(value % 85) as usize];
+            value /= 85;
+        }
+        out.extend(digits);
+    }
+}
+
+// Decodes the first `length` bytes of `encoded`
+fn decode_85(out: &mut Vec<u8>, encoded: &[u8], mut length: usize) -> anyhow::Result<()> {
+    for group in encoded.chunks_exact(5) {
+        if length == 0 {
+            break;
+        }
+        let mut value = 0u32;
+        for &digit in group {
+            let Some(digit) = BASE85.iter().position(|&c| c == digit) else {
+                bail!("invalid base85 alphabet {}", digit as char);
+            };
+            value = value
+                .checked_mul(85)
+                .and_then(|value| value.checked_add(digit as u32))
+                .context("invalid base85 sequence")?;
+        }
+        let bytes = length.min(4);
+        out.extend(&value.to_be_bytes()[..bytes]);
+        length -= bytes;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The patch git diff --binary makes from 1024 bytes to the same with 10 bytes overwritten and
+    // 48 inserted
+    const PATCH: &str = "GIT binary patch\n\
+                         delta 74\n\
+                         kcmZqR*uXI%g{uM%Hs-xzG|0>=PAw`)O(B-v7_ov80EY%24*&oF\n\
+                         \n\
+                         delta 20\n\
+                         YcmdnM(ZI2ZF@kY%1S1$vj$m8?06z!?+W-In\n\
+                         \n";
+
+    fn versions() -> (Vec<u8>, Vec<u8>) {
+        let old: Vec<u8> = (0..=255).cycle().take(1024).collect();
+        let mut new = old.clone();
+        new[100..110].fill(b'x');
+        new.splice(600..600, b"inserted".repeat(6));
+        (old, new)
+    }
+
+    #[test]
+    fn format_matches_git() {
+        let (old, new) = versions();
+        let patch = format(&old, &new, Compression::fast()).unwrap();
+        assert_eq!(String::from_utf8(patch).unwrap(), PATCH);
+    }
+
+    #[test]
+    fn parse_and_apply() {
+        let (old, new) = versions();
+        let mut lines = PATCH.lines().skip(1).peekable();
+        let patch = parse(&mut lines).unwrap();
+        assert_eq!(patch.forward.unwrap().apply(&old).unwrap(), new);
+        assert_eq!(patch.reverse.unwrap().apply(&new).unwrap(), old);
+    }
+
+    #[test]
+    fn base85() {
+        let data = [0, 1, 2, 3, 0xff, 0xfe, 0xfd];
+        let mut encoded = Vec::new();
+        encode_85(&mut encoded, &data);
+        assert_eq!(encoded, b"009C6|Ni{|");
+        let mut decoded = Vec::new();
+        decode_85(&mut decoded, &encoded, data.len()).unwrap();
+        assert_eq!(decoded, data);
+        assert!(decode_85(&mut Vec::new(), b"009C\"", 4).is_err());
+        // Five digits can overflow four bytes
+        assert!(decode_85(&mut Vec::new(), b"~~~~~", 4).is_err());
+    }
+
+    #[test]
+    fn corrupt_lines() {
+        let mut lines = "literal 3\nZcmZ?\n\n".lines().peekable();
+        assert!(parse(&mut lines).is_err());
+    }
+}
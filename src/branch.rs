@@ -0,0 +1,400 @@
+// Branch helpers: the branch HEAD is on, the ones it was on before, and the upstream a branch
+// tracks. A branch's upstream is recorded in branch.<name>.remote and branch.<name>.merge, where
+// the merge value names the branch on the remote and a remote of `.` stands for the local
+// repository. Setting an upstream finds which branch of which remote a remote-tracking branch
+// stands for through the remote's fetch refspecs, as git does. Previous branches come from the `checkout: moving from <old> to <new>` entries git
+// writes to the HEAD reflog. Deleting a branch is refused while it is checked out, and unless
+// forced, while it has commits that would be lost.
+use std::fs;
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::config::{self, Config};
+use crate::ref_filter;
+use crate::refs::{self, Ref, RefNameOptions, RefUpdate};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::worktree::Worktree;
+
+/// Checks that `name` can be used for a new branch
+pub fn verify_name(name: &str) -> anyhow::Result<()> {
+    // git reserves HEAD, and names that look like options would be confusing to pass around
+    if name == "HEAD"
+        || name.starts_with('-')
+        || !refs::check_name(&format!("refs/heads/{}", name), &RefNameOptions::default())
+    {
+        bail!("'{}' is not a valid branch name", name);
+    }
+    Ok(())
+}
+
+/// Returns the name of the branch HEAD is attached to (eg. `master`), or None if it is detached
+pub fn current(repository: &Repository) -> anyhow::Result<Option<String>> {
+    Ok(Ref::read_symbolic(repository, "HEAD")?
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string)))
+}
+
+/// Returns the full name of the ref `branch` tracks (eg. `refs/remotes/origin/master`), if an
+/// upstream is configured
+pub fn upstream(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    Some(match remote {
+        "." => merge.to_string(),
+        remote => format!(
+            "refs/remotes/{}/{}",
+            remote,
+            merge.strip_prefix("refs/heads/").unwrap_or(merge)
+        ),
+    })
+}
+
+/// A branch another can track, as branch.<name>.remote and branch.<name>.merge record it
+pub struct Upstream {
+    pub remote: String,
+    pub merge: String,
+    /// The name git reports it by, eg. `origin/master` or `master`
+    pub name: String,
+}
+
+/// Finds the local or remote-tracking branch `name` refers to, to be tracked by another. Returns
+/// None if it refers to something else, like a tag or a commit, or to nothing.
+pub fn find_upstream(
+    repository: &Repository,
+    config: &Config,
+    name: &str,
+) -> anyhow::Result<Option<Upstream>> {
+    let full_name = if name == "HEAD" || name == "@" {
+        current(repository)?.map(|branch| format!("refs/heads/{}", branch))
+    } else if let Some(previous) = expand_previous(repository, name)? {
+        Some(format!("refs/heads/{}", previous))
+    } else if let Some((branch, suffix)) = name.rsplit_once("@{")
+        && ["u}", "upstream}"].contains(&suffix.to_lowercase().as_str())
+    {
+        let branch = match branch {
+            "" | "HEAD" => current(repository)?.context("HEAD does not point to a branch")?,
+            branch => branch.to_string(),
+        };
+        upstream(config, &branch)
+    } else {
+        // Like git, a tag of the same name is preferred to a branch
+        let mut found = None;
+        for candidate in [
+            name.to_string(),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+        ] {
+            if candidate.starts_with("refs/") && Ref::try_resolve(repository, &candidate)?.is_some()
+            {
+                found = Some(candidate);
+                break;
+            }
+        }
+        found
+    };
+    let Some(full_name) = full_name else {
+        return Ok(None);
+    };
+    if Ref::try_resolve(repository, &full_name)?.is_none() {
+        return Ok(None);
+    }
+
+    if let Some(branch) = full_name.strip_prefix("refs/heads/") {
+        return Ok(Some(Upstream {
+            remote: ".".to_string(),
+            merge: full_name.clone(),
+            name: branch.to_string(),
+        }));
+    }
+    let Some(short) = full_name.strip_prefix("refs/remotes/") else {
+        return Ok(None);
+    };
+    for (key, value) in config.entries() {
+        let (Some(remote), Some(refspec)) = (
+            key.strip_prefix("remote.")
+                .and_then(|rest| rest.strip_suffix(".fetch")),
+            value,
+        ) else {
+            continue;
+        };
+        if let Some(merge) = fetched_from(refspec, &full_name) {
+            return Ok(Some(Upstream {
+                remote: remote.to_string(),
+                merge,
+                name: short.to_string(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+// Maps the ref `name` a fetch refspec such as `+refs/heads/*:refs/remotes/origin/*` writes to back
+// to the ref on the remote it comes from
+fn fetched_from(refspec: &str, name: &str) -> Option<String> {
+    let (source, destination) = refspec.trim_start_matches('+').split_once(':')?;
+    match (source.split_once('*'), destination.split_once('*')) {
+        (Some((source_prefix, source_suffix)), Some((prefix, suffix))) => {
+            let matched = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{}{}{}", source_prefix, matched, source_suffix))
+        }
+        (None, None) if destination == name => Some(source.to_string()),
+        _ => None,
+    }
+}
+
+/// Records `upstream` as the branch `branch` tracks, in the repository's config
+pub fn set_upstream(
+    repository: &Repository,
+    branch: &str,
+    upstream: &Upstream,
+) -> anyhow::Result<()> {
+    let path = repository.get_path(&["config"]);
+    config::set_value(
+        &path,
+        &format!("branch.{}.remote", branch),
+        &upstream.remote,
+    )?;
+    config::set_value(&path, &format!("branch.{}.merge", branch), &upstream.merge)
+}
+
+/// Describes how `branch`, at `hash`, compares with its upstream as branch -v shows it, eg.
+/// `[ahead 1, behind 2]`, or with `named` set as -vv does, eg. `[origin/master: ahead 1]`. Returns
+/// None if there is no upstream, or nothing to say about one that isn't named.
+pub fn describe_upstream(
+    repository: &Repository,
+    config: &Config,
+    branch: &str,
+    hash: &str,
+    named: bool,
+) -> anyhow::Result<Option<String>> {
+    let Some(upstream) = upstream(config, branch) else {
+        return Ok(None);
+    };
+    let track = match Ref::try_resolve(repository, &upstream)? {
+        None => "gone".to_string(),
+        Some(upstream_hash) => match revwalk::ahead_behind(repository, hash, &upstream_hash)? {
+            (0, 0) => String::new(),
+            (ahead, 0) => format!("ahead {}", ahead),
+            (0, behind) => format!("behind {}", behind),
+            (ahead, behind) => format!("ahead {}, behind {}", ahead, behind),
+        },
+    };
+    let name = ref_filter::shorten_ref_name(&upstream);
+    Ok(match (named, track.is_empty()) {
+        (true, true) => Some(format!("[{}]", name)),
+        (true, false) => Some(format!("[{}: {}]", name, track)),
+        (false, true) => None,
+        (false, false) => Some(format!("[{}]", track)),
+    })
+}
+
+/// Resolves `<branch>@{upstream}` (or `@{u}`) to the hash its upstream points to, where an empty
+/// branch means the current one. Returns None if `name` doesn't use the suffix.
+pub fn resolve_upstream(repository: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    let Some((branch, suffix)) = name.rsplit_once("@{") else {
+        return Ok(None);
+    };
+    if !["u}", "upstream}"].contains(&suffix.to_lowercase().as_str()) {
+        return Ok(None);
+    }
+
+    let branch = match branch {
+        "" | "HEAD" => current(repository)?.context("HEAD does not point to a branch")?,
+        branch => branch.to_string(),
+    };
+    if Ref::try_resolve(repository, &format!("refs/heads/{}", branch))?.is_none() {
+        bail!("No such branch: '{}'", branch);
+    }
+
+    let config = Config::load(repository)?;
+    let upstream = upstream(&config, &branch)
+        .ok_or_else(|| anyhow!("No upstream configured for branch '{}'", branch))?;
+    Ref::try_resolve(repository, &upstream)?
+        .map(Some)
+        .with_context(|| format!("Upstream branch '{}' does not exist", upstream))
+}
+
+/// Returns the branch (or commit hash, if HEAD was detached) checked out before the `n`th most
+/// recent checkout, if there were that many
+pub fn previous(repository: &Repository, n: usize) -> anyhow::Result<Option<String>> {
+    let (Some(skip), Ok(log)) = (
+        n.checked_sub(1),
+        fs::read_to_string(repository.gitdir.join("logs").join("HEAD")),
+    ) else {
+        return Ok(None);
+    };
+    Ok(log
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let (_, message) = line.split_once('\t')?;
+            let (from, _) = message
+                .strip_prefix("checkout: moving from ")?
+                .split_once(" to ")?;
+            Some(from.to_string())
+        })
+        .nth(skip))
+}
+
+/// Expands `@{-<n>}` to the name of the `n`th previously checked out branch (or commit hash).
+/// Returns None if `name` doesn't use the syntax.
+pub fn expand_previous(repository: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    let Some(Ok(n)) = name
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .map(str::parse::<usize>)
+    else {
+        return Ok(None);
+    };
+    previous(repository, n)?
+        .map(Some)
+        .with_context(|| format!("Fewer than {} branches were checked out before", n))
+}
+
+/// Resolves `@{-<n>}` to the hash of the `n`th previously checked out branch or commit. Returns
+/// None if `name` doesn't use the syntax.
+pub fn resolve_previous(repository: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+    let Some(previous) = expand_previous(repository, name)? else {
+        return Ok(None);
+    };
+    if previous.len() == 40 && previous.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(Some(previous));
+    }
+    Ref::try_resolve(repository, &format!("refs/heads/{}", previous))?
+        .map(Some)
+        .with_context(|| format!("Previous branch '{}' no longer exists", previous))
+}
+
+/// Deletes the branch, returning the hash it pointed to. Unless `force` is set, the branch has to
+/// be merged into its upstream, or into HEAD if it has none.
+pub fn delete(
+    repository: &Repository,
+    config: &Config,
+    name: &str,
+    force: bool,
+) -> anyhow::Result<String> {
+    let full_name = format!("refs/heads/{}", name);
+    let hash = Ref::try_resolve(repository, &full_name)?
+        .with_context(|| format!("branch '{}' not found.", name))?;
+    if let Some(worktree) = Worktree::list(repository)?
+        .into_iter()
+        .find(|worktree| worktree.head.trim() == format!("ref: {}", full_name))
+    {
+        bail!(
+            "Cannot delete branch '{}' checked out at '{}'",
+            name,
+            worktree.path.display()
+        );
+    }
+
+    if !force {
+        let merged_into = match upstream(config, name) {
+            Some(upstream) => Ref::try_resolve(repository, &upstream)?,
+            None => None,
+        };
+        let merged_into = match merged_into {
+            Some(hash) => Some(hash),
+            None => Ref::try_resolve(repository, "HEAD")?,
+        };
+        let merged = match merged_into {
+            Some(target) => revwalk::is_ancestor(repository, &hash, &target)?,
+            None => false,
+        };
+        if !merged {
+            bail!(
+                "The branch '{}' is not fully merged.\n\
+                 If you are sure you want to delete it, run 'gitrs branch -D {}'.",
+                name,
+                name
+            );
+        }
+    }
+
+    Ref::transaction(
+        repository,
+        &[RefUpdate {
+            name: full_name,
+            new: None,
+            old: Some(hash.clone()),
+            verify_only: false,
+            deref: false,
+        }],
+    )?;
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_upstreams_like_git() {
+        let worktree = std::env::temp_dir().join(format!("gitrs-upstream-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let commit = "a".repeat(40);
+        for name in ["heads/master", "remotes/origin/main", "tags/v1"] {
+            let mut path = vec!["refs"];
+            path.extend(name.split('/'));
+            Ref::create_at(&repository, &commit, &path).unwrap();
+        }
+        let path = repository.get_path(&["config"]);
+        config::set_value(&path, "remote.origin.url", "/tmp/origin").unwrap();
+        config::set_value(
+            &path,
+            "remote.origin.fetch",
+            "+refs/heads/*:refs/remotes/origin/*",
+        )
+        .unwrap();
+        let config = Config::load(&repository).unwrap();
+        let find = |name: &str| {
+            find_upstream(&repository, &config, name)
+                .unwrap()
+                .map(|upstream| (upstream.remote, upstream.merge, upstream.name))
+        };
+
+        // As git 2.39 branch --track records them
+        let local = Some((
+            ".".to_string(),
+            "refs/heads/master".to_string(),
+            "master".to_string(),
+        ));
+        assert_eq!(find("master"), local);
+        assert_eq!(find("HEAD"), local);
+        let remote = Some((
+            "origin".to_string(),
+            "refs/heads/main".to_string(),
+            "origin/main".to_string(),
+        ));
+        assert_eq!(find("origin/main"), remote);
+        assert_eq!(find("refs/remotes/origin/main"), remote);
+        assert_eq!(find("v1"), None);
+        assert_eq!(find(&commit), None);
+        assert_eq!(find("nope"), None);
+
+        set_upstream(
+            &repository,
+            "master",
+            &find_upstream(&repository, &config, "origin/main")
+                .unwrap()
+                .unwrap(),
+        )
+        .unwrap();
+        let config = Config::load(&repository).unwrap();
+        assert_eq!(
+            upstream(&config, "master").as_deref(),
+            Some("refs/remotes/origin/main")
+        );
+        // The commits don't exist, so only a gone upstream can be described
+        let describe = |named| describe_upstream(&repository, &config, "master", &commit, named);
+        Ref::delete_at(&repository, &["refs", "remotes", "origin", "main"]).unwrap();
+        assert_eq!(
+            describe(true).unwrap().as_deref(),
+            Some("[origin/main: gone]")
+        );
+        assert_eq!(describe(false).unwrap().as_deref(), Some("[gone]"));
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+// Batch mode for cat-file: object names are read from the stdin, one per line, and a header
+// (optionally followed by the object's contents) is written back for each. The header format
+// understands %(objectname), %(objecttype), %(objectsize) and %(rest), the text after the first
+// whitespace of the input line. Without %(rest) the whole line is the object name.
+use std::io::{self, BufRead, Write};
+
+use anyhow::{anyhow, bail};
+
+use crate::object::GitrsObject;
+use crate::repository::Repository;
+
+pub const DEFAULT_BATCH_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// Answers every object name on the stdin, printing the contents after each header if `contents`
+/// is set. Names that don't resolve to an object are reported as `<name> missing`.
+pub fn batch(repository: &Repository, format: &str, contents: bool) -> anyhow::Result<()> {
+    let mut stdout = io::stdout().lock();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        // The line is only split when the format asks for the rest of it
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if format.contains("%(rest)") => (name, rest.trim_start()),
+            _ => (line.as_str(), ""),
+        };
+
+        let object = GitrsObject::find(repository, name)
+            .and_then(|hash| Ok((GitrsObject::read(repository, &hash)?, hash)));
+        let Ok((mut object, hash)) = object else {
+            writeln!(stdout, "{} missing", name)?;
+            stdout.flush()?;
+            continue;
+        };
+
+        let data = object.serialize();
+        let header = expand(
+            format,
+            &hash,
+            &object.get_type().to_string(),
+            data.len(),
+            rest,
+        )?;
+        writeln!(stdout, "{}", header)?;
+        if contents {
+            stdout.write_all(&data)?;
+            writeln!(stdout)?;
+        }
+        // Flushed after every object, so that callers can wait for each answer
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+fn expand(
+    format: &str,
+    hash: &str,
+    object_type: &str,
+    size: usize,
+    rest: &str,
+) -> anyhow::Result<String> {
+    let mut output = String::new();
+    let mut remaining = format;
+
+    while let Some(idx) = remaining.find("%(") {
+        output.push_str(&remaining[..idx]);
+        let after = &remaining[idx + 2..];
+        let end = after
+            .find(')')
+            .ok_or_else(|| anyhow!("Malformed format string: {}", format))?;
+        match &after[..end] {
+            "objectname" => output.push_str(hash),
+            "objecttype" => output.push_str(object_type),
+            "objectsize" => output.push_str(&size.to_string()),
+            "rest" => output.push_str(rest),
+            atom => bail!("Unknown format element: {}", atom),
+        }
+        remaining = &after[end + 1..];
+    }
+    output.push_str(remaining);
+
+    Ok(output)
+}
@@ -0,0 +1,180 @@
+// Removes untracked files from the worktree. Without an index, the files recorded by the commit
+// HEAD points to are the ones considered tracked.
+use std::collections::HashSet;
+use std::fs;
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::ignore::Ignore;
+use crate::object::tree::Tree;
+use crate::pathspec::Pathspec;
+use crate::refs::Ref;
+use crate::repository::Repository;
+
+pub struct CleanOptions {
+    /// Only report what would be removed
+    pub dry_run: bool,
+    /// Also remove untracked directories
+    pub directories: bool,
+    /// Also remove ignored files
+    pub include_ignored: bool,
+    /// Only remove the paths it selects
+    pub pathspec: Pathspec,
+}
+
+struct Cleaner<'a> {
+    repository: &'a Repository,
+    options: &'a CleanOptions,
+    ignore: Ignore<'a>,
+    tracked_files: HashSet<String>,
+    // Every directory containing a tracked file
+    tracked_dirs: HashSet<String>,
+    removed: Vec<String>,
+}
+
+/// Removes the untracked files under `dir` (relative to the worktree, `/` separated), returning
+/// the removed paths in the order they were visited. Removed directories end with a `/`.
+pub fn clean(
+    repository: &Repository,
+    config: &Config,
+    dir: &str,
+    options: &CleanOptions,
+) -> anyhow::Result<Vec<String>> {
+    let tracked_files: HashSet<String> = match head_commit(repository)? {
+        Some(hash) => Tree::of_commit(repository, &hash)?
+            .files(repository)?
+            .into_iter()
+            .collect(),
+        None => HashSet::new(),
+    };
+    let tracked_dirs = tracked_files
+        .iter()
+        .flat_map(|file| {
+            file.match_indices('/')
+                .map(|(idx, _)| file[..idx].to_string())
+        })
+        .collect();
+
+    let mut cleaner = Cleaner {
+        repository,
+        options,
+        ignore: Ignore::new(repository, config),
+        tracked_files,
+        tracked_dirs,
+        removed: Vec::new(),
+    };
+    cleaner.clean_dir(dir)?;
+    Ok(cleaner.removed)
+}
+
+// Resolves HEAD, returning None if the current branch has no commits yet
+fn head_commit(repository: &Repository) -> anyhow::Result<Option<String>> {
+    let head_path = repository
+        .get_path_to_file(&["HEAD"])
+        .context("Couldn't find HEAD")?;
+    let head = fs::read_to_string(head_path)?;
+
+    match head.trim().strip_prefix("ref: ") {
+        Some(target) => Ref::try_resolve(repository, target),
+        None => Ok(Some(head.trim().to_string())),
+    }
+}
+
+impl Cleaner<'_> {
+    fn clean_dir(&mut self, dir: &str) -> anyhow::Result<()> {
+        let mut entries: Vec<_> = fs::read_dir(self.repository.worktree.join(dir))
+            .with_context(|| format!("Failed to read directory {}", dir))?
+            .collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == ".gitrs" {
+                continue;
+            }
+
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+
+            if entry.file_type()?.is_dir() {
+                self.clean_untracked_dir(&path)?;
+            } else if self.options.pathspec.matches(&path, false)
+                && !self.tracked_files.contains(&path)
+                && (self.options.include_ignored || !self.ignore.is_ignored(&path, false))
+            {
+                self.remove(&path, false)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clean_untracked_dir(&mut self, path: &str) -> anyhow::Result<()> {
+        let pathspec = &self.options.pathspec;
+        if !pathspec.matches(path, true) && !pathspec.could_match_below(path) {
+            return Ok(());
+        }
+        if self.tracked_dirs.contains(path) {
+            return self.clean_dir(path);
+        }
+
+        // Nested repositories are left alone
+        if !self.options.directories || self.repository.worktree.join(path).join(".gitrs").exists()
+        {
+            return Ok(());
+        }
+
+        // Only what's selected goes when the pathspec names paths inside the directory
+        if !pathspec.matches(path, true) {
+            return self.clean_dir(path);
+        }
+        if self.options.include_ignored {
+            return self.remove(path, true);
+        }
+        if self.ignore.is_ignored(path, true) {
+            return Ok(());
+        }
+
+        // Only remove the whole directory if nothing inside it has to be kept
+        if self.contains_ignored(path)? {
+            self.clean_dir(path)
+        } else {
+            self.remove(path, true)
+        }
+    }
+
+    fn contains_ignored(&mut self, dir: &str) -> anyhow::Result<bool> {
+        for entry in fs::read_dir(self.repository.worktree.join(dir))? {
+            let entry = entry?;
+            let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            let is_dir = entry.file_type()?.is_dir();
+            if self.ignore.is_ignored(&path, is_dir) || (is_dir && self.contains_ignored(&path)?) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn remove(&mut self, path: &str, is_dir: bool) -> anyhow::Result<()> {
+        if !self.options.dry_run {
+            let full_path = self.repository.worktree.join(path);
+            if is_dir {
+                fs::remove_dir_all(&full_path)
+            } else {
+                fs::remove_file(&full_path)
+            }
+            .with_context(|| format!("Failed to remove {}", path))?;
+        }
+
+        self.removed.push(if is_dir {
+            format!("{}/", path)
+        } else {
+            path.to_string()
+        });
+        Ok(())
+    }
+}
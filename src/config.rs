@@ -0,0 +1,652 @@
+// Reads git-style configuration files. Values from the user's global config (~/.gitrsconfig) are
+// loaded first, and are overridden by the repository's own config. A file can pull in others with
+// include.path, or with includeIf.<condition>.path when the condition holds: `gitdir:<pattern>`
+// (or `gitdir/i:` ignoring case) for repositories whose gitdir matches, and `onbranch:<pattern>`
+// for those on a matching branch. Included entries take effect where the include is.
+//
+// Values are written the way git config writes them: setting a key changes the line that last set
+// it, and a new key goes at the end of the last section it belongs in, or in a new section at the
+// end of the file. The rest of the file, comments and all, is left as it is.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::branch;
+use crate::repository::Repository;
+use crate::wildmatch::wildmatch;
+
+// Same as git, to stop include cycles
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+pub struct Config {
+    // Entries in the order they were read, keyed by their normalized name (see `normalize_key`).
+    // A `None` value is a key without a `=`, which counts as boolean true.
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Config {
+    /// Loads the global config followed by the config of the given repository
+    pub fn load(repository: &Repository) -> anyhow::Result<Self> {
+        let mut config = Self {
+            entries: Vec::new(),
+        };
+        if let Some(global) = Self::global_path() {
+            config.read_file(&global, Some(repository), 0)?;
+        }
+        if let Some(local) = repository.get_path_to_file(&["config"]) {
+            config.read_file(&local, Some(repository), 0)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Loads only the global config, for commands that can run outside of a repository
+    pub fn load_global() -> anyhow::Result<Self> {
+        let mut config = Self {
+            entries: Vec::new(),
+        };
+        if let Some(global) = Self::global_path() {
+            config.read_file(&global, None, 0)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the last value set for `key`, eg. `core.fileMode` or `remote.origin.url`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_deref().unwrap_or("true"))
+    }
+
+    /// Returns every value set for a multi-valued `key`, in order. Like git, an empty value clears
+    /// the ones set before it.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        let mut values = Vec::new();
+        for (_, value) in self.entries.iter().filter(|(name, _)| *name == key) {
+            match value.as_deref() {
+                Some("") => values.clear(),
+                value => values.push(value.unwrap_or("true")),
+            }
+        }
+        values
+    }
+
+    /// Lists every entry in the order read, with None for keys without a value
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    pub fn get_bool(&self, key: &str) -> anyhow::Result<Option<bool>> {
+        self.get(key)
+            .map(|value| parse_bool(key, value))
+            .transpose()
+    }
+
+    /// The user's global config file, whether or not there is one yet
+    pub fn global_file() -> anyhow::Result<PathBuf> {
+        let home = env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .context("$HOME not set")?;
+        Ok(Path::new(&home).join(".gitrsconfig"))
+    }
+
+    fn global_path() -> Option<PathBuf> {
+        Self::global_file().ok().filter(|path| path.is_file())
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        repository: Option<&Repository>,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            bail!(
+                "exceeded maximum include depth ({}) while including {}",
+                MAX_INCLUDE_DEPTH,
+                path.display()
+            );
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let entries =
+            parse(&content).with_context(|| format!("Bad config file: {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        for (name, value) in entries {
+            let include = match (name.strip_prefix("includeif."), &value) {
+                (_, Some(value)) if name == "include.path" => Some(value.clone()),
+                (Some(rest), Some(value)) => match rest.strip_suffix(".path") {
+                    Some(condition) if include_applies(condition, dir, repository)? => {
+                        Some(value.clone())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            };
+            self.entries.push((name, value));
+            if let Some(include) = include {
+                let included = dir.join(expand_home(&include));
+                // Like git, includes of files that don't exist are ignored
+                if included.is_file() {
+                    self.read_file(&included, repository, depth + 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets `key` to `value` in the config file at `path`, replacing the value it was last set to
+pub fn set_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    edit(path, |lines| {
+        let normalized = normalize_key(key);
+        match lines.iter().rposition(|line| line.sets(&normalized)) {
+            Some(i) => lines[i].text = format_entry(key, value),
+            None => insert(lines, key, value),
+        }
+    })
+}
+
+/// Adds another value for the multi-valued `key` to the config file at `path`
+pub fn add_value(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+    edit(path, |lines| insert(lines, key, value))
+}
+
+/// Removes the lines setting `key` from the config file at `path`, or only those setting it to
+/// `value`, returning whether there were any
+pub fn unset_value(path: &Path, key: &str, value: Option<&str>) -> anyhow::Result<bool> {
+    let mut removed = false;
+    edit(path, |lines| {
+        let key = normalize_key(key);
+        lines.retain(|line| {
+            let matches = line.sets(&key)
+                && value.is_none_or(|value| {
+                    line.entry.as_ref().and_then(|(_, set)| set.as_deref()) == Some(value)
+                });
+            removed |= matches;
+            !matches
+        });
+    })?;
+    Ok(removed)
+}
+
+// A line of a config file, with the section it is in and the entry it holds, if any
+struct Line {
+    text: String,
+    section: Option<String>,
+    entry: Option<(String, Option<String>)>,
+}
+
+impl Line {
+    fn sets(&self, key: &str) -> bool {
+        self.entry.as_ref().is_some_and(|(name, _)| name == key)
+    }
+}
+
+// Rewrites the config file at `path` with what `change` makes of its lines, through a lock file
+fn edit(path: &Path, change: impl FnOnce(&mut Vec<Line>)) -> anyhow::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    parse(&content).with_context(|| format!("Bad config file: {}", path.display()))?;
+
+    let mut section = None;
+    let mut lines = Vec::new();
+    for text in content.lines() {
+        let trimmed = text.trim_start();
+        let mut entry = None;
+        if let Some(header) = trimmed
+            .strip_prefix('[')
+            .and_then(|rest| rest.split_once(']'))
+        {
+            section = parse_section_header(header.0);
+        } else if let Some(section) = &section
+            && trimmed.starts_with(|c: char| c.is_ascii_alphanumeric())
+        {
+            // Only the line itself is parsed, so a value continued onto the next line isn't
+            // recognized, and its line is left alone
+            entry = parse(&format!("[s]\n{}", trimmed))
+                .ok()
+                .and_then(|entries| entries.into_iter().next())
+                .map(|(name, value)| (format!("{}.{}", section, &name[2..]), value));
+        }
+        lines.push(Line {
+            text: text.to_string(),
+            section: section.clone(),
+            entry,
+        });
+    }
+    change(&mut lines);
+
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(&line.text);
+        content.push('\n');
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_path = PathBuf::from(lock_path);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+        .with_context(|| format!("could not lock config file {}", path.display()))?;
+    fs::rename(&lock_path, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// Adds an entry setting `key` after the last line of the last section it goes in, starting that
+// section at the end if there is none
+fn insert(lines: &mut Vec<Line>, key: &str, value: &str) {
+    let normalized = normalize_key(key);
+    let section = normalized
+        .rsplit_once('.')
+        .map_or("", |(section, _)| section);
+    let line = Line {
+        text: format_entry(key, value),
+        section: Some(section.to_string()),
+        entry: Some((normalized.clone(), Some(value.to_string()))),
+    };
+    match lines
+        .iter()
+        .rposition(|line| line.section.as_deref() == Some(section))
+    {
+        Some(i) => lines.insert(i + 1, line),
+        None => {
+            let (name, rest) = key.split_once('.').unwrap_or((key, ""));
+            let header = match rest.rsplit_once('.') {
+                Some((subsection, _)) => format!(
+                    "[{} \"{}\"]",
+                    name,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{}]", name),
+            };
+            lines.push(Line {
+                text: header,
+                section: Some(section.to_string()),
+                entry: None,
+            });
+            lines.push(line);
+        }
+    }
+}
+
+// Formats an entry for `key`, quoting its value where git would
+fn format_entry(key: &str, value: &str) -> String {
+    let name = key.rsplit_once('.').map_or(key, |(_, name)| name);
+    let quoted = value.starts_with(' ') || value.ends_with(' ') || value.contains([';', '#']);
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    match quoted {
+        true => format!("\t{} = \"{}\"", name, escaped),
+        false => format!("\t{} = {}", name, escaped),
+    }
+}
+
+// Checks an includeIf condition, for a config file in `dir`
+fn include_applies(
+    condition: &str,
+    dir: &Path,
+    repository: Option<&Repository>,
+) -> anyhow::Result<bool> {
+    let Some(repository) = repository else {
+        return Ok(false);
+    };
+    let (kind, pattern) = condition.split_once(':').unwrap_or((condition, ""));
+    let mut pattern = pattern.to_string();
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    match kind {
+        "gitdir" | "gitdir/i" => {
+            if let Some(rest) = pattern.strip_prefix("./") {
+                pattern = format!("{}/{}", dir.display(), rest);
+            } else if pattern.starts_with('~') {
+                pattern = expand_home(&pattern).to_string_lossy().into_owned();
+            } else if !pattern.starts_with('/') {
+                pattern = format!("**/{}", pattern);
+            }
+            let gitdir = fs::canonicalize(&repository.gitdir)?;
+            let gitdir = gitdir.to_string_lossy();
+            Ok(if kind == "gitdir/i" {
+                wildmatch(&pattern.to_lowercase(), &gitdir.to_lowercase())
+            } else {
+                wildmatch(&pattern, &gitdir)
+            })
+        }
+        "onbranch" => {
+            Ok(branch::current(repository)?.is_some_and(|branch| wildmatch(&pattern, &branch)))
+        }
+        _ => Ok(false),
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> anyhow::Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => bail!("Bad boolean config value '{}' for '{}'", value, key),
+    }
+}
+
+// Section and variable names are case-insensitive, subsection names are not
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_lowercase(),
+            &key[first..last],
+            key[last..].to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+fn parse(content: &str) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut entries = Vec::new();
+    let mut section: Option<String> = None;
+    let mut chars = content.chars().peekable();
+    let mut line_number = 1;
+
+    while let Some(&ch) = chars.peek() {
+        match ch {
+            '\n' => {
+                line_number += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' | ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '[' => {
+                chars.next();
+                let mut header = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('\n') | None => bail!("Bad section header on line {}", line_number),
+                        Some(c) => header.push(c),
+                    }
+                }
+                section = Some(
+                    parse_section_header(&header)
+                        .ok_or_else(|| anyhow!("Bad section header on line {}", line_number))?,
+                );
+            }
+            c if c.is_ascii_alphanumeric() => {
+                let Some(section) = &section else {
+                    bail!("Variable outside of a section on line {}", line_number);
+                };
+
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '-') {
+                    name.push(c);
+                }
+                while chars.next_if(|&c| c == ' ' || c == '\t').is_some() {}
+
+                let value = match chars.peek() {
+                    Some('=') => {
+                        chars.next();
+                        Some(parse_value(&mut chars, &mut line_number)?)
+                    }
+                    Some('\n') | Some('#') | Some(';') | None => None,
+                    _ => bail!("Bad variable name on line {}", line_number),
+                };
+
+                entries.push((format!("{}.{}", section, name.to_lowercase()), value));
+            }
+            _ => bail!("Unexpected character on line {}", line_number),
+        }
+    }
+
+    Ok(entries)
+}
+
+// Parses `section`, `section "subsection"` or the deprecated `section.subsection`
+fn parse_section_header(header: &str) -> Option<String> {
+    match header.split_once(char::is_whitespace) {
+        Some((name, subsection)) => {
+            let quoted = subsection.trim().strip_prefix('"')?.strip_suffix('"')?;
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                unescaped.push(if c == '\\' { chars.next()? } else { c });
+            }
+            Some(format!("{}.{}", name.to_lowercase(), unescaped))
+        }
+        None => Some(header.to_lowercase()),
+    }
+}
+
+// Parses a value up to the end of the line, handling quotes, escapes and line continuations
+fn parse_value(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    line_number: &mut usize,
+) -> anyhow::Result<String> {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    // Length of the value excluding trailing unquoted whitespace
+    let mut trimmed_len = 0;
+
+    while let Some(&c) = chars.peek() {
+        if c == '\n' && !in_quotes {
+            break;
+        }
+        chars.next();
+
+        match c {
+            '\n' => bail!("Unterminated quote on line {}", line_number),
+            '"' => {
+                in_quotes = !in_quotes;
+                trimmed_len = value.len();
+            }
+            '#' | ';' if !in_quotes => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                break;
+            }
+            '\\' => {
+                match chars.next() {
+                    Some('\n') => *line_number += 1,
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    _ => bail!("Bad escape sequence on line {}", line_number),
+                }
+                trimmed_len = value.len();
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                // Leading whitespace is dropped, inner whitespace is kept
+                if !value.is_empty() {
+                    value.push(c);
+                }
+            }
+            c => {
+                value.push(c);
+                trimmed_len = value.len();
+            }
+        }
+    }
+
+    if in_quotes {
+        bail!("Unterminated quote on line {}", line_number);
+    }
+
+    value.truncate(trimmed_len);
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_like_git() {
+        let content = "# comment\n\
+                       [Core]\n\
+                       \tfileMode = false ; trailing comment\n\
+                       \tbare\n\
+                       \tName = \"  quoted  \"  value  \n\
+                       [remote \"Origin\"]\n\
+                       \turl = https://example.com/a\\\nb.git\n\
+                       \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+                       [section.Sub]\n\
+                       \tkey = a\\tb\\\"c\\\\d # not in value\n\
+                       [multi]\n\
+                       \tv = 1\n\
+                       \tv = 2\n\
+                       \tempty =\n\
+                       [Weird-Name \"sub \\\"quoted\\\" \\\\ x\"]\n\
+                       \tx-y = \"a;b#c\"\n";
+        let config = Config {
+            entries: parse(content).unwrap(),
+        };
+        // As git 2.39 config --list gives them
+        let expected = [
+            ("core.filemode", Some("false")),
+            ("core.bare", None),
+            ("core.name", Some("  quoted    value")),
+            ("remote.Origin.url", Some("https://example.com/ab.git")),
+            (
+                "remote.Origin.fetch",
+                Some("+refs/heads/*:refs/remotes/origin/*"),
+            ),
+            ("section.sub.key", Some("a\tb\"c\\d")),
+            ("multi.v", Some("1")),
+            ("multi.v", Some("2")),
+            ("multi.empty", Some("")),
+            ("weird-name.sub \"quoted\" \\ x.x-y", Some("a;b#c")),
+        ];
+        assert_eq!(config.entries().collect::<Vec<_>>(), expected);
+
+        assert_eq!(config.get("CORE.FILEMODE"), Some("false"));
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get_bool("core.fileMode").unwrap(), Some(false));
+        assert!(config.get_bool("core.name").is_err());
+        assert_eq!(config.get_all("multi.v"), ["1", "2"]);
+        assert!(config.get_all("multi.empty").is_empty());
+    }
+
+    #[test]
+    fn bad_files() {
+        for content in [
+            "[core\n",
+            "key = value\n",
+            "[core]\n\tkey = \"open\n",
+            "[core]\n\tkey = a\\q\n",
+            "[core]\n\tkey value\n",
+            "[core \"unquoted]\n",
+        ] {
+            assert!(parse(content).is_err(), "{:?}", content);
+        }
+    }
+
+    #[test]
+    fn writes_like_git() {
+        let path = env::temp_dir().join(format!("gitrs-config-write-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# top\n[core]\n\tbare = false ; note\n[branch \"main\"]\n\tremote = origin\n\
+             [core]\n\tfileMode = true\n",
+        )
+        .unwrap();
+        set_value(&path, "core.bare", "true").unwrap();
+        set_value(&path, "core.FileMode", "false").unwrap();
+        set_value(&path, "branch.main.merge", "refs/heads/main").unwrap();
+        set_value(&path, "branch.My\"x.remote", " a;b").unwrap();
+        for value in ["1", "2", "1"] {
+            add_value(&path, "multi.v", value).unwrap();
+        }
+        assert!(unset_value(&path, "multi.v", Some("1")).unwrap());
+        assert!(unset_value(&path, "branch.main.remote", None).unwrap());
+        assert!(!unset_value(&path, "branch.main.remote", None).unwrap());
+
+        // As git 2.39 config leaves it
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# top\n[core]\n\tbare = true\n[branch \"main\"]\n\tmerge = refs/heads/main\n\
+             [core]\n\tFileMode = false\n[branch \"My\\\"x\"]\n\tremote = \" a;b\"\n\
+             [multi]\n\tv = 2\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn includes() {
+        let root = env::temp_dir().join(format!("gitrs-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let worktree = root.join("repo");
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let write = |name: &str, content: &str| fs::write(root.join(name), content).unwrap();
+        write("included", "[a]\n\tx = included\n\ty = 1\n");
+        write("branch", "[b]\n\ton = yes\n");
+        write("gitdir", "[c]\n\tdir = yes\n");
+        write("other", "[d]\n\tno = yes\n");
+        write("cycle", "[include]\n\tpath = cycle\n");
+        let config_path = repository.gitdir.join("config");
+        fs::write(
+            &config_path,
+            "[a]\n\tx = before\n\
+             [include]\n\tpath = ../../included\n\
+             [includeIf \"onbranch:master\"]\n\tpath = ../../branch\n\
+             [includeIf \"onbranch:next\"]\n\tpath = ../../other\n\
+             [includeIf \"gitdir/i:REPO/\"]\n\tpath = ../../gitdir\n\
+             [includeIf \"gitdir:elsewhere/\"]\n\tpath = ../../other\n\
+             [include]\n\tpath = ../../missing\n\
+             [a]\n\ty = 2\n",
+        )
+        .unwrap();
+
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        config
+            .read_file(&config_path, Some(&repository), 0)
+            .unwrap();
+        assert_eq!(config.get("a.x"), Some("included"));
+        assert_eq!(config.get("a.y"), Some("2"));
+        assert_eq!(config.get("b.on"), Some("yes"));
+        assert_eq!(config.get("c.dir"), Some("yes"));
+        assert_eq!(config.get("d.no"), None);
+
+        let mut config = Config {
+            entries: Vec::new(),
+        };
+        assert!(config.read_file(&root.join("cycle"), None, 0).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,665 @@
+// Formats the `timestamp timezone` dates recorded in commits and tags in git's date formats
+// (default, relative, iso, iso-strict, rfc, short, unix, raw and `format:<strftime>`), and parses
+// dates back, both strictly as in GIT_AUTHOR_DATE and approximately as in `--since=2.weeks.ago`.
+// gitrs doesn't read the system timezone, so local time is UTC.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+];
+const LONG_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// A date broken down in the timezone it is shown in
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    // Index into WEEKDAYS
+    weekday: usize,
+    day_of_year: i64,
+}
+
+impl Civil {
+    fn new(timestamp: i64, offset: i64) -> Self {
+        let local = timestamp + offset * 60;
+        let days = local.div_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let seconds = local.rem_euclid(86400);
+        Self {
+            year,
+            month,
+            day,
+            hour: seconds / 3600,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+            weekday: days.rem_euclid(7) as usize,
+            day_of_year: days - days_from_civil(year, 1, 1) + 1,
+        }
+    }
+}
+
+/// Formats a date in one of git's named formats, in the timezone it was recorded in. A `-local`
+/// suffix (or `local` alone, for the default format) shows it in local time instead.
+pub fn format(timestamp: i64, timezone: &str, format: &str) -> anyhow::Result<String> {
+    let format = if format == "local" {
+        "default-local"
+    } else {
+        format
+    };
+    let (format, timezone, local) = match format.strip_suffix("-local") {
+        Some(format) => (format, "+0000", true),
+        None => (format, timezone, false),
+    };
+    let offset = parse_timezone(timezone);
+    let date = Civil::new(timestamp, offset);
+    let (year, month, day) = (date.year, date.month, date.day);
+    let (hour, minute, second) = (date.hour, date.minute, date.second);
+    let weekday = WEEKDAYS[date.weekday];
+    let month_name = MONTHS[month as usize - 1];
+
+    if let Some(strftime) = format.strip_prefix("format:") {
+        return Ok(format_strftime(&date, timestamp, timezone, strftime));
+    }
+    Ok(match format {
+        // The local timezone goes without saying
+        "default" if local => format!(
+            "{} {} {} {:02}:{:02}:{:02} {}",
+            weekday, month_name, day, hour, minute, second, year
+        ),
+        "default" => format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            weekday, month_name, day, hour, minute, second, year, timezone
+        ),
+        "relative" => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+            format_relative(timestamp, now)
+        }
+        "iso" | "iso8601" => format!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+            year, month, day, hour, minute, second, timezone
+        ),
+        "iso-strict" | "iso8601-strict" => {
+            let zone = match offset {
+                0 => "Z".to_string(),
+                _ => format!("{}:{}", &timezone[..3], &timezone[3..]),
+            };
+            format!(
+                "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+                year, month, day, hour, minute, second, zone
+            )
+        }
+        "rfc" | "rfc2822" => format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            weekday, day, month_name, year, hour, minute, second, timezone
+        ),
+        "short" => format!("{}-{:02}-{:02}", year, month, day),
+        "unix" => timestamp.to_string(),
+        "raw" => format!("{} {}", timestamp, timezone),
+        _ => bail!("unknown date format {}", format),
+    })
+}
+
+/// Describes how long before `now` the date was, eg. `3 weeks ago` or `2 years, 1 month ago`,
+/// rounding like git does
+pub fn format_relative(timestamp: i64, now: i64) -> String {
+    let plural = |count: i64, unit: &str| match count {
+        1 => format!("{} {}", count, unit),
+        _ => format!("{} {}s", count, unit),
+    };
+    let mut diff = now - timestamp;
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    // From here on in days
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let total_months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        return match months {
+            0 => format!("{} ago", plural(years, "year")),
+            _ => format!("{}, {} ago", plural(years, "year"), plural(months, "month")),
+        };
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+// Expands the strftime conversions git passes on to the C library, for `--date=format:...`
+fn format_strftime(date: &Civil, timestamp: i64, timezone: &str, format: &str) -> String {
+    let hour12 = match date.hour % 12 {
+        0 => 12,
+        hour => hour,
+    };
+    // Monday is 1 and Sunday is 7 (or 0 for %w)
+    let iso_weekday = (date.weekday as i64 + 3) % 7 + 1;
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let Some(conversion) = chars.next() else {
+            output.push('%');
+            break;
+        };
+        let expansion = match conversion {
+            'a' => WEEKDAYS[date.weekday].to_string(),
+            'A' => LONG_WEEKDAYS[date.weekday].to_string(),
+            'b' | 'h' => MONTHS[date.month as usize - 1].to_string(),
+            'B' => LONG_MONTHS[date.month as usize - 1].to_string(),
+            'c' => format!(
+                "{} {} {:>2} {:02}:{:02}:{:02} {}",
+                WEEKDAYS[date.weekday],
+                MONTHS[date.month as usize - 1],
+                date.day,
+                date.hour,
+                date.minute,
+                date.second,
+                date.year
+            ),
+            'C' => format!("{:02}", date.year.div_euclid(100)),
+            'd' => format!("{:02}", date.day),
+            'D' => format!(
+                "{:02}/{:02}/{:02}",
+                date.month,
+                date.day,
+                date.year.rem_euclid(100)
+            ),
+            'e' => format!("{:>2}", date.day),
+            'F' => format!("{}-{:02}-{:02}", date.year, date.month, date.day),
+            'H' => format!("{:02}", date.hour),
+            'I' => format!("{:02}", hour12),
+            'j' => format!("{:03}", date.day_of_year),
+            'k' => format!("{:>2}", date.hour),
+            'l' => format!("{:>2}", hour12),
+            'm' => format!("{:02}", date.month),
+            'M' => format!("{:02}", date.minute),
+            'n' => "\n".to_string(),
+            'p' => if date.hour < 12 { "AM" } else { "PM" }.to_string(),
+            'P' => if date.hour < 12 { "am" } else { "pm" }.to_string(),
+            'R' => format!("{:02}:{:02}", date.hour, date.minute),
+            's' => timestamp.to_string(),
+            'S' => format!("{:02}", date.second),
+            't' => "\t".to_string(),
+            'T' => format!("{:02}:{:02}:{:02}", date.hour, date.minute, date.second),
+            'u' => iso_weekday.to_string(),
+            'w' => (iso_weekday % 7).to_string(),
+            'y' => format!("{:02}", date.year.rem_euclid(100)),
+            'Y' => date.year.to_string(),
+            'z' => timezone.to_string(),
+            // Like git, the zone's name is only known for local time
+            'Z' => String::new(),
+            '%' => "%".to_string(),
+            other => format!("%{}", other),
+        };
+        output.push_str(&expansion);
+    }
+    output
+}
+
+/// Parses an expiry date relative to `now`, as taken by prune and friends: `now`, `never`, or any
+/// date `approxidate` understands, such as `2.weeks.ago`. Everything not newer than the returned
+/// time has expired, so `never` is the earliest possible time.
+pub fn parse_expiry(value: &str, now: i64) -> anyhow::Result<i64> {
+    match value {
+        "now" | "all" => Ok(now),
+        "never" | "false" => Ok(i64::MIN),
+        _ => approxidate(value, now).map_err(|_| anyhow!("Malformed expiration date: {}", value)),
+    }
+}
+
+/// Parses a date the forgiving way git's `--since` and `--until` do, relative to `now`: anything
+/// `parse` takes, periods such as `2.weeks.ago` or `1 day 2 hours ago`, `yesterday`, `noon`,
+/// `midnight`, `last friday`, and partial dates such as `Apr 7` or `2005-04-07`, where the
+/// missing parts are taken from now. Fails if a word isn't understood.
+pub fn approxidate(value: &str, now: i64) -> anyhow::Result<i64> {
+    if let Ok((timestamp, _)) = parse(value) {
+        return Ok(timestamp);
+    }
+
+    let date = Civil::new(now, 0);
+    let (mut year, mut month, mut day) = (date.year, date.month, date.day);
+    let mut seconds = now.rem_euclid(86400);
+    let mut offset = 0;
+    // Seconds to go back from the date built up
+    let mut ago = 0;
+    // A number waiting for the unit after it
+    let mut number: Option<i64> = None;
+
+    let tokens: Vec<&str> = value
+        .split([' ', '.', ',', '\t'])
+        .filter(|token| !token.is_empty())
+        .collect();
+    for (idx, token) in tokens.iter().enumerate() {
+        let lower = token.to_lowercase();
+        let named = |names: &[&str]| {
+            names
+                .iter()
+                .position(|name| lower.len() >= 3 && name.to_lowercase().starts_with(&lower))
+        };
+
+        if let Some(period) = period(&lower) {
+            let count = number.take().unwrap_or(1);
+            match period {
+                Period::Seconds(seconds) => ago += count * seconds,
+                Period::Months(months) => month -= count * months,
+            }
+        } else if let Ok(n) = lower.parse::<i64>() {
+            // A number is either a count of the period after it, a year or a day of the month
+            if tokens
+                .get(idx + 1)
+                .is_some_and(|next| period(&next.to_lowercase()).is_some())
+            {
+                number = Some(n);
+            } else if lower.len() == 4 {
+                year = n;
+            } else if (1..=31).contains(&n) {
+                day = n;
+            } else {
+                bail!("invalid date format: {}", value);
+            }
+        } else if let Some(hour) = lower
+            .strip_suffix("am")
+            .or_else(|| lower.strip_suffix("pm"))
+            .and_then(|hour| hour.parse::<i64>().ok())
+            .filter(|hour| (1..=12).contains(hour))
+        {
+            let afternoon = i64::from(lower.ends_with("pm")) * 12;
+            seconds = (hour % 12 + afternoon) * 3600;
+        } else {
+            match lower.as_str() {
+                "ago" | "now" | "today" => {}
+                "last" => number = Some(1),
+                "yesterday" => ago += 86400,
+                "noon" | "midnight" | "tea" => {
+                    let hour = match lower.as_str() {
+                        "noon" => 12,
+                        "midnight" => 0,
+                        _ => 17,
+                    };
+                    // The most recent one, so before noon `noon` is yesterday's
+                    if hour * 3600 > now.rem_euclid(86400) {
+                        ago += 86400;
+                    }
+                    seconds = hour * 3600;
+                }
+                _ => {
+                    if let Some(idx) = named(&LONG_MONTHS) {
+                        month = idx as i64 + 1;
+                    } else if let Some(idx) = named(&LONG_WEEKDAYS) {
+                        // `last friday`, never today
+                        if number.take().is_some() {
+                            let back = (date.weekday as i64 - idx as i64).rem_euclid(7);
+                            ago += if back == 0 { 7 } else { back } * 86400;
+                        }
+                    } else if let Some((y, m, d, time)) = split_iso_date(token) {
+                        (year, month, day) = (y, m, d);
+                        if let Some(time) = time {
+                            let (hour, minute, second) = parse_time(split_zone(time).0)
+                                .ok_or_else(|| anyhow!("invalid date format: {}", value))?;
+                            seconds = hour * 3600 + minute * 60 + second;
+                        }
+                    } else if let Some((hour, minute, second)) = parse_time(split_zone(token).0) {
+                        seconds = hour * 3600 + minute * 60 + second;
+                        if let Some(zone) = split_zone(token).1.and_then(parse_zone) {
+                            offset = zone;
+                        }
+                    } else if let Some(zone) = parse_zone(token) {
+                        offset = zone;
+                    } else {
+                        bail!("invalid date format: {}", value);
+                    }
+                }
+            }
+        }
+    }
+
+    // Going back months or years can leave the month out of range
+    year += (month - 1).div_euclid(12);
+    month = (month - 1).rem_euclid(12) + 1;
+    Ok(days_from_civil(year, month, day) * 86400 + seconds - offset * 60 - ago)
+}
+
+enum Period {
+    Seconds(i64),
+    // Months are counted on the calendar, as they differ in length
+    Months(i64),
+}
+
+// Recognizes the unit of a period such as `2 weeks`
+fn period(word: &str) -> Option<Period> {
+    Some(match word.strip_suffix('s').unwrap_or(word) {
+        "second" | "sec" => Period::Seconds(1),
+        "minute" | "min" => Period::Seconds(60),
+        "hour" => Period::Seconds(3600),
+        "day" => Period::Seconds(86400),
+        "week" => Period::Seconds(7 * 86400),
+        "fortnight" => Period::Seconds(14 * 86400),
+        "month" => Period::Months(1),
+        "year" => Period::Months(12),
+        _ => return None,
+    })
+}
+
+/// Parses a date as given in GIT_AUTHOR_DATE and GIT_COMMITTER_DATE into a timestamp and timezone:
+/// git's raw format (optionally prefixed with `@`), RFC 2822 (`Thu, 7 Apr 2005 22:13:13 +0200`) or
+/// ISO 8601 (`2005-04-07T22:13:13+02:00`). Times without a timezone are taken to be in UTC.
+pub fn parse(value: &str) -> anyhow::Result<(i64, String)> {
+    let invalid = || anyhow!("invalid date format: {}", value);
+    let mut words = value.split_whitespace();
+    if let Some(first) = words.next()
+        && let Ok(timestamp) = first.strip_prefix('@').unwrap_or(first).parse::<i64>()
+        && (first.starts_with('@') || first.len() >= 9)
+    {
+        let timezone = match words.next() {
+            Some(zone) => format_timezone(parse_zone(zone).ok_or_else(invalid)?),
+            None => "+0000".to_string(),
+        };
+        return match words.next() {
+            None => Ok((timestamp, timezone)),
+            Some(_) => Err(invalid()),
+        };
+    }
+
+    let (mut year, mut month, mut day) = (None, None, None);
+    let (mut time, mut offset) = (None, None);
+    let tokens = value
+        .split([' ', ',', '\t'])
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        let lower = token.to_lowercase();
+        if let Some(idx) = MONTHS
+            .iter()
+            .position(|m| lower.starts_with(&m.to_lowercase()))
+        {
+            month = Some(idx as i64 + 1);
+        } else if WEEKDAYS
+            .iter()
+            .any(|d| lower.starts_with(&d.to_lowercase()))
+        {
+            continue;
+        } else if let Some((y, m, d, rest)) = split_iso_date(token) {
+            (year, month, day) = (Some(y), Some(m), Some(d));
+            if let Some(rest) = rest {
+                let (clock, zone) = split_zone(rest);
+                time = Some(parse_time(clock).ok_or_else(invalid)?);
+                if let Some(zone) = zone {
+                    offset = Some(parse_zone(zone).ok_or_else(invalid)?);
+                }
+            }
+        } else if token.contains(':') && time.is_none() {
+            let (clock, zone) = split_zone(token);
+            time = Some(parse_time(clock).ok_or_else(invalid)?);
+            if let Some(zone) = zone {
+                offset = Some(parse_zone(zone).ok_or_else(invalid)?);
+            }
+        } else if let Some(zone) = parse_zone(token) {
+            offset = Some(zone);
+        } else if let Ok(number) = token.parse::<i64>() {
+            match (day, number) {
+                (None, 1..=31) if token.len() <= 2 => day = Some(number),
+                _ if token.len() == 4 && year.is_none() => year = Some(number),
+                _ => return Err(invalid()),
+            }
+        } else {
+            return Err(invalid());
+        }
+    }
+
+    let (Some(year), Some(month), Some(day), Some((hour, minute, second))) =
+        (year, month, day, time)
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let offset = offset.unwrap_or(0);
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Ok((local - offset * 60, format_timezone(offset)))
+}
+
+// Splits an ISO 8601 `YYYY-MM-DD` date from the front of `token`, along with the time after a `T`
+fn split_iso_date(token: &str) -> Option<(i64, i64, i64, Option<&str>)> {
+    let (date, time) = match token.split_once(['T', 't']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (token, None),
+    };
+    let mut parts = date.split('-');
+    let (Some(year), Some(month), Some(day), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if year.len() != 4 {
+        return None;
+    }
+    Some((
+        year.parse().ok()?,
+        month.parse().ok()?,
+        day.parse().ok()?,
+        time,
+    ))
+}
+
+// Splits a `hh:mm:ss` time from a timezone written right after it, as ISO 8601 allows
+fn split_zone(token: &str) -> (&str, Option<&str>) {
+    match token.find(['+', '-', 'Z', 'z']) {
+        Some(idx) => (&token[..idx], Some(&token[idx..])),
+        None => (token, None),
+    }
+}
+
+// Parses `hh:mm` or `hh:mm:ss`, ignoring fractions of a second
+fn parse_time(clock: &str) -> Option<(i64, i64, i64)> {
+    let clock = clock.split_once('.').map_or(clock, |(clock, _)| clock);
+    let mut parts = clock.split(':').map(str::parse::<i64>);
+    let hour = parts.next()?.ok()?;
+    let minute = parts.next()?.ok()?;
+    let second = parts.next().transpose().ok()?.unwrap_or(0);
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some((hour, minute, second))
+}
+
+// Parses a timezone (`+hhmm`, `+hh:mm`, `+hh`, `Z`, `UTC` or `GMT`) into minutes east of UTC
+fn parse_zone(zone: &str) -> Option<i64> {
+    if ["z", "utc", "gmt"].contains(&zone.to_lowercase().as_str()) {
+        return Some(0);
+    }
+    let (sign, digits) = match zone.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    let digits = digits.replace(':', "");
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (
+            digits[..2].parse::<i64>().ok()?,
+            digits[2..].parse::<i64>().ok()?,
+        ),
+        _ => return None,
+    };
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn format_timezone(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}{:02}", sign, offset.abs() / 60, offset.abs() % 60)
+}
+
+// Converts a `+hhmm` offset into minutes east of UTC, treating malformed offsets as UTC
+fn parse_timezone(timezone: &str) -> i64 {
+    let (sign, digits) = match timezone.split_at_checked(1) {
+        Some(("-", digits)) => (-1, digits),
+        Some(("+", digits)) => (1, digits),
+        _ => return 0,
+    };
+    match (digits.get(..2), digits.get(2..4)) {
+        (Some(hours), Some(minutes)) => {
+            sign * (hours.parse::<i64>().unwrap_or(0) * 60 + minutes.parse::<i64>().unwrap_or(0))
+        }
+        _ => 0,
+    }
+}
+
+// Converts days since the epoch into a (year, month, day) date in the proleptic Gregorian
+// calendar, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`, see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = (month + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Thu Apr 7 22:13:13 2005 UTC
+    const NOW: i64 = 1112911993;
+
+    #[test]
+    fn formats() {
+        let format = |name| format(NOW, "+0200", name).unwrap();
+        assert_eq!(format("default"), "Fri Apr 8 00:13:13 2005 +0200");
+        assert_eq!(format("iso"), "2005-04-08 00:13:13 +0200");
+        assert_eq!(format("iso-strict"), "2005-04-08T00:13:13+02:00");
+        assert_eq!(format("rfc"), "Fri, 8 Apr 2005 00:13:13 +0200");
+        assert_eq!(format("short"), "2005-04-08");
+        assert_eq!(format("unix"), "1112911993");
+        assert_eq!(format("raw"), "1112911993 +0200");
+        assert_eq!(
+            format("format:%Y-%m-%d %H:%M %a %b %j %%"),
+            "2005-04-08 00:13 Fri Apr 098 %"
+        );
+        assert_eq!(format("default-local"), "Thu Apr 7 22:13:13 2005");
+        assert_eq!(format("iso-local"), "2005-04-07 22:13:13 +0000");
+        assert!(super::format(NOW, "+0200", "nope").is_err());
+    }
+
+    #[test]
+    fn relative() {
+        for (ago, expected) in [
+            (30, "30 seconds ago"),
+            (3600, "60 minutes ago"),
+            (86400, "24 hours ago"),
+            (864000, "10 days ago"),
+            (5000000, "8 weeks ago"),
+            (40000000, "1 year, 3 months ago"),
+            (70000000, "2 years, 3 months ago"),
+            (-10, "in the future"),
+        ] {
+            assert_eq!(format_relative(NOW - ago, NOW), expected);
+        }
+    }
+
+    #[test]
+    fn strict() {
+        let parsed = |value| parse(value).unwrap();
+        assert_eq!(parsed("1112911993 +0200"), (NOW, "+0200".to_string()));
+        assert_eq!(parsed("@1112911993"), (NOW, "+0000".to_string()));
+        let expected = (NOW - 7200, "+0200".to_string());
+        assert_eq!(parsed("Thu, 7 Apr 2005 22:13:13 +0200"), expected);
+        assert_eq!(parsed("2005-04-07T22:13:13+02:00"), expected);
+        assert!(parse("2.weeks.ago").is_err());
+    }
+
+    #[test]
+    fn approximate() {
+        for (value, expected) in [
+            ("2.weeks.ago", 1111702393),
+            ("yesterday", 1112825593),
+            ("noon", 1112875200),
+            ("midnight", 1112832000),
+            ("last friday", 1112393593),
+            ("Apr 7", 1112911993),
+            ("2005-04-01", 1112393593),
+            ("1 day 2 hours ago", 1112818393),
+            ("3.months.ago", 1105135993),
+            ("noon yesterday", 1112788800),
+            ("Thu, 7 Apr 2005 22:13:13 +0200", 1112904793),
+        ] {
+            assert_eq!(approxidate(value, NOW).unwrap(), expected, "{}", value);
+        }
+        assert!(approxidate("whenever", NOW).is_err());
+        assert_eq!(parse_expiry("never", NOW).unwrap(), i64::MIN);
+        assert_eq!(parse_expiry("now", NOW).unwrap(), NOW);
+    }
+}
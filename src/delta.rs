@@ -0,0 +1,412 @@
+// Creates and applies deltas in git's format: the sizes of the source and the target as varints,
+// then instructions that either copy a range of the source or insert bytes of their own. Deltas
+// are made as git makes them, so that they come out byte for byte the same: every 16 byte block
+// of the source is indexed by its Rabin fingerprint, and a fingerprint rolled along the target
+// finds where to look for the longest match with the source.
+use anyhow::{bail, ensure};
+
+// The size of the blocks fingerprinted
+const WINDOW: usize = 16;
+// How far the fingerprint's top bits are shifted to look them up in T
+const SHIFT: u32 = 23;
+// The most blocks kept with the same fingerprint
+const HASH_LIMIT: usize = 64;
+// Matches this long are taken without looking for a longer one
+const GOOD_ENOUGH: usize = 4096;
+// The most a single copy instruction copies
+const MAX_COPY: usize = 0x10000;
+// The most bytes a single insert instruction inserts
+const MAX_INSERT: usize = 0x7f;
+
+// The polynomial fingerprints are taken modulo, over GF(2)
+const POLYNOMIAL: u64 = 0xab59b4d1;
+// Reduces the byte shifted out of the top of a fingerprint, which also clears its low bit
+const T: [u32; 256] = table(31, true);
+// Removes the byte leaving the window from a fingerprint
+const U: [u32; 256] = table(8 * (WINDOW as u32 - 1), false);
+
+// The table of `i * x^exponent` modulo the polynomial for each byte `i`, with `i`'s low bit kept
+// in the top bit if `overflow` is set
+const fn table(exponent: u32, overflow: bool) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = (i as u128) << exponent;
+        let mut bit = 127;
+        while bit > 30 {
+            if value >> bit & 1 == 1 {
+                value ^= (POLYNOMIAL as u128) << (bit - 31);
+            }
+            bit -= 1;
+        }
+        if overflow {
+            value ^= (i as u128 & 1) << 31;
+        }
+        table[i] = value as u32;
+        i += 1;
+    }
+    table
+}
+
+// Rolls `byte` into the fingerprint `value`
+fn roll(value: u32, byte: u8) -> u32 {
+    ((value << 8) | byte as u32) ^ T[(value >> SHIFT) as usize]
+}
+
+// A block of the source, by where it ends
+#[derive(Clone, Copy)]
+struct Entry {
+    position: usize,
+    fingerprint: u32,
+}
+
+// The blocks of a source, bucketed by fingerprint
+struct Index<'a> {
+    source: &'a [u8],
+    mask: u32,
+    // The start of each bucket in `entries`, and where the last one ends
+    buckets: Vec<usize>,
+    entries: Vec<Entry>,
+}
+
+impl<'a> Index<'a> {
+    fn new(source: &'a [u8]) -> Self {
+        // The first byte is left out of every block, which the target's first fingerprint
+        // makes up for
+        let blocks = (source.len() - 1) / WINDOW;
+        let size = (blocks / 4).next_power_of_two().max(16);
+        let mask = size as u32 - 1;
+
+        // Blocks are taken from the end, and only the first of a run of identical blocks is kept
+        let mut buckets: Vec<Vec<Entry>> = vec![Vec::new(); size];
+        let mut previous: Option<(u32, usize)> = None;
+        for start in (0..blocks).rev().map(|block| block * WINDOW) {
+            let fingerprint = source[start + 1..=start + WINDOW]
+                .iter()
+                .fold(0, |value, &byte| roll(value, byte));
+            let position = start + WINDOW;
+            match previous {
+                Some((last, bucket)) if last == fingerprint => {
+                    buckets[bucket]
+                        .last_mut()
+                        .expect("The bucket has the last block")
+                        .position = position;
+                }
+                _ => {
+                    let bucket = (fingerprint & mask) as usize;
+                    buckets[bucket].push(Entry {
+                        position,
+                        fingerprint,
+                    });
+                    previous = Some((fingerprint, bucket));
+                }
+            }
+        }
+        // Each bucket lists its blocks in order
+        buckets.iter_mut().for_each(|bucket| bucket.reverse());
+
+        // Buckets with too many blocks are thinned out evenly across the source
+        for bucket in buckets
+            .iter_mut()
+            .filter(|bucket| bucket.len() > HASH_LIMIT)
+        {
+            let excess = bucket.len() - HASH_LIMIT;
+            let mut kept = Vec::with_capacity(HASH_LIMIT);
+            let mut balance = 0;
+            let mut i = 0;
+            while i < bucket.len() {
+                kept.push(bucket[i]);
+                balance += excess as isize;
+                while balance > 0 {
+                    i += 1;
+                    balance -= HASH_LIMIT as isize;
+                }
+                i += 1;
+            }
+            *bucket = kept;
+        }
+
+        let mut starts = Vec::with_capacity(size + 1);
+        let mut entries = Vec::new();
+        for bucket in buckets {
+            starts.push(entries.len());
+            entries.extend(bucket);
+        }
+        starts.push(entries.len());
+        Self {
+            source,
+            mask,
+            buckets: starts,
+            entries,
+        }
+    }
+
+    // The offset and length of the longest match in the source for the start of `target` among
+    // the blocks with `fingerprint`, if it is longer than the `best` so far
+    fn find(&self, fingerprint: u32, target: &[u8], mut best: (usize, usize)) -> (usize, usize) {
+        let bucket = (fingerprint & self.mask) as usize;
+        for entry in &self.entries[self.buckets[bucket]..self.buckets[bucket + 1]] {
+            if entry.fingerprint != fingerprint {
+                continue;
+            }
+            let limit = (self.source.len() - entry.position).min(target.len());
+            if limit <= best.1 {
+                break;
+            }
+            let length = self.source[entry.position..entry.position + limit]
+                .iter()
+                .zip(target)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.1 {
+                best = (entry.position, length);
+                if length >= GOOD_ENOUGH {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// Makes a delta from `source` to `target`, or returns None if either is empty or the delta
+/// would be longer than `max_size`, when that is set
+pub fn create(source: &[u8], target: &[u8], max_size: Option<usize>) -> Option<Vec<u8>> {
+    if source.is_empty() || target.is_empty() {
+        return None;
+    }
+    let index = Index::new(source);
+    let mut delta = Vec::new();
+    write_size(&mut delta, source.len());
+    write_size(&mut delta, target.len());
+
+    // Inserted bytes are written straight away, behind a count that is filled in when the insert
+    // instruction ends: this is where that count is, while one is open
+    let mut insert = None;
+    let mut fingerprint = 0;
+    let mut position = 0;
+    while position < WINDOW.min(target.len()) {
+        insert_byte(&mut delta, &mut insert, target[position]);
+        fingerprint = roll(fingerprint, target[position]);
+        position += 1;
+    }
+
+    let (mut offset, mut length) = (0, 0);
+    while position < target.len() {
+        if length < GOOD_ENOUGH {
+            fingerprint ^= U[target[position - WINDOW] as usize];
+            fingerprint = roll(fingerprint, target[position]);
+            (offset, length) = index.find(fingerprint, &target[position..], (offset, length));
+        }
+
+        if length < 4 {
+            insert_byte(&mut delta, &mut insert, target[position]);
+            position += 1;
+            length = 0;
+        } else {
+            // The match may extend back over the bytes just inserted
+            if let Some(at) = insert {
+                while offset > 0 && source[offset - 1] == target[position - 1] {
+                    length += 1;
+                    offset -= 1;
+                    position -= 1;
+                    delta.pop();
+                    if delta.len() == at + 1 {
+                        delta.pop();
+                        insert = None;
+                        break;
+                    }
+                }
+                end_insert(&mut delta, &mut insert);
+            }
+
+            let left = length.saturating_sub(MAX_COPY);
+            length -= left;
+            write_copy(&mut delta, offset, length);
+            position += length;
+            offset += length;
+            length = left;
+            if offset > u32::MAX as usize {
+                length = 0;
+            }
+            if length < GOOD_ENOUGH {
+                fingerprint = target[position - WINDOW..position]
+                    .iter()
+                    .fold(0, |value, &byte| roll(value, byte));
+            }
+        }
+
+        // Like git, give up as soon as the delta is too long, even if a match extending back
+        // would have made it short enough again
+        if max_size.is_some_and(|max_size| delta.len() > max_size) {
+            return None;
+        }
+    }
+    end_insert(&mut delta, &mut insert);
+
+    match max_size {
+        Some(max_size) if delta.len() > max_size => None,
+        _ => Some(delta),
+    }
+}
+
+/// Applies `delta` to `source`
+pub fn apply(source: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut data = delta;
+    ensure!(
+        read_size(&mut data)? == source.len(),
+        "delta source size mismatch"
+    );
+    let size = read_size(&mut data)?;
+    let mut target = Vec::with_capacity(size);
+
+    while let Some((&instruction, rest)) = data.split_first() {
+        data = rest;
+        if instruction & 0x80 != 0 {
+            let mut read = |bits: std::ops::Range<u8>| -> anyhow::Result<usize> {
+                let mut value = 0;
+                for (shift, bit) in bits.enumerate() {
+                    if instruction & (1 << bit) != 0 {
+                        let Some((&byte, rest)) = data.split_first() else {
+                            bail!("delta replay has gone wild");
+                        };
+                        data = rest;
+                        value |= (byte as usize) << (8 * shift);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = read(0..4)?;
+            let length = match read(4..7)? {
+                0 => MAX_COPY,
+                length => length,
+            };
+            let Some(copied) = source.get(offset..offset + length) else {
+                bail!("delta replay has gone wild");
+            };
+            ensure!(target.len() + length <= size, "delta replay has gone wild");
+            target.extend_from_slice(copied);
+        } else if instruction != 0 {
+            let length = instruction as usize;
+            ensure!(
+                length <= data.len() && target.len() + length <= size,
+                "delta replay has gone wild"
+            );
+            target.extend_from_slice(&data[..length]);
+            data = &data[length..];
+        } else {
+            bail!("unexpected delta opcode 0");
+        }
+    }
+    ensure!(target.len() == size, "delta replay has gone wild");
+    Ok(target)
+}
+
+// Inserts `byte`, starting an insert instruction if none is open and ending it once it is full
+fn insert_byte(delta: &mut Vec<u8>, insert: &mut Option<usize>, byte: u8) {
+    let at = *insert.get_or_insert_with(|| {
+        delta.push(0);
+        delta.len() - 1
+    });
+    delta.push(byte);
+    if delta.len() - at - 1 == MAX_INSERT {
+        end_insert(delta, insert);
+    }
+}
+
+// Fills in the count of the open insert instruction, if there is one
+fn end_insert(delta: &mut [u8], insert: &mut Option<usize>) {
+    if let Some(at) = insert.take() {
+        delta[at] = (delta.len() - at - 1) as u8;
+    }
+}
+
+// Sizes are little-endian, seven bits to a byte, the top bit set on all but the last
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push(size as u8 | 0x80);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+fn read_size(data: &mut &[u8]) -> anyhow::Result<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+    loop {
+        let Some((&byte, rest)) = data.split_first() else {
+            bail!("delta is truncated");
+        };
+        *data = rest;
+        size |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+        shift += 7;
+    }
+}
+
+// A copy instruction has a bit for each byte of the offset and length that isn't zero, followed
+// by those bytes
+fn write_copy(delta: &mut Vec<u8>, offset: usize, length: usize) {
+    let at = delta.len();
+    delta.push(0x80);
+    for (bit, byte) in (0..4)
+        .map(|i| (offset >> (8 * i)) as u8)
+        .chain((0..2).map(|i| (length >> (8 * i)) as u8))
+        .enumerate()
+    {
+        if byte != 0 {
+            delta[at] |= 1 << bit;
+            delta.push(byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1024 bytes, and the same with 10 bytes overwritten and 48 inserted
+    fn versions() -> (Vec<u8>, Vec<u8>) {
+        let old: Vec<u8> = (0..=255).cycle().take(1024).collect();
+        let mut new = old.clone();
+        new[100..110].fill(b'x');
+        new.splice(600..600, b"inserted".repeat(6));
+        (old, new)
+    }
+
+    #[test]
+    fn create_matches_git() {
+        let (old, new) = versions();
+        // As git diff --binary makes them
+        let forward = "8008b00890640a78787878787878787878b16eea0130696e736572746564696e7365727465\
+                       64696e736572746564696e736572746564696e736572746564696e736572746564b158a801";
+        let reverse = "b0088008b2015801a3580101a3580101935801a8";
+        assert_eq!(
+            create(&old, &new, None).map(hex::encode).as_deref(),
+            Some(forward)
+        );
+        assert_eq!(
+            create(&new, &old, None).map(hex::encode).as_deref(),
+            Some(reverse)
+        );
+    }
+
+    #[test]
+    fn apply_round_trips() {
+        let (old, new) = versions();
+        let delta = create(&old, &new, None).unwrap();
+        assert_eq!(apply(&old, &delta).unwrap(), new);
+        assert!(apply(&new, &delta).is_err());
+        assert!(apply(&old, &delta[..delta.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn create_gives_up() {
+        let (old, new) = versions();
+        assert_eq!(create(&[], &new, None), None);
+        assert_eq!(create(&old, &[], None), None);
+        assert_eq!(create(&old, &new, Some(10)), None);
+    }
+}
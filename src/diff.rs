@@ -0,0 +1,223 @@
+// Compares trees with each other, or with the worktree, producing the raw changes the diff-*
+// plumbing commands print as `:old_mode new_mode old_hash new_hash status\tpath`. Entries are
+// compared in tree order, and subtrees are only descended into when diffing recursively. The
+// content on either side of a change can be read back for patches and stats.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::object::ObjectType;
+use crate::object::blob::Blob;
+use crate::object::tree::{self, Leaf, SYMLINK_MODE, Tree};
+use crate::pathspec::Pathspec;
+use crate::refs::ZERO_HASH;
+use crate::repository::Repository;
+use crate::tree_walk::{TreeWalk, TreeWalkOptions};
+
+/// Mode recorded for the missing side of an addition or deletion
+pub const NO_MODE: &str = "000000";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Added,
+    Deleted,
+    Modified,
+    // Changed between a regular file, a symlink and a submodule
+    TypeChanged,
+}
+
+pub struct Change {
+    pub old_mode: String,
+    pub new_mode: String,
+    pub old_hash: String,
+    /// ZERO_HASH for worktree files, which aren't hashed when they differ
+    pub new_hash: String,
+    pub status: Status,
+    pub path: String,
+}
+
+/// Lists the changes from `old` to `new`. Changed subtrees are reported as single entries unless
+/// `recursive` is set, in which case only the files within them are.
+pub fn diff_trees(
+    repository: &Repository,
+    old: &Tree,
+    new: &Tree,
+    recursive: bool,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<Change>> {
+    let options = TreeWalkOptions {
+        recursive,
+        skip_identical: true,
+        pathspec: pathspec.clone(),
+    };
+    let mut changes = Vec::new();
+    for entry in TreeWalk::new(repository, &[old, new], options) {
+        let mut entry = entry?;
+        let new = entry.entries.pop().expect("Walking two trees");
+        let old = entry.entries.pop().expect("Walking two trees");
+        changes.extend(Change::between(entry.path, old, new));
+    }
+    Ok(changes)
+}
+
+/// Lists the changes from `tree` to the worktree, where the files tracked in `head` stand in for
+/// the index. Like git's diff-index, files that differ from `head` on disk are reported with
+/// ZERO_HASH instead of their hash, and untracked files are not reported.
+pub fn diff_worktree(
+    repository: &Repository,
+    tree: &Tree,
+    head: &Tree,
+    worktree: &Path,
+    trust_executable_bit: bool,
+    pathspec: &Pathspec,
+) -> anyhow::Result<Vec<Change>> {
+    let mut tracked = BTreeMap::new();
+    for (path, (mode, hash)) in flatten(repository, head, pathspec)? {
+        // Submodules are never checked out, so there's nothing to compare
+        if Leaf::get_type_from_mode(&mode) == ObjectType::Commit {
+            tracked.insert(path, (mode, hash));
+            continue;
+        }
+        match tree::hash_worktree_file(&worktree.join(&path), &mode, trust_executable_bit)? {
+            Some(entry) if entry == (mode.clone(), hash.clone()) => {
+                tracked.insert(path, (mode, hash));
+            }
+            Some((mode, _)) => {
+                tracked.insert(path, (mode, ZERO_HASH.to_string()));
+            }
+            None => {}
+        }
+    }
+
+    let mut old = flatten(repository, tree, pathspec)?;
+    let paths: BTreeSet<String> = old.keys().chain(tracked.keys()).cloned().collect();
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let (old, new) = (old.remove(&path), tracked.remove(&path));
+            Change::between(path, old, new)
+        })
+        .collect())
+}
+
+/// Maps the path of every non-tree entry of `tree` selected by `pathspec`, recursively, to its mode
+/// and hash
+pub fn flatten(
+    repository: &Repository,
+    tree: &Tree,
+    pathspec: &Pathspec,
+) -> anyhow::Result<BTreeMap<String, (String, String)>> {
+    let options = TreeWalkOptions {
+        recursive: true,
+        pathspec: pathspec.clone(),
+        ..TreeWalkOptions::default()
+    };
+    let mut entries = BTreeMap::new();
+    for entry in TreeWalk::new(repository, &[tree], options) {
+        let mut entry = entry?;
+        if let Some(Some(file)) = entry.entries.pop() {
+            entries.insert(entry.path, file);
+        }
+    }
+    Ok(entries)
+}
+
+impl Change {
+    // Describes how the (mode, hash) entry at `path` changed, or returns None if it didn't
+    fn between(
+        path: String,
+        old: Option<(String, String)>,
+        new: Option<(String, String)>,
+    ) -> Option<Self> {
+        let status = match (&old, &new) {
+            (Some(a), Some(b)) if a == b => return None,
+            (Some((old_mode, _)), Some((new_mode, _))) => modification(old_mode, new_mode),
+            (Some(_), None) => Status::Deleted,
+            (None, Some(_)) => Status::Added,
+            (None, None) => return None,
+        };
+        let (old_mode, old_hash) = old.unwrap_or((NO_MODE.to_string(), ZERO_HASH.to_string()));
+        let (new_mode, new_hash) = new.unwrap_or((NO_MODE.to_string(), ZERO_HASH.to_string()));
+
+        Some(Self {
+            old_mode,
+            new_mode,
+            old_hash,
+            new_hash,
+            status,
+            path,
+        })
+    }
+
+    /// Reads the content being compared on the old side, or None if it is missing
+    pub fn old_content(&self, repository: &Repository) -> anyhow::Result<Option<Vec<u8>>> {
+        content(repository, &self.path, &self.old_mode, &self.old_hash)
+    }
+
+    /// Reads the content being compared on the new side, from the worktree when it differs there,
+    /// or None if it is missing
+    pub fn new_content(&self, repository: &Repository) -> anyhow::Result<Option<Vec<u8>>> {
+        content(repository, &self.path, &self.new_mode, &self.new_hash)
+    }
+
+    /// Formats the change as a raw diff line, NUL-terminating the status and path if `nul` is set
+    pub fn to_raw(&self, nul: bool) -> String {
+        let header = format!(
+            ":{} {} {} {} {}",
+            self.old_mode, self.new_mode, self.old_hash, self.new_hash, self.status
+        );
+        if nul {
+            format!("{}\0{}\0", header, self.path)
+        } else {
+            format!("{}\t{}\n", header, self.path)
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self {
+            Status::Added => "A",
+            Status::Deleted => "D",
+            Status::Modified => "M",
+            Status::TypeChanged => "T",
+        };
+        write!(f, "{}", letter)
+    }
+}
+
+/// Reads what git compares for one side of a change: a file's content, or the commit a submodule
+/// is at, as a line of text. ZERO_HASH reads the worktree's copy, and NO_MODE gives None.
+pub fn content(
+    repository: &Repository,
+    path: &str,
+    mode: &str,
+    hash: &str,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if mode == NO_MODE {
+        return Ok(None);
+    }
+    if Leaf::get_type_from_mode(mode) == ObjectType::Commit {
+        return Ok(Some(format!("Subproject commit {}\n", hash).into_bytes()));
+    }
+    if hash == ZERO_HASH {
+        let path = repository.worktree.join(path);
+        return tree::read_worktree_file(&path, mode)
+            .with_context(|| format!("Failed to read {}", path.display()));
+    }
+    Ok(Some(Blob::read(repository, hash)?.data().to_vec()))
+}
+
+// A file whose mode changes class (eg. to a symlink) has its type changed, otherwise it is modified
+fn modification(old_mode: &str, new_mode: &str) -> Status {
+    let is_symlink = |mode: &str| mode == SYMLINK_MODE;
+    if is_symlink(old_mode) != is_symlink(new_mode)
+        || Leaf::get_type_from_mode(old_mode) != Leaf::get_type_from_mode(new_mode)
+    {
+        Status::TypeChanged
+    } else {
+        Status::Modified
+    }
+}
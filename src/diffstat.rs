@@ -0,0 +1,197 @@
+// Summarizes changes the way git's --stat and --summary do: a line per file with the number of
+// lines added and removed and a histogram of them, a total, and the files that were created,
+// deleted or had their mode changed. The histogram is scaled down when it wouldn't fit in the
+// terminal's width (COLUMNS, or 80), with long paths shortened from the left to make room.
+use std::env;
+
+use crate::diff::{Change, Status};
+use crate::line_diff::{self, DiffOptions, Whitespace};
+use crate::merge_file;
+use crate::repository::Repository;
+
+const DEFAULT_WIDTH: usize = 80;
+
+struct FileStat<'a> {
+    path: &'a str,
+    added: usize,
+    deleted: usize,
+    /// The sizes before and after, for binary files, whose lines aren't counted
+    binary: Option<(usize, usize)>,
+}
+
+/// Formats the --stat lines for `changes`, ending with the total, comparing lines as `options`
+/// says to. Lines differing only in the whitespace ignored aren't counted, and files left with
+/// nothing to count aren't listed.
+pub fn stat(
+    repository: &Repository,
+    changes: &[Change],
+    options: &DiffOptions,
+) -> anyhow::Result<Vec<String>> {
+    let mut stats = Vec::new();
+    for change in changes {
+        let old = change.old_content(repository)?.unwrap_or_default();
+        let new = change.new_content(repository)?.unwrap_or_default();
+        let mut stat = FileStat {
+            path: &change.path,
+            added: 0,
+            deleted: 0,
+            binary: None,
+        };
+        if merge_file::is_binary(&old) || merge_file::is_binary(&new) {
+            stat.binary = Some((old.len(), new.len()));
+        } else {
+            let (old, new) = (line_diff::lines(&old), line_diff::lines(&new));
+            for region in line_diff::diff_lines(&old, &new, options) {
+                stat.deleted += region.old.len();
+                stat.added += region.new.len();
+            }
+            let unchanged = stat.added == 0
+                && stat.deleted == 0
+                && change.status == Status::Modified
+                && change.old_mode == change.new_mode;
+            if options.whitespace != Whitespace::Exact && unchanged {
+                continue;
+            }
+        }
+        stats.push(stat);
+    }
+
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(DEFAULT_WIDTH);
+    let mut lines = format(&stats, width);
+    let (added, deleted) = stats.iter().fold((0, 0), |(added, deleted), stat| {
+        (added + stat.added, deleted + stat.deleted)
+    });
+    lines.push(total(stats.len(), added, deleted));
+    Ok(lines)
+}
+
+/// Formats the --summary lines for `changes`, for the files created, deleted or changing mode
+pub fn summary(changes: &[Change]) -> Vec<String> {
+    changes
+        .iter()
+        .filter_map(|change| match change.status {
+            Status::Added => Some(format!(" create mode {} {}", change.new_mode, change.path)),
+            Status::Deleted => Some(format!(" delete mode {} {}", change.old_mode, change.path)),
+            _ if change.old_mode != change.new_mode => Some(format!(
+                " mode change {} => {} {}",
+                change.old_mode, change.new_mode, change.path
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+fn format(stats: &[FileStat], width: usize) -> Vec<String> {
+    let changed = |stat: &FileStat| stat.added + stat.deleted;
+    let max_len = stats
+        .iter()
+        .map(|stat| stat.path.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .filter(|stat| stat.binary.is_none())
+        .map(changed)
+        .max()
+        .unwrap_or(0);
+    let mut number_width = max_change.to_string().len();
+    if stats.iter().any(|stat| stat.binary.is_some()) {
+        number_width = number_width.max("Bin".len());
+    }
+
+    // The name and the histogram share what is left of the width after " | NNN " and a spare
+    // column, the histogram taking no more than 3/8 of it
+    let (mut name_width, mut graph_width) = (max_len, max_change);
+    let fixed = number_width + 6;
+    if name_width + graph_width + fixed > width {
+        let limit = (width * 3 / 8).saturating_sub(fixed);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        if name_width > width.saturating_sub(fixed + graph_width) {
+            name_width = width.saturating_sub(fixed + graph_width);
+        } else {
+            graph_width = width.saturating_sub(fixed + name_width);
+        }
+    }
+
+    stats
+        .iter()
+        .map(|stat| {
+            let name = shorten(stat.path, name_width);
+            if let Some((old, new)) = stat.binary {
+                return format!(
+                    " {:<name_width$} | {:>number_width$} {} -> {} bytes",
+                    name, "Bin", old, new
+                );
+            }
+            let (mut added, mut deleted) = (stat.added, stat.deleted);
+            if graph_width < max_change {
+                let mut total = scale(added + deleted, graph_width, max_change);
+                if total < 2 && added > 0 && deleted > 0 {
+                    total = 2;
+                }
+                if added < deleted {
+                    added = scale(added, graph_width, max_change);
+                    deleted = total - added;
+                } else {
+                    deleted = scale(deleted, graph_width, max_change);
+                    added = total - deleted;
+                }
+            }
+            format!(
+                " {:<name_width$} | {:>number_width$}{}{}{}",
+                name,
+                changed(stat),
+                if changed(stat) > 0 { " " } else { "" },
+                "+".repeat(added),
+                "-".repeat(deleted)
+            )
+        })
+        .collect()
+}
+
+// Shortens `path` to `width` characters by replacing its start with `...`, cutting at a directory
+// boundary where there is one
+fn shorten(path: &str, width: usize) -> String {
+    let length = path.chars().count();
+    if length <= width {
+        return path.to_string();
+    }
+    let keep = width.saturating_sub(3);
+    let tail: String = path.chars().skip(length - keep).collect();
+    match tail.find('/') {
+        Some(slash) => format!("...{}", &tail[slash..]),
+        None => format!("...{}", tail),
+    }
+}
+
+// Scales a count of changed lines to a histogram `width` long for the largest, keeping any change
+// visible
+fn scale(count: usize, width: usize, max_change: usize) -> usize {
+    match count {
+        0 => 0,
+        _ => 1 + count * (width.saturating_sub(1)) / max_change,
+    }
+}
+
+fn total(files: usize, added: usize, deleted: usize) -> String {
+    let plural = |count: usize, word: &str| match count {
+        1 => format!("{} {}", count, word),
+        _ => format!("{} {}s", count, word),
+    };
+    if files == 0 {
+        return " 0 files changed".to_string();
+    }
+    let mut line = format!(" {} changed", plural(files, "file"));
+    if added > 0 || deleted == 0 {
+        line.push_str(&format!(", {}(+)", plural(added, "insertion")));
+    }
+    if deleted > 0 || added == 0 {
+        line.push_str(&format!(", {}(-)", plural(deleted, "deletion")));
+    }
+    line
+}
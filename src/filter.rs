@@ -0,0 +1,441 @@
+// Rewrites history the way git filter-repo does, to scrub secrets from it or make it smaller:
+// paths can be removed, blobs above a size stripped, and the identities of authors, committers
+// and taggers mapped to others with a file in mailmap format. Every commit and annotated tag that
+// refs reach is rewritten, parents first, those left unchanged keeping their hash. Subtrees left
+// empty are dropped, and so are commits left empty that weren't empty to begin with, their
+// children taking their parent instead. Signatures are dropped from whatever is rewritten, as they
+// no longer hold. The refs are then updated together, those left with no commit deleted, the
+// worktree is moved to the new HEAD, and the old-to-new commit map is written to
+// filter-repo/commit-map, as filter-repo does. Reflogs still point at the old commits, which keeps
+// them around until the reflogs are expired. A dry run only hashes the objects it would write,
+// and lists the refs it would update without touching them, the worktree or the commit map.
+use std::collections::HashMap;
+
+use anyhow::{Context, anyhow, bail};
+use indexmap::IndexMap;
+
+use crate::config::Config;
+use crate::diff;
+use crate::ident::Ident;
+use crate::mailmap::Mailmap;
+use crate::object::commit::Commit;
+use crate::object::tag::Tag;
+use crate::object::tree::{Leaf, Tree};
+use crate::object::{GitrsObject, Object, ObjectType};
+use crate::pathspec::Pathspec;
+use crate::plan::{Action, Plan};
+use crate::refs::{Ref, RefUpdate, ZERO_HASH};
+use crate::repository::Repository;
+use crate::sequencer::Operation;
+use crate::signature;
+use crate::switch;
+
+/// What is filtered out of history
+#[derive(Default)]
+pub struct FilterOptions {
+    /// Paths to remove, each a file or a directory
+    pub remove_paths: Vec<String>,
+    /// Remove the blobs larger than this many bytes
+    pub max_blob_size: Option<u64>,
+    /// Map the identities of authors, committers and taggers with this
+    pub author_map: Option<Mailmap>,
+}
+
+/// What filtering changed
+pub struct Filtered {
+    /// Each commit looked at, parents first, with what it was rewritten to, or None if it was
+    /// pruned and no commit took its place
+    pub commits: IndexMap<String, Option<String>>,
+    /// How many commits were pruned for being left empty
+    pub pruned: usize,
+    /// The refs that changed, with their new value, or None if they were deleted
+    pub refs: Vec<(String, Option<String>)>,
+    /// How many distinct blobs were stripped for their size
+    pub stripped: usize,
+}
+
+struct Filter<'a> {
+    repository: &'a Repository,
+    options: &'a FilterOptions,
+    plan: &'a mut Plan,
+    empty_tree: String,
+    // What each tree became at each path, or None if nothing was left of it
+    trees: HashMap<(String, String), Option<String>>,
+    commits: IndexMap<String, Option<String>>,
+    // The trees of the commits written, which a dry run can't read back
+    commit_trees: HashMap<String, String>,
+    pruned: usize,
+    tags: HashMap<String, Option<String>>,
+    // Whether each blob looked at is larger than the limit
+    large: HashMap<String, bool>,
+}
+
+/// Parses a size in bytes, which may end in a k, m or g suffix
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let bad_size = || anyhow!("Invalid size: {}", size);
+    let (digits, multiplier) = match size.char_indices().last().ok_or_else(bad_size)? {
+        (end, 'k' | 'K') => (&size[..end], 1 << 10),
+        (end, 'm' | 'M') => (&size[..end], 1 << 20),
+        (end, 'g' | 'G') => (&size[..end], 1 << 30),
+        _ => (size, 1),
+    };
+    let number: u64 = digits.parse().map_err(|_| bad_size())?;
+    number.checked_mul(multiplier).ok_or_else(bad_size)
+}
+
+/// Rewrites the history of every ref with the `options` filters, updating the refs and the
+/// worktree to match
+pub fn filter(
+    repository: &Repository,
+    options: &FilterOptions,
+    plan: &mut Plan,
+) -> anyhow::Result<Filtered> {
+    if let Some(operation) = Operation::in_progress(repository) {
+        bail!("cannot rewrite history while {}", operation);
+    }
+    let old_head = Ref::try_resolve(repository, "HEAD")?;
+    let old_tree = match &old_head {
+        Some(hash) => Tree::of_commit(repository, hash)?,
+        None => Tree {
+            records: Vec::new(),
+        },
+    };
+    let config = Config::load(repository)?;
+    let trust_executable_bit = config.get_bool("core.fileMode")?.unwrap_or(true);
+    let local = diff::diff_worktree(
+        repository,
+        &old_tree,
+        &old_tree,
+        &repository.worktree,
+        trust_executable_bit,
+        &Pathspec::default(),
+    )?;
+    if !local.is_empty() {
+        bail!("Refusing to rewrite history with local changes; commit or stash them first");
+    }
+
+    let mut filter = Filter {
+        repository,
+        options,
+        plan,
+        empty_tree: GitrsObject::TreeObject(Tree {
+            records: Vec::new(),
+        })
+        .hash(),
+        trees: HashMap::new(),
+        commits: IndexMap::new(),
+        commit_trees: HashMap::new(),
+        pruned: 0,
+        tags: HashMap::new(),
+        large: HashMap::new(),
+    };
+
+    // Notes and replacements refer to commits by their hash without being history themselves, and
+    // symbolic refs follow whatever they point to
+    let mut refs = Vec::new();
+    for (name, hash) in Ref::list(repository)? {
+        let skipped = name.starts_with("refs/notes/") || name.starts_with("refs/replace/");
+        if !skipped && Ref::read_symbolic(repository, &name)?.is_none() {
+            refs.push((name, hash));
+        }
+    }
+    if let (Some(hash), None) = (&old_head, Ref::read_symbolic(repository, "HEAD")?) {
+        refs.push(("HEAD".to_string(), hash.clone()));
+    }
+
+    let mut updates = Vec::new();
+    for (name, hash) in refs {
+        let new = filter.rewrite(&hash)?;
+        if new.as_deref() != Some(hash.as_str()) {
+            updates.push(RefUpdate {
+                name,
+                new,
+                old: Some(hash),
+                verify_only: false,
+                deref: false,
+            });
+        }
+    }
+    // HEAD's commit was rewritten along with the ref it's on
+    let new_head = match &old_head {
+        Some(hash) => filter.rewrite(hash)?,
+        None => None,
+    };
+    let plan = filter.plan;
+    plan.update_refs(repository, &updates)?;
+
+    if new_head != old_head {
+        plan.perform(Action::CheckOut(new_head.clone()), || {
+            let new_tree = match &new_head {
+                Some(hash) => Tree::of_commit(repository, hash)?,
+                None => Tree {
+                    records: Vec::new(),
+                },
+            };
+            let changes =
+                diff::diff_trees(repository, &old_tree, &new_tree, true, &Pathspec::default())?;
+            switch::update_worktree(repository, &changes)
+        })?;
+    }
+
+    let mut map = format!("{:<40} new\n", "old");
+    for (old, new) in &filter.commits {
+        map.push_str(&format!(
+            "{} {}\n",
+            old,
+            new.as_deref().unwrap_or(ZERO_HASH)
+        ));
+    }
+    plan.write_file(
+        &repository.gitdir.join("filter-repo").join("commit-map"),
+        map.as_bytes(),
+    )?;
+
+    Ok(Filtered {
+        stripped: filter.large.values().filter(|&&large| large).count(),
+        commits: filter.commits,
+        pruned: filter.pruned,
+        refs: updates
+            .into_iter()
+            .map(|update| (update.name, update.new))
+            .collect(),
+    })
+}
+
+impl Filter<'_> {
+    // Rewrites the object a ref points to, leaving anything but commits and tags as it is
+    fn rewrite(&mut self, hash: &str) -> anyhow::Result<Option<String>> {
+        match GitrsObject::read_raw(self.repository, hash)?.get_type() {
+            ObjectType::Commit => self.rewrite_commit(hash),
+            ObjectType::Tag => self.rewrite_tag(hash),
+            _ => Ok(Some(hash.to_string())),
+        }
+    }
+
+    // Rewrites the commit `hash` after all of its ancestors
+    fn rewrite_commit(&mut self, hash: &str) -> anyhow::Result<Option<String>> {
+        let mut stack = vec![hash.to_string()];
+        while let Some(hash) = stack.last().cloned() {
+            if self.commits.contains_key(&hash) {
+                stack.pop();
+                continue;
+            }
+            let commit = self.read_commit(&hash)?;
+            let pending: Vec<String> = commit
+                .parents()
+                .iter()
+                .filter(|parent| !self.commits.contains_key(*parent))
+                .cloned()
+                .collect();
+            if !pending.is_empty() {
+                stack.extend(pending);
+                continue;
+            }
+            stack.pop();
+            let new = self.rewrite_one(&hash, commit)?;
+            self.commits.insert(hash, new);
+        }
+        Ok(self.commits[hash].clone())
+    }
+
+    // Rewrites a commit whose parents have all been rewritten
+    fn rewrite_one(&mut self, hash: &str, mut commit: Commit) -> anyhow::Result<Option<String>> {
+        let mut parents = Vec::new();
+        for parent in commit.parents() {
+            if let Some(new) = &self.commits[parent]
+                && !parents.contains(new)
+            {
+                parents.push(new.clone());
+            }
+        }
+        let old_tree = commit.get_tree_hash().clone();
+        let tree = match self.rewrite_tree(&old_tree, "")? {
+            Some(tree) => tree,
+            None => self.plan.write_object(
+                self.repository,
+                GitrsObject::TreeObject(Tree {
+                    records: Vec::new(),
+                }),
+            )?,
+        };
+        let (old_author, old_committer) = (commit.author()?, commit.committer()?);
+        let (author, committer) = (self.map(&old_author), self.map(&old_committer));
+        if tree == old_tree
+            && parents == commit.parents()
+            && author == old_author
+            && committer == old_committer
+        {
+            return Ok(Some(hash.to_string()));
+        }
+
+        // A commit, or a merge with only one parent left, that no longer changes anything goes,
+        // unless it never did
+        if parents.len() <= 1 {
+            let was_empty = match commit.parents() {
+                [] => old_tree == self.empty_tree,
+                [parent] => self.tree_of(parent)? == old_tree,
+                _ => false,
+            };
+            let parent_tree = match parents.first() {
+                Some(parent) => self.tree_of(parent)?,
+                None => self.empty_tree.clone(),
+            };
+            if !was_empty && tree == parent_tree {
+                self.pruned += 1;
+                return Ok(parents.first().cloned());
+            }
+        }
+
+        commit.rewrite(&tree, &parents, &author, &committer);
+        let new = self
+            .plan
+            .write_object(self.repository, GitrsObject::CommitObject(commit))?;
+        self.commit_trees.insert(new.clone(), tree);
+        Ok(Some(new))
+    }
+
+    // Rewrites an annotated tag to point at what its object was rewritten to, returning None if
+    // nothing was left of it
+    fn rewrite_tag(&mut self, hash: &str) -> anyhow::Result<Option<String>> {
+        if let Some(new) = self.tags.get(hash) {
+            return Ok(new.clone());
+        }
+        let GitrsObject::TagObject(mut tag) = GitrsObject::read_raw(self.repository, hash)? else {
+            bail!("Expected a tag object: {}", hash);
+        };
+        let object = tag
+            .object()
+            .with_context(|| format!("Malformed tag: {}", hash))?
+            .to_string();
+        let tagger = tag.tagger();
+        let new_tagger = tagger.as_ref().map(|tagger| self.map(tagger));
+
+        let new = match self.rewrite(&object)? {
+            None => None,
+            Some(new) if new == object && new_tagger == tagger => Some(hash.to_string()),
+            Some(new) => {
+                let data = tag.serialize();
+                let payload = signature::split_signature(&data, ObjectType::Tag)
+                    .map_or(data, |(payload, _)| payload);
+                let mut tag = Tag::deserialize(&payload)?;
+                tag.rewrite(&new, new_tagger.as_ref());
+                Some(
+                    self.plan
+                        .write_object(self.repository, GitrsObject::TagObject(tag))?,
+                )
+            }
+        };
+        self.tags.insert(hash.to_string(), new.clone());
+        Ok(new)
+    }
+
+    // Rewrites the tree `hash` found at `prefix`, returning None if nothing was left of it
+    fn rewrite_tree(&mut self, hash: &str, prefix: &str) -> anyhow::Result<Option<String>> {
+        // Where a tree is only matters if paths are removed
+        let at = match self.options.remove_paths.is_empty() {
+            true => String::new(),
+            false => prefix.to_string(),
+        };
+        let key = (hash.to_string(), at);
+        if let Some(new) = self.trees.get(&key) {
+            return Ok(new.clone());
+        }
+        let GitrsObject::TreeObject(tree) = GitrsObject::read_raw(self.repository, hash)? else {
+            bail!("Expected a tree object: {}", hash);
+        };
+
+        let mut records = Vec::new();
+        let mut changed = false;
+        for mut leaf in tree.records {
+            let name = leaf.path.to_string_lossy();
+            let path = match prefix {
+                "" => name.into_owned(),
+                _ => format!("{}/{}", prefix, name),
+            };
+            if self.removed(&path) {
+                changed = true;
+                continue;
+            }
+            match Leaf::get_type_from_mode(&leaf.file_mode) {
+                ObjectType::Tree => match self.rewrite_tree(&leaf.hash, &path)? {
+                    Some(new) => {
+                        changed |= new != leaf.hash;
+                        leaf.hash = new;
+                    }
+                    None => {
+                        changed = true;
+                        continue;
+                    }
+                },
+                ObjectType::Blob if self.is_large(&leaf.hash)? => {
+                    changed = true;
+                    continue;
+                }
+                _ => {}
+            }
+            records.push(leaf);
+        }
+
+        let new = match (changed, records.is_empty()) {
+            (false, _) => Some(hash.to_string()),
+            (true, true) => None,
+            (true, false) => Some(
+                self.plan
+                    .write_object(self.repository, GitrsObject::TreeObject(Tree { records }))?,
+            ),
+        };
+        self.trees.insert(key, new.clone());
+        Ok(new)
+    }
+
+    fn removed(&self, path: &str) -> bool {
+        self.options.remove_paths.iter().any(|removed| {
+            let removed = removed.trim_end_matches('/');
+            path.strip_prefix(removed)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    fn is_large(&mut self, hash: &str) -> anyhow::Result<bool> {
+        let Some(limit) = self.options.max_blob_size else {
+            return Ok(false);
+        };
+        if let Some(&large) = self.large.get(hash) {
+            return Ok(large);
+        }
+        let GitrsObject::BlobObject(blob) = GitrsObject::read_raw(self.repository, hash)? else {
+            bail!("Expected a blob object: {}", hash);
+        };
+        let large = blob.data().len() as u64 > limit;
+        self.large.insert(hash.to_string(), large);
+        Ok(large)
+    }
+
+    fn map(&self, ident: &Ident) -> Ident {
+        match &self.options.author_map {
+            Some(mailmap) => {
+                let (name, email) = mailmap.map(&ident.name, &ident.email);
+                Ident {
+                    name,
+                    email,
+                    ..ident.clone()
+                }
+            }
+            None => ident.clone(),
+        }
+    }
+
+    fn tree_of(&self, commit: &str) -> anyhow::Result<String> {
+        match self.commit_trees.get(commit) {
+            Some(tree) => Ok(tree.clone()),
+            None => Ok(self.read_commit(commit)?.get_tree_hash().clone()),
+        }
+    }
+
+    fn read_commit(&self, hash: &str) -> anyhow::Result<Commit> {
+        match GitrsObject::read_raw(self.repository, hash)? {
+            GitrsObject::CommitObject(commit) => Ok(commit),
+            _ => bail!("Expected a commit object: {}", hash),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+// Checks the repository's objects, like git fsck. Every object is read and checked to be
+// well-formed, as git's fsck.c does for fsck and for objects received with --strict, and then the
+// objects reachable from refs, worktree HEADs and reflogs are walked to check that everything they
+// link to is there. Unreachable objects nothing else links to are reported as dangling, and can be
+// written to lost-found to recover them from. Checking connectivity only leaves the reachable
+// blobs unread and the hashes of objects unchecked, which is much faster.
+//
+// A commit needs `tree`, `parent`, `author` and `committer` headers in that order, though any other
+// headers can follow, and a tag the headers mktag requires. A tree's entries need a mode git writes
+// and a name that is safe to check out, which rules out empty names, slashes, `.`, `..` and `.git`
+// in any case, and must be sorted the way git sorts them, with no name twice. Like git's strict
+// mode, what git only warns about is an error here. unpack-objects --strict checks the objects it
+// receives the same way.
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+
+use anyhow::{Context, bail, ensure};
+
+use crate::ident::Ident;
+use crate::loose;
+use crate::object::tag::Tag;
+use crate::object::tree::Leaf;
+use crate::object::{GitrsObject, ObjectType};
+use crate::pack::PackIndex;
+use crate::prune;
+use crate::repository::Repository;
+
+#[derive(Default)]
+pub struct FsckOptions {
+    /// Only check that the reachable objects are there and link up, without reading blobs or
+    /// checking hashes
+    pub connectivity_only: bool,
+    /// Write dangling objects to lost-found
+    pub lost_found: bool,
+}
+
+/// An object linked to that isn't in the repository
+pub struct Missing {
+    pub hash: String,
+    /// The type it is linked to as, unless it is a root
+    pub object_type: Option<ObjectType>,
+    /// The object linking to it, unless it is a root
+    pub from: Option<(ObjectType, String)>,
+}
+
+#[derive(Default)]
+pub struct FsckReport {
+    /// The objects that are corrupt or malformed, with what is wrong with them
+    pub errors: Vec<(String, String)>,
+    pub missing: Vec<Missing>,
+    /// The unreachable objects that nothing else links to
+    pub dangling: Vec<(ObjectType, String)>,
+}
+
+impl FsckReport {
+    /// Whether anything was found to be wrong, dangling objects being no problem
+    pub fn is_broken(&self) -> bool {
+        !self.errors.is_empty() || !self.missing.is_empty()
+    }
+}
+
+// State of a check: what has been found wrong so far
+struct Fsck<'a> {
+    repository: &'a Repository,
+    options: &'a FsckOptions,
+    report: FsckReport,
+}
+
+/// Checks that `data` is a well-formed object of the given type, returning the objects it links
+/// to along with the type each should be. Submodule commits are left out, as they live in another
+/// repository.
+pub fn check(object_type: &ObjectType, data: &[u8]) -> anyhow::Result<Vec<(String, ObjectType)>> {
+    match object_type {
+        ObjectType::Blob => Ok(Vec::new()),
+        ObjectType::Commit => check_commit(data),
+        ObjectType::Tag => Ok(vec![Tag::check(data)?]),
+        ObjectType::Tree => check_tree(data),
+    }
+}
+
+/// Checks the repository's objects as `options` says
+pub fn fsck(repository: &Repository, options: &FsckOptions) -> anyhow::Result<FsckReport> {
+    let mut fsck = Fsck {
+        repository,
+        options,
+        report: FsckReport::default(),
+    };
+    let mut objects: BTreeSet<String> = loose::scan(repository)?
+        .objects
+        .into_iter()
+        .map(|object| object.hash)
+        .collect();
+    for index in PackIndex::load_all(repository)? {
+        objects.extend(index.objects().iter().cloned());
+    }
+
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<_> = prune::roots(repository)?
+        .into_iter()
+        .map(|hash| (hash, None, None))
+        .collect();
+    while let Some((hash, object_type, from)) = pending.pop() {
+        if !reachable.insert(hash.clone()) {
+            continue;
+        }
+        if !objects.contains(&hash) {
+            fsck.report.missing.push(Missing {
+                hash,
+                object_type,
+                from,
+            });
+            continue;
+        }
+        if options.connectivity_only && object_type == Some(ObjectType::Blob) {
+            continue;
+        }
+        let Some((actual, links)) = fsck.inspect(&hash) else {
+            continue;
+        };
+        if let Some(expected) = object_type.filter(|expected| *expected != actual) {
+            let error = format!("linked to as a {}, but is a {}", expected, actual);
+            fsck.report.errors.push((hash, error));
+            continue;
+        }
+        for (link, link_type) in links {
+            pending.push((link, Some(link_type), Some((actual.clone(), hash.clone()))));
+        }
+    }
+
+    // Dangling objects are the unreachable ones no other unreachable object links to
+    let mut unreachable = HashMap::new();
+    for hash in objects.iter().filter(|hash| !reachable.contains(*hash)) {
+        if let Some(object) = fsck.inspect(hash) {
+            unreachable.insert(hash.as_str(), object);
+        }
+    }
+    let linked: HashSet<&String> = unreachable
+        .values()
+        .flat_map(|(_, links)| links.iter().map(|(link, _)| link))
+        .collect();
+    for hash in &objects {
+        if let Some((object_type, _)) = unreachable.get(hash.as_str())
+            && !linked.contains(hash)
+        {
+            fsck.report
+                .dangling
+                .push((object_type.clone(), hash.clone()));
+        }
+    }
+
+    if options.lost_found {
+        lost_found(repository, &fsck.report.dangling)?;
+    }
+    Ok(fsck.report)
+}
+
+impl Fsck<'_> {
+    // Reads the object `hash`, returning its type and what it links to, or None if it is broken,
+    // which is reported
+    fn inspect(&mut self, hash: &str) -> Option<(ObjectType, Vec<(String, ObjectType)>)> {
+        let result =
+            GitrsObject::read_data(self.repository, hash).and_then(|(object_type, data)| {
+                if !self.options.connectivity_only {
+                    let actual = GitrsObject::hash_data(object_type.clone(), &data);
+                    ensure!(actual == hash, "hash mismatch, contents hash to {}", actual);
+                }
+                let links = check(&object_type, &data)?;
+                Ok((object_type, links))
+            });
+        match result {
+            Ok(object) => Some(object),
+            Err(e) => {
+                self.report.errors.push((hash.to_string(), e.to_string()));
+                None
+            }
+        }
+    }
+}
+
+// Writes the dangling objects to lost-found, commits to commit/ and the rest to other/, each in a
+// file named after its hash holding the hash, or for blobs their contents
+fn lost_found(repository: &Repository, dangling: &[(ObjectType, String)]) -> anyhow::Result<()> {
+    for (object_type, hash) in dangling {
+        let kind = match object_type {
+            ObjectType::Commit => "commit",
+            _ => "other",
+        };
+        let dir = repository.get_path(&["lost-found", kind]);
+        fs::create_dir_all(&dir).with_context(|| format!("Could not create {}", dir.display()))?;
+        let contents = match object_type {
+            ObjectType::Blob => GitrsObject::read_data(repository, hash)?.1,
+            _ => format!("{}\n", hash).into_bytes(),
+        };
+        let path = dir.join(hash);
+        fs::write(&path, contents)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+fn check_commit(data: &[u8]) -> anyhow::Result<Vec<(String, ObjectType)>> {
+    let end = data
+        .windows(2)
+        .position(|pair| pair == b"\n\n")
+        .map(|end| end + 1)
+        .or_else(|| data.ends_with(b"\n").then_some(data.len()))
+        .context("Unterminated commit header")?;
+    let headers = String::from_utf8_lossy(&data[..end]);
+    let mut lines = headers.lines().peekable();
+
+    let tree = lines
+        .next()
+        .and_then(|line| line.strip_prefix("tree "))
+        .context("Invalid format, expected 'tree' line")?;
+    let mut links = vec![(check_hash(tree)?, ObjectType::Tree)];
+    while let Some(parent) = lines.peek().and_then(|line| line.strip_prefix("parent ")) {
+        links.push((check_hash(parent)?, ObjectType::Commit));
+        lines.next();
+    }
+    for key in ["author", "committer"] {
+        let ident = lines
+            .next()
+            .and_then(|line| line.strip_prefix(key)?.strip_prefix(' '))
+            .with_context(|| format!("Invalid format, expected '{}' line", key))?;
+        Ident::parse(ident)?;
+    }
+    Ok(links)
+}
+
+fn check_tree(mut data: &[u8]) -> anyhow::Result<Vec<(String, ObjectType)>> {
+    let mut links = Vec::new();
+    let mut names = HashSet::new();
+    let mut previous: Option<Vec<u8>> = None;
+    while !data.is_empty() {
+        let space = data
+            .iter()
+            .position(|&byte| byte == b' ')
+            .context("Malformed tree: missing space after mode")?;
+        let mode = std::str::from_utf8(&data[..space]).context("Malformed tree: bad mode")?;
+        ensure!(
+            ["100644", "100755", "120000", "40000", "160000"].contains(&mode),
+            "Malformed tree: bad mode {}",
+            mode
+        );
+        let rest = &data[space + 1..];
+        let nul = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .context("Malformed tree: missing NUL after name")?;
+        let name = &rest[..nul];
+        ensure!(
+            rest.len() >= nul + 21,
+            "Malformed tree: truncated entry {}",
+            String::from_utf8_lossy(name)
+        );
+        let hash = hex::encode(&rest[nul + 1..nul + 21]);
+        data = &rest[nul + 21..];
+
+        let shown = String::from_utf8_lossy(name);
+        if name.is_empty() || name.contains(&b'/') {
+            bail!("Tree has a bad name: '{}'", shown);
+        }
+        if name == b"." || name == b".." || name.eq_ignore_ascii_case(b".git") {
+            bail!("Tree has an entry that can't be checked out: '{}'", shown);
+        }
+        ensure!(names.insert(name.to_vec()), "Tree has '{}' twice", shown);
+        // Trees sort as though their names ended with a slash
+        let object_type = Leaf::get_type_from_mode(mode);
+        let mut key = name.to_vec();
+        if object_type == ObjectType::Tree {
+            key.push(b'/');
+        }
+        if previous.as_ref().is_some_and(|previous| *previous >= key) {
+            bail!("Tree is not sorted at '{}'", shown);
+        }
+        previous = Some(key);
+
+        if object_type != ObjectType::Commit {
+            links.push((hash, object_type));
+        }
+    }
+    Ok(links)
+}
+
+// Checks that a hash in a header is 40 lowercase hex digits
+fn check_hash(hash: &str) -> anyhow::Result<String> {
+    ensure!(
+        hash.len() == 40
+            && hash
+                .bytes()
+                .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f')),
+        "Invalid object hash: {}",
+        hash
+    );
+    Ok(hash.to_string())
+}
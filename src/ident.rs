@@ -0,0 +1,170 @@
+// Identities as recorded in commit and tag headers: `Name <email> timestamp timezone`. The author
+// and committer of new objects are resolved like git does: GIT_AUTHOR_NAME and friends from the
+// environment, then author.* or committer.* config, then user.*, and finally the account name and
+// hostname (unless user.useConfigOnly is set). GIT_AUTHOR_DATE and GIT_COMMITTER_DATE override the
+// current time.
+use std::env;
+use std::fmt;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+
+use crate::config::Config;
+use crate::date;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub email: String,
+    // Seconds since the epoch
+    pub timestamp: i64,
+    // Offset from UTC as written, eg. `+0100`
+    pub timezone: String,
+}
+
+impl Ident {
+    pub fn parse(raw: &str) -> anyhow::Result<Self> {
+        let bad_ident = || anyhow!("Malformed identity: {}", raw);
+        let (name, rest) = raw.split_once('<').ok_or_else(bad_ident)?;
+        let (email, date) = rest.split_once('>').ok_or_else(bad_ident)?;
+        let mut date = date.split_whitespace();
+        let timestamp = date
+            .next()
+            .and_then(|timestamp| timestamp.parse().ok())
+            .ok_or_else(bad_ident)?;
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            timestamp,
+            timezone: date.next().unwrap_or("+0000").to_string(),
+        })
+    }
+
+    /// The identity to record as the author of new commits and tags
+    pub fn author(config: &Config) -> anyhow::Result<Self> {
+        Self::resolve(config, "author")
+    }
+
+    /// The identity to record as the committer of new commits, and in reflogs
+    pub fn committer(config: &Config) -> anyhow::Result<Self> {
+        Self::resolve(config, "committer")
+    }
+
+    fn resolve(config: &Config, role: &str) -> anyhow::Result<Self> {
+        let variable = |field: &str| {
+            env::var(format!(
+                "GIT_{}_{}",
+                role.to_uppercase(),
+                field.to_uppercase()
+            ))
+            .ok()
+            .or_else(|| {
+                config
+                    .get(&format!("{}.{}", role, field))
+                    .map(str::to_string)
+            })
+            .or_else(|| config.get(&format!("user.{}", field)).map(str::to_string))
+        };
+        let name = variable("name");
+        let email = variable("email").or_else(|| env::var("EMAIL").ok());
+
+        let (name, email) = match (name, email) {
+            (Some(name), Some(email)) => (name, email),
+            _ if config.get_bool("user.useConfigOnly")?.unwrap_or(false) => {
+                bail!(
+                    "no name was given and auto-detection is disabled, set user.name and user.email"
+                )
+            }
+            (name, email) => {
+                let user = system_user().ok_or_else(|| {
+                    anyhow!("Author identity unknown, set user.name and user.email")
+                })?;
+                let email =
+                    email.unwrap_or_else(|| format!("{}@{}", user.login, system_hostname()));
+                (name.unwrap_or(user.full_name), email)
+            }
+        };
+        if name.trim().is_empty() {
+            bail!("empty ident name (for <{}>) not allowed", email);
+        }
+
+        let date = env::var(format!("GIT_{}_DATE", role.to_uppercase()));
+        let (timestamp, timezone) = match date {
+            Ok(date) => date::parse(&date)?,
+            Err(_) => (
+                SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+                "+0000".to_string(),
+            ),
+        };
+        Ok(Self {
+            name: name.trim().to_string(),
+            email: email.trim().to_string(),
+            timestamp,
+            timezone,
+        })
+    }
+}
+
+struct SystemUser {
+    login: String,
+    // From the comment field of the passwd entry, falling back to the login
+    full_name: String,
+}
+
+// Looks up the account running gitrs in /etc/passwd, by the login in the environment or else by
+// the user id
+fn system_user() -> Option<SystemUser> {
+    let login = ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|variable| env::var(variable).ok().filter(|value| !value.is_empty()));
+    let uid = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status.lines().find_map(|line| {
+                Some(
+                    line.strip_prefix("Uid:")?
+                        .split_whitespace()
+                        .next()?
+                        .to_string(),
+                )
+            })
+        });
+    let passwd = fs::read_to_string("/etc/passwd").unwrap_or_default();
+    let entry = passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .find(|fields| match &login {
+            Some(login) => fields[0] == login,
+            None => fields.get(2).copied() == uid.as_deref(),
+        });
+
+    let login = login.or_else(|| entry.as_ref().map(|fields| fields[0].to_string()))?;
+    let full_name = entry
+        .and_then(|fields| fields.get(4)?.split(',').next().map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| login.clone());
+    Some(SystemUser { login, full_name })
+}
+
+fn system_hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| fs::read_to_string(path).ok())
+        .map(|hostname| hostname.trim().to_string())
+        .or_else(|| env::var("HOSTNAME").ok())
+        .or_else(|| env::var("COMPUTERNAME").ok())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name, self.email, self.timestamp, self.timezone
+        )
+    }
+}
@@ -0,0 +1,125 @@
+// Decides which untracked worktree paths are ignored. Patterns are read from core.excludesFile,
+// `info/exclude` and the `.gitignore` file in each directory leading up to a path, in increasing
+// order of precedence. The last matching pattern wins, and `!` patterns re-include paths.
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::repository::Repository;
+use crate::wildmatch::path_matches;
+
+struct IgnoreRule {
+    pattern: String,
+    // Directory (relative to the worktree) of the .gitignore the rule was read from
+    base: String,
+    negated: bool,
+    // Patterns with a trailing slash only match directories
+    dir_only: bool,
+}
+
+pub struct Ignore<'a> {
+    repository: &'a Repository,
+    // core.excludesFile followed by info/exclude
+    global_rules: Vec<IgnoreRule>,
+    // Rules read from each directory's `.gitignore`, keyed by directory
+    dir_rules: HashMap<String, Vec<IgnoreRule>>,
+}
+
+impl<'a> Ignore<'a> {
+    pub fn new(repository: &'a Repository, config: &Config) -> Self {
+        let excludes_file = config.get("core.excludesFile").map(|path| {
+            match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+                (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+                _ => PathBuf::from(path),
+            }
+        });
+
+        let global_rules = excludes_file
+            .into_iter()
+            .chain(repository.get_path_to_file(&["info", "exclude"]))
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .flat_map(|content| Self::parse(&content, ""))
+            .collect();
+
+        Self {
+            repository,
+            global_rules,
+            dir_rules: HashMap::new(),
+        }
+    }
+
+    /// Returns true if `path` (relative to the worktree, `/` separated) is ignored, either directly
+    /// or because one of its parent directories is
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> bool {
+        let components: Vec<&str> = path.split('/').collect();
+        (1..components.len()).any(|i| self.is_excluded(&components[..i].join("/"), true))
+            || self.is_excluded(path, is_dir)
+    }
+
+    // Checks the patterns against `path` itself, ignoring its parents
+    fn is_excluded(&mut self, path: &str, is_dir: bool) -> bool {
+        let mut dirs = vec![String::new()];
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for i in 1..=components.len() {
+            dirs.push(components[..i].join("/"));
+        }
+
+        dirs.iter().for_each(|dir| self.load_dir(dir));
+        self.global_rules
+            .iter()
+            .chain(dirs.iter().flat_map(|dir| self.dir_rules[dir].iter()))
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only) && path_matches(&rule.pattern, &rule.base, path)
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    // Loads and caches the rules from `dir/.gitignore`
+    fn load_dir(&mut self, dir: &str) {
+        if !self.dir_rules.contains_key(dir) {
+            let path = self.repository.worktree.join(dir).join(".gitignore");
+            let rules = fs::read_to_string(path)
+                .map(|content| Self::parse(&content, dir))
+                .unwrap_or_default();
+            self.dir_rules.insert(dir.to_string(), rules);
+        }
+    }
+
+    fn parse(content: &str, base: &str) -> Vec<IgnoreRule> {
+        content
+            .lines()
+            .filter_map(|line| {
+                // Trailing spaces are dropped unless escaped
+                let trimmed = line.trim_end_matches(' ');
+                let line = if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+                    line[..trimmed.len() + 1].to_string()
+                } else {
+                    trimmed.to_string()
+                };
+
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest.to_string()),
+                    None => (false, line),
+                };
+                let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                    Some(rest) => (true, rest.to_string()),
+                    None => (false, pattern),
+                };
+
+                Some(IgnoreRule {
+                    pattern,
+                    base: base.to_string(),
+                    negated,
+                    dir_only,
+                })
+            })
+            .collect()
+    }
+}
@@ -1,4 +1,5 @@
 // Key-Value List with Message
+use anyhow::Context;
 use indexmap::IndexMap;
 
 pub struct Kvlm {
@@ -12,10 +13,10 @@ pub struct Kvlm {
 }
 
 impl Kvlm {
-    pub fn new(raw_data: &[u8]) -> Self {
-        Self {
-            data: Self::parse(raw_data),
-        }
+    pub fn new(raw_data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            data: Self::parse(raw_data)?,
+        })
     }
 
     pub fn init() -> Self {
@@ -32,7 +33,7 @@ impl Kvlm {
                 for value in values {
                     // Replace `\n` with `\n `
                     let mut encoded = Vec::with_capacity(value.len());
-                    for (i, part) in value.split(|ch| ch == '\n').enumerate() {
+                    for (i, part) in value.split('\n').enumerate() {
                         if i > 0 {
                             encoded.extend_from_slice(b"\n ");
                         }
@@ -48,13 +49,10 @@ impl Kvlm {
         }
 
         // Append the message (None key), if it exists
-        if let Some(messages) = self.data.get(&None) {
-            if let Some(message) = messages.first() {
-                // Don't need to iterate since there
-                // is always just 1 message
-                output.push(b'\n');
-                output.extend_from_slice(message.as_bytes());
-            }
+        // Don't need to iterate since there is always just 1 message
+        if let Some(message) = self.data.get(&None).and_then(|messages| messages.first()) {
+            output.push(b'\n');
+            output.extend_from_slice(message.as_bytes());
         }
 
         output
@@ -70,7 +68,11 @@ impl Kvlm {
     }
 
     pub fn get_key(&self, key: &str) -> Option<&Vec<String>> {
-        self.data.get(&Some(key.to_string()))
+        // Objects only have a handful of keys, so scanning them beats allocating a lookup key
+        self.data
+            .iter()
+            .find(|(name, _)| name.as_deref() == Some(key))
+            .map(|(_, values)| values)
     }
 
     pub fn insert(&mut self, key: &str, value: &str) {
@@ -78,7 +80,32 @@ impl Kvlm {
             .insert(Some(key.to_string()), vec![value.to_string()]);
     }
 
-    fn parse(raw_data: &[u8]) -> IndexMap<Option<String>, Vec<String>> {
+    /// Adds another value for `key`, keeping any existing ones
+    pub fn append(&mut self, key: &str, value: &str) {
+        self.data
+            .entry(Some(key.to_string()))
+            .or_default()
+            .push(value.to_string());
+    }
+
+    /// Replaces the values of `key`, keeping its place, or removes it if there are none
+    pub fn set_values(&mut self, key: &str, values: &[String]) {
+        let key = Some(key.to_string());
+        match values.is_empty() {
+            true => {
+                self.data.shift_remove(&key);
+            }
+            false => {
+                self.data.insert(key, values.to_vec());
+            }
+        }
+    }
+
+    pub fn set_message(&mut self, message: &str) {
+        self.data.insert(None, vec![message.to_string()]);
+    }
+
+    fn parse(raw_data: &[u8]) -> anyhow::Result<IndexMap<Option<String>, Vec<String>>> {
         let mut pos = 0;
         let mut result: IndexMap<Option<String>, Vec<String>> = IndexMap::new();
 
@@ -95,9 +122,9 @@ impl Kvlm {
                 .iter()
                 .position(|&b| b == b' ')
                 .map(|i| i + pos)
-                .expect("Expected space after key");
+                .context("Expected space after key")?;
 
-            let key = String::from_utf8_lossy(&raw_data[pos..space_idx]).into_owned();
+            let key = &raw_data[pos..space_idx];
 
             // Find the end of the value, including continuation lines
             let mut end = space_idx;
@@ -106,29 +133,37 @@ impl Kvlm {
                     .iter()
                     .position(|&b| b == b'\n')
                     .map(|i| i + end + 1)
-                    .expect("Expected newline");
+                    .context("Expected newline")?;
 
                 end = newline_idx;
                 if raw_data.get(newline_idx + 1) != Some(&b' ') {
                     break;
                 }
-                end = newline_idx;
             }
 
-            // Extract and de-indent continuation lines
-            let value_raw_data = &raw_data[space_idx + 1..=end];
-            let value = String::from_utf8_lossy(value_raw_data)
-                .replace("\n ", "\n")
-                .to_string();
-
-            result
-                .entry(Some(key))
-                .and_modify(|v| v.push(value.clone()))
-                .or_insert_with(|| vec![value]);
+            // Extract and de-indent continuation lines, which only multi-line values (eg.
+            // signatures) need
+            let value = String::from_utf8_lossy(&raw_data[space_idx + 1..end]);
+            let value = if value.contains("\n ") {
+                value.replace("\n ", "\n")
+            } else {
+                value.into_owned()
+            };
+
+            // Repeated keys (eg. `parent`) follow each other, so only the last entry needs checking
+            match result.last_mut() {
+                Some((Some(last), values)) if last.as_bytes() == key => values.push(value),
+                _ => result
+                    .entry(Some(String::from_utf8_lossy(key).into_owned()))
+                    .or_default()
+                    .push(value),
+            }
 
             pos = end + 1;
         }
+        // Without a blank line there is no message, which is as good as an empty one
+        result.entry(None).or_insert_with(|| vec![String::new()]);
 
-        result
+        Ok(result)
     }
 }
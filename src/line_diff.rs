@@ -0,0 +1,1252 @@
+// Compares the lines of two versions of a file the way git's xdiff does, so that diffs come out
+// the same as git's. Lines are first grouped into classes of equal lines (after normalizing any
+// whitespace being ignored), the lines in common at the start and end are set aside, and lines
+// with no match on the other side are taken as changed without searching for them. The rest is
+// compared with Myers' O(ND) algorithm, searching from both ends at once and splitting at the
+// middle snake found, with git's heuristics for settling on a good enough split when the search
+// gets expensive. The patience and histogram algorithms instead recursively split the files at
+// the lines they have in common that are unique to each side, or the rarest ones, only falling
+// back to Myers where there are none. Finally each group of changed lines is slid to where git
+// would show it: as far down as it goes, unless that lines it up with a change on the other side,
+// or, with the indent heuristic, to where the indentation suggests the change begins and ends.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::{Index, IndexMut, Range};
+
+/// A region of `old` replaced by a region of `new`, either of which may be empty
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Which differences in whitespace lines are compared with, each ignoring more than the last
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Whitespace {
+    #[default]
+    Exact,
+    /// Ignore whitespace at the end of lines, including a missing final newline
+    IgnoreAtEol,
+    /// Ignore changes in the amount of whitespace, as well as at the end of lines
+    IgnoreChange,
+    /// Ignore all whitespace
+    IgnoreAll,
+}
+
+/// How the lines that changed are found, as git's --diff-algorithm names them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    /// Myers without the heuristics that trade the smallest diff for speed
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct DiffOptions {
+    pub whitespace: Whitespace,
+    /// Place changes by how the lines around them are indented, as git diff does by default
+    pub indent_heuristic: bool,
+    pub algorithm: Algorithm,
+}
+
+// Lines occurring more often than this on the old side aren't split at by the histogram algorithm
+const MAX_CHAIN_LENGTH: usize = 64;
+
+// Past this edit cost, the search settles for splitting where it got furthest
+const MAX_COST_MIN: isize = 256;
+// The edit cost from which the search looks out for long snakes to split at
+const HEURISTIC_MIN_COST: isize = 256;
+// How long a run of matching lines has to be to make a good place to split
+const SNAKE_COUNT: isize = 20;
+const HEURISTIC_FACTOR: isize = 4;
+// Lines with this many matches (or the square root of the number of lines, if fewer) may be set
+// aside when they sit among lines without any, looking this far around them
+const MAX_EQUAL_LIMIT: usize = 1024;
+const SIMILAR_SCAN_WINDOW: usize = 100;
+const DISCARD_RUN_FACTOR: usize = 4;
+
+/// Lists the regions where the lines `old` and `new` differ, in order. Everything between them is
+/// the same on both sides.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], options: &DiffOptions) -> Vec<Region> {
+    // Lines are compared by the class of lines equal to them
+    let mut classes: HashMap<Cow<[u8]>, usize> = HashMap::new();
+    let [old_classes, new_classes] = [old, new].map(|lines| {
+        lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                *classes
+                    .entry(options.whitespace.normalize(line))
+                    .or_insert(next)
+            })
+            .collect()
+    });
+    let mut files = [File::new(old, old_classes), File::new(new, new_classes)];
+    let [old_file, new_file] = &mut files;
+    let (a, b) = (0..old.len(), 0..new.len());
+    match options.algorithm {
+        Algorithm::Myers => myers(old_file, new_file, a, b, false),
+        Algorithm::Minimal => myers(old_file, new_file, a, b, true),
+        Algorithm::Patience => patience(old_file, new_file, a, b),
+        Algorithm::Histogram => histogram(old_file, new_file, a, b),
+    }
+
+    compact(old_file, new_file, options.indent_heuristic);
+    compact(new_file, old_file, options.indent_heuristic);
+    regions(old_file, new_file)
+}
+
+/// Splits a file's content into lines, each keeping its line terminator
+pub fn lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+impl Algorithm {
+    /// Parses an algorithm's name, as --diff-algorithm and diff.algorithm take it
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "myers" | "default" => Some(Algorithm::Myers),
+            "minimal" => Some(Algorithm::Minimal),
+            "patience" => Some(Algorithm::Patience),
+            "histogram" => Some(Algorithm::Histogram),
+            _ => None,
+        }
+    }
+}
+
+impl Whitespace {
+    /// Rewrites `line` so that lines differing only in the ignored whitespace become the same
+    pub fn normalize(self, line: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            Whitespace::Exact => Cow::Borrowed(line),
+            Whitespace::IgnoreAtEol => {
+                let end = line.len() - line.iter().rev().take_while(|b| is_space(b)).count();
+                Cow::Borrowed(&line[..end])
+            }
+            Whitespace::IgnoreChange => {
+                let mut normalized = Vec::with_capacity(line.len());
+                let mut words = line.split(is_space).filter(|word| !word.is_empty());
+                if line.first().is_some_and(is_space) {
+                    normalized.push(b' ');
+                }
+                if let Some(word) = words.next() {
+                    normalized.extend_from_slice(word);
+                }
+                for word in words {
+                    normalized.push(b' ');
+                    normalized.extend_from_slice(word);
+                }
+                Cow::Owned(normalized)
+            }
+            Whitespace::IgnoreAll => {
+                Cow::Owned(line.iter().copied().filter(|b| !is_space(b)).collect())
+            }
+        }
+    }
+}
+
+/// Whether git counts `b` as whitespace, which unlike C leaves out vertical tabs and form feeds
+pub fn is_space(b: &u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+// One side of the comparison, with the lines found to be changed so far
+struct File<'a> {
+    lines: &'a [&'a [u8]],
+    classes: Vec<usize>,
+    changed: Vec<bool>,
+}
+
+impl<'a> File<'a> {
+    fn new(lines: &'a [&'a [u8]], classes: Vec<usize>) -> Self {
+        Self {
+            lines,
+            classes,
+            changed: vec![false; lines.len()],
+        }
+    }
+
+    fn len(&self) -> isize {
+        self.lines.len() as isize
+    }
+
+    fn is_changed(&self, i: isize) -> bool {
+        (0..self.len()).contains(&i) && self.changed[i as usize]
+    }
+}
+
+// Marks the lines that differ between the lines `a` of old and `b` of new with Myers' algorithm.
+// These are compared as if they were the whole files, the lines in common at their start and end
+// set aside and the lines that can be taken as changed judged by the counts of lines within them.
+fn myers(old: &mut File, new: &mut File, a: Range<usize>, b: Range<usize>, minimal: bool) {
+    // The lines of each class on either side
+    let mut counts: HashMap<usize, [usize; 2]> = HashMap::new();
+    for (side, (file, range)) in [(&*old, &a), (&*new, &b)].into_iter().enumerate() {
+        for i in range.clone() {
+            counts.entry(file.classes[i]).or_default()[side] += 1;
+        }
+    }
+
+    let shortest = a.len().min(b.len());
+    let prefix = (0..shortest)
+        .take_while(|&i| old.classes[a.start + i] == new.classes[b.start + i])
+        .count();
+    let suffix = (0..shortest - prefix)
+        .take_while(|&i| old.classes[a.end - 1 - i] == new.classes[b.end - 1 - i])
+        .count();
+
+    // The index and class of each line left to compare
+    let keep = |file: &mut File, range: Range<usize>, side: usize| {
+        let length = range.len();
+        let range = range.start + prefix..range.end - suffix;
+        let discards = discards(file, range.clone(), length, &counts, side, minimal);
+        let mut kept = Vec::new();
+        for (i, discard) in range.zip(discards) {
+            if discard {
+                file.changed[i] = true;
+            } else {
+                kept.push((i, file.classes[i]));
+            }
+        }
+        kept
+    };
+    let (old_lines, new_lines) = (keep(old, a, 0), keep(new, b, 1));
+
+    let mut search = Search {
+        old: &old_lines,
+        new: &new_lines,
+        forward: Diagonals::new(old_lines.len(), new_lines.len()),
+        backward: Diagonals::new(old_lines.len(), new_lines.len()),
+        max_cost: bogo_sqrt(old_lines.len() + new_lines.len() + 3).max(MAX_COST_MIN),
+    };
+    let (old_length, new_length) = (old_lines.len() as isize, new_lines.len() as isize);
+    search.compare(0..old_length, 0..new_length, minimal, old, new);
+}
+
+// Picks out the lines in `range`, out of `length` being compared, that can be taken as changed
+// without comparing them: those that don't appear on the other side at all, and unless the diff
+// has to be minimal, those appearing there too often to be worth matching when they sit among
+// lines of the first kind
+fn discards(
+    file: &File,
+    range: Range<usize>,
+    length: usize,
+    counts: &HashMap<usize, [usize; 2]>,
+    side: usize,
+    minimal: bool,
+) -> Vec<bool> {
+    let limit = (bogo_sqrt(length) as usize).min(MAX_EQUAL_LIMIT);
+    let matches: Vec<Matches> = range
+        .map(|i| {
+            match counts
+                .get(&file.classes[i])
+                .map_or(0, |count| count[1 - side])
+            {
+                0 => Matches::None,
+                count if count >= limit && !minimal => Matches::Many,
+                _ => Matches::Some,
+            }
+        })
+        .collect();
+    (0..matches.len())
+        .map(|i| match matches[i] {
+            Matches::None => true,
+            Matches::Some => false,
+            Matches::Many => among_unmatched(&matches, i),
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Matches {
+    None,
+    Some,
+    Many,
+}
+
+// Checks whether the line at `i`, which has many matches, sits in a run of lines with many or no
+// matches that mostly has none
+fn among_unmatched(matches: &[Matches], i: usize) -> bool {
+    let run = |around: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut many) = (0, 1);
+        for j in around {
+            match matches[j] {
+                Matches::None => unmatched += 1,
+                Matches::Many => many += 1,
+                Matches::Some => break,
+            }
+        }
+        (unmatched, many)
+    };
+    let start = i.saturating_sub(SIMILAR_SCAN_WINDOW);
+    let (unmatched_before, many_before) = run(&mut (start..i).rev());
+    if unmatched_before == 0 {
+        return false;
+    }
+    let end = (i + SIMILAR_SCAN_WINDOW).min(matches.len() - 1);
+    let (unmatched_after, many_after) = run(&mut (i + 1..=end));
+    if unmatched_after == 0 {
+        return false;
+    }
+    let many = many_before + many_after;
+    many * DISCARD_RUN_FACTOR < many + unmatched_before + unmatched_after
+}
+
+// The rough square root xdiff bases its limits on
+fn bogo_sqrt(mut n: usize) -> isize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+// The furthest line of old reached so far on each diagonal of the edit graph, which is indexed
+// by the difference between the lines of old and new
+struct Diagonals {
+    furthest: Vec<isize>,
+    offset: isize,
+}
+
+impl Diagonals {
+    fn new(old_length: usize, new_length: usize) -> Self {
+        Self {
+            furthest: vec![0; old_length + new_length + 3],
+            offset: new_length as isize + 1,
+        }
+    }
+}
+
+impl Index<isize> for Diagonals {
+    type Output = isize;
+
+    fn index(&self, diagonal: isize) -> &isize {
+        &self.furthest[(diagonal + self.offset) as usize]
+    }
+}
+
+impl IndexMut<isize> for Diagonals {
+    fn index_mut(&mut self, diagonal: isize) -> &mut isize {
+        &mut self.furthest[(diagonal + self.offset) as usize]
+    }
+}
+
+// Where to split a comparison in two, and whether each half still has to be solved minimally
+struct Split {
+    old: isize,
+    new: isize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+impl Split {
+    fn new(old: isize, new: isize, minimal_before: bool, minimal_after: bool) -> Self {
+        Self {
+            old,
+            new,
+            minimal_before,
+            minimal_after,
+        }
+    }
+}
+
+// The lines being compared, as the index and class of each, and the state of the search
+struct Search<'a> {
+    old: &'a [(usize, usize)],
+    new: &'a [(usize, usize)],
+    forward: Diagonals,
+    backward: Diagonals,
+    max_cost: isize,
+}
+
+impl Search<'_> {
+    fn same(&self, i: isize, j: isize) -> bool {
+        self.old[i as usize].1 == self.new[j as usize].1
+    }
+
+    // Marks the lines that differ between the lines `a` of old and `b` of new as changed
+    fn compare(
+        &mut self,
+        mut a: Range<isize>,
+        mut b: Range<isize>,
+        minimal: bool,
+        old_file: &mut File,
+        new_file: &mut File,
+    ) {
+        while a.start < a.end && b.start < b.end && self.same(a.start, b.start) {
+            a.start += 1;
+            b.start += 1;
+        }
+        while a.start < a.end && b.start < b.end && self.same(a.end - 1, b.end - 1) {
+            a.end -= 1;
+            b.end -= 1;
+        }
+
+        if a.is_empty() {
+            for j in b {
+                new_file.changed[self.new[j as usize].0] = true;
+            }
+        } else if b.is_empty() {
+            for i in a {
+                old_file.changed[self.old[i as usize].0] = true;
+            }
+        } else {
+            let split = self.split(a.clone(), b.clone(), minimal);
+            self.compare(
+                a.start..split.old,
+                b.start..split.new,
+                split.minimal_before,
+                old_file,
+                new_file,
+            );
+            self.compare(
+                split.old..a.end,
+                split.new..b.end,
+                split.minimal_after,
+                old_file,
+                new_file,
+            );
+        }
+    }
+
+    // Finds where an optimal edit path crosses the middle of the edit graph, searching forwards
+    // from the start and backwards from the end until the searches meet. Unless the path has to
+    // be minimal, a long enough snake or the furthest point reached does once that gets costly.
+    fn split(&mut self, a: Range<isize>, b: Range<isize>, minimal: bool) -> Split {
+        let (off1, lim1, off2, lim2) = (a.start, a.end, b.start, b.end);
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+        self.forward[fmid] = off1;
+        self.backward[bmid] = lim1;
+
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            // Search one more diagonal on each side, or one fewer where that leaves the graph
+            if fmin > dmin {
+                fmin -= 1;
+                self.forward[fmin - 1] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.forward[fmax + 1] = -1;
+            } else {
+                fmax -= 1;
+            }
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = if self.forward[d - 1] >= self.forward[d + 1] {
+                    self.forward[d - 1] + 1
+                } else {
+                    self.forward[d + 1]
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                got_snake |= i1 - start > SNAKE_COUNT;
+                self.forward[d] = i1;
+                if odd && (bmin..=bmax).contains(&d) && self.backward[d] <= i1 {
+                    return Split::new(i1, i2, true, true);
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.backward[bmin - 1] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.backward[bmax + 1] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = if self.backward[d - 1] < self.backward[d + 1] {
+                    self.backward[d - 1]
+                } else {
+                    self.backward[d + 1] - 1
+                };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                got_snake |= start - i1 > SNAKE_COUNT;
+                self.backward[d] = i1;
+                if !odd && (fmin..=fmax).contains(&d) && i1 <= self.forward[d] {
+                    return Split::new(i1, i2, true, true);
+                }
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // A diagonal that got far from its corner along a long snake, without straying far
+            // from the middle, is good enough to split at
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = (0, 0);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let i1 = self.forward[d];
+                    let i2 = i1 - d;
+                    let value = (i1 - off1) + (i2 - off2) - (d - fmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && value > best
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| self.same(i1 - k, i2 - k))
+                    {
+                        best = value;
+                        split = (i1, i2);
+                    }
+                }
+                if best > 0 {
+                    return Split::new(split.0, split.1, true, false);
+                }
+
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let i1 = self.backward[d];
+                    let i2 = i1 - d;
+                    let value = (lim1 - i1) + (lim2 - i2) - (d - bmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && value > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| self.same(i1 + k, i2 + k))
+                    {
+                        best = value;
+                        split = (i1, i2);
+                    }
+                }
+                if best > 0 {
+                    return Split::new(split.0, split.1, false, true);
+                }
+            }
+
+            if cost >= self.max_cost {
+                let (mut forward_best, mut forward_old) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = self.forward[d].min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if forward_best < i1 + i2 {
+                        forward_best = i1 + i2;
+                        forward_old = i1;
+                    }
+                }
+                let (mut backward_best, mut backward_old) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = self.backward[d].max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < backward_best {
+                        backward_best = i1 + i2;
+                        backward_old = i1;
+                    }
+                }
+                return if (lim1 + lim2) - backward_best < forward_best - (off1 + off2) {
+                    Split::new(forward_old, forward_best - forward_old, true, false)
+                } else {
+                    Split::new(backward_old, backward_best - backward_old, false, true)
+                };
+            }
+            cost += 1;
+        }
+    }
+}
+
+// A line of old in the patience algorithm: where it first is, and where it is in new if it occurs
+// once on each side
+struct Unique {
+    old: usize,
+    new: Occurrences,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Occurrences {
+    None,
+    Once(usize),
+    Many,
+}
+
+// Marks the lines that differ between the lines `a` of old and `b` of new with the patience
+// algorithm: the longest sequence of lines occurring once on either side, in the same order, is
+// kept, along with the lines in common around each, and what is left between them compared
+// again. Where no line is unique, the comparison falls back to Myers'.
+fn patience(old: &mut File, new: &mut File, a: Range<usize>, b: Range<usize>) {
+    if a.is_empty() || b.is_empty() {
+        a.for_each(|i| old.changed[i] = true);
+        b.for_each(|j| new.changed[j] = true);
+        return;
+    }
+
+    // The lines of old in order, by where each class first occurs
+    let mut lines: Vec<Unique> = Vec::new();
+    let mut by_class: HashMap<usize, usize> = HashMap::new();
+    for i in a.clone() {
+        match by_class.get(&old.classes[i]) {
+            Some(&line) => lines[line].new = Occurrences::Many,
+            None => {
+                by_class.insert(old.classes[i], lines.len());
+                lines.push(Unique {
+                    old: i,
+                    new: Occurrences::None,
+                });
+            }
+        }
+    }
+    let mut matched = false;
+    for j in b.clone() {
+        if let Some(&line) = by_class.get(&new.classes[j]) {
+            matched = true;
+            let line = &mut lines[line];
+            line.new = match line.new {
+                Occurrences::None => Occurrences::Once(j),
+                _ => Occurrences::Many,
+            };
+        }
+    }
+    if !matched {
+        a.for_each(|i| old.changed[i] = true);
+        b.for_each(|j| new.changed[j] = true);
+        return;
+    }
+
+    // The longest increasing sequence of the unique lines' places in new, found by keeping the
+    // sequence with the smallest last line for each length
+    let unique: Vec<(usize, usize)> = lines
+        .iter()
+        .filter_map(|line| match line.new {
+            Occurrences::Once(j) => Some((line.old, j)),
+            _ => None,
+        })
+        .collect();
+    if unique.is_empty() {
+        myers(old, new, a, b, false);
+        return;
+    }
+    let mut ends: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(unique.len());
+    for (k, &(_, j)) in unique.iter().enumerate() {
+        let length = ends.partition_point(|&end| unique[end].1 < j);
+        previous.push(length.checked_sub(1).map(|before| ends[before]));
+        if length == ends.len() {
+            ends.push(k);
+        } else {
+            ends[length] = k;
+        }
+    }
+    let mut sequence = Vec::new();
+    let mut next = ends.last().copied();
+    while let Some(k) = next {
+        sequence.push(unique[k]);
+        next = previous[k];
+    }
+    sequence.reverse();
+
+    // Compare what lies between the lines of the sequence, once those next to them that are the
+    // same are taken off
+    let (mut i, mut j) = (a.start, b.start);
+    let mut anchors = sequence.iter().peekable();
+    loop {
+        let (mut next_i, mut next_j) = match anchors.peek() {
+            Some(&&anchor) => anchor,
+            None => (a.end, b.end),
+        };
+        if anchors.peek().is_some() {
+            while next_i > i && next_j > j && old.classes[next_i - 1] == new.classes[next_j - 1] {
+                next_i -= 1;
+                next_j -= 1;
+            }
+        }
+        while i < next_i && j < next_j && old.classes[i] == new.classes[j] {
+            i += 1;
+            j += 1;
+        }
+        if next_i > i || next_j > j {
+            patience(old, new, i..next_i, j..next_j);
+        }
+        let Some(mut anchor) = anchors.next() else {
+            return;
+        };
+        while let Some(&&following) = anchors.peek()
+            && following == (anchor.0 + 1, anchor.1 + 1)
+        {
+            anchor = anchors.next().expect("It was peeked");
+        }
+        (i, j) = (anchor.0 + 1, anchor.1 + 1);
+    }
+}
+
+// The histogram algorithm's view of the lines `a` of old: how often each class occurs there and
+// where it first does, and for each line where its class occurs next
+struct Histogram<'a> {
+    old: &'a File<'a>,
+    new: &'a File<'a>,
+    a: Range<usize>,
+    b: Range<usize>,
+    first: HashMap<usize, (usize, usize)>,
+    next: Vec<Option<usize>>,
+    /// The fewest occurrences of the lines in the best match found so far
+    count: usize,
+    /// Whether any line of new occurs in old
+    common: bool,
+}
+
+// What the histogram algorithm finds to split at
+enum Anchor {
+    /// The lines the two sides have in common, which occur least often in old
+    Lines(Range<usize>, Range<usize>),
+    /// Nothing in common, all lines having changed
+    Nothing,
+    /// The lines in common all occur too often to be worth splitting at
+    TooCommon,
+}
+
+// Marks the lines that differ between the lines `a` of old and `b` of new with the histogram
+// algorithm: the longest run of lines in common among those occurring least often in old is kept,
+// and what is before and after it compared again. Where every line in common occurs too often,
+// the comparison falls back to Myers'.
+fn histogram(old: &mut File, new: &mut File, mut a: Range<usize>, mut b: Range<usize>) {
+    loop {
+        if a.is_empty() || b.is_empty() {
+            a.for_each(|i| old.changed[i] = true);
+            b.for_each(|j| new.changed[j] = true);
+            return;
+        }
+        match Histogram::new(old, new, a.clone(), b.clone()).anchor() {
+            Anchor::Lines(old_lines, new_lines) => {
+                histogram(old, new, a.start..old_lines.start, b.start..new_lines.start);
+                a = old_lines.end..a.end;
+                b = new_lines.end..b.end;
+            }
+            Anchor::Nothing => {
+                a.for_each(|i| old.changed[i] = true);
+                b.for_each(|j| new.changed[j] = true);
+                return;
+            }
+            Anchor::TooCommon => {
+                myers(old, new, a, b, false);
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> Histogram<'a> {
+    fn new(old: &'a File, new: &'a File, a: Range<usize>, b: Range<usize>) -> Self {
+        let mut first: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut next = vec![None; a.len()];
+        for i in a.clone().rev() {
+            let (line, count) = first.entry(old.classes[i]).or_insert((i, 0));
+            if *line != i {
+                next[i - a.start] = Some(*line);
+                *line = i;
+            }
+            *count += 1;
+        }
+        Self {
+            old,
+            new,
+            a,
+            b,
+            first,
+            next,
+            count: MAX_CHAIN_LENGTH + 1,
+            common: false,
+        }
+    }
+
+    fn same(&self, i: usize, j: usize) -> bool {
+        self.old.classes[i] == self.new.classes[j]
+    }
+
+    fn count(&self, i: usize) -> usize {
+        self.first[&self.old.classes[i]].1
+    }
+
+    fn anchor(mut self) -> Anchor {
+        let mut best = (0..0, 0..0);
+        let mut j = self.b.start;
+        while j < self.b.end {
+            j = self.try_line(j, &mut best);
+        }
+        if self.common && self.count > MAX_CHAIN_LENGTH {
+            Anchor::TooCommon
+        } else if best.0.is_empty() {
+            Anchor::Nothing
+        } else {
+            Anchor::Lines(best.0, best.1)
+        }
+    }
+
+    // Tries each run of lines in common through line `j` of new, keeping the run in `best` if it
+    // is longer than the best so far or its lines occur less often. Returns the next line of new
+    // to try.
+    fn try_line(&mut self, j: usize, best: &mut (Range<usize>, Range<usize>)) -> usize {
+        let mut next_j = j + 1;
+        let Some(&(first, count)) = self.first.get(&self.new.classes[j]) else {
+            return next_j;
+        };
+        self.common = true;
+        if count > self.count {
+            return next_j;
+        }
+        let mut i = first;
+        loop {
+            let next_i = self.next[i - self.a.start];
+            let (mut start_i, mut start_j, mut end_i, mut end_j) = (i, j, i + 1, j + 1);
+            let mut fewest = count;
+            while start_i > self.a.start
+                && start_j > self.b.start
+                && self.same(start_i - 1, start_j - 1)
+            {
+                start_i -= 1;
+                start_j -= 1;
+                if fewest > 1 {
+                    fewest = fewest.min(self.count(start_i));
+                }
+            }
+            while end_i < self.a.end && end_j < self.b.end && self.same(end_i, end_j) {
+                if fewest > 1 {
+                    fewest = fewest.min(self.count(end_i));
+                }
+                end_i += 1;
+                end_j += 1;
+            }
+            next_j = next_j.max(end_j);
+            if best.0.len().max(1) < end_i - start_i || fewest < self.count {
+                *best = (start_i..end_i, start_j..end_j);
+                self.count = fewest;
+            }
+
+            // Carry on from the next occurrence past the run
+            let mut next_i = next_i;
+            loop {
+                match next_i {
+                    None => return next_j,
+                    Some(following) if following < end_i => {
+                        next_i = self.next[following - self.a.start];
+                    }
+                    Some(following) => {
+                        i = following;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A run of changed lines, or the empty run between two unchanged ones
+struct Group {
+    start: isize,
+    end: isize,
+}
+
+impl Group {
+    fn first(file: &File) -> Self {
+        let mut group = Group { start: 0, end: 0 };
+        while file.is_changed(group.end) {
+            group.end += 1;
+        }
+        group
+    }
+
+    // Moves to the next group, returning false at the end of the file
+    fn next(&mut self, file: &File) -> bool {
+        if self.end == file.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while file.is_changed(self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    // Moves to the previous group, returning false at the start of the file
+    fn previous(&mut self, file: &File) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while file.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    // Shifts the group down a line if the line after it matches its first, taking in the group
+    // it then touches
+    fn slide_down(&mut self, file: &mut File) -> bool {
+        let (start, end) = (self.start as usize, self.end as usize);
+        if self.end >= file.len() || file.classes[start] != file.classes[end] {
+            return false;
+        }
+        file.changed[start] = false;
+        file.changed[end] = true;
+        self.start += 1;
+        self.end += 1;
+        while file.is_changed(self.end) {
+            self.end += 1;
+        }
+        true
+    }
+
+    // Shifts the group up a line if the line before it matches its last, taking in the group it
+    // then touches
+    fn slide_up(&mut self, file: &mut File) -> bool {
+        let (start, end) = (self.start as usize, self.end as usize);
+        if self.start == 0 || file.classes[start - 1] != file.classes[end - 1] {
+            return false;
+        }
+        file.changed[start - 1] = true;
+        file.changed[end - 1] = false;
+        self.start -= 1;
+        self.end -= 1;
+        while file.is_changed(self.start - 1) {
+            self.start -= 1;
+        }
+        true
+    }
+}
+
+// Slides each group of changed lines in `file` to where git shows it, following along the groups
+// of `other`, the other side of the comparison, to keep both in step
+fn compact(file: &mut File, other: &File, indent_heuristic: bool) {
+    const IN_STEP: &str = "The groups of both sides should be in step";
+    let mut group = Group::first(file);
+    let mut other_group = Group::first(other);
+    loop {
+        if group.end != group.start {
+            // Slide the group up and then down as far as it goes, until it stops taking in others
+            let (mut size, mut earliest_end, mut matching_other);
+            loop {
+                size = group.end - group.start;
+                matching_other = false;
+                while group.slide_up(file) {
+                    assert!(other_group.previous(other), "{}", IN_STEP);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    matching_other = true;
+                }
+                while group.slide_down(file) {
+                    assert!(other_group.next(other), "{}", IN_STEP);
+                    if other_group.end > other_group.start {
+                        matching_other = true;
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            // The group is now as far down as it goes, where it stays unless that leaves it out
+            // of line with a change on the other side further up, or the indentation is better
+            // further up
+            if group.end == earliest_end {
+                // It can't move
+            } else if matching_other {
+                while other_group.end == other_group.start {
+                    assert!(group.slide_up(file), "{}", IN_STEP);
+                    assert!(other_group.previous(other), "{}", IN_STEP);
+                }
+            } else if indent_heuristic {
+                let best = best_shift(file, group.end, earliest_end, size);
+                while group.end > best {
+                    assert!(group.slide_up(file), "{}", IN_STEP);
+                    assert!(other_group.previous(other), "{}", IN_STEP);
+                }
+            }
+        }
+
+        if !group.next(file) {
+            break;
+        }
+        assert!(other_group.next(other), "{}", IN_STEP);
+    }
+}
+
+// Collects the regions from the changed lines, pairing up the unchanged ones from the end
+fn regions(old: &File, new: &File) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let (mut i1, mut i2) = (old.len(), new.len());
+    while i1 >= 0 || i2 >= 0 {
+        if old.is_changed(i1 - 1) || new.is_changed(i2 - 1) {
+            let (end1, end2) = (i1, i2);
+            while old.is_changed(i1 - 1) {
+                i1 -= 1;
+            }
+            while new.is_changed(i2 - 1) {
+                i2 -= 1;
+            }
+            regions.push(Region {
+                old: i1 as usize..end1 as usize,
+                new: i2 as usize..end2 as usize,
+            });
+        }
+        i1 -= 1;
+        i2 -= 1;
+    }
+    regions.reverse();
+    regions
+}
+
+// The weights git's indent heuristic scores the places a change could go by
+const INDENT_HEURISTIC_MAX_SLIDING: isize = 100;
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+
+// The lines around a split between two lines, with indents of -1 for none
+struct Measurement {
+    end_of_file: bool,
+    /// The indent of the line after the split, or -1 if it is blank
+    indent: isize,
+    /// The blank lines before the split, and the indent of the line before them
+    pre_blank: isize,
+    pre_indent: isize,
+    /// The blank lines after the line after the split, and the indent of the line after them
+    post_blank: isize,
+    post_indent: isize,
+}
+
+// How bad the splits a group makes look, as the indent they split at and a penalty
+#[derive(Clone, Copy, Default)]
+struct Score {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+// Picks the end for a group of `size` changed lines, ending at `end` but able to end as early as
+// `earliest_end`, where the splits it makes before and after itself look best
+fn best_shift(file: &File, end: isize, earliest_end: isize, size: isize) -> isize {
+    let first = earliest_end
+        .max(end - size - 1)
+        .max(end - INDENT_HEURISTIC_MAX_SLIDING);
+    let mut best: Option<(isize, Score)> = None;
+    for shift in first..=end {
+        let mut score = Score::default();
+        score.add(&measure(file, shift));
+        score.add(&measure(file, shift - size));
+        // Ties go to the later shift
+        if best.is_none_or(|(_, best)| score.compare(&best) <= 0) {
+            best = Some((shift, score));
+        }
+    }
+    best.map_or(end, |(shift, _)| shift)
+}
+
+fn measure(file: &File, split: isize) -> Measurement {
+    let indent_of = |i: isize| indent(file.lines[i as usize]);
+    let blanks = |lines: &mut dyn Iterator<Item = isize>| {
+        let (mut blank, mut indent) = (0, -1);
+        for i in lines {
+            indent = indent_of(i);
+            if indent != -1 {
+                break;
+            }
+            blank += 1;
+            if blank == MAX_BLANKS {
+                indent = 0;
+                break;
+            }
+        }
+        (blank, indent)
+    };
+    let (pre_blank, pre_indent) = blanks(&mut (0..split).rev());
+    let (post_blank, post_indent) = blanks(&mut (split + 1..file.len()));
+    Measurement {
+        end_of_file: split >= file.len(),
+        indent: if split >= file.len() {
+            -1
+        } else {
+            indent_of(split)
+        },
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+// The width of a line's leading whitespace, with tabs to multiples of 8, or -1 for a blank line
+fn indent(line: &[u8]) -> isize {
+    let mut indent = 0;
+    for b in line {
+        if !is_space(b) {
+            return indent;
+        }
+        match b {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            _ => {}
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+impl Score {
+    fn add(&mut self, m: &Measurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank + POST_BLANK_WEIGHT * post_blank;
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // Nothing more to weigh
+        } else if indent > m.pre_indent {
+            self.penalty += if blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if blanks {
+                RELATIVE_OUTDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_OUTDENT_PENALTY
+            };
+        } else {
+            self.penalty += if blanks {
+                RELATIVE_DEDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_DEDENT_PENALTY
+            };
+        }
+    }
+
+    // Negative if this score is better than `other`, zero if they are as good
+    fn compare(&self, other: &Score) -> isize {
+        let indents = self.effective_indent.cmp(&other.effective_indent) as isize;
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Diffs two texts, giving each region as the old and new lines it spans
+    fn diff(old: &str, new: &str, options: &DiffOptions) -> Vec<(Range<usize>, Range<usize>)> {
+        diff_lines(&lines(old.as_bytes()), &lines(new.as_bytes()), options)
+            .into_iter()
+            .map(|region| (region.old, region.new))
+            .collect()
+    }
+
+    #[test]
+    fn changes_slide_down() {
+        let options = DiffOptions::default();
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nb\nc\n", &options), [(2..2, 2..3)]);
+        assert_eq!(diff("a\nb\nc\n", "a\nb\nc\n", &options), []);
+        assert_eq!(diff("", "a\n", &options), [(0..0, 0..1)]);
+    }
+
+    #[test]
+    fn indent_heuristic() {
+        let (old, new) = ("{\n  x\n}\n\n{\n}\n", "{\n  x\n}\n  y\n  x\na\n}\n\n{\n}\n");
+        let mut options = DiffOptions::default();
+        assert_eq!(diff(old, new, &options), [(3..3, 3..7)]);
+        options.indent_heuristic = true;
+        assert_eq!(diff(old, new, &options), [(2..2, 2..6)]);
+    }
+
+    #[test]
+    fn algorithms() {
+        let (old, new) = ("x\na\nb\nc\na\nb\nc\ny\n", "a\nb\nc\nz\nx\na\nb\nc\n");
+        let myers = [(0..1, 0..0), (4..4, 3..5), (7..8, 8..8)];
+        let unique = [(0..0, 0..4), (4..8, 8..8)];
+        for (algorithm, expected) in [
+            (Algorithm::Myers, &myers[..]),
+            (Algorithm::Minimal, &myers),
+            (Algorithm::Patience, &unique),
+            (Algorithm::Histogram, &unique),
+        ] {
+            let options = DiffOptions {
+                algorithm,
+                indent_heuristic: true,
+                ..DiffOptions::default()
+            };
+            assert_eq!(diff(old, new, &options), expected, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn whitespace() {
+        let diff = |whitespace, old: &str, new: &str| {
+            let options = DiffOptions {
+                whitespace,
+                ..DiffOptions::default()
+            };
+            diff(old, new, &options).len()
+        };
+        assert_eq!(diff(Whitespace::Exact, "a \n", "a\n"), 1);
+        assert_eq!(diff(Whitespace::IgnoreAtEol, "a \t\n", "a\n"), 0);
+        assert_eq!(diff(Whitespace::IgnoreAtEol, "a", "a\n"), 0);
+        assert_eq!(diff(Whitespace::IgnoreAtEol, " a\n", "a\n"), 1);
+        assert_eq!(diff(Whitespace::IgnoreChange, "a  b \n", "a\tb\n"), 0);
+        assert_eq!(diff(Whitespace::IgnoreChange, "a b\n", "ab\n"), 1);
+        assert_eq!(diff(Whitespace::IgnoreChange, " a\n", "a\n"), 1);
+        assert_eq!(diff(Whitespace::IgnoreAll, "a b\n", "ab\n"), 0);
+        assert_eq!(
+            Whitespace::IgnoreChange.normalize(b"\t a  b\n").as_ref(),
+            b" a b"
+        );
+    }
+}
@@ -0,0 +1,525 @@
+// Traces ranges of lines back through history for git log -L. Each commit's ranges are compared
+// with the file in its parents: the lines a change touched are replaced by the lines they were
+// made from, and the rest are shifted past the changes before them, giving the ranges to trace in
+// each parent. A commit is shown if it touched any of its ranges, with a hunk for each range that
+// shows every line of it, as git does. At a merge that left the ranges as one of its parents had
+// them, they are only traced into that parent; other merges are shown without a diff. Like git,
+// the commits shown are put in topological order. A file a commit added is followed back through
+// a rename, when the commit deleted a file similar enough for rename detection to pair them,
+// with the lines traced on in the file it was renamed from.
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+
+use crate::diff::{self, Status};
+use crate::line_diff::{self, DiffOptions, Region};
+use crate::object::blob::Blob;
+use crate::object::commit::Commit;
+use crate::object::tree::Tree;
+use crate::pathspec::Pathspec;
+use crate::regex::Regex;
+use crate::rename::{self, RenameOptions};
+use crate::repository::Repository;
+
+// The ranges traced in each file, sorted and neither overlapping nor touching
+type Ranges = BTreeMap<String, Vec<Range<usize>>>;
+
+// Traces ranges a commit at a time
+struct Tracer<'a> {
+    repository: &'a Repository,
+    // The ranges yet to be traced into each commit
+    pending: HashMap<String, Ranges>,
+}
+
+// A commit traced through: the diff to show it with, if it is shown, and the parents given the
+// ranges it had
+struct Traced {
+    diff: Option<Vec<u8>>,
+    parents: Vec<String>,
+}
+
+// How a commit changed a file, as far as its ranges are concerned
+struct FileDiff {
+    // The path the file had in the parent, which differs if it was renamed
+    source: String,
+    old: Option<Vec<u8>>,
+    new: Vec<u8>,
+    // The changes that touched the ranges
+    touched: Vec<Region>,
+}
+
+/// Traces the ranges `specs` give, each `<start>,<end>:<file>`, from `commit` back through
+/// `commits`, the history it leads to newest first. Returns the commits that changed them, as
+/// indices into `commits` in the order to show them, each with the diff showing how.
+pub fn trace(
+    repository: &Repository,
+    commit: &str,
+    specs: &[String],
+    commits: &[(String, Commit)],
+) -> anyhow::Result<Vec<(usize, Vec<u8>)>> {
+    let mut tracer = Tracer::new(repository, commit, specs)?;
+    let mut traced = Vec::new();
+    for (i, (hash, commit)) in commits.iter().enumerate() {
+        if let Some(commit) = tracer.next(hash, commit)? {
+            traced.push((i, commit));
+        }
+    }
+    Ok(graph_order(commits, traced))
+}
+
+// Orders the commits traced through, each given with the parents it passed ranges on to, as git's
+// topological sort does: each after all of its children, and given a merge, the history of its
+// last parent before that of its first. Only the commits with a diff are kept.
+fn graph_order(
+    commits: &[(String, Commit)],
+    traced: Vec<(usize, Traced)>,
+) -> Vec<(usize, Vec<u8>)> {
+    let nodes: HashMap<&str, usize> = traced
+        .iter()
+        .enumerate()
+        .map(|(node, (i, _))| (commits[*i].0.as_str(), node))
+        .collect();
+    let parents = |node: usize| {
+        traced[node]
+            .1
+            .parents
+            .iter()
+            .filter_map(|parent| nodes.get(parent.as_str()).copied())
+    };
+    let mut children = vec![0; traced.len()];
+    for parent in (0..traced.len()).flat_map(parents) {
+        children[parent] += 1;
+    }
+
+    let mut stack: Vec<usize> = (0..traced.len())
+        .filter(|&node| children[node] == 0)
+        .rev()
+        .collect();
+    let mut order = Vec::new();
+    while let Some(node) = stack.pop() {
+        order.push(node);
+        for parent in parents(node) {
+            children[parent] -= 1;
+            if children[parent] == 0 {
+                stack.push(parent);
+            }
+        }
+    }
+    let mut traced: Vec<_> = traced.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .filter_map(|node| {
+            let (i, commit) = traced[node].take()?;
+            Some((i, commit.diff?))
+        })
+        .collect()
+}
+
+impl<'a> Tracer<'a> {
+    // Starts tracing the ranges `specs` give in `commit`
+    fn new(repository: &'a Repository, commit: &str, specs: &[String]) -> anyhow::Result<Self> {
+        let mut files = Vec::new();
+        let mut parsed = Vec::new();
+        for spec in specs {
+            let malformed = move || {
+                format!(
+                    "-L argument not 'start,end:file' or ':funcname:file': {}",
+                    spec
+                )
+            };
+            let (range, file) = spec.rsplit_once(':').with_context(malformed)?;
+            let path = repository.relative_to_worktree(Path::new(file))?;
+            files.push(file.to_string());
+            parsed.push((range, file, path, malformed));
+        }
+        let pathspec = Pathspec::parse(repository, &files)?;
+
+        let tree = Tree::of_commit(repository, commit)?;
+        let entries = diff::flatten(repository, &tree, &pathspec)?;
+        let mut ranges = Ranges::new();
+        for (range, file, path, malformed) in parsed {
+            let Some((_, hash)) = entries.get(&path) else {
+                bail!("There is no path {} in the commit", path);
+            };
+            let blob = Blob::read(repository, hash)?;
+            let lines = line_diff::lines(blob.data());
+            let Some((start, end)) = parse_range(range, &lines)? else {
+                bail!(malformed());
+            };
+            if lines.len() < start || lines.is_empty() && (start, end) != (0, 0) {
+                bail!("file {} has only {} lines", file, lines.len());
+            }
+            let end = match end {
+                0 => lines.len(),
+                end => end.min(lines.len()),
+            };
+            let traced = ranges.entry(path).or_default();
+            *traced = union(traced, std::slice::from_ref(&(start.max(1) - 1..end)));
+        }
+        Ok(Self {
+            repository,
+            pending: HashMap::from([(commit.to_string(), ranges)]),
+        })
+    }
+
+    // Traces the ranges in `commit` into its parents, if it has any. Commits must come after all
+    // of their children.
+    fn next(&mut self, hash: &str, commit: &Commit) -> anyhow::Result<Option<Traced>> {
+        let Some(ranges) = self.pending.remove(hash) else {
+            return Ok(None);
+        };
+        let tree = Tree::of_commit(self.repository, hash)?;
+        let parents = match commit.parents() {
+            [] => vec![None],
+            parents => parents.iter().map(Some).collect(),
+        };
+        let mut traced = Vec::new();
+        for parent in parents {
+            let parent_tree = match parent {
+                Some(parent) => Tree::of_commit(self.repository, parent)?,
+                None => Tree {
+                    records: Vec::new(),
+                },
+            };
+            let (parent_ranges, diffs) = self.trace(&parent_tree, &tree, &ranges)?;
+            traced.push((parent, parent_ranges, diffs));
+        }
+
+        // A merge is passed over if one of its parents already had the ranges as they are
+        if traced.len() > 1
+            && let Some(same) = traced.iter().position(|(_, _, diffs)| diffs.is_empty())
+        {
+            let (parent, parent_ranges, _) = traced.swap_remove(same);
+            let parents = self.pass(parent, parent_ranges).into_iter().collect();
+            return Ok(Some(Traced {
+                diff: None,
+                parents,
+            }));
+        }
+        let merge = traced.len() > 1;
+        let mut parents = Vec::new();
+        let mut shown = None;
+        for (parent, parent_ranges, diffs) in traced {
+            parents.extend(self.pass(parent, parent_ranges));
+            if shown.is_none() {
+                shown = Some(diffs);
+            }
+        }
+        let diffs = shown.expect("There is a first parent");
+        let diff = match merge {
+            true => Some(Vec::new()),
+            false if diffs.is_empty() => None,
+            false => {
+                let mut output = Vec::new();
+                for (path, diff) in &diffs {
+                    diff.write(&mut output, path, &ranges[path]);
+                }
+                Some(output)
+            }
+        };
+        Ok(Some(Traced { diff, parents }))
+    }
+
+    // Maps `ranges` in `tree` to those in `parent`, with the diffs of the files whose ranges were
+    // touched
+    fn trace(
+        &self,
+        parent: &Tree,
+        tree: &Tree,
+        ranges: &Ranges,
+    ) -> anyhow::Result<(Ranges, BTreeMap<String, FileDiff>)> {
+        // Every change is needed to find what a file was renamed from
+        let changes = diff::diff_trees(self.repository, parent, tree, true, &Pathspec::default())?;
+        let mut parent_ranges = Ranges::new();
+        let mut diffs = BTreeMap::new();
+        for (path, ranges) in ranges {
+            let Some(change) = changes.iter().find(|change| &change.path == path) else {
+                parent_ranges.insert(path.clone(), ranges.clone());
+                continue;
+            };
+            let rename = match change.status {
+                Status::Added => {
+                    let options = RenameOptions {
+                        destination: Some(path),
+                        copies_from: None,
+                    };
+                    rename::find(self.repository, &changes, &options)?.pop()
+                }
+                _ => None,
+            };
+            let (source, old) = match (rename, &change.status) {
+                (Some(rename), _) => {
+                    let blob = Blob::read(self.repository, &rename.source_hash)?;
+                    (rename.source, Some(blob.data().to_vec()))
+                }
+                (None, Status::Added | Status::TypeChanged) => (path.clone(), None),
+                (None, _) => (path.clone(), change.old_content(self.repository)?),
+            };
+            let new = change.new_content(self.repository)?.unwrap_or_default();
+            let regions = {
+                // Like git, without the indent heuristic
+                let old_lines = line_diff::lines(old.as_deref().unwrap_or_default());
+                line_diff::diff_lines(&old_lines, &line_diff::lines(&new), &DiffOptions::default())
+            };
+
+            let touched: Vec<Region> = regions
+                .iter()
+                .filter(|region| ranges.iter().any(|range| overlaps(&region.new, range)))
+                .cloned()
+                .collect();
+            let untouched = difference(
+                ranges,
+                &touched
+                    .iter()
+                    .map(|region| region.new.clone())
+                    .collect::<Vec<_>>(),
+            );
+            let shifted: Vec<Range<usize>> = untouched
+                .iter()
+                .map(|range| {
+                    let (removed, added) = regions
+                        .iter()
+                        .take_while(|region| region.new.start <= range.start)
+                        .fold((0, 0), |(removed, added), region| {
+                            (removed + region.old.len(), added + region.new.len())
+                        });
+                    range.start + removed - added..range.end + removed - added
+                })
+                .collect();
+            let old_ranges: Vec<Range<usize>> =
+                touched.iter().map(|region| region.old.clone()).collect();
+            let mapped = union(&shifted, &old_ranges);
+            if old.is_some() && !mapped.is_empty() {
+                let traced = parent_ranges.entry(source.clone()).or_default();
+                *traced = union(traced, &mapped);
+            }
+            if !touched.is_empty() {
+                let diff = FileDiff {
+                    source,
+                    old,
+                    new,
+                    touched,
+                };
+                diffs.insert(path.clone(), diff);
+            }
+        }
+        Ok((parent_ranges, diffs))
+    }
+
+    // Adds `ranges` to those to trace into `parent`, returning the parent if there are any
+    fn pass(&mut self, parent: Option<&String>, ranges: Ranges) -> Option<String> {
+        let parent = parent.filter(|_| !ranges.is_empty())?;
+        let pending = self.pending.entry(parent.clone()).or_default();
+        for (path, ranges) in ranges {
+            let traced = pending.entry(path).or_default();
+            *traced = union(traced, &ranges);
+        }
+        Some(parent.clone())
+    }
+}
+
+impl FileDiff {
+    // Writes a hunk for each of `ranges` that the changes touched, laid out as git lays them out:
+    // the changes overlapping the range with every other line of it as context. The old side of
+    // the hunk header is worked out from the first and last of those changes.
+    fn write(&self, output: &mut Vec<u8>, path: &str, ranges: &[Range<usize>]) {
+        let old_lines = line_diff::lines(self.old.as_deref().unwrap_or_default());
+        let new_lines = line_diff::lines(&self.new);
+        let old_name = match self.old {
+            Some(_) => format!("a/{}", self.source),
+            None => "/dev/null".to_string(),
+        };
+        output.extend(
+            format!(
+                "diff --git a/{} b/{}\n--- {}\n+++ b/{}\n",
+                self.source, path, old_name, path
+            )
+            .as_bytes(),
+        );
+
+        for range in ranges {
+            let changes: Vec<&Region> = self
+                .touched
+                .iter()
+                .filter(|region| overlaps(&region.new, range))
+                .collect();
+            let (Some(first), Some(last)) = (changes.first(), changes.last()) else {
+                continue;
+            };
+            let old_start = first.old.start - first.new.start.saturating_sub(range.start);
+            let old_end = last.old.end + range.end.saturating_sub(last.new.end);
+            // The start is 1-based, except for an empty file
+            let old_start = match (old_start, old_end) {
+                (0, 0) => 0,
+                _ => old_start + 1,
+            };
+            output.extend(
+                format!(
+                    "@@ -{},{} +{},{} @@\n",
+                    old_start,
+                    old_end - old_start.saturating_sub(1),
+                    range.start + 1,
+                    range.len()
+                )
+                .as_bytes(),
+            );
+
+            // A change starting before the range only shows the lines it added within it
+            let mut line = range.start;
+            for region in &changes {
+                for context in &new_lines[line..region.new.start.max(line)] {
+                    write_line(output, b' ', context);
+                }
+                line = line.max(region.new.start);
+                for removed in &old_lines[region.old.clone()] {
+                    write_line(output, b'-', removed);
+                }
+                for added in &new_lines[line..region.new.end.min(range.end).max(line)] {
+                    write_line(output, b'+', added);
+                }
+                line = line.max(region.new.end.min(range.end));
+            }
+            for context in &new_lines[line..range.end] {
+                write_line(output, b' ', context);
+            }
+        }
+    }
+}
+
+fn write_line(output: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    output.push(prefix);
+    output.extend_from_slice(line.strip_suffix(b"\n").unwrap_or(line));
+    output.push(b'\n');
+    if !line.ends_with(b"\n") {
+        output.extend_from_slice(b"\\ No newline at end of file\n");
+    }
+}
+
+// Parses `<start>,<end>` into 1-based lines, either of which is 0 if left out, or returns None if
+// it is malformed. The start is a line number or a /regex/ matched against the lines from the
+// first, and the end can also be the number of lines to take from the start (`+<count>`), or to
+// take up to it (`-<count>`), or a /regex/ matched against the lines after it.
+fn parse_range(range: &str, lines: &[&[u8]]) -> anyhow::Result<Option<(usize, usize)>> {
+    let (start, rest) = parse_location(range, lines, None)?;
+    let (end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => parse_location(rest, lines, Some(start + 1))?,
+        None => (0, rest),
+    };
+    if !rest.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(match start != 0 && end != 0 && end < start {
+        true => (end, start),
+        false => (start, end),
+    }))
+}
+
+// Parses the line at the start of `spec`, returning it and what follows, or 0 and all of `spec`
+// if none is there. `begin` is the line after the start, when this is the end of a range.
+fn parse_location<'s>(
+    spec: &'s str,
+    lines: &[&[u8]],
+    begin: Option<usize>,
+) -> anyhow::Result<(usize, &'s str)> {
+    let digits = |spec: &str| {
+        spec.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(spec.len())
+    };
+    if let Some(begin) = begin
+        && let Some(sign @ ('+' | '-')) = spec.chars().next()
+        && digits(&spec[1..]) > 0
+    {
+        let length = digits(&spec[1..]) + 1;
+        let count: usize = spec[1..length].parse().context("-L invalid line number")?;
+        if count == 0 {
+            bail!("-L invalid empty range");
+        }
+        let line = match sign {
+            '+' => begin + count - 2,
+            _ => begin.saturating_sub(count).max(1),
+        };
+        return Ok((line, &spec[length..]));
+    }
+    if digits(spec) > 0 {
+        let line: usize = spec[..digits(spec)]
+            .parse()
+            .context("-L invalid line number")?;
+        if line == 0 {
+            bail!("-L invalid line number: 0");
+        }
+        return Ok((line, &spec[digits(spec)..]));
+    }
+
+    // A regex at the start of a range can be anchored to the first line with `^`, which is where
+    // it is searched from anyway
+    let (begin, pattern) = match begin {
+        Some(begin) => (begin, spec),
+        None => (1, spec.strip_prefix('^').unwrap_or(spec)),
+    };
+    let Some(pattern) = pattern.strip_prefix('/') else {
+        return Ok((0, spec));
+    };
+    let mut escaped = false;
+    let Some(length) = pattern.find(|c| {
+        let end = !escaped && c == '/';
+        escaped = !escaped && c == '\\';
+        end
+    }) else {
+        return Ok((0, spec));
+    };
+    let not_found = |error: &str| {
+        format!(
+            "-L parameter '{}' starting at line {}: {}",
+            &pattern[..length],
+            begin,
+            error
+        )
+    };
+    let regex = Regex::new(&pattern.as_bytes()[..length], false)
+        .map_err(|e| anyhow::anyhow!(not_found(&e.to_string())))?;
+    let Some(line) = lines.iter().skip(begin - 1).position(|line| {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        regex.find(line).is_some()
+    }) else {
+        bail!(not_found("No match"));
+    };
+    Ok((begin + line, &pattern[length + 1..]))
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+// The lines of `ranges` outside `removed`, both sorted
+fn difference(ranges: &[Range<usize>], removed: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut left = Vec::new();
+    for range in ranges {
+        let mut start = range.start;
+        for removed in removed.iter().filter(|removed| overlaps(removed, range)) {
+            if start < removed.start {
+                left.push(start..removed.start);
+            }
+            start = start.max(removed.end);
+        }
+        if start < range.end {
+            left.push(start..range.end);
+        }
+    }
+    left
+}
+
+// The lines in either of `a` and `b`, as ranges that neither overlap nor touch
+fn union(a: &[Range<usize>], b: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = a.iter().chain(b).cloned().collect();
+    ranges.sort_by_key(|range| (range.start, range.end));
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges.into_iter().filter(|range| !range.is_empty()) {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
@@ -0,0 +1,192 @@
+// Solves the linear assignment problem: given what assigning each column to each row costs, finds
+// the assignment of every column to a row of the least total cost. This is Jonker and Volgenant's
+// shortest augmenting path algorithm ("A shortest augmenting path algorithm for dense and sparse
+// linear assignment problems", 1987) as git's linear-assignment.c has it, quirks and all, so that the
+// same assignment is picked. Columns are first assigned to their cheapest rows,
+// what that leaves over is passed on to the rows, and the rows left free are assigned along the
+// cheapest paths of reassignments that make room for them.
+
+/// Assigns columns to rows, assigning column `j` to row `i` costing `cost[j + column_count * i]`.
+/// Returns the row each column is assigned to and the column each row is assigned to.
+pub fn compute(column_count: usize, row_count: usize, cost: &[i64]) -> (Vec<isize>, Vec<isize>) {
+    let cost = |j: usize, i: usize| cost[j + column_count * i];
+    if column_count < 2 {
+        return (vec![0; column_count], vec![0; row_count]);
+    }
+    let mut column2row = vec![-1isize; column_count];
+    let mut row2column = vec![-1isize; row_count];
+    let mut v = vec![0; column_count];
+
+    // Column reduction: each column goes to its cheapest row, unless a later column took it. A row
+    // wanted by several columns is marked by encoding its column as -2 - column.
+    for j in (0..column_count).rev() {
+        let mut i1 = 0;
+        for i in 1..row_count {
+            if cost(j, i1) > cost(j, i) {
+                i1 = i;
+            }
+        }
+        v[j] = cost(j, i1);
+        if row2column[i1] == -1 {
+            row2column[i1] = j as isize;
+            column2row[j] = i1 as isize;
+        } else {
+            if row2column[i1] >= 0 {
+                row2column[i1] = -2 - row2column[i1];
+            }
+            column2row[j] = -1;
+        }
+    }
+
+    // Reduction transfer
+    let mut free_rows = Vec::with_capacity(row_count);
+    for (i, j1) in row2column.iter_mut().enumerate() {
+        if *j1 == -1 {
+            free_rows.push(i);
+        } else if *j1 < -1 {
+            *j1 = -2 - *j1;
+        } else {
+            let j1 = *j1 as usize;
+            let other = (j1 == 0) as usize;
+            let min = (1..column_count)
+                .filter(|&j| j != j1)
+                .map(|j| cost(j, i) - v[j])
+                .fold(cost(other, i) - v[other], i64::min);
+            v[j1] -= min;
+        }
+    }
+    if free_rows.len() == row_count.saturating_sub(column_count) {
+        return (column2row, row2column);
+    }
+
+    // Augmenting row reduction, twice. A free row takes its cheapest column, and the row that had
+    // it is tried again right away if that made it cheaper, or else later.
+    for _ in 0..2 {
+        let saved = free_rows.len();
+        let mut free_count = 0;
+        let mut k = 0;
+        while k < saved {
+            let i = free_rows[k];
+            k += 1;
+            let mut j1 = 0;
+            let mut u1 = cost(j1, i) - v[j1];
+            let (mut j2, mut u2) = (None, i64::MAX);
+            for (j, &v) in v.iter().enumerate().skip(1) {
+                let c = cost(j, i) - v;
+                if u2 > c {
+                    if u1 < c {
+                        (u2, j2) = (c, Some(j));
+                    } else {
+                        (u2, u1, j2, j1) = (u1, c, Some(j1), j);
+                    }
+                }
+            }
+            let j2 = j2.unwrap_or_else(|| {
+                u2 = u1;
+                j1
+            });
+
+            let mut i0 = column2row[j1];
+            if u1 < u2 {
+                v[j1] -= u2 - u1;
+            } else if i0 >= 0 {
+                j1 = j2;
+                i0 = column2row[j1];
+            }
+            if i0 >= 0 {
+                if u1 < u2 {
+                    k -= 1;
+                    free_rows[k] = i0 as usize;
+                } else {
+                    free_rows[free_count] = i0 as usize;
+                    free_count += 1;
+                }
+            }
+            row2column[i] = j1 as isize;
+            column2row[j1] = i as isize;
+        }
+        free_rows.truncate(free_count);
+    }
+
+    // Augmentation: each row still free is assigned along the cheapest path of reassignments
+    // ending at a free column, found like Dijkstra's shortest paths, the columns in col[..low]
+    // being settled and those in col[low..up] the closest of the rest
+    let mut d = vec![0; column_count];
+    let mut pred = vec![0; column_count];
+    let mut col = vec![0; column_count];
+    for &i1 in &free_rows {
+        let (mut low, mut up) = (0, 0);
+        for j in 0..column_count {
+            d[j] = cost(j, i1) - v[j];
+            pred[j] = i1;
+            col[j] = j;
+        }
+
+        // Like git, a path found among the closest columns ends at the last column looked at
+        // rather than at the free one, which at times makes the assignment less cheap
+        let mut end = None;
+        let (last, min) = 'search: loop {
+            let last = low;
+            let mut min = d[col[up]];
+            up += 1;
+            let start = up;
+            for k in start..column_count {
+                let j = col[k];
+                end = Some(j);
+                let c = d[j];
+                if c <= min {
+                    if c < min {
+                        up = low;
+                        min = c;
+                    }
+                    col[k] = col[up];
+                    col[up] = j;
+                    up += 1;
+                }
+            }
+            if col[low..up].iter().any(|&j| column2row[j] == -1) {
+                break 'search (last, min);
+            }
+
+            // Scan the rows of the closest columns for cheaper paths
+            while low != up {
+                let j1 = col[low];
+                low += 1;
+                let i = column2row[j1] as usize;
+                let u1 = cost(j1, i) - v[j1] - min;
+                let start = up;
+                for k in start..column_count {
+                    let j = col[k];
+                    end = Some(j);
+                    let c = cost(j, i) - v[j] - u1;
+                    if c < d[j] {
+                        d[j] = c;
+                        pred[j] = i;
+                        if c == min {
+                            if column2row[j] == -1 {
+                                break 'search (last, min);
+                            }
+                            col[k] = col[up];
+                            col[up] = j;
+                            up += 1;
+                        }
+                    }
+                }
+            }
+        };
+
+        for &j1 in &col[..last] {
+            v[j1] += d[j1] - min;
+        }
+        let mut j = end.expect("Some column was looked at") as isize;
+        loop {
+            let i = pred[j as usize];
+            column2row[j as usize] = i as isize;
+            std::mem::swap(&mut j, &mut row2column[i]);
+            if i == i1 {
+                break;
+            }
+        }
+    }
+    (column2row, row2column)
+}
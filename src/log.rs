@@ -0,0 +1,321 @@
+// Lists commits like git log: the history of a commit, newest first, each with its author, date,
+// message and notes, and with --show-signature what checking its signature reports. Authors go
+// through the mailmap unless log.mailmap is off. The commits shown can be limited to those
+// changing some paths, those with commit dates within --since and --until, and those whose diff
+// changes how many times a string occurs (-S) or adds or removes a line matching a regex (-G).
+//
+// With --follow, the history of the one path given is listed past the commits that renamed or
+// copied it, by following where it came from from then on. With -L, the history of a range of
+// lines is listed instead, each commit followed by the diff of how it changed them. With --patch,
+// each commit other than a merge is followed by its patch, limited to the paths given, which is
+// what patch-id reads.
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, bail};
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::date;
+use crate::diff::{self, Change};
+use crate::line_log;
+use crate::mailmap::Mailmap;
+use crate::notes::Notes;
+use crate::object::GitrsObject;
+use crate::object::commit::Commit;
+use crate::object::tree::Tree;
+use crate::patch::{self, PatchOptions};
+use crate::pathspec::Pathspec;
+use crate::pickaxe::Pickaxe;
+use crate::rename::{self, Rename, RenameOptions};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::signature;
+
+#[derive(Default)]
+pub struct LogOptions {
+    /// The commit whose history is listed
+    pub commit: String,
+    /// Show authors as recorded, without applying the mailmap
+    pub no_mailmap: bool,
+    /// Check the signatures of signed commits
+    pub show_signature: bool,
+    /// Format of author dates, instead of log.date
+    pub date: Option<String>,
+    /// Only show commits committed after this date, in any form approxidate takes
+    pub since: Option<String>,
+    /// Only show commits committed before this date
+    pub until: Option<String>,
+    /// Show the patch of each commit other than a merge
+    pub patch: bool,
+    /// Ranges of lines to trace the history of, as `START,END:FILE`
+    pub line_ranges: Vec<String>,
+    /// Follow the one path given past renames, which log.follow otherwise decides
+    pub follow: bool,
+    /// A string whose number of occurrences a commit must change
+    pub pickaxe: Option<String>,
+    /// A regex a line a commit adds or removes must match
+    pub grep_diff: Option<String>,
+    /// Take the pickaxe string as a regex
+    pub pickaxe_regex: bool,
+    /// Ignore case in what the pickaxe looks for
+    pub ignore_case: bool,
+    /// Only show commits changing these paths
+    pub paths: Vec<String>,
+}
+
+// What decides which commits are shown
+struct Filter<'a> {
+    repository: &'a Repository,
+    config: &'a Config,
+    attributes: Attributes<'a>,
+    pathspec: Pathspec,
+    limited: bool,
+    follow: bool,
+    pickaxe: Option<Pickaxe>,
+    // Whether the diffs the pickaxe looks at detect renames, and copies too
+    detect_renames: Option<bool>,
+}
+
+/// Lists the history `options` asks for, returning what git log prints
+pub fn log(repository: &Repository, options: &LogOptions) -> anyhow::Result<Vec<u8>> {
+    let config = Config::load(repository)?;
+    if options.follow && options.paths.len() != 1 {
+        bail!("--follow requires exactly one pathspec");
+    }
+    let follow = options.paths.len() == 1
+        && (options.follow || config.get_bool("log.follow")?.unwrap_or(false));
+    let pickaxe = match (&options.pickaxe, &options.grep_diff) {
+        (Some(needle), _) => Some(Pickaxe::string(
+            needle,
+            options.pickaxe_regex,
+            options.ignore_case,
+        )),
+        (None, Some(pattern)) => Some(Pickaxe::grep(pattern, options.ignore_case)),
+        (None, None) => None,
+    }
+    .transpose()?;
+    let use_mailmap = !options.no_mailmap && config.get_bool("log.mailmap")?.unwrap_or(true);
+    let mailmap = Mailmap::load(repository, &config);
+    let notes = Notes::load(repository, &config, None)?;
+    let date_format = options
+        .date
+        .clone()
+        .or_else(|| config.get("log.date").map(str::to_string))
+        .unwrap_or_else(|| "default".to_string());
+    // Catch bad formats before anything is listed
+    date::format(0, "+0000", &date_format)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let approxidate = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|value| date::approxidate(value, now))
+            .transpose()
+    };
+    let (since, until) = (approxidate(&options.since)?, approxidate(&options.until)?);
+
+    let hash = GitrsObject::find(repository, &options.commit)
+        .ok()
+        .with_context(|| format!("Couldn't find commit: {}", options.commit))?;
+    let commits = match since {
+        Some(since) => revwalk::walk_since(repository, std::slice::from_ref(&hash), since),
+        None => revwalk::walk(repository, std::slice::from_ref(&hash)),
+    }
+    .context("Couldn't walk history")?;
+    let commits: Vec<_> = match options.line_ranges.is_empty() {
+        true => commits
+            .iter()
+            .map(|(hash, commit)| (hash, commit, None))
+            .collect(),
+        false => line_log::trace(repository, &hash, &options.line_ranges, &commits)?
+            .into_iter()
+            .map(|(i, line_diff)| (&commits[i].0, &commits[i].1, Some(line_diff)))
+            .collect(),
+    };
+
+    let mut filter = Filter {
+        repository,
+        config: &config,
+        attributes: Attributes::new(repository),
+        pathspec: Pathspec::parse(repository, &options.paths)?,
+        limited: !options.paths.is_empty(),
+        follow,
+        pickaxe,
+        detect_renames: rename::configured(&config)?,
+    };
+    let mut out = Vec::new();
+    for (hash, commit, line_diff) in commits {
+        // Like git, --since and --until go by the commit date
+        if let Some(until) = until {
+            let committer = commit
+                .committer()
+                .with_context(|| format!("Couldn't read committer of {}", hash))?;
+            if committer.timestamp > until {
+                continue;
+            }
+        }
+        // --follow moves on to the path a commit renamed, but the commit's patch shows the rename
+        let pathspec = filter.pathspec.clone();
+        if !filter
+            .shows(commit)
+            .with_context(|| format!("Couldn't diff {}", hash))?
+        {
+            continue;
+        }
+
+        let author = commit
+            .author()
+            .with_context(|| format!("Couldn't read author of {}", hash))?;
+        let (name, email) = match use_mailmap {
+            true => mailmap.map(&author.name, &author.email),
+            false => (author.name, author.email),
+        };
+        if !out.is_empty() {
+            writeln!(out)?;
+        }
+        writeln!(out, "commit {}", hash)?;
+        if options.show_signature
+            && let Some((_, verification)) = signature::verify_object(repository, &config, hash)
+                .with_context(|| format!("Couldn't verify {}", hash))?
+        {
+            write!(out, "{}", verification.output)?;
+        }
+        writeln!(out, "Author: {} <{}>", name, email)?;
+        let date = date::format(author.timestamp, &author.timezone, &date_format)?;
+        writeln!(out, "Date:   {}", date)?;
+        writeln!(out)?;
+        for line in commit.message().lines() {
+            writeln!(out, "    {}", line)?;
+        }
+
+        if let Some(note) = notes.get(hash)? {
+            writeln!(out)?;
+            writeln!(out, "Notes:")?;
+            for line in note.lines() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        if let Some(line_diff) = line_diff {
+            writeln!(out)?;
+            out.extend_from_slice(&line_diff);
+        }
+        if options.patch
+            && let Some((_, _, changes)) = filter.commit_diff(commit, &pathspec)?
+            && !changes.is_empty()
+        {
+            writeln!(out)?;
+            out.extend(patch::format(
+                repository,
+                &changes,
+                &PatchOptions::default(),
+            )?);
+        }
+    }
+    Ok(out)
+}
+
+impl Filter<'_> {
+    // Whether `commit` is shown, following the path to where it came from if the commit renamed
+    // or copied it
+    fn shows(&mut self, commit: &Commit) -> anyhow::Result<bool> {
+        let (changes, mut renames) = match self.follow {
+            true => self.follows(commit)?,
+            false if self.limited && !self.changes_pathspec(commit)? => return Ok(false),
+            false if self.pickaxe.is_none() => return Ok(true),
+            false => match self.commit_diff(commit, &self.pathspec)? {
+                Some((_, _, changes)) => (changes, Vec::new()),
+                None => (Vec::new(), Vec::new()),
+            },
+        };
+        let Some(pickaxe) = &self.pickaxe else {
+            return Ok(!changes.is_empty());
+        };
+        // Unless --follow found where the path came from, renames are detected among the changes
+        // as git detects them in any diff
+        if renames.is_empty() {
+            renames = match self.detect_renames {
+                // Copies are looked for among the files changed
+                Some(copies) => rename::detect(self.repository, &changes, copies)?,
+                None => Vec::new(),
+            };
+        }
+        pickaxe.picks_any(
+            self.repository,
+            self.config,
+            &mut self.attributes,
+            &changes,
+            &renames,
+        )
+    }
+
+    // Whether `commit` changes the paths against every parent, which leaves out merges that only
+    // take what one side has
+    fn changes_pathspec(&self, commit: &Commit) -> anyhow::Result<bool> {
+        let tree = Tree::from_name(self.repository, commit.get_tree_hash())?;
+        let trees = match commit.parents() {
+            [] => vec![Tree {
+                records: Vec::new(),
+            }],
+            parents => parents
+                .iter()
+                .map(|parent| Tree::from_name(self.repository, parent))
+                .collect::<anyhow::Result<_>>()?,
+        };
+        for parent in &trees {
+            if diff::diff_trees(self.repository, parent, &tree, true, &self.pathspec)?.is_empty() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Like git, the diff of the paths of a commit other than a merge against its parent, or the
+    // empty tree
+    fn commit_diff(
+        &self,
+        commit: &Commit,
+        pathspec: &Pathspec,
+    ) -> anyhow::Result<Option<(Tree, Tree, Vec<Change>)>> {
+        let tree = Tree::from_name(self.repository, commit.get_tree_hash())?;
+        let parent = match commit.parents() {
+            [] => Tree {
+                records: Vec::new(),
+            },
+            [parent] => Tree::from_name(self.repository, parent)?,
+            _ => return Ok(None),
+        };
+        let changes = diff::diff_trees(self.repository, &parent, &tree, true, pathspec)?;
+        Ok(Some((parent, tree, changes)))
+    }
+
+    // Like git, --follow shows the commits other than merges that change the path, and where one
+    // adds it as a rename or copy, follows the path it came from from then on
+    fn follows(&mut self, commit: &Commit) -> anyhow::Result<(Vec<Change>, Vec<Rename>)> {
+        let Some((parent, tree, changes)) = self.commit_diff(commit, &self.pathspec)? else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let mut renames = Vec::new();
+        // Renames are only looked for to the path itself, not to paths below it
+        if let [change] = changes.as_slice()
+            && change.status == diff::Status::Added
+            && self.pathspec.first_path() == Some(&change.path)
+            && !parent.records.is_empty()
+        {
+            // Like git, files are followed through copies too
+            let all =
+                diff::diff_trees(self.repository, &parent, &tree, true, &Pathspec::default())?;
+            let files = diff::flatten(self.repository, &parent, &Pathspec::default())?;
+            let options = RenameOptions {
+                destination: Some(&change.path),
+                copies_from: Some(&files),
+            };
+            renames = rename::find(self.repository, &all, &options)?;
+            renames.retain(|rename| rename.destination == change.path);
+            if let Some(rename) = renames.first() {
+                let source = format!(":(top,literal){}", rename.source);
+                self.pathspec = Pathspec::parse(self.repository, &[source])?;
+            }
+        }
+        Ok((changes, renames))
+    }
+}
@@ -0,0 +1,96 @@
+// Enumerates the loose objects stored under objects/xx/, where xx are the first two characters of
+// the hash and the file is named after the other 38. Anything else found in those directories
+// (eg. temporary files left behind by an interrupted write) is reported as garbage.
+use std::fs;
+use std::path::PathBuf;
+
+use crate::repository::Repository;
+use crate::trace;
+
+pub struct LooseFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Space used on disk, which can be more than `size`
+    pub disk_size: u64,
+}
+
+pub struct LooseObject {
+    pub hash: String,
+    pub file: LooseFile,
+}
+
+#[derive(Default)]
+pub struct LooseScan {
+    pub objects: Vec<LooseObject>,
+    pub garbage: Vec<LooseFile>,
+}
+
+/// Lists the loose objects and garbage files in the repository, ordered by hash
+pub fn scan(repository: &Repository) -> anyhow::Result<LooseScan> {
+    let _region = trace::region("scan loose objects");
+    let mut scan = LooseScan::default();
+    let Some(objects_dir) = repository.get_path_to_dir(&["objects"]) else {
+        return Ok(scan);
+    };
+
+    let mut dirs: Vec<(String, PathBuf)> = fs::read_dir(&objects_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.path(),
+            )
+        })
+        .filter(|(name, path)| name.len() == 2 && is_hex(name) && path.is_dir())
+        .collect();
+    dirs.sort();
+
+    for (prefix, dir) in dirs {
+        let mut entries: Vec<(String, PathBuf)> = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .collect();
+        entries.sort();
+
+        for (name, path) in entries {
+            let metadata = fs::symlink_metadata(&path)?;
+            let file = LooseFile {
+                path,
+                size: metadata.len(),
+                disk_size: disk_size(&metadata),
+            };
+            if name.len() == 38 && is_hex(&name) && file.path.is_file() {
+                scan.objects.push(LooseObject {
+                    hash: format!("{}{}", prefix, name).to_lowercase(),
+                    file,
+                });
+            } else {
+                scan.garbage.push(file);
+            }
+        }
+    }
+
+    Ok(scan)
+}
+
+// Space used by a file on disk, rounded up to whole blocks where the platform reports them
+fn disk_size(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+fn is_hex(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_hexdigit())
+}
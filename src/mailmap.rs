@@ -0,0 +1,126 @@
+// Canonicalizes the names and emails recorded in commits using a mailmap, read from `.mailmap`
+// at the root of the worktree and the file named by mailmap.file. Each line maps a commit
+// identity to a proper one, in one of the forms:
+//
+//   Proper Name <commit@email>
+//   <proper@email> <commit@email>
+//   Proper Name <proper@email> <commit@email>
+//   Proper Name <proper@email> Commit Name <commit@email>
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::repository::Repository;
+
+struct MailmapEntry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    // Only identities with this name are mapped, if set
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+pub struct Mailmap {
+    entries: Vec<MailmapEntry>,
+}
+
+impl Mailmap {
+    pub fn load(repository: &Repository, config: &Config) -> Self {
+        let files = [
+            Some(repository.worktree.join(".mailmap")),
+            config.get("mailmap.file").map(PathBuf::from),
+        ];
+        let entries = files
+            .into_iter()
+            .flatten()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .flat_map(|content| parse(&content))
+            .collect();
+
+        Self { entries }
+    }
+
+    /// Reads a mailmap from the file at `path` alone
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read mailmap {}", path.display()))?;
+        Ok(Self {
+            entries: parse(&content),
+        })
+    }
+
+    /// Returns the canonical name and email for an identity. Entries that also match the name are
+    /// preferred over ones matching only the email, and later entries override earlier ones.
+    pub fn map(&self, name: &str, email: &str) -> (String, String) {
+        let matching = |entry: &&MailmapEntry| entry.commit_email.eq_ignore_ascii_case(email);
+        let entry = self
+            .entries
+            .iter()
+            .rev()
+            .filter(matching)
+            .find(|entry| {
+                entry
+                    .commit_name
+                    .as_ref()
+                    .is_some_and(|commit_name| commit_name.eq_ignore_ascii_case(name))
+            })
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .rev()
+                    .filter(matching)
+                    .find(|entry| entry.commit_name.is_none())
+            });
+
+        match entry {
+            Some(entry) => (
+                entry.proper_name.as_deref().unwrap_or(name).to_string(),
+                entry.proper_email.as_deref().unwrap_or(email).to_string(),
+            ),
+            None => (name.to_string(), email.to_string()),
+        }
+    }
+}
+
+/// Splits a `Name <email>` contact into its name (possibly empty) and email
+pub fn parse_contact(contact: &str) -> Option<(&str, &str)> {
+    let (name, rest) = contact.split_once('<')?;
+    let email = rest.strip_suffix('>')?;
+    Some((name.trim(), email))
+}
+
+fn parse(content: &str) -> Vec<MailmapEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(parse_line)
+        .collect()
+}
+
+fn parse_line(line: &str) -> Option<MailmapEntry> {
+    let non_empty = |name: &str| Some(name.trim().to_string()).filter(|name| !name.is_empty());
+
+    let (first_name, rest) = line.split_once('<')?;
+    let (first_email, rest) = rest.split_once('>')?;
+    let entry = match rest.split_once('<') {
+        Some((second_name, rest)) => {
+            let (second_email, _) = rest.split_once('>')?;
+            MailmapEntry {
+                proper_name: non_empty(first_name),
+                proper_email: Some(first_email.to_string()),
+                commit_name: non_empty(second_name),
+                commit_email: second_email.to_string(),
+            }
+        }
+        None => MailmapEntry {
+            proper_name: non_empty(first_name),
+            proper_email: None,
+            commit_name: None,
+            commit_email: first_email.to_string(),
+        },
+    };
+
+    Some(entry)
+}
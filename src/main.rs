@@ -1,19 +1,108 @@
+mod alias;
+mod apply;
+mod attributes;
+mod binary_patch;
+mod branch;
+mod cat_file;
+mod clean;
+mod config;
+mod credential;
+mod date;
+mod delta;
+mod diff;
+mod diffstat;
+mod filter;
+mod fsck;
+mod ident;
+mod ignore;
 mod kvlm;
+mod line_diff;
+mod line_log;
+mod linear_assignment;
+mod log;
+mod loose;
+mod mailmap;
+mod maintenance;
+mod merge;
+mod merge_driver;
+mod merge_file;
+mod name_rev;
+mod notes;
 mod object;
+mod pack;
+mod pack_objects;
+mod patch;
+mod patch_id;
+mod path_safety;
+mod pathspec;
+mod pickaxe;
+mod plan;
+mod prune;
+mod range_diff;
+mod ref_filter;
 mod refs;
+mod regex;
+mod rename;
+mod repack;
 mod repository;
+mod revwalk;
+mod sequencer;
+mod show_branch;
+mod signature;
+mod stash;
+mod switch;
+mod temporary;
+mod trace;
+mod trailers;
+mod tree_walk;
+mod unpack_objects;
+mod userdiff;
+mod var;
+mod wildmatch;
+mod word_diff;
+mod worktree;
 
-use clap::{Parser, Subcommand};
+use alias::Expansion;
+use anyhow::{Context, anyhow, bail};
+use apply::ApplyOptions;
+use attributes::{AttrValue, Attributes};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clean::CleanOptions;
+use config::Config;
+use filter::FilterOptions;
+use fsck::FsckOptions;
+use line_diff::{Algorithm, DiffOptions, Whitespace};
+use log::LogOptions;
+use mailmap::Mailmap;
+use maintenance::{MaintenanceOptions, Schedule, Scheduler, Task};
+use merge::{MergeError, MergeOptions, Outcome};
+use name_rev::NameRev;
+use notes::Notes;
 use object::GitrsObject::{CommitObject, TreeObject};
 use object::commit::Commit;
 use object::tag::{Tag, TagType};
-use object::tree::Leaf;
+use object::tree::{Leaf, Tree};
 use object::{GitrsObject, ObjectType};
-use refs::Ref;
+use pack::DeltaOptions;
+use pack::PackIndex;
+use pack_objects::PackObjectsOptions;
+use patch::PatchOptions;
+use pathspec::Pathspec;
+use plan::Plan;
+use prune::PruneOptions;
+use range_diff::RangeDiffOptions;
+use ref_filter::{RefFormatter, RefItem};
+use refs::{Ref, RefUpdate};
+use repack::RepackOptions;
 use repository::Repository;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use trailers::{IfExists, IfMissing, Message, Placement, Trailer, Where};
+use tree_walk::{TreeWalk, TreeWalkOptions};
+use unpack_objects::UnpackOptions;
+use worktree::Worktree;
 
 #[derive(Subcommand, Debug)]
 enum Command {
@@ -33,27 +122,974 @@ enum Command {
     },
     /// Prints the raw contents of an object (uncompressed and without the git header) to the
     /// stdout
-    CatFile { object: String },
+    CatFile {
+        /// Print the header and contents of each object named on the stdin, with the header in
+        /// FORMAT if given
+        #[arg(long = "batch", num_args = 0..=1, default_missing_value = cat_file::DEFAULT_BATCH_FORMAT)]
+        batch: Option<String>,
+        /// Like --batch, but only print the headers
+        #[arg(
+            long = "batch-check",
+            num_args = 0..=1,
+            default_missing_value = cat_file::DEFAULT_BATCH_FORMAT,
+            conflicts_with = "batch"
+        )]
+        batch_check: Option<String>,
+        #[arg(required_unless_present_any = ["batch", "batch_check"])]
+        object: Option<String>,
+    },
     /// Logs commits on the current branch
     Log {
         #[arg(default_value = "HEAD")]
         commit: String,
+        /// Show authors as recorded, without applying the mailmap
+        #[arg(long = "no-mailmap")]
+        no_mailmap: bool,
+        /// Check the signatures of signed commits, showing what the verifying program reports
+        #[arg(long = "show-signature")]
+        show_signature: bool,
+        /// Format of author dates, eg. `relative`, `iso` or `format:%Y-%m-%d`. Defaults to
+        /// log.date, or `default`.
+        #[arg(long = "date")]
+        date: Option<String>,
+        /// Only show commits more recent than DATE, eg. `2.weeks.ago` or `2005-04-07`
+        #[arg(long = "since", visible_alias = "after", value_name = "DATE")]
+        since: Option<String>,
+        /// Only show commits older than DATE
+        #[arg(long = "until", visible_alias = "before", value_name = "DATE")]
+        until: Option<String>,
+        /// Show the patch of each commit other than a merge, of the paths given if any. Like
+        /// `gitrs diff`, renames show as a deletion and a creation.
+        #[arg(short = 'p', long, conflicts_with = "line_ranges")]
+        patch: bool,
+        /// Trace the history of lines START to END of FILE, showing how each commit that changed
+        /// them did. Either end can be a /regex/, and END can be +COUNT or -COUNT lines from
+        /// START.
+        #[arg(short = 'L', value_name = "START,END:FILE", conflicts_with = "paths")]
+        line_ranges: Vec<String>,
+        /// Keep listing the history of the one path given past the commits that renamed it.
+        /// Defaults to log.follow.
+        #[arg(long)]
+        follow: bool,
+        /// Only show commits that change how many times STRING occurs in a file
+        #[arg(short = 'S', value_name = "STRING", conflicts_with_all = ["line_ranges", "grep_diff"])]
+        pickaxe: Option<String>,
+        /// Only show commits whose diff adds or removes a line matching REGEX
+        #[arg(short = 'G', value_name = "REGEX", conflicts_with = "line_ranges")]
+        grep_diff: Option<String>,
+        /// Take the STRING of -S as a regex, counting its matches
+        #[arg(long = "pickaxe-regex", conflicts_with = "grep_diff")]
+        pickaxe_regex: bool,
+        /// Ignore the case of letters in what -S and -G look for
+        #[arg(short = 'i', long = "regexp-ignore-case")]
+        ignore_case: bool,
+        /// Only show commits changing these paths, which are left out when they match what every
+        /// parent has
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// List the commits reachable from the given ones, newest first. `^<commit>` excludes the
+    /// commits reachable from it, `A..B` is short for `^A B`, and `A...B` lists the commits
+    /// reachable from either side but not both.
+    RevList {
+        /// Print the number of commits instead
+        #[arg(long)]
+        count: bool,
+        /// Mark commits with `<` or `>` for the side of a symmetric difference they are on
+        #[arg(long = "left-right")]
+        left_right: bool,
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    /// List the commits HEAD has that UPSTREAM doesn't, oldest first, marking with `-` those whose
+    /// change UPSTREAM already has under another commit, going by their patch ids, and with `+`
+    /// the rest. Merges are left out.
+    Cherry {
+        /// Also show each commit's subject
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Abbreviate hashes to N characters
+        #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "7")]
+        abbrev: Option<usize>,
+        /// Defaults to the upstream of the branch HEAD is on
+        upstream: Option<String>,
+        #[arg(default_value = "HEAD")]
+        head: String,
+        /// Leave out the commits reachable from LIMIT
+        limit: Option<String>,
+    },
+    /// Compute the patch id of each patch read from the stdin, such as `gitrs log -p` prints, and
+    /// print it followed by the commit the patch was found after
+    PatchId {
+        /// Add up the ids of each file's changes, so that the order of the files doesn't matter,
+        /// as patchid.stable does
+        #[arg(long, conflicts_with_all = ["unstable", "verbatim"])]
+        stable: bool,
+        /// Hash the whole patch at once, overriding patchid.stable
+        #[arg(long, conflicts_with = "verbatim")]
+        unstable: bool,
+        /// Like --stable, but keeping whitespace, as patchid.verbatim does
+        #[arg(long)]
+        verbatim: bool,
+    },
+    /// Compare two versions of a series of commits, pairing the commits of one with those of the
+    /// other whose changes are most alike and showing how each pair's patches differ
+    ///
+    /// The series are given as two ranges (OLD_BASE..OLD NEW_BASE..NEW), as the commits since BASE
+    /// on two branches (BASE OLD NEW), or as a symmetric range (OLD...NEW), which compares the
+    /// commits each side has that the other hasn't.
+    RangeDiff {
+        /// How much a pair's patches may differ, in percent of a commit's own diff
+        #[arg(long, value_name = "FACTOR", default_value_t = range_diff::DEFAULT_CREATION_FACTOR)]
+        creation_factor: usize,
+        /// Only list the pairs, without the diffs between their patches
+        #[arg(short = 's', long = "no-patch")]
+        no_patch: bool,
+        /// Leave out the commits only the new series has
+        #[arg(long, conflicts_with = "right_only")]
+        left_only: bool,
+        /// Leave out the commits only the old series has
+        #[arg(long)]
+        right_only: bool,
+        #[arg(required = true, num_args = 1..=3)]
+        ranges: Vec<String>,
+    },
+    /// Rewrite the history of every ref, removing paths and large blobs and mapping identities,
+    /// and write the old and new hash of each commit to .gitrs/filter-repo/commit-map
+    Filter {
+        /// Remove this file, or directory, from every commit
+        #[arg(long = "remove-path", value_name = "PATH")]
+        remove_paths: Vec<String>,
+        /// Remove the blobs larger than this, eg. `10M`
+        #[arg(long, value_name = "SIZE")]
+        strip_blobs_bigger_than: Option<String>,
+        /// Map the names and emails of authors, committers and taggers with this file, which is
+        /// in the format of .mailmap
+        #[arg(long, value_name = "FILE")]
+        author_map: Option<String>,
+        /// Only report how history and the refs would be rewritten
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
     },
     LsTree {
+        /// Recurse into subtrees, listing the entries within them instead
         #[arg(short = 'r', long = "recursive")]
         recursive: bool,
         tree: String,
+        /// Only list these paths
+        paths: Vec<String>,
+    },
+    /// Build a tree object from ls-tree formatted lines on the stdin, printing its hash
+    Mktree {
+        /// Read NUL-terminated lines
+        #[arg(short = 'z')]
+        nul: bool,
+        /// Don't check that the listed objects exist
+        #[arg(long = "missing")]
+        missing: bool,
+    },
+    /// Validate a tag object read from the stdin and write it, printing its hash
+    Mktag,
+    /// Count the loose objects and the disk space they use
+    CountObjects {
+        /// Also report packs, loose objects that are already packed, and garbage files
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Print sizes in human readable units
+        #[arg(short = 'H', long = "human-readable")]
+        human_readable: bool,
+    },
+    /// Check that packs are intact and match their indexes
+    VerifyPack {
+        /// List each object with how it is stored, and how long the chains of deltas are
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Only list how long the chains of deltas are
+        #[arg(short = 's', long = "stat-only")]
+        stat_only: bool,
+        /// The packs, by their .idx or .pack file
+        #[arg(required = true)]
+        packs: Vec<PathBuf>,
+    },
+    /// Delete unreachable loose objects, and loose objects that are already packed
+    Prune {
+        /// Only report the objects that would be removed
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        /// Report the objects removed
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Only delete unreachable objects older than this, eg. `2.weeks.ago`
+        #[arg(long = "expire")]
+        expire: Option<String>,
+    },
+    /// Delete loose objects that are already packed
+    PrunePacked {
+        /// Only print the files that would be removed
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+    /// Pack the reachable objects that aren't packed yet into a new pack
+    Repack {
+        /// Pack everything reachable into a single pack
+        #[arg(short = 'a')]
+        all: bool,
+        /// Delete the packs and loose objects that were repacked
+        #[arg(short = 'd')]
+        delete: bool,
+        /// Roll the smallest packs and the loose objects together so that each pack has at least
+        /// FACTOR times the objects of the next smaller one
+        #[arg(short = 'g', long, value_name = "FACTOR", conflicts_with = "all",
+              value_parser = clap::value_parser!(u64).range(1..))]
+        geometric: Option<u64>,
+        /// Leave this pack alone, as though it had a .keep file
+        #[arg(long = "keep-pack", value_name = "PACK_NAME")]
+        keep_packs: Vec<String>,
+        /// Repack the objects of kept packs too, still leaving the kept packs in place
+        #[arg(long)]
+        pack_kept_objects: bool,
+        /// Only report the pack that would be written and the files that would be deleted
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+    /// Write a pack of the objects listed on the stdin, or with --revs those the revisions there
+    /// reach, to the stdout or to BASE_NAME-<hash>.pack
+    PackObjects {
+        /// Write the pack to the stdout rather than to a file
+        #[arg(long, conflicts_with = "base_name")]
+        stdout: bool,
+        /// Read revisions rather than objects, packing what they reach
+        #[arg(long)]
+        revs: bool,
+        /// Pack everything reachable from refs and HEAD, implying --revs
+        #[arg(long)]
+        all: bool,
+        /// How many other objects each object is tried against for a delta, 0 for none
+        #[arg(long, value_name = "N")]
+        window: Option<usize>,
+        /// The longest chain of deltas
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..4096))]
+        depth: Option<u64>,
+        /// Refer to delta bases by where they are in the pack rather than by hash
+        #[arg(long)]
+        delta_base_offset: bool,
+        /// Leave out objects in packs with a .keep file
+        #[arg(long)]
+        honor_pack_keep: bool,
+        /// Pack the true parents of commits rather than those grafts give them. gitrs has no
+        /// grafts and always walks the history as recorded, so this is what it does anyway.
+        #[arg(long)]
+        keep_true_parents: bool,
+        /// Leave out objects that are packed already
+        #[arg(long)]
+        incremental: bool,
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+        #[arg(required_unless_present = "stdout")]
+        base_name: Option<String>,
+    },
+    /// Check that the objects are well-formed and that what is reachable is all there, listing the
+    /// dangling objects
+    Fsck {
+        /// Only check that the reachable objects are there, which is much faster
+        #[arg(long)]
+        connectivity_only: bool,
+        /// Write dangling commits to lost-found/commit and other dangling objects to
+        /// lost-found/other, blobs with their contents
+        #[arg(long)]
+        lost_found: bool,
+        /// Don't list dangling objects
+        #[arg(long = "no-dangling")]
+        no_dangling: bool,
+    },
+    /// Unpack the pack on the stdin into loose objects
+    UnpackObjects {
+        /// Check the pack without writing anything
+        #[arg(short = 'n')]
+        dry_run: bool,
+        /// Check that the objects are well-formed and link to objects that exist, writing nothing
+        /// if any don't
+        #[arg(long)]
+        strict: bool,
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+    /// Compare two trees, or a commit with its parent, printing the changed entries
+    DiffTree {
+        /// Descend into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Terminate paths with NUL instead of newlines
+        #[arg(short = 'z')]
+        nul: bool,
+        /// Show a root commit as adding all of its files
+        #[arg(long = "root")]
+        root: bool,
+        /// Don't print the commit being compared with its parent
+        #[arg(long = "no-commit-id")]
+        no_commit_id: bool,
+        /// Exit with 1 if there are differences and 0 otherwise
+        #[arg(long = "exit-code")]
+        exit_code: bool,
+        /// Don't print anything, implying --exit-code
+        #[arg(long = "quiet")]
+        quiet: bool,
+        tree: String,
+        other: Option<String>,
+        /// Only compare these paths
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Show the changes from HEAD, which stands in for the index, or from a commit to the
+    /// worktree, or between two commits, as patches
+    Diff {
+        /// Compare with HEAD instead of the worktree, as there is no index
+        #[arg(long = "cached", alias = "staged")]
+        cached: bool,
+        /// Print a diffstat instead of the patch
+        #[arg(long = "stat")]
+        stat: bool,
+        /// Show N lines of context around each change, also printing the patch with --stat
+        #[arg(short = 'U', long = "unified")]
+        unified: Option<usize>,
+        /// Ignore whitespace when comparing lines
+        #[arg(short = 'w', long = "ignore-all-space")]
+        ignore_all_space: bool,
+        /// Ignore changes in the amount of whitespace
+        #[arg(short = 'b', long = "ignore-space-change")]
+        ignore_space_change: bool,
+        /// Ignore changes in whitespace at the end of lines
+        #[arg(long = "ignore-space-at-eol")]
+        ignore_space_at_eol: bool,
+        /// Find the changed lines with myers, minimal, patience or histogram, over diff.algorithm
+        #[arg(
+            long = "diff-algorithm",
+            value_name = "ALGORITHM",
+            value_parser = ["myers", "default", "minimal", "patience", "histogram"]
+        )]
+        diff_algorithm: Option<String>,
+        /// Show the changes word by word: plain, color, porcelain or none
+        #[arg(
+            long = "word-diff",
+            value_name = "MODE",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "plain",
+            value_parser = ["plain", "color", "porcelain", "none"]
+        )]
+        word_diff: Option<String>,
+        /// What counts as a word, implying --word-diff
+        #[arg(long = "word-diff-regex", value_name = "REGEX")]
+        word_diff_regex: Option<String>,
+        /// Show the changed words in color, optionally with what counts as a word
+        #[arg(
+            long = "color-words",
+            value_name = "REGEX",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = ""
+        )]
+        color_words: Option<String>,
+        /// Compare files as they are, without converting them with their textconv drivers
+        #[arg(long = "no-textconv")]
+        no_textconv: bool,
+        /// Don't run external diff programs
+        #[arg(long = "no-ext-diff")]
+        no_ext_diff: bool,
+        /// Show binary files as patches git apply can apply, with hashes in full, implying the
+        /// patch is printed with --stat
+        #[arg(long = "binary")]
+        binary: bool,
+        /// Exit with 1 if there are differences and 0 otherwise
+        #[arg(long = "exit-code")]
+        exit_code: bool,
+        /// Don't print anything, implying --exit-code
+        #[arg(long = "quiet")]
+        quiet: bool,
+        /// A commit to compare with the worktree, two commits, or a range A..B
+        commits: Vec<String>,
+        /// Only compare these paths
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Compare a tree with the worktree, treating the files tracked in HEAD as the index
+    DiffIndex {
+        /// Compare with HEAD instead of the worktree, as there is no index
+        #[arg(long = "cached")]
+        cached: bool,
+        /// Terminate paths with NUL instead of newlines
+        #[arg(short = 'z')]
+        nul: bool,
+        /// Exit with 1 if there are differences and 0 otherwise
+        #[arg(long = "exit-code")]
+        exit_code: bool,
+        /// Don't print anything, implying --exit-code
+        #[arg(long = "quiet")]
+        quiet: bool,
+        tree: String,
+        /// Only compare these paths
+        paths: Vec<String>,
+    },
+    /// Compare the files tracked in HEAD with the worktree
+    DiffFiles {
+        /// Terminate paths with NUL instead of newlines
+        #[arg(short = 'z')]
+        nul: bool,
+        /// Exit with 1 if there are differences and 0 otherwise
+        #[arg(long = "exit-code")]
+        exit_code: bool,
+        /// Don't print anything, implying --exit-code
+        #[arg(long = "quiet")]
+        quiet: bool,
+        /// Only compare these paths
+        paths: Vec<String>,
     },
     /// Checkout a commit inside of a directory
     Checkout { commit: String, path: String },
+    /// Switch to a branch, updating the files that differ from HEAD. `-` is the branch that was
+    /// checked out before.
+    Switch {
+        /// Create a new branch at the given start point (HEAD by default) and switch to it
+        #[arg(short = 'c', long = "create", value_name = "NEW_BRANCH")]
+        create: Option<String>,
+        /// Detach HEAD at the given commit (HEAD by default) instead of switching branches
+        #[arg(long = "detach", conflicts_with = "create")]
+        detach: bool,
+        #[arg(required_unless_present_any = ["create", "detach"])]
+        branch: Option<String>,
+    },
     /// List references
-    ShowRef,
+    ShowRef {
+        /// Only show branches
+        #[arg(long = "heads")]
+        heads: bool,
+        /// Only show tags
+        #[arg(long = "tags")]
+        tags: bool,
+        /// Also show the objects annotated tags point to, as `<tag>^{}`
+        #[arg(short = 'd', long = "dereference")]
+        dereference: bool,
+        /// Only show the hashes, abbreviated to N characters if given
+        #[arg(short = 's', long = "hash", num_args = 0..=1, default_missing_value = "40")]
+        hash: Option<usize>,
+        /// Require each pattern to be the full name of an existing ref
+        #[arg(long = "verify")]
+        verify: bool,
+        /// Don't print anything, only set the exit status
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+        /// Also show HEAD
+        #[arg(long = "head")]
+        head: bool,
+        /// Show refs whose name ends with one of PATTERNS, matching whole components
+        patterns: Vec<String>,
+    },
+    /// Name COMMITS after the refs they are reachable from, eg. `master~2`
+    NameRev {
+        /// Only print the names, without the commits
+        #[arg(long = "name-only")]
+        name_only: bool,
+        /// Only use tags to name commits
+        #[arg(long = "tags")]
+        tags: bool,
+        /// Only use refs matching the pattern, which can be given several times
+        #[arg(long = "refs")]
+        refs: Vec<String>,
+        /// Copy the stdin to the stdout, naming every full commit hash in it
+        #[arg(long = "annotate-stdin")]
+        annotate_stdin: bool,
+        #[arg(required_unless_present = "annotate_stdin")]
+        commits: Vec<String>,
+    },
+    /// Fill, approve or reject a credential read from the stdin, using the credential.helper
+    /// programs
+    Credential {
+        #[arg(value_parser = ["fill", "approve", "reject"])]
+        action: String,
+    },
+    /// Credential helper that stores credentials in plain text, in ~/.gitrs-credentials
+    CredentialStore {
+        /// Store the credentials in this file instead
+        #[arg(long = "file")]
+        file: Option<String>,
+        action: String,
+    },
+    /// Print a logical variable, such as GIT_AUTHOR_IDENT or GIT_EDITOR
+    Var {
+        /// List the config followed by every variable instead
+        #[arg(short = 'l', conflicts_with = "variable")]
+        list: bool,
+        #[arg(required_unless_present = "list")]
+        variable: Option<String>,
+    },
+    /// List branches, create one at START (HEAD by default), or delete them
+    Branch {
+        /// Delete the branches, which must be merged into their upstream or HEAD
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+        /// Delete the branches even if they aren't merged
+        #[arg(short = 'D', conflicts_with = "delete")]
+        force_delete: bool,
+        /// List the branches matching the NAMES patterns instead of creating one
+        #[arg(short = 'l', long = "list")]
+        list: bool,
+        /// Only list branches containing COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "contains", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Vec<String>,
+        /// Only list branches not containing COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "no-contains", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_contains: Vec<String>,
+        /// Only list branches reachable from COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "merged", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Vec<String>,
+        /// Only list branches not reachable from COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "no-merged", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Vec<String>,
+        /// When listing, show each branch's commit and how it compares with its upstream, naming
+        /// the upstream too if given twice
+        #[arg(short = 'v', long = "verbose", action = clap::ArgAction::Count)]
+        verbose: u8,
+        /// Have the new branch track its start point, which must be a branch
+        #[arg(short = 't', long = "track")]
+        track: bool,
+        /// Have the branch given (the current one by default) track UPSTREAM
+        #[arg(
+            short = 'u',
+            long = "set-upstream-to",
+            value_name = "UPSTREAM",
+            conflicts_with = "track"
+        )]
+        set_upstream_to: Option<String>,
+        names: Vec<String>,
+    },
+    /// Show branches side by side, with the commits each has that the others don't, down to the
+    /// first commit common to all of them. Without REVS, all local branches are shown.
+    ShowBranch {
+        /// Go this many commits past the first common one
+        #[arg(long = "more", default_value_t = 0)]
+        more: usize,
+        revs: Vec<String>,
+    },
     /// Create or list tags
     Tag {
         #[arg(short = 'a', long = "annotated")]
         annotated: bool,
-        name: Option<String>,
-        object: Option<String>,
+        /// List the tags matching the patterns given instead of NAME and OBJECT
+        #[arg(short = 'l', long = "list")]
+        list: bool,
+        /// Field to sort the listing by, as for for-each-ref. `version:refname` sorts by version
+        /// number. Defaults to tag.sort, or refname.
+        #[arg(long = "sort")]
+        sort: Vec<String>,
+        /// Show up to this many lines of each tag's message (1 if not given). Implies --list.
+        #[arg(short = 'n', value_name = "N", num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<usize>,
+        /// Only list tags containing COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "contains", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Vec<String>,
+        /// Only list tags not containing COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "no-contains", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_contains: Vec<String>,
+        /// Only list tags reachable from COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "merged", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Vec<String>,
+        /// Only list tags not reachable from COMMIT (HEAD if not given). Implies --list.
+        #[arg(long = "no-merged", value_name = "COMMIT", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Vec<String>,
+        /// NAME and OBJECT of the tag to create, or patterns when listing
+        args: Vec<String>,
+    },
+    /// Display the gitattributes of each PATH
+    ///
+    /// Without `--`, the first argument is the attribute and the remaining ones are paths
+    CheckAttr {
+        /// Report every attribute specified for the paths
+        #[arg(short = 'a', long = "all")]
+        all: bool,
+        args: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Check the signatures of commits, printing what the verifying program reports. Exits with
+    /// status 1 unless all of them are signed and valid.
+    VerifyCommit {
+        /// Also print the contents of each commit, without the signature
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Print gpg's machine-readable status lines instead
+        #[arg(long = "raw")]
+        raw: bool,
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    /// Check the signatures of tag objects, like verify-commit
+    VerifyTag {
+        /// Also print the contents of each tag, without the signature
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        /// Print gpg's machine-readable status lines instead
+        #[arg(long = "raw")]
+        raw: bool,
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+    /// Remove untracked files from the worktree, starting from the current directory
+    Clean {
+        /// Actually remove the files, required unless clean.requireForce is false
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        /// Only show what would be removed
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        /// Also remove untracked directories
+        #[arg(short = 'd')]
+        directories: bool,
+        /// Also remove files ignored by .gitignore and info/exclude
+        #[arg(short = 'x')]
+        include_ignored: bool,
+        /// Only remove these paths
+        paths: Vec<String>,
+    },
+    /// Summarize history, grouping commit subjects by author
+    Shortlog {
+        /// Only show the number of commits per author
+        #[arg(short = 's', long = "summary")]
+        summary: bool,
+        /// Sort authors by their number of commits instead of by name
+        #[arg(short = 'n', long = "numbered")]
+        numbered: bool,
+        /// Show the email address of each author
+        #[arg(short = 'e', long = "email")]
+        email: bool,
+        #[arg(default_value = "HEAD")]
+        revisions: Vec<String>,
+    },
+    /// Show the canonical name and email of each CONTACT (`Name <email>` or `<email>`)
+    /// according to the mailmap
+    CheckMailmap { contacts: Vec<String> },
+    /// Add, show or remove notes attached to objects
+    Notes {
+        /// Notes ref to use instead of core.notesRef or refs/notes/commits
+        #[arg(long = "ref")]
+        notes_ref: Option<String>,
+        #[command(subcommand)]
+        cmd: NotesCommand,
+    },
+    /// Replace OBJECT with REPLACEMENT wherever it is read, or list and delete replacements
+    Replace {
+        /// List the replaced objects matching the pattern
+        #[arg(short = 'l', long = "list", conflicts_with = "delete")]
+        list: bool,
+        /// Delete the replacements of the given objects
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+        /// Overwrite an existing replacement, and allow objects of different types
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        args: Vec<String>,
+    },
+    /// Apply unified diffs read from PATCHES, or the stdin if none are given, to the files in the
+    /// current directory
+    Apply {
+        /// Only check that the patches apply, without changing any files
+        #[arg(long = "check")]
+        check: bool,
+        /// Apply the patches in reverse
+        #[arg(short = 'R', long = "reverse")]
+        reverse: bool,
+        /// Apply the hunks that apply, and write the others to <file>.rej
+        #[arg(long = "reject")]
+        reject: bool,
+        /// Merge the patches into the files from the versions they were made from, leaving
+        /// conflict markers where they don't apply
+        #[arg(short = '3', long = "3way", conflicts_with = "reject")]
+        three_way: bool,
+        /// Remove N leading path components from the paths in the patches
+        #[arg(short = 'p', default_value_t = 1)]
+        strip: usize,
+        /// Report progress on the stderr
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+        patches: Vec<String>,
+    },
+    /// Set REF to NEWVALUE, optionally checking that it is currently OLDVALUE
+    ///
+    /// An OLDVALUE of all zeros (or an empty string) requires that REF doesn't exist yet
+    UpdateRef {
+        /// Delete REF instead of updating it
+        #[arg(short = 'd')]
+        delete: bool,
+        /// Update symbolic refs themselves rather than the refs they point to
+        #[arg(long = "no-deref")]
+        no_deref: bool,
+        /// Read update/create/delete/verify commands from the stdin and apply them atomically
+        #[arg(long = "stdin", conflicts_with = "delete")]
+        stdin: bool,
+        /// Only check the updates and report what they would do
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        #[arg(required_unless_present = "stdin")]
+        args: Vec<String>,
+    },
+    /// Read, change or delete the symbolic ref NAME
+    SymbolicRef {
+        /// Delete the symbolic ref
+        #[arg(short = 'd', long = "delete")]
+        delete: bool,
+        /// Don't report an error if NAME is not a symbolic ref
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+        /// Shorten the printed target, eg. refs/heads/master to master
+        #[arg(long = "short")]
+        short: bool,
+        name: String,
+        target: Option<String>,
+    },
+    /// Check that REFNAME is a valid ref name, exiting with status 1 if it isn't
+    CheckRefFormat {
+        /// Collapse repeated slashes and drop a leading one, printing the result if it is valid
+        #[arg(long = "normalize")]
+        normalize: bool,
+        /// Allow names with a single component
+        #[arg(long = "allow-onelevel")]
+        allow_onelevel: bool,
+        /// Allow a single `*`, as in refspecs
+        #[arg(long = "refspec-pattern")]
+        refspec_pattern: bool,
+        /// Check a branch name instead, expanding `@{-N}` and printing the result
+        #[arg(long = "branch", conflicts_with_all = ["normalize", "allow_onelevel", "refspec_pattern"])]
+        branch: bool,
+        refname: String,
+    },
+    /// List the refs matching PATTERNS (all refs if none are given), formatted with `%(field)`
+    /// placeholders
+    ForEachRef {
+        /// Format of each line, eg. `%(refname:short) %(upstream:track)`
+        #[arg(long = "format", default_value = ref_filter::DEFAULT_FORMAT)]
+        format: String,
+        /// Field to sort by, descending if prefixed with `-`. Can be repeated, the first being the
+        /// primary key
+        #[arg(long = "sort", default_value = "refname")]
+        sort: Vec<String>,
+        /// Stop after showing COUNT refs
+        #[arg(long = "count")]
+        count: Option<usize>,
+        patterns: Vec<String>,
+    },
+    /// Add or parse trailers in commit messages read from FILES, or the stdin if none are given
+    InterpretTrailers {
+        /// Trailer to add, as `token: value` or `token=value`
+        #[arg(long = "trailer", value_parser = Trailer::parse_arg)]
+        trailers: Vec<Trailer>,
+        /// Edit the files in place instead of printing to the stdout
+        #[arg(long = "in-place")]
+        in_place: bool,
+        /// Remove trailers with an empty value
+        #[arg(long = "trim-empty")]
+        trim_empty: bool,
+        /// Only output the trailers
+        #[arg(long = "only-trailers")]
+        only_trailers: bool,
+        /// Don't add the trailers given with --trailer
+        #[arg(long = "only-input")]
+        only_input: bool,
+        /// Join continuation lines of multi-line trailers
+        #[arg(long = "unfold")]
+        unfold: bool,
+        /// Shorthand for --only-trailers --only-input --unfold
+        #[arg(long = "parse")]
+        parse: bool,
+        /// Where to add the trailers given after it: end (the default), start, after or before
+        #[arg(long = "where")]
+        position: Vec<Where>,
+        /// What to do with the trailers given after it if a trailer with the same token exists:
+        /// addIfDifferentNeighbor (the default), addIfDifferent, add, replace or doNothing
+        #[arg(long = "if-exists")]
+        if_exists: Vec<IfExists>,
+        /// What to do with the trailers given after it if no trailer with the same token exists:
+        /// add (the default) or doNothing
+        #[arg(long = "if-missing")]
+        if_missing: Vec<IfMissing>,
+        files: Vec<String>,
+    },
+    /// Run tasks that keep the repository fast to read as it grows
+    Maintenance {
+        #[command(subcommand)]
+        cmd: MaintenanceCommand,
+    },
+    /// Run a gitrs command in each repository the multi-valued config KEY lists, as scheduled
+    /// maintenance does, stopping at the first that fails
+    ForEachRepo {
+        #[arg(long = "config", value_name = "KEY")]
+        config: String,
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Manage multiple worktrees attached to the same repository
+    Worktree {
+        #[command(subcommand)]
+        cmd: WorktreeCommand,
+    },
+    /// Show, apply and drop the stashes recorded in refs/stash
+    Stash {
+        #[command(subcommand)]
+        cmd: StashCommand,
+    },
+    /// Merge one or more commits into HEAD, fast-forwarding when HEAD is behind them
+    Merge {
+        /// Leave the merged files in the worktree without committing them, as after a conflict
+        #[arg(long = "no-commit")]
+        no_commit: bool,
+        /// Apply the changes without recording a merge, leaving a message for committing them
+        /// in SQUASH_MSG
+        #[arg(long = "squash")]
+        squash: bool,
+        /// Make a merge commit even when HEAD could be fast-forwarded
+        #[arg(long = "no-ff")]
+        no_ff: bool,
+        /// Message for the merge commit
+        #[arg(short = 'm', long = "message")]
+        message: Option<String>,
+        /// Pass an option to the merge strategy: ignore-space-change, ignore-all-space or
+        /// ignore-space-at-eol
+        #[arg(short = 'X', long = "strategy-option")]
+        strategy_options: Vec<String>,
+        /// Give up on the merge in progress, restoring the files it changed
+        #[arg(long = "abort", conflicts_with_all = ["commits", "continue_merge", "quit"])]
+        abort: bool,
+        /// Commit the merge in progress once its conflicts are resolved
+        #[arg(long = "continue", conflicts_with_all = ["commits", "quit"])]
+        continue_merge: bool,
+        /// Forget about the merge in progress, leaving the worktree as it is
+        #[arg(long = "quit", conflicts_with = "commits")]
+        quit: bool,
+        /// The commits to merge, several of which are merged together in one commit
+        #[arg(required_unless_present_any = ["abort", "continue_merge", "quit"])]
+        commits: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StashCommand {
+    /// List the stashes, newest first
+    List,
+    /// Show the status and path of each file a stash changes, the newest one by default
+    Show { stash: Option<String> },
+    /// Restore the changes recorded in a stash to the worktree
+    Apply { stash: Option<String> },
+    /// Apply a stash, then drop it
+    Pop { stash: Option<String> },
+    /// Remove a stash from the list
+    Drop { stash: Option<String> },
+    /// Create a branch at the commit a stash was made on, and pop the stash onto it
+    Branch { name: String, stash: Option<String> },
+}
+
+#[derive(Subcommand, Debug)]
+enum MaintenanceCommand {
+    /// Run the maintenance tasks given, or those maintenance.<task>.enabled turns on
+    Run {
+        /// A task to run: loose-objects or incremental-repack
+        #[arg(long = "task", value_name = "TASK")]
+        tasks: Vec<Task>,
+        /// Only run the tasks that have enough to do
+        #[arg(long, conflicts_with = "schedule")]
+        auto: bool,
+        /// Only run the tasks scheduled to run at least this often: hourly, daily or weekly
+        #[arg(long, value_name = "FREQUENCY")]
+        schedule: Option<Schedule>,
+        /// Only report what the tasks would pack and delete
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+    /// Add the repository to maintenance.repo, for scheduled maintenance to run on it
+    Register,
+    /// Remove the repository from maintenance.repo
+    Unregister,
+    /// Register the repository and install the schedule that runs maintenance in the background
+    Start {
+        /// What installs the schedule: auto or crontab
+        #[arg(long, default_value = "auto")]
+        scheduler: Scheduler,
+    },
+    /// Remove the schedule that runs maintenance in the background
+    Stop {
+        /// What installed the schedule: auto or crontab
+        #[arg(long, default_value = "auto")]
+        scheduler: Scheduler,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum WorktreeCommand {
+    /// Create a worktree at PATH and checkout COMMIT into it
+    ///
+    /// If COMMIT is a branch name, the new worktree's HEAD is attached to that branch
+    Add {
+        /// Create a new branch at COMMIT and attach the worktree to it
+        #[arg(short = 'b')]
+        branch: Option<String>,
+        path: String,
+        #[arg(default_value = "HEAD")]
+        commit: String,
+    },
+    /// List the main worktree and all linked worktrees
+    List,
+    /// Delete a linked worktree
+    Remove {
+        /// Delete the worktree even if it contains modified or untracked files
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        path: String,
+    },
+    /// Remove administrative files of worktrees whose directory no longer exists
+    Prune {
+        /// Only report the worktrees that would be pruned
+        #[arg(short = 'n', long = "dry-run")]
+        dry_run: bool,
+        /// Report the pruned worktrees
+        #[arg(short = 'v', long = "verbose")]
+        verbose: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum NotesCommand {
+    /// List the notes object for OBJECT, or all notes and the objects they annotate
+    List { object: Option<String> },
+    /// Add a note to OBJECT
+    Add {
+        /// Note contents, multiple messages are joined as separate paragraphs
+        #[arg(short = 'm', long = "message", required = true)]
+        messages: Vec<String>,
+        /// Overwrite an existing note
+        #[arg(short = 'f', long = "force")]
+        force: bool,
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Append to the note of OBJECT, creating it if needed
+    Append {
+        #[arg(short = 'm', long = "message", required = true)]
+        messages: Vec<String>,
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Show the note attached to OBJECT
+    Show {
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Remove the note attached to OBJECT
+    Remove {
+        #[arg(default_value = "HEAD")]
+        object: String,
     },
 }
 
@@ -61,148 +1097,2641 @@ enum Command {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Gitrs {
+    /// Don't use the replacement objects recorded under refs/replace
+    #[arg(long = "no-replace-objects", global = true)]
+    no_replace_objects: bool,
+    /// Only show the refs stored under refs/namespaces/<namespace>, as though they were all the
+    /// repository has
+    #[arg(long, global = true, value_name = "namespace")]
+    namespace: Option<String>,
     #[command(subcommand)]
     cmd: Command,
 }
 
 fn main() {
-    let gitrs = Gitrs::parse();
+    // Like git, a closed pipe on stdout ends the command quietly, with the status of a command
+    // killed by SIGPIPE. Any other panic is a bug, reported as usual.
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if closed_pipe(info) {
+            std::process::exit(141);
+        }
+        report(info)
+    }));
+    if let Err(e) = run() {
+        let broken_pipe = e
+            .root_cause()
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe);
+        if broken_pipe {
+            std::process::exit(141);
+        }
+        // Like git, what a command failed with is reported after `fatal: `, with status 128
+        eprintln!("fatal: {:#}", e);
+        std::process::exit(128);
+    }
+}
+
+// Runs the command the command line asks for
+fn run() -> anyhow::Result<()> {
+    let args = expand_aliases()?;
+    let _trace = trace::command(&args[1..]);
+    let command = Gitrs::command();
+    let parsed = command
+        .try_get_matches_from(args)
+        .and_then(|matches| Gitrs::from_arg_matches(&matches).map(|gitrs| (gitrs, matches)));
+    let (gitrs, matches) = parsed.unwrap_or_else(|e| {
+        // Help and version requests aren't usage errors
+        let code = if e.use_stderr() { 129 } else { 0 };
+        let _ = e.print();
+        std::process::exit(code)
+    });
+    if gitrs.no_replace_objects {
+        // SAFETY: no other threads have been spawned yet
+        unsafe { std::env::set_var("GITRS_NO_REPLACE_OBJECTS", "1") };
+    }
+    if let Some(namespace) = &gitrs.namespace {
+        // SAFETY: as above
+        unsafe { std::env::set_var("GITRS_NAMESPACE", namespace) };
+    }
 
     match gitrs.cmd {
         Command::Init { path } => {
-            match Repository::init(Path::new(&path)) {
-                Ok(_) => println!("Successfully initialized git repository"),
-                Err(e) => println!("An error occurred initializing gitrs repository: {}", e),
-            };
+            Repository::init(Path::new(&path))
+                .context("An error occurred initializing gitrs repository")?;
+            println!("Successfully initialized git repository");
         }
         Command::HashObject { object_type, path } => {
-            let file = File::open(path).expect("Could not open file");
+            let file = File::open(path).context("Could not open file")?;
             let mut data = Vec::new();
             let _size = BufReader::new(file)
                 .read_to_end(&mut data)
-                .expect("Could not read file");
+                .context("Could not read file")?;
 
-            let repository = Repository::find_repository();
+            let repository = Repository::find_repository()?;
             let hash =
-                GitrsObject::deserialize_and_write(&repository, data.as_slice(), object_type);
+                GitrsObject::deserialize_and_write(&repository, data.as_slice(), object_type)?;
             println!("{}", hash);
         }
-        Command::CatFile { object } => {
-            let repository = Repository::find_repository();
+        Command::CatFile {
+            batch,
+            batch_check,
+            object,
+        } => {
+            let repository = Repository::find_repository()?;
+            if let Some(format) = &batch {
+                return cat_file::batch(&repository, format, true).context("Batch failed");
+            }
+            if let Some(format) = &batch_check {
+                return cat_file::batch(&repository, format, false).context("Batch failed");
+            }
+            let object = object.context("An object is required outside of batch mode")?;
 
             let hash = GitrsObject::find(&repository, &object)
-                .expect(&format!("Couldn't find object with name: {}", object));
+                .map_err(|_| anyhow!("Couldn't find object with name: {}", object))?;
 
             let mut obj = GitrsObject::read(&repository, &hash)
-                .expect(&format!("Couldn't read object with hash: {}", hash));
+                .map_err(|_| anyhow!("Couldn't read object with hash: {}", hash))?;
 
             print!("Object contents");
             GitrsObject::dump(&obj.serialize());
         }
-        Command::Log { commit } => {
-            // TODO: list all commits, also default the commit to HEAD rather than using the actual
-            // hash
-            // This can be achieved using the `object_find` method
+        Command::Log {
+            commit,
+            no_mailmap,
+            show_signature,
+            date,
+            since,
+            until,
+            patch,
+            line_ranges,
+            follow,
+            pickaxe,
+            grep_diff,
+            pickaxe_regex,
+            ignore_case,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let options = LogOptions {
+                commit,
+                no_mailmap,
+                show_signature,
+                date,
+                since,
+                until,
+                patch,
+                line_ranges,
+                follow,
+                pickaxe,
+                grep_diff,
+                pickaxe_regex,
+                ignore_case,
+                paths,
+            };
+            let output = log::log(&repository, &options)?;
+            std::io::stdout()
+                .write_all(&output)
+                .context("Couldn't write log")?;
+        }
+        Command::RevList {
+            count,
+            left_right,
+            commits,
+        } => {
+            let repository = Repository::find_repository()?;
+            let find = |name: &str| {
+                revwalk::find_commit(&repository, name)
+                    .map_err(|_| anyhow!("Couldn't find commit: {}", name))
+            };
 
-            let repository = Repository::find_repository();
-            if let Ok(CommitObject(commit_obj)) = GitrsObject::read(&repository, &commit) {
-                println!("[{}] {}", Commit::short(&commit), commit_obj.message());
-            } else {
-                panic!("Expected commit");
+            let (mut left, mut right, mut hidden) = (Vec::new(), Vec::new(), Vec::new());
+            for name in &commits {
+                if let Some((a, b)) = name.split_once("...") {
+                    left.push(find(a)?);
+                    right.push(find(b)?);
+                } else if let Some((a, b)) = name.split_once("..") {
+                    hidden.push(find(a)?);
+                    right.push(find(b)?);
+                } else if let Some(name) = name.strip_prefix('^') {
+                    hidden.push(find(name)?);
+                } else {
+                    right.push(find(name)?);
+                }
             }
-        }
-        Command::LsTree { recursive, tree } => {
-            let repository = Repository::find_repository();
-            if let Ok(TreeObject(tree_obj)) = GitrsObject::read(&repository, &tree) {
-                // TODO : fix formatting of the lst tree message
-                // and implement recursive handling
-                tree_obj.records.iter().for_each(|leaf| {
-                    let obj_type = Leaf::get_type_from_mode(&leaf.file_mode);
-                    println!("Found {} object", &obj_type.to_string());
-                })
-            } else {
-                panic!("Expected a tree");
+
+            let commits = revwalk::difference(&repository, &left, &right, &hidden)
+                .context("Couldn't walk history")?;
+            if count {
+                let lefts = commits
+                    .iter()
+                    .filter(|(_, side)| *side == revwalk::Side::Left)
+                    .count();
+                if left_right {
+                    println!("{}\t{}", lefts, commits.len() - lefts);
+                } else {
+                    println!("{}", commits.len());
+                }
+                return Ok(());
+            }
+            for (hash, side) in commits {
+                match side {
+                    _ if !left_right => println!("{}", hash),
+                    revwalk::Side::Left => println!("<{}", hash),
+                    revwalk::Side::Right => println!(">{}", hash),
+                }
             }
         }
-        Command::Checkout {
-            commit,
-            path: path_str,
+        Command::Cherry {
+            verbose,
+            abbrev,
+            upstream,
+            head,
+            limit,
         } => {
-            let path = Path::new(&path_str);
-            // TODO: could also make a dir if not exists
-            if !repository::is_empty_dir(path) {
-                panic!("Expected an empty dir at {}", path_str);
+            let repository = Repository::find_repository()?;
+            let upstream = match upstream {
+                Some(upstream) => upstream,
+                None => {
+                    let config = Config::load(&repository).context("Couldn't read config")?;
+                    branch::current(&repository)
+                        .context("Couldn't read HEAD")?
+                        .and_then(|branch| branch::upstream(&config, &branch))
+                        .context("Could not find a tracked remote branch, please specify <upstream> manually.")?
+                }
+            };
+            let find = |name: &str| {
+                revwalk::find_commit(&repository, name)
+                    .map_err(|_| anyhow!("unknown commit {}", name))
+            };
+            let (head, upstream) = (find(&head)?, find(&upstream)?);
+            if head == upstream {
+                return Ok(());
             }
+            let mut hidden = vec![upstream.clone()];
+            hidden.extend(limit.as_deref().map(find).transpose()?);
 
-            let repository = Repository::find_repository();
-            let Ok(CommitObject(commit_obj)) = GitrsObject::read(&repository, &commit) else {
-                panic!("Expected a commit object");
+            let patch_id = |hash: &str| {
+                let commit = revwalk::read_commit(&repository, hash)?;
+                anyhow::Ok((patch_id::commit(&repository, &commit)?, commit))
+            };
+            // The changes the commits only upstream has make, which the limit doesn't hide
+            let mut applied = HashSet::new();
+            let upstream_only =
+                revwalk::difference(&repository, &[], &[upstream], std::slice::from_ref(&head))
+                    .context("Couldn't walk history")?;
+            for (hash, _) in upstream_only {
+                let (id, _) = patch_id(&hash)?;
+                applied.extend(id);
+            }
+            let commits = revwalk::difference(&repository, &[], &[head], &hidden)
+                .context("Couldn't walk history")?;
+            for (hash, _) in commits.iter().rev() {
+                let (id, commit) = patch_id(hash)?;
+                let Some(id) = id else {
+                    continue;
+                };
+                let sign = match applied.contains(&id) {
+                    true => '-',
+                    false => '+',
+                };
+                let hash = &hash[..abbrev.map_or(hash.len(), |n| n.clamp(4, hash.len()))];
+                match verbose {
+                    true => println!("{} {} {}", sign, hash, commit.subject()),
+                    false => println!("{} {}", sign, hash),
+                }
+            }
+        }
+        Command::PatchId {
+            stable,
+            unstable,
+            verbatim,
+        } => {
+            let config = match Repository::find_repository_at(Path::new(".")) {
+                Some(repository) => Config::load(&repository),
+                None => Config::load_global(),
+            }
+            .context("Couldn't read config")?;
+            let setting = |key: &str| anyhow::Ok(config.get_bool(key)?.unwrap_or(false));
+            let mode = match (stable, unstable, verbatim) {
+                (false, false, false) => patch_id::Mode {
+                    stable: setting("patchid.stable")?,
+                    verbatim: setting("patchid.verbatim")?,
+                },
+                _ => patch_id::Mode {
+                    stable: stable || verbatim,
+                    verbatim,
+                },
+            };
+            let ids = patch_id::read(std::io::stdin().lock(), mode)
+                .context("Couldn't read from the stdin")?;
+            for (id, commit) in ids {
+                println!("{} {}", id, commit);
+            }
+        }
+        Command::RangeDiff {
+            creation_factor,
+            no_patch,
+            left_only,
+            right_only,
+            ranges,
+        } => {
+            let repository = Repository::find_repository()?;
+            let (old, new) = match ranges.as_slice() {
+                [base, old, new] => (format!("{}..{}", base, old), format!("{}..{}", base, new)),
+                [old, new] => {
+                    for range in [old, new] {
+                        if !range.contains("..") {
+                            bail!("not a commit range: '{}'", range);
+                        }
+                    }
+                    (old.clone(), new.clone())
+                }
+                [range] => {
+                    let (a, b) = range
+                        .split_once("...")
+                        .context("single arg format must be symmetric range")?;
+                    let side = |name: &str| match name {
+                        "" => "HEAD".to_string(),
+                        _ => name.to_string(),
+                    };
+                    let (a, b) = (side(a), side(b));
+                    (format!("{}..{}", b, a), format!("{}..{}", a, b))
+                }
+                _ => unreachable!("clap takes one to three ranges"),
+            };
+            let options = RangeDiffOptions {
+                creation_factor,
+                no_patch,
+                left_only,
+                right_only,
+            };
+            let old = series(&repository, &old)?;
+            let new = series(&repository, &new)?;
+            let output = range_diff::format(&repository, &old, &new, &options)?;
+            std::io::stdout()
+                .write_all(&output)
+                .context("Couldn't write range-diff")?;
+        }
+        Command::Filter {
+            remove_paths,
+            strip_blobs_bigger_than,
+            author_map,
+            dry_run,
+        } => {
+            let repository = Repository::find_repository()?;
+            let options = FilterOptions {
+                remove_paths,
+                max_blob_size: strip_blobs_bigger_than
+                    .map(|size| filter::parse_size(&size))
+                    .transpose()?,
+                author_map: author_map
+                    .map(|path| Mailmap::read(Path::new(&path)))
+                    .transpose()?,
+            };
+            let mut plan = Plan::new(dry_run);
+            let filtered = filter::filter(&repository, &options, &mut plan)?;
+            // Pruned commits map to their parent, or to nothing
+            let changed = filtered
+                .commits
+                .iter()
+                .filter(|(old, new)| new.as_ref() != Some(*old))
+                .count();
+            if dry_run {
+                println!(
+                    "Would rewrite {} of {} commits and prune {}, stripping {} blobs",
+                    changed - filtered.pruned,
+                    filtered.commits.len(),
+                    filtered.pruned,
+                    filtered.stripped
+                );
+                print_plan(&repository, &plan);
+                return Ok(());
+            }
+            println!(
+                "Rewrote {} of {} commits and pruned {}, stripping {} blobs",
+                changed - filtered.pruned,
+                filtered.commits.len(),
+                filtered.pruned,
+                filtered.stripped
+            );
+            for (name, new) in filtered.refs {
+                match new {
+                    Some(new) => println!("Updated {} to {}", name, new),
+                    None => println!("Deleted {}", name),
+                }
+            }
+        }
+        Command::LsTree {
+            recursive,
+            tree,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let tree_obj = Tree::from_name(&repository, &tree)?;
+            let options = TreeWalkOptions {
+                recursive,
+                pathspec: Pathspec::parse(&repository, &paths)?,
+                ..TreeWalkOptions::default()
             };
+            for entry in TreeWalk::new(&repository, &[&tree_obj], options) {
+                let entry = entry?;
+                if let Some(Some((mode, hash))) = entry.entries.first() {
+                    let obj_type = Leaf::get_type_from_mode(mode);
+                    println!("{} {} {}\t{}", mode, obj_type, hash, entry.path);
+                }
+            }
+        }
+        Command::Mktree { nul, missing } => {
+            let repository = Repository::find_repository()?;
+            let mut listing = String::new();
+            std::io::stdin()
+                .read_to_string(&mut listing)
+                .context("Couldn't read from the stdin")?;
 
-            let Ok(TreeObject(tree_obj)) =
-                GitrsObject::read(&repository, commit_obj.get_tree_hash())
-            else {
-                panic!("Couldn't find tree for {}", commit);
+            let tree = Tree::from_listing(&repository, &listing, nul, missing)?;
+            println!("{}", TreeObject(tree).write(&repository)?);
+        }
+        Command::Mktag => {
+            let repository = Repository::find_repository()?;
+            let mut data = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("Couldn't read from the stdin")?;
+
+            Tag::verify(&repository, &data).context("Tag input does not pass validation")?;
+            let hash = GitrsObject::deserialize_and_write(&repository, &data, ObjectType::Tag)?;
+            println!("{}", hash);
+        }
+        Command::CountObjects {
+            verbose,
+            human_readable,
+        } => {
+            let repository = Repository::find_repository()?;
+            let scan = loose::scan(&repository).context("Couldn't list loose objects")?;
+            let packs = PackIndex::load_all(&repository).context("Couldn't read pack indexes")?;
+
+            let size = |bytes: u64| {
+                if !human_readable {
+                    return (bytes / 1024).to_string();
+                }
+                // Rounded to two decimals the same way as git
+                match bytes {
+                    0..=1024 => format!("{} {}", bytes, if bytes == 1 { "byte" } else { "bytes" }),
+                    1025..=0x100000 => {
+                        let x = bytes + 5;
+                        format!("{}.{:02} KiB", x >> 10, ((x & 0x3ff) * 100) >> 10)
+                    }
+                    0x100001..=0x40000000 => {
+                        let x = bytes + 5243;
+                        format!("{}.{:02} MiB", x >> 20, ((x & 0xfffff) * 100) >> 20)
+                    }
+                    _ => format!("{}.{:02} GiB", bytes >> 30, (bytes & 0x3fffffff) / 10737419),
+                }
             };
+            let loose_size: u64 = scan.objects.iter().map(|obj| obj.file.disk_size).sum();
 
-            tree_obj
-                .checkout(&repository, path)
-                .expect("An error occurred during checkout");
+            if !verbose {
+                let unit = if human_readable { "" } else { " kilobytes" };
+                println!(
+                    "{} objects, {}{}",
+                    scan.objects.len(),
+                    size(loose_size),
+                    unit
+                );
+                return Ok(());
+            }
+
+            let file_size = |path: &Path| std::fs::metadata(path).map_or(0, |m| m.len());
+            let pack_size: u64 = packs
+                .iter()
+                .map(|pack| file_size(&pack.pack) + file_size(&pack.pack.with_extension("idx")))
+                .sum();
+            let prune_packable = scan
+                .objects
+                .iter()
+                .filter(|obj| packs.iter().any(|pack| pack.contains(&obj.hash)))
+                .count();
+            let garbage_size: u64 = scan.garbage.iter().map(|file| file.size).sum();
+            for file in &scan.garbage {
+                let path = file
+                    .path
+                    .strip_prefix(&repository.worktree)
+                    .unwrap_or(&file.path);
+                eprintln!("warning: garbage found: {}", path.display());
+            }
+
+            println!("count: {}", scan.objects.len());
+            println!("size: {}", size(loose_size));
+            println!(
+                "in-pack: {}",
+                packs.iter().map(|pack| pack.objects().len()).sum::<usize>()
+            );
+            println!("packs: {}", packs.len());
+            println!("size-pack: {}", size(pack_size));
+            println!("prune-packable: {}", prune_packable);
+            println!("garbage: {}", scan.garbage.len());
+            println!("size-garbage: {}", size(garbage_size));
         }
-        Command::ShowRef => {
-            let repository = Repository::find_repository();
-            let refs = Ref::list_at(
-                &repository,
-                &repository
-                    .get_path_to_dir(&["refs"])
-                    .expect("Expected refs dir to exist"),
-            )
-            .expect("Couldn't resolve all refs");
+        Command::VerifyPack {
+            verbose,
+            stat_only,
+            packs,
+        } => {
+            // Longer chains are counted together, as git does
+            const MAX_CHAIN: usize = 50;
+            let repository = Repository::find_repository()?;
+            let plural = |count: usize| if count == 1 { "object" } else { "objects" };
+            let mut ok = true;
+            for path in packs {
+                let index = path.with_extension("idx");
+                let pack = path.with_extension("pack");
+                let objects = match pack::verify(&repository, &index) {
+                    Ok(objects) => objects,
+                    Err(e) => {
+                        ok = false;
+                        eprintln!("error: {:#}", e);
+                        if verbose {
+                            println!("{}: bad", pack.display());
+                        }
+                        continue;
+                    }
+                };
+                if !verbose && !stat_only {
+                    continue;
+                }
 
-            // TODO: Make a pretty print function for refs
-            for (ref_key, ref_val) in refs.iter() {
-                println!("ref: {:?} {}", ref_key, ref_val);
+                let mut chains = [0; MAX_CHAIN + 1];
+                let mut non_delta = 0;
+                for (object, stored) in &objects {
+                    let line = format!(
+                        "{} {:<6} {} {} {}",
+                        object.hash,
+                        object.object_type.to_string(),
+                        stored.size,
+                        stored.stored_size,
+                        stored.offset
+                    );
+                    match &stored.delta {
+                        Some((base, depth)) => {
+                            chains[(*depth).min(MAX_CHAIN + 1) - 1] += 1;
+                            if !stat_only {
+                                println!("{} {} {}", line, depth, base);
+                            }
+                        }
+                        None => {
+                            non_delta += 1;
+                            if !stat_only {
+                                println!("{}", line);
+                            }
+                        }
+                    }
+                }
+                if non_delta > 0 {
+                    println!("non delta: {} {}", non_delta, plural(non_delta));
+                }
+                for (length, &count) in chains[..MAX_CHAIN].iter().enumerate() {
+                    if count > 0 {
+                        println!("chain length = {}: {} {}", length + 1, count, plural(count));
+                    }
+                }
+                if chains[MAX_CHAIN] > 0 {
+                    let count = chains[MAX_CHAIN];
+                    println!("chain length > {}: {} {}", MAX_CHAIN, count, plural(count));
+                }
+                if !stat_only {
+                    println!("{}: ok", pack.display());
+                }
+            }
+            if !ok {
+                std::process::exit(1);
             }
         }
-        Command::Tag {
-            annotated,
-            name: name_opt,
-            object: object_opt,
+        Command::Prune {
+            dry_run,
+            verbose,
+            expire,
         } => {
-            let repository = Repository::find_repository();
-            // List of create is decided by whether the NAME arg is provided
-            match name_opt {
-                Some(name) => {
-                    let tag_type = if annotated {
-                        TagType::Object
-                    } else {
-                        TagType::Lightweight
-                    };
+            let repository = Repository::find_repository()?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .context("Clock is set before the epoch")?
+                .as_secs() as i64;
+            let expire = match expire {
+                Some(expire) => date::parse_expiry(&expire, now)?,
+                // Without --expire everything unreachable goes, however recent
+                None => i64::MAX,
+            };
 
-                    Tag::create(
-                        &repository,
-                        &name,
-                        &object_opt.expect("Must provide hash if creating tag"),
-                        tag_type,
-                    )
-                    .expect("Couldn't create tag");
+            let options = PruneOptions { dry_run, expire };
+            let pruned = prune::prune(&repository, &options)?;
+            if dry_run || verbose {
+                for (hash, object_type) in pruned {
+                    println!("{} {}", hash, object_type);
+                }
+            }
+            prune::prune_packed(&repository, &mut Plan::new(dry_run))
+                .context("Couldn't prune packed objects")?;
+        }
+        Command::Repack {
+            all,
+            delete,
+            geometric,
+            keep_packs,
+            pack_kept_objects,
+            dry_run,
+            quiet,
+        } => {
+            let repository = Repository::find_repository()?;
+            let options = RepackOptions {
+                all,
+                delete,
+                geometric,
+                keep_packs,
+                pack_kept_objects,
+            };
+            let mut plan = Plan::new(dry_run);
+            let repacked = repack::repack(&repository, &options, &mut plan)?;
+            if dry_run {
+                print_plan(&repository, &plan);
+                return Ok(());
+            }
+            if quiet {
+                return Ok(());
+            }
+            match repacked.pack {
+                Some((path, count)) => println!("Packed {} objects into {}", count, path.display()),
+                None => println!("Nothing new to pack."),
+            }
+            for path in repacked.removed {
+                println!("Removed {}", path.display());
+            }
+        }
+        Command::PackObjects {
+            stdout: _,
+            revs,
+            all,
+            window,
+            depth,
+            delta_base_offset,
+            honor_pack_keep,
+            keep_true_parents: _,
+            incremental,
+            quiet,
+            base_name,
+        } => {
+            let mut repository = Repository::find_repository()?;
+            repository.replace_objects = false;
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Couldn't read from the stdin")?;
+
+            let options = PackObjectsOptions {
+                revs,
+                all,
+                honor_pack_keep,
+                incremental,
+            };
+            let objects = pack_objects::collect(&repository, &input, &options)?;
+            let level = object::pack_compression(&repository)?;
+            let config = Config::load(&repository).context("Couldn't load config")?;
+            let mut deltas = DeltaOptions::load(&config, delta_base_offset)?;
+            deltas.window = window.unwrap_or(deltas.window);
+            deltas.depth = depth.map_or(deltas.depth, |depth| depth as usize);
+
+            match base_name {
+                Some(base_name) => {
+                    let hash = pack::write_at(Path::new(&base_name), &objects, level, &deltas)?;
+                    println!("{}", hash);
                 }
                 None => {
-                    // TODO: extract into a common method
-                    let refs = Ref::list_at(
-                        &repository,
-                        &repository
-                            .get_path_to_dir(&["refs"])
-                            .expect("Expected refs dir to exist"),
-                    )
-                    .expect("Couldn't resolve all refs");
+                    let (pack, _) = pack::encode(&objects, level, &deltas);
+                    std::io::stdout()
+                        .write_all(&pack)
+                        .context("Couldn't write to the stdout")?;
+                }
+            }
+            if !quiet {
+                eprintln!("Total {}", objects.len());
+            }
+        }
+        Command::Fsck {
+            connectivity_only,
+            lost_found,
+            no_dangling,
+        } => {
+            let repository = Repository::find_repository()?;
+            let options = FsckOptions {
+                connectivity_only,
+                lost_found,
+            };
+            let report = fsck::fsck(&repository, &options)?;
+            for (hash, error) in &report.errors {
+                eprintln!("error in object {}: {}", hash, error);
+            }
+            for missing in &report.missing {
+                let object_type = missing
+                    .object_type
+                    .as_ref()
+                    .map_or("object".to_string(), ToString::to_string);
+                if let Some((from_type, from)) = &missing.from {
+                    println!("broken link from {:>6} {}", from_type.to_string(), from);
+                    println!("              to {:>6} {}", object_type, missing.hash);
+                }
+                println!("missing {} {}", object_type, missing.hash);
+            }
+            for (object_type, hash) in report.dangling.iter().filter(|_| !no_dangling) {
+                println!("dangling {} {}", object_type, hash);
+            }
+            if report.is_broken() {
+                std::process::exit(1);
+            }
+        }
+        Command::UnpackObjects {
+            dry_run,
+            strict,
+            quiet,
+        } => {
+            let repository = Repository::find_repository()?;
+            let mut pack = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut pack)
+                .context("Couldn't read from the stdin")?;
 
-                    // TODO: Make a pretty print function for refs
-                    for (ref_key, ref_val) in refs.iter() {
-                        println!("ref: {} {}", ref_key, ref_val);
+            let options = UnpackOptions { dry_run, strict };
+            let unpacked = unpack_objects::unpack(&repository, &pack, &options)?;
+            if !quiet {
+                eprintln!(
+                    "Unpacked {} objects, {} new",
+                    unpacked.objects, unpacked.written
+                );
+            }
+        }
+        Command::Maintenance { cmd } => match cmd {
+            MaintenanceCommand::Run {
+                tasks,
+                auto,
+                schedule,
+                dry_run,
+                quiet,
+            } => {
+                let repository = Repository::find_repository()?;
+                let options = MaintenanceOptions {
+                    tasks,
+                    auto,
+                    schedule,
+                };
+                let mut plan = Plan::new(dry_run);
+                let Some(reports) = maintenance::run(&repository, &options, &mut plan)? else {
+                    return Ok(());
+                };
+                if dry_run {
+                    print_plan(&repository, &plan);
+                    return Ok(());
+                }
+                if quiet {
+                    return Ok(());
+                }
+                for report in reports {
+                    if report.removed_loose > 0 {
+                        println!(
+                            "{}: Removed {} loose objects that were packed",
+                            report.task, report.removed_loose
+                        );
+                    }
+                    if let Some((path, count)) = report.pack {
+                        println!(
+                            "{}: Packed {} objects into {}",
+                            report.task,
+                            count,
+                            path.display()
+                        );
                     }
+                    for path in report.removed_packs {
+                        println!("{}: Removed {}", report.task, path.display());
+                    }
+                }
+            }
+            MaintenanceCommand::Register => maintenance::register(&Repository::find_repository()?)?,
+            MaintenanceCommand::Unregister => {
+                maintenance::unregister(&Repository::find_repository()?)?
+            }
+            MaintenanceCommand::Start { scheduler } => {
+                maintenance::start(&Repository::find_repository()?, scheduler)?
+            }
+            MaintenanceCommand::Stop { scheduler } => maintenance::stop(scheduler)?,
+        },
+        Command::ForEachRepo { config, args } => {
+            let values = match Repository::find_repository_at(Path::new(".")) {
+                Some(repository) => Config::load(&repository),
+                None => Config::load_global(),
+            }?;
+            let program = std::env::current_exe().context("Couldn't find the gitrs executable")?;
+            for path in values.get_all(&config) {
+                let status = std::process::Command::new(&program)
+                    .args(&args)
+                    .current_dir(path)
+                    .status()
+                    .with_context(|| format!("Couldn't run gitrs in {}", path))?;
+                if !status.success() {
+                    std::process::exit(status.code().unwrap_or(1));
                 }
             }
         }
-    };
+        Command::PrunePacked { dry_run, quiet } => {
+            let repository = Repository::find_repository()?;
+            let removed = prune::prune_packed(&repository, &mut Plan::new(dry_run))
+                .context("Couldn't prune packed objects")?;
+            if dry_run && !quiet {
+                for path in removed {
+                    let path = path.strip_prefix(&repository.worktree).unwrap_or(&path);
+                    println!("rm -f {}", path.display());
+                }
+            }
+        }
+        Command::DiffTree {
+            recursive,
+            nul,
+            root,
+            no_commit_id,
+            exit_code,
+            quiet,
+            tree,
+            other,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let pathspec = Pathspec::parse(&repository, &paths)?;
+            let read_tree = |name: &str| Tree::from_name(&repository, name);
+
+            let (commit_id, old, new) = match other {
+                Some(other) => (None, read_tree(&tree)?, read_tree(&other)?),
+                None => {
+                    let hash = GitrsObject::find(&repository, &tree)
+                        .map_err(|_| anyhow!("Couldn't find object with name: {}", tree))?;
+                    let commit = revwalk::read_commit(&repository, &hash)?;
+                    let old = match commit.parents() {
+                        [] if root => Tree {
+                            records: Vec::new(),
+                        },
+                        [parent] => read_tree(parent)?,
+                        // Root commits need --root, and merges a combined diff
+                        _ => return Ok(()),
+                    };
+                    (Some(hash.clone()), old, read_tree(&hash)?)
+                }
+            };
+
+            let changes = diff::diff_trees(&repository, &old, &new, recursive, &pathspec)
+                .context("Couldn't diff trees")?;
+            if let Some(commit_id) =
+                commit_id.filter(|_| !no_commit_id && !quiet && !changes.is_empty())
+            {
+                print!("{}{}", commit_id, if nul { '\0' } else { '\n' });
+            }
+            print_changes(&changes, nul, quiet, exit_code);
+        }
+        Command::Diff {
+            cached,
+            stat,
+            unified,
+            ignore_all_space,
+            ignore_space_change,
+            ignore_space_at_eol,
+            diff_algorithm,
+            word_diff,
+            word_diff_regex,
+            color_words,
+            no_textconv,
+            no_ext_diff,
+            binary,
+            exit_code,
+            quiet,
+            commits,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let pathspec = Pathspec::parse(&repository, &paths)?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let head = Tree::from_name(&repository, "HEAD").unwrap_or(Tree {
+                records: Vec::new(),
+            });
+            let tree = |name: &str| Tree::from_name(&repository, name);
+            let commits: Vec<&str> = match commits.as_slice() {
+                [range] if range.contains("..") => {
+                    let (from, to) = range.split_once("..").expect("Checked for ..");
+                    [from, to]
+                        .into_iter()
+                        .map(|name| if name.is_empty() { "HEAD" } else { name })
+                        .collect()
+                }
+                _ => commits.iter().map(String::as_str).collect(),
+            };
+
+            let changes = match commits.as_slice() {
+                [] if cached => diff::diff_trees(&repository, &head, &head, true, &pathspec),
+                [name] if cached => {
+                    diff::diff_trees(&repository, &tree(name)?, &head, true, &pathspec)
+                }
+                [] | [_] => {
+                    let old = commits
+                        .first()
+                        .map_or_else(|| tree("HEAD"), |name| tree(name))?;
+                    let trust_executable_bit = config
+                        .get_bool("core.fileMode")
+                        .context("Couldn't read core.fileMode")?
+                        .unwrap_or(true);
+                    diff::diff_worktree(
+                        &repository,
+                        &old,
+                        &head,
+                        &repository.worktree,
+                        trust_executable_bit,
+                        &pathspec,
+                    )
+                }
+                [old, new] => {
+                    diff::diff_trees(&repository, &tree(old)?, &tree(new)?, true, &pathspec)
+                }
+                _ => bail!("Can't compare more than two commits"),
+            }
+            .context("Couldn't diff")?;
+
+            let whitespace = [
+                (ignore_space_at_eol, Whitespace::IgnoreAtEol),
+                (ignore_space_change, Whitespace::IgnoreChange),
+                (ignore_all_space, Whitespace::IgnoreAll),
+            ]
+            .into_iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, whitespace)| whitespace)
+            .fold(Whitespace::Exact, Whitespace::max);
+            let algorithm = match diff_algorithm {
+                Some(name) => Algorithm::parse(&name).expect("Checked by clap"),
+                None => config
+                    .get("diff.algorithm")
+                    .map_or(Ok(Algorithm::Myers), |name| {
+                        Algorithm::parse(name).with_context(|| {
+                            format!("unknown value for config 'diff.algorithm': {}", name)
+                        })
+                    })?,
+            };
+            let word_diff = match (&color_words, word_diff.as_deref()) {
+                (Some(_), _) | (None, Some("color")) => Some(word_diff::Style::Color),
+                (None, Some("plain")) => Some(word_diff::Style::Plain),
+                (None, Some("porcelain")) => Some(word_diff::Style::Porcelain),
+                (None, Some(_)) => None,
+                (None, None) => word_diff_regex.as_ref().map(|_| word_diff::Style::Plain),
+            };
+            let word_regex = color_words
+                .filter(|regex| !regex.is_empty())
+                .or(word_diff_regex);
+            let show_patch = !stat || unified.is_some() || binary;
+            let options = PatchOptions {
+                context: unified.unwrap_or(patch::DEFAULT_CONTEXT),
+                whitespace,
+                algorithm,
+                word_diff,
+                word_regex,
+                textconv: !no_textconv,
+                // The patch is also made to find out whether anything changed, which needs no
+                // programs run
+                external_diff: !no_ext_diff && show_patch && !quiet,
+                binary,
+            };
+            let patch = patch::format(&repository, &changes, &options)?;
+            if !quiet {
+                if stat {
+                    let diff_options = DiffOptions {
+                        whitespace,
+                        algorithm,
+                        ..Default::default()
+                    };
+                    let stat = diffstat::stat(&repository, &changes, &diff_options)
+                        .context("Couldn't diff files")?;
+                    // Like git, a diffstat of nothing is nothing at all
+                    if stat.len() > 1 {
+                        stat.iter().for_each(|line| println!("{}", line));
+                    }
+                    if show_patch && !changes.is_empty() {
+                        println!();
+                    }
+                }
+                if show_patch {
+                    std::io::stdout()
+                        .write_all(&patch)
+                        .context("Couldn't write patch")?;
+                }
+            }
+            // With whitespace ignored, git only finds out whether anything changed from the patch,
+            // which it doesn't make for a diffstat alone
+            let changed = if whitespace == Whitespace::Exact {
+                !changes.is_empty()
+            } else {
+                (quiet || show_patch) && !patch.is_empty()
+            };
+            if (quiet || exit_code) && changed {
+                std::process::exit(1);
+            }
+        }
+        Command::DiffIndex {
+            cached,
+            nul,
+            exit_code,
+            quiet,
+            tree,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let pathspec = Pathspec::parse(&repository, &paths)?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let tree = Tree::from_name(&repository, &tree)?;
+            let head = Tree::from_name(&repository, "HEAD").unwrap_or(Tree {
+                records: Vec::new(),
+            });
+
+            let changes = if cached {
+                diff::diff_trees(&repository, &tree, &head, true, &pathspec)
+            } else {
+                let trust_executable_bit = config
+                    .get_bool("core.fileMode")
+                    .context("Couldn't read core.fileMode")?
+                    .unwrap_or(true);
+                diff::diff_worktree(
+                    &repository,
+                    &tree,
+                    &head,
+                    &repository.worktree,
+                    trust_executable_bit,
+                    &pathspec,
+                )
+            }
+            .context("Couldn't diff against the worktree")?;
+            print_changes(&changes, nul, quiet, exit_code);
+        }
+        Command::DiffFiles {
+            nul,
+            exit_code,
+            quiet,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let pathspec = Pathspec::parse(&repository, &paths)?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let head = Tree::from_name(&repository, "HEAD").unwrap_or(Tree {
+                records: Vec::new(),
+            });
+            let trust_executable_bit = config
+                .get_bool("core.fileMode")
+                .context("Couldn't read core.fileMode")?
+                .unwrap_or(true);
+
+            let changes = diff::diff_worktree(
+                &repository,
+                &head,
+                &head,
+                &repository.worktree,
+                trust_executable_bit,
+                &pathspec,
+            )
+            .context("Couldn't diff against the worktree")?;
+            print_changes(&changes, nul, quiet, exit_code);
+        }
+        Command::Checkout {
+            commit,
+            path: path_str,
+        } => {
+            let path = Path::new(&path_str);
+            // TODO: could also make a dir if not exists
+            if !repository::is_empty_dir(path) {
+                bail!("Expected an empty dir at {}", path_str);
+            }
+
+            let repository = Repository::find_repository()?;
+            let Ok(CommitObject(commit_obj)) = GitrsObject::read(&repository, &commit) else {
+                bail!("Expected a commit object");
+            };
+
+            let Ok(TreeObject(tree_obj)) =
+                GitrsObject::read(&repository, commit_obj.get_tree_hash())
+            else {
+                bail!("Couldn't find tree for {}", commit);
+            };
+
+            tree_obj
+                .checkout(&repository, path)
+                .context("An error occurred during checkout")?;
+        }
+        Command::Switch {
+            create,
+            detach,
+            branch: name,
+        } => {
+            let repository = Repository::find_repository()?;
+            let find = |name: &str| {
+                revwalk::find_commit(&repository, name)
+                    .map_err(|_| anyhow!("invalid reference: {}", name))
+            };
+
+            let target = if let Some(new_branch) = create {
+                let start = find(name.as_deref().unwrap_or("HEAD"))?;
+                switch::Target::NewBranch(new_branch, start)
+            } else if detach {
+                switch::Target::Detached(find(name.as_deref().unwrap_or("HEAD"))?)
+            } else {
+                let mut name = name.context("A branch is required")?;
+                if name == "-" {
+                    name = branch::previous(&repository, 1)
+                        .context("Couldn't read the HEAD reflog")?
+                        .context("No previous branch to switch to")?;
+                }
+                let is_branch = Ref::try_resolve(&repository, &format!("refs/heads/{}", name))
+                    .context("Couldn't read branches")?
+                    .is_some();
+                if !is_branch && revwalk::find_commit(&repository, &name).is_ok() {
+                    bail!(
+                        "a branch is expected, got '{}'; use --detach to switch to a commit",
+                        name
+                    );
+                }
+                switch::Target::Branch(name)
+            };
+            let previous = branch::current(&repository).context("Couldn't read HEAD")?;
+
+            let orphans = switch::switch(&repository, &target)?;
+            if !orphans.is_empty() {
+                let describe = |hash: &String| {
+                    let commit = revwalk::read_commit(&repository, hash)
+                        .context("Couldn't read an orphaned commit")?;
+                    eprintln!("  {} {}", &hash[..7], commit.subject());
+                    anyhow::Ok(())
+                };
+                let plural = orphans.len() > 1;
+                eprintln!(
+                    "Warning: you are leaving {} commit{} behind, not connected to\nany of your branches:\n",
+                    orphans.len(),
+                    if plural { "s" } else { "" }
+                );
+                // Like git, at most 5 are listed
+                if orphans.len() <= 5 {
+                    orphans.iter().try_for_each(describe)?;
+                } else {
+                    orphans[..4].iter().try_for_each(describe)?;
+                    eprintln!(" ... and {} more.", orphans.len() - 4);
+                }
+                eprintln!(
+                    "\nIf you want to keep {} by creating a new branch, this may be a good time\n\
+                     to do so with:\n\n git branch <new-branch-name> {}\n",
+                    if plural { "them" } else { "it" },
+                    &orphans[0][..7]
+                );
+            }
+            match target {
+                switch::Target::Branch(name) if previous.as_ref() == Some(&name) => {
+                    eprintln!("Already on '{}'", name)
+                }
+                switch::Target::Branch(name) => eprintln!("Switched to branch '{}'", name),
+                switch::Target::NewBranch(name, _) => {
+                    eprintln!("Switched to a new branch '{}'", name)
+                }
+                switch::Target::Detached(hash) => {
+                    let commit = revwalk::read_commit(&repository, &hash)
+                        .context("Couldn't read the new HEAD")?;
+                    eprintln!("HEAD is now at {} {}", &hash[..7], commit.subject());
+                }
+            }
+        }
+        Command::ShowRef {
+            heads,
+            tags,
+            dereference,
+            hash,
+            verify,
+            quiet,
+            head,
+            patterns,
+        } => {
+            let repository = Repository::find_repository()?;
+            let mut refs = Vec::new();
+
+            if verify {
+                for pattern in &patterns {
+                    match Ref::try_resolve(&repository, pattern).context("Couldn't read ref")? {
+                        Some(hash) if pattern == "HEAD" || pattern.starts_with("refs/") => {
+                            refs.push((pattern.clone(), hash))
+                        }
+                        _ if quiet => std::process::exit(1),
+                        _ => bail!("'{}' - not a valid ref", pattern),
+                    }
+                }
+            } else {
+                let head_ref = head
+                    .then(|| Ref::try_resolve(&repository, "HEAD").context("Couldn't read HEAD"))
+                    .transpose()?
+                    .flatten()
+                    .map(|hash| ("HEAD".to_string(), hash));
+                refs.extend(head_ref);
+                refs.extend(
+                    Ref::list_namespaced(&repository)
+                        .context("Couldn't list refs")?
+                        .into_iter()
+                        .filter(|(name, _)| {
+                            let kind_matches = match (heads, tags) {
+                                (false, false) => true,
+                                _ => {
+                                    (heads && name.starts_with("refs/heads/"))
+                                        || (tags && name.starts_with("refs/tags/"))
+                                }
+                            };
+                            kind_matches
+                                && (patterns.is_empty()
+                                    || patterns.iter().any(|pattern| {
+                                        name == pattern || name.ends_with(&format!("/{}", pattern))
+                                    }))
+                        }),
+                );
+            }
+
+            if refs.is_empty() {
+                std::process::exit(1);
+            }
+            if quiet {
+                return Ok(());
+            }
+            for (name, object) in refs {
+                let print = |object: &str, name: &str| match hash {
+                    Some(len) => println!("{}", &object[..len.clamp(4, 40)]),
+                    None => println!("{} {}", object, name),
+                };
+                print(&object, &name);
+
+                if dereference
+                    && let GitrsObject::TagObject(tag) =
+                        GitrsObject::read(&repository, &object).context("Couldn't read object")?
+                    && let Some(target) = tag.object()
+                {
+                    print(target, &format!("{}^{{}}", name));
+                }
+            }
+        }
+        Command::NameRev {
+            name_only,
+            tags,
+            refs,
+            annotate_stdin,
+            commits,
+        } => {
+            let repository = Repository::find_repository()?;
+            let name_rev =
+                NameRev::new(&repository, tags, &refs).context("Couldn't name commits")?;
+
+            if annotate_stdin {
+                for line in std::io::stdin().lock().lines() {
+                    let line = line.context("Couldn't read from the stdin")?;
+                    let mut annotated = String::new();
+                    let mut rest = line.as_str();
+                    // Only whole 40 character hashes are named
+                    while let Some(start) = rest.find(|c: char| c.is_ascii_hexdigit()) {
+                        annotated.push_str(&rest[..start]);
+                        rest = &rest[start..];
+                        let len = rest
+                            .find(|c: char| !c.is_ascii_hexdigit())
+                            .unwrap_or(rest.len());
+                        let (word, after) = rest.split_at(len);
+                        match name_rev.name(word).filter(|_| len == 40) {
+                            Some(name) if name_only => annotated.push_str(&name),
+                            Some(name) => annotated.push_str(&format!("{} ({})", word, name)),
+                            None => annotated.push_str(word),
+                        }
+                        rest = after;
+                    }
+                    annotated.push_str(rest);
+                    println!("{}", annotated);
+                }
+                return Ok(());
+            }
+
+            for commit in commits {
+                let hash = GitrsObject::find(&repository, &commit)
+                    .map_err(|_| anyhow!("Could not get sha1 for {}", commit))?;
+                let name = name_rev
+                    .name(&hash)
+                    .unwrap_or_else(|| "undefined".to_string());
+                if name_only {
+                    println!("{}", name);
+                } else {
+                    println!("{} {}", commit, name);
+                }
+            }
+        }
+        Command::Credential { action } => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Couldn't read from the stdin")?;
+            let credential = credential::Credential::parse(&input)?;
+            let config = match Repository::find_repository_at(Path::new(".")) {
+                Some(repository) => Config::load(&repository),
+                None => Config::load_global(),
+            }
+            .context("Couldn't read config")?;
+
+            match action.as_str() {
+                "fill" => {
+                    let filled = credential::fill(&config, &credential)?;
+                    print!("{}", filled.to_protocol()?);
+                }
+                "approve" => credential::approve(&config, &credential)
+                    .context("Couldn't store the credential")?,
+                _ => credential::reject(&config, &credential)
+                    .context("Couldn't erase the credential")?,
+            }
+        }
+        Command::Var { list, variable } => {
+            let config = match Repository::find_repository_at(Path::new(".")) {
+                Some(repository) => Config::load(&repository),
+                None => Config::load_global(),
+            }
+            .context("Couldn't read config")?;
+
+            if list {
+                for (key, value) in config.entries() {
+                    match value {
+                        Some(value) => println!("{}={}", key, value),
+                        None => println!("{}", key),
+                    }
+                }
+                // Like git, variables that can't be resolved (eg. an unknown identity) are left out
+                for name in var::VARIABLES {
+                    if let Ok(Some(value)) = var::read(&config, name) {
+                        println!("{}={}", name, value);
+                    }
+                }
+            } else {
+                let variable = variable.expect("Required unless listing");
+                match var::read(&config, &variable)? {
+                    Some(value) => println!("{}", value),
+                    None => bail!("usage: gitrs var (-l | <variable>)"),
+                }
+            }
+        }
+        Command::CredentialStore { file, action } => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Couldn't read from the stdin")?;
+            let credential = credential::Credential::parse(&input)?;
+            let answer =
+                credential::store_helper(file.as_deref().map(Path::new), &action, &credential)
+                    .context("Couldn't access the credential store")?;
+            if let Some(answer) = answer {
+                print!("{}", answer.to_protocol()?);
+            }
+        }
+        Command::Branch {
+            delete,
+            force_delete,
+            list,
+            contains,
+            no_contains,
+            merged,
+            no_merged,
+            verbose,
+            track,
+            set_upstream_to,
+            names,
+        } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+
+            if let Some(upstream) = set_upstream_to {
+                let name = match names.as_slice() {
+                    [] => branch::current(&repository)
+                        .context("Couldn't read HEAD")?
+                        .with_context(|| {
+                            format!(
+                                "could not set upstream of HEAD to {} when it does not point to any branch.",
+                                upstream
+                            )
+                        })?,
+                    [name] => name.clone(),
+                    _ => bail!("too many arguments to set new upstream"),
+                };
+                if Ref::try_resolve(&repository, &format!("refs/heads/{}", name))
+                    .context("Couldn't read branches")?
+                    .is_none()
+                {
+                    bail!("branch '{}' does not exist", name);
+                }
+                let found = branch::find_upstream(&repository, &config, &upstream)
+                    .context("Couldn't read branches")?
+                    .with_context(|| {
+                        format!(
+                            "the requested upstream branch '{}' does not exist",
+                            upstream
+                        )
+                    })?;
+                if found.remote == "." && found.name == name {
+                    eprintln!("warning: not setting branch '{}' as its own upstream", name);
+                    return Ok(());
+                }
+                branch::set_upstream(&repository, &name, &found)?;
+                println!("branch '{}' set up to track '{}'.", name, found.name);
+                return Ok(());
+            }
+
+            if delete || force_delete {
+                // Like git, a branch that can't be deleted doesn't stop the others being deleted
+                let mut failed = false;
+                for name in names {
+                    match branch::delete(&repository, &config, &name, force_delete) {
+                        Ok(hash) => {
+                            println!("Deleted branch {} (was {}).", name, Commit::short(&hash))
+                        }
+                        Err(e) => {
+                            eprintln!("error: {:#}", e);
+                            failed = true;
+                        }
+                    }
+                }
+                if failed {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+
+            let filter = ref_filter::ReachFilter {
+                contains: find_commits(&repository, &contains)?,
+                no_contains: find_commits(&repository, &no_contains)?,
+                merged: find_commits(&repository, &merged)?,
+                no_merged: find_commits(&repository, &no_merged)?,
+            };
+
+            match names.as_slice() {
+                patterns if list || patterns.is_empty() || !filter.is_empty() => {
+                    let current = branch::current(&repository).context("Couldn't read HEAD")?;
+                    let branches =
+                        ref_filter::filter_refs(&repository, &["refs/heads".to_string()])
+                            .context("Couldn't list branches")?;
+                    let branches = filter
+                        .apply(&repository, branches)
+                        .context("Couldn't filter branches")?;
+                    let branches: Vec<_> = branches
+                        .into_iter()
+                        .filter(|item| {
+                            let name = ref_filter::shorten_ref_name(&item.name);
+                            patterns.is_empty()
+                                || patterns
+                                    .iter()
+                                    .any(|pattern| wildmatch::wildmatch(pattern, name))
+                        })
+                        .collect();
+                    let width = branches
+                        .iter()
+                        .map(|item| ref_filter::shorten_ref_name(&item.name).len())
+                        .max()
+                        .unwrap_or_default();
+                    for item in branches {
+                        let name = ref_filter::shorten_ref_name(&item.name);
+                        let marker = if current.as_deref() == Some(name) {
+                            '*'
+                        } else {
+                            ' '
+                        };
+                        if verbose == 0 {
+                            println!("{} {}", marker, name);
+                            continue;
+                        }
+                        let commit = revwalk::read_commit(&repository, &item.hash)
+                            .context("Couldn't read branches")?;
+                        let upstream = branch::describe_upstream(
+                            &repository,
+                            &config,
+                            name,
+                            &item.hash,
+                            verbose > 1,
+                        )
+                        .context("Couldn't compare branches with their upstreams")?
+                        .map(|upstream| format!("{} ", upstream))
+                        .unwrap_or_default();
+                        println!(
+                            "{} {:<width$} {} {}{}",
+                            marker,
+                            name,
+                            Commit::short(&item.hash),
+                            upstream,
+                            commit.subject()
+                        );
+                    }
+                }
+                [name, start @ ..] if start.len() <= 1 => {
+                    branch::verify_name(name)?;
+                    let start = start.first().map_or("HEAD", String::as_str);
+                    let hash = revwalk::find_commit(&repository, start)
+                        .map_err(|_| anyhow!("Not a valid object name: '{}'", start))?;
+                    if Ref::try_resolve(&repository, &format!("refs/heads/{}", name))
+                        .context("Couldn't read branches")?
+                        .is_some()
+                    {
+                        bail!("A branch named '{}' already exists", name);
+                    }
+                    let upstream = match track {
+                        true => Some(
+                            branch::find_upstream(&repository, &config, start)
+                                .context("Couldn't read branches")?
+                                .with_context(|| {
+                                    format!(
+                                        "cannot set up tracking information; starting point '{}' is not a branch",
+                                        start
+                                    )
+                                })?,
+                        ),
+                        false => None,
+                    };
+                    Ref::transaction(
+                        &repository,
+                        &[RefUpdate {
+                            name: format!("refs/heads/{}", name),
+                            new: Some(hash),
+                            old: Some(refs::ZERO_HASH.to_string()),
+                            verify_only: false,
+                            deref: false,
+                        }],
+                    )?;
+                    if let Some(upstream) = upstream {
+                        branch::set_upstream(&repository, name, &upstream)?;
+                        println!("branch '{}' set up to track '{}'.", name, upstream.name);
+                    }
+                }
+                _ => bail!("Expected a branch name and at most one start point"),
+            }
+        }
+        Command::ShowBranch { more, revs } => {
+            let repository = Repository::find_repository()?;
+            let revs: Vec<(String, String)> = if revs.is_empty() {
+                let mut branches = Vec::new();
+                for item in ref_filter::filter_refs(&repository, &["refs/heads".to_string()])
+                    .context("Couldn't list branches")?
+                {
+                    if let Some(hash) = revwalk::peel_to_commit(&repository, &item.hash)
+                        .context("Couldn't read branches")?
+                    {
+                        let name = ref_filter::shorten_ref_name(&item.name).to_string();
+                        branches.push((name, hash));
+                    }
+                }
+                branches
+            } else {
+                revs.into_iter()
+                    .map(|name| {
+                        let hash = revwalk::find_commit(&repository, &name)
+                            .map_err(|_| anyhow!("bad sha1 reference {}", name))?;
+                        anyhow::Ok((name, hash))
+                    })
+                    .collect::<anyhow::Result<_>>()?
+            };
+
+            // The branch HEAD is on is marked, as long as it is shown at the commit HEAD is at
+            let head_name = branch::current(&repository)
+                .context("Couldn't read HEAD")?
+                .unwrap_or_else(|| "HEAD".to_string());
+            let head_hash = Ref::try_resolve(&repository, "HEAD").context("Couldn't read HEAD")?;
+            let head = revs.iter().position(|(name, hash)| {
+                name.strip_prefix("refs/heads/").unwrap_or(name) == head_name
+                    && Some(hash) == head_hash.as_ref()
+            });
+
+            let lines = show_branch::show_branch(&repository, &revs, head, more)?;
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        Command::Tag {
+            annotated,
+            list,
+            sort,
+            lines,
+            contains,
+            no_contains,
+            merged,
+            no_merged,
+            args,
+        } => {
+            let repository = Repository::find_repository()?;
+            let filter = ref_filter::ReachFilter {
+                contains: find_commits(&repository, &contains)?,
+                no_contains: find_commits(&repository, &no_contains)?,
+                merged: find_commits(&repository, &merged)?,
+                no_merged: find_commits(&repository, &no_merged)?,
+            };
+
+            match args.as_slice() {
+                patterns
+                    if list || patterns.is_empty() || lines.is_some() || !filter.is_empty() =>
+                {
+                    let config = Config::load(&repository).context("Couldn't read config")?;
+                    let sort = match (sort.is_empty(), config.get("tag.sort")) {
+                        (true, Some(key)) => vec![key.to_string()],
+                        (true, None) => vec!["refname".to_string()],
+                        (false, _) => sort,
+                    };
+                    let tags = ref_filter::filter_refs(&repository, &["refs/tags".to_string()])
+                        .context("Couldn't list tags")?;
+                    let mut tags: Vec<RefItem> = filter
+                        .apply(&repository, tags)
+                        .context("Couldn't filter tags")?
+                        .into_iter()
+                        .filter(|item| {
+                            let name = ref_filter::shorten_ref_name(&item.name);
+                            patterns.is_empty()
+                                || patterns
+                                    .iter()
+                                    .any(|pattern| wildmatch::wildmatch(pattern, name))
+                        })
+                        .collect();
+                    let mut formatter =
+                        RefFormatter::new(&repository, &config).context("Couldn't read HEAD")?;
+                    formatter.sort(&mut tags, &sort)?;
+
+                    for item in &tags {
+                        let name = ref_filter::shorten_ref_name(&item.name);
+                        match lines {
+                            Some(count) if count > 0 => {
+                                let message = formatter
+                                    .format(item, &format!("%(contents:lines={})", count))?;
+                                println!("{:<15} {}", name, message);
+                            }
+                            _ => println!("{}", name),
+                        }
+                    }
+                }
+                [name, object] => {
+                    let tag_type = if annotated {
+                        TagType::Object
+                    } else {
+                        TagType::Lightweight
+                    };
+                    Tag::create(&repository, name, object, tag_type)
+                        .context("Couldn't create tag")?;
+                }
+                [_] => bail!("Must provide hash if creating tag"),
+                _ => bail!("Too many arguments"),
+            }
+        }
+        Command::CheckAttr {
+            all,
+            mut args,
+            mut paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            if paths.is_empty() {
+                let split = if all || args.is_empty() { 0 } else { 1 };
+                paths = args.split_off(split);
+            }
+            if all && !args.is_empty() {
+                bail!("Cannot specify attributes together with --all");
+            }
+            if !all && args.is_empty() {
+                bail!("No attribute specified");
+            }
+
+            let mut attributes = Attributes::new(&repository);
+            for path in paths {
+                let relative = repository
+                    .relative_to_worktree(Path::new(&path))
+                    .context("Couldn't resolve path")?;
+                let specified = attributes.check(&relative);
+
+                if all {
+                    for (name, value) in specified
+                        .iter()
+                        .filter(|(_, value)| **value != AttrValue::Unspecified)
+                    {
+                        println!("{}: {}: {}", path, name, value);
+                    }
+                } else {
+                    for name in &args {
+                        let value = specified.get(name).unwrap_or(&AttrValue::Unspecified);
+                        println!("{}: {}: {}", path, name, value);
+                    }
+                }
+            }
+        }
+        Command::VerifyCommit {
+            verbose,
+            raw,
+            commits,
+        } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let hashes: Vec<String> = commits
+                .iter()
+                .map(|name| {
+                    revwalk::find_commit(&repository, name)
+                        .map_err(|_| anyhow!("{}: unable to resolve commit", name))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            if !verify_signatures(&repository, &config, &hashes, verbose, raw)? {
+                std::process::exit(1);
+            }
+        }
+        Command::VerifyTag { verbose, raw, tags } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let hashes: Vec<String> = tags
+                .iter()
+                .map(|name| {
+                    let hash = GitrsObject::find(&repository, name)
+                        .map_err(|_| anyhow!("tag '{}' not found.", name))?;
+                    let object_type = GitrsObject::read(&repository, &hash)?.get_type();
+                    if object_type != ObjectType::Tag {
+                        bail!(
+                            "{}: cannot verify a non-tag object of type {}.",
+                            name,
+                            object_type
+                        );
+                    }
+                    Ok(hash)
+                })
+                .collect::<anyhow::Result<_>>()?;
+            if !verify_signatures(&repository, &config, &hashes, verbose, raw)? {
+                std::process::exit(1);
+            }
+        }
+        Command::Clean {
+            force,
+            dry_run,
+            directories,
+            include_ignored,
+            paths,
+        } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let require_force = config
+                .get_bool("clean.requireForce")
+                .context("Couldn't read clean.requireForce")?
+                .unwrap_or(true);
+            if require_force && !force && !dry_run {
+                bail!(
+                    "clean.requireForce defaults to true and neither -n nor -f given; refusing to clean"
+                );
+            }
+
+            let dir = repository
+                .relative_to_worktree(Path::new("."))
+                .context("Couldn't resolve the current directory")?;
+            let options = CleanOptions {
+                dry_run,
+                directories,
+                include_ignored,
+                pathspec: Pathspec::parse(&repository, &paths)?,
+            };
+            let removed = clean::clean(&repository, &config, &dir, &options)
+                .context("Couldn't clean the worktree")?;
+
+            let action = if dry_run { "Would remove" } else { "Removing" };
+            for path in removed {
+                let display = if dir.is_empty() {
+                    path.as_str()
+                } else {
+                    &path[dir.len() + 1..]
+                };
+                println!("{} {}", action, display);
+            }
+        }
+        Command::Shortlog {
+            summary,
+            numbered,
+            email,
+            revisions,
+        } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let mailmap = Mailmap::load(&repository, &config);
+
+            let tips: Vec<String> = revisions
+                .iter()
+                .map(|revision| {
+                    GitrsObject::find(&repository, revision)
+                        .map_err(|_| anyhow!("Couldn't find revision: {}", revision))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let commits = revwalk::walk(&repository, &tips).context("Couldn't walk history")?;
+
+            let mut authors: std::collections::BTreeMap<String, Vec<String>> =
+                std::collections::BTreeMap::new();
+            // Oldest commits first, as git does
+            for (hash, commit) in commits.iter().rev() {
+                let author = commit
+                    .author()
+                    .map_err(|_| anyhow!("Couldn't read author of {}", hash))?;
+                let (name, mail) = mailmap.map(&author.name, &author.email);
+                let key = if email {
+                    format!("{} <{}>", name, mail)
+                } else {
+                    name
+                };
+                authors
+                    .entry(key)
+                    .or_default()
+                    .push(commit.subject().to_string());
+            }
+
+            let mut authors: Vec<(String, Vec<String>)> = authors.into_iter().collect();
+            if numbered {
+                authors.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+            }
+            for (author, subjects) in authors {
+                if summary {
+                    println!("{:>6}\t{}", subjects.len(), author);
+                } else {
+                    println!("{} ({}):", author, subjects.len());
+                    for subject in subjects {
+                        println!("      {}", subject);
+                    }
+                    println!();
+                }
+            }
+        }
+        Command::CheckMailmap { contacts } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let mailmap = Mailmap::load(&repository, &config);
+
+            for contact in contacts {
+                let (name, email) = mailmap::parse_contact(&contact)
+                    .with_context(|| format!("Unable to parse contact: {}", contact))?;
+                match mailmap.map(name, email) {
+                    (name, email) if name.is_empty() => println!("<{}>", email),
+                    (name, email) => println!("{} <{}>", name, email),
+                }
+            }
+        }
+        Command::Notes { notes_ref, cmd } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let mut notes = Notes::load(&repository, &config, notes_ref.as_deref())
+                .context("Couldn't read notes")?;
+            let find = |object: &str| {
+                GitrsObject::find(&repository, object)
+                    .map_err(|_| anyhow!("Couldn't find object: {}", object))
+            };
+
+            match cmd {
+                NotesCommand::List { object: None } => {
+                    for (object, note) in notes.list() {
+                        println!("{} {}", note, object);
+                    }
+                }
+                NotesCommand::List {
+                    object: Some(object),
+                } => match notes.list().get(&find(&object)?) {
+                    Some(note) => println!("{}", note),
+                    None => bail!("No note found for object {}", object),
+                },
+                NotesCommand::Add {
+                    messages,
+                    force,
+                    object,
+                } => {
+                    let hash = find(&object)?;
+                    if !force && notes.list().contains_key(&hash) {
+                        bail!(
+                            "Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+                            hash
+                        );
+                    }
+                    notes.set(&hash, &format!("{}\n", messages.join("\n\n")))?;
+                    notes
+                        .commit(&config, "Notes added by 'gitrs notes add'\n")
+                        .context("Couldn't write notes")?;
+                }
+                NotesCommand::Append { messages, object } => {
+                    let hash = find(&object)?;
+                    let mut note = notes
+                        .get(&hash)
+                        .context("Couldn't read note")?
+                        .unwrap_or_default();
+                    if !note.is_empty() {
+                        note.push('\n');
+                    }
+                    note.push_str(&format!("{}\n", messages.join("\n\n")));
+                    notes.set(&hash, &note)?;
+                    notes
+                        .commit(&config, "Notes added by 'gitrs notes append'\n")
+                        .context("Couldn't write notes")?;
+                }
+                NotesCommand::Show { object } => {
+                    match notes.get(&find(&object)?).context("Couldn't read note")? {
+                        Some(note) => print!("{}", note),
+                        None => bail!("No note found for object {}", object),
+                    }
+                }
+                NotesCommand::Remove { object } => {
+                    if !notes.remove(&find(&object)?) {
+                        bail!("Object {} has no note", object);
+                    }
+                    println!("Removing note for object {}", object);
+                    notes
+                        .commit(&config, "Notes removed by 'gitrs notes remove'\n")
+                        .context("Couldn't write notes")?;
+                }
+            }
+        }
+        Command::Replace {
+            list,
+            delete,
+            force,
+            args,
+        } => {
+            let repository = Repository::find_repository()?;
+            let find = |object: &str| {
+                GitrsObject::find(&repository, object)
+                    .map_err(|_| anyhow!("Couldn't find object: {}", object))
+            };
+
+            if delete {
+                for object in args {
+                    let hash = find(&object)?;
+                    Ref::delete_at(&repository, &["refs", "replace", &hash])
+                        .map_err(|_| anyhow!("Replace ref '{}' not found", hash))?;
+                    println!("Deleted replace ref '{}'", hash);
+                }
+            } else if list || args.len() < 2 {
+                let pattern = args.first().map(String::as_str).unwrap_or("*");
+                let refs = Ref::list(&repository).context("Couldn't read replace refs")?;
+                for (name, _) in &refs {
+                    let Some(object) = name.strip_prefix("refs/replace/") else {
+                        continue;
+                    };
+                    if wildmatch::wildmatch(pattern, object) {
+                        println!("{}", object);
+                    }
+                }
+            } else {
+                let [object, replacement] = args.as_slice() else {
+                    bail!("Expected an object and its replacement");
+                };
+                let (object, replacement) = (find(object)?, find(replacement)?);
+                if !force {
+                    if Ref::try_resolve(&repository, &format!("refs/replace/{}", object))?.is_some()
+                    {
+                        bail!("Replace ref 'refs/replace/{}' already exists", object);
+                    }
+                    let object_type = GitrsObject::read_raw(&repository, &object)
+                        .context("Couldn't read object")?
+                        .get_type();
+                    let replacement_type = GitrsObject::read_raw(&repository, &replacement)
+                        .context("Couldn't read replacement")?
+                        .get_type();
+                    if object_type != replacement_type {
+                        bail!(
+                            "Objects must be of the same type. '{}' points to a replaced object of type '{}' while '{}' points to a replacement object of type '{}'",
+                            object,
+                            object_type,
+                            replacement,
+                            replacement_type
+                        );
+                    }
+                }
+                Ref::create_at(&repository, &replacement, &["refs", "replace", &object])
+                    .context("Couldn't create replace ref")?;
+            }
+        }
+        Command::Apply {
+            check,
+            reverse,
+            reject,
+            three_way,
+            strip,
+            verbose,
+            patches,
+        } => {
+            let mut input = String::new();
+            if patches.is_empty() {
+                std::io::stdin()
+                    .read_to_string(&mut input)
+                    .context("Couldn't read the stdin")?;
+            }
+            for patch in &patches {
+                input.push_str(
+                    &std::fs::read_to_string(patch)
+                        .map_err(|_| anyhow!("Couldn't read patch: {}", patch))?,
+                );
+            }
+
+            let options = ApplyOptions {
+                check,
+                reverse,
+                reject,
+                verbose,
+                three_way,
+            };
+            let repository = match three_way {
+                true => Some(Repository::find_repository()?),
+                false => None,
+            };
+            let file_patches = apply::parse(&input, strip).context("Couldn't parse patch")?;
+            if !apply::apply(repository.as_ref(), file_patches, &options)
+                .context("Couldn't apply patch")?
+            {
+                std::process::exit(1);
+            }
+        }
+        Command::UpdateRef {
+            delete,
+            no_deref,
+            stdin,
+            dry_run,
+            args,
+        } => {
+            let repository = Repository::find_repository()?;
+            let parse_value = |value: &str| {
+                if value.is_empty() || value.chars().all(|c| c == '0') {
+                    Ok(refs::ZERO_HASH.to_string())
+                } else {
+                    GitrsObject::find(&repository, value)
+                        .map_err(|_| anyhow!("{}: not a valid SHA1", value))
+                }
+            };
+
+            if !stdin {
+                let update = match (delete, args.as_slice()) {
+                    (true, [name, old @ ..]) if old.len() <= 1 => RefUpdate {
+                        name: name.clone(),
+                        new: None,
+                        old: old.first().map(|old| parse_value(old)).transpose()?,
+                        verify_only: false,
+                        deref: !no_deref,
+                    },
+                    (false, [name, new, old @ ..]) if old.len() <= 1 => RefUpdate {
+                        name: name.clone(),
+                        new: Some(parse_value(new)?),
+                        old: old.first().map(|old| parse_value(old)).transpose()?,
+                        verify_only: false,
+                        deref: !no_deref,
+                    },
+                    _ => bail!("Usage: update-ref [-d] <ref> [<new-value>] [<old-value>]"),
+                };
+                let mut plan = Plan::new(dry_run);
+                plan.update_refs(&repository, &[update])?;
+                if dry_run {
+                    print_plan(&repository, &plan);
+                }
+                return Ok(());
+            }
+
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .context("Couldn't read the stdin")?;
+            let mut plan = Plan::new(dry_run);
+            let mut updates = Vec::new();
+            let mut deref = !no_deref;
+            for line in input.lines().filter(|line| !line.trim().is_empty()) {
+                let words: Vec<&str> = line.split(' ').collect();
+                let update = |name: &str, new, old, verify_only| RefUpdate {
+                    name: name.to_string(),
+                    new,
+                    old,
+                    verify_only,
+                    deref,
+                };
+                match words.as_slice() {
+                    ["update", name, new, old @ ..] if old.len() <= 1 => updates.push(update(
+                        name,
+                        Some(parse_value(new)?),
+                        old.first().map(|old| parse_value(old)).transpose()?,
+                        false,
+                    )),
+                    ["create", name, new] => updates.push(update(
+                        name,
+                        Some(parse_value(new)?),
+                        Some(refs::ZERO_HASH.to_string()),
+                        false,
+                    )),
+                    ["delete", name, old @ ..] if old.len() <= 1 => updates.push(update(
+                        name,
+                        None,
+                        old.first().map(|old| parse_value(old)).transpose()?,
+                        false,
+                    )),
+                    ["verify", name, old @ ..] if old.len() <= 1 => updates.push(update(
+                        name,
+                        None,
+                        Some(
+                            old.first()
+                                .map_or(Ok(refs::ZERO_HASH.to_string()), |old| parse_value(old))?,
+                        ),
+                        true,
+                    )),
+                    ["option", "no-deref"] => {
+                        deref = false;
+                        continue;
+                    }
+                    ["start"] => println!("start: ok"),
+                    ["abort"] => {
+                        updates.clear();
+                        println!("abort: ok");
+                    }
+                    ["commit"] => {
+                        plan.update_refs(&repository, &updates)?;
+                        updates.clear();
+                        println!("commit: ok");
+                    }
+                    _ => bail!("Unknown command: {}", line),
+                }
+                // `option` only applies to the command after it
+                deref = !no_deref;
+            }
+            plan.update_refs(&repository, &updates)?;
+            if dry_run {
+                print_plan(&repository, &plan);
+            }
+        }
+        Command::SymbolicRef {
+            delete,
+            quiet,
+            short,
+            name,
+            target,
+        } => {
+            let repository = Repository::find_repository()?;
+            let current = Ref::read_symbolic(&repository, &name).context("Couldn't read ref")?;
+
+            match (delete, target) {
+                (true, _) => {
+                    if current.is_none() {
+                        if quiet {
+                            std::process::exit(1);
+                        }
+                        bail!("Cannot delete {}, not a symbolic ref", name);
+                    }
+                    let update = RefUpdate {
+                        name,
+                        new: None,
+                        old: None,
+                        verify_only: false,
+                        deref: false,
+                    };
+                    Ref::transaction(&repository, &[update])?;
+                }
+                (false, Some(target)) => Ref::write_symbolic(&repository, &name, &target)?,
+                (false, None) => match current {
+                    Some(target) if short => {
+                        println!("{}", ref_filter::shorten_ref_name(&target))
+                    }
+                    Some(target) => println!("{}", target),
+                    None if quiet => std::process::exit(1),
+                    None => bail!("ref {} is not a symbolic ref", name),
+                },
+            }
+        }
+        Command::CheckRefFormat {
+            normalize,
+            allow_onelevel,
+            refspec_pattern,
+            branch,
+            refname,
+        } => {
+            if branch {
+                // Only `@{-N}` needs a repository
+                let expanded = match Repository::find_repository_at(&std::env::current_dir()?) {
+                    Some(repository) => branch::expand_previous(&repository, &refname)?,
+                    None => None,
+                };
+                let name = expanded.unwrap_or(refname);
+                branch::verify_name(&name)?;
+                println!("{}", name);
+                return Ok(());
+            }
+
+            let refname = if normalize {
+                refs::normalize_name(&refname)
+            } else {
+                refname
+            };
+            let options = refs::RefNameOptions {
+                allow_onelevel,
+                refspec_pattern,
+            };
+            if !refs::check_name(&refname, &options) {
+                std::process::exit(1);
+            }
+            if normalize {
+                println!("{}", refname);
+            }
+        }
+        Command::ForEachRef {
+            format,
+            sort,
+            count,
+            patterns,
+        } => {
+            let repository = Repository::find_repository()?;
+            let config = Config::load(&repository).context("Couldn't read config")?;
+            let mut items =
+                ref_filter::filter_refs(&repository, &patterns).context("Couldn't list refs")?;
+            let mut formatter =
+                RefFormatter::new(&repository, &config).context("Couldn't read HEAD")?;
+            formatter.sort(&mut items, &sort)?;
+
+            for item in items.iter().take(count.unwrap_or(usize::MAX)) {
+                let line = formatter.format(item, &format)?;
+                println!("{}", line);
+            }
+        }
+        Command::InterpretTrailers {
+            trailers,
+            in_place,
+            trim_empty,
+            mut only_trailers,
+            mut only_input,
+            mut unfold,
+            parse,
+            position: _,
+            if_exists: _,
+            if_missing: _,
+            files,
+        } => {
+            if parse {
+                (only_trailers, only_input, unfold) = (true, true, true);
+            }
+            if only_input && !trailers.is_empty() {
+                bail!("--trailer with --only-input does not make sense");
+            }
+            if in_place && files.is_empty() {
+                bail!("--in-place requires at least one file");
+            }
+
+            // Like git, --where, --if-exists and --if-missing apply to the trailers given after
+            // them, so each trailer's placement is found from where it is among the arguments
+            let matches = matches
+                .subcommand_matches("interpret-trailers")
+                .expect("Parsed as interpret-trailers");
+            let placements: Vec<Placement> = matches
+                .indices_of("trailers")
+                .into_iter()
+                .flatten()
+                .map(|index| Placement {
+                    position: last_before(matches, "position", index).unwrap_or(Where::End),
+                    if_exists: last_before(matches, "if_exists", index)
+                        .unwrap_or(IfExists::AddIfDifferentNeighbor),
+                    if_missing: last_before(matches, "if_missing", index).unwrap_or(IfMissing::Add),
+                })
+                .collect();
+            let inputs: Vec<Option<String>> = if files.is_empty() {
+                vec![None]
+            } else {
+                files.into_iter().map(Some).collect()
+            };
+
+            for input in inputs {
+                let content = match &input {
+                    Some(file) => std::fs::read_to_string(file)
+                        .map_err(|_| anyhow!("Couldn't read file: {}", file))?,
+                    None => {
+                        let mut content = String::new();
+                        std::io::stdin()
+                            .read_to_string(&mut content)
+                            .context("Couldn't read the stdin")?;
+                        content
+                    }
+                };
+
+                let mut message = Message::parse(&content);
+                for (trailer, placement) in trailers.iter().zip(&placements) {
+                    message.add(trailer.clone(), placement);
+                }
+                if trim_empty {
+                    message.trim_empty();
+                }
+                if unfold {
+                    message.unfold();
+                }
+
+                let output = if only_trailers {
+                    message
+                        .trailers()
+                        .iter()
+                        .map(|trailer| format!("{}\n", trailer))
+                        .collect()
+                } else {
+                    message.to_string()
+                };
+
+                match input {
+                    Some(file) if in_place => std::fs::write(&file, output)
+                        .map_err(|_| anyhow!("Couldn't write file: {}", file))?,
+                    _ => print!("{}", output),
+                }
+            }
+        }
+        Command::Stash { cmd } => {
+            let repository = Repository::find_repository()?;
+            let find = |name: Option<String>| stash::find(&repository, name.as_deref());
+            let drop = |found: &stash::Stash| {
+                stash::drop(&repository, found)?;
+                println!("Dropped {} ({})", found.name, found.hash);
+                anyhow::Ok(())
+            };
+            match cmd {
+                StashCommand::List => {
+                    let stashes = stash::list(&repository).context("Couldn't read the stashes")?;
+                    for (i, (_, message)) in stashes.iter().enumerate() {
+                        println!("stash@{{{}}}: {}", i, message);
+                    }
+                }
+                StashCommand::Show { stash } => {
+                    let found = find(stash)?;
+                    let changes =
+                        stash::changes(&repository, &found).context("Couldn't diff trees")?;
+                    let stat = diffstat::stat(&repository, &changes, &DiffOptions::default())
+                        .context("Couldn't diff files")?;
+                    // Like git, a diffstat of nothing is nothing at all
+                    if stat.len() > 1 {
+                        stat.iter().for_each(|line| println!("{}", line));
+                    }
+                }
+                StashCommand::Apply { stash } => {
+                    let applied = stash::apply(&repository, &find(stash)?)?;
+                    print_applied(&applied, false);
+                }
+                StashCommand::Pop { stash } => {
+                    let found = find(stash)?;
+                    let applied = stash::apply(&repository, &found)?;
+                    print_applied(&applied, true);
+                    drop(&found)?;
+                }
+                StashCommand::Drop { stash } => drop(&find(stash)?)?,
+                StashCommand::Branch { name, stash } => {
+                    let found = find(stash)?;
+                    let applied = stash::branch(&repository, &name, &found)?;
+                    eprintln!("Switched to a new branch '{}'", name);
+                    print_applied(&applied, found.index.is_some());
+                    if found.index.is_some() {
+                        println!("Dropped {} ({})", found.name, found.hash);
+                    }
+                }
+            }
+        }
+        Command::Merge {
+            no_commit,
+            squash,
+            no_ff,
+            message,
+            strategy_options,
+            abort,
+            continue_merge,
+            quit,
+            commits,
+        } => {
+            let repository = Repository::find_repository()?;
+            if abort {
+                merge::abort(&repository)?;
+                return Ok(());
+            }
+            if quit {
+                merge::clear_state(&repository)?;
+                return Ok(());
+            }
+            if continue_merge {
+                let (hash, subject) = merge::continue_merge(&repository)?;
+                let branch = branch::current(&repository)
+                    .context("Couldn't read HEAD")?
+                    .unwrap_or_else(|| "detached HEAD".to_string());
+                println!("[{} {}] {}", branch, Commit::short(&hash), subject);
+                return Ok(());
+            }
+            if squash && no_ff {
+                bail!("options '--squash' and '--no-ff.' cannot be used together");
+            }
+
+            let mut whitespace = Whitespace::Exact;
+            for option in &strategy_options {
+                let ignored = match option.as_str() {
+                    "ignore-space-at-eol" => Whitespace::IgnoreAtEol,
+                    "ignore-space-change" => Whitespace::IgnoreChange,
+                    "ignore-all-space" => Whitespace::IgnoreAll,
+                    _ => bail!("unknown strategy option: -X{}", option),
+                };
+                whitespace = whitespace.max(ignored);
+            }
+            let options = MergeOptions {
+                no_commit,
+                squash,
+                no_ff,
+                message,
+                whitespace,
+            };
+            let head = Ref::try_resolve(&repository, "HEAD")
+                .context("Couldn't read HEAD")?
+                .context("No commits yet on HEAD")?;
+            let result = match merge::merge(&repository, &commits, &options) {
+                Ok(result) => result,
+                Err(e) => {
+                    match e.downcast_ref::<MergeError>() {
+                        Some(MergeError::NotMergeable(_)) => eprintln!("merge: {}", e),
+                        Some(MergeError::WouldOverwrite { strategy, .. }) => {
+                            eprintln!("error: {}", e);
+                            if let Some(strategy) = strategy {
+                                eprintln!("Merge with strategy {} failed.", strategy);
+                                std::process::exit(2);
+                            }
+                        }
+                        None => return Err(e),
+                    }
+                    std::process::exit(1);
+                }
+            };
+
+            let print_stat = |from: &str, to: &str| {
+                let old = Tree::of_commit(&repository, from).context("Couldn't read tree")?;
+                let new = Tree::of_commit(&repository, to).context("Couldn't read tree")?;
+                let changes = diff::diff_trees(&repository, &old, &new, true, &Pathspec::default())
+                    .context("Couldn't diff trees")?;
+                let stat = diffstat::stat(&repository, &changes, &DiffOptions::default())
+                    .context("Couldn't diff files")?;
+                for line in stat.iter().chain(&diffstat::summary(&changes)) {
+                    println!("{}", line);
+                }
+                anyhow::Ok(())
+            };
+            for message in &result.messages {
+                println!("{}", message);
+            }
+            match result.outcome {
+                Outcome::UpToDate if squash => println!("Already up to date. (nothing to squash)"),
+                Outcome::UpToDate => println!("Already up to date."),
+                Outcome::FastForward { from, to } => {
+                    println!("Updating {}..{}", Commit::short(&from), Commit::short(&to));
+                    println!("Fast-forward");
+                    if squash {
+                        println!("Squash commit -- not updating HEAD");
+                    }
+                    print_stat(&from, &to)?;
+                }
+                Outcome::Merged {
+                    commit: Some(hash), ..
+                } => {
+                    println!("Merge made by the '{}' strategy.", result.strategy);
+                    print_stat(&head, &hash)?;
+                }
+                Outcome::Failed => {
+                    eprintln!("Merge with strategy {} failed.", result.strategy);
+                    std::process::exit(2);
+                }
+                Outcome::Merged { conflicts, .. } => {
+                    if squash {
+                        println!("Squash commit -- not updating HEAD");
+                    }
+                    if conflicts {
+                        println!(
+                            "Automatic merge failed; fix conflicts and then commit the result."
+                        );
+                        std::process::exit(1);
+                    }
+                    eprintln!("Automatic merge went well; stopped before committing as requested");
+                }
+            }
+        }
+        Command::Worktree { cmd } => {
+            let repository = Repository::find_repository()?;
+            match cmd {
+                WorktreeCommand::Add {
+                    branch,
+                    path,
+                    commit,
+                } => {
+                    let worktree =
+                        Worktree::add(&repository, Path::new(&path), &commit, branch.as_deref())
+                            .context("Couldn't add worktree")?;
+                    println!("Preparing worktree at {}", worktree.path.display());
+                }
+                WorktreeCommand::List => {
+                    let worktrees =
+                        Worktree::list(&repository).context("Couldn't list worktrees")?;
+                    for worktree in worktrees {
+                        let hash = worktree
+                            .resolve_head(&repository)
+                            .unwrap_or_else(|_| "0".repeat(40));
+                        let head = match worktree.head.strip_prefix("ref: refs/heads/") {
+                            Some(branch) => format!("[{}]", branch),
+                            None => "(detached HEAD)".to_string(),
+                        };
+                        println!(
+                            "{}  {} {}",
+                            worktree.path.display(),
+                            Commit::short(&hash),
+                            head
+                        );
+                    }
+                }
+                WorktreeCommand::Remove { force, path } => {
+                    Worktree::remove(&repository, Path::new(&path), force)
+                        .context("Couldn't remove worktree")?;
+                }
+                WorktreeCommand::Prune { dry_run, verbose } => {
+                    let pruned = Worktree::prune(&repository, dry_run)
+                        .context("Couldn't prune worktrees")?;
+                    for (name, reason) in pruned.iter().filter(|_| dry_run || verbose) {
+                        eprintln!("Removing worktrees/{}: {}", name, reason);
+                    }
+                }
+            }
+        }
+    };
+    Ok(())
+}
+
+// Prints what applying a stash said about the files it merged, exiting with 1 if it left conflicts,
+// after saying the stash is kept if it would have been dropped
+fn print_applied(applied: &stash::Applied, dropping: bool) {
+    for message in &applied.messages {
+        println!("{}", message);
+    }
+    if !applied.conflicts.is_empty() {
+        if dropping {
+            println!("The stash entry is kept in case you need it again.");
+        }
+        std::process::exit(1);
+    }
+}
+
+// Lists the changes a dry run would have made
+fn print_plan(repository: &Repository, plan: &Plan) {
+    for action in plan.actions() {
+        println!("{}", action.describe(repository));
+    }
+}
+
+// Returns the command line with the alias it starts with expanded. Shell aliases are run right
+// away, exiting with their status.
+fn expand_aliases() -> anyhow::Result<Vec<String>> {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| "gitrs".to_string());
+    let config = match Repository::find_repository_at(Path::new(".")) {
+        Some(repository) => Config::load(&repository),
+        None => Config::load_global(),
+    }
+    .context("Couldn't read config")?;
+
+    let commands = Gitrs::command();
+    let is_command = |name: &str| name == "help" || commands.find_subcommand(name).is_some();
+    match alias::expand(&config, args.collect(), is_command)? {
+        Expansion::Args(args) => Ok(std::iter::once(program).chain(args).collect()),
+        Expansion::Shell(command, args) => {
+            let status = alias::run_shell(&command, &args)?;
+            std::process::exit(status);
+        }
+    }
+}
+
+// Whether a panic is print! failing on a closed stdout
+fn closed_pipe(info: &std::panic::PanicHookInfo) -> bool {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .is_some_and(|message| message.starts_with("failed printing to stdout"))
+}
+
+// Prints the changes found by the diff plumbing commands, exiting with 1 if there are any and
+// --exit-code or --quiet asked for it
+fn print_changes(changes: &[diff::Change], nul: bool, quiet: bool, exit_code: bool) {
+    if !quiet {
+        for change in changes {
+            print!("{}", change.to_raw(nul));
+        }
+    }
+    if (quiet || exit_code) && !changes.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// Resolves the commits given to the --contains style options
+fn find_commits(repository: &Repository, names: &[String]) -> anyhow::Result<Vec<String>> {
+    names
+        .iter()
+        .map(|name| {
+            revwalk::find_commit(repository, name)
+                .map_err(|_| anyhow!("malformed object name {}", name))
+        })
+        .collect()
+}
+
+// Lists the commits of a range (A..B, or A...B for those either side has that the other hasn't)
+// other than merges, oldest first, as range-diff compares them. A side left out is HEAD.
+fn series(repository: &Repository, range: &str) -> anyhow::Result<Vec<String>> {
+    let find = |name: &str| {
+        let name = if name.is_empty() { "HEAD" } else { name };
+        revwalk::find_commit(repository, name)
+            .map_err(|_| anyhow!("Couldn't find commit: {}", name))
+    };
+    let commits = match (range.split_once("..."), range.split_once("..")) {
+        (Some((a, b)), _) => revwalk::difference(repository, &[find(a)?], &[find(b)?], &[]),
+        (None, Some((a, b))) => revwalk::difference(repository, &[], &[find(b)?], &[find(a)?]),
+        (None, None) => bail!("not a commit range: '{}'", range),
+    }
+    .context("Couldn't walk history")?;
+    let mut series = Vec::new();
+    for (hash, _) in commits.into_iter().rev() {
+        let commit = revwalk::read_commit(repository, &hash)
+            .with_context(|| format!("Couldn't read commit {}", hash))?;
+        if commit.parents().len() < 2 {
+            series.push(hash);
+        }
+    }
+    Ok(series)
+}
+
+// Verifies and reports on the signature of each object for verify-commit and verify-tag, returning
+// true if all of them are valid
+fn verify_signatures(
+    repository: &Repository,
+    config: &Config,
+    hashes: &[String],
+    verbose: bool,
+    raw: bool,
+) -> anyhow::Result<bool> {
+    let mut all_good = true;
+    for hash in hashes {
+        let Some((payload, verification)) = signature::verify_object(repository, config, hash)
+            .with_context(|| format!("Couldn't verify {}", hash))?
+        else {
+            all_good = false;
+            continue;
+        };
+        eprint!(
+            "{}",
+            if raw {
+                &verification.raw
+            } else {
+                &verification.output
+            }
+        );
+        if verbose {
+            print!("{}", String::from_utf8_lossy(&payload));
+        }
+        all_good &= verification.is_good();
+    }
+    Ok(all_good)
+}
+
+// The value of the last `id` option given before the argument at `index`, for options that apply
+// to the arguments after them
+fn last_before<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    index: usize,
+) -> Option<T> {
+    let values = matches.get_many::<T>(id)?;
+    let indices = matches.indices_of(id)?;
+    values
+        .zip(indices)
+        .take_while(|(_, at)| *at < index)
+        .last()
+        .map(|(value, _)| value.clone())
 }
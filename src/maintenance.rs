@@ -0,0 +1,522 @@
+// Runs maintenance tasks that keep a repository fast to read as it grows, like git maintenance run.
+// The loose-objects task deletes the loose objects that are packed already, then packs the rest,
+// up to 50,000 of them, into a pack named loose-<hash>.pack, whose objects are deleted as loose
+// ones the next time it runs. The incremental-repack task rolls small packs together: taking the
+// packs from the oldest, those smaller than the second largest pack go into one new pack, once
+// together they are at least as large as it. git does this through its multi-pack-index, deleting
+// the old packs on the next run; gitrs has no multi-pack-index, so they are deleted right away,
+// unless they are kept. With --auto, a task only runs once there is enough for it to do: as many
+// loose objects as maintenance.loose-objects.auto says (100 by default), or as many packs as
+// maintenance.incremental-repack.auto says (10 by default).
+//
+// Tasks run in the order given, or without any given, those maintenance.<task>.enabled turns on.
+// git runs its gc task by default, which gitrs doesn't have, so with no task configured either
+// way, both run. Only one maintenance runs at a time, which objects/maintenance.lock marks.
+//
+// Registering a repository adds it to maintenance.repo in the global config, and starting
+// maintenance also installs a schedule, which runs `for-each-repo --config=maintenance.repo
+// maintenance run --schedule=<frequency>` hourly, daily and weekly. A scheduled run only runs the
+// tasks maintenance.<task>.schedule has run at least that often, which with maintenance.strategy
+// set to incremental, as registering sets it unless it is set already, is daily for both. Of the
+// schedulers git can install it with, gitrs only supports cron, through the crontab program, whose
+// table keeps everything outside of the lines gitrs marks as its own.
+//
+// A dry run plans what the tasks would do without taking the lock or changing anything. Each task
+// then works from the packs on disk, so one doesn't see the pack the task before it would write.
+use std::env;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, anyhow, bail, ensure};
+
+use crate::config::{self, Config};
+use crate::loose;
+use crate::pack::PackIndex;
+use crate::plan::Plan;
+use crate::prune;
+use crate::repack;
+use crate::repository::Repository;
+use crate::temporary::TempDir;
+
+// The most loose objects git packs in one go
+const LOOSE_OBJECTS_BATCH: usize = 50_000;
+// Packs larger than this are never rolled together
+const MAX_BATCH_SIZE: u64 = 2 << 30;
+// The lines around the schedule in the crontab
+const BEGIN_LINE: &str = "# BEGIN GITRS MAINTENANCE SCHEDULE";
+const END_LINE: &str = "# END GITRS MAINTENANCE SCHEDULE";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Task {
+    LooseObjects,
+    IncrementalRepack,
+}
+
+const TASKS: [Task; 2] = [Task::LooseObjects, Task::IncrementalRepack];
+
+/// How often tasks run in the background, from the least often
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Schedule {
+    Weekly,
+    Daily,
+    Hourly,
+}
+
+const SCHEDULES: [Schedule; 3] = [Schedule::Hourly, Schedule::Daily, Schedule::Weekly];
+
+/// What installs the schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheduler {
+    Auto,
+    Crontab,
+}
+
+#[derive(Default)]
+pub struct MaintenanceOptions {
+    /// The tasks to run, in order, instead of those configured
+    pub tasks: Vec<Task>,
+    /// Only run the tasks that have enough to do
+    pub auto: bool,
+    /// Only run the tasks scheduled to run at least this often
+    pub schedule: Option<Schedule>,
+}
+
+/// What a task did
+pub struct TaskReport {
+    pub task: Task,
+    /// The new pack and how many objects it has, if anything was packed
+    pub pack: Option<(PathBuf, usize)>,
+    /// The packs that were deleted
+    pub removed_packs: Vec<PathBuf>,
+    /// How many loose objects were deleted, as they were packed already
+    pub removed_loose: usize,
+}
+
+/// Runs the maintenance tasks `options` asks for, returning what each did, or None if another
+/// maintenance is running
+pub fn run(
+    repository: &Repository,
+    options: &MaintenanceOptions,
+    plan: &mut Plan,
+) -> anyhow::Result<Option<Vec<TaskReport>>> {
+    let config = Config::load(repository)?;
+    let mut tasks = match options.tasks.is_empty() {
+        true => configured(&config)?,
+        false => options.tasks.clone(),
+    };
+    if let Some(schedule) = options.schedule {
+        tasks.retain(|&task| scheduled(&config, task) >= Some(schedule));
+    }
+    for (i, task) in tasks.iter().enumerate() {
+        if tasks[..i].contains(task) {
+            bail!("task '{}' cannot be selected multiple times", task);
+        }
+    }
+
+    let lock = repository.get_path(&["objects", "maintenance.lock"]);
+    let taken = match plan.is_dry_run() {
+        true => lock.exists(),
+        false => OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+            .is_err(),
+    };
+    if taken {
+        if !options.auto {
+            eprintln!(
+                "warning: lock file '{}' exists, skipping maintenance",
+                lock.display()
+            );
+        }
+        return Ok(None);
+    }
+    let reports = (|| {
+        let mut reports = Vec::new();
+        for task in tasks {
+            if options.auto && !needed(repository, &config, task)? {
+                continue;
+            }
+            let report = match task {
+                Task::LooseObjects => loose_objects(repository, plan),
+                Task::IncrementalRepack => incremental_repack(repository, plan),
+            }
+            .with_context(|| format!("task '{}' failed", task))?;
+            reports.push(report);
+        }
+        Ok(reports)
+    })();
+    if !plan.is_dry_run() {
+        fs::remove_file(&lock).with_context(|| format!("Failed to delete {}", lock.display()))?;
+    }
+    reports.map(Some)
+}
+
+// The tasks maintenance.<task>.enabled turns on, or all of them if none is configured
+fn configured(config: &Config) -> anyhow::Result<Vec<Task>> {
+    let mut tasks = Vec::new();
+    let mut any = false;
+    for task in TASKS {
+        let enabled = config.get_bool(&format!("maintenance.{}.enabled", task))?;
+        any |= enabled.is_some();
+        if enabled == Some(true) {
+            tasks.push(task);
+        }
+    }
+    Ok(match any {
+        true => tasks,
+        false => TASKS.to_vec(),
+    })
+}
+
+// How often `task` is scheduled to run: as maintenance.<task>.schedule says, or daily with the
+// incremental strategy
+fn scheduled(config: &Config, task: Task) -> Option<Schedule> {
+    match config.get(&format!("maintenance.{}.schedule", task)) {
+        // Like git, a schedule it doesn't know is no schedule
+        Some(schedule) => schedule.parse().ok(),
+        None if config.get("maintenance.strategy") == Some("incremental") => Some(Schedule::Daily),
+        None => None,
+    }
+}
+
+/// Registers the repository for scheduled maintenance, adding it to maintenance.repo in the global
+/// config. Like git, this turns off maintenance.auto, which scheduled maintenance does instead of,
+/// and picks the incremental strategy if there is none.
+pub fn register(repository: &Repository) -> anyhow::Result<()> {
+    let local = repository.get_path(&["config"]);
+    config::set_value(&local, "maintenance.auto", "false")?;
+    let config = Config::load(repository)?;
+    if config.get("maintenance.strategy").is_none() {
+        config::set_value(&local, "maintenance.strategy", "incremental")?;
+    }
+    let path = registered_path(repository)?;
+    if !config.get_all("maintenance.repo").contains(&path.as_str()) {
+        config::add_value(&Config::global_file()?, "maintenance.repo", &path)?;
+    }
+    Ok(())
+}
+
+/// Removes the repository from maintenance.repo in the global config
+pub fn unregister(repository: &Repository) -> anyhow::Result<()> {
+    let global = Config::global_file()?;
+    if global.exists() {
+        config::unset_value(
+            &global,
+            "maintenance.repo",
+            Some(&registered_path(repository)?),
+        )?;
+    }
+    Ok(())
+}
+
+/// Registers the repository and installs the schedule that runs maintenance on the registered
+/// repositories
+pub fn start(repository: &Repository, scheduler: Scheduler) -> anyhow::Result<()> {
+    if let Err(e) = register(repository) {
+        eprintln!("warning: failed to add repo to global config: {:#}", e);
+    }
+    update_schedule(scheduler, true)
+}
+
+/// Removes the schedule, leaving the repositories registered
+pub fn stop(scheduler: Scheduler) -> anyhow::Result<()> {
+    update_schedule(scheduler, false)
+}
+
+// The path maintenance.repo records a repository by
+fn registered_path(repository: &Repository) -> anyhow::Result<String> {
+    let path = fs::canonicalize(&repository.worktree)
+        .with_context(|| format!("Couldn't resolve {}", repository.worktree.display()))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+// Installs the schedule in the crontab, or with `enable` unset takes it out
+fn update_schedule(scheduler: Scheduler, enable: bool) -> anyhow::Result<()> {
+    // Only cron is supported, which is what auto picks everywhere
+    match scheduler {
+        Scheduler::Auto | Scheduler::Crontab => {}
+    }
+    let listed = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .context("failed to run 'crontab -l'; your system might not support 'cron'")?;
+    // crontab -l fails for a user without a crontab yet
+    let current = match listed.status.success() {
+        true => String::from_utf8_lossy(&listed.stdout).into_owned(),
+        false => String::new(),
+    };
+    let program = env::current_exe().context("Couldn't find the gitrs executable")?;
+    let table = crontab(&current, enable.then_some(program.as_path()));
+
+    let directory = TempDir::new("crontab")?;
+    let file = directory.path().join("crontab");
+    fs::write(&file, table).with_context(|| format!("Couldn't write {}", file.display()))?;
+    let status = Command::new("crontab")
+        .arg(&file)
+        .status()
+        .context("failed to run 'crontab'; your system might not support 'cron'")?;
+    ensure!(status.success(), "'crontab' died");
+    Ok(())
+}
+
+// The crontab `current` with its schedule replaced by one running `program`, or taken out
+fn crontab(current: &str, program: Option<&Path>) -> String {
+    let mut table = String::new();
+    let mut in_schedule = false;
+    for line in current.lines() {
+        match line {
+            BEGIN_LINE if !in_schedule => in_schedule = true,
+            END_LINE if in_schedule => in_schedule = false,
+            _ if !in_schedule => {
+                table.push_str(line);
+                table.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(program) = program {
+        table.push_str(BEGIN_LINE);
+        table.push_str(
+            "\n# The following schedule was created by gitrs\n\
+             # Any edits made in this region might be\n\
+             # replaced in the future by a gitrs command.\n\n",
+        );
+        for schedule in SCHEDULES {
+            let (hours, days) = match schedule {
+                Schedule::Hourly => ("1-23", "*"),
+                Schedule::Daily => ("0", "1-6"),
+                Schedule::Weekly => ("0", "0"),
+            };
+            table.push_str(&format!(
+                "0 {} * * {} \"{}\" for-each-repo --config=maintenance.repo maintenance run \
+                 --schedule={}\n",
+                hours,
+                days,
+                program.display(),
+                schedule
+            ));
+        }
+        table.push('\n');
+        table.push_str(END_LINE);
+        table.push('\n');
+    }
+    table
+}
+
+// Whether `task` has enough to do to run with --auto: at least maintenance.<task>.auto loose
+// objects or packs, where 0 means never and anything negative always
+fn needed(repository: &Repository, config: &Config, task: Task) -> anyhow::Result<bool> {
+    let key = format!("maintenance.{}.auto", task);
+    let default = match task {
+        Task::LooseObjects => 100,
+        Task::IncrementalRepack => 10,
+    };
+    let limit: i64 = match config.get(&key) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| anyhow!("Bad numeric config value '{}' for '{}'", value, key))?,
+        None => default,
+    };
+    if limit <= 0 {
+        return Ok(limit < 0);
+    }
+    let count = match task {
+        Task::LooseObjects => loose::scan(repository)?.objects.len(),
+        Task::IncrementalRepack => rollable(repository)?.len(),
+    };
+    Ok(count as i64 >= limit)
+}
+
+// Deletes the loose objects that are packed, then packs the others
+fn loose_objects(repository: &Repository, plan: &mut Plan) -> anyhow::Result<TaskReport> {
+    let removed = prune::prune_packed(repository, plan)?;
+    let hashes = loose::scan(repository)?
+        .objects
+        .into_iter()
+        .filter(|object| !plan.is_removed(&object.file.path))
+        .take(LOOSE_OBJECTS_BATCH)
+        .map(|object| object.hash)
+        .collect();
+    Ok(TaskReport {
+        task: Task::LooseObjects,
+        pack: repack::write_pack(repository, plan, "loose", hashes)?,
+        removed_packs: Vec::new(),
+        removed_loose: removed.len(),
+    })
+}
+
+// Rolls the small packs together, as git's multi-pack-index repack does with a batch size of
+// "auto": one more than the size of the second largest pack
+fn incremental_repack(repository: &Repository, plan: &mut Plan) -> anyhow::Result<TaskReport> {
+    let mut packs = rollable(repository)?;
+    packs.retain(|(index, _, _)| !plan.is_removed(&index.pack));
+    let mut sizes: Vec<u64> = packs.iter().map(|(_, size, _)| *size).collect();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    let batch_size = (sizes.get(1).copied().unwrap_or(0) + 1).min(MAX_BATCH_SIZE);
+
+    packs.sort_by_key(|(index, _, modified)| (*modified, index.pack.clone()));
+    let mut batch = Vec::new();
+    let mut total = 0;
+    for (index, size, _) in packs {
+        if total >= batch_size {
+            break;
+        }
+        if size >= batch_size {
+            continue;
+        }
+        total += size;
+        batch.push(index);
+    }
+
+    let mut report = TaskReport {
+        task: Task::IncrementalRepack,
+        pack: None,
+        removed_packs: Vec::new(),
+        removed_loose: 0,
+    };
+    if total >= batch_size && batch.len() >= 2 {
+        let repacked = repack::roll(repository, plan, &batch)?;
+        report.pack = repacked.pack;
+        report.removed_packs = repacked.removed;
+    }
+    Ok(report)
+}
+
+// The packs that can be rolled together, those neither kept nor from a promisor remote, with
+// their sizes and when they were written
+fn rollable(
+    repository: &Repository,
+) -> anyhow::Result<Vec<(Arc<PackIndex>, u64, std::time::SystemTime)>> {
+    let mut packs = Vec::new();
+    for index in PackIndex::load_all(repository)? {
+        if index.is_kept() || index.is_promisor() || index.objects().is_empty() {
+            continue;
+        }
+        let metadata = fs::metadata(&index.pack)
+            .with_context(|| format!("Failed to read {}", index.pack.display()))?;
+        packs.push((index, metadata.len(), metadata.modified()?));
+    }
+    Ok(packs)
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Hourly => write!(f, "hourly"),
+            Schedule::Daily => write!(f, "daily"),
+            Schedule::Weekly => write!(f, "weekly"),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(Schedule::Hourly),
+            "daily" => Ok(Schedule::Daily),
+            "weekly" => Ok(Schedule::Weekly),
+            _ => Err(format!("unrecognized --schedule argument '{}'", s)),
+        }
+    }
+}
+
+impl FromStr for Scheduler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Scheduler::Auto),
+            "crontab" => Ok(Scheduler::Crontab),
+            "launchctl" | "schtasks" | "systemd-timer" => {
+                Err(format!("the {} scheduler isn't supported", s))
+            }
+            _ => Err(format!("unrecognized --scheduler argument '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Task::LooseObjects => write!(f, "loose-objects"),
+            Task::IncrementalRepack => write!(f, "incremental-repack"),
+        }
+    }
+}
+
+impl FromStr for Task {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "loose-objects" => Ok(Task::LooseObjects),
+            "incremental-repack" => Ok(Task::IncrementalRepack),
+            "commit-graph" | "prefetch" | "gc" | "pack-refs" => {
+                Err(format!("the {} task isn't supported", s))
+            }
+            _ => Err(format!("'{}' is not a valid task", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_schedule_in_crontab() {
+        let program = Path::new("/bin/gitrs");
+        let installed = crontab("MAILTO=a\n0 * * * * backup\n", Some(program));
+        assert_eq!(
+            installed,
+            "MAILTO=a\n0 * * * * backup\n\
+             # BEGIN GITRS MAINTENANCE SCHEDULE\n\
+             # The following schedule was created by gitrs\n\
+             # Any edits made in this region might be\n\
+             # replaced in the future by a gitrs command.\n\n\
+             0 1-23 * * * \"/bin/gitrs\" for-each-repo --config=maintenance.repo maintenance run --schedule=hourly\n\
+             0 0 * * 1-6 \"/bin/gitrs\" for-each-repo --config=maintenance.repo maintenance run --schedule=daily\n\
+             0 0 * * 0 \"/bin/gitrs\" for-each-repo --config=maintenance.repo maintenance run --schedule=weekly\n\
+             \n# END GITRS MAINTENANCE SCHEDULE\n"
+        );
+        // Installing again replaces the schedule rather than adding another
+        assert_eq!(crontab(&installed, Some(program)), installed);
+        assert_eq!(crontab(&installed, None), "MAILTO=a\n0 * * * * backup\n");
+    }
+
+    #[test]
+    fn schedules() {
+        let worktree = env::temp_dir().join(format!("gitrs-schedules-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let local = repository.get_path(&["config"]);
+
+        config::set_value(&local, "maintenance.strategy", "incremental").unwrap();
+        let config = Config::load(&repository).unwrap();
+        assert_eq!(
+            scheduled(&config, Task::LooseObjects),
+            Some(Schedule::Daily)
+        );
+        config::set_value(&local, "maintenance.loose-objects.schedule", "hourly").unwrap();
+        config::set_value(&local, "maintenance.incremental-repack.schedule", "never").unwrap();
+        let config = Config::load(&repository).unwrap();
+        assert_eq!(
+            scheduled(&config, Task::LooseObjects),
+            Some(Schedule::Hourly)
+        );
+        assert_eq!(scheduled(&config, Task::IncrementalRepack), None);
+        // A daily run runs what runs hourly too
+        assert!(Schedule::Hourly > Schedule::Daily && Schedule::Daily > Schedule::Weekly);
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+}
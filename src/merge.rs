@@ -0,0 +1,1129 @@
+// Merges another commit into HEAD. When HEAD is behind it, the branch is just fast-forwarded;
+// otherwise the trees of both sides are merged against their merge base path by path, the way git's
+// ort strategy does without rename detection. A file changed on only one side takes that side's
+// version, and one changed on both has its contents merged by the merge driver its `merge`
+// attribute picks, by default line by line, leaving conflict markers where the changes overlap. The
+// result is committed with both commits as parents, unless there were conflicts or the merge was
+// asked to stop first, in which case MERGE_HEAD and MERGE_MSG record it for `--continue`. A squash
+// merge applies the changes without recording a merge at all, leaving a message describing the
+// squashed commits in SQUASH_MSG. Several commits can be merged at once, the way the octopus
+// strategy does, into a commit with a parent for each, as long as none but the last conflicts.
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+use anyhow::{Context, bail};
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::date;
+use crate::diff;
+use crate::ident::Ident;
+use crate::line_diff::Whitespace;
+use crate::merge_driver;
+use crate::merge_file::{ConflictStyle, Labels};
+use crate::object::blob::Blob;
+use crate::object::commit::Commit;
+use crate::object::tree::{self, Leaf, SYMLINK_MODE, Tree};
+use crate::object::{GitrsObject, ObjectType};
+use crate::pathspec::Pathspec;
+use crate::refs::{Ref, RefUpdate};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::sequencer::Operation;
+use crate::switch;
+use crate::tree_walk::{TreeWalk, TreeWalkOptions};
+
+// The files recording a merge that stopped before being committed
+const STATE_FILES: [&str; 3] = ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"];
+
+#[derive(Default)]
+pub struct MergeOptions {
+    /// Leave the result in the worktree rather than committing it (--no-commit)
+    pub no_commit: bool,
+    /// Apply the changes without recording a merge, even for a fast-forward
+    pub squash: bool,
+    /// Make a merge commit even when HEAD could be fast-forwarded
+    pub no_ff: bool,
+    /// The merge commit's message, instead of the default one naming what was merged
+    pub message: Option<String>,
+    /// The differences in whitespace to ignore when merging lines (-Xignore-space-change etc.)
+    pub whitespace: Whitespace,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MergeError {
+    #[error("{0} - not something we can merge")]
+    NotMergeable(String),
+    /// Nothing was touched, as the merge would have overwritten local changes or untracked files.
+    /// `strategy` is the merge strategy that stopped, or None for a fast-forward.
+    #[error("{message}")]
+    WouldOverwrite {
+        message: String,
+        strategy: Option<&'static str>,
+    },
+}
+
+pub enum Outcome {
+    UpToDate,
+    /// HEAD moved from one commit to the other, or would have, for a squash merge
+    FastForward {
+        from: String,
+        to: String,
+    },
+    /// The trees were merged, and the result committed unless there were conflicts or it wasn't
+    /// wanted
+    Merged {
+        commit: Option<String>,
+        conflicts: bool,
+    },
+    /// The commits couldn't be merged together automatically, so nothing was changed
+    Failed,
+}
+
+pub struct Merge {
+    /// What happened to each file that needed merging, in git's words
+    pub messages: Vec<String>,
+    /// The strategy the merge was made by, "ort" for one commit or "octopus" for several
+    pub strategy: &'static str,
+    pub outcome: Outcome,
+}
+
+// The mode and hash of a file, or None where it is missing
+type FileEntry = Option<(String, String)>;
+
+// The result of merging three trees, as changes to ours
+/// The result of merging trees
+pub struct TreeMerge {
+    /// The files to change in our tree, with what they become, or None to delete them
+    pub updates: Vec<(String, FileEntry)>,
+    /// The files left with conflicts
+    pub conflicts: Vec<String>,
+    /// What git says about the files it merges, eg. `Auto-merging a`
+    pub messages: Vec<String>,
+}
+
+/// Merges the commits `names` into HEAD. The ones already merged, into HEAD or into another of
+/// them, are left out, and if more than one is left they are merged together by the octopus
+/// strategy.
+pub fn merge(
+    repository: &Repository,
+    names: &[String],
+    options: &MergeOptions,
+) -> anyhow::Result<Merge> {
+    match Operation::in_progress(repository) {
+        Some(Operation::Merge) => bail!(
+            "You have not concluded your merge (MERGE_HEAD exists).\n\
+             Please, commit your changes before you merge."
+        ),
+        Some(Operation::CherryPick) => bail!(
+            "You have not concluded your cherry-pick (CHERRY_PICK_HEAD exists).\n\
+             Please, commit your changes before you merge."
+        ),
+        // A bisection only checks out commits, which can be merged into like any other
+        Some(operation) if operation.blocks_switching() => {
+            bail!("cannot merge while {}", operation)
+        }
+        _ => {}
+    }
+    let mut commits = Vec::new();
+    for name in names {
+        let hash = revwalk::find_commit(repository, name)
+            .map_err(|_| MergeError::NotMergeable(name.to_string()))?;
+        commits.push((name.as_str(), hash));
+    }
+    let head = Ref::try_resolve(repository, "HEAD")?.context("No commits yet on HEAD")?;
+    let mut heads: Vec<(&str, String)> = Vec::new();
+    for (name, hash) in &commits {
+        if heads.iter().any(|(_, merged)| merged == hash)
+            || revwalk::is_ancestor(repository, hash, &head)?
+        {
+            continue;
+        }
+        let mut merged_elsewhere = false;
+        for (_, other) in commits.iter().filter(|(_, other)| other != hash) {
+            merged_elsewhere |= revwalk::is_ancestor(repository, hash, other)?;
+        }
+        if !merged_elsewhere {
+            heads.push((name, hash.clone()));
+        }
+    }
+
+    write_state(repository, "ORIG_HEAD", &format!("{}\n", head))?;
+    let names: Vec<&str> = heads.iter().map(|(name, _)| *name).collect();
+    let reflog_action = format!("merge {}", names.join(" "));
+    match heads.as_slice() {
+        [] => Ok(Merge {
+            messages: Vec::new(),
+            strategy: "ort",
+            outcome: Outcome::UpToDate,
+        }),
+        [(name, theirs)] => merge_one(repository, &head, name, theirs, options, &reflog_action),
+        _ => octopus(repository, &head, &heads, options, &reflog_action),
+    }
+}
+
+// Merges the single commit `theirs` into HEAD, fast-forwarding to it if HEAD is an ancestor
+fn merge_one(
+    repository: &Repository,
+    head: &str,
+    name: &str,
+    theirs: &str,
+    options: &MergeOptions,
+    reflog_action: &str,
+) -> anyhow::Result<Merge> {
+    let bases = revwalk::merge_bases(repository, head, &[theirs.to_string()])?;
+    let config = Config::load(repository)?;
+    let head_tree = Tree::of_commit(repository, head)?;
+    let their_tree = Tree::of_commit(repository, theirs)?;
+    let heads = [(name, theirs.to_string())];
+    if bases.iter().any(|base| base == head) && !options.no_ff {
+        let updates = changes(repository, &head_tree, &their_tree)?;
+        update_worktree(repository, &config, &head_tree, &updates, None)?;
+        if options.squash {
+            write_state(
+                repository,
+                "SQUASH_MSG",
+                &squash_message(repository, head, &heads)?,
+            )?;
+        } else {
+            let message = format!("{}: Fast-forward", reflog_action);
+            advance_head(repository, &config, head, theirs, &message)?;
+        }
+        return Ok(Merge {
+            messages: Vec::new(),
+            strategy: "ort",
+            outcome: Outcome::FastForward {
+                from: head.to_string(),
+                to: theirs.to_string(),
+            },
+        });
+    }
+
+    let base = bases
+        .first()
+        .context("refusing to merge unrelated histories")?;
+    let base_label = Commit::short(base).to_string();
+    let labels = Labels {
+        base: &base_label,
+        ours: "HEAD",
+        theirs: name,
+    };
+    let base_tree = Tree::of_commit(repository, base)?;
+    let merged = merge_trees(
+        repository,
+        &config,
+        [&base_tree, &head_tree, &their_tree],
+        &labels,
+        options.whitespace,
+    )?;
+    update_worktree(
+        repository,
+        &config,
+        &head_tree,
+        &merged.updates,
+        Some("ort"),
+    )?;
+
+    let mut files = diff::flatten(repository, &head_tree, &Pathspec::default())?;
+    for (path, entry) in merged.updates {
+        match entry {
+            Some(entry) => files.insert(path, entry),
+            None => files.remove(&path),
+        };
+    }
+    let conclusion = Conclusion {
+        head,
+        heads: &heads,
+        files: &files,
+        conflicts: &merged.conflicts,
+        strategy: "ort",
+        reflog_action,
+    };
+    let outcome = conclude(repository, &config, &conclusion, options)?;
+    Ok(Merge {
+        messages: merged.messages,
+        strategy: "ort",
+        outcome,
+    })
+}
+
+// Merges several commits into HEAD in one go, the way git's octopus strategy does: each is merged
+// in turn into the result of merging the ones before it, so the first ones can fast-forward HEAD,
+// and only the last may leave conflicts to be resolved by hand. Nothing is changed when an earlier
+// one conflicts, as the ones after it would have to be merged into the conflicts.
+fn octopus(
+    repository: &Repository,
+    head: &str,
+    heads: &[(&str, String)],
+    options: &MergeOptions,
+    reflog_action: &str,
+) -> anyhow::Result<Merge> {
+    let config = Config::load(repository)?;
+    let head_tree = Tree::of_commit(repository, head)?;
+
+    let mut messages = Vec::new();
+    let mut merged_commits = vec![head.to_string()];
+    let mut files = diff::flatten(repository, &head_tree, &Pathspec::default())?;
+    let mut tree = Tree::of_commit(repository, head)?;
+    let mut fast_forward = true;
+    let mut conflicts = Vec::new();
+    for (name, theirs) in heads {
+        if !conflicts.is_empty() {
+            messages.push("Automated merge did not work.".to_string());
+            messages.push("Should not be doing an octopus.".to_string());
+            return Ok(Merge {
+                messages,
+                strategy: "octopus",
+                outcome: Outcome::Failed,
+            });
+        }
+        let bases = revwalk::merge_bases(repository, theirs, &merged_commits)?;
+        let their_tree = Tree::of_commit(repository, theirs)?;
+        if fast_forward && bases == merged_commits {
+            messages.push(format!("Fast-forwarding to: {}", name));
+            files = diff::flatten(repository, &their_tree, &Pathspec::default())?;
+            tree = their_tree;
+            merged_commits = vec![theirs.clone()];
+            continue;
+        }
+        fast_forward = false;
+
+        let base = bases
+            .first()
+            .with_context(|| format!("Unable to find common commit with {}", name))?;
+        messages.push(format!("Trying simple merge with {}", name));
+        let base_label = Commit::short(base).to_string();
+        let labels = Labels {
+            base: &base_label,
+            ours: "HEAD",
+            theirs: name,
+        };
+        let base_tree = Tree::of_commit(repository, base)?;
+        let merged = merge_trees(
+            repository,
+            &config,
+            [&base_tree, &tree, &their_tree],
+            &labels,
+            options.whitespace,
+        )?;
+        // Anything that took more than picking a side's version needed a real merge
+        if !merged.messages.is_empty() {
+            messages.push("Simple merge did not work, trying automatic merge.".to_string());
+            messages.extend(merged.messages);
+        }
+        for (path, entry) in merged.updates {
+            match entry {
+                Some(entry) => files.insert(path, entry),
+                None => files.remove(&path),
+            };
+        }
+        conflicts = merged.conflicts;
+        tree = Tree::from_name(repository, &Tree::write_files(repository, &files)?)?;
+        merged_commits.push(theirs.clone());
+    }
+
+    let updates = changes(repository, &head_tree, &tree)?;
+    update_worktree(repository, &config, &head_tree, &updates, Some("octopus"))?;
+    let conclusion = Conclusion {
+        head,
+        heads,
+        files: &files,
+        conflicts: &conflicts,
+        strategy: "octopus",
+        reflog_action,
+    };
+    let outcome = conclude(repository, &config, &conclusion, options)?;
+    Ok(Merge {
+        messages,
+        strategy: "octopus",
+        outcome,
+    })
+}
+
+// The result of merging trees, waiting to be recorded
+struct Conclusion<'a> {
+    head: &'a str,
+    /// The commits merged into HEAD, with the names they were given by
+    heads: &'a [(&'a str, String)],
+    files: &'a BTreeMap<String, (String, String)>,
+    conflicts: &'a [String],
+    strategy: &'a str,
+    reflog_action: &'a str,
+}
+
+// Records a merge whose result is in the worktree: as a commit, or in the files describing the
+// merge in progress if there are conflicts or it was asked to stop, or for a squash merge in
+// SQUASH_MSG only
+fn conclude(
+    repository: &Repository,
+    config: &Config,
+    conclusion: &Conclusion,
+    options: &MergeOptions,
+) -> anyhow::Result<Outcome> {
+    let head = conclusion.head;
+    let conflicts = !conclusion.conflicts.is_empty();
+    let outcome = |commit| Outcome::Merged { commit, conflicts };
+    if options.squash {
+        let message = squash_message(repository, head, conclusion.heads)?;
+        write_state(repository, "SQUASH_MSG", &message)?;
+        return Ok(outcome(None));
+    }
+
+    let names: Vec<&str> = conclusion.heads.iter().map(|(name, _)| *name).collect();
+    let message = match &options.message {
+        Some(message) => format!("{}\n", message.trim_end()),
+        None => default_message(repository, &names)?,
+    };
+    if conflicts || options.no_commit {
+        let mut merge_message = message;
+        if conflicts {
+            merge_message.push_str("\n# Conflicts:\n");
+            for path in conclusion.conflicts {
+                merge_message.push_str(&format!("#\t{}\n", path));
+            }
+        }
+        let merge_heads: String = conclusion
+            .heads
+            .iter()
+            .map(|(_, hash)| format!("{}\n", hash))
+            .collect();
+        write_state(repository, "MERGE_HEAD", &merge_heads)?;
+        write_state(repository, "MERGE_MSG", &merge_message)?;
+        write_state(
+            repository,
+            "MERGE_MODE",
+            if options.no_ff { "no-ff" } else { "" },
+        )?;
+        return Ok(outcome(None));
+    }
+
+    let merge_heads: Vec<String> = conclusion
+        .heads
+        .iter()
+        .map(|(_, hash)| hash.clone())
+        .collect();
+    let parents = parents(repository, head, &merge_heads, options.no_ff)?;
+    let tree = Tree::write_files(repository, conclusion.files)?;
+    let hash = write_commit(repository, config, &tree, &parents, &message)?;
+    let reflog = format!(
+        "{}: Merge made by the '{}' strategy.",
+        conclusion.reflog_action, conclusion.strategy
+    );
+    advance_head(repository, config, head, &hash, &reflog)?;
+    Ok(outcome(Some(hash)))
+}
+
+/// Commits the merge that stopped before being committed, once the conflicts are resolved in the
+/// worktree. The files the merge touched are committed as they are in the worktree, and the rest as
+/// they are in HEAD, leaving local changes to them uncommitted. Returns the new commit's hash and
+/// message.
+pub fn continue_merge(repository: &Repository) -> anyhow::Result<(String, String)> {
+    let merge_heads = read_merge_heads(repository)?
+        .context("There is no merge in progress (MERGE_HEAD missing).")?;
+    let config = Config::load(repository)?;
+    let trust_executable_bit = config.get_bool("core.fileMode")?.unwrap_or(true);
+    let message = fs::read_to_string(repository.gitdir.join("MERGE_MSG")).unwrap_or_default();
+    let worktree = &repository.worktree;
+
+    // MERGE_MSG lists the conflicted files, which mustn't still have markers in them
+    for path in message.lines().filter_map(|line| line.strip_prefix("#\t")) {
+        let content = fs::read(worktree.join(path)).unwrap_or_default();
+        let has_marker = content
+            .split(|&b| b == b'\n')
+            .any(|line| line.starts_with(b"<<<<<<< ") || line.starts_with(b">>>>>>> "));
+        if has_marker {
+            bail!("Committing is not possible because you have unmerged files.");
+        }
+    }
+
+    let head = Ref::try_resolve(repository, "HEAD")?.context("No commits yet on HEAD")?;
+    let mode = fs::read_to_string(repository.gitdir.join("MERGE_MODE")).unwrap_or_default();
+    let parents = parents(repository, &head, &merge_heads, mode.contains("no-ff"))?;
+    let head_tree = Tree::of_commit(repository, &head)?;
+    let mut files = diff::flatten(repository, &head_tree, &Pathspec::default())?;
+    let mut merged = merged_paths(repository, &head, &head_tree, &merge_heads)?;
+    for path in message.lines().filter_map(|line| line.strip_prefix("#\t")) {
+        merged.entry(path.to_string()).or_insert(None);
+    }
+    for (path, theirs) in merged {
+        let Some((mode, hash)) = files.get(&path).or(theirs.as_ref()).cloned() else {
+            continue;
+        };
+        // Submodules are never checked out, so they keep their recorded commit
+        if Leaf::get_type_from_mode(&mode) == ObjectType::Commit {
+            files.insert(path, (mode, hash));
+            continue;
+        }
+        let full_path = worktree.join(&path);
+        match tree::write_worktree_file(repository, &full_path, &mode, trust_executable_bit)? {
+            Some(entry) => files.insert(path, entry),
+            None => files.remove(&path),
+        };
+    }
+
+    let message: String = message
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{}\n", line.trim_end()))
+        .collect();
+    let message = format!("{}\n", message.trim_end());
+    let tree = Tree::write_files(repository, &files)?;
+    let hash = write_commit(repository, &config, &tree, &parents, &message)?;
+    let subject = message.lines().next().unwrap_or("").to_string();
+    advance_head(
+        repository,
+        &config,
+        &head,
+        &hash,
+        &format!("commit (merge): {}", subject),
+    )?;
+    clear_state(repository)?;
+    Ok((hash, subject))
+}
+
+// Finds the paths a merge of `merge_heads` into `head` could have touched, with their versions in
+// the merge head changing them: those a merge head changed since its merge base with HEAD, to
+// something other than what HEAD has
+fn merged_paths(
+    repository: &Repository,
+    head: &str,
+    head_tree: &Tree,
+    merge_heads: &[String],
+) -> anyhow::Result<BTreeMap<String, FileEntry>> {
+    let mut paths = BTreeMap::new();
+    for merge_head in merge_heads {
+        let bases = revwalk::merge_bases(repository, head, std::slice::from_ref(merge_head))?;
+        let base = bases
+            .first()
+            .context("refusing to merge unrelated histories")?;
+        let base_tree = Tree::of_commit(repository, base)?;
+        let their_tree = Tree::of_commit(repository, merge_head)?;
+        let options = TreeWalkOptions {
+            recursive: true,
+            skip_identical: true,
+            ..TreeWalkOptions::default()
+        };
+        for entry in TreeWalk::new(repository, &[&base_tree, head_tree, &their_tree], options) {
+            let entry = entry?;
+            let [base, ours, theirs] = &entry.entries[..] else {
+                unreachable!("Walking three trees");
+            };
+            if theirs != base && theirs != ours {
+                paths.entry(entry.path).or_insert(theirs.clone());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Gives up on the merge that stopped before being committed, restoring the files it changed to
+/// their versions in HEAD
+pub fn abort(repository: &Repository) -> anyhow::Result<()> {
+    let merge_heads = read_merge_heads(repository)?
+        .context("There is no merge to abort (MERGE_HEAD missing).")?;
+    let head = Ref::try_resolve(repository, "HEAD")?.context("No commits yet on HEAD")?;
+    let head_tree = Tree::of_commit(repository, &head)?;
+    let mut paths = HashSet::new();
+    for merge_head in &merge_heads {
+        let tree = Tree::of_commit(repository, merge_head)?;
+        for change in diff::diff_trees(repository, &head_tree, &tree, true, &Pathspec::default())? {
+            paths.insert(change.path);
+        }
+    }
+
+    let head_files = diff::flatten(repository, &head_tree, &Pathspec::default())?;
+    let worktree = &repository.worktree;
+    let mut files = Vec::new();
+    for path in &paths {
+        match head_files.get(path) {
+            Some((mode, hash)) => files.push((path.as_str(), mode.as_str(), hash.as_str())),
+            None => {
+                let path = worktree.join(path);
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {}", path.display()))?;
+                    switch::remove_empty_parents(worktree, &path);
+                }
+            }
+        }
+    }
+    Tree::checkout_files(repository, worktree, &files)?;
+    clear_state(repository)
+}
+
+/// Forgets about the merge that stopped before being committed, leaving the worktree as it is
+pub fn clear_state(repository: &Repository) -> anyhow::Result<()> {
+    for name in STATE_FILES {
+        let path = repository.gitdir.join(name);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges the changes from `base` to `theirs` into `ours`, path by path
+pub fn merge_trees(
+    repository: &Repository,
+    config: &Config,
+    [base, ours, theirs]: [&Tree; 3],
+    labels: &Labels,
+    whitespace: Whitespace,
+) -> anyhow::Result<TreeMerge> {
+    let style = conflict_style(config);
+    let mut attributes = Attributes::new(repository);
+    let options = TreeWalkOptions {
+        recursive: true,
+        skip_identical: true,
+        ..TreeWalkOptions::default()
+    };
+    let mut merged = TreeMerge {
+        updates: Vec::new(),
+        conflicts: Vec::new(),
+        messages: Vec::new(),
+    };
+    for entry in TreeWalk::new(repository, &[base, ours, theirs], options) {
+        let entry = entry?;
+        let path = entry.path;
+        let [base, ours, theirs] = &entry.entries[..] else {
+            unreachable!("Walking three trees");
+        };
+        if ours == theirs || theirs == base {
+            continue;
+        }
+        if ours == base {
+            merged.updates.push((path, theirs.clone()));
+            continue;
+        }
+
+        let (ours, theirs) = match (ours, theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (None, theirs) => {
+                merged.messages.push(format!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} \
+                     of {} left in tree.",
+                    path, labels.ours, labels.theirs, labels.theirs, path
+                ));
+                merged.updates.push((path.clone(), theirs.clone()));
+                merged.conflicts.push(path);
+                continue;
+            }
+            (Some(_), None) => {
+                merged.messages.push(format!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  Version {} \
+                     of {} left in tree.",
+                    path, labels.theirs, labels.ours, labels.ours, path
+                ));
+                merged.conflicts.push(path);
+                continue;
+            }
+        };
+
+        // A side that kept the base's mode takes the other's
+        let mode = match base {
+            Some((base_mode, _)) if base_mode == &ours.0 => &theirs.0,
+            _ => &ours.0,
+        };
+        if ours.1 == theirs.1 {
+            if mode != &ours.0 {
+                merged
+                    .updates
+                    .push((path, Some((mode.clone(), ours.1.clone()))));
+            }
+            continue;
+        }
+
+        merged.messages.push(format!("Auto-merging {}", path));
+        let kind = match base {
+            Some(_) => "content",
+            None => "add/add",
+        };
+        let is_file =
+            |mode: &str| Leaf::get_type_from_mode(mode) == ObjectType::Blob && mode != SYMLINK_MODE;
+        // Symlinks and submodules can't be merged either, so our version is kept
+        let result = if is_file(&ours.0) && is_file(&theirs.0) {
+            let read = |hash: &str| Blob::read(repository, hash).map(|blob| blob.data().to_vec());
+            let base_content = match base {
+                Some((base_mode, hash)) if is_file(base_mode) => read(hash)?,
+                _ => Vec::new(),
+            };
+            let (our_content, their_content) = (read(&ours.1)?, read(&theirs.1)?);
+            let contents = [&base_content[..], &our_content, &their_content];
+            let driver = merge_driver::driver(config, &mut attributes, &path);
+            let result = driver.merge(repository, &path, contents, labels, style, whitespace)?;
+            if result.is_none() {
+                merged.messages.push(format!(
+                    "warning: Cannot merge binary files: {} ({} vs. {})",
+                    path, labels.ours, labels.theirs
+                ));
+            }
+            result
+        } else {
+            None
+        };
+        match result {
+            Some(result) => {
+                let hash = GitrsObject::BlobObject(Blob::new(result.content)).write(repository)?;
+                if result.conflicts > 0 {
+                    merged
+                        .messages
+                        .push(format!("CONFLICT ({}): Merge conflict in {}", kind, path));
+                    merged.conflicts.push(path.clone());
+                }
+                if (mode, &hash) != (&ours.0, &ours.1) {
+                    merged.updates.push((path, Some((mode.clone(), hash))));
+                }
+            }
+            None => {
+                merged
+                    .messages
+                    .push(format!("CONFLICT ({}): Merge conflict in {}", kind, path));
+                merged.conflicts.push(path);
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Writes the merged files to the worktree, refusing to overwrite local changes to them (compared
+/// against `head`) or untracked files
+pub fn update_worktree(
+    repository: &Repository,
+    config: &Config,
+    head: &Tree,
+    updates: &[(String, FileEntry)],
+    strategy: Option<&'static str>,
+) -> anyhow::Result<()> {
+    let trust_executable_bit = config.get_bool("core.fileMode")?.unwrap_or(true);
+    let worktree = &repository.worktree;
+    let local: HashSet<String> = diff::diff_worktree(
+        repository,
+        head,
+        head,
+        worktree,
+        trust_executable_bit,
+        &Pathspec::default(),
+    )?
+    .into_iter()
+    .map(|change| change.path)
+    .collect();
+    let tracked = diff::flatten(repository, head, &Pathspec::default())?;
+
+    let mut overwritten = Vec::new();
+    let mut untracked = Vec::new();
+    for (path, entry) in updates {
+        if local.contains(path) {
+            overwritten.push(path.as_str());
+        } else if entry.is_some()
+            && !tracked.contains_key(path)
+            && fs::symlink_metadata(worktree.join(path)).is_ok()
+        {
+            untracked.push(path.as_str());
+        }
+    }
+    let refuse = |message: String| MergeError::WouldOverwrite { message, strategy };
+    if !overwritten.is_empty() {
+        return Err(refuse(format!(
+            "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+             Please commit your changes or stash them before you merge.\nAborting",
+            overwritten.join("\n\t")
+        ))
+        .into());
+    }
+    if !untracked.is_empty() {
+        return Err(refuse(format!(
+            "The following untracked working tree files would be overwritten by merge:\n\t{}\n\
+             Please move or remove them before you merge.\nAborting",
+            untracked.join("\n\t")
+        ))
+        .into());
+    }
+
+    let mut files = Vec::new();
+    for (path, entry) in updates {
+        match entry {
+            Some((mode, hash)) => files.push((path.as_str(), mode.as_str(), hash.as_str())),
+            None => {
+                let path = worktree.join(path);
+                if fs::symlink_metadata(&path).is_ok() {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to delete {}", path.display()))?;
+                }
+                switch::remove_empty_parents(worktree, &path);
+            }
+        }
+    }
+    Tree::checkout_files(repository, worktree, &files)
+}
+
+// Names what is being merged the way git's default merge message does, eg. `Merge branches 'a'
+// and 'b' into next`. The branches, remote-tracking branches and tags are grouped by kind, and
+// each plain commit is named on its own. Merging into master or main goes without saying.
+fn default_message(repository: &Repository, names: &[&str]) -> anyhow::Result<String> {
+    let mut kinds: [(&str, &str, Vec<&str>); 3] = [
+        ("branch", "branches", Vec::new()),
+        (
+            "remote-tracking branch",
+            "remote-tracking branches",
+            Vec::new(),
+        ),
+        ("tag", "tags", Vec::new()),
+    ];
+    // The named refs count as one source, placed where the first of them was named
+    let mut sources: Vec<Option<String>> = Vec::new();
+    for name in names {
+        let exists = |prefix: &str| Ref::try_resolve(repository, &format!("{}{}", prefix, name));
+        let kind = if exists("refs/heads/")?.is_some() {
+            Some(0)
+        } else if exists("refs/remotes/")?.is_some() {
+            Some(1)
+        } else if exists("refs/tags/")?.is_some() {
+            Some(2)
+        } else {
+            None
+        };
+        match kind {
+            Some(kind) => {
+                kinds[kind].2.push(name);
+                if !sources.contains(&None) {
+                    sources.push(None);
+                }
+            }
+            None => sources.push(Some(format!("commit '{}'", name))),
+        }
+    }
+
+    let refs = kinds
+        .iter()
+        .filter_map(|(one, many, names)| {
+            let quoted: Vec<String> = names.iter().map(|name| format!("'{}'", name)).collect();
+            match quoted.as_slice() {
+                [] => None,
+                [name] => Some(format!("{} {}", one, name)),
+                [rest @ .., last] => Some(format!("{} {} and {}", many, rest.join(", "), last)),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let what = sources
+        .into_iter()
+        .map(|source| source.unwrap_or_else(|| refs.clone()))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let into = match crate::branch::current(repository)?.as_deref() {
+        Some("master" | "main") => String::new(),
+        Some(branch) => format!(" into {}", branch),
+        None => " into HEAD".to_string(),
+    };
+    Ok(format!("Merge {}{}\n", what, into))
+}
+
+// Describes the commits a squash merge brings in, newest first, as git log would
+fn squash_message(
+    repository: &Repository,
+    head: &str,
+    heads: &[(&str, String)],
+) -> anyhow::Result<String> {
+    let tips: Vec<String> = heads.iter().map(|(_, hash)| hash.clone()).collect();
+    let commits = revwalk::difference(repository, &tips, &[], &[head.to_string()])?;
+    let mut entries = Vec::new();
+    for (hash, _) in commits {
+        let commit = revwalk::read_commit(repository, &hash)?;
+        let author = commit.author()?;
+        let mut entry = format!("commit {}\n", hash);
+        if commit.parents().len() > 1 {
+            let parents: Vec<&str> = commit.parents().iter().map(|p| Commit::short(p)).collect();
+            entry.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        entry.push_str(&format!("Author: {} <{}>\n", author.name, author.email));
+        let date = date::format(author.timestamp, &author.timezone, "default")?;
+        entry.push_str(&format!("Date:   {}\n\n", date));
+        for line in commit.message().lines() {
+            entry.push_str(&format!("    {}\n", line));
+        }
+        entries.push(entry);
+    }
+    Ok(format!(
+        "Squashed commit of the following:\n\n{}",
+        entries.join("\n")
+    ))
+}
+
+// Lists the files that differ between two trees, with their versions in `to`
+fn changes(
+    repository: &Repository,
+    from: &Tree,
+    to: &Tree,
+) -> anyhow::Result<Vec<(String, FileEntry)>> {
+    Ok(
+        diff::diff_trees(repository, from, to, true, &Pathspec::default())?
+            .into_iter()
+            .map(|change| {
+                let entry = (change.status != diff::Status::Deleted)
+                    .then_some((change.new_mode, change.new_hash));
+                (change.path, entry)
+            })
+            .collect(),
+    )
+}
+
+/// The conflict style merge.conflictStyle asks for
+pub fn conflict_style(config: &Config) -> ConflictStyle {
+    match config.get("merge.conflictStyle") {
+        Some("diff3") => ConflictStyle::Diff3,
+        _ => ConflictStyle::Merge,
+    }
+}
+
+// The parents of a merge of `merge_heads` into `head`. HEAD is left out when one of them already
+// contains it, as for a fast-forward, unless a merge commit was asked for.
+fn parents(
+    repository: &Repository,
+    head: &str,
+    merge_heads: &[String],
+    no_ff: bool,
+) -> anyhow::Result<Vec<String>> {
+    let mut subsumed = false;
+    for merge_head in merge_heads {
+        subsumed |= revwalk::is_ancestor(repository, head, merge_head)?;
+    }
+    let mut parents = Vec::new();
+    if no_ff || !subsumed {
+        parents.push(head.to_string());
+    }
+    parents.extend(merge_heads.iter().cloned());
+    Ok(parents)
+}
+
+fn write_commit(
+    repository: &Repository,
+    config: &Config,
+    tree: &str,
+    parents: &[String],
+    message: &str,
+) -> anyhow::Result<String> {
+    let author = Ident::author(config)?;
+    let committer = Ident::committer(config)?;
+    let commit = Commit::new(tree, parents, &author, &committer, message);
+    GitrsObject::CommitObject(commit).write(repository)
+}
+
+// Moves HEAD, or the branch it is on, from `old` to `new`, logging `message` for both
+fn advance_head(
+    repository: &Repository,
+    config: &Config,
+    old: &str,
+    new: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    Ref::transaction(
+        repository,
+        &[RefUpdate {
+            name: "HEAD".to_string(),
+            new: Some(new.to_string()),
+            old: Some(old.to_string()),
+            verify_only: false,
+            deref: true,
+        }],
+    )?;
+    // The ref itself moved, so a missing identity only costs the reflog entries
+    if let Ok(ident) = Ident::committer(config) {
+        Ref::append_log(repository, "HEAD", Some(old), new, &ident, message)?;
+        if let Some(branch) = Ref::read_symbolic(repository, "HEAD")? {
+            Ref::append_log(repository, &branch, Some(old), new, &ident, message)?;
+        }
+    }
+    Ok(())
+}
+
+// Reads the commits being merged from MERGE_HEAD, or returns None if no merge is in progress
+fn read_merge_heads(repository: &Repository) -> anyhow::Result<Option<Vec<String>>> {
+    let path = repository.gitdir.join("MERGE_HEAD");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(
+        content
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
+fn write_state(repository: &Repository, name: &str, content: &str) -> anyhow::Result<()> {
+    let path = repository.gitdir.join(name);
+    fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    // Writes a commit of `files`, each a path and its contents
+    fn commit(repository: &Repository, files: &[(&str, &str)], parents: &[String]) -> String {
+        let config = Config::load(repository).unwrap();
+        let files = files
+            .iter()
+            .map(|(path, content)| {
+                let mut blob = GitrsObject::BlobObject(Blob::new(content.as_bytes().to_vec()));
+                (
+                    path.to_string(),
+                    ("100644".to_string(), blob.write(repository).unwrap()),
+                )
+            })
+            .collect();
+        let tree = Tree::write_files(repository, &files).unwrap();
+        write_commit(repository, &config, &tree, parents, "commit\n").unwrap()
+    }
+
+    #[test]
+    fn continue_leaves_local_changes_out() {
+        let worktree = env::temp_dir().join(format!("gitrs-merge-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        fs::write(
+            repository.gitdir.join("config"),
+            "[user]\n\tname = A U Thor\n\temail = author@example.com\n",
+        )
+        .unwrap();
+
+        let base = commit(&repository, &[("f", "a\n"), ("h", "h\n")], &[]);
+        let side = commit(
+            &repository,
+            &[("f", "a\nb\n"), ("h", "h\n")],
+            std::slice::from_ref(&base),
+        );
+        let head = commit(
+            &repository,
+            &[("f", "a\n"), ("g", "g\n"), ("h", "h\n")],
+            &[base],
+        );
+        Ref::create_at(&repository, &side, &["refs", "heads", "side"]).unwrap();
+        Ref::create_at(&repository, &head, &["refs", "heads", "master"]).unwrap();
+        fs::write(worktree.join("f"), "a\n").unwrap();
+        fs::write(worktree.join("g"), "g\n").unwrap();
+        // A local change the merge doesn't touch
+        fs::write(worktree.join("h"), "h\nlocal\n").unwrap();
+
+        let options = MergeOptions {
+            no_commit: true,
+            ..MergeOptions::default()
+        };
+        merge(&repository, &["side".to_string()], &options).unwrap();
+        let (hash, _) = continue_merge(&repository).unwrap();
+
+        let tree = Tree::of_commit(&repository, &hash).unwrap();
+        let files = diff::flatten(&repository, &tree, &Pathspec::default()).unwrap();
+        let content = |path: &str| {
+            Blob::read(&repository, &files[path].1)
+                .unwrap()
+                .data()
+                .to_vec()
+        };
+        assert_eq!(content("f"), b"a\nb\n");
+        assert_eq!(content("g"), b"g\n");
+        assert_eq!(content("h"), b"h\n");
+        assert_eq!(fs::read(worktree.join("h")).unwrap(), b"h\nlocal\n");
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+
+    #[test]
+    fn drivers_from_attributes() {
+        let worktree = env::temp_dir().join(format!("gitrs-merge-drivers-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        fs::write(
+            repository.gitdir.join("config"),
+            "[user]\n\tname = A U Thor\n\temail = author@example.com\n\
+             [merge \"theirs\"]\n\tdriver = cat %B > %A\n\
+             [merge \"fail\"]\n\tdriver = exit 1\n",
+        )
+        .unwrap();
+
+        let paths = ["u", "b", "o", "t", "f", "x"];
+        let attributes = "u merge=union\nb -merge\no merge=ours\nt merge=theirs\nf merge=fail\n";
+        let version = |line: &str| {
+            let mut files = vec![(".gitattributes", attributes.to_string())];
+            files.extend(paths.map(|path| (path, format!("a\n{}\nc\n", line))));
+            files
+        };
+        let commit_version = |line: &str, parents: &[String]| {
+            let files = version(line);
+            let files: Vec<_> = files.iter().map(|(p, c)| (*p, c.as_str())).collect();
+            commit(&repository, &files, parents)
+        };
+        let base = commit_version("b", &[]);
+        let side = commit_version("theirs", std::slice::from_ref(&base));
+        let head = commit_version("ours", &[base]);
+        Ref::create_at(&repository, &side, &["refs", "heads", "side"]).unwrap();
+        Ref::create_at(&repository, &head, &["refs", "heads", "master"]).unwrap();
+        for (path, content) in version("ours") {
+            fs::write(worktree.join(path), content).unwrap();
+        }
+
+        let merged = merge(&repository, &["side".to_string()], &MergeOptions::default()).unwrap();
+        let content = |path: &str| fs::read_to_string(worktree.join(path)).unwrap();
+        assert_eq!(content("u"), "a\nours\ntheirs\nc\n");
+        assert_eq!(content("b"), "a\nours\nc\n");
+        assert_eq!(content("o"), "a\nours\nc\n");
+        assert_eq!(content("t"), "a\ntheirs\nc\n");
+        assert_eq!(content("f"), "a\nours\nc\n");
+        assert!(content("x").contains("<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> side\n"));
+        let conflicts: Vec<_> = merged
+            .messages
+            .iter()
+            .filter_map(|message| message.strip_prefix("CONFLICT (content): Merge conflict in "))
+            .collect();
+        assert_eq!(conflicts, ["b", "f", "x"]);
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+
+    #[test]
+    fn refuses_during_other_operations() {
+        let worktree = env::temp_dir().join(format!("gitrs-merge-busy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        fs::write(
+            repository.gitdir.join("config"),
+            "[user]\n\tname = A U Thor\n\temail = author@example.com\n",
+        )
+        .unwrap();
+        let base = commit(&repository, &[("f", "a\n")], &[]);
+        let side = commit(&repository, &[("f", "b\n")], std::slice::from_ref(&base));
+        Ref::create_at(&repository, &side, &["refs", "heads", "side"]).unwrap();
+        Ref::create_at(&repository, &base, &["refs", "heads", "master"]).unwrap();
+        fs::write(worktree.join("f"), "a\n").unwrap();
+
+        for (state, message) in [
+            ("rebase-merge", "cannot merge while rebasing"),
+            (
+                "CHERRY_PICK_HEAD",
+                "You have not concluded your cherry-pick",
+            ),
+            ("REVERT_HEAD", "cannot merge while reverting"),
+        ] {
+            let path = repository.gitdir.join(state);
+            match state {
+                "rebase-merge" => fs::create_dir(&path).unwrap(),
+                _ => fs::write(&path, format!("{}\n", side)).unwrap(),
+            }
+            let error = merge(&repository, &["side".to_string()], &MergeOptions::default())
+                .err()
+                .unwrap();
+            assert!(error.to_string().starts_with(message), "{}", error);
+            assert!(!repository.gitdir.join("MERGE_HEAD").exists());
+            let _ = fs::remove_dir(&path);
+            let _ = fs::remove_file(&path);
+        }
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+}
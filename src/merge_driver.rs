@@ -0,0 +1,149 @@
+// Finds the merge driver for a path, which the path's `merge` attribute names, and runs it on the
+// three versions of a file both sides of a merge changed. The built-in text driver merges them
+// line by line, binary keeps our version and leaves a conflict, and union merges line by line
+// but takes the lines of both sides where they conflict, which suits files like changelogs.
+// Setting `merge` picks text, unsetting it picks binary, and leaving it unspecified picks
+// merge.default, or text. A driver named anything else is run as merge.<driver>.driver says, like
+// git does: through the shell from the top of the worktree, with %O, %A and %B replaced by files
+// holding the base, our and their versions, %L by the conflict marker size, %P by the path and %%
+// by a %. It leaves the result in the file of our version, and exits with a non-zero status if it
+// left conflicts. Git has no built-in ours driver, and is usually given `merge.ours.driver = true`
+// for one; here ours is built in for when no driver is configured, and keeps our version as it is.
+use std::fs;
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+
+use crate::attributes::{AttrValue, Attributes};
+use crate::config::Config;
+use crate::line_diff::Whitespace;
+use crate::merge_file::{self, ConflictStyle, FileMerge, Labels};
+use crate::repository::Repository;
+use crate::temporary::TempDir;
+
+// How long the conflict markers git writes are
+const MARKER_SIZE: usize = 7;
+
+pub enum Driver {
+    Text,
+    Binary,
+    Union,
+    Ours,
+    /// A program from merge.<driver>.driver
+    External {
+        name: String,
+        command: String,
+    },
+}
+
+/// Looks up the driver the `merge` attribute of `path` names
+pub fn driver(config: &Config, attributes: &mut Attributes, path: &str) -> Driver {
+    let name = match attributes.check(path).get("merge") {
+        Some(AttrValue::Set) => return Driver::Text,
+        Some(AttrValue::Unset) => return Driver::Binary,
+        Some(AttrValue::Value(name)) => name.clone(),
+        _ => match config.get("merge.default") {
+            Some(name) => name.to_string(),
+            None => return Driver::Text,
+        },
+    };
+    if let Some(command) = config.get(&format!("merge.{}.driver", name)) {
+        return Driver::External {
+            command: command.to_string(),
+            name,
+        };
+    }
+    // Like git, a driver that isn't defined merges as text
+    match name.as_str() {
+        "binary" => Driver::Binary,
+        "union" => Driver::Union,
+        "ours" => Driver::Ours,
+        _ => Driver::Text,
+    }
+}
+
+impl Driver {
+    /// Merges the changes `ours` and `theirs` made to `base` at `path`. Returns None where the
+    /// driver leaves our version as a conflict, as binary does and the others do for binary files.
+    pub fn merge(
+        &self,
+        repository: &Repository,
+        path: &str,
+        contents: [&[u8]; 3],
+        labels: &Labels,
+        style: ConflictStyle,
+        whitespace: Whitespace,
+    ) -> anyhow::Result<Option<FileMerge>> {
+        match self {
+            Driver::Text => Ok(merge_file::merge(contents, labels, style, whitespace)),
+            Driver::Binary => Ok(None),
+            Driver::Union => Ok(
+                merge_file::union(contents, style, whitespace).map(|content| FileMerge {
+                    content,
+                    conflicts: 0,
+                }),
+            ),
+            Driver::Ours => Ok(Some(FileMerge {
+                content: contents[1].to_vec(),
+                conflicts: 0,
+            })),
+            Driver::External { name, command } => {
+                run(repository, name, command, path, contents).map(Some)
+            }
+        }
+    }
+}
+
+// Runs the program of the driver `name` on the three versions in temporary files, returning what
+// it left in the one of our version, as a conflict if it failed
+fn run(
+    repository: &Repository,
+    name: &str,
+    command: &str,
+    path: &str,
+    contents: [&[u8]; 3],
+) -> anyhow::Result<FileMerge> {
+    let directory = TempDir::new("merge-driver")?;
+    let mut files = Vec::new();
+    for (version, content) in ["base", "ours", "theirs"].iter().zip(contents) {
+        let file = directory.path().join(version);
+        fs::write(&file, content).with_context(|| format!("Couldn't write {}", file.display()))?;
+        files.push(file);
+    }
+
+    let quote = |value: &str| format!("'{}'", value.replace('\'', "'\\''"));
+    let mut script = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            script.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('O') => script += &quote(&files[0].to_string_lossy()),
+            Some('A') => script += &quote(&files[1].to_string_lossy()),
+            Some('B') => script += &quote(&files[2].to_string_lossy()),
+            Some('L') => script += &MARKER_SIZE.to_string(),
+            Some('P') => script += &quote(path),
+            Some('%') => script.push('%'),
+            Some(other) => {
+                script.push('%');
+                script.push(other);
+            }
+            None => script.push('%'),
+        }
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .current_dir(&repository.worktree)
+        .stdin(Stdio::null())
+        .status()
+        .with_context(|| format!("Couldn't run merge driver {}", name))?;
+    let content = fs::read(&files[1])
+        .with_context(|| format!("Couldn't read what merge driver {} left", name))?;
+    Ok(FileMerge {
+        content,
+        conflicts: usize::from(!status.success()),
+    })
+}
@@ -0,0 +1,330 @@
+// Merges two versions of a file that were changed independently from a common base, line by line.
+// Each side is diffed against the base; changes only one side made are taken as they are, and
+// changes both sides made to the same lines (or right next to each other) are conflicts, unless
+// the sides made the same change. Conflicts are narrowed down to the lines the sides disagree on,
+// as git does, and written out between its markers. When whitespace is ignored, lines differing
+// only in it count as unchanged and are taken from our side. A union merge leaves no conflicts,
+// taking both sides' lines where they disagree instead.
+use crate::line_diff::{self, DiffOptions, Region, Whitespace};
+
+// git only looks this far into a file when deciding whether it is binary
+const BINARY_CHECK_SIZE: usize = 8000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConflictStyle {
+    /// Only show each side's version
+    Merge,
+    /// Show the base version between the sides too
+    Diff3,
+}
+
+/// The names shown on the conflict markers for each version
+pub struct Labels<'a> {
+    pub base: &'a str,
+    pub ours: &'a str,
+    pub theirs: &'a str,
+}
+
+// Conflicts separated by no more than this many unchanged lines are shown as one
+const MERGE_DISTANCE: usize = 3;
+
+// A run of lines in the merged file
+enum Piece<'a> {
+    /// Lines neither side changed, or both changed the same way
+    Unchanged(Vec<&'a [u8]>),
+    /// Lines changed by one side only
+    Changed(Vec<&'a [u8]>),
+    Conflict {
+        ours: Vec<&'a [u8]>,
+        base: Vec<&'a [u8]>,
+        theirs: Vec<&'a [u8]>,
+    },
+}
+
+pub struct FileMerge {
+    pub content: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Returns true if `data` looks binary to git, which is having a NUL byte near the start
+pub fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_SIZE)].contains(&0)
+}
+
+/// Merges the changes `ours` and `theirs` made to `base`, ignoring the changes to whitespace that
+/// `whitespace` says to. Returns None for binary files, which can't be merged.
+pub fn merge(
+    contents: [&[u8]; 3],
+    labels: &Labels,
+    style: ConflictStyle,
+    whitespace: Whitespace,
+) -> Option<FileMerge> {
+    let mut content = Vec::new();
+    let mut conflicts = 0;
+    for piece in &pieces(contents, style, whitespace)? {
+        match piece {
+            Piece::Unchanged(lines) | Piece::Changed(lines) => {
+                lines
+                    .iter()
+                    .for_each(|line| content.extend_from_slice(line));
+            }
+            Piece::Conflict { ours, base, theirs } => {
+                conflicts += 1;
+                write_conflict(&mut content, [ours, base, theirs], labels, style);
+            }
+        }
+    }
+    Some(FileMerge { content, conflicts })
+}
+
+/// Merges like `merge`, but where the sides conflict takes our lines followed by theirs, as git's
+/// union merge does
+pub fn union(
+    contents: [&[u8]; 3],
+    style: ConflictStyle,
+    whitespace: Whitespace,
+) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    for piece in &pieces(contents, style, whitespace)? {
+        match piece {
+            Piece::Unchanged(lines) | Piece::Changed(lines) => {
+                lines
+                    .iter()
+                    .for_each(|line| content.extend_from_slice(line));
+            }
+            Piece::Conflict { ours, theirs, .. } => {
+                ours.iter().for_each(|line| content.extend_from_slice(line));
+                // Their lines start a line of their own
+                if ours.last().is_some_and(|line| !line.ends_with(b"\n")) && !theirs.is_empty() {
+                    content.push(b'\n');
+                }
+                theirs
+                    .iter()
+                    .for_each(|line| content.extend_from_slice(line));
+            }
+        }
+    }
+    Some(content)
+}
+
+// Splits the merged file into pieces, narrowing down the conflicts unless the base is shown, or
+// returns None for binary files
+fn pieces<'a>(
+    [base, ours, theirs]: [&'a [u8]; 3],
+    style: ConflictStyle,
+    whitespace: Whitespace,
+) -> Option<Vec<Piece<'a>>> {
+    if [base, ours, theirs].iter().any(|data| is_binary(data)) {
+        return None;
+    }
+    let (base, ours, theirs) = (
+        line_diff::lines(base),
+        line_diff::lines(ours),
+        line_diff::lines(theirs),
+    );
+    let pieces = split(&base, &ours, &theirs, whitespace);
+    // Showing the base only makes sense for conflicts left as they are
+    Some(match style {
+        ConflictStyle::Merge => refine(pieces, whitespace),
+        ConflictStyle::Diff3 => pieces,
+    })
+}
+
+// Splits the merged file into the lines neither side changed (or both changed the same way), the
+// changes made by one side, and the conflicting ones
+fn split<'a>(
+    base: &[&'a [u8]],
+    ours: &[&'a [u8]],
+    theirs: &[&'a [u8]],
+    whitespace: Whitespace,
+) -> Vec<Piece<'a>> {
+    let options = DiffOptions {
+        whitespace,
+        ..Default::default()
+    };
+    let mut our_regions = line_diff::diff_lines(base, ours, &options)
+        .into_iter()
+        .peekable();
+    let mut their_regions = line_diff::diff_lines(base, theirs, &options)
+        .into_iter()
+        .peekable();
+    let mut pieces = Vec::new();
+    let mut position = 0;
+    // How far each side's lines have shifted from the base's by the changes taken so far
+    let (mut our_shift, mut their_shift) = (0isize, 0isize);
+    loop {
+        let start = match (our_regions.peek(), their_regions.peek()) {
+            (None, None) => break,
+            (Some(a), Some(b)) => a.old.start.min(b.old.start),
+            (Some(region), None) | (None, Some(region)) => region.old.start,
+        };
+
+        // Gather every region overlapping (or touching) the ones gathered so far
+        let mut end = start;
+        let (mut our_chunk, mut their_chunk) = (Vec::new(), Vec::new());
+        loop {
+            if let Some(region) = our_regions.next_if(|region| region.old.start <= end) {
+                end = end.max(region.old.end);
+                our_chunk.push(region);
+            } else if let Some(region) = their_regions.next_if(|region| region.old.start <= end) {
+                end = end.max(region.old.end);
+                their_chunk.push(region);
+            } else {
+                break;
+            }
+        }
+
+        pieces.push(Piece::Unchanged(
+            side(ours, position..start, &mut our_shift, &[]).to_vec(),
+        ));
+        let our_lines = side(ours, start..end, &mut our_shift, &our_chunk);
+        let their_lines = side(theirs, start..end, &mut their_shift, &their_chunk);
+        let same = |a: &[&[u8]], b: &[&[u8]]| {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|(a, b)| whitespace.normalize(a) == whitespace.normalize(b))
+        };
+        if same(our_lines, their_lines) {
+            pieces.push(Piece::Unchanged(our_lines.to_vec()));
+        } else if their_chunk.is_empty() {
+            pieces.push(Piece::Changed(our_lines.to_vec()));
+        } else if our_chunk.is_empty() {
+            pieces.push(Piece::Changed(their_lines.to_vec()));
+        } else {
+            pieces.push(Piece::Conflict {
+                ours: our_lines.to_vec(),
+                base: base[start..end].to_vec(),
+                theirs: their_lines.to_vec(),
+            });
+        }
+        position = end;
+    }
+    let end = base.len();
+    pieces.push(Piece::Unchanged(
+        side(ours, position..end, &mut our_shift, &[]).to_vec(),
+    ));
+    pieces
+}
+
+// Narrows conflicts down to the lines where the sides actually differ, as git does at its default
+// "zealous" level: the lines both sides agree on split a conflict in several, and conflicts left
+// at most MERGE_DISTANCE unchanged lines apart are joined back into one
+fn refine(pieces: Vec<Piece>, whitespace: Whitespace) -> Vec<Piece> {
+    let options = DiffOptions {
+        whitespace,
+        ..Default::default()
+    };
+    let mut refined = Vec::new();
+    for piece in pieces {
+        let Piece::Conflict { ours, theirs, .. } = &piece else {
+            refined.push(piece);
+            continue;
+        };
+        if ours.is_empty() || theirs.is_empty() {
+            refined.push(piece);
+            continue;
+        }
+        let mut position = (0, 0);
+        for region in line_diff::diff_lines(ours, theirs, &options) {
+            refined.push(Piece::Unchanged(
+                ours[position.0..region.old.start].to_vec(),
+            ));
+            refined.push(Piece::Conflict {
+                ours: ours[region.old.clone()].to_vec(),
+                base: Vec::new(),
+                theirs: theirs[region.new.clone()].to_vec(),
+            });
+            position = (region.old.end, region.new.end);
+        }
+        refined.push(Piece::Unchanged(ours[position.0..].to_vec()));
+    }
+
+    let mut joined: Vec<Piece> = Vec::new();
+    for piece in refined {
+        if let Piece::Unchanged(lines) = &piece {
+            match joined.last_mut() {
+                Some(Piece::Unchanged(previous)) => previous.extend(lines),
+                _ if lines.is_empty() => {}
+                _ => joined.push(piece),
+            }
+            continue;
+        }
+        if let Piece::Conflict { ours, theirs, .. } = &piece {
+            // Looks back past the unchanged lines for the previous conflict
+            let between = match joined.as_slice() {
+                [.., Piece::Conflict { .. }, Piece::Unchanged(lines)]
+                    if lines.len() <= MERGE_DISTANCE =>
+                {
+                    Some(lines.clone())
+                }
+                [.., Piece::Conflict { .. }] => Some(Vec::new()),
+                _ => None,
+            };
+            if let Some(between) = between {
+                if !between.is_empty() {
+                    joined.pop();
+                }
+                let Some(Piece::Conflict {
+                    ours: previous_ours,
+                    theirs: previous_theirs,
+                    ..
+                }) = joined.last_mut()
+                else {
+                    unreachable!("Matched a conflict");
+                };
+                previous_ours.extend(between.iter().chain(ours));
+                previous_theirs.extend(between.iter().chain(theirs));
+                continue;
+            }
+        }
+        joined.push(piece);
+    }
+    joined
+}
+
+// Returns one side's version of the base lines in `range`, given the side's regions within it,
+// and moves `shift` past them
+fn side<'a, 'b>(
+    lines: &'b [&'a [u8]],
+    range: std::ops::Range<usize>,
+    shift: &mut isize,
+    regions: &[Region],
+) -> &'b [&'a [u8]] {
+    let start = (range.start as isize + *shift) as usize;
+    *shift += regions
+        .iter()
+        .map(|region| region.new.len() as isize - region.old.len() as isize)
+        .sum::<isize>();
+    let end = (range.end as isize + *shift) as usize;
+    &lines[start..end]
+}
+
+fn write_conflict(
+    content: &mut Vec<u8>,
+    [ours, base, theirs]: [&[&[u8]]; 3],
+    labels: &Labels,
+    style: ConflictStyle,
+) {
+    let mut section = |marker: &str, label: &str, lines: &[&[u8]]| {
+        content.extend_from_slice(marker.as_bytes());
+        if !label.is_empty() {
+            content.push(b' ');
+            content.extend_from_slice(label.as_bytes());
+        }
+        content.push(b'\n');
+        lines
+            .iter()
+            .for_each(|line| content.extend_from_slice(line));
+        // Markers always start a line of their own
+        if lines.last().is_some_and(|line| !line.ends_with(b"\n")) {
+            content.push(b'\n');
+        }
+    };
+    section("<<<<<<<", labels.ours, ours);
+    if style == ConflictStyle::Diff3 {
+        section("|||||||", labels.base, base);
+    }
+    section("=======", "", theirs);
+    content.extend_from_slice(format!(">>>>>>> {}\n", labels.theirs).as_bytes());
+}
@@ -0,0 +1,195 @@
+// Names commits relative to the refs they can be reached from, eg. `master~2` or `tags/v1.0^2~1`.
+// Each ref's history is walked from its tip, and a commit keeps the best name found for it: names
+// from tags win over others, then names closer to their tip. Going through a merge's second or
+// later parent counts as much further than following first parents.
+use std::collections::HashMap;
+
+use crate::object::GitrsObject;
+use crate::refs::Ref;
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::wildmatch::wildmatch;
+
+const MERGE_TRAVERSAL_WEIGHT: usize = 65535;
+
+struct RevName {
+    // Name of the ref, or of the merge this commit's branch was reached through
+    tip_name: String,
+    // First-parent steps from `tip_name`
+    generation: usize,
+    distance: usize,
+    from_tag: bool,
+    tagger_date: i64,
+}
+
+pub struct NameRev {
+    names: HashMap<String, RevName>,
+}
+
+impl RevName {
+    fn name(&self) -> String {
+        if self.generation == 0 {
+            return self.tip_name.clone();
+        }
+        let tip_name = self.tip_name.strip_suffix("^0").unwrap_or(&self.tip_name);
+        format!("{}~{}", tip_name, self.generation)
+    }
+
+    fn is_better_than(&self, other: &RevName) -> bool {
+        if self.from_tag && other.from_tag {
+            return other.tagger_date > self.tagger_date
+                || (other.tagger_date == self.tagger_date && other.distance > self.distance);
+        }
+        if self.from_tag != other.from_tag {
+            return self.from_tag;
+        }
+        if self.distance != other.distance {
+            return other.distance > self.distance;
+        }
+        other.generation > self.generation
+    }
+}
+
+impl NameRev {
+    /// Names the commits reachable from the refs matching any of `patterns` (all refs if there are
+    /// none), only using tags if `tags_only` is set
+    pub fn new(
+        repository: &Repository,
+        tags_only: bool,
+        patterns: &[String],
+    ) -> anyhow::Result<Self> {
+        let mut name_rev = Self {
+            names: HashMap::new(),
+        };
+
+        for (ref_name, hash) in Ref::list(repository)? {
+            let from_tag = ref_name.starts_with("refs/tags/");
+            if (tags_only && !from_tag)
+                || (!patterns.is_empty() && !patterns.iter().any(|p| Self::matches(p, &ref_name)))
+            {
+                continue;
+            }
+
+            let (commit, tagger_date, peeled) = Self::peel(repository, &hash)?;
+            let Some(commit) = commit else {
+                continue;
+            };
+            let mut tip_name = ref_name
+                .strip_prefix("refs/heads/")
+                .or(ref_name.strip_prefix("refs/"))
+                .unwrap_or(&ref_name)
+                .to_string();
+            if peeled {
+                tip_name.push_str("^0");
+            }
+            let tagger_date = match tagger_date {
+                Some(date) => date,
+                None => {
+                    revwalk::read_commit(repository, &commit)?
+                        .committer()?
+                        .timestamp
+                }
+            };
+
+            name_rev.name_from(
+                repository,
+                commit,
+                RevName {
+                    tip_name,
+                    generation: 0,
+                    distance: 0,
+                    from_tag,
+                    tagger_date,
+                },
+            )?;
+        }
+
+        Ok(name_rev)
+    }
+
+    // Matches a --refs pattern against the full ref name or any trailing part of it, so that
+    // `master` matches refs/heads/master
+    fn matches(pattern: &str, ref_name: &str) -> bool {
+        wildmatch(pattern, ref_name)
+            || ref_name
+                .match_indices('/')
+                .any(|(idx, _)| wildmatch(pattern, &ref_name[idx + 1..]))
+    }
+
+    // Follows tags to the commit they point to, returning the date of the outermost tag and
+    // whether any tag was peeled. Refs to trees and blobs have no commit.
+    fn peel(
+        repository: &Repository,
+        hash: &str,
+    ) -> anyhow::Result<(Option<String>, Option<i64>, bool)> {
+        let mut hash = hash.to_string();
+        let mut tagger_date = None;
+        let mut peeled = false;
+        loop {
+            match GitrsObject::read(repository, &hash)? {
+                GitrsObject::CommitObject(_) => return Ok((Some(hash), tagger_date, peeled)),
+                GitrsObject::TagObject(tag) => {
+                    let Some(object) = tag.object() else {
+                        return Ok((None, tagger_date, peeled));
+                    };
+                    if !peeled {
+                        tagger_date = tag.tagger().map(|ident| ident.timestamp);
+                    }
+                    hash = object.to_string();
+                    peeled = true;
+                }
+                _ => return Ok((None, tagger_date, peeled)),
+            }
+        }
+    }
+
+    fn name_from(
+        &mut self,
+        repository: &Repository,
+        tip: String,
+        name: RevName,
+    ) -> anyhow::Result<()> {
+        let mut stack = vec![(tip, name)];
+        while let Some((hash, name)) = stack.pop() {
+            if self
+                .names
+                .get(&hash)
+                .is_some_and(|existing| !name.is_better_than(existing))
+            {
+                continue;
+            }
+
+            let commit = revwalk::read_commit(repository, &hash)?;
+            // Pushed in reverse so the first parent is named first
+            for (idx, parent) in commit.parents().iter().enumerate().rev() {
+                let parent_name = if idx == 0 {
+                    RevName {
+                        tip_name: name.tip_name.clone(),
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        from_tag: name.from_tag,
+                        tagger_date: name.tagger_date,
+                    }
+                } else {
+                    let base = name.name();
+                    let base = base.strip_suffix("^0").unwrap_or(&base);
+                    RevName {
+                        tip_name: format!("{}^{}", base, idx + 1),
+                        generation: 0,
+                        distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                        from_tag: name.from_tag,
+                        tagger_date: name.tagger_date,
+                    }
+                };
+                stack.push((parent.clone(), parent_name));
+            }
+            self.names.insert(hash, name);
+        }
+        Ok(())
+    }
+
+    /// The name of the commit with the given hash, if it is reachable from any of the refs
+    pub fn name(&self, hash: &str) -> Option<String> {
+        self.names.get(hash).map(RevName::name)
+    }
+}
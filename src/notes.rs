@@ -0,0 +1,191 @@
+// Notes attach extra text to objects without changing them. A notes ref (refs/notes/commits by
+// default) points to a commit whose tree holds one blob per annotated object, named after the
+// object's hash. Git splits large notes trees into fanout directories named after the first
+// characters of the hash, which are understood when reading but never written. Notes also serve
+// as a cache, whose commit has no parents and is only trusted while its message is the same.
+use std::collections::BTreeMap;
+
+use anyhow::{Context, bail};
+
+use crate::config::Config;
+use crate::ident::Ident;
+use crate::object::blob::Blob;
+use crate::object::commit::Commit;
+use crate::object::tree::{Leaf, Tree};
+use crate::object::{GitrsObject, ObjectType};
+use crate::refs::Ref;
+use crate::repository::Repository;
+
+pub const DEFAULT_NOTES_REF: &str = "refs/notes/commits";
+
+pub struct Notes<'a> {
+    repository: &'a Repository,
+    ref_name: String,
+    // Commit the notes ref currently points to, if it exists
+    tip: Option<String>,
+    // Note blob hashes keyed by the hash of the object they annotate
+    notes: BTreeMap<String, String>,
+}
+
+impl<'a> Notes<'a> {
+    /// Loads the notes from `ref_name`, or from core.notesRef (falling back to refs/notes/commits)
+    /// if not given
+    pub fn load(
+        repository: &'a Repository,
+        config: &Config,
+        ref_name: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let ref_name = ref_name
+            .or_else(|| config.get("core.notesRef"))
+            .unwrap_or(DEFAULT_NOTES_REF);
+        let ref_name = if ref_name.starts_with("refs/") {
+            ref_name.to_string()
+        } else {
+            format!("refs/notes/{}", ref_name)
+        };
+
+        let tip = Ref::try_resolve(repository, &ref_name)?;
+
+        let mut notes = BTreeMap::new();
+        if let Some(tip) = &tip {
+            let tree = Tree::of_commit(repository, tip)?;
+            Self::read_tree(repository, &tree, "", &mut notes)?;
+        }
+
+        Ok(Self {
+            repository,
+            ref_name,
+            tip,
+            notes,
+        })
+    }
+
+    /// Loads the cache kept in notes on `ref_name`, which only holds while the message of its
+    /// commit is `validity`. A cache made for anything else is started over.
+    pub fn load_cache(
+        repository: &'a Repository,
+        config: &Config,
+        ref_name: &str,
+        validity: &str,
+    ) -> anyhow::Result<Self> {
+        let mut cache = Self::load(repository, config, Some(ref_name))?;
+        if let Some(tip) = &cache.tip {
+            let GitrsObject::CommitObject(commit) = GitrsObject::read(repository, tip)? else {
+                bail!("Expected a commit object: {}", tip);
+            };
+            if commit.subject().trim() != validity {
+                cache.notes.clear();
+            }
+        }
+        Ok(cache)
+    }
+
+    // Collects the notes in `tree`, descending into fanout directories
+    fn read_tree(
+        repository: &Repository,
+        tree: &Tree,
+        prefix: &str,
+        notes: &mut BTreeMap<String, String>,
+    ) -> anyhow::Result<()> {
+        for record in &tree.records {
+            let name = format!("{}{}", prefix, record.path.to_string_lossy());
+            match Leaf::get_type_from_mode(&record.file_mode) {
+                ObjectType::Tree => {
+                    let GitrsObject::TreeObject(subtree) =
+                        GitrsObject::read(repository, &record.hash)?
+                    else {
+                        bail!("Expected a tree object: {}", record.hash);
+                    };
+                    Self::read_tree(repository, &subtree, &name, notes)?;
+                }
+                _ => {
+                    notes.insert(name, record.hash.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Note blob hashes keyed by the hash of the object they annotate
+    pub fn list(&self) -> &BTreeMap<String, String> {
+        &self.notes
+    }
+
+    /// Returns the note attached to `object`, if any
+    pub fn get(&self, object: &str) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .get_data(object)?
+            .map(|data| String::from_utf8_lossy(&data).into_owned()))
+    }
+
+    /// Returns the content of the note attached to `object` as is, if any
+    pub fn get_data(&self, object: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(blob_hash) = self.notes.get(object) else {
+            return Ok(None);
+        };
+        match GitrsObject::read(self.repository, blob_hash)? {
+            GitrsObject::BlobObject(blob) => Ok(Some(blob.data().to_vec())),
+            _ => bail!("Expected a blob object for the note: {}", blob_hash),
+        }
+    }
+
+    /// Attaches `note` to `object`, replacing any existing note
+    pub fn set(&mut self, object: &str, note: &str) -> anyhow::Result<()> {
+        self.set_data(object, note.as_bytes())
+    }
+
+    /// Attaches a note with the content `data` to `object`, replacing any existing note
+    pub fn set_data(&mut self, object: &str, data: &[u8]) -> anyhow::Result<()> {
+        let blob_hash = GitrsObject::BlobObject(Blob::new(data.to_vec())).write(self.repository)?;
+        self.notes.insert(object.to_string(), blob_hash);
+        Ok(())
+    }
+
+    /// Removes the note attached to `object`, returning false if there was none
+    pub fn remove(&mut self, object: &str) -> bool {
+        self.notes.remove(object).is_some()
+    }
+
+    /// Records the current notes in a new commit on the notes ref
+    pub fn commit(&mut self, config: &Config, message: &str) -> anyhow::Result<String> {
+        let parents: Vec<String> = self.tip.iter().cloned().collect();
+        self.write_commit(config, &parents, message)
+    }
+
+    /// Records a cache loaded with `load_cache` in a commit of its own, `validity` being its
+    /// message
+    pub fn commit_cache(&mut self, config: &Config, validity: &str) -> anyhow::Result<String> {
+        self.write_commit(config, &[], validity)
+    }
+
+    fn write_commit(
+        &mut self,
+        config: &Config,
+        parents: &[String],
+        message: &str,
+    ) -> anyhow::Result<String> {
+        let tree = Tree {
+            records: self
+                .notes
+                .iter()
+                .map(|(object, blob_hash)| Leaf {
+                    file_mode: "100644".to_string(),
+                    path: object.into(),
+                    hash: blob_hash.clone(),
+                })
+                .collect(),
+        };
+        let tree_hash = GitrsObject::TreeObject(tree).write(self.repository)?;
+
+        let author = Ident::author(config)?;
+        let committer = Ident::committer(config)?;
+        let commit = Commit::new(&tree_hash, parents, &author, &committer, message);
+        let hash = GitrsObject::CommitObject(commit).write(self.repository)?;
+
+        let ref_parts: Vec<&str> = self.ref_name.split('/').collect();
+        Ref::create_at(self.repository, &hash, &ref_parts)
+            .with_context(|| format!("Couldn't update {}", self.ref_name))?;
+        self.tip = Some(hash.clone());
+        Ok(hash)
+    }
+}
@@ -9,12 +9,18 @@ use std::fs::{self, File};
 use std::io::{BufReader, Read};
 use std::str::{FromStr, from_utf8};
 
-use anyhow::anyhow;
+use anyhow::{Context, anyhow, bail};
+use flate2::Compression;
 use flate2::bufread::ZlibDecoder;
 use sha1::{Digest, Sha1};
 
-use crate::refs::Ref;
+use crate::branch;
+use crate::config::Config;
+use crate::date;
+use crate::pack::{self, PackIndex};
+use crate::refs::{self, Ref};
 use crate::repository::Repository;
+use crate::trace;
 use blob::Blob;
 use commit::Commit;
 use error::ObjectError;
@@ -22,14 +28,52 @@ use tag::Tag;
 use tree::Tree;
 
 /////////////////////////////////////
-///Object Representation
+// Object Representation
 /////////////////////////////////////
 
+/// Level loose objects and binary patches are compressed with, from core.looseCompression or else
+/// core.compression. Like git, the default is the fastest level, and -1 stands for zlib's own
+/// default.
+pub fn loose_compression(repository: &Repository) -> anyhow::Result<Compression> {
+    let config = Config::load(repository)?;
+    match config
+        .get("core.looseCompression")
+        .or_else(|| config.get("core.compression"))
+    {
+        Some(value) => compression_level(value),
+        None => Ok(Compression::fast()),
+    }
+}
+
+/// Level packs are compressed with, from pack.compression or else core.compression, zlib's own
+/// default if neither is set
+pub fn pack_compression(repository: &Repository) -> anyhow::Result<Compression> {
+    let config = Config::load(repository)?;
+    match config
+        .get("pack.compression")
+        .or_else(|| config.get("core.compression"))
+    {
+        Some(value) => compression_level(value),
+        None => Ok(Compression::default()),
+    }
+}
+
+fn compression_level(value: &str) -> anyhow::Result<Compression> {
+    match value.parse::<i32>() {
+        Ok(-1) => Ok(Compression::default()),
+        Ok(level @ 0..=9) => Ok(Compression::new(level as u32)),
+        _ => bail!("Bad zlib compression level {}", value),
+    }
+}
+
 pub trait Object {
     fn serialize(&mut self) -> Vec<u8>;
-    fn deserialize(data: &[u8]) -> Self;
+    fn deserialize(data: &[u8]) -> anyhow::Result<Self>
+    where
+        Self: Sized;
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum GitrsObject {
     BlobObject(Blob),
     CommitObject(Commit),
@@ -37,7 +81,7 @@ pub enum GitrsObject {
     TreeObject(Tree),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ObjectType {
     Blob,
     Commit,
@@ -104,47 +148,79 @@ impl GitrsObject {
         }
     }
 
-    pub fn deserialize(data: &[u8], object_type: &str) -> Self {
-        match ObjectType::try_from(object_type).unwrap() {
-            ObjectType::Blob => Self::BlobObject(Blob::deserialize(data)),
-            ObjectType::Commit => Self::CommitObject(Commit::deserialize(data)),
-            ObjectType::Tag => Self::TagObject(Tag::deserialize(data)),
-            ObjectType::Tree => Self::TreeObject(Tree::deserialize(data)),
-        }
+    pub fn deserialize(data: &[u8], object_type: &str) -> anyhow::Result<Self> {
+        Ok(match ObjectType::try_from(object_type)? {
+            ObjectType::Blob => Self::BlobObject(Blob::deserialize(data)?),
+            ObjectType::Commit => Self::CommitObject(Commit::deserialize(data)?),
+            ObjectType::Tag => Self::TagObject(Tag::deserialize(data)?),
+            ObjectType::Tree => Self::TreeObject(Tree::deserialize(data)?),
+        })
     }
 
     pub fn deserialize_and_write(
         repository: &Repository,
         data: &[u8],
         object_type: ObjectType,
-    ) -> String {
-        Self::deserialize(data, object_type.to_string().as_str()).write(repository)
+    ) -> anyhow::Result<String> {
+        Self::deserialize(data, object_type.to_string().as_str())?.write(repository)
     }
 
-    /// Read and parse the object specified by `sha` in the given repository
+    /// Read and parse the object specified by `sha` in the given repository, or the object
+    /// replacing it under refs/replace
     pub fn read(repository: &Repository, sha: &str) -> anyhow::Result<Self> {
-        let path = repository
-            .get_path_to_file(&["objects", &sha[..2], &sha[2..]])
-            .ok_or_else(|| anyhow!("Object file does not exist"))?;
+        Self::read_raw(repository, &Self::replacement(repository, sha)?)
+    }
+
+    // Follows refs/replace from `sha`, returning the hash of the object that should be read in its
+    // place
+    fn replacement(repository: &Repository, sha: &str) -> anyhow::Result<String> {
+        // Same limit as git, so replacement cycles can't loop forever
+        const MAX_REPLACE_DEPTH: usize = 5;
+
+        let mut sha = sha.to_string();
+        if !repository.replace_objects {
+            return Ok(sha);
+        }
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match Ref::try_resolve(repository, &format!("refs/replace/{}", sha))? {
+                Some(replacement) => sha = replacement,
+                None => return Ok(sha),
+            }
+        }
+        Err(anyhow!("Replace depth too high for object {}", sha))
+    }
+
+    /// Read the object specified by `sha`, ignoring replacements
+    pub fn read_raw(repository: &Repository, sha: &str) -> anyhow::Result<Self> {
+        let (object_type, data) = Self::read_data(repository, sha)?;
+        Self::deserialize(&data, &object_type.to_string())
+            .with_context(|| format!("Malformed {} {}", object_type, sha))
+    }
+
+    /// Reads the type and contents of the object specified by `sha`, loose or packed, ignoring
+    /// replacements
+    pub fn read_data(repository: &Repository, sha: &str) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+        let _region = trace::region("read object");
+        let Some(path) = repository.get_path_to_file(&["objects", &sha[..2], &sha[2..]]) else {
+            return pack::find(repository, sha)?
+                .ok_or_else(|| anyhow!("Object file does not exist"));
+        };
 
         // Decompressing object (header + contents)
-        let file = File::open(path).expect("Could not open file");
+        let file =
+            File::open(&path).with_context(|| format!("Could not open {}", path.display()))?;
         let buf_reader = BufReader::new(file);
         let mut decoder = ZlibDecoder::new(buf_reader);
         let mut decompressed_data = Vec::new();
         decoder.read_to_end(&mut decompressed_data)?;
 
-        // hex dump
-        print!("Raw object");
-        Self::dump(&decompressed_data);
-
         // Extract the object type
         let obj_type_end_idx = decompressed_data
             .iter()
             .position(|&byte| byte == b' ')
             .ok_or_else(|| anyhow!("Malformed object: Missing space in header"))?;
 
-        let object_type_str = from_utf8(&decompressed_data[..obj_type_end_idx])?;
+        let object_type = ObjectType::try_from(from_utf8(&decompressed_data[..obj_type_end_idx])?)?;
 
         // Extract the object size
         let obj_size_end_idx = decompressed_data[obj_type_end_idx..]
@@ -159,8 +235,8 @@ impl GitrsObject {
         let expected_length = decompressed_data.len() - (obj_size_end_idx + 1);
 
         if object_size == expected_length {
-            let object_data = &decompressed_data[obj_size_end_idx + 1..];
-            Ok(Self::deserialize(object_data, object_type_str))
+            decompressed_data.drain(..obj_size_end_idx + 1);
+            Ok((object_type, decompressed_data))
         } else {
             Err(anyhow!(
                 "Malformed object {}: Bad length - actual {} expected {}",
@@ -172,42 +248,42 @@ impl GitrsObject {
     }
 
     /// Write the current object to the repository
-    pub fn write(&mut self, repository: &Repository) -> String {
-        let data = self.serialize();
-
-        let header = format!("{}\x20{}\x00", self.get_type(), data.len());
-        let mut payload = header.into_bytes();
-        payload.extend(data);
-
-        // Compute SHA-1 hash
-        let sha = {
-            let mut hasher = Sha1::new();
-            hasher.update(&payload);
-            hex::encode(hasher.finalize()) // SHA-1 produces a 160-bit hash
-        };
+    pub fn write(&mut self, repository: &Repository) -> anyhow::Result<String> {
+        let _region = trace::region("write object");
+        let (sha, payload) = self.encode();
+        let level = loose_compression(repository)?;
 
         repository
-            .upsert_file(&["objects", &sha[..2], &sha[2..]], &payload)
-            .expect("Could not write object file");
+            .upsert_file(&["objects", &sha[..2], &sha[2..]], &payload, level)
+            .with_context(|| format!("Could not write object {}", sha))?;
 
-        sha
+        Ok(sha)
+    }
+
+    /// Compute the hash of the current object, without writing it to the repository
+    pub fn hash(&mut self) -> String {
+        self.encode().0
     }
 
     pub fn find(repository: &Repository, name: &str) -> anyhow::Result<String> {
         let shas = Self::resolve(repository, name)?;
         match shas.len() {
             0 => Err(anyhow!("Couldn't find object with name: {}", name)),
-            1 => Ok(shas.get(0).unwrap().clone()),
-            _ => Err(anyhow!(
-                "Ambigious reference ({}). Candidates are: {:?}",
-                name,
-                shas
-            )),
+            1 => Ok(shas.first().unwrap().clone()),
+            _ => {
+                // Git lists the candidates before failing
+                eprintln!("error: short object ID {} is ambiguous", name);
+                eprintln!("hint: The candidates are:");
+                for candidate in Self::describe_candidates(repository, &shas) {
+                    eprintln!("hint:   {}", candidate);
+                }
+                Err(anyhow!("Not a valid object name {}", name))
+            }
         }
     }
 
     // Hex dump
-    pub fn dump(buf: &Vec<u8>) {
+    pub fn dump(buf: &[u8]) {
         for (i, byte) in buf.iter().enumerate() {
             if i % 16 == 0 {
                 print!("\n{:08x}: ", i);
@@ -217,54 +293,227 @@ impl GitrsObject {
         println!();
     }
 
+    /// Writes an object of the given type with exactly `data` as its contents, returning its hash
+    pub fn write_data(
+        repository: &Repository,
+        object_type: ObjectType,
+        data: &[u8],
+    ) -> anyhow::Result<String> {
+        let _region = trace::region("write object");
+        let (sha, payload) = Self::encode_data(object_type, data);
+        let level = loose_compression(repository)?;
+
+        repository
+            .upsert_file(&["objects", &sha[..2], &sha[2..]], &payload, level)
+            .ok_or_else(|| anyhow!("Could not write object {}", sha))?;
+        Ok(sha)
+    }
+
+    /// Compute the hash of an object of the given type with `data` as its contents
+    pub fn hash_data(object_type: ObjectType, data: &[u8]) -> String {
+        Self::encode_data(object_type, data).0
+    }
+
+    /// Whether the object `sha` is in the repository, loose or packed
+    pub fn exists(repository: &Repository, sha: &str) -> anyhow::Result<bool> {
+        if repository
+            .get_path_to_file(&["objects", &sha[..2], &sha[2..]])
+            .is_some()
+        {
+            return Ok(true);
+        }
+        Ok(PackIndex::load_all(repository)?
+            .iter()
+            .any(|index| index.contains(sha)))
+    }
+
+    // Prepends the header to the serialized object, returning the payload alongside its hash
+    fn encode(&mut self) -> (String, Vec<u8>) {
+        let data = self.serialize();
+        Self::encode_data(self.get_type(), &data)
+    }
+
+    fn encode_data(object_type: ObjectType, data: &[u8]) -> (String, Vec<u8>) {
+        let header = format!("{}\x20{}\x00", object_type, data.len());
+        let mut payload = header.into_bytes();
+        payload.extend(data);
+
+        // Compute SHA-1 hash
+        let sha = {
+            let mut hasher = Sha1::new();
+            hasher.update(&payload);
+            hex::encode(hasher.finalize()) // SHA-1 produces a 160-bit hash
+        };
+
+        (sha, payload)
+    }
+
     /// Resolves a human-readable name to an object hash
     fn resolve(repository: &Repository, name: &str) -> anyhow::Result<Vec<String>> {
+        if let Some(upstream) = branch::resolve_upstream(repository, name)? {
+            return Ok(vec![upstream]);
+        }
+        if let Some(previous) = branch::resolve_previous(repository, name)? {
+            return Ok(vec![previous]);
+        }
+
         match name {
             _ if name.trim().is_empty() => {
                 Err(anyhow!("Cannot resolve empty string as object name"))
             }
             "HEAD" => Ok(vec![Ref::resolve(repository, &["HEAD"])?]),
-            hash if hash.chars().all(|c| c.is_digit(16)) => {
-                let dir = &hash.to_lowercase()[..2];
-
-                // Read objects
-                let obj_path = repository
-                    .get_path_to_dir(&["objects", &dir])
-                    .ok_or_else(|| anyhow!("Object dir doesn't exist: objects/{}", dir))?;
-                let obj_name_prefix = &hash.to_lowercase()[2..];
-                let objs = fs::read_dir(obj_path)?;
-
-                Ok(objs.fold(Vec::new(), |mut acc, entry_res| {
-                    if let Ok(entry) = entry_res {
-                        let file_name = entry.file_name().to_string_lossy().into_owned();
-                        if file_name.starts_with(obj_name_prefix) {
-                            acc.push(format!("{}{}", dir, file_name.to_string()));
-                        }
-                    }
-                    acc
-                }))
+            pseudo_ref if refs::PSEUDO_REFS.contains(&pseudo_ref) => {
+                Ok(Ref::try_resolve(repository, pseudo_ref)?
+                    .into_iter()
+                    .collect())
             }
+            hash if hash.len() == 40 && is_hex(hash) => Self::find_prefix(repository, hash),
             _ => {
-                // eg. master, v10.4, etc.
-                let mut candidates = Vec::new();
-
-                // Read references (tags)
-                if let Some(tag) = Ref::resolve(repository, &["refs", "tags", name]).ok() {
-                    candidates.push(tag);
+                // Like git, refs are looked for in this order, eg. master, v10.4, origin/master,
+                // the first found being the one meant
+                let rules = [
+                    name.strip_prefix("refs/").map(|_| name.to_string()),
+                    Some(format!("refs/{}", name)),
+                    Some(format!("refs/tags/{}", name)),
+                    Some(format!("refs/heads/{}", name)),
+                    Some(format!("refs/remotes/{}", name)),
+                    Some(format!("refs/remotes/{}/HEAD", name)),
+                ];
+                let mut found = Vec::new();
+                for path in rules.into_iter().flatten() {
+                    if let Some(hash) = Ref::try_resolve(repository, &path)? {
+                        found.push(hash);
+                    }
                 }
-
-                // Find local branches
-                if let Some(branch) = Ref::resolve(repository, &["refs", "heads", name]).ok() {
-                    candidates.push(branch);
+                if found.len() > 1 {
+                    eprintln!("warning: refname '{}' is ambiguous.", name);
+                }
+                if let Some(hash) = found.into_iter().next() {
+                    return Ok(vec![hash]);
                 }
 
-                // Find remote branches
-                if let Some(remote) = Ref::resolve(repository, &["refs", "remotes", name]).ok() {
-                    candidates.push(remote);
+                // Then as git does, a name that is no ref can be an abbreviated hash of at least
+                // 4 digits
+                match name.len() >= 4 && is_hex(name) {
+                    true => Self::find_prefix(repository, name),
+                    false => Ok(Vec::new()),
                 }
+            }
+        }
+    }
 
-                Ok(candidates)
+    // Finds the objects whose hashes start with `prefix`, loose then packed
+    fn find_prefix(repository: &Repository, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = prefix.to_lowercase();
+        let dir = &prefix[..2];
+
+        let mut shas = Vec::new();
+        if let Some(obj_path) = repository.get_path_to_dir(&["objects", dir]) {
+            let obj_name_prefix = &prefix[2..];
+            for entry in fs::read_dir(obj_path)?.filter_map(Result::ok) {
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                if file_name.starts_with(obj_name_prefix) {
+                    shas.push(format!("{}{}", dir, file_name));
+                }
             }
         }
+        for index in PackIndex::load_all(repository)? {
+            let packed = index
+                .objects()
+                .iter()
+                .filter(|sha| sha.starts_with(&prefix));
+            shas.extend(packed.cloned());
+        }
+        shas.sort();
+        shas.dedup();
+        Ok(shas)
+    }
+
+    // Like git, describes each object an abbreviated hash could mean, tags first, then commits,
+    // trees and blobs
+    fn describe_candidates(repository: &Repository, shas: &[String]) -> Vec<String> {
+        let mut candidates: Vec<_> = shas
+            .iter()
+            .map(|sha| {
+                let short = &sha[..7];
+                match Self::read_raw(repository, sha) {
+                    Ok(GitrsObject::TagObject(tag)) => (
+                        0,
+                        format!("{} tag {}", short, tag.name().unwrap_or_default()),
+                    ),
+                    Ok(GitrsObject::CommitObject(commit)) => {
+                        let date = commit
+                            .author()
+                            .ok()
+                            .and_then(|author| {
+                                date::format(author.timestamp, &author.timezone, "short").ok()
+                            })
+                            .unwrap_or_default();
+                        (
+                            1,
+                            format!("{} commit {} - {}", short, date, commit.subject()),
+                        )
+                    }
+                    Ok(GitrsObject::TreeObject(_)) => (2, format!("{} tree", short)),
+                    Ok(GitrsObject::BlobObject(_)) => (3, format!("{} blob", short)),
+                    Err(_) => (4, format!("{} [bad object]", short)),
+                }
+            })
+            .collect();
+        candidates.sort();
+        candidates
+            .into_iter()
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+}
+
+fn is_hex(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn find_names() {
+        let worktree = env::temp_dir().join(format!("gitrs-object-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        // Blobs whose hashes share their first 4 digits, as git hash-object gives them
+        let first = GitrsObject::write_data(&repository, ObjectType::Blob, b"x70\n").unwrap();
+        let second = GitrsObject::write_data(&repository, ObjectType::Blob, b"x167\n").unwrap();
+        assert_eq!(first, "9a803dc629a13e51c87a0c6737a52cc340115caa");
+        assert_eq!(second, "9a80961be0d8f67a543838ff8790ffdb7f772014");
+
+        let find = |name: &str| GitrsObject::find(&repository, name).ok();
+        assert_eq!(find("9a803"), Some(first.clone()));
+        assert_eq!(find(&second[..7]), Some(second.clone()));
+        assert_eq!(find("9a80"), None);
+        // Fewer than 4 digits are no abbreviated hash
+        assert_eq!(find("9a8"), None);
+        assert_eq!(find("9"), None);
+
+        // Refs come first, even if their names could be abbreviated hashes
+        let write_ref = |name: &str, hash: &str| {
+            let path = repository.gitdir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("{}\n", hash)).unwrap();
+        };
+        write_ref("refs/heads/a", &first);
+        write_ref("refs/tags/9a80", &second);
+        assert_eq!(find("a"), Some(first.clone()));
+        assert_eq!(find("9a80"), Some(second.clone()));
+        // With a tag and a branch of the same name, the tag is meant
+        write_ref("refs/heads/9a80", &first);
+        assert_eq!(find("9a80"), Some(second.clone()));
+        assert_eq!(find("heads/9a80"), Some(first.clone()));
+        assert_eq!(find("refs/heads/9a80"), Some(first));
+
+        fs::remove_dir_all(&worktree).unwrap();
     }
 }
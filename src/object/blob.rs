@@ -1,19 +1,40 @@
 // Represents a blob object type. This is used to store user files being tracked by gitrs.
 
-use crate::object::Object;
+use anyhow::bail;
+
+use crate::object::{GitrsObject, Object};
+use crate::repository::Repository;
 
 pub struct Blob {
     data: Vec<u8>,
 }
 
+impl Blob {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Reads the blob with the given hash, failing if it is some other kind of object
+    pub fn read(repository: &Repository, hash: &str) -> anyhow::Result<Self> {
+        match GitrsObject::read(repository, hash)? {
+            GitrsObject::BlobObject(blob) => Ok(blob),
+            _ => bail!("Expected a blob object: {}", hash),
+        }
+    }
+}
+
 impl Object for Blob {
     fn serialize(&mut self) -> Vec<u8> {
         self.data.clone()
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
+    fn deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
             data: data.to_vec(),
-        }
+        })
     }
 }
@@ -1,3 +1,6 @@
+use anyhow::ensure;
+
+use crate::ident::Ident;
 use crate::{kvlm::Kvlm, object::Object};
 
 pub struct Commit {
@@ -9,14 +12,49 @@ impl Object for Commit {
         self.kvlm.serialize()
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
-            kvlm: Kvlm::new(data),
-        }
+    fn deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        let kvlm = Kvlm::new(data)?;
+        ensure!(
+            kvlm.get_key("tree").is_some_and(|trees| !trees.is_empty()),
+            "Commit has no tree"
+        );
+        Ok(Self { kvlm })
     }
 }
 
 impl Commit {
+    pub fn new(
+        tree: &str,
+        parents: &[String],
+        author: &Ident,
+        committer: &Ident,
+        message: &str,
+    ) -> Self {
+        let mut kvlm = Kvlm::init();
+        kvlm.insert("tree", tree);
+        for parent in parents {
+            kvlm.append("parent", parent);
+        }
+        kvlm.insert("author", &author.to_string());
+        kvlm.insert("committer", &committer.to_string());
+        kvlm.set_message(message);
+
+        Self { kvlm }
+    }
+
+    /// Points the commit at another tree and parents, with other identities, keeping its other
+    /// headers and its message. Its signature, and the signed tags of the parents it merged, vouch
+    /// for the commit as it was, so they are dropped.
+    pub fn rewrite(&mut self, tree: &str, parents: &[String], author: &Ident, committer: &Ident) {
+        self.kvlm.insert("tree", tree);
+        self.kvlm.set_values("parent", parents);
+        self.kvlm.insert("author", &author.to_string());
+        self.kvlm.insert("committer", &committer.to_string());
+        for key in ["gpgsig", "gpgsig-sha256", "mergetag"] {
+            self.kvlm.set_values(key, &[]);
+        }
+    }
+
     pub fn short(sha: &str) -> &str {
         &sha[0..7]
     }
@@ -32,4 +70,30 @@ impl Commit {
             .first()
             .expect("Tree cannot be assigned to empty value")
     }
+
+    pub fn parents(&self) -> &[String] {
+        self.kvlm.get_key("parent").map_or(&[], Vec::as_slice)
+    }
+
+    pub fn author(&self) -> anyhow::Result<Ident> {
+        self.ident("author")
+    }
+
+    pub fn committer(&self) -> anyhow::Result<Ident> {
+        self.ident("committer")
+    }
+
+    /// The first line of the message
+    pub fn subject(&self) -> &str {
+        self.message().lines().next().unwrap_or("")
+    }
+
+    fn ident(&self, key: &str) -> anyhow::Result<Ident> {
+        let raw = self
+            .kvlm
+            .get_key(key)
+            .and_then(|values| values.first())
+            .ok_or_else(|| anyhow::anyhow!("Commit has no {}", key))?;
+        Ident::parse(raw)
+    }
 }
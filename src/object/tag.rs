@@ -1,4 +1,12 @@
-use crate::{kvlm::Kvlm, object::Object, refs::Ref, repository::Repository};
+use anyhow::{Context, bail, ensure};
+
+use crate::{
+    ident::Ident,
+    kvlm::Kvlm,
+    object::{GitrsObject, Object, ObjectType},
+    refs::{self, Ref, RefNameOptions},
+    repository::Repository,
+};
 
 pub struct Tag {
     kvlm: Kvlm,
@@ -15,18 +23,109 @@ impl Object for Tag {
         self.kvlm.serialize()
     }
 
-    fn deserialize(data: &[u8]) -> Self {
-        Self {
-            kvlm: Kvlm::new(data),
-        }
+    fn deserialize(data: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            kvlm: Kvlm::new(data)?,
+        })
     }
 }
 
 impl Tag {
+    #[allow(dead_code)]
     pub fn new(kvlm: Kvlm) -> Self {
         Self { kvlm }
     }
 
+    pub fn message(&self) -> &str {
+        self.kvlm.get_message()
+    }
+
+    /// Hash of the tagged object
+    pub fn object(&self) -> Option<&str> {
+        self.kvlm
+            .get_key("object")
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+
+    /// Name the tag was created with
+    pub fn name(&self) -> Option<&str> {
+        self.kvlm
+            .get_key("tag")
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+
+    pub fn tagger(&self) -> Option<Ident> {
+        self.kvlm
+            .get_key("tagger")
+            .and_then(|values| values.first())
+            .and_then(|raw| Ident::parse(raw).ok())
+    }
+
+    /// Points the tag at another object, and with `tagger` gives it another tagger
+    pub fn rewrite(&mut self, object: &str, tagger: Option<&Ident>) {
+        self.kvlm.insert("object", object);
+        if let Some(tagger) = tagger {
+            self.kvlm.insert("tagger", &tagger.to_string());
+        }
+    }
+
+    /// Checks that `data` is a well-formed tag object pointing to an existing object of the type it
+    /// claims, as mktag requires
+    pub fn verify(repository: &Repository, data: &[u8]) -> anyhow::Result<()> {
+        let (object, object_type) = Self::check(data)?;
+        let actual = GitrsObject::read_raw(repository, &object)
+            .with_context(|| format!("Could not read tagged object '{}'", object))?
+            .get_type();
+        ensure!(
+            actual == object_type,
+            "Object '{}' tagged as '{}', but is a '{}'",
+            object,
+            object_type,
+            actual
+        );
+        Ok(())
+    }
+
+    /// Checks that `data` is a well-formed tag object, with `object`, `type`, `tag` and `tagger`
+    /// headers, in that order, followed by an optional message, returning what it tags and its type
+    pub fn check(data: &[u8]) -> anyhow::Result<(String, ObjectType)> {
+        let data = std::str::from_utf8(data).context("Tag is not valid UTF-8")?;
+        let headers = match data.split_once("\n\n") {
+            Some((headers, _)) => headers,
+            None => data.strip_suffix('\n').unwrap_or(data),
+        };
+        let mut lines = headers.split('\n');
+        let mut header = |key: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(key)?.strip_prefix(' '))
+                .with_context(|| format!("Invalid format, expected '{}' line", key))
+        };
+
+        let object = header("object")?;
+        ensure!(
+            object.len() == 40 && object.chars().all(|c| c.is_ascii_hexdigit()),
+            "Invalid object hash: {}",
+            object
+        );
+        let object_type: ObjectType = header("type")?.try_into()?;
+        let name = header("tag")?;
+        ensure!(!name.is_empty(), "Empty tag name");
+        // Like git's fsck, the name has to be one a tag could be created under
+        ensure!(
+            refs::check_name(&format!("refs/tags/{}", name), &RefNameOptions::default()),
+            "invalid 'tag' name: {}",
+            name
+        );
+        Ident::parse(header("tagger")?)?;
+        if let Some(line) = lines.next() {
+            bail!("Unexpected header: {}", line);
+        }
+        Ok((object.to_string(), object_type))
+    }
+
     // TODO: again, replace the hash here with the object_find method
     pub fn create(
         repository: &Repository,
@@ -34,6 +133,11 @@ impl Tag {
         hash: &str,
         tag_type: TagType,
     ) -> anyhow::Result<()> {
+        ensure!(
+            refs::check_name(&format!("refs/tags/{}", name), &RefNameOptions::default()),
+            "'{}' is not a valid tag name.",
+            name
+        );
         match tag_type {
             TagType::Lightweight => Ref::create_at(repository, hash, &["refs", "tags", name]),
             TagType::Object => {
@@ -48,3 +152,36 @@ impl Tag {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_names_like_mktag() {
+        let tag = |name: &str| {
+            format!(
+                "object {}\ntype commit\ntag {}\ntagger a <a@b> 1700000000 +0000\n\nmessage\n",
+                "a".repeat(40),
+                name
+            )
+        };
+        // Whether git 2.39 mktag takes a tag of each name
+        for (name, expected) in [
+            ("v9", true),
+            ("release/v1.0", true),
+            ("v 9", false),
+            ("v9.lock", false),
+            ("v..9", false),
+            ("v9~1", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                Tag::check(tag(name).as_bytes()).is_ok(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+}
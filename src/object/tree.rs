@@ -1,19 +1,39 @@
-use anyhow::anyhow;
+use anyhow::{Context, anyhow, bail, ensure};
 
-use crate::{object::Object, repository::Repository};
+use crate::{
+    config::Config,
+    object::Object,
+    path_safety,
+    repository::Repository,
+    tree_walk::{TreeWalk, TreeWalkOptions},
+};
 use std::{
-    fs,
-    io::{Cursor, Read, Write},
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    io::{Cursor, Write},
     path::{Path, PathBuf},
-    str::FromStr,
 };
 
-use super::{GitrsObject, ObjectType};
+use super::{GitrsObject, ObjectType, blob::Blob};
+
+// Symlinks are stored as blobs holding the link target
+pub const SYMLINK_MODE: &str = "120000";
+pub const EXECUTABLE_MODE: &str = "100755";
 
 pub struct Tree {
     pub records: Vec<Leaf>,
 }
 
+// Repository settings that affect how trees are written to, and compared against, the worktree
+struct WorktreeOptions {
+    // core.fileMode: whether the executable bit on disk can be trusted
+    trust_executable_bit: bool,
+    // core.ignorecase: whether the worktree is on a case-insensitive filesystem
+    ignore_case: bool,
+    protections: path_safety::Protections,
+}
+
+#[derive(Clone)]
 pub struct Leaf {
     pub file_mode: String,
     pub path: PathBuf, // relative to worktree
@@ -24,7 +44,7 @@ impl Object for Tree {
     fn serialize(&mut self) -> Vec<u8> {
         // Sort leaf nodes
         self.records.sort_by_key(|leaf| {
-            let is_dir = !leaf.file_mode.starts_with("10");
+            let is_dir = matches!(Leaf::get_type_from_mode(&leaf.file_mode), ObjectType::Tree);
             let mut file_path_str = leaf.path.to_string_lossy().to_string();
             if is_dir {
                 file_path_str.push('/');
@@ -35,51 +55,285 @@ impl Object for Tree {
 
         let mut output = Vec::new();
         self.records.iter().for_each(|leaf| {
+            // Modes are normalized to 6 bytes when parsed, but git stores directories as `40000`
             output.extend_from_slice(
                 format!(
-                    "{}\x20{}\x00{}",
-                    leaf.file_mode,
-                    leaf.path.to_string_lossy().to_string(),
-                    leaf.hash
+                    "{}\x20{}\x00",
+                    leaf.file_mode.trim_start_matches('0'),
+                    leaf.path.to_string_lossy(),
                 )
                 .as_bytes(),
             );
+            output.extend(hex::decode(&leaf.hash).expect("Leaf hash must be a hex string"));
         });
 
         output
     }
 
-    fn deserialize(data: &[u8]) -> Self {
+    fn deserialize(data: &[u8]) -> anyhow::Result<Self> {
         let mut cursor = Cursor::new(data);
         let len = cursor.get_ref().len();
 
         let mut records = Vec::new();
         while (cursor.position() as usize) < len {
-            let leaf = Leaf::parse(&mut cursor, data);
+            let leaf = Leaf::parse(&mut cursor, data)?;
             records.push(leaf);
         }
 
-        Self { records }
+        Ok(Self { records })
     }
 }
 
 impl Tree {
+    /// Reads the tree recorded by the commit with the given hash
+    pub fn of_commit(repository: &Repository, hash: &str) -> anyhow::Result<Self> {
+        let GitrsObject::CommitObject(commit_obj) = GitrsObject::read(repository, hash)? else {
+            bail!("Expected a commit object: {}", hash);
+        };
+        match GitrsObject::read(repository, commit_obj.get_tree_hash())? {
+            GitrsObject::TreeObject(tree_obj) => Ok(tree_obj),
+            _ => bail!("Couldn't find tree for {}", hash),
+        }
+    }
+
+    /// Builds a tree from ls-tree style `<mode> <type> <hash>\t<name>` lines, separated by NULs
+    /// instead of newlines if `nul` is set. The listed objects must exist, with the given types,
+    /// unless `allow_missing` is set. Submodule commits are never checked.
+    pub fn from_listing(
+        repository: &Repository,
+        listing: &str,
+        nul: bool,
+        allow_missing: bool,
+    ) -> anyhow::Result<Self> {
+        let separator = if nul { '\0' } else { '\n' };
+        let mut records = Vec::new();
+
+        for line in listing.split(separator).filter(|line| !line.is_empty()) {
+            let bad_line = || anyhow!("Input is not an ls-tree line: {}", line);
+            let (info, name) = line.split_once('\t').ok_or_else(bad_line)?;
+            let mut info = info.split(' ');
+            let (Some(mode), Some(object_type), Some(hash), None) =
+                (info.next(), info.next(), info.next(), info.next())
+            else {
+                return Err(bad_line());
+            };
+
+            if name.contains('/') {
+                bail!("Path {} contains slash", name);
+            }
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                bail!("Invalid object hash: {}", hash);
+            }
+            let object_type = ObjectType::try_from(object_type)?;
+            let file_mode = format!("{:0>6}", mode);
+            let mode_type = match file_mode.as_str() {
+                "040000" => ObjectType::Tree,
+                "100644" | EXECUTABLE_MODE | SYMLINK_MODE => ObjectType::Blob,
+                "160000" => ObjectType::Commit,
+                _ => bail!("Invalid mode: {}", mode),
+            };
+            if mode_type != object_type {
+                bail!(
+                    "Entry '{}' object type ({}) doesn't match mode type ({})",
+                    name,
+                    object_type,
+                    mode_type
+                );
+            }
+
+            if !allow_missing && object_type != ObjectType::Commit {
+                let actual = GitrsObject::read_raw(repository, hash)
+                    .map_err(|_| anyhow!("Entry '{}' object {} is unavailable", name, hash))?
+                    .get_type();
+                if actual != object_type {
+                    bail!(
+                        "Entry '{}' object {} is a {} but specified type was ({})",
+                        name,
+                        hash,
+                        actual,
+                        object_type
+                    );
+                }
+            }
+
+            records.push(Leaf {
+                file_mode,
+                path: PathBuf::from(name),
+                hash: hash.to_lowercase(),
+            });
+        }
+
+        Ok(Self { records })
+    }
+
+    /// Writes the trees holding `files`, which maps `/` separated paths to the mode and hash of
+    /// each file, and returns the hash of the top one
+    pub fn write_files(
+        repository: &Repository,
+        files: &BTreeMap<String, (String, String)>,
+    ) -> anyhow::Result<String> {
+        let mut records = Vec::new();
+        let mut subtrees: BTreeMap<&str, BTreeMap<String, (String, String)>> = BTreeMap::new();
+        for (path, (file_mode, hash)) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => {
+                    let subtree = subtrees.entry(dir).or_default();
+                    subtree.insert(rest.to_string(), (file_mode.clone(), hash.clone()));
+                }
+                None => records.push(Leaf {
+                    file_mode: file_mode.clone(),
+                    path: PathBuf::from(path),
+                    hash: hash.clone(),
+                }),
+            }
+        }
+        for (dir, subtree) in subtrees {
+            records.push(Leaf {
+                file_mode: "040000".to_string(),
+                path: PathBuf::from(dir),
+                hash: Self::write_files(repository, &subtree)?,
+            });
+        }
+        GitrsObject::TreeObject(Self { records }).write(repository)
+    }
+
+    /// Reads the tree named by `name`, following tags and commits to the tree they point to
+    pub fn from_name(repository: &Repository, name: &str) -> anyhow::Result<Self> {
+        let mut hash = GitrsObject::find(repository, name)?;
+        loop {
+            match GitrsObject::read(repository, &hash)? {
+                GitrsObject::TreeObject(tree_obj) => return Ok(tree_obj),
+                GitrsObject::CommitObject(commit_obj) => {
+                    hash = commit_obj.get_tree_hash().clone();
+                }
+                GitrsObject::TagObject(tag_obj) => match tag_obj.object() {
+                    Some(object) => hash = object.to_string(),
+                    None => bail!("Malformed tag: {}", hash),
+                },
+                GitrsObject::BlobObject(_) => bail!("Not a tree: {}", name),
+            }
+        }
+    }
+
+    /// Lists the paths (relative to the tree, `/` separated) of every non-tree entry, recursively
+    pub fn files(&self, repository: &Repository) -> anyhow::Result<Vec<String>> {
+        let options = TreeWalkOptions {
+            recursive: true,
+            ..TreeWalkOptions::default()
+        };
+        TreeWalk::new(repository, &[self], options)
+            .map(|entry| entry.map(|entry| entry.path))
+            .collect()
+    }
+
     // TODO: clean up partially created tree in case of failure
     pub fn checkout(&self, repository: &Repository, path: &Path) -> anyhow::Result<()> {
-        for record in &self.records {
-            let obj = GitrsObject::read(repository, &record.hash)?;
+        // Canonical paths on Windows use the `\\?\` prefix, which lifts the 260 character limit
+        #[cfg(windows)]
+        let path = &fs::canonicalize(path)?;
+
+        self.checkout_with(repository, path, &WorktreeOptions::load(repository)?)
+    }
+
+    /// Writes individual files into the worktree, replacing whatever is at their paths and creating
+    /// missing parent directories. Each file is given as a `/` separated path relative to the
+    /// worktree, and the mode and hash it is recorded with.
+    pub fn checkout_files(
+        repository: &Repository,
+        worktree: &Path,
+        files: &[(&str, &str, &str)],
+    ) -> anyhow::Result<()> {
+        let options = WorktreeOptions::load(repository)?;
+        for (path, file_mode, hash) in files {
+            for component in path.split('/') {
+                path_safety::verify_component(component, &options.protections)?;
+            }
+            let dest = worktree.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if let Ok(metadata) = fs::symlink_metadata(&dest) {
+                if metadata.is_dir() {
+                    fs::remove_dir_all(&dest)?;
+                } else {
+                    fs::remove_file(&dest)?;
+                }
+            }
+
+            match (Leaf::get_type_from_mode(file_mode), *file_mode) {
+                // Submodules are left uninitialized, as in checkout
+                (ObjectType::Commit, _) => fs::create_dir(&dest)?,
+                (ObjectType::Blob, mode) => {
+                    let GitrsObject::BlobObject(mut blob_obj) =
+                        GitrsObject::read(repository, hash)?
+                    else {
+                        bail!("Expected a blob object: {}", hash);
+                    };
+                    if mode == SYMLINK_MODE {
+                        create_symlink(&blob_obj.serialize(), &dest)?
+                    } else {
+                        let executable = options.trust_executable_bit && mode == EXECUTABLE_MODE;
+                        create_file(&blob_obj.serialize(), &dest, executable)?
+                    }
+                }
+                _ => bail!("Not a file: {}", path),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the directory at `path` holds exactly the contents of this tree
+    pub fn matches_dir(&self, repository: &Repository, path: &Path) -> anyhow::Result<bool> {
+        self.matches_dir_with(repository, path, &WorktreeOptions::load(repository)?)
+    }
+
+    fn checkout_with(
+        &self,
+        repository: &Repository,
+        path: &Path,
+        options: &WorktreeOptions,
+    ) -> anyhow::Result<()> {
+        let (records, colliding) = self.records_for_worktree(options);
+        for record in colliding {
+            eprintln!(
+                "warning: '{}' differs only in case from another path and is not checked out",
+                path.join(&record.path).display()
+            );
+        }
+
+        for record in records {
+            path_safety::verify_component(&record.path.to_string_lossy(), &options.protections)?;
             let dest = path.join(record.path.as_path());
-            println!("Final path is: {}", dest.display());
+
+            // Names differing only in case refer to the same file on case-insensitive filesystems
+            if fs::symlink_metadata(&dest).is_ok() {
+                bail!(
+                    "'{}' collides with a path that is already checked out",
+                    dest.display()
+                );
+            }
+
+            // Submodule commits live in another repository, so, like an uninitialized submodule in
+            // git, only an empty directory is created for them
+            if let ObjectType::Commit = Leaf::get_type_from_mode(&record.file_mode) {
+                fs::create_dir(&dest)?;
+                continue;
+            }
+
+            let obj = GitrsObject::read(repository, &record.hash)?;
 
             match obj {
                 GitrsObject::TreeObject(tree_obj) => {
                     fs::create_dir(&dest)?;
-                    tree_obj.checkout(repository, &dest)?
+                    tree_obj.checkout_with(repository, &dest, options)?
+                }
+                GitrsObject::BlobObject(mut blob_obj) if record.file_mode == SYMLINK_MODE => {
+                    create_symlink(&blob_obj.serialize(), &dest)?
                 }
                 GitrsObject::BlobObject(mut blob_obj) => {
-                    // TODO: support symlinks
-                    let mut file = fs::File::create(&dest)?;
-                    file.write_all(&blob_obj.serialize())?
+                    let executable =
+                        options.trust_executable_bit && record.file_mode == EXECUTABLE_MODE;
+                    create_file(&blob_obj.serialize(), &dest, executable)?
                 }
                 _ => {
                     return Err(anyhow!(
@@ -93,59 +347,131 @@ impl Tree {
 
         Ok(())
     }
+
+    fn matches_dir_with(
+        &self,
+        repository: &Repository,
+        path: &Path,
+        options: &WorktreeOptions,
+    ) -> anyhow::Result<bool> {
+        let (records, _) = self.records_for_worktree(options);
+        for record in &records {
+            let dest = path.join(record.path.as_path());
+            let matches = match Leaf::get_type_from_mode(&record.file_mode) {
+                ObjectType::Tree => match GitrsObject::read(repository, &record.hash)? {
+                    GitrsObject::TreeObject(tree_obj) => {
+                        dest.is_dir() && tree_obj.matches_dir_with(repository, &dest, options)?
+                    }
+                    _ => false,
+                },
+                ObjectType::Blob => match read_worktree_file(&dest, &record.file_mode)? {
+                    Some(content) => {
+                        (!options.trust_executable_bit
+                            || record.file_mode == SYMLINK_MODE
+                            || is_executable(&dest)? == (record.file_mode == EXECUTABLE_MODE))
+                            && GitrsObject::BlobObject(Blob::new(content)).hash() == record.hash
+                    }
+                    None => false,
+                },
+                // Submodules are not checked out, so only their directory needs to exist
+                _ => dest.is_dir(),
+            };
+
+            if !matches {
+                return Ok(false);
+            }
+        }
+
+        // Anything left over is untracked
+        let entry_count = fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name() != ".gitrs")
+            .count();
+
+        Ok(entry_count == records.len())
+    }
+
+    // Splits the records into the ones that can be represented in the worktree and the ones that
+    // can't. On case-insensitive filesystems only the first of several names that differ in case can
+    // be checked out.
+    fn records_for_worktree(&self, options: &WorktreeOptions) -> (Vec<&Leaf>, Vec<&Leaf>) {
+        if !options.ignore_case {
+            return (self.records.iter().collect(), Vec::new());
+        }
+
+        let mut seen = HashSet::new();
+        self.records
+            .iter()
+            .partition(|record| seen.insert(record.path.to_string_lossy().to_lowercase()))
+    }
+}
+
+impl WorktreeOptions {
+    fn load(repository: &Repository) -> anyhow::Result<Self> {
+        let config = Config::load(repository)?;
+        Ok(Self {
+            trust_executable_bit: config.get_bool("core.fileMode")?.unwrap_or(true),
+            ignore_case: config.get_bool("core.ignoreCase")?.unwrap_or(false),
+            protections: path_safety::Protections {
+                ntfs: config.get_bool("core.protectNTFS")?.unwrap_or(true),
+                hfs: config
+                    .get_bool("core.protectHFS")?
+                    .unwrap_or(cfg!(target_os = "macos")),
+            },
+        })
+    }
 }
 
 impl Leaf {
-    fn parse(cursor: &mut Cursor<&[u8]>, data: &[u8]) -> Self {
+    // Parses the `<mode> <path>\0<20 byte hash>` record at the cursor. The fields are read from
+    // slices of `data`, so only the final strings are allocated.
+    fn parse(cursor: &mut Cursor<&[u8]>, data: &[u8]) -> anyhow::Result<Self> {
         let curr_pos = cursor.position() as usize;
+        let record = &data[curr_pos..];
 
-        // Extract the file mode
-        let space_idx = data[curr_pos..]
+        let space_idx = record
             .iter()
             .position(|&b| b == b' ')
-            .ok_or("Malformed leaf record: Missing space")
-            .unwrap()
-            + curr_pos;
-        let mut mode = String::from_utf8_lossy(&data[curr_pos..space_idx]).into_owned();
+            .context("Malformed leaf record: Missing space")?;
+        let null_idx = space_idx
+            + record[space_idx..]
+                .iter()
+                .position(|&b| b == 0)
+                .context("Malformed leaf record: Expected null byte")?;
+        let hash = record
+            .get(null_idx + 1..null_idx + 21)
+            .context("Couldn't read SHA-1 hash from leaf record")?;
+        cursor.set_position((curr_pos + null_idx + 21) as u64);
 
-        println!("size for mode: {}", space_idx - curr_pos);
         // Normalize to 6 bytes
-        if space_idx - curr_pos == 5 {
-            mode.insert(0, '0');
-        }
-
-        // Extract the file path
-        let null_idx = data[space_idx..]
-            .iter()
-            .position(|&b| b == 0)
-            .ok_or("Mallformed leaf record: Expected null byte")
-            .unwrap()
-            + space_idx;
-        let path = String::from_utf8_lossy(&data[space_idx + 1..null_idx]).into_owned();
-
-        // Finally, extract the SHA-1 hash
-        cursor.set_position((null_idx + 1) as u64);
-        let mut hash_buf = vec![0; 20];
-        cursor
-            .read_exact(&mut hash_buf)
-            .expect("Couldn't read SHA-1 hash from leaf record");
-
-        let hash = String::from_utf8_lossy(&hash_buf).into_owned();
+        let mode = String::from_utf8_lossy(&record[..space_idx]);
+        let file_mode = if mode.len() == 5 {
+            format!("0{}", mode)
+        } else {
+            mode.into_owned()
+        };
+        // Checked here so the type of any leaf read can be told from its mode
+        ensure!(
+            matches!(file_mode.get(..2), Some("04" | "10" | "12" | "16")),
+            "Malformed leaf record: Weird mode {}",
+            file_mode
+        );
 
-        Self {
-            file_mode: mode.to_string().to_owned(),
-            path: PathBuf::from_str(&path).expect("Couldn't create PathBuf"),
-            hash,
-        }
+        Ok(Self {
+            file_mode,
+            path: PathBuf::from(
+                String::from_utf8_lossy(&record[space_idx + 1..null_idx]).into_owned(),
+            ),
+            hash: hex::encode(hash),
+        })
     }
 
     pub fn get_type_from_mode(file_mode: &str) -> ObjectType {
-        let file_type;
-        if file_mode.len() == 5 {
-            file_type = &file_mode[..1]
+        let file_type = if file_mode.len() == 5 {
+            &file_mode[..1]
         } else {
-            file_type = &file_mode[..2]
-        }
+            &file_mode[..2]
+        };
 
         match file_type {
             "4" | "04" => ObjectType::Tree,
@@ -155,3 +481,161 @@ impl Leaf {
         }
     }
 }
+
+// Writes a regular file, marking it executable (subject to the umask) if requested
+fn create_file(content: &[u8], dest: &Path, executable: bool) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(if executable { 0o777 } else { 0o666 });
+    }
+    #[cfg(not(unix))]
+    let _ = executable;
+
+    options.open(dest)?.write_all(content)
+}
+
+fn is_executable(path: &Path) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(fs::metadata(path)?.permissions().mode() & 0o100 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(false)
+    }
+}
+
+// Restores a symlink pointing to `target`, falling back to a plain file holding the target if
+// symlinks can't be created (eg. on Windows without the required privilege)
+fn create_symlink(target: &[u8], dest: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        if std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), dest).is_ok() {
+            return Ok(());
+        }
+    }
+    #[cfg(windows)]
+    {
+        let target = String::from_utf8_lossy(target).replace('/', "\\");
+        if std::os::windows::fs::symlink_file(target, dest).is_ok() {
+            return Ok(());
+        }
+    }
+
+    fs::write(dest, target)
+}
+
+/// Replaces whatever is at `dest` with a file of mode `file_mode` holding `content`, or for a
+/// symlink's mode with a symlink pointing to `content`
+pub fn replace_worktree_file(content: &[u8], dest: &Path, file_mode: &str) -> io::Result<()> {
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+    match file_mode {
+        SYMLINK_MODE => create_symlink(content, dest),
+        _ => create_file(content, dest, file_mode == EXECUTABLE_MODE),
+    }
+}
+
+/// Returns the mode of the worktree file at `path`, or None if there is no file there
+pub fn worktree_mode(path: &Path) -> io::Result<Option<&'static str>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    Ok(if metadata.is_symlink() {
+        Some(SYMLINK_MODE)
+    } else if !metadata.is_file() {
+        None
+    } else if is_executable(path)? {
+        Some(EXECUTABLE_MODE)
+    } else {
+        Some("100644")
+    })
+}
+
+/// Returns the mode and blob hash gitrs would record for the worktree file at `path`, or None if
+/// there is no file there. `file_mode` is the recorded mode, which is kept unless the executable
+/// bit can be trusted or the file has become (or stopped being) a symlink.
+pub fn hash_worktree_file(
+    path: &Path,
+    file_mode: &str,
+    trust_executable_bit: bool,
+) -> io::Result<Option<(String, String)>> {
+    Ok(worktree_blob(path, file_mode, trust_executable_bit)?
+        .map(|(mode, blob)| (mode.to_string(), GitrsObject::BlobObject(blob).hash())))
+}
+
+/// Like `hash_worktree_file`, but also writes the blob to the object store
+pub fn write_worktree_file(
+    repository: &Repository,
+    path: &Path,
+    file_mode: &str,
+    trust_executable_bit: bool,
+) -> anyhow::Result<Option<(String, String)>> {
+    let Some((mode, blob)) = worktree_blob(path, file_mode, trust_executable_bit)? else {
+        return Ok(None);
+    };
+    let hash = GitrsObject::BlobObject(blob).write(repository)?;
+    Ok(Some((mode.to_string(), hash)))
+}
+
+// Reads the worktree file at `path` as the blob gitrs would record for it, with its mode
+fn worktree_blob(
+    path: &Path,
+    file_mode: &str,
+    trust_executable_bit: bool,
+) -> io::Result<Option<(&'static str, Blob)>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+    let mode = if metadata.is_symlink() {
+        SYMLINK_MODE
+    } else if !metadata.is_file() {
+        return Ok(None);
+    } else if file_mode == SYMLINK_MODE {
+        // Symlinks checked out as plain files still count as symlinks
+        SYMLINK_MODE
+    } else if !trust_executable_bit {
+        match file_mode {
+            EXECUTABLE_MODE => EXECUTABLE_MODE,
+            _ => "100644",
+        }
+    } else if is_executable(path)? {
+        EXECUTABLE_MODE
+    } else {
+        "100644"
+    };
+
+    let Some(content) = read_worktree_file(path, mode)? else {
+        return Ok(None);
+    };
+    Ok(Some((mode, Blob::new(content))))
+}
+
+/// Reads the content gitrs would store for the file at `path`, or None if there is no file of the
+/// kind described by `file_mode` there
+pub fn read_worktree_file(path: &Path, file_mode: &str) -> io::Result<Option<Vec<u8>>> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(None);
+    };
+
+    if metadata.is_symlink() {
+        if file_mode != SYMLINK_MODE {
+            return Ok(None);
+        }
+        Ok(Some(
+            fs::read_link(path)?.into_os_string().into_encoded_bytes(),
+        ))
+    } else if metadata.is_file() {
+        // Symlinks checked out as plain files hold their target as content
+        Ok(Some(fs::read(path)?))
+    } else {
+        Ok(None)
+    }
+}
@@ -0,0 +1,1177 @@
+// Reads and writes packs (objects/pack/*.pack), which hold many objects in one file, and their
+// indexes (*.idx), which list the objects' hashes in order along with where each starts in the
+// pack. Both index versions are read: v2 starts with a `\377tOc` magic number, while v1 starts
+// straight away with the fanout table. A pack starts with `PACK`, its version and its number of
+// objects, each entry then being a header with its type and size followed by its zlib-compressed
+// contents, and ends with the hash of all that. An entry can also be a delta against a base
+// object, found at an earlier offset in the pack or by its hash. Packs are written in version 2,
+// with v2 indexes, compressing objects as deltas against similar ones where that makes them
+// smaller, and like git's are named after the hash they end with. Since that name changes
+// with anything in the pack, packs are never modified, so their indexes stay loaded once read.
+//
+// Like git, the objects deltas are applied to are kept as they are read, up to
+// core.deltaBaseCacheLimit bytes of them for each pack, so that reading objects whose chains of
+// deltas share bases doesn't decompress the same bases over and over. The ones used longest ago
+// are dropped first.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, anyhow, bail, ensure};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+use crate::delta;
+use crate::filter;
+use crate::object::{self, GitrsObject, ObjectType};
+use crate::repository::Repository;
+use crate::trace;
+
+const IDX_V2_MAGIC: &[u8] = b"\xfftOc";
+const PACK_MAGIC: &[u8] = b"PACK";
+const FANOUT_SIZE: usize = 256 * 4;
+// Entry types for deltas, against a base at an earlier offset or with the given hash
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+// Offsets in v2 indexes with this bit set point into the table of 8 byte offsets instead
+const LARGE_OFFSET: u32 = 0x8000_0000;
+// git's default for core.deltaBaseCacheLimit
+const DELTA_BASE_CACHE_LIMIT: usize = 96 << 20;
+
+pub struct PackIndex {
+    /// The pack the index describes
+    pub pack: PathBuf,
+    // Sorted hashes of the objects in the pack
+    objects: Vec<String>,
+    // Where each object starts in the pack
+    offsets: Vec<u64>,
+    // The CRC-32 of each object's entry, which only v2 indexes have
+    crcs: Vec<u32>,
+    delta_bases: Mutex<DeltaBaseCache>,
+}
+
+// The objects deltas in a pack were applied to, by where they are in it
+struct DeltaBaseCache {
+    limit: usize,
+    size: usize,
+    // Each object with when it was last used
+    entries: HashMap<u64, (ObjectType, Arc<Vec<u8>>, u64)>,
+    // Where each object is, by when it was last used
+    used: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+/// An object to write to a pack
+pub struct PackObject {
+    pub hash: String,
+    pub object_type: ObjectType,
+    pub data: Vec<u8>,
+    /// Where the object was found, which objects are matched up by to look for deltas
+    pub path: Option<String>,
+}
+
+/// How objects are compressed as deltas against each other as they are packed
+pub struct DeltaOptions {
+    /// How many other objects each is tried against, none meaning no deltas
+    pub window: usize,
+    /// The longest chain of deltas
+    pub depth: usize,
+    /// Refer to the base of a delta by where it is in the pack rather than by its hash, which
+    /// packs written before git 1.4 can't do
+    pub offsets: bool,
+}
+
+impl DeltaOptions {
+    /// The options pack.window and pack.depth set, which are 10 and 50 by default as in git
+    pub fn load(config: &Config, offsets: bool) -> anyhow::Result<Self> {
+        let number = |key: &str, default: usize| match config.get(key) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| anyhow!("Bad numeric config value '{}' for '{}'", value, key)),
+            None => Ok(default),
+        };
+        Ok(Self {
+            window: number("pack.window", 10)?,
+            depth: number("pack.depth", 50)?.clamp(1, 4095),
+            offsets,
+        })
+    }
+}
+
+/// How an object is stored in a pack
+pub struct StoredEntry {
+    pub offset: u64,
+    /// How many bytes the entry takes, with its header
+    pub stored_size: u64,
+    /// The size of the contents, or for a delta of the delta
+    pub size: usize,
+    pub crc: u32,
+    /// For a delta, the hash of its base and how long the chain of deltas up to it is
+    pub delta: Option<(String, usize)>,
+}
+
+/// Where to find an object in a pack, as its index records it
+pub struct IndexEntry {
+    pub hash: String,
+    /// CRC-32 of the object's entry, as stored
+    pub crc: u32,
+    pub offset: u64,
+}
+
+// What an entry's header says about it
+struct EntryHeader {
+    // The object type, or OFS_DELTA or REF_DELTA
+    kind: u8,
+    // The size of the contents, or of the delta
+    size: usize,
+    base: Base,
+}
+
+enum Base {
+    None,
+    Offset(u64),
+    Hash(String),
+}
+
+// Indexes already loaded, by path
+static LOADED: Mutex<Option<HashMap<PathBuf, Arc<PackIndex>>>> = Mutex::new(None);
+
+impl PackIndex {
+    /// Loads the index of every pack in the repository
+    pub fn load_all(repository: &Repository) -> anyhow::Result<Vec<Arc<Self>>> {
+        let _region = trace::region("load pack indexes");
+        let Some(dir) = repository.get_path_to_dir(&["objects", "pack"]) else {
+            return Ok(Vec::new());
+        };
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+            .collect();
+        paths.sort();
+
+        let mut loaded = LOADED.lock().expect("Pack index cache is poisoned");
+        let loaded = loaded.get_or_insert_with(HashMap::new);
+        // The config is only read for packs not loaded yet
+        let mut delta_base_limit = None;
+        paths
+            .into_iter()
+            .map(|path| match loaded.get(&path) {
+                Some(index) => Ok(index.clone()),
+                None => {
+                    let limit = match delta_base_limit {
+                        Some(limit) => limit,
+                        None => *delta_base_limit.insert(delta_base_cache_limit(repository)?),
+                    };
+                    let index = Arc::new(Self::open(&path, limit)?);
+                    loaded.insert(path, index.clone());
+                    Ok(index)
+                }
+            })
+            .collect()
+    }
+
+    /// Reads the index at `path`, keeping up to `delta_base_limit` bytes of the objects deltas in
+    /// its pack are applied to as they are read
+    pub fn open(path: &Path, delta_base_limit: usize) -> anyhow::Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let truncated = || anyhow!("Pack index {} is truncated", path.display());
+        let word = |start: usize| {
+            data.get(start..start + 4)
+                .map(|bytes| u32::from_be_bytes(bytes.try_into().expect("Slice has 4 bytes")))
+                .ok_or_else(truncated)
+        };
+
+        let v2 = data.starts_with(IDX_V2_MAGIC);
+        let fanout_start = match v2 {
+            true => {
+                ensure!(
+                    word(4)? == 2,
+                    "Unsupported pack index version in {}",
+                    path.display()
+                );
+                8
+            }
+            false => 0,
+        };
+        let count = word(fanout_start + FANOUT_SIZE - 4)? as usize;
+        let entries_start = fanout_start + FANOUT_SIZE;
+        let hash_at = |start: usize| {
+            data.get(start..start + 20)
+                .map(hex::encode)
+                .ok_or_else(truncated)
+        };
+
+        let (objects, offsets, crcs) = match v2 {
+            // The hashes, then a CRC-32 for each, then their offsets, then the large offsets
+            true => {
+                let crcs_start = entries_start + count * 20;
+                let offsets_start = entries_start + count * 24;
+                let large_start = offsets_start + count * 4;
+                let objects = (0..count)
+                    .map(|idx| hash_at(entries_start + idx * 20))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let crcs = (0..count)
+                    .map(|idx| word(crcs_start + idx * 4))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                let offsets = (0..count)
+                    .map(|idx| {
+                        let offset = word(offsets_start + idx * 4)?;
+                        if offset & LARGE_OFFSET == 0 {
+                            return Ok(offset as u64);
+                        }
+                        let start = large_start + (offset & !LARGE_OFFSET) as usize * 8;
+                        Ok(((word(start)? as u64) << 32) | word(start + 4)? as u64)
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                (objects, offsets, crcs)
+            }
+            // Each entry is a 4 byte offset followed by the hash
+            false => {
+                let (objects, offsets) = (0..count)
+                    .map(|idx| {
+                        let start = entries_start + idx * 24;
+                        Ok((hash_at(start + 4)?, word(start)? as u64))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+                    .into_iter()
+                    .unzip();
+                (objects, offsets, Vec::new())
+            }
+        };
+
+        Ok(Self {
+            pack: path.with_extension("pack"),
+            objects,
+            offsets,
+            crcs,
+            delta_bases: Mutex::new(DeltaBaseCache::new(delta_base_limit)),
+        })
+    }
+
+    /// Whether a `.keep` file asks for the pack to be left as it is
+    pub fn is_kept(&self) -> bool {
+        self.pack.with_extension("keep").exists()
+    }
+
+    /// Whether the pack came from a promisor remote, which git marks with a `.promisor` file
+    pub fn is_promisor(&self) -> bool {
+        self.pack.with_extension("promisor").exists()
+    }
+
+    /// The pack's name, like `pack-<hash>`
+    pub fn name(&self) -> String {
+        self.pack
+            .file_stem()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+    }
+
+    pub fn objects(&self) -> &[String] {
+        &self.objects
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.position(hash).is_some()
+    }
+
+    /// Reads the type and contents of the object `hash`, if the pack has it
+    pub fn read(
+        &self,
+        repository: &Repository,
+        hash: &str,
+    ) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+        let Some(position) = self.position(hash) else {
+            return Ok(None);
+        };
+        let mut file = File::open(&self.pack)
+            .with_context(|| format!("Failed to open {}", self.pack.display()))?;
+        let object = self
+            .read_at(repository, &mut file, self.offsets[position])
+            .with_context(|| format!("Could not read {} from {}", hash, self.pack.display()))?;
+        Ok(Some(object))
+    }
+
+    fn position(&self, hash: &str) -> Option<usize> {
+        self.objects
+            .binary_search_by(|object| object.as_str().cmp(hash))
+            .ok()
+    }
+
+    // Reads the entry at `offset`, applying it to its base if it is a delta
+    fn read_at(
+        &self,
+        repository: &Repository,
+        file: &mut File,
+        offset: u64,
+    ) -> anyhow::Result<(ObjectType, Vec<u8>)> {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(&mut *file);
+        let header = read_entry_header(&mut reader, offset)?;
+        let data = inflate(&mut reader, header.size)?;
+        drop(reader);
+
+        let (object_type, base) = match header.base {
+            Base::None => return Ok((object_type(header.kind)?, data)),
+            Base::Offset(base) => self.read_base(repository, file, base)?,
+            Base::Hash(base) => match self.position(&base) {
+                Some(position) => self.read_base(repository, file, self.offsets[position])?,
+                None => {
+                    let (object_type, base) = GitrsObject::read_data(repository, &base)?;
+                    (object_type, Arc::new(base))
+                }
+            },
+        };
+        Ok((object_type, delta::apply(&base, &data)?))
+    }
+
+    // Reads the base of a delta at `offset`, from the cache if it was read before
+    fn read_base(
+        &self,
+        repository: &Repository,
+        file: &mut File,
+        offset: u64,
+    ) -> anyhow::Result<(ObjectType, Arc<Vec<u8>>)> {
+        let cached = self
+            .delta_bases
+            .lock()
+            .expect("Delta base cache is poisoned")
+            .get(offset);
+        if let Some(base) = cached {
+            return Ok(base);
+        }
+        let (object_type, data) = self.read_at(repository, file, offset)?;
+        let data = Arc::new(data);
+        self.delta_bases
+            .lock()
+            .expect("Delta base cache is poisoned")
+            .insert(offset, object_type.clone(), data.clone());
+        Ok((object_type, data))
+    }
+}
+
+impl DeltaBaseCache {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            size: 0,
+            entries: HashMap::new(),
+            used: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, offset: u64) -> Option<(ObjectType, Arc<Vec<u8>>)> {
+        let (object_type, data, used) = self.entries.get_mut(&offset)?;
+        self.used.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.used.insert(self.clock, offset);
+        Some((object_type.clone(), data.clone()))
+    }
+
+    // Keeps the object at `offset`, dropping the ones used longest ago to make room for it
+    fn insert(&mut self, offset: u64, object_type: ObjectType, data: Arc<Vec<u8>>) {
+        if data.len() > self.limit || self.entries.contains_key(&offset) {
+            return;
+        }
+        while self.size + data.len() > self.limit
+            && let Some((_, oldest)) = self.used.pop_first()
+        {
+            if let Some((_, dropped, _)) = self.entries.remove(&oldest) {
+                self.size -= dropped.len();
+            }
+        }
+        self.clock += 1;
+        self.size += data.len();
+        self.used.insert(self.clock, offset);
+        self.entries.insert(offset, (object_type, data, self.clock));
+    }
+}
+
+// How many bytes of delta bases to keep for each pack (core.deltaBaseCacheLimit)
+fn delta_base_cache_limit(repository: &Repository) -> anyhow::Result<usize> {
+    match Config::load(repository)?.get("core.deltaBaseCacheLimit") {
+        Some(limit) => Ok(filter::parse_size(limit)? as usize),
+        None => Ok(DELTA_BASE_CACHE_LIMIT),
+    }
+}
+
+/// Reads the type and contents of the object `hash` from whichever pack has it
+pub fn find(repository: &Repository, hash: &str) -> anyhow::Result<Option<(ObjectType, Vec<u8>)>> {
+    for index in PackIndex::load_all(repository)? {
+        if let Some(object) = index.read(repository, hash)? {
+            return Ok(Some(object));
+        }
+    }
+    Ok(None)
+}
+
+/// Reads every object in `pack`, a whole pack as it is sent, checking that it is well-formed and
+/// ends with its checksum right after the last entry. Deltas are resolved against the objects in
+/// the pack, or against those in the repository for bases given by hash that aren't in it.
+pub fn parse(repository: &Repository, pack: &[u8]) -> anyhow::Result<Vec<PackObject>> {
+    Ok(parse_stored(repository, pack)?
+        .into_iter()
+        .map(|(object, _)| object)
+        .collect())
+}
+
+/// Reads every object in `pack` like `parse`, along with how each is stored in it
+pub fn parse_stored(
+    repository: &Repository,
+    pack: &[u8],
+) -> anyhow::Result<Vec<(PackObject, StoredEntry)>> {
+    let _region = trace::region("parse pack");
+    ensure!(
+        pack.len() >= 12 && &pack[..4] == PACK_MAGIC,
+        "Bad pack header"
+    );
+    let version = u32::from_be_bytes(pack[4..8].try_into().expect("Slice has 4 bytes"));
+    ensure!(
+        version == 2 || version == 3,
+        "Pack version {} unsupported",
+        version
+    );
+    let count = u32::from_be_bytes(pack[8..12].try_into().expect("Slice has 4 bytes")) as usize;
+
+    let mut reader = Cursor::new(pack);
+    reader.set_position(12);
+    let mut entries = Vec::with_capacity(count.min(pack.len() / 2));
+    let mut stored = Vec::with_capacity(count.min(pack.len() / 2));
+    let mut positions = HashMap::new();
+    for position in 0..count {
+        let offset = reader.position();
+        let header = read_entry_header(&mut reader, offset)?;
+        let data = inflate(&mut reader, header.size)?;
+        let entry = &pack[offset as usize..reader.position() as usize];
+        let mut crc = Crc::new();
+        crc.update(entry);
+        stored.push(StoredEntry {
+            offset,
+            stored_size: entry.len() as u64,
+            size: header.size,
+            crc: crc.sum(),
+            delta: None,
+        });
+        positions.insert(offset, position);
+        entries.push((header, data));
+    }
+    let end = reader.position() as usize;
+    ensure!(pack.len() >= end + 20, "Pack is truncated");
+    ensure!(pack.len() == end + 20, "Pack has junk at the end");
+    ensure!(
+        Sha1::digest(&pack[..end])[..] == pack[end..],
+        "Pack checksum mismatch"
+    );
+
+    // Deltas can come before their bases when those are given by hash, so the entries are
+    // resolved over as many passes as that takes. Bases outside the pack are only looked for once
+    // no more can be resolved from inside it.
+    let mut objects: Vec<Option<PackObject>> = (0..count).map(|_| None).collect();
+    let mut hashes: HashMap<String, usize> = HashMap::new();
+    let mut outside = false;
+    loop {
+        let mut resolved = 0;
+        let mut left = 0;
+        for (position, (header, data)) in entries.iter_mut().enumerate() {
+            if objects[position].is_some() {
+                continue;
+            }
+            // The base inside the pack, if the entry is a delta against one
+            let base = match &header.base {
+                Base::None => None,
+                Base::Offset(offset) => Some(
+                    *positions
+                        .get(offset)
+                        .context("Delta base offset is not an entry")?,
+                ),
+                Base::Hash(hash) => hashes.get(hash).copied(),
+            };
+            let (object_type, data) = match (&header.base, base) {
+                (Base::None, _) => (object_type(header.kind)?, std::mem::take(data)),
+                (_, Some(base)) => match &objects[base] {
+                    Some(base_object) => {
+                        let depth = stored[base].delta.as_ref().map_or(0, |(_, depth)| *depth);
+                        stored[position].delta = Some((base_object.hash.clone(), depth + 1));
+                        let data = delta::apply(&base_object.data, data)?;
+                        (base_object.object_type.clone(), data)
+                    }
+                    None => {
+                        left += 1;
+                        continue;
+                    }
+                },
+                (Base::Hash(hash), None) if outside => {
+                    let (object_type, base) = GitrsObject::read_data(repository, hash)
+                        .with_context(|| format!("Missing delta base {}", hash))?;
+                    stored[position].delta = Some((hash.clone(), 1));
+                    (object_type, delta::apply(&base, data)?)
+                }
+                (_, None) => {
+                    left += 1;
+                    continue;
+                }
+            };
+            let hash = GitrsObject::hash_data(object_type.clone(), &data);
+            hashes.insert(hash.clone(), position);
+            objects[position] = Some(PackObject {
+                hash,
+                object_type,
+                data,
+                path: None,
+            });
+            resolved += 1;
+        }
+        if left == 0 {
+            break;
+        }
+        outside = resolved == 0;
+    }
+    Ok(objects
+        .into_iter()
+        .map(|object| object.expect("Every entry is resolved"))
+        .zip(stored)
+        .collect())
+}
+
+/// Checks the pack of the index at `path` against it, as git verify-pack does: that both end with
+/// the right checksums, and that the index lists every object in the pack, where it is and with
+/// the checksum of its entry. Returns every object in the pack with how it is stored, in the
+/// order they are in it.
+pub fn verify(
+    repository: &Repository,
+    path: &Path,
+) -> anyhow::Result<Vec<(PackObject, StoredEntry)>> {
+    let index_data =
+        fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let index = PackIndex::open(path, 0)?;
+    let pack = fs::read(&index.pack)
+        .with_context(|| format!("Failed to read {}", index.pack.display()))?;
+    let does_not_match = || anyhow!("packfile {} does not match index", index.pack.display());
+
+    let (content, checksum) = index_data
+        .split_at_checked(index_data.len().saturating_sub(20))
+        .filter(|(content, _)| content.len() >= 20)
+        .with_context(|| format!("Pack index {} is truncated", path.display()))?;
+    ensure!(
+        Sha1::digest(content)[..] == *checksum,
+        "index file {} is corrupted (SHA1 mismatch)",
+        path.display()
+    );
+    ensure!(
+        pack.len() >= 20 && content[content.len() - 20..] == pack[pack.len() - 20..],
+        "{}",
+        does_not_match()
+    );
+    let objects = parse_stored(repository, &pack)
+        .with_context(|| format!("pack {} is corrupted", index.pack.display()))?;
+    ensure!(objects.len() == index.objects.len(), "{}", does_not_match());
+    for (object, stored) in &objects {
+        let position = index.position(&object.hash).ok_or_else(does_not_match)?;
+        ensure!(
+            index.offsets[position] == stored.offset
+                && index
+                    .crcs
+                    .get(position)
+                    .is_none_or(|crc| *crc == stored.crc),
+            "{}",
+            does_not_match()
+        );
+    }
+    Ok(objects)
+}
+
+fn object_type(kind: u8) -> anyhow::Result<ObjectType> {
+    match kind {
+        1 => Ok(ObjectType::Commit),
+        2 => Ok(ObjectType::Tree),
+        3 => Ok(ObjectType::Blob),
+        4 => Ok(ObjectType::Tag),
+        _ => bail!("Bad object type {} in pack", kind),
+    }
+}
+
+fn kind(object_type: &ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+// Reads the header of the entry at `offset`: its type and size in the low 4 bits of the first
+// byte and 7 more bits of each byte after it while their top bit is set, then for deltas where the
+// base is
+fn read_entry_header(reader: &mut impl BufRead, offset: u64) -> anyhow::Result<EntryHeader> {
+    let mut byte = || -> anyhow::Result<u8> {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).context("Pack is truncated")?;
+        Ok(byte[0])
+    };
+    let mut c = byte()?;
+    let kind = (c >> 4) & 7;
+    let mut size = (c & 15) as usize;
+    let mut shift = 4;
+    while c & 0x80 != 0 {
+        c = byte()?;
+        ensure!(shift < usize::BITS, "Bad object header in pack");
+        size += ((c & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match kind {
+        // How far back the base is, 7 bits a byte with the most significant first, each byte
+        // after the first adding one before its bits are shifted in
+        OFS_DELTA => {
+            c = byte()?;
+            let mut distance = (c & 0x7f) as u64;
+            while c & 0x80 != 0 {
+                c = byte()?;
+                distance = distance
+                    .checked_add(1)
+                    .and_then(|distance| distance.checked_mul(128))
+                    .context("Bad delta base offset in pack")?
+                    + (c & 0x7f) as u64;
+            }
+            ensure!(
+                distance > 0 && distance <= offset,
+                "Delta base offset out of bound"
+            );
+            Base::Offset(offset - distance)
+        }
+        REF_DELTA => {
+            let mut hash = [0; 20];
+            reader.read_exact(&mut hash).context("Pack is truncated")?;
+            Base::Hash(hex::encode(hash))
+        }
+        _ => Base::None,
+    };
+    Ok(EntryHeader { kind, size, base })
+}
+
+// Decompresses the contents of an entry, which must come to `size` bytes, leaving `reader` just
+// after them
+fn inflate(reader: &mut impl BufRead, size: usize) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size);
+    ZlibDecoder::new(reader)
+        .read_to_end(&mut data)
+        .context("Corrupt object in pack")?;
+    ensure!(
+        data.len() == size,
+        "Object in pack has size {} but should be {}",
+        data.len(),
+        size
+    );
+    Ok(data)
+}
+
+fn encode_entry_header(kind: u8, size: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut c = (kind << 4) | (size & 15) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        header.push(c | 0x80);
+        c = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(c);
+    header
+}
+
+// Encodes how far back an entry's base is, the inverse of how read_entry_header reads it
+fn encode_distance(mut distance: u64) -> Vec<u8> {
+    let mut encoded = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        encoded.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    encoded.reverse();
+    encoded
+}
+
+// git's hash of a path, which sorts by its last sixteen characters that aren't whitespace, the
+// last counting the most, so that files with the same name or extension sort together
+fn name_hash(path: Option<&str>) -> u32 {
+    path.unwrap_or("")
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .fold(0, |hash, byte| {
+            (hash >> 2).wrapping_add((byte as u32) << 24)
+        })
+}
+
+// Picks a base for each object that a delta against it makes smaller, returning the base and the
+// delta. Like git, objects are sorted by type, by the hash of their name and then largest first,
+// and each is tried against the objects of the same type in the window before it, keeping the
+// smallest delta. How small a delta must be to be taken depends on how long the chain it would
+// start is, so that short chains are picked over slightly smaller deltas.
+fn find_deltas(objects: &[PackObject], options: &DeltaOptions) -> Vec<Option<(usize, Vec<u8>)>> {
+    let mut order: Vec<usize> = (0..objects.len()).collect();
+    order.sort_by_key(|&i| {
+        let object = &objects[i];
+        Reverse((
+            kind(&object.object_type),
+            name_hash(object.path.as_deref()),
+            object.data.len(),
+        ))
+    });
+
+    let mut deltas: Vec<Option<(usize, Vec<u8>)>> = (0..objects.len()).map(|_| None).collect();
+    let mut depths = vec![0; objects.len()];
+    for (position, &i) in order.iter().enumerate() {
+        let target = &objects[i];
+        // A delta has to save a good part of the object, and its base's hash, to be worth it
+        let mut max_size = (target.data.len() / 2).saturating_sub(20);
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let window = &order[position.saturating_sub(options.window)..position];
+        for &j in window.iter().rev() {
+            let base = &objects[j];
+            if base.object_type != target.object_type || depths[j] >= options.depth {
+                continue;
+            }
+            let chain = best.as_ref().map_or(1, |(best, _)| depths[*best] + 1);
+            let limit = max_size * (options.depth - depths[j]) / (options.depth - chain + 1);
+            let (base_size, target_size) = (base.data.len(), target.data.len());
+            if limit == 0
+                || target_size.saturating_sub(base_size) >= limit
+                || target_size < base_size / 32
+            {
+                continue;
+            }
+            if let Some(delta) = delta::create(&base.data, &target.data, Some(limit - 1)) {
+                max_size = delta.len();
+                best = Some((j, delta));
+            }
+        }
+        if let Some((base, delta)) = best {
+            depths[i] = depths[base] + 1;
+            deltas[i] = Some((base, delta));
+        }
+    }
+    deltas
+}
+
+/// Encodes `objects` as a pack, compressing them as deltas against each other as `deltas` says,
+/// returning the pack's bytes and the entries of its index. The objects are written in the order
+/// given, except that the bases of deltas are moved before them.
+pub fn encode(
+    objects: &[PackObject],
+    level: Compression,
+    deltas: &DeltaOptions,
+) -> (Vec<u8>, Vec<IndexEntry>) {
+    let _region = trace::region("encode pack");
+    let mut pack = PACK_MAGIC.to_vec();
+    pack.extend(2u32.to_be_bytes());
+    pack.extend((objects.len() as u32).to_be_bytes());
+
+    let found = match deltas.window {
+        0 => (0..objects.len()).map(|_| None).collect(),
+        _ => find_deltas(objects, deltas),
+    };
+    let mut offsets: Vec<Option<u64>> = vec![None; objects.len()];
+    let mut entries = Vec::with_capacity(objects.len());
+    for i in 0..objects.len() {
+        let mut chain = vec![i];
+        while let Some((base, _)) = found[*chain.last().expect("The chain has an object")] {
+            if offsets[base].is_some() {
+                break;
+            }
+            chain.push(base);
+        }
+
+        for &i in chain.iter().rev() {
+            if offsets[i].is_some() {
+                continue;
+            }
+            let object = &objects[i];
+            let offset = pack.len() as u64;
+            let data = match &found[i] {
+                Some((base, delta)) if deltas.offsets => {
+                    pack.extend(encode_entry_header(OFS_DELTA, delta.len()));
+                    let base = offsets[*base].expect("Bases are written first");
+                    pack.extend(encode_distance(offset - base));
+                    delta
+                }
+                Some((base, delta)) => {
+                    pack.extend(encode_entry_header(REF_DELTA, delta.len()));
+                    pack.extend(hex::decode(&objects[*base].hash).expect("Hashes are hex"));
+                    delta
+                }
+                None => {
+                    pack.extend(encode_entry_header(
+                        kind(&object.object_type),
+                        object.data.len(),
+                    ));
+                    &object.data
+                }
+            };
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder
+                .write_all(data)
+                .expect("Writing to memory can't fail");
+            pack.extend(encoder.finish().expect("Writing to memory can't fail"));
+
+            let mut crc = Crc::new();
+            crc.update(&pack[offset as usize..]);
+            entries.push(IndexEntry {
+                hash: object.hash.clone(),
+                crc: crc.sum(),
+                offset,
+            });
+            offsets[i] = Some(offset);
+        }
+    }
+    let checksum = Sha1::digest(&pack);
+    pack.extend(checksum);
+    (pack, entries)
+}
+
+/// Encodes the v2 index of the pack ending with `checksum` that has `entries`
+pub fn encode_index(entries: &mut [IndexEntry], checksum: &[u8]) -> Vec<u8> {
+    entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+    let mut index = IDX_V2_MAGIC.to_vec();
+    index.extend(2u32.to_be_bytes());
+
+    let mut fanout = [0u32; 256];
+    for entry in entries.iter() {
+        let first = u8::from_str_radix(&entry.hash[..2], 16).expect("Hashes are hex");
+        fanout[first as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        index.extend(total.to_be_bytes());
+    }
+    for entry in entries.iter() {
+        index.extend(hex::decode(&entry.hash).expect("Hashes are hex"));
+    }
+    for entry in entries.iter() {
+        index.extend(entry.crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for entry in entries.iter() {
+        match u32::try_from(entry.offset) {
+            Ok(offset) if offset & LARGE_OFFSET == 0 => index.extend(offset.to_be_bytes()),
+            _ => {
+                index.extend((LARGE_OFFSET | (large.len() / 8) as u32).to_be_bytes());
+                large.extend(entry.offset.to_be_bytes());
+            }
+        }
+    }
+    index.extend(large);
+    index.extend(checksum);
+    let own = Sha1::digest(&index);
+    index.extend(own);
+    index
+}
+
+/// Writes `objects` to a new pack in the repository named `<prefix>-<hash>.pack`, with its index,
+/// returning the pack's path
+pub fn write(
+    repository: &Repository,
+    prefix: &str,
+    objects: &[PackObject],
+) -> anyhow::Result<PathBuf> {
+    let level = object::pack_compression(repository)?;
+    let deltas = DeltaOptions::load(&Config::load(repository)?, true)?;
+    let dir = repository.get_path(&["objects", "pack"]);
+    fs::create_dir_all(&dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let name = write_at(&dir.join(prefix), objects, level, &deltas)?;
+    Ok(dir.join(format!("{}-{}.pack", prefix, name)))
+}
+
+/// The path `write` would write `objects` to, which is named after what the pack holds, without
+/// writing anything
+pub fn path_of(
+    repository: &Repository,
+    prefix: &str,
+    objects: &[PackObject],
+) -> anyhow::Result<PathBuf> {
+    let level = object::pack_compression(repository)?;
+    let deltas = DeltaOptions::load(&Config::load(repository)?, true)?;
+    let (pack, _) = encode(objects, level, &deltas);
+    let hash = hex::encode(&pack[pack.len() - 20..]);
+    Ok(repository
+        .get_path(&["objects", "pack"])
+        .join(format!("{}-{}.pack", prefix, hash)))
+}
+
+/// Writes `objects` to a new pack at `<base>-<hash>.pack`, with its index next to it, returning
+/// the hash
+pub fn write_at(
+    base: &Path,
+    objects: &[PackObject],
+    level: Compression,
+    deltas: &DeltaOptions,
+) -> anyhow::Result<String> {
+    let _region = trace::region("write pack");
+    let (pack, mut entries) = encode(objects, level, deltas);
+    let checksum = &pack[pack.len() - 20..];
+    let index = encode_index(&mut entries, checksum);
+
+    let hash = hex::encode(checksum);
+    let name = format!(
+        "{}-{}",
+        base.file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+        hash
+    );
+    let dir = match base.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    // The index goes in last, since packs are found by their index
+    for (data, extension) in [(&pack, "pack"), (&index, "idx")] {
+        let target = dir.join(format!("{}.{}", name, extension));
+        let temporary = dir.join(format!("tmp_{}_{}", extension, name));
+        fs::write(&temporary, data)
+            .with_context(|| format!("Could not write {}", temporary.display()))?;
+        fs::rename(&temporary, &target)
+            .with_context(|| format!("Could not write {}", target.display()))?;
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn object(object_type: ObjectType, data: &[u8], path: Option<&str>) -> PackObject {
+        PackObject {
+            hash: GitrsObject::hash_data(object_type.clone(), data),
+            object_type,
+            data: data.to_vec(),
+            path: path.map(str::to_string),
+        }
+    }
+
+    // A few blobs, two of which are versions of the same file that delta well against each other,
+    // and a tree holding them
+    fn objects() -> Vec<PackObject> {
+        let text: String = (0..200).map(|i| format!("line {}\n", i)).collect();
+        let changed = text.replace("line 100\n", "changed\n");
+        let mut objects = vec![
+            object(ObjectType::Blob, b"hello\n", Some("hello")),
+            object(ObjectType::Blob, text.as_bytes(), Some("lines")),
+            object(ObjectType::Blob, changed.as_bytes(), Some("lines")),
+        ];
+        let mut tree = Vec::new();
+        for (name, object) in [("hello", &objects[0]), ("lines", &objects[2])] {
+            tree.extend(format!("100644 {}\0", name).as_bytes());
+            tree.extend(hex::decode(&object.hash).unwrap());
+        }
+        objects.push(object(ObjectType::Tree, &tree, None));
+        objects
+    }
+
+    fn deltas(window: usize, offsets: bool) -> DeltaOptions {
+        DeltaOptions {
+            window,
+            depth: 50,
+            offsets,
+        }
+    }
+
+    // The type of each entry in `pack`, in order
+    fn kinds(pack: &[u8], entries: &[IndexEntry]) -> Vec<u8> {
+        let mut offsets: Vec<u64> = entries.iter().map(|entry| entry.offset).collect();
+        offsets.sort();
+        offsets
+            .into_iter()
+            .map(|offset| {
+                let mut reader = &pack[offset as usize..];
+                read_entry_header(&mut reader, offset).unwrap().kind
+            })
+            .collect()
+    }
+
+    fn sorted(objects: Vec<PackObject>) -> Vec<(String, ObjectType, Vec<u8>)> {
+        let mut objects: Vec<_> = objects
+            .into_iter()
+            .map(|object| (object.hash, object.object_type, object.data))
+            .collect();
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        objects
+    }
+
+    #[test]
+    fn entry_headers() {
+        assert_eq!(encode_entry_header(3, 15), [0x3f]);
+        assert_eq!(encode_entry_header(3, 100), [0xb4, 0x06]);
+        assert_eq!(encode_entry_header(1, 0x12345), [0x95, 0xb4, 0x24]);
+        assert_eq!(encode_distance(127), [0x7f]);
+        assert_eq!(encode_distance(128), [0x80, 0x00]);
+        assert_eq!(encode_distance(16512), [0x80, 0x80, 0x00]);
+        for (kind, size) in [(1, 0), (2, 15), (3, 16), (4, 1 << 40)] {
+            let header = encode_entry_header(kind, size);
+            let parsed = read_entry_header(&mut &header[..], 0).unwrap();
+            assert_eq!((parsed.kind, parsed.size), (kind, size));
+        }
+        for distance in [1, 127, 128, 16511, 16512, 1 << 33] {
+            let mut entry = encode_entry_header(OFS_DELTA, 1);
+            entry.extend(encode_distance(distance));
+            let parsed = read_entry_header(&mut &entry[..], distance).unwrap();
+            assert!(matches!(parsed.base, Base::Offset(0)));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let repository = Repository::new(&env::current_dir().unwrap());
+        let expected = sorted(objects());
+        for (options, kind) in [
+            (deltas(0, true), None),
+            (deltas(10, true), Some(OFS_DELTA)),
+            (deltas(10, false), Some(REF_DELTA)),
+        ] {
+            let (pack, entries) = encode(&objects(), Compression::default(), &options);
+            let kinds = kinds(&pack, &entries);
+            assert_eq!(
+                kinds.iter().filter(|&&found| found >= OFS_DELTA).count(),
+                kind.map_or(0, |_| 1)
+            );
+            if let Some(kind) = kind {
+                assert!(kinds.contains(&kind));
+            }
+            assert_eq!(sorted(parse(&repository, &pack).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn index_matches_git() {
+        // Stored rather than compressed, so that the pack doesn't depend on zlib's version
+        let (pack, mut entries) = encode(&objects(), Compression::none(), &deltas(10, true));
+        let checksum = &pack[pack.len() - 20..];
+        // The name git 2.39 index-pack gives the pack, and the hash of the index it writes for it
+        assert_eq!(
+            hex::encode(checksum),
+            "ee41770ca0172a4b83b0a12f3ffceedd1fa04595"
+        );
+        let index = encode_index(&mut entries, checksum);
+        assert_eq!(
+            hex::encode(Sha1::digest(&index)),
+            "d6152dc5e6afcc5556cb71dfce3c1ac95e1b0c75"
+        );
+    }
+
+    #[test]
+    fn write_and_read() {
+        let dir = env::temp_dir().join(format!("gitrs-pack-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repository = Repository::new(&dir);
+        let before = objects();
+        let name = write_at(
+            &dir.join("pack"),
+            &before,
+            Compression::default(),
+            &deltas(10, true),
+        )
+        .unwrap();
+        let index = PackIndex::open(
+            &dir.join(format!("pack-{}.idx", name)),
+            DELTA_BASE_CACHE_LIMIT,
+        )
+        .unwrap();
+        assert_eq!(index.objects().len(), before.len());
+        // Deltas read the same whether or not their bases are kept
+        let uncached = PackIndex::open(&dir.join(format!("pack-{}.idx", name)), 0).unwrap();
+        for object in &before {
+            for index in [&index, &index, &uncached] {
+                let read = index.read(&repository, &object.hash).unwrap();
+                assert_eq!(
+                    read,
+                    Some((object.object_type.clone(), object.data.clone()))
+                );
+            }
+        }
+        assert_eq!(index.read(&repository, &"0".repeat(40)).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_against_index() {
+        let dir = env::temp_dir().join(format!("gitrs-verify-pack-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repository = Repository::new(&dir);
+        let name = write_at(
+            &dir.join("pack"),
+            &objects(),
+            Compression::default(),
+            &deltas(10, true),
+        )
+        .unwrap();
+        let path = dir.join(format!("pack-{}.idx", name));
+        let pack_size = fs::metadata(path.with_extension("pack")).unwrap().len();
+
+        let verified = verify(&repository, &path).unwrap();
+        assert_eq!(verified.len(), 4);
+        let stored: u64 = verified.iter().map(|(_, stored)| stored.stored_size).sum();
+        assert_eq!(stored, pack_size - 12 - 20);
+        let deltas: Vec<_> = verified
+            .iter()
+            .filter_map(|(_, stored)| stored.delta.as_ref())
+            .collect();
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(deltas[0].1, 1);
+
+        // A CRC that doesn't match its entry, in an index with the right checksum otherwise
+        let mut index = fs::read(&path).unwrap();
+        index[8 + FANOUT_SIZE + 4 * 20] ^= 0xff;
+        let end = index.len() - 20;
+        let checksum = Sha1::digest(&index[..end]);
+        index[end..].copy_from_slice(&checksum);
+        fs::write(&path, &index).unwrap();
+        assert!(verify(&repository, &path).is_err());
+        index[0] ^= 0xff;
+        fs::write(&path, &index).unwrap();
+        assert!(verify(&repository, &path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delta_base_cache_drops_oldest() {
+        let mut cache = DeltaBaseCache::new(10);
+        let data = |size: usize| Arc::new(vec![0; size]);
+        cache.insert(1, ObjectType::Blob, data(4));
+        cache.insert(2, ObjectType::Blob, data(4));
+        // Using the first makes the second the one to drop
+        assert!(cache.get(1).is_some());
+        cache.insert(3, ObjectType::Blob, data(4));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert_eq!(cache.size, 8);
+        // Whatever is larger than the limit isn't kept at all
+        cache.insert(4, ObjectType::Blob, data(11));
+        assert!(cache.get(4).is_none());
+        assert_eq!(cache.entries.len(), 2);
+    }
+
+    #[test]
+    fn bad_packs() {
+        let repository = Repository::new(&env::current_dir().unwrap());
+        let (pack, _) = encode(&objects(), Compression::default(), &deltas(10, true));
+        assert!(parse(&repository, &pack[..pack.len() - 1]).is_err());
+        assert!(parse(&repository, &pack[..pack.len() / 2]).is_err());
+        let mut junk = pack.clone();
+        junk.push(0);
+        assert!(parse(&repository, &junk).is_err());
+        let mut corrupt = pack;
+        corrupt[20] ^= 0xff;
+        assert!(parse(&repository, &corrupt).is_err());
+    }
+}
@@ -0,0 +1,246 @@
+// Lists the objects to put in a pack, like git pack-objects, which is how packs are made to send
+// elsewhere or back up. The objects are either listed one per line, each optionally followed by
+// the path it was found at, or with --revs found from revisions: one per line, `^` or --not
+// marking those whose history is left out, read up to the end or a blank line. Then the commits
+// reachable from the others are walked along with everything their trees hold, leaving out what
+// the trees of the commits at the edge of the left out history hold, so a pack sent with the
+// history the other side lacks only has what is new. The path each object is found at is kept,
+// which is how objects are matched up to look for deltas between them.
+//
+// Like git, history is walked as it was recorded rather than as replace refs rewrite it, so that
+// a pack holds the true parents of its commits.
+use std::collections::HashSet;
+
+use anyhow::{Context, anyhow, bail};
+
+use crate::object::tree::Leaf;
+use crate::object::{GitrsObject, ObjectType};
+use crate::pack::{PackIndex, PackObject};
+use crate::refs::Ref;
+use crate::repository::Repository;
+use crate::revwalk::{self, Side};
+
+#[derive(Default)]
+pub struct PackObjectsOptions {
+    /// Read revisions rather than objects
+    pub revs: bool,
+    /// Pack everything reachable from refs and HEAD, as though they were listed as revisions
+    pub all: bool,
+    /// Leave out objects in packs with a `.keep` file
+    pub honor_pack_keep: bool,
+    /// Leave out objects that are packed already
+    pub incremental: bool,
+}
+
+/// Lists and reads the objects `input` names, as `options` says, commits first
+pub fn collect(
+    repository: &Repository,
+    input: &str,
+    options: &PackObjectsOptions,
+) -> anyhow::Result<Vec<PackObject>> {
+    let listed = match options.revs || options.all {
+        true => walk(repository, input, options.all)?,
+        false => list(input)?,
+    };
+
+    let packs = PackIndex::load_all(repository)?;
+    let left_out = |hash: &str| {
+        packs.iter().any(|index| {
+            (options.incremental || (options.honor_pack_keep && index.is_kept()))
+                && index.contains(hash)
+        })
+    };
+    let mut objects = Vec::with_capacity(listed.len());
+    for (hash, path) in listed {
+        if left_out(&hash) {
+            continue;
+        }
+        let (object_type, data) = GitrsObject::read_data(repository, &hash)
+            .with_context(|| format!("Could not read object {}", hash))?;
+        objects.push(PackObject {
+            hash,
+            object_type,
+            data,
+            path,
+        });
+    }
+    Ok(objects)
+}
+
+// Reads objects listed one per line
+fn list(input: &str) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    for line in input.lines().filter(|line| !line.is_empty()) {
+        let (hash, path) = match line.split_once(' ') {
+            Some((hash, path)) => (hash, Some(path.to_string())),
+            None => (line, None),
+        };
+        if hash.len() != 40 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            bail!("Expected an object hash, got: {}", line);
+        }
+        let hash = hash.to_ascii_lowercase();
+        if seen.insert(hash.clone()) {
+            objects.push((hash, path));
+        }
+    }
+    Ok(objects)
+}
+
+// Finds the objects reachable from the revisions listed, and not from those left out
+fn walk(
+    repository: &Repository,
+    input: &str,
+    all: bool,
+) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let (mut positive, mut negative) = (Vec::new(), Vec::new());
+    if all {
+        positive.extend(Ref::list(repository)?.into_iter().map(|(_, hash)| hash));
+        // An unborn branch has no commit yet
+        positive.extend(GitrsObject::find(repository, "HEAD").ok());
+    }
+    let mut not = false;
+    for line in input.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let find = |name: &str| {
+            GitrsObject::find(repository, name).with_context(|| format!("Bad revision '{}'", name))
+        };
+        if line == "--not" {
+            not = !not;
+        } else if line.starts_with("--") {
+            bail!("Unsupported revision option: {}", line);
+        } else if let Some((a, b)) = line.split_once("..") {
+            negative.push(find(a)?);
+            positive.push(find(b)?);
+        } else if let Some(name) = line.strip_prefix('^') {
+            (if not { &mut positive } else { &mut negative }).push(find(name)?);
+        } else {
+            (if not { &mut negative } else { &mut positive }).push(find(line)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    // The objects named, peeled down to the commits to walk from, keeping the tags along the way
+    let mut tags = Vec::new();
+    let mut tips = Vec::new();
+    let mut roots = Vec::new();
+    for hash in positive {
+        let mut hash = hash;
+        loop {
+            if seen.contains(&hash) {
+                break;
+            }
+            match GitrsObject::read_raw(repository, &hash)? {
+                GitrsObject::TagObject(tag) => {
+                    let object = tag
+                        .object()
+                        .ok_or_else(|| anyhow!("Malformed tag: {}", hash))?
+                        .to_string();
+                    seen.insert(hash.clone());
+                    tags.push((hash, None));
+                    hash = object;
+                }
+                GitrsObject::CommitObject(_) => {
+                    tips.push(hash);
+                    break;
+                }
+                object => {
+                    roots.push((hash, object.get_type()));
+                    break;
+                }
+            }
+        }
+    }
+    let mut hidden = Vec::new();
+    for hash in negative {
+        match revwalk::peel_to_commit(repository, &hash)? {
+            Some(commit) => hidden.push(commit),
+            None => bail!("Expected a commit to leave out: {}", hash),
+        }
+    }
+
+    let commits: Vec<String> = revwalk::difference(repository, &[], &tips, &hidden)?
+        .into_iter()
+        .filter(|(_, side)| *side == Side::Right)
+        .map(|(hash, _)| hash)
+        .collect();
+    // What the commits at the edge of the history left out hold is left out too
+    let walked: HashSet<&String> = commits.iter().collect();
+    let mut edge = hidden;
+    let mut trees = Vec::with_capacity(commits.len());
+    for hash in &commits {
+        let commit = revwalk::read_commit(repository, hash)?;
+        edge.extend(
+            commit
+                .parents()
+                .iter()
+                .filter(|parent| !walked.contains(parent))
+                .cloned(),
+        );
+        trees.push((commit.get_tree_hash().clone(), ObjectType::Tree));
+    }
+    for hash in edge {
+        let tree = revwalk::read_commit(repository, &hash)?
+            .get_tree_hash()
+            .clone();
+        mark_seen(repository, &tree, &mut seen)?;
+    }
+
+    let mut objects: Vec<(String, Option<String>)> =
+        commits.iter().map(|hash| (hash.clone(), None)).collect();
+    objects.extend(tags);
+    for (root, object_type) in trees.into_iter().chain(roots) {
+        let mut pending = vec![(root, String::new(), object_type)];
+        while let Some((hash, path, object_type)) = pending.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            if object_type == ObjectType::Tree {
+                for leaf in read_tree(repository, &hash)?.into_iter().rev() {
+                    let name = leaf.path.to_string_lossy();
+                    let path = match path.as_str() {
+                        "" => name.into_owned(),
+                        _ => format!("{}/{}", path, name),
+                    };
+                    let object_type = Leaf::get_type_from_mode(&leaf.file_mode);
+                    pending.push((leaf.hash, path, object_type));
+                }
+            }
+            objects.push((hash, Some(path).filter(|path| !path.is_empty())));
+        }
+    }
+    Ok(objects)
+}
+
+// Reads the entries of a tree, leaving out submodule commits, which live in another repository
+fn read_tree(repository: &Repository, hash: &str) -> anyhow::Result<Vec<Leaf>> {
+    match GitrsObject::read_raw(repository, hash)? {
+        GitrsObject::TreeObject(tree) => Ok(tree
+            .records
+            .into_iter()
+            .filter(|leaf| Leaf::get_type_from_mode(&leaf.file_mode) != ObjectType::Commit)
+            .collect()),
+        _ => bail!("Expected a tree object: {}", hash),
+    }
+}
+
+// Marks the tree `hash` and everything it holds as seen, so that it is left out
+fn mark_seen(
+    repository: &Repository,
+    hash: &str,
+    seen: &mut HashSet<String>,
+) -> anyhow::Result<()> {
+    let mut pending = vec![(hash.to_string(), ObjectType::Tree)];
+    while let Some((hash, object_type)) = pending.pop() {
+        if seen.insert(hash.clone()) && object_type == ObjectType::Tree {
+            pending.extend(read_tree(repository, &hash)?.into_iter().map(|leaf| {
+                let object_type = Leaf::get_type_from_mode(&leaf.file_mode);
+                (leaf.hash, object_type)
+            }));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,473 @@
+// Formats changes as the patches git diff prints: for each file a `diff --git` header, lines
+// describing a creation, deletion or mode change, the abbreviated hashes of both versions, and
+// hunks of the lines that differ with a few unchanged lines around them for context. Each hunk
+// header ends with the nearest line before it that the file's diff driver's funcname patterns
+// find, or else that starts like a function definition would, as git does by default. A file
+// changing type is shown as a deletion followed by a creation, and
+// binary files are only reported as differing, unless a binary patch that git apply can apply is
+// asked for. With a word diff the hunks show the changes word by word instead, in color if asked
+// to, as some of git's styles are.
+use std::env;
+
+use anyhow::Context;
+
+use crate::attributes::Attributes;
+use crate::binary_patch;
+use crate::config::Config;
+use crate::diff::{Change, NO_MODE, Status};
+use crate::line_diff::{self, Algorithm, DiffOptions, Region, Whitespace};
+use crate::merge_file;
+use crate::object::blob::Blob;
+use crate::object::{self, GitrsObject};
+use crate::refs::ZERO_HASH;
+use crate::regex::Regex;
+use crate::repository::Repository;
+use crate::userdiff::{self, Driver, Funcname, Side};
+use crate::word_diff::{self, WordDiff};
+
+/// How many unchanged lines git shows around each change by default
+pub const DEFAULT_CONTEXT: usize = 3;
+// How many characters of each hash the index line shows
+const ABBREV: usize = 7;
+// The longest function line shown in a hunk header
+const FUNCNAME_LENGTH: usize = 80;
+
+// git's default colors for each part of a patch
+const META_COLOR: &str = "\x1b[1m";
+const FRAGINFO_COLOR: &str = "\x1b[36m";
+pub const OLD_COLOR: &str = "\x1b[31m";
+pub const NEW_COLOR: &str = "\x1b[32m";
+pub const RESET: &str = "\x1b[m";
+
+pub struct PatchOptions {
+    /// How many unchanged lines to show around each change
+    pub context: usize,
+    pub whitespace: Whitespace,
+    pub algorithm: Algorithm,
+    /// Show the changes word by word, in this style
+    pub word_diff: Option<word_diff::Style>,
+    /// What counts as a word, over the diff driver's pattern and diff.wordRegex
+    pub word_regex: Option<String>,
+    /// Convert files to text with their diff driver's textconv program before comparing them
+    pub textconv: bool,
+    /// Show changes with the external program GIT_EXTERNAL_DIFF, diff.external or the diff
+    /// driver's command name, instead of as a patch
+    pub external_diff: bool,
+    /// Show binary files as `GIT binary patch` sections, and hashes in full
+    pub binary: bool,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            context: DEFAULT_CONTEXT,
+            whitespace: Whitespace::Exact,
+            algorithm: Algorithm::Myers,
+            word_diff: None,
+            word_regex: None,
+            textconv: false,
+            external_diff: false,
+            binary: false,
+        }
+    }
+}
+
+/// Formats the patch for `changes`. Files whose only differences are in the whitespace being
+/// ignored are left out.
+pub fn format(
+    repository: &Repository,
+    changes: &[Change],
+    options: &PatchOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let config = Config::load(repository)?;
+    let mut attributes = Attributes::new(repository);
+    let external_diff = env::var("GIT_EXTERNAL_DIFF")
+        .ok()
+        .or_else(|| config.get("diff.external").map(String::from))
+        .filter(|_| options.external_diff);
+    let mut external_diffs = 0;
+    let mut patch = Vec::new();
+    for change in changes {
+        let driver = userdiff::driver(&config, &mut attributes, &change.path)?;
+        let program = driver
+            .as_ref()
+            .and_then(|driver| driver.command.clone())
+            .filter(|_| options.external_diff)
+            .or_else(|| external_diff.clone());
+        if let Some(program) = program {
+            external_diffs += 1;
+            patch.extend(run_external_diff(
+                repository,
+                &program,
+                change,
+                external_diffs,
+                changes.len(),
+            )?);
+            continue;
+        }
+
+        let word_regex = match options.word_diff {
+            Some(_) => word_regex(&config, driver.as_ref(), options)?,
+            None => None,
+        };
+        let funcname = match &driver {
+            Some(driver) => driver.funcname()?,
+            None => None,
+        };
+        let textconv = driver.as_ref().filter(|_| options.textconv);
+        let format_file = |change: &Change| {
+            format_file(
+                repository,
+                &config,
+                change,
+                options,
+                textconv,
+                word_regex.as_ref(),
+                funcname.as_ref(),
+            )
+        };
+        if change.status != Status::TypeChanged {
+            patch.extend(format_file(change)?);
+            continue;
+        }
+        let deletion = Change {
+            old_mode: change.old_mode.clone(),
+            new_mode: NO_MODE.to_string(),
+            old_hash: change.old_hash.clone(),
+            new_hash: ZERO_HASH.to_string(),
+            status: Status::Deleted,
+            path: change.path.clone(),
+        };
+        let creation = Change {
+            old_mode: NO_MODE.to_string(),
+            new_mode: change.new_mode.clone(),
+            old_hash: ZERO_HASH.to_string(),
+            new_hash: change.new_hash.clone(),
+            status: Status::Added,
+            path: change.path.clone(),
+        };
+        patch.extend(format_file(&deletion)?);
+        patch.extend(format_file(&creation)?);
+    }
+    Ok(patch)
+}
+
+// Shows `change` with an external diff program, the `counter`th of `total` changes
+fn run_external_diff(
+    repository: &Repository,
+    program: &str,
+    change: &Change,
+    counter: usize,
+    total: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let (old, new) = (
+        change.old_content(repository)?,
+        change.new_content(repository)?,
+    );
+    let old = Side {
+        path: &change.path,
+        mode: &change.old_mode,
+        hash: &change.old_hash,
+        content: old.as_deref(),
+    };
+    let new = Side {
+        path: &change.path,
+        mode: &change.new_mode,
+        hash: &change.new_hash,
+        content: new.as_deref(),
+    };
+    userdiff::external_diff(
+        repository,
+        program,
+        &change.path,
+        [&old, &new],
+        counter,
+        total,
+    )
+}
+
+// Compiles the pattern words are found with in a file with `driver`, if any is set
+fn word_regex(
+    config: &Config,
+    driver: Option<&Driver>,
+    options: &PatchOptions,
+) -> anyhow::Result<Option<Regex>> {
+    let pattern = options
+        .word_regex
+        .clone()
+        .map(String::into_bytes)
+        .or_else(|| driver?.word_regex.clone())
+        .or_else(|| config.get("diff.wordRegex").map(Vec::from));
+    pattern
+        .map(|pattern| {
+            Regex::new(&pattern, false).with_context(|| {
+                format!(
+                    "invalid regular expression: {}",
+                    String::from_utf8_lossy(&pattern)
+                )
+            })
+        })
+        .transpose()
+}
+
+// Formats the patch for a file, its content converted to text first by `textconv`'s program if
+// it has one, and its hunk headers found with `funcname` if set
+fn format_file(
+    repository: &Repository,
+    config: &Config,
+    change: &Change,
+    options: &PatchOptions,
+    textconv: Option<&Driver>,
+    word_regex: Option<&Regex>,
+    funcname: Option<&Funcname>,
+) -> anyhow::Result<Vec<u8>> {
+    let color = options.word_diff == Some(word_diff::Style::Color);
+    let old = change.old_content(repository)?;
+    let new = change.new_content(repository)?;
+    let path = &change.path;
+    let mut header = format!("diff --git a/{} b/{}\n", path, path);
+    match change.status {
+        Status::Added => header.push_str(&format!("new file mode {}\n", change.new_mode)),
+        Status::Deleted => header.push_str(&format!("deleted file mode {}\n", change.old_mode)),
+        _ if change.old_mode != change.new_mode => header.push_str(&format!(
+            "old mode {}\nnew mode {}\n",
+            change.old_mode, change.new_mode
+        )),
+        _ => {}
+    }
+
+    // Worktree files aren't hashed when they are found to differ, so are hashed here
+    let (old_hash, new_hash) = (hash(&old, &change.old_hash), hash(&new, &change.new_hash));
+    if old_hash == new_hash {
+        return Ok(meta(&header, color));
+    }
+    // Hashes are shown in full for binary patches, which are checked against them
+    let binary = |content: &Option<Vec<u8>>| content.as_deref().is_some_and(merge_file::is_binary);
+    let abbrev = match options.binary && (binary(&old) || binary(&new)) {
+        true => old_hash.len(),
+        false => ABBREV,
+    };
+    header.push_str(&format!(
+        "index {}..{}",
+        &old_hash[..abbrev],
+        &new_hash[..abbrev]
+    ));
+    if change.old_mode == change.new_mode {
+        header.push_str(&format!(" {}", change.new_mode));
+    }
+    header.push('\n');
+
+    let side = |prefix: &str, content: &Option<Vec<u8>>| match content {
+        Some(_) => format!("{}/{}", prefix, path),
+        None => "/dev/null".to_string(),
+    };
+    let (old_name, new_name) = (side("a", &old), side("b", &new));
+    let convert = |mode, hash, content: &Option<Vec<u8>>| match textconv {
+        Some(driver) => {
+            let side = Side {
+                path,
+                mode,
+                hash,
+                content: content.as_deref(),
+            };
+            driver.convert(repository, config, &side)
+        }
+        None => Ok(None),
+    };
+    let converted = [
+        convert(&change.old_mode, &change.old_hash, &old)?,
+        convert(&change.new_mode, &change.new_hash, &new)?,
+    ];
+    let text = textconv.is_some_and(|driver| driver.textconv.is_some());
+    let [old, new] = [(old, 0), (new, 1)]
+        .map(|(content, side)| converted[side].clone().or(content).unwrap_or_default());
+    if !text && (merge_file::is_binary(&old) || merge_file::is_binary(&new)) {
+        let mut patch = meta(&header, color);
+        if options.binary {
+            let level = object::loose_compression(repository)?;
+            patch.extend(binary_patch::format(&old, &new, level)?);
+        } else {
+            patch.extend(format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes());
+        }
+        return Ok(patch);
+    }
+
+    let (old, new) = (line_diff::lines(&old), line_diff::lines(&new));
+    let diff_options = DiffOptions {
+        whitespace: options.whitespace,
+        indent_heuristic: true,
+        algorithm: options.algorithm,
+    };
+    let regions = line_diff::diff_lines(&old, &new, &diff_options);
+    if regions.is_empty() {
+        let unchanged = change.status == Status::Modified && change.old_mode == change.new_mode;
+        return Ok(if unchanged {
+            Vec::new()
+        } else {
+            meta(&header, color)
+        });
+    }
+    header.push_str(&format!("--- {}\n+++ {}\n", old_name, new_name));
+    let mut patch = meta(&header, color);
+    let mut words = options
+        .word_diff
+        .map(|style| WordDiff::new(style, word_regex));
+    write_hunks(
+        &mut patch, &old, &new, &regions, options, &mut words, funcname,
+    );
+    Ok(patch)
+}
+
+/// Formats the hunks of the changes from `old` to `new` alone, without any header, their hunk
+/// headers found with `funcname` if set
+pub fn hunks(
+    old: &[u8],
+    new: &[u8],
+    diff_options: &DiffOptions,
+    funcname: Option<&Funcname>,
+) -> Vec<u8> {
+    let (old, new) = (line_diff::lines(old), line_diff::lines(new));
+    let regions = line_diff::diff_lines(&old, &new, diff_options);
+    let mut patch = Vec::new();
+    let options = PatchOptions::default();
+    write_hunks(
+        &mut patch, &old, &new, &regions, &options, &mut None, funcname,
+    );
+    patch
+}
+
+// Colors each line of a patch's header, if asked to
+fn meta(header: &str, color: bool) -> Vec<u8> {
+    if !color {
+        return header.as_bytes().to_vec();
+    }
+    header
+        .lines()
+        .flat_map(|line| format!("{}{}{}\n", META_COLOR, line, RESET).into_bytes())
+        .collect()
+}
+
+// The full hash of one side's content, which for the worktree isn't known yet
+fn hash(content: &Option<Vec<u8>>, hash: &str) -> String {
+    match content {
+        Some(data) if hash == ZERO_HASH => GitrsObject::BlobObject(Blob::new(data.clone())).hash(),
+        _ => hash.to_string(),
+    }
+}
+
+// Writes the hunks showing `regions`, those whose context would touch or overlap joined into one,
+// through `words` for a word diff. Unchanged lines are shown from the new side, which only makes
+// a difference when whitespace is ignored.
+fn write_hunks(
+    patch: &mut Vec<u8>,
+    old: &[&[u8]],
+    new: &[&[u8]],
+    regions: &[Region],
+    options: &PatchOptions,
+    words: &mut Option<WordDiff>,
+    matcher: Option<&Funcname>,
+) {
+    let context = options.context;
+    let color = options.word_diff == Some(word_diff::Style::Color);
+    let mut first = 0;
+    while first < regions.len() {
+        let mut last = first;
+        while last + 1 < regions.len()
+            && regions[last + 1].old.start - regions[last].old.end <= 2 * context
+        {
+            last += 1;
+        }
+        let hunk = &regions[first..=last];
+        let before = context.min(hunk[0].old.start);
+        let after = context.min(old.len() - hunk[hunk.len() - 1].old.end);
+        let old_start = hunk[0].old.start - before;
+        let new_start = hunk[0].new.start - before;
+        let old_end = hunk[hunk.len() - 1].old.end + after;
+        let new_end = hunk[hunk.len() - 1].new.end + after;
+
+        let ranges = format!(
+            "@@ -{} +{} @@",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        );
+        let funcname = funcname(&old[..old_start], matcher);
+        if color {
+            patch.extend(format!("{}{}{}", FRAGINFO_COLOR, ranges, RESET).as_bytes());
+            if let Some(funcname) = funcname {
+                patch.extend(format!(" {}", RESET).as_bytes());
+                patch.extend_from_slice(funcname);
+                patch.extend_from_slice(RESET.as_bytes());
+            }
+        } else {
+            patch.extend_from_slice(ranges.as_bytes());
+            if let Some(funcname) = funcname {
+                patch.push(b' ');
+                patch.extend_from_slice(funcname);
+            }
+        }
+        patch.push(b'\n');
+
+        let mut position = new_start;
+        for region in hunk {
+            write_lines(patch, b' ', &new[position..region.new.start], words);
+            write_lines(patch, b'-', &old[region.old.clone()], words);
+            write_lines(patch, b'+', &new[region.new.clone()], words);
+            position = region.new.end;
+        }
+        write_lines(patch, b' ', &new[position..new_end], words);
+        if let Some(words) = words {
+            words.flush(patch);
+        }
+        first = last + 1;
+    }
+}
+
+// Formats a hunk's range of lines as git does: 1-based, with the count left out if it is 1, and
+// an empty range given by the line before it
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+fn write_lines(patch: &mut Vec<u8>, prefix: u8, lines: &[&[u8]], words: &mut Option<WordDiff>) {
+    if let Some(words) = words {
+        for line in lines {
+            match prefix {
+                b'-' => words.remove(line),
+                b'+' => words.add(line),
+                _ => words.context(patch, line),
+            }
+        }
+        return;
+    }
+    for line in lines {
+        patch.push(prefix);
+        patch.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            patch.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+// Finds what the hunk header shows of the last of `lines` that `matcher` finds, or without one,
+// that starts with a letter, `_` or `$`, which git takes to be where the function around a hunk
+// begins
+fn funcname<'a>(lines: &[&'a [u8]], matcher: Option<&Funcname>) -> Option<&'a [u8]> {
+    let line = lines.iter().rev().find_map(|&line| match matcher {
+        Some(matcher) => matcher.find(line),
+        None => line
+            .first()
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+            .then_some(line),
+    })?;
+    let mut line = &line[..line.len().min(FUNCNAME_LENGTH)];
+    while let Some((last, rest)) = line.split_last()
+        && line_diff::is_space(last)
+    {
+        line = rest;
+    }
+    (!line.is_empty()).then_some(line)
+}
@@ -0,0 +1,294 @@
+// Computes patch ids, which identify the change a patch makes regardless of where it is applied:
+// the hash of the patch with its whitespace, line numbers and hashes left out, so that a commit
+// cherry-picked elsewhere keeps its id. Ids are read from patches the way git patch-id reads them,
+// from the first `diff` line after each `commit <hash>` or `From <hash>` line to the end of the
+// last hunk, and computed for a commit the way git cherry compares commits, from its diff with its
+// parent. A binary file counts as the hashes of its two sides. Unstable ids hash the whole patch at
+// once, while stable ids add up the hash of each file, so that reordering files keeps the id.
+use std::io::BufRead;
+
+use sha1::{Digest, Sha1};
+
+use crate::diff::{self, Change, NO_MODE, Status};
+use crate::line_diff::{self, DiffOptions};
+use crate::merge_file;
+use crate::object::commit::Commit;
+use crate::object::tree::Tree;
+use crate::patch;
+use crate::pathspec::Pathspec;
+use crate::refs::ZERO_HASH;
+use crate::repository::Repository;
+
+/// How patch ids are computed
+#[derive(Clone, Copy)]
+pub struct Mode {
+    /// Add up the hash of each file instead of hashing the whole patch
+    pub stable: bool,
+    /// Keep the whitespace
+    pub verbatim: bool,
+}
+
+// The lines of a hunk git leaves out of what it hashes, like `\ No newline at end of file`
+fn is_comment(line: &[u8]) -> bool {
+    line.starts_with(b"\\ ") && line.len() > 12
+}
+
+/// Reads the patches in `input`, returning the id of each that has any changes, along with the
+/// commit it was found after (ZERO_HASH if none)
+pub fn read(mut input: impl BufRead, mode: Mode) -> anyhow::Result<Vec<(String, String)>> {
+    let mut ids = Vec::new();
+    let mut commit = ZERO_HASH.to_string();
+    let mut line = Vec::new();
+    loop {
+        let (length, id, next) = read_one(&mut input, &mut line, mode)?;
+        if length > 0 {
+            ids.push((hex::encode(id), commit));
+        }
+        commit = next.unwrap_or_else(|| ZERO_HASH.to_string());
+        if input.fill_buf()?.is_empty() {
+            return Ok(ids);
+        }
+    }
+}
+
+// Reads one patch, returning how many bytes it hashed, its id, and the commit the next patch
+// belongs to if it stopped at one
+fn read_one(
+    input: &mut impl BufRead,
+    line: &mut Vec<u8>,
+    mode: Mode,
+) -> anyhow::Result<(usize, [u8; 20], Option<String>)> {
+    let mut hasher = Sha1::new();
+    let mut id = [0; 20];
+    let mut length = 0;
+    // How many lines are left in the hunk on each side, or -1 while in a file's header
+    let (mut before, mut after) = (-1, -1);
+    let mut binary = false;
+    let (mut old_hash, mut new_hash) = (Vec::new(), Vec::new());
+    loop {
+        line.clear();
+        if input.read_until(b'\n', line)? == 0 {
+            break;
+        }
+        // Like git, lines are taken to end at a NUL
+        if let Some(end) = line.iter().position(|&b| b == 0) {
+            line.truncate(end);
+        }
+
+        let rest = match (line.strip_prefix(b"commit "), line.strip_prefix(b"From ")) {
+            (Some(rest), _) | (_, Some(rest)) => rest,
+            _ if is_comment(line) => {
+                if mode.verbatim {
+                    hasher.update(&line);
+                }
+                continue;
+            }
+            _ => line,
+        };
+        if rest.len() >= 40 && rest[..40].iter().all(u8::is_ascii_hexdigit) {
+            let next = String::from_utf8_lossy(&rest[..40]).to_ascii_lowercase();
+            return Ok((length, flush(id, hasher), Some(next)));
+        }
+
+        // What comes before the first diff is the commit message
+        if length == 0 && !line.starts_with(b"diff ") {
+            continue;
+        }
+
+        if before == -1 {
+            if line.starts_with(b"GIT binary patch") || line.starts_with(b"Binary files") {
+                binary = true;
+                before = 0;
+                hasher.update(&old_hash);
+                hasher.update(&new_hash);
+                if mode.stable {
+                    id = flush(id, std::mem::take(&mut hasher));
+                }
+                continue;
+            } else if let Some(hashes) = line.strip_prefix(b"index ") {
+                // The hashes are kept for binary files, which are only told apart by them
+                let hashes = hashes.strip_suffix(b"\n").unwrap_or(hashes);
+                if let Some(dots) = hashes.windows(2).position(|w| w == b"..") {
+                    let new = &hashes[dots + 2..];
+                    let end = new.iter().position(|&b| b == b' ').unwrap_or(new.len());
+                    old_hash = hashes[..dots].to_vec();
+                    new_hash = new[..end].to_vec();
+                }
+                continue;
+            } else if line.starts_with(b"--- ") {
+                (before, after) = (1, 1);
+            } else if !line.first().is_some_and(u8::is_ascii_alphabetic) {
+                break;
+            }
+        }
+
+        if binary {
+            if line.starts_with(b"diff ") {
+                binary = false;
+                before = -1;
+            }
+            continue;
+        }
+
+        if before == 0 && after == 0 {
+            // Only the number of lines a hunk has matters, not where it is
+            if line.starts_with(b"@@ -") {
+                (before, after) = scan_hunk_header(line, after);
+                continue;
+            }
+            if !line.starts_with(b"diff ") {
+                break;
+            }
+            // Another file's header
+            if mode.stable {
+                id = flush(id, std::mem::take(&mut hasher));
+            }
+            (before, after) = (-1, -1);
+        }
+
+        if matches!(line.first(), Some(b'-' | b' ')) {
+            before -= 1;
+        }
+        if matches!(line.first(), Some(b'+' | b' ')) {
+            after -= 1;
+        }
+        match mode.verbatim {
+            true => {
+                length += line.len();
+                hasher.update(&line);
+            }
+            false => length += update(&mut hasher, line),
+        }
+    }
+    Ok((length, flush(id, hasher), None))
+}
+
+// Reads how many lines each side of a hunk has from its header, `@@ -<start>[,<count>]
+// +<start>[,<count>] @@`, a missing count being 1. Like git, a malformed header
+// leaves the count of the new side as it was.
+fn scan_hunk_header(line: &[u8], mut after: isize) -> (isize, isize) {
+    let digits = |data: &[u8]| data.iter().take_while(|b| b.is_ascii_digit()).count();
+    let number = |data: &[u8]| {
+        let n = digits(data);
+        std::str::from_utf8(&data[..n.min(9)])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0)
+    };
+    let mut old = &line[4..];
+    let mut n = digits(old);
+    let before = match old.get(n) {
+        Some(b',') => {
+            old = &old[n + 1..];
+            n = digits(old);
+            number(old)
+        }
+        _ => 1,
+    };
+    if n == 0 || old.get(n) != Some(&b' ') || old.get(n + 1) != Some(&b'+') {
+        return (before, after);
+    }
+    let new = &old[n + 2..];
+    after = match new.get(digits(new)) {
+        Some(b',') => number(&new[digits(new) + 1..]),
+        _ => 1,
+    };
+    (before, after)
+}
+
+// Hashes `data` without its whitespace, returning how many bytes were hashed
+fn update(hasher: &mut Sha1, data: &[u8]) -> usize {
+    let data: Vec<u8> = data
+        .iter()
+        .copied()
+        .filter(|b| !line_diff::is_space(b))
+        .collect();
+    hasher.update(&data);
+    data.len()
+}
+
+// Adds the hash of what `hasher` was given to `id`, as a 20-byte number with its lowest byte first
+fn flush(id: [u8; 20], hasher: Sha1) -> [u8; 20] {
+    let hash = hasher.finalize();
+    let mut sum = [0; 20];
+    let mut carry = 0u16;
+    for (sum, (a, b)) in sum.iter_mut().zip(id.iter().zip(hash)) {
+        carry += *a as u16 + b as u16;
+        *sum = carry as u8;
+        carry >>= 8;
+    }
+    sum
+}
+
+/// Computes the unstable patch id of the change `commit` makes to its parent, or to the empty
+/// tree for a root commit. Merges have none.
+pub fn commit(repository: &Repository, commit: &Commit) -> anyhow::Result<Option<String>> {
+    let parent = match commit.parents() {
+        [] => Tree {
+            records: Vec::new(),
+        },
+        [parent] => Tree::from_name(repository, parent)?,
+        _ => return Ok(None),
+    };
+    let tree = Tree::from_name(repository, commit.get_tree_hash())?;
+    let changes = diff::diff_trees(repository, &parent, &tree, true, &Pathspec::default())?;
+    let mut hasher = Sha1::new();
+    for change in &changes {
+        hash_change(repository, &mut hasher, change)?;
+    }
+    Ok(Some(hex::encode(flush([0; 20], hasher))))
+}
+
+// Hashes the header of the change to one file, then its hunks, or for a binary file its hashes
+fn hash_change(repository: &Repository, hasher: &mut Sha1, change: &Change) -> anyhow::Result<()> {
+    let path = &change.path;
+    update(
+        hasher,
+        format!("diff --git a/{} b/{}", path, path).as_bytes(),
+    );
+    let (old_missing, new_missing) = (change.old_mode == NO_MODE, change.new_mode == NO_MODE);
+    if old_missing {
+        update(
+            hasher,
+            format!("new file mode {}", change.new_mode).as_bytes(),
+        );
+    } else if new_missing {
+        update(
+            hasher,
+            format!("deleted file mode {}", change.old_mode).as_bytes(),
+        );
+    } else if change.old_mode != change.new_mode {
+        update(
+            hasher,
+            format!("old mode {} new mode {}", change.old_mode, change.new_mode).as_bytes(),
+        );
+    }
+
+    let old = change.old_content(repository)?.unwrap_or_default();
+    let new = change.new_content(repository)?.unwrap_or_default();
+    if merge_file::is_binary(&old) || merge_file::is_binary(&new) {
+        hasher.update(change.old_hash.as_bytes());
+        hasher.update(change.new_hash.as_bytes());
+        return Ok(());
+    }
+    let old_name = match change.status {
+        Status::Added => "/dev/null".to_string(),
+        _ => format!("a/{}", path),
+    };
+    let new_name = match change.status {
+        Status::Deleted => "/dev/null".to_string(),
+        _ => format!("b/{}", path),
+    };
+    update(
+        hasher,
+        format!("--- {}+++ {}", old_name, new_name).as_bytes(),
+    );
+    // Like git, without the indent heuristic
+    let hunks = patch::hunks(&old, &new, &DiffOptions::default(), None);
+    for line in hunks.split_inclusive(|&b| b == b'\n') {
+        if !line.starts_with(b"@@ ") && !is_comment(line) {
+            update(hasher, line);
+        }
+    }
+    Ok(())
+}
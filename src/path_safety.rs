@@ -0,0 +1,134 @@
+// Validation of tree entry names before they are written to the worktree. A malicious tree could
+// otherwise escape the worktree (`..`, absolute paths) or overwrite the repository itself
+// (`.gitrs`, including the aliases NTFS and HFS+ resolve to it).
+
+use anyhow::bail;
+
+// Device names Windows reserves in every directory, regardless of extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Code points HFS+ ignores when comparing file names
+const HFS_IGNORABLE: [char; 16] = [
+    '\u{200c}', '\u{200d}', '\u{200e}', '\u{200f}', '\u{202a}', '\u{202b}', '\u{202c}', '\u{202d}',
+    '\u{202e}', '\u{206a}', '\u{206b}', '\u{206c}', '\u{206d}', '\u{206e}', '\u{206f}', '\u{feff}',
+];
+
+/// Filesystem quirks to guard against, as configured by core.protectNTFS and core.protectHFS
+pub struct Protections {
+    pub ntfs: bool,
+    pub hfs: bool,
+}
+
+/// Checks that a single tree entry name can be safely created inside the worktree
+pub fn verify_component(name: &str, protections: &Protections) -> anyhow::Result<()> {
+    if name.is_empty() || name == "." || name == ".." {
+        bail!("Invalid path '{}'", name);
+    }
+
+    if name.contains(['/', '\0']) {
+        bail!("Invalid path '{}': contains a path separator", name);
+    }
+
+    if is_gitdir_name(name)
+        || (protections.ntfs && is_ntfs_gitdir_alias(name))
+        || (protections.hfs && is_hfs_gitdir_alias(name))
+    {
+        bail!(
+            "Invalid path '{}': refers to the repository directory",
+            name
+        );
+    }
+
+    if cfg!(windows) {
+        verify_windows_component(name)?;
+    }
+
+    Ok(())
+}
+
+fn is_gitdir_name(name: &str) -> bool {
+    name.eq_ignore_ascii_case(".gitrs")
+}
+
+// NTFS strips trailing dots and spaces, treats anything after a `:` as a stream name, and gives
+// `.gitrs` the 8.3 short name `GITRS~1`
+fn is_ntfs_gitdir_alias(name: &str) -> bool {
+    let base = name.split(':').next().unwrap_or(name);
+    let base = base.trim_end_matches(['.', ' ']);
+    is_gitdir_name(base) || base.eq_ignore_ascii_case("gitrs~1")
+}
+
+// HFS+ ignores certain zero-width code points, so eg. `.git\u{200c}rs` names the repository
+fn is_hfs_gitdir_alias(name: &str) -> bool {
+    let stripped: String = name
+        .chars()
+        .filter(|c| !HFS_IGNORABLE.contains(c))
+        .collect();
+    is_gitdir_name(&stripped)
+}
+
+// Windows can't represent names containing path separators or reserved characters, silently
+// drops trailing dots and spaces, and maps device names to devices
+fn verify_windows_component(name: &str) -> anyhow::Result<()> {
+    if let Some(ch) = name
+        .chars()
+        .find(|&ch| matches!(ch, '\\' | ':' | '<' | '>' | '"' | '|' | '?' | '*') || ch < ' ')
+    {
+        bail!("Invalid path '{}': contains {:?}", name, ch);
+    }
+
+    if name.ends_with('.') || name.ends_with(' ') {
+        bail!("Invalid path '{}': ends with a dot or a space", name);
+    }
+
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        bail!("Invalid path '{}': reserved device name on Windows", name);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_names_like_git() {
+        let protections = [(false, false), (true, false), (false, true)]
+            .map(|(ntfs, hfs)| Protections { ntfs, hfs });
+        // Whether git 2.39 update-index takes each name (with .git for .gitrs) as a directory,
+        // plainly, with core.protectNTFS and with core.protectHFS. Windows refuses more names.
+        for (name, expected) in [
+            ("..", [false; 3]),
+            (".", [false; 3]),
+            ("", [false; 3]),
+            (".gitrs", [false; 3]),
+            (".GITRS", [false; 3]),
+            (".GitRs", [false; 3]),
+            (".git\u{200c}rs", [true, true, false]),
+            (".gitrs\u{feff}", [true, true, false]),
+            ("gitrs~1", [true, false, true]),
+            ("GITRS~1", [true, false, true]),
+            ("gitrs~2", [true; 3]),
+            (".gitrs.", [true, false, true]),
+            (".gitrs ", [true, false, true]),
+            (".gitrs. . ", [true, false, true]),
+            (".gitrs::$INDEX_ALLOCATION", [true, false, true]),
+            (".gitrs:stream", [true, false, true]),
+            (".gitrsx", [true; 3]),
+            ("...", [true; 3]),
+        ] {
+            for (protections, expected) in protections.iter().zip(expected) {
+                let ok = verify_component(name, protections).is_ok();
+                assert!(ok == expected || (cfg!(windows) && !ok), "{:?}", name);
+            }
+        }
+    }
+}
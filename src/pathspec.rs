@@ -0,0 +1,303 @@
+// Selects paths the way git's path arguments (pathspecs) do. A pathspec without wildcards matches
+// that path and everything below it, one with wildcards is matched against whole paths, with `*`
+// crossing directories. Magic given as `:(top,icase)path`, or in the short `:/path` and `:!path`
+// forms, changes how it matches:
+// - top: the path is relative to the worktree root instead of the current directory
+// - literal: `*`, `?` and `[` are ordinary characters
+// - glob: wildcards follow wildmatch rules, so only `**` crosses directories
+// - icase: letters match regardless of case
+// - exclude: paths it matches are left out, even if others match them
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::repository::Repository;
+use crate::wildmatch;
+
+#[derive(Default, Clone)]
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+#[derive(Clone)]
+struct Item {
+    // Relative to the worktree, `/` separated, and lowercase with icase
+    pattern: String,
+    // Length of the part of `pattern` before its first wildcard
+    fixed_len: usize,
+    glob: bool,
+    icase: bool,
+    exclude: bool,
+}
+
+impl Pathspec {
+    /// Parses path arguments given relative to the current directory. No arguments match
+    /// everything.
+    pub fn parse(repository: &Repository, args: &[String]) -> anyhow::Result<Self> {
+        let items = args
+            .iter()
+            .map(|arg| Item::parse(repository, arg))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { items })
+    }
+
+    /// The path of its first item, relative to the worktree
+    pub fn first_path(&self) -> Option<&str> {
+        self.items.first().map(|item| item.pattern.as_str())
+    }
+
+    /// Returns true if `path` (relative to the worktree, `/` separated) is selected
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let mut positive = self.items.iter().filter(|item| !item.exclude).peekable();
+        let included = positive.peek().is_none() || positive.any(|item| item.matches(path, is_dir));
+        included
+            && !self
+                .items
+                .iter()
+                .any(|item| item.exclude && item.matches(path, is_dir))
+    }
+
+    /// Returns true if something below `dir` could be selected, so that walks can skip the
+    /// directories that can't
+    pub fn could_match_below(&self, dir: &str) -> bool {
+        let dir = format!("{}/", dir);
+        let mut positive = self.items.iter().filter(|item| !item.exclude).peekable();
+        let included = positive.peek().is_none()
+            || positive.any(|item| {
+                let dir = item.fold(&dir);
+                let fixed = &item.pattern[..item.fixed_len];
+                // Either the pattern continues below `dir`, or `dir` is within what it matches
+                fixed.starts_with(dir.as_str())
+                    || (dir.starts_with(fixed) && (!item.is_literal() || item.matches_prefix(&dir)))
+            });
+        // An exclusion matching the directory itself covers everything below it too, unless its
+        // wildcards stop at slashes
+        included
+            && !self.items.iter().any(|item| {
+                item.exclude
+                    && (item.is_literal() || !item.glob)
+                    && item.matches(dir.trim_end_matches('/'), true)
+            })
+    }
+
+    /// Returns true if some pattern names a path below `dir`, without wildcards up to there. Such
+    /// directories are reported by walks that don't descend into subtrees.
+    pub fn leads_below(&self, dir: &str) -> bool {
+        let dir = format!("{}/", dir);
+        self.items.iter().any(|item| {
+            !item.exclude && item.pattern[..item.fixed_len].starts_with(item.fold(&dir).as_str())
+        })
+    }
+}
+
+impl Item {
+    fn parse(repository: &Repository, arg: &str) -> anyhow::Result<Self> {
+        let (magic, path) = split_magic(arg)?;
+        let mut item = Self {
+            pattern: String::new(),
+            fixed_len: 0,
+            glob: false,
+            icase: false,
+            exclude: false,
+        };
+        let mut top = false;
+        let mut literal = false;
+        for word in magic {
+            match word {
+                "top" => top = true,
+                "literal" => literal = true,
+                "glob" => item.glob = true,
+                "icase" => item.icase = true,
+                "exclude" => item.exclude = true,
+                _ => bail!("Invalid pathspec magic '{}' in '{}'", word, arg),
+            }
+        }
+        if literal && item.glob {
+            bail!(
+                "'literal' and 'glob' pathspec magic are incompatible: '{}'",
+                arg
+            );
+        }
+
+        let mut pattern = if top {
+            path.trim_start_matches('/').to_string()
+        } else {
+            repository.relative_to_worktree(Path::new(path))?
+        };
+        // A trailing slash only matches directories, which normalizing drops
+        if path.ends_with('/') && !pattern.is_empty() && !pattern.ends_with('/') {
+            pattern.push('/');
+        }
+
+        item.fixed_len = if literal {
+            pattern.len()
+        } else {
+            pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len())
+        };
+        item.pattern = item.fold(&pattern);
+        Ok(item)
+    }
+
+    fn is_literal(&self) -> bool {
+        self.fixed_len == self.pattern.len()
+    }
+
+    fn fold(&self, text: &str) -> String {
+        if self.icase {
+            text.to_lowercase()
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let path = self.fold(path);
+        if self.pattern.is_empty() {
+            return true;
+        }
+        // Like git, a pattern with wildcards still matches the path it spells out
+        let name = self.pattern.trim_end_matches('/');
+        if (path == name && (is_dir || !self.pattern.ends_with('/'))) || self.matches_prefix(&path)
+        {
+            return true;
+        }
+        if self.is_literal() {
+            return false;
+        }
+        if !path.starts_with(&self.pattern[..self.fixed_len]) {
+            return false;
+        }
+        if self.glob {
+            wildmatch::wildmatch(&self.pattern, &path)
+        } else {
+            wildmatch::fnmatch(&self.pattern, &path)
+        }
+    }
+
+    // Returns true if the pattern, taken literally, names a directory that `path` is below
+    fn matches_prefix(&self, path: &str) -> bool {
+        self.pattern.is_empty()
+            || path
+                .strip_prefix(self.pattern.trim_end_matches('/'))
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+// Splits the magic words off the start of a pathspec, in either the long `:(word,...)` or the
+// short `:/!^` form
+fn split_magic(arg: &str) -> anyhow::Result<(Vec<&str>, &str)> {
+    let Some(rest) = arg.strip_prefix(':') else {
+        return Ok((Vec::new(), arg));
+    };
+    if let Some(long) = rest.strip_prefix('(') {
+        let Some((words, path)) = long.split_once(')') else {
+            bail!("Missing ')' at the end of pathspec magic in '{}'", arg);
+        };
+        let words = words
+            .split(',')
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .collect();
+        return Ok((words, path));
+    }
+
+    let mut magic = Vec::new();
+    let mut chars = rest.char_indices();
+    loop {
+        match chars.next() {
+            Some((_, '/')) => magic.push("top"),
+            Some((_, '!' | '^')) => magic.push("exclude"),
+            // A `:` ends the magic, so that paths can start with magic characters
+            Some((idx, ':')) => return Ok((magic, &rest[idx + 1..])),
+            Some((idx, _)) => return Ok((magic, &rest[idx..])),
+            None => return Ok((magic, "")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    const FILES: [&str; 8] = [
+        "[x]",
+        "a.c",
+        "b.txt",
+        "doc/A.md",
+        "src/a.c",
+        "src/lib/x.c",
+        "src/lib/y.h",
+        "x",
+    ];
+
+    // The files the pathspec selects, as git ls-files lists them
+    fn select(args: &[&str]) -> Vec<&'static str> {
+        let repository = Repository::new(&env::current_dir().unwrap());
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let pathspec = Pathspec::parse(&repository, &args).unwrap();
+        FILES
+            .into_iter()
+            .filter(|path| pathspec.matches(path, false))
+            .collect()
+    }
+
+    #[test]
+    fn matches_like_ls_files() {
+        let lib = ["src/lib/x.c", "src/lib/y.h"];
+        let src = ["src/a.c", "src/lib/x.c", "src/lib/y.h"];
+        let c = ["a.c", "src/a.c", "src/lib/x.c"];
+        for (args, expected) in [
+            (&["src"][..], &src[..]),
+            (&["src/"], &src),
+            (&["src/*"], &src),
+            (&["sr"], &[]),
+            (&["src/lib/x.c"], &["src/lib/x.c"]),
+            (&["*.c"], &c),
+            (&["s*c"], &["src/a.c", "src/lib/x.c"]),
+            (&["[x]"], &["[x]", "x"]),
+            (&[":(literal)[x]"], &["[x]"]),
+            (&[":(glob)*.c"], &["a.c"]),
+            (&[":(glob)**/*.c"], &c),
+            (&[":(glob)src/*.c"], &["src/a.c"]),
+            (&[":(glob)src/**"], &src),
+            (&["*.C"], &[]),
+            (&[":(icase)*.C"], &c),
+            (&[":(icase)DOC"], &["doc/A.md"]),
+            (&[":(icase)doc/a.MD"], &["doc/A.md"]),
+            (&[":/src/lib"], &lib),
+            (&[":!src"], &["[x]", "a.c", "b.txt", "doc/A.md", "x"]),
+            (
+                &[":(exclude)*.c"],
+                &["[x]", "b.txt", "doc/A.md", "src/lib/y.h", "x"],
+            ),
+            (&["*.c", ":!src/lib"], &["a.c", "src/a.c"]),
+        ] {
+            assert_eq!(select(args), expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn directories() {
+        let repository = Repository::new(&env::current_dir().unwrap());
+        let parse = |arg: &str| Pathspec::parse(&repository, &[arg.to_string()]).unwrap();
+        let pathspec = parse("src/lib/x.c");
+        assert!(pathspec.could_match_below("src"));
+        assert!(!pathspec.could_match_below("doc"));
+        assert!(pathspec.leads_below("src"));
+        assert!(parse("*.c").could_match_below("doc"));
+        assert!(!parse("*.c").leads_below("doc"));
+        assert!(!parse(":!src").could_match_below("src"));
+        assert!(parse("doc/").matches("doc", true));
+        assert!(!parse("doc/").matches("doc", false));
+    }
+
+    #[test]
+    fn bad_magic() {
+        let repository = Repository::new(&env::current_dir().unwrap());
+        for arg in [":(nope)a", ":(literal,glob)a", ":(top"] {
+            assert!(Pathspec::parse(&repository, &[arg.to_string()]).is_err());
+        }
+    }
+}
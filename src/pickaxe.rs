@@ -0,0 +1,192 @@
+// Picks out the changes git's pickaxe looks for: with -S, those that change how many times a
+// string occurs in a file, counting occurrences that don't overlap, or with --pickaxe-regex how
+// many times a regex matches; with -G, those whose diff adds or removes a line a regex matches.
+// Like git, files are compared as their diff driver's textconv program converts them, -G leaves
+// out binary files, and a file whose content is the same on both sides is never picked.
+use anyhow::{anyhow, bail};
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::diff::{self, Change, NO_MODE, Status};
+use crate::line_diff::{self, DiffOptions};
+use crate::merge_file;
+use crate::regex::Regex;
+use crate::rename::Rename;
+use crate::repository::Repository;
+use crate::userdiff::{self, Driver, Side};
+
+/// What the pickaxe looks for
+pub enum Pickaxe {
+    /// A string, which may ignore case, whose number of occurrences changes (-S)
+    String(Vec<u8>, bool),
+    /// A regex whose number of matches changes (-S with --pickaxe-regex)
+    Regex(Regex),
+    /// A regex matching a line the diff adds or removes (-G)
+    Grep(Regex),
+}
+
+/// A side of a file pair: its path, mode and hash, the mode being NO_MODE if it is missing
+pub type File<'a> = (&'a str, &'a str, &'a str);
+
+impl Pickaxe {
+    /// The pickaxe for -S, taking `needle` as a regex if `regex` is set
+    pub fn string(needle: &str, regex: bool, ignore_case: bool) -> anyhow::Result<Self> {
+        match regex {
+            true => Ok(Self::Regex(compile(needle, ignore_case)?)),
+            false if needle.is_empty() => bail!("-S needs a string to look for"),
+            false => Ok(Self::String(needle.as_bytes().to_vec(), ignore_case)),
+        }
+    }
+
+    /// The pickaxe for -G
+    pub fn grep(pattern: &str, ignore_case: bool) -> anyhow::Result<Self> {
+        Ok(Self::Grep(compile(pattern, ignore_case)?))
+    }
+
+    /// Returns true if it looks for any of `changes`, those `renames` adds being compared with
+    /// where they came from, and the deletions they come from left out
+    pub fn picks_any(
+        &self,
+        repository: &Repository,
+        config: &Config,
+        attributes: &mut Attributes,
+        changes: &[Change],
+        renames: &[Rename],
+    ) -> anyhow::Result<bool> {
+        for change in changes {
+            let source = |rename: &Rename| rename.source == change.path;
+            if change.status == Status::Deleted && renames.iter().any(source) {
+                continue;
+            }
+            let old = match renames
+                .iter()
+                .find(|rename| rename.destination == change.path)
+            {
+                Some(rename) => (
+                    rename.source.as_str(),
+                    rename.source_mode.as_str(),
+                    rename.source_hash.as_str(),
+                ),
+                None => (
+                    change.path.as_str(),
+                    change.old_mode.as_str(),
+                    change.old_hash.as_str(),
+                ),
+            };
+            let new = (
+                change.path.as_str(),
+                change.new_mode.as_str(),
+                change.new_hash.as_str(),
+            );
+            if self.picks(repository, config, attributes, old, new)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns true if the change from `old` to `new` is one it looks for
+    pub fn picks(
+        &self,
+        repository: &Repository,
+        config: &Config,
+        attributes: &mut Attributes,
+        old: File,
+        new: File,
+    ) -> anyhow::Result<bool> {
+        let [old_driver, new_driver] = [old, new].map(|(path, mode, _)| {
+            let driver = match mode {
+                NO_MODE => None,
+                _ => userdiff::driver(config, attributes, path)?,
+            };
+            anyhow::Ok(driver.filter(|driver| driver.textconv.is_some()))
+        });
+        let (old_driver, new_driver) = (old_driver?, new_driver?);
+        let name = |driver: &Option<Driver>| driver.as_ref().map(|driver| driver.name.clone());
+        if old.1 == new.1 && old.2 == new.2 && name(&old_driver) == name(&new_driver) {
+            return Ok(false);
+        }
+
+        let mut sides = Vec::new();
+        for ((path, mode, hash), driver) in [(old, &old_driver), (new, &new_driver)] {
+            let content = diff::content(repository, path, mode, hash)?;
+            let converted = match driver {
+                Some(driver) => {
+                    let side = Side {
+                        path,
+                        mode,
+                        hash,
+                        content: content.as_deref(),
+                    };
+                    driver.convert(repository, config, &side)?
+                }
+                None => None,
+            };
+            let binary = driver.is_none() && content.as_deref().is_some_and(merge_file::is_binary);
+            sides.push((converted.or(content).unwrap_or_default(), binary));
+        }
+        let [(old, old_binary), (new, new_binary)] =
+            <[_; 2]>::try_from(sides).unwrap_or_else(|_| unreachable!("There are two sides"));
+
+        Ok(match self {
+            Self::String(..) | Self::Regex(_) => self.count(&old) != self.count(&new),
+            Self::Grep(_) if old_binary || new_binary => false,
+            Self::Grep(regex) => {
+                let (old, new) = (line_diff::lines(&old), line_diff::lines(&new));
+                line_diff::diff_lines(&old, &new, &DiffOptions::default())
+                    .iter()
+                    .flat_map(|region| {
+                        old[region.old.clone()]
+                            .iter()
+                            .chain(&new[region.new.clone()])
+                    })
+                    // As git's diff output has it, a last line without a newline gets one
+                    .any(|line| match line.ends_with(b"\n") {
+                        true => regex.find(line).is_some(),
+                        false => regex.find(&[*line, b"\n".as_slice()].concat()).is_some(),
+                    })
+            }
+        })
+    }
+
+    // Counts the occurrences of the string, or the matches of the regex, in `data`. Like git, the
+    // search for each match after the first picks up where the last one ended, past an empty one.
+    fn count(&self, data: &[u8]) -> usize {
+        let mut count = 0;
+        match self {
+            Self::String(needle, ignore_case) => {
+                let mut data = data;
+                let same = |window: &[u8]| match ignore_case {
+                    true => window.eq_ignore_ascii_case(needle),
+                    false => window == needle.as_slice(),
+                };
+                while let Some(offset) = data.windows(needle.len()).position(same) {
+                    data = &data[offset + needle.len()..];
+                    count += 1;
+                }
+            }
+            Self::Regex(regex) | Self::Grep(regex) => {
+                let mut data = data;
+                while !data.is_empty() {
+                    let found = match count {
+                        0 => regex.find(data),
+                        _ => regex.find_not_bol(data),
+                    };
+                    let Some(found) = found else {
+                        break;
+                    };
+                    data = &data[found.end..];
+                    if found.is_empty() && !data.is_empty() {
+                        data = &data[1..];
+                    }
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+fn compile(pattern: &str, ignore_case: bool) -> anyhow::Result<Regex> {
+    Regex::new(pattern.as_bytes(), ignore_case).map_err(|e| anyhow!("invalid regex: {}", e))
+}
@@ -0,0 +1,255 @@
+// Carries out the changes that commands which delete or rewrite things make, or for a dry run only
+// plans them. Such a command makes each change through its Plan rather than by itself: writing a
+// pack or a file, removing a file, updating refs, checking out a commit. Every change is recorded
+// as an Action, and is only made if it isn't a dry run, so that a dry run lists the changes a real
+// run would make, after checking what a real run checks first, like that refs are at their
+// expected values. Since nothing is written, a dry run keeps track of what would have been for the
+// steps that follow: the objects of packs it wrote count as packed, the files it removed as gone,
+// and the objects it wrote are only hashed.
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use crate::object::GitrsObject;
+use crate::pack::{self, PackIndex, PackObject};
+use crate::refs::{Ref, RefUpdate};
+use crate::repository::Repository;
+
+/// A change to the repository or its worktree
+#[derive(Debug)]
+pub enum Action {
+    /// A new pack, with how many objects it has
+    WritePack {
+        path: PathBuf,
+        objects: usize,
+    },
+    WriteFile(PathBuf),
+    RemoveFile(PathBuf),
+    /// A ref set to a new value, or deleted if None
+    UpdateRef {
+        name: String,
+        new: Option<String>,
+    },
+    /// The worktree moved to a commit, or emptied if None
+    CheckOut(Option<String>),
+}
+
+pub struct Plan {
+    dry_run: bool,
+    actions: Vec<Action>,
+    // In a dry run, the objects of the packs that would have been written
+    packed: HashSet<String>,
+}
+
+impl Plan {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            actions: Vec::new(),
+            packed: HashSet::new(),
+        }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The changes made, or that would have been, in order
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// Records `action`, then makes it with `apply` unless this is a dry run
+    pub fn perform(
+        &mut self,
+        action: Action,
+        apply: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.actions.push(action);
+        match self.dry_run {
+            true => Ok(()),
+            false => apply(),
+        }
+    }
+
+    /// Writes `objects` to a new pack named `<prefix>-<hash>.pack`, returning its path
+    pub fn write_pack(
+        &mut self,
+        repository: &Repository,
+        prefix: &str,
+        objects: &[PackObject],
+    ) -> anyhow::Result<PathBuf> {
+        let path = match self.dry_run {
+            true => {
+                self.packed
+                    .extend(objects.iter().map(|object| object.hash.clone()));
+                pack::path_of(repository, prefix, objects)?
+            }
+            false => pack::write(repository, prefix, objects)?,
+        };
+        self.actions.push(Action::WritePack {
+            path: path.clone(),
+            objects: objects.len(),
+        });
+        Ok(path)
+    }
+
+    pub fn write_file(&mut self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        self.perform(Action::WriteFile(path.to_path_buf()), || {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Could not create {}", dir.display()))?;
+            }
+            fs::write(path, content).with_context(|| format!("Could not write {}", path.display()))
+        })
+    }
+
+    pub fn remove_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.perform(Action::RemoveFile(path.to_path_buf()), || {
+            fs::remove_file(path).with_context(|| format!("Failed to delete {}", path.display()))
+        })
+    }
+
+    /// Writes `object`, returning its hash
+    pub fn write_object(
+        &mut self,
+        repository: &Repository,
+        mut object: GitrsObject,
+    ) -> anyhow::Result<String> {
+        match self.dry_run {
+            true => Ok(object.hash()),
+            false => object.write(repository),
+        }
+    }
+
+    /// Applies the updates together, as `Ref::transaction` does
+    pub fn update_refs(
+        &mut self,
+        repository: &Repository,
+        updates: &[RefUpdate],
+    ) -> anyhow::Result<()> {
+        let names = match self.dry_run {
+            true => Ref::check_transaction(repository, updates)?,
+            false => {
+                Ref::transaction(repository, updates)?;
+                updates.iter().map(|update| update.name.clone()).collect()
+            }
+        };
+        for (name, update) in names.into_iter().zip(updates) {
+            if !update.verify_only {
+                self.actions.push(Action::UpdateRef {
+                    name,
+                    new: update.new.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the file at `path` was removed
+    pub fn is_removed(&self, path: &Path) -> bool {
+        self.actions
+            .iter()
+            .any(|action| matches!(action, Action::RemoveFile(removed) if removed == path))
+    }
+
+    /// Whether the object `hash` is in one of the packs that are left of `packs`, or in one that
+    /// was written
+    pub fn is_packed(&self, packs: &[Arc<PackIndex>], hash: &str) -> bool {
+        self.packed.contains(hash)
+            || packs
+                .iter()
+                .any(|index| !self.is_removed(&index.pack) && index.contains(hash))
+    }
+}
+
+impl Action {
+    /// Describes what the action would do, with paths relative to the worktree
+    pub fn describe(&self, repository: &Repository) -> String {
+        let relative = |path: &Path| {
+            path.strip_prefix(&repository.worktree)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
+        match self {
+            Action::WritePack { path, objects } => {
+                format!("Would pack {} objects into {}", objects, relative(path))
+            }
+            Action::WriteFile(path) => format!("Would write {}", relative(path)),
+            Action::RemoveFile(path) => format!("Would remove {}", relative(path)),
+            Action::UpdateRef { name, new } => match new {
+                Some(new) => format!("Would update {} to {}", name, new),
+                None => format!("Would delete {}", name),
+            },
+            Action::CheckOut(commit) => match commit {
+                Some(commit) => format!("Would check out {}", commit),
+                None => "Would remove the tracked files from the worktree".to_string(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::object::ObjectType;
+    use crate::prune;
+    use crate::repack;
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let worktree = env::temp_dir().join(format!("gitrs-plan-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let hashes: Vec<String> = ["one\n", "two\n"]
+            .iter()
+            .map(|data| {
+                GitrsObject::write_data(&repository, ObjectType::Blob, data.as_bytes()).unwrap()
+            })
+            .collect();
+        let commit = "a".repeat(40);
+        Ref::create_at(&repository, &commit, &["refs", "heads", "master"]).unwrap();
+
+        let mut plan = Plan::new(true);
+        let (pack, count) = repack::write_pack(&repository, &mut plan, "pack", hashes.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(!pack.exists());
+        // The objects count as packed, though the pack wasn't written
+        let removed = prune::prune_packed(&repository, &mut plan).unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(removed.iter().all(|path| path.exists()));
+
+        let delete = |old: &str| RefUpdate {
+            name: "HEAD".to_string(),
+            new: None,
+            old: Some(old.to_string()),
+            verify_only: false,
+            deref: true,
+        };
+        assert!(plan.update_refs(&repository, &[delete("b")]).is_err());
+        plan.update_refs(&repository, &[delete(&commit)]).unwrap();
+        assert!(matches!(
+            plan.actions().last(),
+            Some(Action::UpdateRef { name, new: None }) if name == "refs/heads/master"
+        ));
+        assert_eq!(
+            Ref::try_resolve(&repository, "refs/heads/master").unwrap(),
+            Some(commit)
+        );
+
+        // A real run writes the pack the dry run named
+        let written = repack::write_pack(&repository, &mut Plan::new(false), "pack", hashes);
+        assert_eq!(written.unwrap().unwrap().0, pack);
+        assert!(pack.exists());
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+// Deletes loose objects that are no longer needed, either because nothing refers to them (prune)
+// or because a pack already holds a copy (prune-packed). An object is reachable if it can be
+// reached from a ref, including replace and notes refs, from the HEAD of any worktree, or from a
+// reflog entry (gitrs only logs switches to HEAD, but keeps what git recorded).
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+
+use crate::loose;
+use crate::object::tree::Leaf;
+use crate::object::{GitrsObject, ObjectType};
+use crate::pack::PackIndex;
+use crate::plan::Plan;
+use crate::refs::{Ref, ZERO_HASH};
+use crate::repository::Repository;
+use crate::worktree::Worktree;
+
+pub struct PruneOptions {
+    /// Only report what would be removed
+    pub dry_run: bool,
+    /// Keep unreachable objects modified after this unix timestamp
+    pub expire: i64,
+}
+
+/// Removes the unreachable loose objects older than the expiry, returning their hashes and types
+pub fn prune(
+    repository: &Repository,
+    options: &PruneOptions,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let reachable = reachable(repository)?;
+    let scan = loose::scan(repository)?;
+    let mut plan = Plan::new(options.dry_run);
+    let mut pruned = Vec::new();
+
+    for object in scan.objects {
+        if reachable.contains(&object.hash) || !expired(&object.file.path, options.expire)? {
+            continue;
+        }
+        let object_type = GitrsObject::read_raw(repository, &object.hash)
+            .map_or("unknown".to_string(), |obj| obj.get_type().to_string());
+        remove(&mut plan, &object.file.path)?;
+        pruned.push((object.hash, object_type));
+    }
+
+    // Leftovers from interrupted writes
+    for file in scan.garbage {
+        let is_temporary = file
+            .path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("tmp_"));
+        if is_temporary && expired(&file.path, options.expire)? {
+            remove(&mut plan, &file.path)?;
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Removes the loose objects that are also in a pack, returning the removed files. Those `plan`
+/// removed already are left out, and the packs it wrote count.
+pub fn prune_packed(repository: &Repository, plan: &mut Plan) -> anyhow::Result<Vec<PathBuf>> {
+    let packs = PackIndex::load_all(repository)?;
+    let mut removed = Vec::new();
+
+    for object in loose::scan(repository)?.objects {
+        if !plan.is_removed(&object.file.path) && plan.is_packed(&packs, &object.hash) {
+            remove(plan, &object.file.path)?;
+            removed.push(object.file.path);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Hashes of every object reachable from the refs, worktree HEADs and reflogs
+pub fn reachable(repository: &Repository) -> anyhow::Result<HashSet<String>> {
+    let mut pending = roots(repository)?;
+    let mut seen = HashSet::new();
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+
+        // Replacements are reachable through their refs, so the history is walked as recorded
+        let object = GitrsObject::read_raw(repository, &hash)
+            .with_context(|| format!("Missing reachable object {}", hash))?;
+        match object {
+            GitrsObject::CommitObject(commit) => {
+                pending.push(commit.get_tree_hash().clone());
+                pending.extend(commit.parents().iter().cloned());
+            }
+            GitrsObject::TreeObject(tree) => pending.extend(
+                tree.records
+                    .iter()
+                    // Submodule commits live in another repository
+                    .filter(|leaf| Leaf::get_type_from_mode(&leaf.file_mode) != ObjectType::Commit)
+                    .map(|leaf| leaf.hash.clone()),
+            ),
+            GitrsObject::TagObject(tag) => pending.extend(tag.object().map(str::to_string)),
+            GitrsObject::BlobObject(_) => {}
+        }
+    }
+
+    Ok(seen)
+}
+
+/// Hashes of what reachability starts from: the objects refs point to, worktree HEADs and the
+/// commits in reflogs
+pub fn roots(repository: &Repository) -> anyhow::Result<Vec<String>> {
+    let mut roots: Vec<String> = Ref::list(repository)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    for worktree in Worktree::list(repository)? {
+        // An unborn branch has no commit yet
+        if let Ok(head) = worktree.resolve_head(repository) {
+            roots.push(head);
+        }
+    }
+
+    let mut logs = vec![repository.commondir.join("logs")];
+    if repository.gitdir != repository.commondir {
+        logs.push(repository.gitdir.join("logs"));
+    }
+    while let Some(path) = logs.pop() {
+        if path.is_dir() {
+            logs.extend(
+                fs::read_dir(&path)?
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path()),
+            );
+        } else if let Ok(log) = fs::read_to_string(&path) {
+            // Entries start with `<old hash> <new hash>`
+            for line in log.lines() {
+                roots.extend(
+                    line.split(' ')
+                        .take(2)
+                        .filter(|hash| hash.len() == 40 && *hash != ZERO_HASH)
+                        .map(str::to_string),
+                );
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+fn expired(path: &Path, expire: i64) -> anyhow::Result<bool> {
+    let modified = fs::symlink_metadata(path)?.modified()?;
+    let modified = match modified.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(_) => 0,
+    };
+    Ok(modified <= expire)
+}
+
+// Removes a loose object file, along with its fanout directory once it is empty
+fn remove(plan: &mut Plan, path: &Path) -> anyhow::Result<()> {
+    plan.remove_file(path)?;
+    if let Some(dir) = path.parent()
+        && !plan.is_dry_run()
+    {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
@@ -0,0 +1,536 @@
+// Compares two versions of a series of commits, as git range-diff does. Each commit is turned into
+// a patch of its own, its author and message first and then the diff it makes to each file, and
+// commits of the two series whose patches are the same are paired first. The rest are paired so
+// that the patches of the pairs differ as little as possible in all, a commit being left unpaired
+// where the diff between its patch and the other's would be longer than what the creation factor
+// allows of its own. The pairs are then listed in the order of the new series, with the commits
+// only the old series has shown where they were in it, each pair whose patches differ followed by
+// the diff between them.
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::diff::{self, Change, NO_MODE, Status};
+use crate::line_diff::{Algorithm, DiffOptions, Whitespace};
+use crate::linear_assignment;
+use crate::mailmap::Mailmap;
+use crate::merge_file;
+use crate::notes::Notes;
+use crate::object::commit::Commit;
+use crate::object::tree::Tree;
+use crate::patch;
+use crate::pathspec::Pathspec;
+use crate::rename;
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::userdiff::{self, Funcname, Side};
+
+/// How much of a commit's diff the diff between patches may differ by for them to be paired, in
+/// percent
+pub const DEFAULT_CREATION_FACTOR: usize = 60;
+// What pairing commits that can't be paired costs
+const COST_MAX: i64 = 1 << 16;
+// The hunk headers of the diff between patches show the section or hunk they are in
+const SECTION_HEADERS: &[u8] = b"^ ## (.*) ##$\n^.?@@ (.*)$";
+// How many characters of each hash are shown
+const ABBREV: usize = 7;
+
+pub struct RangeDiffOptions {
+    /// How much of a commit's diff the diff between patches may differ by for them to be paired
+    pub creation_factor: usize,
+    /// Only list the pairs, without the diffs between their patches
+    pub no_patch: bool,
+    /// Leave out the commits only the new series has
+    pub left_only: bool,
+    /// Leave out the commits only the old series has
+    pub right_only: bool,
+}
+
+// A commit of a series as a patch, and where it went
+struct Patch {
+    hash: String,
+    commit: Commit,
+    text: Vec<u8>,
+    /// Where the diff starts in the text, after the author and message
+    diff_offset: usize,
+    /// How many lines the diff has
+    diff_size: usize,
+    /// The commit of the other series it is paired with
+    matching: Option<usize>,
+    shown: bool,
+}
+
+impl Patch {
+    fn diff(&self) -> &[u8] {
+        &self.text[self.diff_offset..]
+    }
+
+    // Adds a line of a file's section, which counts towards the size of the diff
+    fn push_line(&mut self, line: &[u8]) {
+        self.text.extend_from_slice(line);
+        self.diff_size += 1;
+    }
+}
+
+// What patches are made with
+struct Reader<'a> {
+    repository: &'a Repository,
+    config: Config,
+    attributes: Attributes<'a>,
+    mailmap: Option<Mailmap>,
+    notes: Notes<'a>,
+    renames: Option<bool>,
+    diff_options: DiffOptions,
+}
+
+/// Compares the `old` series of commits with the `new` one, both oldest first, returning the
+/// pairs found with the diffs between their patches
+pub fn format(
+    repository: &Repository,
+    old: &[String],
+    new: &[String],
+    options: &RangeDiffOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let config = Config::load(repository)?;
+    let algorithm = match config.get("diff.algorithm") {
+        Some(name) => Algorithm::parse(name).ok_or_else(|| {
+            anyhow::anyhow!("unknown value for config 'diff.algorithm': {}", name)
+        })?,
+        None => Algorithm::Myers,
+    };
+    let mailmap = match config.get_bool("log.mailmap")?.unwrap_or(true) {
+        true => Some(Mailmap::load(repository, &config)),
+        false => None,
+    };
+    let mut reader = Reader {
+        repository,
+        attributes: Attributes::new(repository),
+        mailmap,
+        notes: Notes::load(repository, &config, None)?,
+        renames: rename::configured(&config)?,
+        diff_options: DiffOptions {
+            whitespace: Whitespace::Exact,
+            indent_heuristic: true,
+            algorithm,
+        },
+        config,
+    };
+    let mut a = old
+        .iter()
+        .map(|hash| reader.read(hash))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut b = new
+        .iter()
+        .map(|hash| reader.read(hash))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    find_exact_matches(&mut a, &mut b);
+    find_correspondences(&mut a, &mut b, options.creation_factor);
+    let funcname = Funcname::new(SECTION_HEADERS, false)?;
+    Ok(output(&mut a, &b, options, &reader.diff_options, &funcname))
+}
+
+impl Reader<'_> {
+    // Turns the commit `hash` into a patch
+    fn read(&mut self, hash: &str) -> anyhow::Result<Patch> {
+        let commit = revwalk::read_commit(self.repository, hash)?;
+        let author = commit.author()?;
+        let (name, email) = match &self.mailmap {
+            Some(mailmap) => mailmap.map(&author.name, &author.email),
+            None => (author.name, author.email),
+        };
+        let mut text = format!(
+            " ## Metadata ##\nAuthor: {} <{}>\n\n ## Commit message ##\n",
+            name, email
+        )
+        .into_bytes();
+        // The message as git log shows it, without the blank lines around it
+        let blank = |line: &&str| line.trim_end_matches(is_space).is_empty();
+        let mut lines: Vec<&str> = commit.message().lines().skip_while(blank).collect();
+        while lines.last().is_some_and(blank) {
+            lines.pop();
+        }
+        for line in lines {
+            push_indented(&mut text, line);
+        }
+        if let Some(note) = self.notes.get(hash)? {
+            text.extend_from_slice(b"\n\n ## Notes ##\n");
+            for line in note.lines() {
+                push_indented(&mut text, line);
+            }
+        }
+
+        let mut patch = Patch {
+            hash: hash.to_string(),
+            commit,
+            text,
+            diff_offset: 0,
+            diff_size: 0,
+            matching: None,
+            shown: false,
+        };
+        let parent = match patch.commit.parents().first() {
+            Some(parent) => Tree::from_name(self.repository, parent)?,
+            None => Tree {
+                records: Vec::new(),
+            },
+        };
+        let tree = Tree::from_name(self.repository, patch.commit.get_tree_hash())?;
+        let changes =
+            diff::diff_trees(self.repository, &parent, &tree, true, &Pathspec::default())?;
+        for (old, new, renamed) in self.pairs(&changes)? {
+            self.read_file(&mut patch, old, new, renamed)?;
+        }
+        Ok(patch)
+    }
+
+    // The files `changes` compares, as git log -p shows them: a rename or copy in the place of
+    // the file it adds, with the deletion it comes from left out, and a file changing type as a
+    // deletion and a creation.
+    fn pairs(&self, changes: &[Change]) -> anyhow::Result<Vec<Pair>> {
+        let renames = match self.renames {
+            Some(copies) => rename::detect(self.repository, changes, copies)?,
+            None => Vec::new(),
+        };
+        let mut pairs = Vec::new();
+        for change in changes {
+            let path = change.path.clone();
+            let old = (
+                path.clone(),
+                change.old_mode.clone(),
+                change.old_hash.clone(),
+            );
+            let new = (path, change.new_mode.clone(), change.new_hash.clone());
+            if change.status == Status::TypeChanged {
+                pairs.push((Some(old), None, false));
+                pairs.push((None, Some(new), false));
+                continue;
+            }
+            if change.status == Status::Deleted
+                && renames.iter().any(|rename| rename.source == change.path)
+            {
+                continue;
+            }
+            let Some(index) = renames
+                .iter()
+                .position(|rename| rename.destination == change.path)
+            else {
+                let side = |file: File| (file.1 != NO_MODE).then_some(file);
+                pairs.push((side(old), side(new), false));
+                continue;
+            };
+            // Like git, of the files added from a deleted file, the last is renamed from it and
+            // the others are copies
+            let rename = &renames[index];
+            let deleted = changes
+                .iter()
+                .any(|change| change.status == Status::Deleted && change.path == rename.source);
+            let later = changes.iter().skip_while(|other| other.path != change.path);
+            let copied_later = later.skip(1).any(|other| {
+                renames
+                    .iter()
+                    .any(|later| later.destination == other.path && later.source == rename.source)
+            });
+            let source = (
+                rename.source.clone(),
+                rename.source_mode.clone(),
+                rename.source_hash.clone(),
+            );
+            pairs.push((Some(source), Some(new), deleted && !copied_later));
+        }
+        Ok(pairs)
+    }
+
+    // Adds the section for the change from `old` to `new` to `patch`: a header naming the file,
+    // then its hunks, each headed by the file's name and what git diff would show after it
+    fn read_file(
+        &mut self,
+        patch: &mut Patch,
+        old: Option<File>,
+        new: Option<File>,
+        renamed: bool,
+    ) -> anyhow::Result<()> {
+        patch.text.push(b'\n');
+        if patch.diff_offset == 0 {
+            patch.diff_offset = patch.text.len();
+        }
+        let (name, file) = match (&old, &new) {
+            (None, Some(new)) => (format!("{} (new)", new.0), &new.0),
+            (Some(old), None) => (format!("{} (deleted)", old.0), &old.0),
+            (Some(old), Some(new)) if renamed => (format!("{} => {}", old.0, new.0), &new.0),
+            (_, Some(new)) => (new.0.clone(), &new.0),
+            (None, None) => unreachable!("A file is on one side at least"),
+        };
+        let mut header = format!(" ## {}", name);
+        if let (Some(old), Some(new)) = (&old, &new)
+            && old.1 != new.1
+        {
+            header.push_str(&format!(" (mode change {} => {})", old.1, new.1));
+        }
+        header.push_str(" ##\n");
+        patch.push_line(header.as_bytes());
+        if old.as_ref().map(|old| &old.2) == new.as_ref().map(|new| &new.2) {
+            return Ok(());
+        }
+
+        // Each side is converted with its own driver's textconv program, and the hunk headers are
+        // found with the old side's funcname patterns, or else the new side's
+        let mut sides = Vec::new();
+        let mut funcname = None;
+        for side in [&old, &new] {
+            let Some((path, mode, hash)) = side else {
+                sides.push((Vec::new(), false));
+                continue;
+            };
+            let driver = userdiff::driver(&self.config, &mut self.attributes, path)?;
+            if funcname.is_none()
+                && let Some(driver) = &driver
+            {
+                funcname = driver.funcname()?;
+            }
+            let content = diff::content(self.repository, path, mode, hash)?;
+            let converted = match driver.as_ref().filter(|driver| driver.textconv.is_some()) {
+                Some(driver) => {
+                    let side = Side {
+                        path,
+                        mode,
+                        hash,
+                        content: content.as_deref(),
+                    };
+                    driver.convert(self.repository, &self.config, &side)?
+                }
+                None => None,
+            };
+            let text = converted.is_some();
+            let content = converted.or(content).unwrap_or_default();
+            let binary = !text && merge_file::is_binary(&content);
+            sides.push((content, binary));
+        }
+        if sides.iter().any(|(_, binary)| *binary) {
+            let name = |side: &Option<File>| {
+                side.as_ref()
+                    .map_or("/dev/null", |side| &side.0)
+                    .to_string()
+            };
+            let line = format!(" Binary files {} and {} differ\n", name(&old), name(&new));
+            patch.push_line(line.as_bytes());
+            return Ok(());
+        }
+
+        let hunks = patch::hunks(
+            &sides[0].0,
+            &sides[1].0,
+            &self.diff_options,
+            funcname.as_ref(),
+        );
+        for line in hunks.split_inclusive(|&b| b == b'\n') {
+            if let Some(ranges) = line.strip_prefix(b"@@ ") {
+                let rest = ranges
+                    .windows(2)
+                    .position(|w| w == b"@@")
+                    .map_or(&b"\n"[..], |end| &ranges[end + 2..]);
+                let mut header = b"@@".to_vec();
+                if rest != b"\n" {
+                    header.extend(format!(" {}:", file).as_bytes());
+                }
+                header.extend_from_slice(rest);
+                patch.push_line(&header);
+            } else if matches!(line.first(), Some(b'+' | b'-' | b' ')) {
+                patch.push_line(line);
+            } else {
+                patch.push_line(&[b" ", line].concat());
+            }
+        }
+        Ok(())
+    }
+}
+
+// A side of a file pair: its path, mode and hash
+type File = (String, String, String);
+// The two sides of a file pair, either of which may be missing, and whether it is a rename
+type Pair = (Option<File>, Option<File>, bool);
+
+fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\r' | '\n')
+}
+
+// Adds a line of a commit's message or notes as git log shows it, indented with its tabs
+// expanded, without the whitespace it ends with
+fn push_indented(text: &mut Vec<u8>, line: &str) {
+    let mut indented = String::from("    ");
+    let mut column = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\t' => {
+                indented.extend(std::iter::repeat_n(' ', 8 - column % 8));
+                column = 0;
+            }
+            // Like git, tabs aren't lined up past characters whose width isn't known
+            _ if c.is_control() => {
+                indented.push_str(&line[i..]);
+                break;
+            }
+            _ => {
+                indented.push(c);
+                column += 1;
+            }
+        }
+    }
+    text.extend_from_slice(indented.trim_end_matches(is_space).as_bytes());
+    text.push(b'\n');
+}
+
+// Pairs the commits whose diffs are the same, the last of those with the same diff in the old
+// series going first
+fn find_exact_matches(a: &mut [Patch], b: &mut [Patch]) {
+    let mut diffs: std::collections::HashMap<&[u8], Vec<usize>> = std::collections::HashMap::new();
+    for (i, patch) in a.iter().enumerate() {
+        diffs.entry(patch.diff()).or_default().push(i);
+    }
+    let mut matches = Vec::new();
+    for (j, patch) in b.iter().enumerate() {
+        if let Some(i) = diffs.get_mut(patch.diff()).and_then(Vec::pop) {
+            matches.push((i, j));
+        }
+    }
+    for (i, j) in matches {
+        a[i].matching = Some(j);
+        b[j].matching = Some(i);
+    }
+}
+
+// Pairs the rest of the commits so that the diffs between their patches are as short as they
+// can be in all, each side also being able to go unpaired at a cost of the creation factor's
+// share of its diff's size
+fn find_correspondences(a: &mut [Patch], b: &mut [Patch], creation_factor: usize) {
+    let n = a.len() + b.len();
+    let mut cost = vec![0; n * n];
+    let creation = |patch: &Patch| match patch.matching {
+        Some(_) => COST_MAX,
+        None => (patch.diff_size * creation_factor / 100) as i64,
+    };
+    for (i, a_patch) in a.iter().enumerate() {
+        for (j, b_patch) in b.iter().enumerate() {
+            cost[i + n * j] = match (a_patch.matching, b_patch.matching) {
+                (Some(matching), _) if matching == j => 0,
+                (None, None) => diff_size(a_patch.diff(), b_patch.diff()),
+                _ => COST_MAX,
+            };
+        }
+        for j in b.len()..n {
+            cost[i + n * j] = creation(a_patch);
+        }
+    }
+    for (j, b_patch) in b.iter().enumerate() {
+        for i in a.len()..n {
+            cost[i + n * j] = creation(b_patch);
+        }
+    }
+
+    let (a2b, _) = linear_assignment::compute(n, n, &cost);
+    for (i, &j) in a2b.iter().enumerate().take(a.len()) {
+        if j >= 0 && (j as usize) < b.len() {
+            a[i].matching = Some(j as usize);
+            b[j as usize].matching = Some(i);
+        }
+    }
+}
+
+// How many lines the diff between two diffs has, hunk headers included
+fn diff_size(a: &[u8], b: &[u8]) -> i64 {
+    let hunks = patch::hunks(a, b, &DiffOptions::default(), None);
+    hunks.split_inclusive(|&b| b == b'\n').count() as i64
+}
+
+// Lists the pairs in the order of the new series, each unpaired commit of the old series coming
+// once the commit paired with the one before it is shown
+fn output(
+    a: &mut [Patch],
+    b: &[Patch],
+    options: &RangeDiffOptions,
+    diff_options: &DiffOptions,
+    funcname: &Funcname,
+) -> Vec<u8> {
+    let width = (1 + a.len().max(b.len())).to_string().len();
+    let mut output = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && a[i].shown {
+            i += 1;
+            continue;
+        }
+        if i < a.len() && a[i].matching.is_none() {
+            if !options.right_only {
+                output.extend(header(width, Some((i, &a[i])), None));
+            }
+            i += 1;
+            continue;
+        }
+        while j < b.len() && b[j].matching.is_none() {
+            if !options.left_only {
+                output.extend(header(width, None, Some((j, &b[j]))));
+            }
+            j += 1;
+        }
+        if let Some(b_patch) = b.get(j) {
+            let k = b_patch.matching.expect("Only paired commits are left");
+            output.extend(header(width, Some((k, &a[k])), Some((j, b_patch))));
+            if !options.no_patch {
+                let hunks = patch::hunks(&a[k].text, &b_patch.text, diff_options, Some(funcname));
+                for line in hunks.split_inclusive(|&b| b == b'\n') {
+                    output.extend_from_slice(b"    ");
+                    // The line counts are left out of hunk headers
+                    match line.strip_prefix(b"@@ ") {
+                        Some(ranges) => {
+                            let end = ranges.windows(2).position(|w| w == b"@@").unwrap_or(0);
+                            output.extend_from_slice(b"@@");
+                            output.extend_from_slice(&ranges[end + 2..]);
+                        }
+                        None => output.extend_from_slice(line),
+                    }
+                }
+            }
+            a[k].shown = true;
+            j += 1;
+        }
+    }
+    output
+}
+
+// The line showing a pair, either side of which may be missing: their positions in their series
+// and hashes, whether the patches are the same, and the subject of the old commit, or else the
+// new one
+fn header(width: usize, a: Option<(usize, &Patch)>, b: Option<(usize, &Patch)>) -> Vec<u8> {
+    let side = |side: Option<(usize, &Patch)>| match side {
+        Some((i, patch)) => format!("{:>w$}:  {}", i + 1, &patch.hash[..ABBREV], w = width),
+        None => format!("{:>w$}:  {}", "-", "-".repeat(ABBREV), w = width),
+    };
+    let status = match (a, b) {
+        (_, None) => '<',
+        (None, _) => '>',
+        (Some((_, a)), Some((_, b))) if a.text != b.text => '!',
+        _ => '=',
+    };
+    let commit = &a
+        .or(b)
+        .expect("A pair has a commit on one side at least")
+        .1
+        .commit;
+    format!(
+        "{} {} {} {}\n",
+        side(a),
+        status,
+        side(b),
+        oneline(commit.message())
+    )
+    .into_bytes()
+}
+
+// The subject of a message as git's oneline format shows it: its first paragraph on one line
+fn oneline(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim_end_matches(is_space).is_empty())
+        .map(|line| line.trim_end_matches(is_space))
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,478 @@
+// Selects, sorts and formats refs for for-each-ref style listings. Formats contain `%(atom)`
+// placeholders (eg. `%(refname:short)` or `%(committerdate:iso)`) that are expanded for each ref,
+// along with `%%` for a literal percent sign and `%xx` for the byte with hex code xx.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+
+use crate::branch;
+use crate::config::Config;
+use crate::date;
+use crate::ident::Ident;
+use crate::object::{GitrsObject, ObjectType};
+use crate::refs::{self, Ref};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::signature;
+use crate::wildmatch::wildmatch;
+
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+pub struct RefItem {
+    pub name: String,
+    pub hash: String,
+}
+
+// The expansion of an atom, with a numeric value to sort by where plain text comparison would be
+// wrong (dates and sizes)
+struct AtomValue {
+    text: String,
+    number: Option<i64>,
+}
+
+pub struct RefFormatter<'a> {
+    repository: &'a Repository,
+    config: &'a Config,
+    // Ref HEAD points to, if it is attached to one
+    head: Option<String>,
+    objects: HashMap<String, GitrsObject>,
+}
+
+/// Lists the refs matching any of `patterns`, or all refs if there are none. A pattern matches a
+/// ref name either as a glob, exactly, or as a prefix ending at a `/`.
+pub fn filter_refs(repository: &Repository, patterns: &[String]) -> anyhow::Result<Vec<RefItem>> {
+    Ok(Ref::list_namespaced(repository)?
+        .into_iter()
+        .filter(|(name, _)| {
+            patterns.is_empty()
+                || patterns.iter().any(|pattern| {
+                    let prefix = pattern.trim_end_matches('/');
+                    name == prefix
+                        || name
+                            .strip_prefix(prefix)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                        || wildmatch(pattern, name)
+                })
+        })
+        .map(|(name, hash)| RefItem { name, hash })
+        .collect())
+}
+
+/// Conditions on what a ref's commit can reach, each holding commit hashes. A ref meets one if it
+/// holds for any of the commits, and refs that don't point to commits meet none.
+#[derive(Default)]
+pub struct ReachFilter {
+    /// The ref's commit must reach one of these
+    pub contains: Vec<String>,
+    /// The ref's commit must not reach any of these
+    pub no_contains: Vec<String>,
+    /// One of these must reach the ref's commit
+    pub merged: Vec<String>,
+    /// None of these may reach the ref's commit
+    pub no_merged: Vec<String>,
+}
+
+impl ReachFilter {
+    pub fn is_empty(&self) -> bool {
+        self.contains.is_empty()
+            && self.no_contains.is_empty()
+            && self.merged.is_empty()
+            && self.no_merged.is_empty()
+    }
+
+    /// Keeps the items that meet every condition
+    pub fn apply(
+        &self,
+        repository: &Repository,
+        items: Vec<RefItem>,
+    ) -> anyhow::Result<Vec<RefItem>> {
+        if self.is_empty() {
+            return Ok(items);
+        }
+        let reaches = |from: &str, targets: &[String]| -> anyhow::Result<bool> {
+            for target in targets {
+                if revwalk::is_ancestor(repository, target, from)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        let reached = |commit: &str, sources: &[String]| -> anyhow::Result<bool> {
+            for source in sources {
+                if revwalk::is_ancestor(repository, commit, source)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+
+        let mut kept = Vec::new();
+        for item in items {
+            let Some(commit) = revwalk::peel_to_commit(repository, &item.hash)? else {
+                continue;
+            };
+            if (self.contains.is_empty() || reaches(&commit, &self.contains)?)
+                && !reaches(&commit, &self.no_contains)?
+                && (self.merged.is_empty() || reached(&commit, &self.merged)?)
+                && !reached(&commit, &self.no_merged)?
+            {
+                kept.push(item);
+            }
+        }
+        Ok(kept)
+    }
+}
+
+// Compares the way git's versioncmp does for the common cases, with runs of digits compared by
+// their value and everything else byte by byte
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+    loop {
+        let (Some(&x), Some(&y)) = (a.first(), b.first()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let digits = |s: &[u8]| s.iter().take_while(|c| c.is_ascii_digit()).count();
+            let (a_len, b_len) = (digits(a), digits(b));
+            let (a_run, b_run) = (&a[..a_len], &b[..b_len]);
+            // Like strverscmp, a run with leading zeros is a fraction, and comes before integers
+            let fraction = |run: &[u8]| run.len() > 1 && run[0] == b'0';
+            let ordering = match (fraction(a_run), fraction(b_run)) {
+                (false, false) => a_len.cmp(&b_len).then_with(|| a_run.cmp(b_run)),
+                (true, true) => a_run.cmp(b_run),
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            (a, b) = (&a[a_len..], &b[b_len..]);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[1..], &b[1..]);
+        }
+    }
+}
+
+/// Shortens a ref name as far as it stays recognizable, eg. `refs/heads/master` to `master`
+pub fn shorten_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl<'a> RefFormatter<'a> {
+    pub fn new(repository: &'a Repository, config: &'a Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            repository,
+            config,
+            head: Ref::read_symbolic(repository, "HEAD")?,
+            objects: HashMap::new(),
+        })
+    }
+
+    /// Expands the placeholders in `format` for the given ref
+    pub fn format(&mut self, item: &RefItem, format: &str) -> anyhow::Result<String> {
+        let mut output = String::new();
+        let mut rest = format;
+
+        while let Some(idx) = rest.find('%') {
+            output.push_str(&rest[..idx]);
+            rest = &rest[idx + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                output.push('%');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('(') {
+                let end = after
+                    .find(')')
+                    .ok_or_else(|| anyhow!("Malformed format string: {}", format))?;
+                output.push_str(&self.atom(item, &after[..end])?.text);
+                rest = &after[end + 1..];
+            } else if let Some(byte) = rest
+                .get(..2)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                output.push(byte as char);
+                rest = &rest[2..];
+            } else {
+                output.push('%');
+            }
+        }
+        output.push_str(rest);
+
+        Ok(output)
+    }
+
+    /// Sorts the refs by the given keys, the first being the primary one. A key prefixed with `-`
+    /// sorts in descending order, and one prefixed with `version:` (or `v:`) compares the numbers
+    /// in it by value, so that `v1.10` comes after `v1.9`.
+    pub fn sort(&mut self, items: &mut [RefItem], keys: &[String]) -> anyhow::Result<()> {
+        for key in keys.iter().rev() {
+            let (descending, atom) = match key.strip_prefix('-') {
+                Some(atom) => (true, atom),
+                None => (false, key.as_str()),
+            };
+            let (by_version, atom) = match ["version:", "v:"]
+                .iter()
+                .find_map(|prefix| atom.strip_prefix(prefix))
+            {
+                Some(atom) => (true, atom),
+                None => (false, atom),
+            };
+
+            let mut values = HashMap::new();
+            for item in items.iter() {
+                values.insert(item.name.clone(), self.atom(item, atom)?);
+            }
+            items.sort_by(|a, b| {
+                let (a, b) = (&values[&a.name], &values[&b.name]);
+                let ordering = match (a.number, b.number) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    _ if by_version => version_cmp(&a.text, &b.text),
+                    _ => a.text.cmp(&b.text),
+                };
+                if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, item: &RefItem, atom: &str) -> anyhow::Result<AtomValue> {
+        // `%(*field)` refers to the object an annotated tag points to, and is empty otherwise
+        if let Some(atom) = atom.strip_prefix('*') {
+            let target = match self.object(&item.hash)? {
+                GitrsObject::TagObject(tag) => tag.object().map(str::to_string),
+                _ => None,
+            };
+            return match target {
+                Some(hash) => self.atom(
+                    &RefItem {
+                        name: item.name.clone(),
+                        hash,
+                    },
+                    atom,
+                ),
+                None => Ok(AtomValue {
+                    text: String::new(),
+                    number: None,
+                }),
+            };
+        }
+
+        let (name, modifier) = match atom.split_once(':') {
+            Some((name, modifier)) => (name, Some(modifier)),
+            None => (atom, None),
+        };
+        let text = |text: String| Ok(AtomValue { text, number: None });
+
+        match name {
+            "refname" => text(Self::format_ref_name(&item.name, modifier)?),
+            "objectname" => match modifier {
+                None => text(item.hash.clone()),
+                Some("short") => text(item.hash[..7].to_string()),
+                Some(other) => match other.strip_prefix("short=").map(str::parse::<usize>) {
+                    Some(Ok(len)) => text(item.hash[..len.clamp(4, 40)].to_string()),
+                    _ => bail!("Unrecognized %(objectname) argument: {}", other),
+                },
+            },
+            "objecttype" => text(self.object(&item.hash)?.get_type().to_string()),
+            "objectsize" => {
+                let size = self.object(&item.hash)?.serialize().len() as i64;
+                Ok(AtomValue {
+                    text: size.to_string(),
+                    number: Some(size),
+                })
+            }
+            "HEAD" => text(if self.head.as_deref() == Some(&item.name) {
+                "*".to_string()
+            } else {
+                " ".to_string()
+            }),
+            "symref" => text(
+                self.read_symbolic(&item.name)?
+                    .map(|target| Self::format_ref_name(&target, modifier))
+                    .transpose()?
+                    .unwrap_or_default(),
+            ),
+            "upstream" => text(self.upstream(item, modifier)?),
+            "subject" | "body" | "contents" => {
+                let message = self.message(&item.hash)?;
+                let (subject, body) = match message.split_once("\n\n") {
+                    Some((subject, body)) => (subject, body),
+                    None => (message.trim_end_matches('\n'), ""),
+                };
+                let part = match (name, modifier) {
+                    ("subject", _) | ("contents", Some("subject")) => {
+                        subject.lines().collect::<Vec<_>>().join(" ")
+                    }
+                    ("body", _) | ("contents", Some("body")) => body.to_string(),
+                    ("contents", None) => message,
+                    ("contents", Some(lines)) if lines.starts_with("lines=") => {
+                        let count: usize = lines["lines=".len()..].parse()?;
+                        Self::message_lines(&message, count)
+                    }
+                    _ => bail!("Unrecognized %(contents) argument: {}", atom),
+                };
+                text(part)
+            }
+            _ => {
+                let Some((role, field)) = ["author", "committer", "tagger", "creator"]
+                    .iter()
+                    .find_map(|role| Some((*role, name.strip_prefix(role)?)))
+                else {
+                    bail!("Unknown field name: {}", name);
+                };
+                let ident = self.ident(&item.hash, role)?;
+
+                match (field, ident) {
+                    ("name" | "email" | "date", None) => text(String::new()),
+                    ("name", Some(ident)) => text(ident.name),
+                    ("email", Some(ident)) => match modifier {
+                        Some("trim") => text(ident.email),
+                        _ => text(format!("<{}>", ident.email)),
+                    },
+                    ("date", Some(ident)) => Ok(AtomValue {
+                        text: date::format(
+                            ident.timestamp,
+                            &ident.timezone,
+                            modifier.unwrap_or("default"),
+                        )?,
+                        number: Some(ident.timestamp),
+                    }),
+                    _ => bail!("Unknown field name: {}", name),
+                }
+            }
+        }
+    }
+
+    // The first `count` lines of a message without its signature, each after the first indented
+    fn message_lines(message: &str, count: usize) -> String {
+        let unsigned = signature::split_signature(message.as_bytes(), ObjectType::Tag)
+            .map(|(payload, _)| String::from_utf8_lossy(&payload).into_owned());
+        let message = unsigned.as_deref().unwrap_or(message);
+        let message = message.strip_suffix('\n').unwrap_or(message);
+        if message.is_empty() {
+            return String::new();
+        }
+        message
+            .split('\n')
+            .take(count)
+            .collect::<Vec<_>>()
+            .join("\n    ")
+    }
+
+    fn format_ref_name(name: &str, modifier: Option<&str>) -> anyhow::Result<String> {
+        let Some(modifier) = modifier else {
+            return Ok(name.to_string());
+        };
+        if modifier == "short" {
+            return Ok(shorten_ref_name(name).to_string());
+        }
+
+        let components: Vec<&str> = name.split('/').collect();
+        let (strip_left, count) = match modifier.split_once('=') {
+            Some(("lstrip" | "strip", count)) => (true, count),
+            Some(("rstrip", count)) => (false, count),
+            _ => bail!("Unrecognized %(refname) argument: {}", modifier),
+        };
+        let count: isize = count.parse()?;
+        // A negative count keeps that many components instead
+        let remove = if count < 0 {
+            components.len().saturating_sub(count.unsigned_abs())
+        } else {
+            (count as usize).min(components.len())
+        };
+
+        Ok(if strip_left {
+            components[remove..].join("/")
+        } else {
+            components[..components.len() - remove].join("/")
+        })
+    }
+
+    // Expands %(upstream) and its :short, :track and :trackshort variants
+    // Reads the symbolic ref `name` names within the repository's namespace, with the namespace
+    // left out of its target
+    fn read_symbolic(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let prefix = refs::namespace_prefix(self.repository);
+        Ok(
+            Ref::read_symbolic(self.repository, &format!("{}{}", prefix, name))?
+                .map(|target| target.strip_prefix(&prefix).unwrap_or(&target).to_string()),
+        )
+    }
+
+    fn upstream(&mut self, item: &RefItem, modifier: Option<&str>) -> anyhow::Result<String> {
+        let Some(branch) = item.name.strip_prefix("refs/heads/") else {
+            return Ok(String::new());
+        };
+        let Some(upstream) = branch::upstream(self.config, branch) else {
+            return Ok(String::new());
+        };
+
+        let track = || -> anyhow::Result<Option<(usize, usize)>> {
+            match Ref::try_resolve(self.repository, &upstream)? {
+                Some(upstream_hash) => Ok(Some(revwalk::ahead_behind(
+                    self.repository,
+                    &item.hash,
+                    &upstream_hash,
+                )?)),
+                None => Ok(None),
+            }
+        };
+
+        Ok(match modifier {
+            None => upstream.clone(),
+            Some("short") => shorten_ref_name(&upstream).to_string(),
+            Some("track") => match track()? {
+                None => "[gone]".to_string(),
+                Some((0, 0)) => String::new(),
+                Some((ahead, 0)) => format!("[ahead {}]", ahead),
+                Some((0, behind)) => format!("[behind {}]", behind),
+                Some((ahead, behind)) => format!("[ahead {}, behind {}]", ahead, behind),
+            },
+            Some("trackshort") => match track()? {
+                None => String::new(),
+                Some((0, 0)) => "=".to_string(),
+                Some((_, 0)) => ">".to_string(),
+                Some((0, _)) => "<".to_string(),
+                Some(_) => "<>".to_string(),
+            },
+            Some(other) => bail!("Unrecognized %(upstream) argument: {}", other),
+        })
+    }
+
+    fn object(&mut self, hash: &str) -> anyhow::Result<&mut GitrsObject> {
+        if !self.objects.contains_key(hash) {
+            let object = GitrsObject::read(self.repository, hash)?;
+            self.objects.insert(hash.to_string(), object);
+        }
+        Ok(self.objects.get_mut(hash).expect("Object was just cached"))
+    }
+
+    fn message(&mut self, hash: &str) -> anyhow::Result<String> {
+        Ok(match self.object(hash)? {
+            GitrsObject::CommitObject(commit) => commit.message().to_string(),
+            GitrsObject::TagObject(tag) => tag.message().to_string(),
+            _ => String::new(),
+        })
+    }
+
+    // The identity in the given role, where the creator is the committer of a commit or the tagger
+    // of a tag
+    fn ident(&mut self, hash: &str, role: &str) -> anyhow::Result<Option<Ident>> {
+        Ok(match (role, &*self.object(hash)?) {
+            ("author", GitrsObject::CommitObject(commit)) => commit.author().ok(),
+            ("committer" | "creator", GitrsObject::CommitObject(commit)) => commit.committer().ok(),
+            ("tagger" | "creator", GitrsObject::TagObject(tag)) => tag.tagger(),
+            _ => None,
+        })
+    }
+}
@@ -1,24 +1,69 @@
 use core::str;
 use std::{
     fs::{self},
-    io,
-    path::Path,
+    io::{self, Write},
+    path::{Path, PathBuf},
 };
 
-use anyhow::Context;
+use anyhow::{Context, bail, ensure};
 use indexmap::IndexMap;
 
+use crate::ident::Ident;
 use crate::repository::Repository;
 
-// Manages git references
+// Manages git references. A ref is a file under the gitdir holding a hash, or `ref: <name>` for a
+// symbolic ref, unless git gc or git pack-refs moved it into packed-refs, which lists such refs
+// with their hashes, each followed by a `^<hash>` line with what it peels to if it is an annotated
+// tag. A ref is only read from there if it has no file of its own, and deleting one deletes it from
+// both.
+
+/// Stands in for the value of a ref that doesn't exist, eg. when requiring that a ref is created
+pub const ZERO_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// The refs besides HEAD that git keeps at the top of the gitdir, recording what an operation acted
+/// on (eg. ORIG_HEAD is where HEAD was before a reset). Like HEAD, they belong to a single
+/// worktree.
+pub const PSEUDO_REFS: [&str; 5] = [
+    "ORIG_HEAD",
+    "MERGE_HEAD",
+    "FETCH_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+];
+
+// Symbolic refs pointing to symbolic refs are followed at most this many times
+const MAX_SYMREF_DEPTH: usize = 5;
 
 pub struct Ref;
 
+/// A change to a single ref, applied atomically with the others in a `Ref::transaction`
+pub struct RefUpdate {
+    pub name: String,
+    /// New value, or None to delete the ref
+    pub new: Option<String>,
+    /// Expected current value, where ZERO_HASH means the ref must not exist
+    pub old: Option<String>,
+    /// Only check the current value, without changing the ref
+    pub verify_only: bool,
+    /// Update the ref a symbolic ref points to, rather than the symbolic ref itself
+    pub deref: bool,
+}
+
+// Guards a ref against concurrent updates by holding `<ref>.lock`, which is removed when dropped
+// unless the new value was committed
+struct RefLock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    committed: bool,
+}
+
 impl Ref {
     pub fn resolve(repository: &Repository, ref_path: &[&str]) -> anyhow::Result<String> {
-        let path = repository
-            .get_path_to_file(ref_path)
-            .with_context(|| format!("Not a file: {:?}", ref_path))?;
+        let Some(path) = repository.get_path_to_file(ref_path) else {
+            return packed(repository)?
+                .swap_remove(&ref_path.join("/"))
+                .with_context(|| format!("Not a file: {:?}", ref_path));
+        };
 
         let mut bytes =
             fs::read(&path).with_context(|| format!("Failed to read file: {}", path.display()))?;
@@ -34,18 +79,66 @@ impl Ref {
             let parts: Vec<&str> = data[5..].split('/').collect();
             Ref::resolve(repository, &parts)
         } else {
-            Ok(data.to_owned())
+            // FETCH_HEAD and MERGE_HEAD list a commit per line, the first of which is their value,
+            // with FETCH_HEAD describing each one after a tab
+            let first = data.lines().next().unwrap_or_default();
+            Ok(first.split('\t').next().unwrap_or_default().to_owned())
         }
     }
 
-    pub fn list_at(
-        repository: &Repository,
-        path: &Path,
-    ) -> anyhow::Result<IndexMap<String, String>> {
-        Ok(Self::list_at_dir(repository, path)?.into_iter().collect())
+    /// Lists every ref under `refs/`, sorted by name, with names relative to the repository (eg.
+    /// `refs/heads/master`) and the hashes they resolve to. Like git, broken refs, such as symbolic
+    /// refs to refs that don't exist, are left out with a warning.
+    pub fn list(repository: &Repository) -> anyhow::Result<Vec<(String, String)>> {
+        let mut names = Vec::new();
+        if let Some(dir) = repository.get_path_to_dir(&["refs"]) {
+            Self::collect_names(&dir, "refs", &mut names)?;
+        }
+        names.extend(packed(repository)?.into_keys());
+        names.sort();
+        names.dedup();
+
+        let mut refs = Vec::new();
+        for name in names {
+            match Self::try_resolve(repository, &name)? {
+                Some(hash) if hash.len() == 40 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+                    refs.push((name, hash))
+                }
+                _ => eprintln!("warning: ignoring broken ref {}", name),
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Lists the refs in the repository's namespace like `list`, named as they are within it, eg.
+    /// `refs/heads/master` for `refs/namespaces/a/refs/heads/master`. Without a namespace, that is
+    /// every ref.
+    pub fn list_namespaced(repository: &Repository) -> anyhow::Result<Vec<(String, String)>> {
+        let prefix = namespace_prefix(repository);
+        Ok(Self::list(repository)?
+            .into_iter()
+            .filter_map(|(name, hash)| Some((name.strip_prefix(&prefix)?.to_string(), hash)))
+            .filter(|(name, _)| name.starts_with("refs/"))
+            .collect())
+    }
+
+    fn collect_names(dir: &Path, prefix: &str, names: &mut Vec<String>) -> anyhow::Result<()> {
+        for entry in
+            fs::read_dir(dir).with_context(|| format!("Failed to read dir: {}", dir.display()))?
+        {
+            let entry = entry?;
+            let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                Self::collect_names(&entry.path(), &name, names)?;
+            } else if !name.ends_with(".lock") {
+                names.push(name);
+            }
+        }
+        Ok(())
     }
 
     pub fn create_at(repository: &Repository, hash: &str, paths: &[&str]) -> anyhow::Result<()> {
+        verify_name(&paths.join("/"))?;
         let path = repository
             .create_file(paths)
             .with_context(|| format!("Couldn't create file at {:?}", paths))?;
@@ -54,41 +147,504 @@ impl Ref {
         Ok(())
     }
 
-    fn list_at_dir(repository: &Repository, path: &Path) -> anyhow::Result<Vec<(String, String)>> {
-        let base_parts: Vec<String> = path
-            .components()
-            .map(|component| {
-                component
-                    .as_os_str()
-                    .to_str()
-                    .expect("Could not convert path component to string")
-                    .to_string()
-            })
-            .collect();
-
-        let mut entries: Vec<_> = fs::read_dir(path)
-            .with_context(|| format!("Failed to read dir: {}", path.display()))?
-            .collect::<Result<_, io::Error>>()?;
-
-        entries.sort_by_key(|dir_entry| dir_entry.file_name());
-
-        entries
-            .iter()
-            .try_fold(vec![], |mut acc, dir_entry| -> anyhow::Result<_> {
-                let file_type = dir_entry.file_type()?;
-                if file_type.is_dir() {
-                    acc.extend(Self::list_at_dir(repository, &dir_entry.path())?);
-                } else {
-                    let file_name = dir_entry.file_name().to_string_lossy().into_owned();
-                    let full_path_parts: Vec<&str> = base_parts
-                        .iter()
-                        .chain(std::iter::once(&file_name))
-                        .map(String::as_str)
-                        .collect();
-                    let resolved = Self::resolve(repository, &full_path_parts)?;
-                    acc.push((full_path_parts.join("/"), resolved));
+    pub fn delete_at(repository: &Repository, paths: &[&str]) -> anyhow::Result<()> {
+        let name = paths.join("/");
+        let was_packed = remove_packed(repository, &name)?;
+        match repository.get_path_to_file(paths) {
+            Some(path) => fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete file: {}", path.display())),
+            None if was_packed => Ok(()),
+            None => bail!("Not a file: {:?}", paths),
+        }
+    }
+
+    /// Resolves the ref called `name` (eg. `HEAD` or `refs/heads/master`), returning None if it,
+    /// or the ref it points to, doesn't exist
+    pub fn try_resolve(repository: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let parts: Vec<&str> = name.split('/').collect();
+            if repository.get_path_to_file(&parts).is_none() {
+                return Ok(packed(repository)?.swap_remove(&name));
+            }
+            match Self::read_symbolic(repository, &name)? {
+                Some(target) => name = target,
+                None => return Self::resolve(repository, &parts).map(Some),
+            }
+        }
+        bail!("Too many levels of symbolic refs at {}", name)
+    }
+
+    /// Returns the target of the symbolic ref `name`, or None if it is a regular ref or doesn't
+    /// exist
+    pub fn read_symbolic(repository: &Repository, name: &str) -> anyhow::Result<Option<String>> {
+        let parts: Vec<&str> = name.split('/').collect();
+        let Some(path) = repository.get_path_to_file(&parts) else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        Ok(content
+            .strip_prefix("ref:")
+            .map(|target| target.trim().to_string()))
+    }
+
+    /// Points the symbolic ref `name` at the ref `target`
+    pub fn write_symbolic(repository: &Repository, name: &str, target: &str) -> anyhow::Result<()> {
+        verify_name(name)?;
+        verify_name(target)?;
+        ensure!(
+            target.starts_with("refs/"),
+            "Refusing to point {} outside of refs/",
+            name
+        );
+
+        let mut lock = RefLock::acquire(repository, name)?;
+        lock.commit(&format!("ref: {}\n", target))
+    }
+
+    /// Appends an entry to the reflog of `name` recording that `ident` moved it from `old` (None
+    /// if it didn't exist) to `new`. HEAD's reflog belongs to the worktree, the others are shared.
+    pub fn append_log(
+        repository: &Repository,
+        name: &str,
+        old: Option<&str>,
+        new: &str,
+        ident: &Ident,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let base = match name {
+            "HEAD" => &repository.gitdir,
+            _ => &repository.commondir,
+        };
+        let path = base.join("logs").join(name);
+        fs::create_dir_all(path.parent().expect("Log has a parent"))?;
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(
+            log,
+            "{} {} {}\t{}",
+            old.unwrap_or(ZERO_HASH),
+            new,
+            ident,
+            message
+        )?;
+        Ok(())
+    }
+
+    /// Applies all the updates, or none of them if any ref is locked or doesn't have its expected
+    /// value
+    pub fn transaction(repository: &Repository, updates: &[RefUpdate]) -> anyhow::Result<()> {
+        let mut locked = Vec::new();
+        let mut names = Vec::new();
+        for update in updates {
+            let name = Self::update_target(repository, update, &names)?;
+            let lock = RefLock::acquire(repository, &name)?;
+            Self::check_old(repository, &name, update)?;
+            names.push(name.clone());
+            locked.push((name, lock, update));
+        }
+
+        for (name, mut lock, update) in locked {
+            if update.verify_only {
+                continue;
+            }
+            match &update.new {
+                Some(hash) => lock.commit(&format!("{}\n", hash))?,
+                None => {
+                    remove_packed(repository, &name)?;
+                    if lock.path.exists() {
+                        fs::remove_file(&lock.path)
+                            .with_context(|| format!("Failed to delete ref {}", name))?;
+                    }
                 }
-                Ok(acc)
-            })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `transaction` would apply all the updates, without locking or changing any
+    /// ref, returning the refs they would update, with symbolic refs followed
+    pub fn check_transaction(
+        repository: &Repository,
+        updates: &[RefUpdate],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for update in updates {
+            let name = Self::update_target(repository, update, &names)?;
+            let parts: Vec<&str> = name.split('/').collect();
+            let lock_path = RefLock::lock_path(&repository.get_path(&parts));
+            ensure!(
+                !lock_path.exists(),
+                "Unable to create '{}': another process may be updating the ref",
+                lock_path.display()
+            );
+            Self::check_old(repository, &name, update)?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    // The ref `update` changes, following symbolic refs if it dereferences them, which must not
+    // be one of those `taken` by the other updates
+    fn update_target(
+        repository: &Repository,
+        update: &RefUpdate,
+        taken: &[String],
+    ) -> anyhow::Result<String> {
+        let mut name = update.name.clone();
+        if update.deref {
+            for _ in 0..MAX_SYMREF_DEPTH {
+                match Self::read_symbolic(repository, &name)? {
+                    Some(target) => name = target,
+                    None => break,
+                }
+            }
+        }
+        verify_name(&name)?;
+        ensure!(
+            !taken.contains(&name),
+            "Multiple updates for ref '{}' not allowed",
+            name
+        );
+        Ok(name)
+    }
+
+    // Checks that `name` has the value `update` expects it to have, if any
+    fn check_old(repository: &Repository, name: &str, update: &RefUpdate) -> anyhow::Result<()> {
+        let Some(expected) = &update.old else {
+            return Ok(());
+        };
+        let current = Self::try_resolve(repository, name)?;
+        match (current.as_deref(), expected.as_str()) {
+            (None, ZERO_HASH) => {}
+            (Some(current), ZERO_HASH) => {
+                bail!(
+                    "cannot lock ref '{}': reference already exists at {}",
+                    name,
+                    current
+                )
+            }
+            (None, _) => bail!("cannot lock ref '{}': unable to resolve reference", name),
+            (Some(current), expected) if current != expected => bail!(
+                "cannot lock ref '{}': is at {} but expected {}",
+                name,
+                current,
+                expected
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// Reads packed-refs, returning the refs it lists with their hashes
+fn packed(repository: &Repository) -> anyhow::Result<IndexMap<String, String>> {
+    let path = repository.commondir.join("packed-refs");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(IndexMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut refs = IndexMap::new();
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let Some((hash, name)) = line.split_once(' ') else {
+            bail!("unexpected line in {}: {}", path.display(), line);
+        };
+        refs.insert(name.to_string(), hash.to_string());
+    }
+    Ok(refs)
+}
+
+// Deletes `name` from packed-refs, with the peeled value after it, returning whether it was there
+fn remove_packed(repository: &Repository, name: &str) -> anyhow::Result<bool> {
+    let path = repository.commondir.join("packed-refs");
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(false);
+    };
+    let mut kept = String::new();
+    let mut removed = false;
+    let mut skipping = false;
+    for line in content.lines() {
+        if line.starts_with('^') && skipping {
+            continue;
+        }
+        skipping = line
+            .split_once(' ')
+            .is_some_and(|(_, packed)| packed == name);
+        if skipping {
+            removed = true;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if removed {
+        let mut lock = RefLock::acquire(repository, "packed-refs")?;
+        lock.commit(&kept)?;
+    }
+    Ok(removed)
+}
+
+impl RefLock {
+    fn acquire(repository: &Repository, name: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = name.split('/').collect();
+        let path = repository.get_path(&parts);
+        let lock_path = Self::lock_path(&path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create the path {}", parent.display()))?;
+        }
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .with_context(|| {
+                format!(
+                    "Unable to create '{}': another process may be updating the ref",
+                    lock_path.display()
+                )
+            })?;
+
+        Ok(Self {
+            path,
+            lock_path,
+            committed: false,
+        })
+    }
+
+    // The lock file `path` is locked with
+    fn lock_path(path: &Path) -> PathBuf {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        PathBuf::from(lock_path)
+    }
+
+    // Writes the new contents to the lock file and moves it into place
+    fn commit(&mut self, content: &str) -> anyhow::Result<()> {
+        fs::File::create(&self.lock_path)?.write_all(content.as_bytes())?;
+        fs::rename(&self.lock_path, &self.path)
+            .with_context(|| format!("Failed to update {}", self.path.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Returns the prefix the refs of the repository's namespace are stored under, eg.
+/// `refs/namespaces/a/refs/namespaces/b/` for the namespace `a/b`, or an empty string without one
+pub fn namespace_prefix(repository: &Repository) -> String {
+    repository
+        .namespace
+        .iter()
+        .flat_map(|namespace| namespace.split('/'))
+        .filter(|component| !component.is_empty())
+        .map(|component| format!("refs/namespaces/{}/", component))
+        .collect()
+}
+
+/// Relaxes the rules `check_name` applies
+#[derive(Default)]
+pub struct RefNameOptions {
+    /// Allow names without a `/`, like HEAD
+    pub allow_onelevel: bool,
+    /// Allow a single `*`, as in refspecs
+    pub refspec_pattern: bool,
+}
+
+/// Returns true if `name` follows git's rules for ref names: no component starts with a `.` or
+/// ends with `.lock`, the name contains no `..`, `@{`, `\`, whitespace, control characters or any
+/// of `~^:?*[`, and it doesn't start or end with a `/`, end with a `.` or equal `@`
+pub fn check_name(name: &str, options: &RefNameOptions) -> bool {
+    let forbidden =
+        |c: char| c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '[' | '\\');
+    let stars = name.matches('*').count();
+    name.split('/').all(|component| {
+        !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+    }) && !name.chars().any(forbidden)
+        && (stars == 0 || (options.refspec_pattern && stars == 1))
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && name != "@"
+        && (options.allow_onelevel || name.contains('/'))
+}
+
+/// Collapses repeated slashes and drops a leading one, as `check-ref-format --normalize` does
+pub fn normalize_name(name: &str) -> String {
+    name.split('/')
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+        + if name.ends_with('/') { "/" } else { "" }
+}
+
+// Rejects ref names that git couldn't handle or that would escape the repository directory
+fn verify_name(name: &str) -> anyhow::Result<()> {
+    let options = RefNameOptions {
+        allow_onelevel: true,
+        ..RefNameOptions::default()
+    };
+    if !check_name(name, &options) {
+        bail!("Invalid ref name: '{}'", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_like_check_ref_format() {
+        let onelevel = RefNameOptions {
+            allow_onelevel: true,
+            ..RefNameOptions::default()
+        };
+        let pattern = RefNameOptions {
+            refspec_pattern: true,
+            ..RefNameOptions::default()
+        };
+        // Whether git 2.39 check-ref-format takes each name plainly, with --allow-onelevel and with
+        // --refspec-pattern
+        for (name, expected) in [
+            ("refs/heads/master", [true, true, true]),
+            ("heads/foo", [true, true, true]),
+            ("foo", [false, true, false]),
+            ("HEAD", [false, true, false]),
+            ("refs/heads/.hidden", [false; 3]),
+            ("refs/heads/a.lock", [false; 3]),
+            ("refs/heads/a..b", [false; 3]),
+            ("refs/heads/a@{1}", [false; 3]),
+            ("refs/heads/a\\b", [false; 3]),
+            ("refs/heads/a b", [false; 3]),
+            ("refs/heads/a\x7fb", [false; 3]),
+            ("refs/heads/a~1", [false; 3]),
+            ("refs/heads/a^", [false; 3]),
+            ("refs/heads/a:b", [false; 3]),
+            ("refs/heads/a?", [false; 3]),
+            ("refs/heads/a[", [false; 3]),
+            ("refs/heads/*", [false, false, true]),
+            ("refs/*/foo", [false, false, true]),
+            ("refs/heads/a*b*", [false; 3]),
+            ("/refs/heads/a", [false; 3]),
+            ("refs/heads/a/", [false; 3]),
+            ("refs//heads/a", [false; 3]),
+            ("refs/heads/a.", [false; 3]),
+            ("@", [false; 3]),
+            ("a@b/c", [true; 3]),
+            ("refs/heads/a.b", [true; 3]),
+            ("refs/heads/lock.b", [true; 3]),
+            ("refs/heads/@", [true; 3]),
+            ("café/x", [true; 3]),
+        ] {
+            let actual = [&RefNameOptions::default(), &onelevel, &pattern]
+                .map(|options| check_name(name, options));
+            assert_eq!(actual, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_name("/refs/heads/a"), "refs/heads/a");
+        assert_eq!(normalize_name("refs//heads///a"), "refs/heads/a");
+        assert_eq!(normalize_name("refs/heads/a"), "refs/heads/a");
+    }
+
+    #[test]
+    fn reads_packed_refs() {
+        let worktree =
+            std::env::temp_dir().join(format!("gitrs-packed-refs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let (commit, tag) = ("a".repeat(40), "b".repeat(40));
+        // As git gc writes it, with side updated since, which its ref file then says
+        fs::write(
+            repository.commondir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {commit} refs/heads/master\n{commit} refs/heads/side\n{tag} refs/tags/v2\n^{commit}\n"
+            ),
+        )
+        .unwrap();
+        Ref::create_at(&repository, &"c".repeat(40), &["refs", "heads", "side"]).unwrap();
+
+        assert_eq!(Ref::resolve(&repository, &["HEAD"]).unwrap(), commit);
+        assert_eq!(
+            Ref::list(&repository).unwrap(),
+            [
+                ("refs/heads/master".to_string(), commit.clone()),
+                ("refs/heads/side".to_string(), "c".repeat(40)),
+                ("refs/tags/v2".to_string(), tag.clone()),
+            ]
+        );
+
+        Ref::delete_at(&repository, &["refs", "tags", "v2"]).unwrap();
+        Ref::transaction(
+            &repository,
+            &[RefUpdate {
+                name: "refs/heads/side".to_string(),
+                new: None,
+                old: Some("c".repeat(40)),
+                verify_only: false,
+                deref: false,
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            Ref::try_resolve(&repository, "refs/heads/side").unwrap(),
+            None
+        );
+        assert_eq!(
+            fs::read_to_string(repository.commondir.join("packed-refs")).unwrap(),
+            format!("# pack-refs with: peeled fully-peeled sorted \n{commit} refs/heads/master\n")
+        );
+        fs::remove_dir_all(&worktree).unwrap();
+    }
+
+    #[test]
+    fn lists_around_broken_refs() {
+        let worktree =
+            std::env::temp_dir().join(format!("gitrs-broken-refs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&worktree);
+        fs::create_dir_all(&worktree).unwrap();
+        let repository = Repository::init(&worktree).unwrap();
+        let commit = "a".repeat(40);
+        Ref::create_at(&repository, &commit, &["refs", "heads", "master"]).unwrap();
+        // A packed ref a symbolic ref points at isn't broken, one to nothing is
+        fs::write(
+            repository.commondir.join("packed-refs"),
+            format!("{commit} refs/heads/packed\n"),
+        )
+        .unwrap();
+        Ref::write_symbolic(&repository, "refs/heads/alias", "refs/heads/packed").unwrap();
+        Ref::write_symbolic(&repository, "refs/heads/dangling", "refs/heads/nope").unwrap();
+        Ref::create_at(&repository, "garbage", &["refs", "heads", "junk"]).unwrap();
+
+        assert_eq!(
+            Ref::list(&repository).unwrap(),
+            [
+                ("refs/heads/alias".to_string(), commit.clone()),
+                ("refs/heads/master".to_string(), commit.clone()),
+                ("refs/heads/packed".to_string(), commit),
+            ]
+        );
+        fs::remove_dir_all(&worktree).unwrap();
     }
 }
@@ -0,0 +1,711 @@
+// Matches POSIX extended regular expressions the way git compiles them for word diffs and diff
+// drivers, with REG_NEWLINE: `.` and negated brackets don't match a newline, and `^` and `$` match
+// at the start and end of every line. GNU's \w, \W, \s, \S, \b, \B, \<, \>, \` and \' are
+// understood too. Patterns are compiled into a program for a Thompson-style machine, which finds
+// the leftmost match and the longest one starting there, as POSIX requires, in time linear in the
+// text. The groups of a match are then found by following the program's paths in order of
+// preference until one ends where the match does.
+use std::ops::Range;
+
+use anyhow::bail;
+
+/// A compiled pattern
+pub struct Regex {
+    program: Vec<Inst>,
+    /// The number of groups, counting the whole match as group 0
+    groups: usize,
+}
+
+// A set of bytes, one bit each
+#[derive(Clone)]
+struct ByteSet([u64; 4]);
+
+#[derive(Clone, Copy)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    TextStart,
+    TextEnd,
+    WordBoundary,
+    NotWordBoundary,
+    WordStart,
+    WordEnd,
+}
+
+enum Node {
+    Empty,
+    Bytes(ByteSet),
+    Assert(Assertion),
+    Group(Box<Node>, usize),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+enum Inst {
+    /// Matches one byte in the set
+    Bytes(ByteSet),
+    /// Tries the first branch before the second
+    Split(usize, usize),
+    Jump(usize),
+    /// Records the position in a group's start or end slot
+    Save(usize),
+    Assert(Assertion),
+    Match,
+}
+
+// Repeating anything more often than this is refused, as glibc does
+const MAX_REPEAT: usize = 255;
+
+impl Regex {
+    /// Compiles `pattern`, ignoring the case of letters if `ignore_case` is set
+    pub fn new(pattern: &[u8], ignore_case: bool) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            pattern,
+            position: 0,
+            groups: 1,
+            ignore_case,
+        };
+        let node = parser.alternation()?;
+        if parser.position < pattern.len() {
+            bail!("Unmatched ) or \\)");
+        }
+        let mut program = Vec::new();
+        compile(&Node::Group(Box::new(node), 0), &mut program);
+        program.push(Inst::Match);
+        Ok(Self {
+            program,
+            groups: parser.groups,
+        })
+    }
+
+    /// Finds the leftmost match in `text`, and the longest of those starting there
+    pub fn find(&self, text: &[u8]) -> Option<Range<usize>> {
+        self.search(text, true)
+    }
+
+    /// Finds a match like `find`, but with `^` not matching at the start of `text`, as REG_NOTBOL
+    /// has it for text that continues a line
+    pub fn find_not_bol(&self, text: &[u8]) -> Option<Range<usize>> {
+        self.search(text, false)
+    }
+
+    // Finds the leftmost longest match, taking the start of `text` to start a line if `bol` is set
+    fn search(&self, text: &[u8], bol: bool) -> Option<Range<usize>> {
+        // Each thread is a position in the program and where its match started. They are kept in
+        // order of their starts, as a thread started earlier always wins.
+        let mut threads: Vec<(usize, usize)> = Vec::new();
+        let mut best: Option<Range<usize>> = None;
+        let mut seen = vec![usize::MAX; self.program.len()];
+        for position in 0..=text.len() {
+            let generation = position;
+            let mut next = Vec::new();
+            let mut stack = Vec::new();
+            let current = std::mem::take(&mut threads);
+            let new_thread = best.is_none().then_some((0, position));
+            for (pc, start) in current.into_iter().chain(new_thread) {
+                stack.push(pc);
+                while let Some(pc) = stack.pop() {
+                    if seen[pc] == generation {
+                        continue;
+                    }
+                    seen[pc] = generation;
+                    match &self.program[pc] {
+                        Inst::Bytes(_) => next.push((pc, start)),
+                        Inst::Split(first, second) => {
+                            stack.push(*second);
+                            stack.push(*first);
+                        }
+                        Inst::Jump(to) => stack.push(*to),
+                        Inst::Save(_) => stack.push(pc + 1),
+                        Inst::Assert(assertion) => {
+                            if assertion.holds(text, position, bol) {
+                                stack.push(pc + 1);
+                            }
+                        }
+                        Inst::Match => {
+                            if best.as_ref().is_none_or(|best| best.start == start) {
+                                best = Some(start..position);
+                            }
+                        }
+                    }
+                }
+            }
+            // Threads that started after the best match can't beat it
+            if let Some(best) = &best {
+                next.retain(|&(_, start)| start <= best.start);
+            }
+            let Some(&byte) = text.get(position) else {
+                break;
+            };
+            threads = next
+                .into_iter()
+                .filter(
+                    |&(pc, _)| matches!(&self.program[pc], Inst::Bytes(set) if set.contains(byte)),
+                )
+                .map(|(pc, start)| (pc + 1, start))
+                .collect();
+            if threads.is_empty() && best.is_some() {
+                break;
+            }
+        }
+        best
+    }
+
+    /// Finds the leftmost longest match in `text` like `find`, along with where each group
+    /// matched within it. Group 0 is the whole match.
+    pub fn captures(&self, text: &[u8]) -> Option<Vec<Option<Range<usize>>>> {
+        let found = self.find(text)?;
+        let mut slots = vec![None; 2 * self.groups];
+        let width = found.len() + 1;
+        let mut visited = vec![false; self.program.len() * width];
+        let mut search = CaptureSearch {
+            regex: self,
+            text,
+            start: found.start,
+            end: found.end,
+            visited: &mut visited,
+            width,
+        };
+        if !search.follow(0, found.start, &mut slots) {
+            unreachable!("The match was found, so a path to it exists");
+        }
+        Some(
+            (0..self.groups)
+                .map(|group| match (slots[2 * group], slots[2 * group + 1]) {
+                    (Some(start), Some(end)) => Some(start..end),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+}
+
+struct CaptureSearch<'a> {
+    regex: &'a Regex,
+    text: &'a [u8],
+    start: usize,
+    end: usize,
+    /// The points of the program already tried at each position
+    visited: &'a mut Vec<bool>,
+    width: usize,
+}
+
+impl CaptureSearch<'_> {
+    // Follows the program from `pc` at `position`, returning true once it reaches a match ending
+    // where the whole match does. Whether a point can get there doesn't depend on the groups
+    // recorded on the way, so each is tried once.
+    fn follow(&mut self, pc: usize, position: usize, slots: &mut Vec<Option<usize>>) -> bool {
+        let Some(offset) = position
+            .checked_sub(self.start)
+            .filter(|&offset| offset < self.width)
+        else {
+            return false;
+        };
+        let index = pc * self.width + offset;
+        if self.visited[index] {
+            return false;
+        }
+        self.visited[index] = true;
+        match &self.regex.program[pc] {
+            Inst::Bytes(set) => {
+                position < self.end
+                    && set.contains(self.text[position])
+                    && self.follow(pc + 1, position + 1, slots)
+            }
+            Inst::Split(first, second) => {
+                let (first, second) = (*first, *second);
+                self.follow(first, position, slots) || self.follow(second, position, slots)
+            }
+            Inst::Jump(to) => self.follow(*to, position, slots),
+            Inst::Save(slot) => {
+                let slot = *slot;
+                let previous = slots[slot].replace(position);
+                if self.follow(pc + 1, position, slots) {
+                    return true;
+                }
+                slots[slot] = previous;
+                false
+            }
+            Inst::Assert(assertion) => {
+                assertion.holds(self.text, position, true) && self.follow(pc + 1, position, slots)
+            }
+            Inst::Match => position == self.end,
+        }
+    }
+}
+
+impl ByteSet {
+    fn empty() -> Self {
+        Self([0; 4])
+    }
+
+    fn of(byte: u8) -> Self {
+        let mut set = Self::empty();
+        set.insert(byte);
+        set
+    }
+
+    fn from_fn(f: impl Fn(u8) -> bool) -> Self {
+        let mut set = Self::empty();
+        (0..=255).filter(|&b| f(b)).for_each(|b| set.insert(b));
+        set
+    }
+
+    fn insert(&mut self, byte: u8) {
+        self.0[byte as usize / 64] |= 1 << (byte % 64);
+    }
+
+    fn contains(&self, byte: u8) -> bool {
+        self.0[byte as usize / 64] & (1 << (byte % 64)) != 0
+    }
+
+    fn union(&mut self, other: &ByteSet) {
+        (0..4).for_each(|i| self.0[i] |= other.0[i]);
+    }
+
+    // Every byte not in the set, except a newline, which only ever matches itself
+    fn negated(&self) -> Self {
+        let mut set = Self(self.0.map(|bits| !bits));
+        set.0[0] &= !(1 << b'\n');
+        set
+    }
+
+    fn ignoring_case(&self) -> Self {
+        Self::from_fn(|b| {
+            self.contains(b)
+                || self.contains(b.to_ascii_lowercase())
+                || self.contains(b.to_ascii_uppercase())
+        })
+    }
+}
+
+impl Assertion {
+    fn holds(self, text: &[u8], position: usize, bol: bool) -> bool {
+        let before = position.checked_sub(1).map(|i| text[i]);
+        let after = text.get(position).copied();
+        let word_before = before.is_some_and(is_word);
+        let word_after = after.is_some_and(is_word);
+        match self {
+            Assertion::LineStart => before.map_or(bol, |b| b == b'\n'),
+            Assertion::LineEnd => after.is_none_or(|b| b == b'\n'),
+            Assertion::TextStart => before.is_none(),
+            Assertion::TextEnd => after.is_none(),
+            Assertion::WordBoundary => word_before != word_after,
+            Assertion::NotWordBoundary => word_before == word_after,
+            Assertion::WordStart => !word_before && word_after,
+            Assertion::WordEnd => word_before && !word_after,
+        }
+    }
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+// The bytes in a named class like `[:alpha:]`, which are the same as in the C locale
+fn class(name: &[u8]) -> Option<ByteSet> {
+    let test: fn(&u8) -> bool = match name {
+        b"alpha" => u8::is_ascii_alphabetic,
+        b"digit" => u8::is_ascii_digit,
+        b"alnum" => u8::is_ascii_alphanumeric,
+        b"upper" => u8::is_ascii_uppercase,
+        b"lower" => u8::is_ascii_lowercase,
+        b"space" => |b| b" \t\n\r\x0b\x0c".contains(b),
+        b"blank" => |b| *b == b' ' || *b == b'\t',
+        b"punct" => u8::is_ascii_punctuation,
+        b"xdigit" => u8::is_ascii_hexdigit,
+        b"cntrl" => u8::is_ascii_control,
+        b"print" => |b| (0x20..0x7f).contains(b),
+        b"graph" => u8::is_ascii_graphic,
+        _ => return None,
+    };
+    Some(ByteSet::from_fn(|b| test(&b)))
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    position: usize,
+    /// The number of groups opened so far, counting the whole match
+    groups: usize,
+    ignore_case: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.position).copied()
+    }
+
+    fn alternation(&mut self) -> anyhow::Result<Node> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some(b'|') {
+            self.position += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().expect("There is a branch")
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn concatenation(&mut self) -> anyhow::Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(b) = self.peek() {
+            if b == b'|' || b == b')' {
+                break;
+            }
+            let mut node = self.atom()?;
+            while let Some((min, max)) = self.repetition()? {
+                node = Node::Repeat {
+                    node: Box::new(node),
+                    min,
+                    max,
+                };
+            }
+            nodes.push(node);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().expect("There is a node"),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    // Parses a `*`, `+`, `?` or interval after an atom
+    fn repetition(&mut self) -> anyhow::Result<Option<(usize, Option<usize>)>> {
+        let repetition = match self.peek() {
+            Some(b'*') => (0, None),
+            Some(b'+') => (1, None),
+            Some(b'?') => (0, Some(1)),
+            Some(b'{') => match self.interval()? {
+                Some(interval) => return Ok(Some(interval)),
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        self.position += 1;
+        Ok(Some(repetition))
+    }
+
+    // Parses `{m}`, `{m,}` or `{m,n}`. A brace that doesn't start one is taken literally.
+    fn interval(&mut self) -> anyhow::Result<Option<(usize, Option<usize>)>> {
+        let rest = &self.pattern[self.position + 1..];
+        let Some(end) = rest.iter().position(|&b| b == b'}') else {
+            return Ok(None);
+        };
+        let body = &rest[..end];
+        let number = |digits: &[u8]| -> Option<usize> {
+            if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            std::str::from_utf8(digits).ok()?.parse().ok()
+        };
+        let (min, max) = match body.iter().position(|&b| b == b',') {
+            None => match number(body) {
+                Some(n) => (n, Some(n)),
+                None => return Ok(None),
+            },
+            Some(comma) => {
+                let Some(min) = number(&body[..comma]) else {
+                    return Ok(None);
+                };
+                let max = &body[comma + 1..];
+                if max.is_empty() {
+                    (min, None)
+                } else {
+                    match number(max) {
+                        Some(max) => (min, Some(max)),
+                        None => return Ok(None),
+                    }
+                }
+            }
+        };
+        if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            bail!("Invalid content of \\{{\\}}");
+        }
+        self.position += end + 2;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> anyhow::Result<Node> {
+        let b = self.peek().expect("Called with more of the pattern left");
+        self.position += 1;
+        let node = match b {
+            b'(' => {
+                let group = self.groups;
+                self.groups += 1;
+                let node = self.alternation()?;
+                if self.peek() != Some(b')') {
+                    bail!("Unmatched ( or \\(");
+                }
+                self.position += 1;
+                Node::Group(Box::new(node), group)
+            }
+            b'[' => Node::Bytes(self.bracket()?),
+            b'.' => Node::Bytes(ByteSet::empty().negated()),
+            b'^' => Node::Assert(Assertion::LineStart),
+            b'$' => Node::Assert(Assertion::LineEnd),
+            b'\\' => {
+                let Some(escaped) = self.peek() else {
+                    bail!("Trailing backslash");
+                };
+                self.position += 1;
+                let word = ByteSet::from_fn(is_word);
+                let space = class(b"space").expect("space is a class");
+                match escaped {
+                    b'w' => Node::Bytes(word),
+                    b'W' => Node::Bytes(word.negated()),
+                    b's' => Node::Bytes(space),
+                    b'S' => Node::Bytes(space.negated()),
+                    b'b' => Node::Assert(Assertion::WordBoundary),
+                    b'B' => Node::Assert(Assertion::NotWordBoundary),
+                    b'<' => Node::Assert(Assertion::WordStart),
+                    b'>' => Node::Assert(Assertion::WordEnd),
+                    b'`' => Node::Assert(Assertion::TextStart),
+                    b'\'' => Node::Assert(Assertion::TextEnd),
+                    b'1'..=b'9' => bail!("Back references aren't supported"),
+                    _ => self.literal(escaped),
+                }
+            }
+            _ => self.literal(b),
+        };
+        Ok(node)
+    }
+
+    fn literal(&self, b: u8) -> Node {
+        let set = ByteSet::of(b);
+        Node::Bytes(if self.ignore_case {
+            set.ignoring_case()
+        } else {
+            set
+        })
+    }
+
+    // Parses a bracket expression after its `[`. A `]` first in the list is taken literally, and
+    // backslashes are never special inside one.
+    fn bracket(&mut self) -> anyhow::Result<ByteSet> {
+        let negate = self.peek() == Some(b'^');
+        if negate {
+            self.position += 1;
+        }
+        let mut set = ByteSet::empty();
+        let mut first = true;
+        loop {
+            let Some(b) = self.peek() else {
+                bail!("Unmatched [, [^, [:, [., or [=");
+            };
+            if b == b']' && !first {
+                self.position += 1;
+                break;
+            }
+            first = false;
+            let rest = &self.pattern[self.position..];
+            if let Some(inner) = rest.strip_prefix(b"[:") {
+                let Some(end) = inner.windows(2).position(|w| w == b":]") else {
+                    bail!("Unmatched [, [^, [:, [., or [=");
+                };
+                let Some(class) = class(&inner[..end]) else {
+                    bail!("Invalid character class name");
+                };
+                set.union(&class);
+                self.position += end + 4;
+                continue;
+            }
+            let low = self.bracket_byte()?;
+            let rest = &self.pattern[self.position..];
+            if rest.len() >= 2 && rest[0] == b'-' && rest[1] != b']' {
+                self.position += 1;
+                let high = self.bracket_byte()?;
+                if high < low {
+                    bail!("Invalid range end");
+                }
+                (low..=high).for_each(|b| set.insert(b));
+            } else {
+                set.insert(low);
+            }
+        }
+        if self.ignore_case {
+            set = set.ignoring_case();
+        }
+        Ok(if negate { set.negated() } else { set })
+    }
+
+    // Takes one byte of a bracket expression, which may be a collating element like `[.-.]` or an
+    // equivalence class like `[=a=]`
+    fn bracket_byte(&mut self) -> anyhow::Result<u8> {
+        let rest = &self.pattern[self.position..];
+        for (open, close) in [(b"[.", b".]"), (b"[=", b"=]")] {
+            if rest.starts_with(open) {
+                if rest.len() < 5 || &rest[3..5] != close {
+                    bail!("Invalid collation character");
+                }
+                self.position += 5;
+                return Ok(rest[2]);
+            }
+        }
+        self.position += 1;
+        Ok(rest[0])
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Bytes(set) => program.push(Inst::Bytes(set.clone())),
+        Node::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+        Node::Group(node, group) => {
+            program.push(Inst::Save(2 * group));
+            compile(node, program);
+            program.push(Inst::Save(2 * group + 1));
+        }
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alternate(branches) => {
+            // Each branch but the last is tried before those after it, then jumps to the end
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    compile(branch, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                let next = program.len();
+                program[split] = Inst::Split(split + 1, next);
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            (0..*min).for_each(|_| compile(node, program));
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    // Each optional copy is only tried after the one before it matched
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<Range<usize>> {
+        Regex::new(pattern.as_bytes(), false)
+            .unwrap()
+            .find(text.as_bytes())
+    }
+
+    #[test]
+    fn alternation_takes_the_longest() {
+        assert_eq!(find("ab|abcd", "xabcd"), Some(1..5));
+        assert_eq!(find("a|b", "cb"), Some(1..2));
+        assert_eq!(find("x(a|bc)y", "xbcy"), Some(0..4));
+        assert_eq!(find("a|b", "cd"), None);
+    }
+
+    #[test]
+    fn brackets() {
+        assert_eq!(find("[a-c]+", "xxbcaz"), Some(2..5));
+        assert_eq!(find("[^a-c]", "abz"), Some(2..3));
+        assert_eq!(find("[]a]+", "x]a]"), Some(1..4));
+        assert_eq!(find("[[:digit:]]+", "ab123"), Some(2..5));
+        assert_eq!(find("[^x]", "\n"), None);
+        assert_eq!(find("a.b", "a\nb"), None);
+        assert!(Regex::new(b"[[:nope:]]", false).is_err());
+        assert!(Regex::new(b"[a", false).is_err());
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(find("a{2,3}", "aaaa"), Some(0..3));
+        assert_eq!(find("a{2}", "a"), None);
+        assert_eq!(find("a{2,}", "baaaaa"), Some(1..6));
+        assert_eq!(find("(ab){2}", "abab"), Some(0..4));
+        assert!(Regex::new(b"a{256}", false).is_err());
+    }
+
+    #[test]
+    fn anchors_match_at_every_line() {
+        assert_eq!(find("^b", "a\nb"), Some(2..3));
+        assert_eq!(find("a$", "a\nb"), Some(0..1));
+        assert_eq!(find("^$", "a\n\nb"), Some(2..2));
+        assert_eq!(find("\\`b", "a\nb"), None);
+        assert_eq!(find("a\\'", "a\na"), Some(2..3));
+    }
+
+    #[test]
+    fn not_bol() {
+        let regex = Regex::new(b"^a", false).unwrap();
+        assert_eq!(regex.find_not_bol(b"a"), None);
+        assert_eq!(regex.find_not_bol(b"b\na"), Some(2..3));
+        assert_eq!(regex.find(b"a"), Some(0..1));
+    }
+
+    #[test]
+    fn gnu_word_escapes() {
+        assert_eq!(find("\\w+", "  foo_1 "), Some(2..7));
+        assert_eq!(find("\\W", "ab-c"), Some(2..3));
+        assert_eq!(find("\\s+\\S", "a \tb"), Some(1..4));
+        assert_eq!(find("\\bfoo\\b", "afoo foo"), Some(5..8));
+        assert_eq!(find("o\\B", "foo"), Some(1..2));
+        assert_eq!(find("\\<b", "ab b"), Some(3..4));
+        assert_eq!(find("a\\>", "aa a"), Some(1..2));
+    }
+
+    #[test]
+    fn ignore_case() {
+        let regex = Regex::new(b"A[b-c]C", true).unwrap();
+        assert_eq!(regex.find(b"xaBc"), Some(1..4));
+    }
+
+    #[test]
+    fn groups() {
+        let captures = |pattern: &str, text: &str| {
+            Regex::new(pattern.as_bytes(), false)
+                .unwrap()
+                .captures(text.as_bytes())
+        };
+        assert_eq!(
+            captures("(a+)(b*)", "xaab"),
+            Some(vec![Some(1..4), Some(1..3), Some(3..4)])
+        );
+        assert_eq!(
+            captures("(a)|(b)", "b"),
+            Some(vec![Some(0..1), None, Some(0..1)])
+        );
+        assert_eq!(
+            captures("(a|ab)(c|bcd)", "abcd"),
+            Some(vec![Some(0..4), Some(0..1), Some(1..4)])
+        );
+        assert_eq!(captures("(a)", "b"), None);
+    }
+}
@@ -0,0 +1,327 @@
+// Detects which of the files a change deletes were renamed to the files it adds, as git's
+// diffcore-rename does. Files with the same content are paired first, then files with the same
+// name in different directories if they are similar enough, then each added file with the most
+// similar of the deleted files left, if it is at least half the same. Looking for copies too, any
+// file on the old side can be a source, even one already paired, and names aren't matched. How
+// similar two files are is how much of the larger one is made up of chunks the other has too, a
+// chunk being a line, or 64 bytes of a longer one, counted by a hash of its bytes as git counts
+// them.
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::Config;
+use crate::diff::{self, Change, Status};
+use crate::merge_file;
+use crate::repository::Repository;
+
+// Similarity scores are out of this
+const MAX_SCORE: u64 = 60000;
+// How similar files must be to be taken as renamed
+const MIN_SCORE: u64 = MAX_SCORE / 2;
+// How similar files with the same name must be to be paired before the rest are compared
+const MIN_BASENAME_SCORE: u64 = MIN_SCORE + (MAX_SCORE - MIN_SCORE) / 2;
+// How many of the sources most similar to a destination are kept as candidates for it
+const CANDIDATES: usize = 4;
+// How many identical sources are looked at for a destination
+const IDENTICAL: usize = 100;
+// Chunk hashes are taken modulo this prime
+const HASH_BASE: u32 = 107927;
+// The longest chunk
+const CHUNK: usize = 64;
+
+/// How renames are looked for
+#[derive(Default)]
+pub struct RenameOptions<'a> {
+    /// Only look for renames to this path
+    pub destination: Option<&'a str>,
+    /// Also look for copies, of any of these files, which are all those on the old side by path
+    /// with their mode and hash, like git's --find-copies-harder
+    pub copies_from: Option<&'a BTreeMap<String, (String, String)>>,
+}
+
+/// A file added as a rename or copy of another, with the mode and hash the other has
+pub struct Rename {
+    pub source: String,
+    pub source_mode: String,
+    pub source_hash: String,
+    pub destination: String,
+}
+
+// A file taking part in rename detection, whose chunks are counted when first needed
+struct File<'a> {
+    path: &'a str,
+    mode: &'a str,
+    hash: &'a str,
+    chunks: Option<(usize, HashMap<u32, usize>)>,
+}
+
+/// Finds which of the files `changes` add were renamed from those it deletes, or with
+/// `copies_from` copied from any file
+pub fn find<'a>(
+    repository: &Repository,
+    changes: &'a [Change],
+    options: &RenameOptions<'a>,
+) -> anyhow::Result<Vec<Rename>> {
+    let deleted = |change: &&Change| change.status == Status::Deleted;
+    // A source is used once something is renamed from it, and files that stay are in use already
+    let (mut sources, mut used): (Vec<File>, Vec<bool>) = match options.copies_from {
+        Some(files) => files
+            .iter()
+            .map(|(path, (mode, hash))| {
+                let deleted = changes
+                    .iter()
+                    .any(|change| deleted(&change) && change.path == *path);
+                (File::new(path, mode, hash), !deleted)
+            })
+            .unzip(),
+        None => changes
+            .iter()
+            .filter(deleted)
+            .map(|change| {
+                (
+                    File::new(&change.path, &change.old_mode, &change.old_hash),
+                    false,
+                )
+            })
+            .unzip(),
+    };
+    let copies = options.copies_from.is_some();
+    let mut destinations: Vec<File> = changes
+        .iter()
+        .filter(|change| {
+            change.status == Status::Added
+                && options
+                    .destination
+                    .is_none_or(|destination| change.path == destination)
+        })
+        .map(|change| File::new(&change.path, &change.new_mode, &change.new_hash))
+        .collect();
+    let mut renamed = vec![false; destinations.len()];
+    let mut renames = Vec::new();
+    let mut pair = |j: usize, i: usize, sources: &[File<'a>], destinations: &[File<'a>]| {
+        let (source, destination) = (&sources[j], &destinations[i]);
+        renames.push(Rename {
+            source: source.path.to_string(),
+            source_mode: source.mode.to_string(),
+            source_hash: source.hash.to_string(),
+            destination: destination.path.to_string(),
+        });
+    };
+
+    // Identical files, preferring those not used yet and those with the same name, one point for
+    // each, then the first. Like git, only the first hundred are looked at.
+    for i in 0..destinations.len() {
+        let identical = (0..sources.len())
+            .filter(|&j| (copies || !used[j]) && sources[j].identical(&destinations[i]))
+            .take(IDENTICAL)
+            .max_by_key(|&j| {
+                let same_name = same_basename(sources[j].path, destinations[i].path);
+                (!used[j] as u8 + same_name as u8, Reverse(j))
+            });
+        if let Some(j) = identical {
+            used[j] = true;
+            renamed[i] = true;
+            pair(j, i, &sources, &destinations);
+        }
+    }
+
+    // Without copies, files whose name no other file left on either side has, if they are similar
+    // enough
+    let unique = |files: &[File], taken: &[bool], name: &str| {
+        let mut named = (0..files.len()).filter(|&k| !taken[k] && basename(files[k].path) == name);
+        match (named.next(), named.next()) {
+            (Some(k), None) => Some(k),
+            _ => None,
+        }
+    };
+    for i in (0..destinations.len()).filter(|_| !copies) {
+        if renamed[i] {
+            continue;
+        }
+        let name = basename(destinations[i].path);
+        let (Some(j), Some(_)) = (
+            unique(&sources, &used, name),
+            unique(&destinations, &renamed, name),
+        ) else {
+            continue;
+        };
+        let score = similarity(
+            repository,
+            &mut sources[j],
+            &mut destinations[i],
+            MIN_BASENAME_SCORE,
+        )?;
+        if score >= MIN_BASENAME_SCORE {
+            used[j] = true;
+            renamed[i] = true;
+            pair(j, i, &sources, &destinations);
+        }
+    }
+
+    // The rest are paired the most similar first, each destination with one of the sources most
+    // similar to it, preferring those with the same name. Sources not used yet go first, and with
+    // copies, any source can be paired after that.
+    let mut candidates = Vec::new();
+    for i in (0..destinations.len()).filter(|&i| !renamed[i]) {
+        // Like git, a source only takes the place of the first of the worst kept so far, and only
+        // if it is better
+        let mut best = [None; CANDIDATES];
+        for j in (0..sources.len()).filter(|&j| copies || !used[j]) {
+            let score = similarity(repository, &mut sources[j], &mut destinations[i], MIN_SCORE)?;
+            let rank = (score, same_basename(sources[j].path, destinations[i].path));
+            let kept = |k: usize| best[k].map(|(rank, _)| rank);
+            let worst = (1..CANDIDATES).fold(0, |worst, k| match kept(k) < kept(worst) {
+                true => k,
+                false => worst,
+            });
+            if kept(worst) < Some(rank) {
+                best[worst] = Some((rank, j));
+            }
+        }
+        candidates.extend(best.into_iter().flatten().map(|(rank, j)| (rank, j, i)));
+    }
+    candidates.sort_by_key(|&(rank, _, _)| Reverse(rank));
+    for reuse in [false, copies] {
+        for &((score, _), j, i) in &candidates {
+            if score < MIN_SCORE {
+                break;
+            }
+            if renamed[i] || (used[j] && !reuse) {
+                continue;
+            }
+            used[j] = true;
+            renamed[i] = true;
+            pair(j, i, &sources, &destinations);
+        }
+    }
+    Ok(renames)
+}
+
+/// Whether diff.renames has the diffs of log and its kin detect renames, as they do by default,
+/// and copies too if true
+pub fn configured(config: &Config) -> anyhow::Result<Option<bool>> {
+    Ok(match config.get("diff.renames") {
+        Some("copy" | "copies") => Some(true),
+        _ => config
+            .get_bool("diff.renames")?
+            .unwrap_or(true)
+            .then_some(false),
+    })
+}
+
+/// Finds the renames among `changes`, and with `copies` the copies of the files they change
+pub fn detect(
+    repository: &Repository,
+    changes: &[Change],
+    copies: bool,
+) -> anyhow::Result<Vec<Rename>> {
+    let files: BTreeMap<_, _> = changes
+        .iter()
+        .filter(|change| change.status != Status::Added)
+        .map(|change| {
+            let side = (change.old_mode.clone(), change.old_hash.clone());
+            (change.path.clone(), side)
+        })
+        .collect();
+    let options = RenameOptions {
+        destination: None,
+        copies_from: copies.then_some(&files),
+    };
+    find(repository, changes, &options)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+fn same_basename(a: &str, b: &str) -> bool {
+    basename(a) == basename(b)
+}
+
+fn is_regular(mode: &str) -> bool {
+    mode.starts_with("100")
+}
+
+// Scores how similar `source` is to `destination`, or gives 0 if their sizes differ too much for
+// the score to reach `minimum`. Only regular files are compared.
+fn similarity(
+    repository: &Repository,
+    source: &mut File,
+    destination: &mut File,
+    minimum: u64,
+) -> anyhow::Result<u64> {
+    if !is_regular(source.mode) || !is_regular(destination.mode) {
+        return Ok(0);
+    }
+    let (source_size, source) = source.chunks(repository)?;
+    let (destination_size, destination) = destination.chunks(repository)?;
+    let size = *source_size.max(destination_size) as u64;
+    let difference = size - *source_size.min(destination_size) as u64;
+    if size * (MAX_SCORE - minimum) < difference * MAX_SCORE || *destination_size == 0 {
+        return Ok(0);
+    }
+    let copied: usize = source
+        .iter()
+        .map(|(hash, &count)| count.min(destination.get(hash).copied().unwrap_or(0)))
+        .sum();
+    Ok(copied as u64 * MAX_SCORE / size)
+}
+
+impl<'a> File<'a> {
+    fn new(path: &'a str, mode: &'a str, hash: &'a str) -> Self {
+        Self {
+            path,
+            mode,
+            hash,
+            chunks: None,
+        }
+    }
+
+    // Files other than regular ones must also have the same mode
+    fn identical(&self, other: &File) -> bool {
+        self.hash == other.hash
+            && ((is_regular(self.mode) && is_regular(other.mode)) || self.mode == other.mode)
+    }
+
+    // The file's size, and how many of its bytes are in chunks with each hash
+    fn chunks(&mut self, repository: &Repository) -> anyhow::Result<&(usize, HashMap<u32, usize>)> {
+        if self.chunks.is_none() {
+            let content =
+                diff::content(repository, self.path, self.mode, self.hash)?.unwrap_or_default();
+            self.chunks = Some((content.len(), count_chunks(&content)));
+        }
+        Ok(self.chunks.as_ref().expect("The chunks were just counted"))
+    }
+}
+
+// Splits `data` into chunks ending at newlines or after 64 bytes, the CR of a CRLF in text left out,
+// counting the bytes of the chunks with each hash
+fn count_chunks(data: &[u8]) -> HashMap<u32, usize> {
+    let text = !merge_file::is_binary(data);
+    let mut counts = HashMap::new();
+    let (mut accumulator, mut carry) = (0u32, 0u32);
+    let mut length = 0;
+    let mut add = |accumulator: u32, carry: u32, length| {
+        let hash = accumulator.wrapping_add(carry.wrapping_mul(0x61)) % HASH_BASE;
+        *counts.entry(hash).or_insert(0) += length;
+    };
+    for (position, &byte) in data.iter().enumerate() {
+        if text && byte == b'\r' && data.get(position + 1) == Some(&b'\n') {
+            continue;
+        }
+        let previous = accumulator;
+        accumulator = (accumulator << 7) ^ (carry >> 25);
+        carry = (carry << 7) ^ (previous >> 25);
+        accumulator = accumulator.wrapping_add(byte as u32);
+        length += 1;
+        if length < CHUNK && byte != b'\n' {
+            continue;
+        }
+        add(accumulator, carry, length);
+        (accumulator, carry, length) = (0, 0, 0);
+    }
+    if length > 0 {
+        add(accumulator, carry, length);
+    }
+    counts
+}
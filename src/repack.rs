@@ -0,0 +1,224 @@
+// Packs objects, like git repack. By default, the objects reachable from refs, worktree HEADs and
+// reflogs that aren't packed yet go into a new pack, so that each repack only costs as much as
+// what was added since the last; with -a, everything reachable goes into one pack instead. A
+// geometric repack rolls packs together so that their sizes, counted in objects, form a
+// progression where each pack has at least `factor` times as many objects as the one before: the
+// smallest packs that break it are packed together with the loose objects, and so are any of the
+// larger ones the new pack would catch up with, which leaves the largest packs alone. Deleting
+// removes the packs that were rolled into the new one, or with -a every other pack, and then the
+// loose objects that are packed.
+//
+// Packs with a `.keep` file, or named with --keep-pack, are never deleted, and their objects are
+// left out of the new pack unless kept objects are packed too. gitrs has no partial clones to
+// fetch missing objects for, so packs git fetched from a promisor remote are always left as they
+// are, rather than repacked into a new promisor pack.
+//
+// Packs are written and deleted through a plan, so a dry run lists the pack it would write and the
+// packs and loose objects it would delete, leaving them all as they are.
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use crate::loose;
+use crate::object::{GitrsObject, ObjectType};
+use crate::pack::{PackIndex, PackObject};
+use crate::plan::Plan;
+use crate::prune;
+use crate::repository::Repository;
+
+// What else git may have written alongside a pack, which goes with it
+const PACK_EXTENSIONS: [&str; 5] = ["pack", "idx", "rev", "bitmap", "mtimes"];
+
+pub struct RepackOptions {
+    /// Pack everything reachable into one pack
+    pub all: bool,
+    /// Delete the packs and loose objects that were repacked
+    pub delete: bool,
+    /// Roll packs together so each has at least this many times the objects of the one before
+    pub geometric: Option<u64>,
+    /// Leave these packs alone as though they had a `.keep` file, by name with or without `.pack`
+    pub keep_packs: Vec<String>,
+    /// Pack the objects of kept packs too, though the packs themselves stay
+    pub pack_kept_objects: bool,
+}
+
+pub struct Repacked {
+    /// The new pack and how many objects it has, if anything was packed
+    pub pack: Option<(PathBuf, usize)>,
+    /// The packs that were deleted
+    pub removed: Vec<PathBuf>,
+}
+
+/// Repacks the repository's objects as `options` says
+pub fn repack(
+    repository: &Repository,
+    options: &RepackOptions,
+    plan: &mut Plan,
+) -> anyhow::Result<Repacked> {
+    let kept = |index: &PackIndex| {
+        let name = index.name();
+        index.is_kept()
+            || options
+                .keep_packs
+                .iter()
+                .any(|keep| keep.strip_suffix(".pack").unwrap_or(keep) == name)
+    };
+    // The packs whose objects stay where they are
+    let (held, packs): (Vec<_>, Vec<_>) = PackIndex::load_all(repository)?
+        .into_iter()
+        .partition(|index| index.is_promisor() || (kept(index) && !options.pack_kept_objects));
+
+    let (hashes, replaced) = match options.geometric {
+        Some(factor) => {
+            let (rolled, mut kept) = split(packs, factor);
+            kept.extend(held);
+            let mut hashes: Vec<String> = loose::scan(repository)?
+                .objects
+                .into_iter()
+                .map(|object| object.hash)
+                .chain(
+                    rolled
+                        .iter()
+                        .flat_map(|index| index.objects().iter().cloned()),
+                )
+                .filter(|hash| !kept.iter().any(|index| index.contains(hash)))
+                .collect();
+            hashes.sort();
+            hashes.dedup();
+            (hashes, rolled)
+        }
+        None => {
+            let mut hashes: Vec<String> = prune::reachable(repository)?
+                .into_iter()
+                .filter(|hash| !held.iter().any(|index| index.contains(hash)))
+                .filter(|hash| options.all || !packs.iter().any(|index| index.contains(hash)))
+                .collect();
+            hashes.sort();
+            let replaced = match options.all {
+                true => packs,
+                false => Vec::new(),
+            };
+            (hashes, replaced)
+        }
+    };
+
+    let written = write_pack(repository, plan, "pack", hashes)?;
+
+    let mut removed = Vec::new();
+    if options.delete {
+        for index in replaced {
+            if Some(&index.pack) == written.as_ref().map(|(path, _)| path) || kept(&index) {
+                continue;
+            }
+            remove_pack(plan, &index)?;
+            removed.push(index.pack.clone());
+        }
+        prune::prune_packed(repository, plan)?;
+    }
+    Ok(Repacked {
+        pack: written,
+        removed,
+    })
+}
+
+/// Packs the objects of `packs` together into one new pack, then deletes them
+pub fn roll(
+    repository: &Repository,
+    plan: &mut Plan,
+    packs: &[Arc<PackIndex>],
+) -> anyhow::Result<Repacked> {
+    let mut hashes: Vec<String> = packs
+        .iter()
+        .flat_map(|index| index.objects().iter().cloned())
+        .collect();
+    hashes.sort();
+    hashes.dedup();
+    let written = write_pack(repository, plan, "pack", hashes)?;
+
+    let mut removed = Vec::new();
+    for index in packs {
+        if Some(&index.pack) != written.as_ref().map(|(path, _)| path) {
+            remove_pack(plan, index)?;
+            removed.push(index.pack.clone());
+        }
+    }
+    Ok(Repacked {
+        pack: written,
+        removed,
+    })
+}
+
+/// Writes the objects `hashes` to a new pack named `<prefix>-<hash>.pack`, returning its path and
+/// how many objects it has, or None if there are none
+pub fn write_pack(
+    repository: &Repository,
+    plan: &mut Plan,
+    prefix: &str,
+    hashes: Vec<String>,
+) -> anyhow::Result<Option<(PathBuf, usize)>> {
+    if hashes.is_empty() {
+        return Ok(None);
+    }
+    let mut objects = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        let (object_type, data) = GitrsObject::read_data(repository, &hash)
+            .with_context(|| format!("Could not read object {}", hash))?;
+        objects.push(PackObject {
+            hash,
+            object_type,
+            data,
+            path: None,
+        });
+    }
+    // Like git, commits go first, as history is mostly read from the top
+    objects.sort_by_key(|object| match object.object_type {
+        ObjectType::Commit => 0,
+        ObjectType::Tag => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+    });
+    let path = plan.write_pack(repository, prefix, &objects)?;
+    Ok(Some((path, objects.len())))
+}
+
+// Deletes a pack along with its index and whatever else was written alongside it
+fn remove_pack(plan: &mut Plan, index: &PackIndex) -> anyhow::Result<()> {
+    for extension in PACK_EXTENSIONS {
+        let path = index.pack.with_extension(extension);
+        if path.exists() {
+            plan.remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+// Splits the packs into those to roll together, the smallest, and those to keep, as git does:
+// going down from the largest, the packs are kept while each has at least `factor` times the
+// objects of the next smaller one. The new pack would then have the objects of all those smaller,
+// so the next kept packs are rolled in as well, as long as it would have more than a `factor`th
+// of their objects.
+fn split(
+    mut packs: Vec<Arc<PackIndex>>,
+    factor: u64,
+) -> (Vec<Arc<PackIndex>>, Vec<Arc<PackIndex>>) {
+    let weight = |index: &PackIndex| index.objects().len() as u64;
+    packs.sort_by_key(|index| weight(index));
+
+    let mut split = (1..packs.len())
+        .rev()
+        .find(|&i| weight(&packs[i]) < factor.saturating_mul(weight(&packs[i - 1])))
+        .unwrap_or(0);
+    // The larger pack of the pair that broke the progression can't be in it
+    if split > 0 {
+        split += 1;
+    }
+    let mut total: u64 = packs[..split].iter().map(|index| weight(index)).sum();
+    while split < packs.len() && weight(&packs[split]) < factor.saturating_mul(total) {
+        total += weight(&packs[split]);
+        split += 1;
+    }
+
+    let kept = packs.split_off(split);
+    (packs, kept)
+}
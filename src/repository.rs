@@ -3,15 +3,25 @@ use core::panic;
 use std::env;
 use std::fs::{self, File, canonicalize};
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{Context, Ok, Result, anyhow, bail, ensure};
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
 
+use crate::refs;
+
 pub struct Repository {
     pub worktree: PathBuf,
     pub gitdir: PathBuf,
+    // Directory holding the data shared between worktrees (objects, refs, etc.). This is the same
+    // as `gitdir`, except in linked worktrees, where `gitdir` only holds the per-worktree files
+    pub commondir: PathBuf,
+    // Whether object lookups honor refs/replace, which is turned off by --no-replace-objects
+    // through the GITRS_NO_REPLACE_OBJECTS environment variable
+    pub replace_objects: bool,
+    // The ref namespace set with --namespace or GITRS_NAMESPACE, eg. `a/b`, if any
+    pub namespace: Option<String>,
 }
 
 impl Repository {
@@ -31,17 +41,33 @@ impl Repository {
         &["config", ""],
     ];
 
+    // Files that are private to each worktree, and so are resolved against `gitdir` rather than
+    // `commondir`
+    const PER_WORKTREE_FILES: [&'static str; 2] = ["HEAD", "index"];
+
     /////////////////////////////////////
-    /// Repository Initialization
+    // Repository Initialization
     /////////////////////////////////////
 
     // Construct a Repository object
     // WARN: Use this to create an in-memory representation of an existing repository, not to
     // initialize a new repository
     pub fn new(worktree: &Path) -> Self {
+        let dotgit = worktree.join(".gitrs");
+        // Linked worktrees contain a `.gitrs` file pointing to their gitdir instead of a directory
+        let gitdir = read_gitdir_pointer(&dotgit).unwrap_or(dotgit);
+        let commondir = fs::read_to_string(gitdir.join("commondir"))
+            .map(|relative| gitdir.join(relative.trim()))
+            .unwrap_or_else(|_| gitdir.clone());
+
         Self {
             worktree: worktree.to_path_buf(),
-            gitdir: worktree.join(".gitrs"),
+            gitdir,
+            commondir,
+            replace_objects: env::var_os("GITRS_NO_REPLACE_OBJECTS").is_none(),
+            namespace: env::var("GITRS_NAMESPACE")
+                .ok()
+                .filter(|namespace| !namespace.is_empty()),
         }
     }
 
@@ -87,37 +113,120 @@ impl Repository {
                 Ok(())
             })?;
 
+        // Record what the filesystem is capable of, so later commands don't have to probe for it
+        let config_path = repository
+            .get_path_to_file(&["config"])
+            .ok_or(anyhow!("Could not make file: config"))?;
+        let mut config = format!(
+            "[core]\n\trepositoryformatversion = 0\n\tfilemode = {}\n\tbare = false\n",
+            supports_executable_bit(&config_path)
+        );
+        // Like git, only record ignorecase when it is needed
+        if config_path.with_file_name("CoNfIg").exists() {
+            config.push_str("\tignorecase = true\n");
+        }
+        repository.write_to_repo_file(&config_path, config.as_bytes())?;
+
         Ok(repository)
     }
 
-    /// Finds the root directory of the nearest gitrs repository by traversing parents of the
-    /// `current_path`
-    // TODO: maybe make the behaviour of finding the repository "closest" to the given one optional
+    /// Finds the repository `path` is in, looking for a `.gitrs` in it and then in each of its
+    /// parents. The `.gitrs` is either the gitdir itself, or a file pointing to it with
+    /// `gitdir: <path>` as in linked worktrees and submodules, in which case the worktree is still
+    /// the directory holding the file. Returns None if there is no repository, and fails if a
+    /// `.gitrs` file doesn't lead to a valid gitdir.
+    pub fn discover(path: &Path) -> Result<Option<Repository>> {
+        let mut dir =
+            canonicalize(path).with_context(|| format!("Cannot change to '{}'", path.display()))?;
+        let mut worktree = path.to_path_buf();
+        loop {
+            let dotgit = dir.join(".gitrs");
+            if dotgit.is_file() {
+                let gitdir = read_gitdir_pointer(&dotgit)
+                    .with_context(|| format!("invalid gitfile format: {}", dotgit.display()))?;
+                let repository = Repository::new(&worktree);
+                ensure!(
+                    repository.is_valid(),
+                    "not a gitrs repository: {}",
+                    gitdir.display()
+                );
+                return Ok(Some(repository));
+            }
+            // Like git, a directory that isn't a gitdir is skipped
+            if dotgit.is_dir() {
+                let repository = Repository::new(&worktree);
+                if repository.is_valid() {
+                    return Ok(Some(repository));
+                }
+            }
+
+            match dir.parent() {
+                Some(parent) => {
+                    dir = parent.to_path_buf();
+                    worktree = dir.clone();
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Finds the nearest gitrs repository like `discover`, treating errors as no repository
     pub fn find_repository_at(current_path: &Path) -> Option<Repository> {
-        let canonical_current_path = canonicalize(current_path).ok()?;
-        canonical_current_path
-            .join(".gitrs")
-            .exists()
-            .then(|| Repository::new(current_path))
-            .or_else(|| match canonical_current_path.parent() {
-                None => None,
-                Some(parent_dir) => Repository::find_repository_at(parent_dir),
-            })
+        Self::discover(current_path).ok().flatten()
     }
 
     /// Find the repository closest to the current active directory
-    pub fn find_repository() -> Repository {
-        Self::find_repository_at(&env::current_dir().unwrap())
-            .expect("Expected a repository at current dir")
+    pub fn find_repository() -> Result<Repository> {
+        Self::discover(&env::current_dir()?)?.context("Expected a repository at current dir")
+    }
+
+    // Checks that the gitdir has the files every repository has
+    fn is_valid(&self) -> bool {
+        self.gitdir.join("HEAD").is_file()
+            && self.commondir.join("objects").is_dir()
+            && self.commondir.join("refs").is_dir()
+    }
+
+    /// Converts `path` (relative to the current directory) into a `/` separated path relative to
+    /// the worktree. The path does not need to exist.
+    pub fn relative_to_worktree(&self, path: &Path) -> Result<String> {
+        let worktree = canonicalize(&self.worktree)?;
+
+        // Normalize lexically, since the path may not exist
+        let mut absolute = PathBuf::new();
+        for component in canonicalize(env::current_dir()?)?.join(path).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                other => absolute.push(other),
+            }
+        }
+
+        let relative = absolute
+            .strip_prefix(&worktree)
+            .map_err(|_| anyhow!("'{}' is outside repository", path.display()))?;
+
+        Ok(relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"))
     }
 
     /////////////////////////////////////
-    /// Repository File Management
+    // Repository File Management
     /////////////////////////////////////
 
     pub fn get_path_to_file(&self, paths: &[&str]) -> Option<PathBuf> {
         self.compute_or_create_repo_file(paths, false)
-            .and_then(|(_, path)| path.exists().then(|| path))
+            .and_then(|(_, path)| path.exists().then_some(path))
+    }
+
+    /// Computes the path of a file or directory in the repository, whether or not it exists
+    pub fn get_path(&self, paths: &[&str]) -> PathBuf {
+        self.compute_repo_path(paths)
     }
 
     pub fn get_path_to_dir(&self, paths: &[&str]) -> Option<PathBuf> {
@@ -126,16 +235,22 @@ impl Repository {
 
     pub fn create_file(&self, paths: &[&str]) -> Option<PathBuf> {
         self.compute_or_create_repo_file(paths, true)
-            .and_then(|(_, path)| path.exists().then(|| path))
+            .and_then(|(_, path)| path.exists().then_some(path))
     }
 
-    // Creates the file if it does not exists or truncates it if it does and appends compressed
-    // data
-    pub fn upsert_file(&self, paths: &[&str], data: &Vec<u8>) -> Option<PathBuf> {
+    // Creates the file if it does not exists or truncates it if it does and appends the data,
+    // compressed at the given level
+    pub fn upsert_file(&self, paths: &[&str], data: &[u8], level: Compression) -> Option<PathBuf> {
         let (file, path) = self.compute_or_create_repo_file(paths, true)?;
-        ZlibEncoder::new(file, Compression::default())
-            .write_all(&data)
-            .map_err(|e| eprintln!("Could not compress file at: {} {}", path.display(), e))
+        ZlibEncoder::new(file, level)
+            .write_all(data)
+            .map_err(|e| {
+                eprintln!(
+                    "error: Could not compress file at: {} {}",
+                    path.display(),
+                    e
+                )
+            })
             .ok()?;
 
         Some(path)
@@ -143,7 +258,16 @@ impl Repository {
 
     // Computes the path under a repository's gitrs directory
     fn compute_repo_path(&self, paths: &[&str]) -> PathBuf {
-        paths.iter().fold(self.gitdir.clone(), |mut acc, path| {
+        let base = match paths.first() {
+            Some(first)
+                if Self::PER_WORKTREE_FILES.contains(first)
+                    || refs::PSEUDO_REFS.contains(first) =>
+            {
+                &self.gitdir
+            }
+            _ => &self.commondir,
+        };
+        paths.iter().fold(base.clone(), |mut acc, path| {
             acc.push(path);
             acc
         })
@@ -158,19 +282,19 @@ impl Repository {
         match self.compute_or_create_repo_dir(&paths[..paths.len() - 1], should_create) {
             Some(_) => {
                 let file_path = self.compute_repo_path(paths);
-                let file = should_create
-                    .then(|| {
-                        File::create(&file_path)
-                            .map_err(|e| {
-                                eprintln!(
-                                    "An error occurred creating file at: {} {}",
-                                    file_path.display(),
-                                    e
-                                )
-                            })
-                            .ok()
-                    })
-                    .unwrap_or_else(|| File::open(&file_path).ok());
+                let file = if should_create {
+                    File::create(&file_path)
+                        .map_err(|e| {
+                            eprintln!(
+                                "error: An error occurred creating file at: {} {}",
+                                file_path.display(),
+                                e
+                            )
+                        })
+                        .ok()
+                } else {
+                    File::open(&file_path).ok()
+                };
                 Some((file?, file_path))
             }
             None => None,
@@ -180,6 +304,10 @@ impl Repository {
     // Same as compute_repo_path, but creates the path if the should_create flag is true
     fn compute_or_create_repo_dir(&self, paths: &[&str], should_create: bool) -> Option<PathBuf> {
         let path = self.compute_repo_path(paths);
+        // Looking up `refs/remotes/origin/main/HEAD` where `origin/main` is a ref finds nothing
+        if path.exists() && !should_create && !path.is_dir() {
+            return None;
+        }
         if path.exists() {
             assert!(path.is_dir(), "Expected a directory at {}", path.display());
             return Some(path);
@@ -201,7 +329,46 @@ impl Repository {
     }
 }
 
+// Reads a `gitdir: <path>` pointer file, returning the path it points to, relative to the
+// directory holding the file unless it is absolute
+fn read_gitdir_pointer(path: &Path) -> Option<PathBuf> {
+    if !path.is_file() {
+        return None;
+    }
+    let content = fs::read_to_string(path).ok()?;
+    let target = content.strip_prefix("gitdir:")?.trim();
+    if target.is_empty() {
+        return None;
+    }
+    Some(path.parent()?.join(target))
+}
+
+// Returns true if the filesystem holding `path` keeps track of the executable bit
+fn supports_executable_bit(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let Some(mode) = fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions().mode())
+        else {
+            return false;
+        };
+        let flipped = fs::set_permissions(path, fs::Permissions::from_mode(mode ^ 0o100)).is_ok()
+            && fs::metadata(path)
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o100 != mode & 0o100);
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+        flipped
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
 // Returns true if the an empty directory exists at the given path
 pub fn is_empty_dir(path: &Path) -> bool {
-    path.is_dir() && fs::read_dir(path).map_or(false, |mut entries| entries.next().is_none())
+    path.is_dir() && fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_none())
 }
@@ -0,0 +1,298 @@
+// Walks commit history. Commits are visited newest first by committer date, the same order
+// `git log` uses by default, and each commit is visited once even if it is reachable along
+// several paths.
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use anyhow::{anyhow, bail};
+
+use crate::object::GitrsObject;
+use crate::object::commit::Commit;
+use crate::repository::Repository;
+
+// State of a `difference` walk: the flags each commit has been marked with so far
+struct Marks<'a> {
+    repository: &'a Repository,
+    flags: HashMap<String, u8>,
+    commits: HashMap<String, Commit>,
+    // Ordered like a Walk's queue
+    queue: BinaryHeap<(i64, usize, String)>,
+    pushed: usize,
+}
+
+struct Walk<'a> {
+    repository: &'a Repository,
+    seen: HashSet<String>,
+    // Ordered by committer timestamp, then by how recently the commit was queued
+    queue: BinaryHeap<(i64, usize, String)>,
+    queued: HashMap<String, Commit>,
+}
+
+/// Returns every commit reachable from `tips` (which must be commit hashes), including the tips
+pub fn walk(repository: &Repository, tips: &[String]) -> anyhow::Result<Vec<(String, Commit)>> {
+    walk_while(repository, tips, |_| Ok(true))
+}
+
+/// Like `walk`, but stops at commits committed before `since`, leaving them out along with the
+/// history only they lead to, as git's --since does
+pub fn walk_since(
+    repository: &Repository,
+    tips: &[String],
+    since: i64,
+) -> anyhow::Result<Vec<(String, Commit)>> {
+    walk_while(repository, tips, |commit| {
+        Ok(commit.committer()?.timestamp >= since)
+    })
+}
+
+// Walks the commits reachable from `tips` through the ones `keep` accepts
+fn walk_while(
+    repository: &Repository,
+    tips: &[String],
+    keep: impl Fn(&Commit) -> anyhow::Result<bool>,
+) -> anyhow::Result<Vec<(String, Commit)>> {
+    let mut walk = Walk {
+        repository,
+        seen: HashSet::new(),
+        queue: BinaryHeap::new(),
+        queued: HashMap::new(),
+    };
+    for tip in tips {
+        walk.push(tip)?;
+    }
+
+    let mut commits = Vec::new();
+    while let Some((_, _, hash)) = walk.queue.pop() {
+        let commit = walk
+            .queued
+            .remove(&hash)
+            .expect("Queued commits must have been read");
+        if !keep(&commit)? {
+            continue;
+        }
+        for parent in commit.parents() {
+            walk.push(parent)?;
+        }
+        commits.push((hash, commit));
+    }
+
+    Ok(commits)
+}
+
+/// Which side of a symmetric difference a commit is on
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+const LEFT: u8 = 1;
+const RIGHT: u8 = 2;
+const HIDDEN: u8 = 4;
+// Marks commits reachable from a merge base, which can't be better merge bases themselves
+const STALE: u8 = 8;
+
+/// Counts the commits reachable from `a` but not `b` and vice versa
+pub fn ahead_behind(repository: &Repository, a: &str, b: &str) -> anyhow::Result<(usize, usize)> {
+    let commits = difference(repository, &[a.to_string()], &[b.to_string()], &[])?;
+    let ahead = commits
+        .iter()
+        .filter(|(_, side)| *side == Side::Left)
+        .count();
+    Ok((ahead, commits.len() - ahead))
+}
+
+/// Returns the commits reachable from `left` or from `right` but not from both, and not from
+/// `hidden`, newest first. Rather than walking each history in full, the tips are walked together
+/// marking every commit with the sides that reach it, stopping once everything left to visit is
+/// reachable from both sides or hidden.
+pub fn difference(
+    repository: &Repository,
+    left: &[String],
+    right: &[String],
+    hidden: &[String],
+) -> anyhow::Result<Vec<(String, Side)>> {
+    let mut marks = Marks {
+        repository,
+        flags: HashMap::new(),
+        commits: HashMap::new(),
+        queue: BinaryHeap::new(),
+        pushed: 0,
+    };
+    for (tips, flag) in [(left, LEFT), (right, RIGHT), (hidden, HIDDEN)] {
+        for tip in tips {
+            marks.mark(tip, flag)?;
+        }
+    }
+
+    let stale = |flags: u8| flags & HIDDEN != 0 || flags & (LEFT | RIGHT) == LEFT | RIGHT;
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    // The commit date of the last commit visited that wasn't stale
+    let mut interesting = i64::MAX;
+    while let Some((timestamp, _, hash)) = marks.queue.pop() {
+        let flag = marks.flags[&hash];
+        if !stale(flag) {
+            interesting = timestamp;
+        }
+        // Once only stale commits older than the last one that wasn't are left, they still pass
+        // their flags on to the commits already seen, which with equal commit dates can have been
+        // visited from one side before the other side reached them, but the walk goes no further.
+        // Like git, stale commits as new as it are walked on, in case they lead to one of its
+        // ancestors with the same date.
+        let finishing = stale(flag)
+            && timestamp < interesting
+            && marks
+                .queue
+                .iter()
+                .all(|(_, _, hash)| stale(marks.flags[hash]));
+        if !finishing && visited.insert(hash.clone()) {
+            order.push(hash.clone());
+        }
+        for parent in marks.commits[&hash].parents().to_vec() {
+            if !finishing || marks.flags.contains_key(&parent) {
+                marks.mark(&parent, flag)?;
+            }
+        }
+    }
+
+    let flags = marks.flags;
+    Ok(order
+        .into_iter()
+        .filter_map(|hash| match flags[&hash] {
+            LEFT => Some((hash, Side::Left)),
+            RIGHT => Some((hash, Side::Right)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Returns the best common ancestors of the commit `a` and any of `others`: those reachable from
+/// both that aren't ancestors of another such commit. There is usually one, or none for unrelated
+/// histories.
+pub fn merge_bases(
+    repository: &Repository,
+    a: &str,
+    others: &[String],
+) -> anyhow::Result<Vec<String>> {
+    if others.iter().any(|other| other == a) {
+        return Ok(vec![a.to_string()]);
+    }
+    let mut marks = Marks {
+        repository,
+        flags: HashMap::new(),
+        commits: HashMap::new(),
+        queue: BinaryHeap::new(),
+        pushed: 0,
+    };
+    marks.mark(a, LEFT)?;
+    for other in others {
+        marks.mark(other, RIGHT)?;
+    }
+
+    let mut bases: Vec<String> = Vec::new();
+    while marks
+        .queue
+        .iter()
+        .any(|(_, _, hash)| marks.flags[hash] & STALE == 0)
+    {
+        let (_, _, hash) = marks.queue.pop().expect("Queue is not empty");
+        let mut flags = marks.flags[&hash];
+        if flags & (LEFT | RIGHT) == LEFT | RIGHT {
+            if !bases.contains(&hash) {
+                bases.push(hash.clone());
+            }
+            flags |= STALE;
+        }
+        for parent in marks.commits[&hash].parents().to_vec() {
+            marks.mark(&parent, flags)?;
+        }
+    }
+
+    // A base found early can turn out to be reachable from one found later, and with several
+    // left, one may still be an ancestor of another through a longer path
+    bases.retain(|hash| marks.flags[hash] & STALE == 0);
+    let mut best = Vec::new();
+    for base in &bases {
+        let mut redundant = false;
+        for other in bases.iter().filter(|other| *other != base) {
+            redundant |= is_ancestor(repository, base, other)?;
+        }
+        if !redundant {
+            best.push(base.clone());
+        }
+    }
+    Ok(best)
+}
+
+/// Returns true if `ancestor` is reachable from `descendant`, which includes them being the same
+pub fn is_ancestor(
+    repository: &Repository,
+    ancestor: &str,
+    descendant: &str,
+) -> anyhow::Result<bool> {
+    Ok(ahead_behind(repository, ancestor, descendant)?.0 == 0)
+}
+
+/// Resolves a name to the hash of a commit, peeling any tags along the way
+pub fn find_commit(repository: &Repository, name: &str) -> anyhow::Result<String> {
+    let hash = GitrsObject::find(repository, name)?;
+    peel_to_commit(repository, &hash)?.ok_or_else(|| anyhow!("Not a commit: {}", name))
+}
+
+/// Follows tags from the object `hash` to the commit they point to, returning None if they point
+/// to some other kind of object
+pub fn peel_to_commit(repository: &Repository, hash: &str) -> anyhow::Result<Option<String>> {
+    let mut hash = hash.to_string();
+    loop {
+        match GitrsObject::read(repository, &hash)? {
+            GitrsObject::CommitObject(_) => return Ok(Some(hash)),
+            GitrsObject::TagObject(tag) => match tag.object() {
+                Some(object) => hash = object.to_string(),
+                None => bail!("Malformed tag: {}", hash),
+            },
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Reads the commit with the given hash, failing if it is some other kind of object
+pub fn read_commit(repository: &Repository, hash: &str) -> anyhow::Result<Commit> {
+    match GitrsObject::read(repository, hash)? {
+        GitrsObject::CommitObject(commit) => Ok(commit),
+        _ => bail!("Expected a commit object: {}", hash),
+    }
+}
+
+impl Walk<'_> {
+    fn push(&mut self, hash: &str) -> anyhow::Result<()> {
+        if self.seen.insert(hash.to_string()) {
+            let commit = read_commit(self.repository, hash)?;
+            let timestamp = commit.committer()?.timestamp;
+            self.queue
+                .push((timestamp, usize::MAX - self.seen.len(), hash.to_string()));
+            self.queued.insert(hash.to_string(), commit);
+        }
+        Ok(())
+    }
+}
+
+impl Marks<'_> {
+    fn mark(&mut self, hash: &str, flag: u8) -> anyhow::Result<()> {
+        let old = self.flags.get(hash).copied().unwrap_or(0);
+        if old | flag == old {
+            return Ok(());
+        }
+        self.flags.insert(hash.to_string(), old | flag);
+        if !self.commits.contains_key(hash) {
+            let commit = read_commit(self.repository, hash)?;
+            self.commits.insert(hash.to_string(), commit);
+        }
+
+        // A commit gaining a flag is visited again so its parents get it too
+        let timestamp = self.commits[hash].committer()?.timestamp;
+        self.pushed += 1;
+        self.queue
+            .push((timestamp, usize::MAX - self.pushed, hash.to_string()));
+        Ok(())
+    }
+}
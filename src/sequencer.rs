@@ -0,0 +1,61 @@
+// The operations that can stop partway through and wait for the user, such as a merge with
+// conflicts or a rebase at an `edit` step. git records each one's state in files under the
+// worktree's gitdir while it is in progress, and gitrs reads the same files, so it knows when an
+// operation started by either of them hasn't finished yet.
+use std::fmt;
+
+use crate::repository::Repository;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operation {
+    Merge,
+    Rebase,
+    /// Applying patches from a mailbox, as git am does
+    Am,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Operation {
+    /// Returns the operation in progress in the repository's worktree, if any. Only one of them
+    /// can be stopped at a time, except for a bisection, which is reported last.
+    pub fn in_progress(repository: &Repository) -> Option<Self> {
+        let exists = |name: &str| repository.gitdir.join(name).exists();
+        if exists("rebase-apply/applying") {
+            Some(Self::Am)
+        } else if exists("rebase-apply") || exists("rebase-merge") {
+            Some(Self::Rebase)
+        } else if exists("MERGE_HEAD") {
+            Some(Self::Merge)
+        } else if exists("CHERRY_PICK_HEAD") {
+            Some(Self::CherryPick)
+        } else if exists("REVERT_HEAD") {
+            Some(Self::Revert)
+        } else if exists("BISECT_START") {
+            Some(Self::Bisect)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the operation leaves the worktree half done, so that HEAD mustn't move to
+    /// another branch until it is finished or aborted
+    pub fn blocks_switching(&self) -> bool {
+        *self != Self::Bisect
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let activity = match self {
+            Operation::Merge => "merging",
+            Operation::Rebase => "rebasing",
+            Operation::Am => "applying a mailbox",
+            Operation::CherryPick => "cherry-picking",
+            Operation::Revert => "reverting",
+            Operation::Bisect => "bisecting",
+        };
+        write!(f, "{}", activity)
+    }
+}
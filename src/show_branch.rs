@@ -0,0 +1,342 @@
+// Shows branches side by side, one column per branch, the way git's show-branch does. The tips are
+// walked together by commit date, marking each commit with the branches it is reachable from, until
+// everything left is reachable from all of them. The commits found are then listed in graph order
+// down to the first one common to every branch (plus `more` commits), each named after the branch
+// it was first reached from, eg. `side~2^2`.
+use std::collections::HashMap;
+
+use anyhow::bail;
+
+use crate::object::commit::Commit;
+use crate::repository::Repository;
+use crate::revwalk;
+
+// Same limit as git, which keeps the flags of every branch in one word
+const MAX_REVS: usize = 26;
+
+// The low bits of a commit's flags, as used by git, with one bit per branch above them
+const SEEN: u32 = 1;
+const UNINTERESTING: u32 = 2;
+const REV_SHIFT: u32 = 2;
+
+struct Node {
+    commit: Commit,
+    timestamp: i64,
+    flags: u32,
+}
+
+struct Name {
+    head_name: String,
+    generation: usize,
+}
+
+struct ShowBranch<'a> {
+    repository: &'a Repository,
+    nodes: HashMap<String, Node>,
+    // Seen commits, most recently seen first
+    seen: Vec<String>,
+    names: HashMap<String, Name>,
+}
+
+/// Lists the lines show-branch prints for `revs` (name and commit hash of each branch), where
+/// `head` is the index of the branch HEAD is on, if any
+pub fn show_branch(
+    repository: &Repository,
+    revs: &[(String, String)],
+    head: Option<usize>,
+    more: usize,
+) -> anyhow::Result<Vec<String>> {
+    if revs.len() > MAX_REVS {
+        bail!("cannot handle more than {} revs.", MAX_REVS);
+    }
+    let mut show = ShowBranch {
+        repository,
+        nodes: HashMap::new(),
+        seen: Vec::new(),
+        names: HashMap::new(),
+    };
+    let mut queue = Vec::new();
+    for (i, (_, hash)) in revs.iter().enumerate() {
+        show.parse(hash)?;
+        let node = show.nodes.get_mut(hash).expect("Just parsed");
+        let flag = 1 << (i as u32 + REV_SHIFT);
+        node.flags |= flag;
+        if node.flags == flag {
+            show.insert_by_date(&mut queue, hash);
+        }
+    }
+    show.join(queue, revs.len(), more)?;
+
+    let mut seen = std::mem::take(&mut show.seen);
+    seen.sort_by_key(|hash| std::cmp::Reverse(show.nodes[hash].timestamp));
+    let seen = show.topo_sort(seen);
+    show.name_commits(&seen, revs);
+
+    let mut lines = Vec::new();
+    if revs.len() > 1 {
+        for (i, (name, hash)) in revs.iter().enumerate() {
+            let marker = if head == Some(i) { '*' } else { '!' };
+            lines.push(format!(
+                "{}{} [{}] {}",
+                " ".repeat(i),
+                marker,
+                name,
+                show.nodes[hash].commit.subject()
+            ));
+        }
+        lines.push("-".repeat(revs.len()));
+    }
+
+    let all_revs = ((1 << (revs.len() as u32 + REV_SHIFT)) - 1) & !((1 << REV_SHIFT) - 1);
+    let mut shown_merge_point = false;
+    let mut extra = more;
+    for hash in &seen {
+        let node = &show.nodes[hash];
+        shown_merge_point |= node.flags & all_revs == all_revs;
+
+        let mut line = String::new();
+        if revs.len() > 1 {
+            let is_merge = node.commit.parents().len() > 1;
+            if is_merge && omit_merge(node, hash, revs) {
+                continue;
+            }
+            for i in 0..revs.len() {
+                line.push(match node.flags & (1 << (i as u32 + REV_SHIFT)) {
+                    0 => ' ',
+                    _ if is_merge => '-',
+                    _ if head == Some(i) => '*',
+                    _ => '+',
+                });
+            }
+            line.push(' ');
+        }
+        let name = match show.names.get(hash) {
+            Some(name) => name.display(),
+            None => Commit::short(hash).to_string(),
+        };
+        line.push_str(&format!("[{}] {}", name, node.commit.subject()));
+        lines.push(line);
+
+        if shown_merge_point {
+            if extra == 0 {
+                break;
+            }
+            extra -= 1;
+        }
+    }
+    Ok(lines)
+}
+
+// Like git, merges only one branch reaches are left out unless they are its tip, as they don't
+// tell the branches apart any more than the commits they merge
+fn omit_merge(node: &Node, hash: &str, revs: &[(String, String)]) -> bool {
+    let reached_by = (0..revs.len())
+        .filter(|i| node.flags & (1 << (*i as u32 + REV_SHIFT)) != 0)
+        .count();
+    reached_by == 1 && revs.iter().all(|(_, rev)| rev != hash)
+}
+
+impl Name {
+    fn display(&self) -> String {
+        match self.generation {
+            0 => self.head_name.clone(),
+            1 => format!("{}^", self.head_name),
+            generation => format!("{}~{}", self.head_name, generation),
+        }
+    }
+}
+
+impl ShowBranch<'_> {
+    fn parse(&mut self, hash: &str) -> anyhow::Result<()> {
+        if !self.nodes.contains_key(hash) {
+            let commit = revwalk::read_commit(self.repository, hash)?;
+            let timestamp = commit.committer()?.timestamp;
+            self.nodes.insert(
+                hash.to_string(),
+                Node {
+                    commit,
+                    timestamp,
+                    flags: 0,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    // Queues `hash` after the commits at least as recent as it
+    fn insert_by_date(&self, queue: &mut Vec<String>, hash: &str) {
+        let timestamp = self.nodes[hash].timestamp;
+        let idx = queue
+            .iter()
+            .position(|queued| self.nodes[queued].timestamp < timestamp)
+            .unwrap_or(queue.len());
+        queue.insert(idx, hash.to_string());
+    }
+
+    fn mark_seen(&mut self, hash: &str) -> bool {
+        let node = self.nodes.get_mut(hash).expect("Seen commits are parsed");
+        if node.flags & SEEN != 0 {
+            return false;
+        }
+        node.flags |= SEEN;
+        self.seen.insert(0, hash.to_string());
+        true
+    }
+
+    // Spreads the branch flags through history until every queued commit is reachable from all
+    // branches, going `extra` commits further
+    fn join(
+        &mut self,
+        mut queue: Vec<String>,
+        num_revs: usize,
+        extra: usize,
+    ) -> anyhow::Result<()> {
+        let all_mask = (1 << (num_revs as u32 + REV_SHIFT)) - 1;
+        let all_revs = all_mask & !((1 << REV_SHIFT) - 1);
+        let mut extra = extra as isize;
+
+        while !queue.is_empty() {
+            let still_interesting = queue
+                .iter()
+                .any(|hash| self.nodes[hash].flags & UNINTERESTING == 0);
+            let hash = queue.remove(0);
+            let mut flags = self.nodes[&hash].flags & all_mask;
+            if !still_interesting && extra <= 0 {
+                break;
+            }
+
+            self.mark_seen(&hash);
+            if flags & all_revs == all_revs {
+                flags |= UNINTERESTING;
+            }
+            for parent in self.nodes[&hash].commit.parents().to_vec() {
+                let parent_flags = self.nodes.get(&parent).map_or(0, |node| node.flags);
+                if parent_flags & flags == flags {
+                    continue;
+                }
+                self.parse(&parent)?;
+                if self.mark_seen(&parent) && !still_interesting {
+                    extra -= 1;
+                }
+                self.nodes.get_mut(&parent).expect("Just parsed").flags |= flags;
+                self.insert_by_date(&mut queue, &parent);
+            }
+        }
+        Ok(())
+    }
+
+    // Orders commits so that each comes before its parents, following one line of history as far
+    // as possible before the next, starting from the tips in the order given
+    fn topo_sort(&self, commits: Vec<String>) -> Vec<String> {
+        let mut indegree: HashMap<&str, usize> =
+            commits.iter().map(|hash| (hash.as_str(), 1)).collect();
+        for hash in &commits {
+            for parent in self.nodes[hash].commit.parents() {
+                if let Some(count) = indegree.get_mut(parent.as_str()) {
+                    *count += 1;
+                }
+            }
+        }
+
+        let mut stack: Vec<&str> = commits
+            .iter()
+            .map(String::as_str)
+            .filter(|hash| indegree[hash] == 1)
+            .collect();
+        stack.reverse();
+        let mut sorted = Vec::new();
+        while let Some(hash) = stack.pop() {
+            for parent in self.nodes[hash].commit.parents() {
+                if let Some(count) = indegree.get_mut(parent.as_str())
+                    && *count > 0
+                {
+                    *count -= 1;
+                    if *count == 1 {
+                        stack.push(parent);
+                    }
+                }
+            }
+            indegree.insert(hash, 0);
+            sorted.push(hash.to_string());
+        }
+        sorted
+    }
+
+    fn parents(&self, hash: &str) -> &[String] {
+        self.nodes
+            .get(hash)
+            .map_or(&[], |node| node.commit.parents())
+    }
+
+    // Names the unnamed first parents down from `hash`, returning how many were named
+    fn name_first_parent_chain(&mut self, hash: &str) -> usize {
+        let mut named = 0;
+        let mut hash = hash.to_string();
+        while let Some(name) = self.names.get(&hash) {
+            let Some(parent) = self.parents(&hash).first().cloned() else {
+                break;
+            };
+            if self.names.contains_key(&parent) {
+                break;
+            }
+            let name = Name {
+                head_name: name.head_name.clone(),
+                generation: name.generation + 1,
+            };
+            self.names.insert(parent.clone(), name);
+            named += 1;
+            hash = parent;
+        }
+        named
+    }
+
+    // Names the branch tips after themselves, then first parents after the tips they descend
+    // from, then the remaining parents after their children, eg. `master~2^2`
+    fn name_commits(&mut self, commits: &[String], revs: &[(String, String)]) {
+        for hash in commits {
+            if let Some((name, _)) = revs.iter().find(|(_, rev)| rev == hash) {
+                self.names.entry(hash.clone()).or_insert(Name {
+                    head_name: name.clone(),
+                    generation: 0,
+                });
+            }
+        }
+        while commits
+            .iter()
+            .map(|hash| self.name_first_parent_chain(hash))
+            .sum::<usize>()
+            > 0
+        {}
+
+        loop {
+            let mut named = 0;
+            for hash in commits {
+                let Some(name) = self.names.get(hash) else {
+                    continue;
+                };
+                let base = name.display();
+                for (nth, parent) in self.parents(hash).to_vec().into_iter().enumerate() {
+                    if self.names.contains_key(&parent) {
+                        continue;
+                    }
+                    let head_name = match nth {
+                        0 => format!("{}^", base),
+                        nth => format!("{}^{}", base, nth + 1),
+                    };
+                    self.names.insert(
+                        parent.clone(),
+                        Name {
+                            head_name,
+                            generation: 0,
+                        },
+                    );
+                    named += 1;
+                    self.name_first_parent_chain(&parent);
+                }
+            }
+            if named == 0 {
+                break;
+            }
+        }
+    }
+}
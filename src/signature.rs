@@ -0,0 +1,257 @@
+// Verifies the signatures other tools embed in commits and tags. A commit is signed in its gpgsig
+// header and a tag at the end of its message; either way, what was signed is the object without
+// the signature. OpenPGP and X.509 signatures are checked with gpg (gpg.program) or gpgsm, SSH ones
+// with ssh-keygen (gpg.ssh.program) against the keys listed in gpg.ssh.allowedSignersFile, the way
+// git does.
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::object::{GitrsObject, ObjectType};
+use crate::repository::Repository;
+use crate::temporary::TempDir;
+use crate::trace::{self, trace};
+
+const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+const X509_SIGNATURE: &str = "-----BEGIN SIGNED MESSAGE-----";
+const SIGNATURE_STARTS: [&str; 4] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN PGP MESSAGE-----",
+    SSH_SIGNATURE,
+    X509_SIGNATURE,
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Good,
+    /// Good, but made by a key that isn't trusted
+    UnknownValidity,
+    Bad,
+    ExpiredSignature,
+    ExpiredKey,
+    RevokedKey,
+    /// The key is missing, or the signer isn't allowed
+    CannotCheck,
+}
+
+pub struct Verification {
+    pub status: Status,
+    /// What the verifying program reported, for people
+    pub output: String,
+    /// gpg's machine-readable status lines, or the output for SSH signatures
+    pub raw: String,
+}
+
+impl Verification {
+    /// Returns true if the signature is valid, like git's verify-commit, even when made by an
+    /// untrusted key
+    pub fn is_good(&self) -> bool {
+        matches!(self.status, Status::Good | Status::UnknownValidity)
+    }
+}
+
+/// Splits the contents of a commit or tag object into the signed payload and the signature, or
+/// returns None if it isn't signed
+pub fn split_signature(data: &[u8], object_type: ObjectType) -> Option<(Vec<u8>, String)> {
+    let text = String::from_utf8_lossy(data);
+    match object_type {
+        ObjectType::Commit => {
+            let header_end = text.find("\n\n").map_or(text.len(), |idx| idx + 1);
+            let mut payload = String::new();
+            let mut signature = String::new();
+            let mut in_signature = false;
+            for line in text[..header_end].split_inclusive('\n') {
+                if let Some(value) = line.strip_prefix("gpgsig ") {
+                    in_signature = true;
+                    signature.push_str(value);
+                } else if let Some(value) = line.strip_prefix(' ').filter(|_| in_signature) {
+                    signature.push_str(value);
+                } else {
+                    in_signature = false;
+                    payload.push_str(line);
+                }
+            }
+            payload.push_str(&text[header_end..]);
+            (!signature.is_empty()).then(|| (payload.into_bytes(), signature))
+        }
+        ObjectType::Tag => {
+            let start = SIGNATURE_STARTS
+                .iter()
+                .filter_map(|start| text.rfind(&format!("\n{}", start)))
+                .max()?
+                + 1;
+            Some((data[..start].to_vec(), text[start..].to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Verifies the signature of the commit or tag `hash`, returning the signed payload along with
+/// the result, or None if the object isn't signed
+pub fn verify_object(
+    repository: &Repository,
+    config: &Config,
+    hash: &str,
+) -> anyhow::Result<Option<(Vec<u8>, Verification)>> {
+    let mut object = GitrsObject::read(repository, hash)?;
+    let Some((payload, signature)) = split_signature(&object.serialize(), object.get_type()) else {
+        return Ok(None);
+    };
+    let verification = verify(config, &payload, &signature)?;
+    Ok(Some((payload, verification)))
+}
+
+/// Checks that `signature` signs `payload`
+pub fn verify(config: &Config, payload: &[u8], signature: &str) -> anyhow::Result<Verification> {
+    let directory = TempDir::new("signature")?;
+    let signature_path = directory.path().join("signature");
+    fs::write(&signature_path, signature)
+        .with_context(|| format!("Couldn't write {}", signature_path.display()))?;
+
+    if signature.starts_with(SSH_SIGNATURE) {
+        verify_ssh(config, payload, &signature_path)
+    } else {
+        let program = if signature.starts_with(X509_SIGNATURE) {
+            config.get("gpg.x509.program").unwrap_or("gpgsm")
+        } else {
+            config
+                .get("gpg.openpgp.program")
+                .or_else(|| config.get("gpg.program"))
+                .unwrap_or("gpg")
+        };
+        verify_gpg(program, payload, &signature_path)
+    }
+}
+
+fn verify_gpg(program: &str, payload: &[u8], signature: &Path) -> anyhow::Result<Verification> {
+    let mut command = Command::new(program);
+    command
+        .args(["--keyid-format=long", "--status-fd=1", "--verify"])
+        .arg(signature)
+        .arg("-");
+    let (_, raw, output) = run(command, payload)?;
+
+    let mut status = Status::CannotCheck;
+    for line in raw.lines() {
+        let Some(keyword) = line
+            .strip_prefix("[GNUPG:] ")
+            .and_then(|line| line.split(' ').next())
+        else {
+            continue;
+        };
+        status = match (keyword, status) {
+            ("GOODSIG", _) => Status::Good,
+            ("BADSIG", _) => Status::Bad,
+            ("EXPSIG", _) => Status::ExpiredSignature,
+            ("EXPKEYSIG", _) => Status::ExpiredKey,
+            ("REVKEYSIG", _) => Status::RevokedKey,
+            ("ERRSIG", _) => Status::CannotCheck,
+            ("TRUST_UNDEFINED" | "TRUST_NEVER", Status::Good) => Status::UnknownValidity,
+            (_, status) => status,
+        };
+    }
+    Ok(Verification {
+        status,
+        output,
+        raw,
+    })
+}
+
+fn verify_ssh(config: &Config, payload: &[u8], signature: &Path) -> anyhow::Result<Verification> {
+    let cannot_check = |output: String| Verification {
+        status: Status::CannotCheck,
+        raw: output.clone(),
+        output,
+    };
+    let Some(allowed_signers) = config
+        .get("gpg.ssh.allowedSignersFile")
+        .map(expand_home)
+        .filter(|path| path.is_file())
+    else {
+        return Ok(cannot_check(
+            "gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature \
+             verification\n"
+                .to_string(),
+        ));
+    };
+    let program = config.get("gpg.ssh.program").unwrap_or("ssh-keygen");
+    let ssh_keygen = |args: &[&str]| {
+        let mut command = Command::new(program);
+        command.args(args).arg("-s").arg(signature);
+        command
+    };
+
+    let allowed = allowed_signers.to_string_lossy();
+    let (found, principals, _) = run(ssh_keygen(&["-Y", "find-principals", "-f", &allowed]), &[])?;
+    let principals: Vec<&str> = principals.lines().filter(|line| !line.is_empty()).collect();
+    if !found || principals.is_empty() {
+        // The signature may still be good, it just isn't from anyone allowed
+        let (_, out, err) = run(
+            ssh_keygen(&["-Y", "check-novalidate", "-n", "git"]),
+            payload,
+        )?;
+        let output = format!("{}{}No principal matched.\n", out, err);
+        return Ok(cannot_check(output.replace('\r', "")));
+    }
+
+    let mut output = String::new();
+    for principal in principals {
+        let verify = ["-Y", "verify", "-f", &allowed, "-I", principal, "-n", "git"];
+        let (verified, out, err) = run(ssh_keygen(&verify), payload)?;
+        // ssh-keygen ends some of its messages with carriage returns
+        output = format!("{}{}", out, err).replace('\r', "");
+        if verified {
+            return Ok(Verification {
+                status: Status::Good,
+                raw: output.clone(),
+                output,
+            });
+        }
+    }
+    Ok(Verification {
+        status: Status::Bad,
+        raw: output.clone(),
+        output,
+    })
+}
+
+// Runs `command` with `input` on its stdin, returning whether it succeeded along with its stdout
+// and stderr
+fn run(mut command: Command, input: &[u8]) -> anyhow::Result<(bool, String, String)> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let args: Vec<String> = std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    trace!("run_command: {}", trace::quote(&args));
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Couldn't run {}", program))?;
+    // A program that doesn't read its input closes the pipe early, which isn't an error
+    let _ = child
+        .stdin
+        .take()
+        .expect("Stdin was piped")
+        .write_all(input);
+    let output = child.wait_with_output()?;
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
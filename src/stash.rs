@@ -0,0 +1,276 @@
+// Shows, applies and drops the stashes git records in refs/stash, and turns them into branches. A
+// stash is a commit of the worktree whose first parent is the commit it was made on and whose
+// second parent holds the index, with a third parent for the untracked files when those were
+// saved too. The entries are the lines of the refs/stash reflog, the newest of them (stash@{0})
+// last. As gitrs has no index, applying a stash restores the worktree changes only. They are
+// merged into the commit checked out as a merge would, the stash as their side, so a file changed
+// both in the stash and since it was made is merged line by line, and left with conflict markers
+// where the changes conflict.
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+
+use crate::config::Config;
+use crate::diff::{self, Change};
+use crate::line_diff::Whitespace;
+use crate::merge;
+use crate::merge_file::Labels;
+use crate::object::GitrsObject;
+use crate::object::commit::Commit;
+use crate::object::tree::Tree;
+use crate::pathspec::Pathspec;
+use crate::refs::{Ref, RefUpdate};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::switch::{self, Target};
+use crate::tree_walk::{TreeWalk, TreeWalkOptions};
+
+pub struct Stash {
+    /// How the stash was named, eg. `stash@{1}`, or `refs/stash@{0}` when it wasn't
+    pub name: String,
+    /// Its position in the reflog, newest first, or None for a stash commit named by its hash
+    pub index: Option<usize>,
+    pub hash: String,
+    commit: Commit,
+}
+
+// The mode and hash of a file, or None where it is missing
+type FileEntry = Option<(String, String)>;
+
+struct LogEntry {
+    old: String,
+    new: String,
+    // The identity and message, separated by a tab
+    rest: String,
+}
+
+/// Lists the hash and message of every stash, newest first
+pub fn list(repository: &Repository) -> anyhow::Result<Vec<(String, String)>> {
+    Ok(read_log(repository)?
+        .into_iter()
+        .rev()
+        .map(|entry| {
+            let message = entry
+                .rest
+                .split_once('\t')
+                .map_or("", |(_, message)| message);
+            (entry.new, message.to_string())
+        })
+        .collect())
+}
+
+/// Finds the stash `name` selects: `stash@{N}`, `refs/stash@{N}`, `N` alone, or a stash commit.
+/// Without a name, that is the newest one.
+pub fn find(repository: &Repository, name: Option<&str>) -> anyhow::Result<Stash> {
+    let log = read_log(repository)?;
+    let (name, selector) = match name {
+        None => ("refs/stash@{0}".to_string(), Some(("refs/stash", 0))),
+        Some(name) => (name.to_string(), parse_selector(name)),
+    };
+    if log.is_empty() && selector.is_some() {
+        bail!("No stash entries found.");
+    }
+
+    let (index, hash) = match selector {
+        Some((base, index)) => {
+            if index >= log.len() {
+                bail!("log for '{}' only has {} entries", base, log.len());
+            }
+            (Some(index), log[log.len() - 1 - index].new.clone())
+        }
+        None => {
+            let hash = GitrsObject::find(repository, &name)
+                .ok()
+                .with_context(|| format!("{} is not a valid reference", name))?;
+            (None, hash)
+        }
+    };
+    let commit = revwalk::read_commit(repository, &hash)
+        .ok()
+        .filter(|commit| commit.parents().len() >= 2)
+        .with_context(|| format!("'{}' is not a stash-like commit", name))?;
+    Ok(Stash {
+        name,
+        index,
+        hash,
+        commit,
+    })
+}
+
+/// Lists the changes the stash makes to the commit it was made on
+pub fn changes(repository: &Repository, stash: &Stash) -> anyhow::Result<Vec<Change>> {
+    let base = Tree::of_commit(repository, &stash.commit.parents()[0])?;
+    let tree = Tree::of_commit(repository, &stash.hash)?;
+    diff::diff_trees(repository, &base, &tree, true, &Pathspec::default())
+}
+
+/// What applying a stash did
+pub struct Applied {
+    /// What git says about the files merged, eg. `Auto-merging a`
+    pub messages: Vec<String>,
+    /// The files left with conflicts
+    pub conflicts: Vec<String>,
+}
+
+/// Restores the changes recorded in the stash to the worktree, along with its untracked files,
+/// merging them with the changes made since. Nothing is touched if a file the stash changes was
+/// changed locally, or an untracked file is in the way.
+pub fn apply(repository: &Repository, stash: &Stash) -> anyhow::Result<Applied> {
+    let empty = || Tree {
+        records: Vec::new(),
+    };
+    let base = Tree::of_commit(repository, &stash.commit.parents()[0])?;
+    let tree = Tree::of_commit(repository, &stash.hash)?;
+    let head = match Ref::try_resolve(repository, "HEAD")? {
+        Some(hash) => Tree::of_commit(repository, &hash)?,
+        None => empty(),
+    };
+    let untracked = match stash.commit.parents().get(2) {
+        Some(hash) => Tree::of_commit(repository, hash)?,
+        None => empty(),
+    };
+
+    let config = Config::load(repository)?;
+    // As git names the sides
+    let labels = Labels {
+        base: "Stash base",
+        ours: "Updated upstream",
+        theirs: "Stashed changes",
+    };
+    let merged = merge::merge_trees(
+        repository,
+        &config,
+        [&base, &head, &tree],
+        &labels,
+        Whitespace::Exact,
+    )?;
+    let worktree = &repository.worktree;
+    let untracked = flatten(repository, &untracked)?;
+    if let Some((path, _)) = untracked
+        .iter()
+        .find(|(path, _)| fs::symlink_metadata(worktree.join(path)).is_ok())
+    {
+        bail!(
+            "{} already exists, no checkout\ncould not restore untracked files from stash",
+            path
+        );
+    }
+
+    merge::update_worktree(repository, &config, &head, &merged.updates, None)?;
+    let files: Vec<(&str, &str, &str)> = untracked
+        .iter()
+        .filter_map(|(path, file)| {
+            file.as_ref()
+                .map(|(mode, hash)| (path.as_str(), mode.as_str(), hash.as_str()))
+        })
+        .collect();
+    Tree::checkout_files(repository, worktree, &files)?;
+    Ok(Applied {
+        messages: merged.messages,
+        conflicts: merged.conflicts,
+    })
+}
+
+/// Removes the stash from the reflog, joining up the entries around it. refs/stash goes away
+/// along with the last entry.
+pub fn drop(repository: &Repository, stash: &Stash) -> anyhow::Result<()> {
+    let index = stash
+        .index
+        .with_context(|| format!("'{}' is not a stash reference", stash.name))?;
+    let mut log = read_log(repository)?;
+    let position = log.len() - 1 - index;
+    let dropped = log.remove(position);
+    if let Some(next) = log.get_mut(position) {
+        next.old = dropped.old;
+    }
+
+    let path = log_path(repository);
+    let new = log.last().map(|entry| entry.new.clone());
+    Ref::transaction(
+        repository,
+        &[RefUpdate {
+            name: "refs/stash".to_string(),
+            new: new.clone(),
+            old: None,
+            verify_only: false,
+            deref: false,
+        }],
+    )?;
+    if new.is_none() {
+        return fs::remove_file(&path)
+            .with_context(|| format!("Failed to delete {}", path.display()));
+    }
+    let contents: String = log
+        .iter()
+        .map(|entry| format!("{} {} {}\n", entry.old, entry.new, entry.rest))
+        .collect();
+    fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Creates the branch `name` at the commit the stash was made on, switches to it and applies the
+/// stash there, dropping it if it came from the stash reflog and applied without conflicts
+pub fn branch(repository: &Repository, name: &str, stash: &Stash) -> anyhow::Result<Applied> {
+    let base = stash.commit.parents()[0].clone();
+    switch::switch(repository, &Target::NewBranch(name.to_string(), base))?;
+    let applied = apply(repository, stash)?;
+    if stash.index.is_some() && applied.conflicts.is_empty() {
+        drop(repository, stash)?;
+    }
+    Ok(applied)
+}
+
+// Parses `stash@{N}`, `refs/stash@{N}` or `N` into the name of the ref and N
+fn parse_selector(name: &str) -> Option<(&str, usize)> {
+    if let Ok(index) = name.parse() {
+        return Some(("refs/stash", index));
+    }
+    let (base, rest) = name.split_once("@{")?;
+    let index = rest.strip_suffix('}')?.parse().ok()?;
+    ["stash", "refs/stash"]
+        .contains(&base)
+        .then_some((base, index))
+}
+
+// Maps the path of every file below `tree` to its mode and hash
+fn flatten(repository: &Repository, tree: &Tree) -> anyhow::Result<Vec<(String, FileEntry)>> {
+    let options = TreeWalkOptions {
+        recursive: true,
+        ..TreeWalkOptions::default()
+    };
+    let mut files = Vec::new();
+    for entry in TreeWalk::new(repository, &[tree], options) {
+        let mut entry = entry?;
+        if let Some(Some(file)) = entry.entries.pop() {
+            files.push((entry.path, Some(file)));
+        }
+    }
+    Ok(files)
+}
+
+fn log_path(repository: &Repository) -> PathBuf {
+    repository.get_path(&["logs", "refs", "stash"])
+}
+
+// Reads the refs/stash reflog, oldest entry first
+fn read_log(repository: &Repository) -> anyhow::Result<Vec<LogEntry>> {
+    let path = log_path(repository);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(Vec::new());
+    };
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(old), Some(new), Some(rest)) => Ok(LogEntry {
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    rest: rest.to_string(),
+                }),
+                _ => bail!("Malformed reflog entry in {}: {}", path.display(), line),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,263 @@
+// Switches the worktree to another branch, or detaches HEAD at a commit. Only the files that differ
+// between the current and the new commit are touched, so local changes to other files carry over;
+// the switch is refused if it would overwrite a local change or an untracked file. Like git, every
+// switch is recorded in the HEAD reflog as `checkout: moving from <old> to <new>`, which is where
+// `@{-N}` looks up previous branches. Switching away from a detached HEAD reports the commits no
+// ref can reach anymore, so they can be rescued before they are pruned. Switching is refused in
+// the middle of a merge, rebase or similar operation, which would be left half done.
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, bail};
+
+use crate::branch;
+use crate::config::Config;
+use crate::diff::{self, Change, Status};
+use crate::ident::Ident;
+use crate::object::tree::Tree;
+use crate::pathspec::Pathspec;
+use crate::refs::{Ref, RefUpdate, ZERO_HASH};
+use crate::repository::Repository;
+use crate::revwalk;
+use crate::sequencer::Operation;
+use crate::worktree::Worktree;
+
+pub enum Target {
+    /// An existing branch
+    Branch(String),
+    /// A branch to create at the given commit
+    NewBranch(String, String),
+    /// A commit to detach HEAD at
+    Detached(String),
+}
+
+/// Checks out `target` and points HEAD at it. Returns the commits left behind by moving a detached
+/// HEAD, newest first.
+pub fn switch(repository: &Repository, target: &Target) -> anyhow::Result<Vec<String>> {
+    match Operation::in_progress(repository) {
+        Some(operation) if operation.blocks_switching() => bail!(
+            "cannot switch branch while {}\nConsider finishing it first, or \"gitrs worktree add\".",
+            operation
+        ),
+        Some(operation) => eprintln!("warning: you are switching branch while {}", operation),
+        None => {}
+    }
+
+    let (commit, branch) = match target {
+        Target::Branch(branch) => (
+            Ref::try_resolve(repository, &format!("refs/heads/{}", branch))?
+                .with_context(|| format!("invalid reference: {}", branch))?,
+            Some(branch),
+        ),
+        Target::NewBranch(branch, start) => {
+            branch::verify_name(branch)?;
+            if Ref::try_resolve(repository, &format!("refs/heads/{}", branch))?.is_some() {
+                bail!("A branch named '{}' already exists", branch);
+            }
+            (start.clone(), Some(branch))
+        }
+        Target::Detached(commit) => (commit.clone(), None),
+    };
+
+    if let Some(branch) = branch {
+        let head = format!("ref: refs/heads/{}", branch);
+        let current = fs::canonicalize(&repository.worktree)?;
+        for worktree in Worktree::list(repository)? {
+            let elsewhere = fs::canonicalize(&worktree.path).map_or(true, |path| path != current);
+            if worktree.head.trim() == head && elsewhere {
+                bail!(
+                    "'{}' is already checked out at '{}'",
+                    branch,
+                    worktree.path.display()
+                );
+            }
+        }
+    }
+
+    let old_head = Ref::try_resolve(repository, "HEAD")?;
+    let empty = || Tree {
+        records: Vec::new(),
+    };
+    let old_tree = match &old_head {
+        Some(hash) => Tree::of_commit(repository, hash)?,
+        None => empty(),
+    };
+    let new_tree = Tree::of_commit(repository, &commit)?;
+    let changes = diff::diff_trees(repository, &old_tree, &new_tree, true, &Pathspec::default())?;
+
+    let config = Config::load(repository)?;
+    let trust_executable_bit = config.get_bool("core.fileMode")?.unwrap_or(true);
+    let worktree = &repository.worktree;
+    let local: HashSet<String> = diff::diff_worktree(
+        repository,
+        &old_tree,
+        &old_tree,
+        worktree,
+        trust_executable_bit,
+        &Pathspec::default(),
+    )?
+    .into_iter()
+    .map(|change| change.path)
+    .collect();
+    let deleted: HashSet<&str> = changes
+        .iter()
+        .filter(|change| change.status == Status::Deleted)
+        .map(|change| change.path.as_str())
+        .collect();
+
+    let mut overwritten = Vec::new();
+    let mut untracked = Vec::new();
+    for change in &changes {
+        if local.contains(&change.path) {
+            overwritten.push(change.path.as_str());
+        } else if change.status == Status::Added && has_untracked(worktree, &change.path, &deleted)?
+        {
+            untracked.push(change.path.as_str());
+        }
+    }
+    if !overwritten.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+             Please commit your changes or stash them before you switch branches.",
+            overwritten.join("\n\t")
+        );
+    }
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+             Please move or remove them before you switch branches.",
+            untracked.join("\n\t")
+        );
+    }
+
+    if let Target::NewBranch(branch, start) = target {
+        Ref::transaction(
+            repository,
+            &[RefUpdate {
+                name: format!("refs/heads/{}", branch),
+                new: Some(start.clone()),
+                old: Some(ZERO_HASH.to_string()),
+                verify_only: false,
+                deref: false,
+            }],
+        )?;
+    }
+
+    update_worktree(repository, &changes)?;
+
+    let detached = branch::current(repository)?.is_none();
+    let from = match branch::current(repository)? {
+        Some(branch) => branch,
+        None => old_head.clone().unwrap_or_default(),
+    };
+    match branch {
+        Some(branch) => Ref::write_symbolic(repository, "HEAD", &format!("refs/heads/{}", branch))?,
+        None => Ref::transaction(
+            repository,
+            &[RefUpdate {
+                name: "HEAD".to_string(),
+                new: Some(commit.clone()),
+                old: None,
+                verify_only: false,
+                deref: false,
+            }],
+        )?,
+    }
+
+    // The switch itself succeeded, so a missing identity only costs the reflog entry
+    if let Ok(ident) = Ident::committer(&config) {
+        let to = branch.cloned().unwrap_or_else(|| commit.clone());
+        Ref::append_log(
+            repository,
+            "HEAD",
+            old_head.as_deref(),
+            &commit,
+            &ident,
+            &format!("checkout: moving from {} to {}", from, to),
+        )?;
+    }
+
+    match old_head {
+        Some(old_head) if detached && old_head != commit => orphans(repository, &old_head),
+        _ => Ok(Vec::new()),
+    }
+}
+
+// Lists the commits reachable from `hash` but not from any ref
+fn orphans(repository: &Repository, hash: &str) -> anyhow::Result<Vec<String>> {
+    // Replace refs can point to any kind of object, and only commits have history
+    let refs: Vec<String> = Ref::list(repository)?
+        .into_iter()
+        .filter_map(|(_, hash)| revwalk::find_commit(repository, &hash).ok())
+        .collect();
+    Ok(
+        revwalk::difference(repository, &[], &[hash.to_string()], &refs)?
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect(),
+    )
+}
+
+// Returns true if there is something at `path` that isn't tracked, other than the files about to
+// be deleted
+fn has_untracked(worktree: &Path, path: &str, deleted: &HashSet<&str>) -> anyhow::Result<bool> {
+    let full_path = worktree.join(path);
+    let Ok(metadata) = fs::symlink_metadata(&full_path) else {
+        return Ok(false);
+    };
+    if !metadata.is_dir() {
+        return Ok(!deleted.contains(path));
+    }
+    for entry in fs::read_dir(&full_path)? {
+        let name = entry?.file_name();
+        if has_untracked(
+            worktree,
+            &format!("{}/{}", path, name.to_string_lossy()),
+            deleted,
+        )? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Makes the `changes` between two trees to the worktree, deleting the files they delete and
+/// writing out the rest
+pub fn update_worktree(repository: &Repository, changes: &[Change]) -> anyhow::Result<()> {
+    let worktree = &repository.worktree;
+    for change in changes
+        .iter()
+        .filter(|change| change.status == Status::Deleted)
+    {
+        let path = worktree.join(&change.path);
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete {}", path.display()))?;
+        }
+        remove_empty_parents(worktree, &path);
+    }
+    let files: Vec<(&str, &str, &str)> = changes
+        .iter()
+        .filter(|change| change.status != Status::Deleted)
+        .map(|change| {
+            (
+                change.path.as_str(),
+                change.new_mode.as_str(),
+                change.new_hash.as_str(),
+            )
+        })
+        .collect();
+    Tree::checkout_files(repository, worktree, &files)
+}
+
+/// Removes the directories above a deleted file that were left empty, up to the worktree
+pub fn remove_empty_parents(worktree: &Path, path: &Path) {
+    let mut dir = path.parent();
+    while let Some(path) = dir {
+        if path == worktree || fs::remove_dir(path).is_err() {
+            break;
+        }
+        dir = path.parent();
+    }
+}
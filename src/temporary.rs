@@ -0,0 +1,85 @@
+// Makes the temporary directories gitrs hands files to other programs in, like the versions of a
+// file a merge driver merges. Each is new, made with `create_dir` rather than `create_dir_all` so
+// that one somebody else made beforehand (or a symlink in its place) is never used, and only its
+// owner can read it. It is deleted with everything in it when dropped.
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, bail};
+
+// How many names are tried before giving up, should they all be taken
+const ATTEMPTS: usize = 100;
+
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Makes a new directory in the system's temporary directory, named after `prefix`
+    pub fn new(prefix: &str) -> anyhow::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        for _ in 0..ATTEMPTS {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.subsec_nanos());
+            let path = env::temp_dir().join(format!(
+                "gitrs-{}-{}-{}-{:08x}",
+                prefix,
+                process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed),
+                nanos
+            ));
+            match builder.create(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Couldn't create {}", path.display()));
+                }
+            }
+        }
+        bail!("Couldn't create a temporary directory for {}", prefix)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_and_removed() {
+        let (first, second) = (TempDir::new("test").unwrap(), TempDir::new("test").unwrap());
+        assert_ne!(first.path(), second.path());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(first.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        let path = first.path().to_path_buf();
+        fs::write(path.join("file"), "content").unwrap();
+        drop(first);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,171 @@
+// Tracing for debugging and profiling, turned on through environment variables like git's
+// GIT_TRACE. GITRS_TRACE reports what gitrs does, such as the command being run, alias expansions
+// and the programs it starts. GITRS_TRACE_PERFORMANCE reports how long the command took when it
+// finishes, along with a summary of the time spent in each region (reading objects, loading pack
+// indexes, ...). Either variable can be 1 or true to write to stderr, or an absolute path to
+// append to that file instead.
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+enum Destination {
+    Stderr,
+    File(PathBuf),
+}
+
+static TRACE: OnceLock<Option<Destination>> = OnceLock::new();
+static PERFORMANCE: OnceLock<Option<Destination>> = OnceLock::new();
+// The number of times each region was entered and the total time spent in it
+static REGIONS: Mutex<BTreeMap<&'static str, (usize, Duration)>> = Mutex::new(BTreeMap::new());
+
+/// Writes a line to the GITRS_TRACE destination, if tracing is on
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::trace::print(format_args!($($arg)*))
+    };
+}
+pub(crate) use trace;
+
+/// Times the code until it is dropped as part of the region `name`, if performance tracing is on
+pub struct Region {
+    name: &'static str,
+    start: Option<Instant>,
+}
+
+/// Starts timing the region `name`
+pub fn region(name: &'static str) -> Region {
+    Region {
+        name,
+        start: destination(&PERFORMANCE, "GITRS_TRACE_PERFORMANCE").map(|_| Instant::now()),
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let mut regions = REGIONS.lock().unwrap_or_else(|e| e.into_inner());
+            let (count, total) = regions.entry(self.name).or_default();
+            *count += 1;
+            *total += start.elapsed();
+        }
+    }
+}
+
+/// Writes `message` to the GITRS_TRACE destination, if tracing is on; see `trace!`
+pub fn print(message: fmt::Arguments) {
+    if let Some(destination) = destination(&TRACE, "GITRS_TRACE") {
+        write(destination, &format!("trace: {}", message));
+    }
+}
+
+/// Reports the command being run, and when dropped, how long it took followed by the time spent
+/// in each region, if performance tracing is on
+pub struct CommandTrace {
+    args: Vec<String>,
+    start: Instant,
+}
+
+/// Starts tracing the command given by `args`, the arguments after the program name
+pub fn command(args: &[String]) -> CommandTrace {
+    trace!("built-in: gitrs {}", quote(args));
+    CommandTrace {
+        args: args.to_vec(),
+        start: Instant::now(),
+    }
+}
+
+impl Drop for CommandTrace {
+    fn drop(&mut self) {
+        let Some(destination) = destination(&PERFORMANCE, "GITRS_TRACE_PERFORMANCE") else {
+            return;
+        };
+        let seconds = self.start.elapsed().as_secs_f64();
+        write(
+            destination,
+            &format!("performance: {:.9} s: gitrs {}", seconds, quote(&self.args)),
+        );
+        let regions = REGIONS.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, (count, total)) in regions.iter() {
+            write(
+                destination,
+                &format!(
+                    "performance: {:.9} s: {} ({} times)",
+                    total.as_secs_f64(),
+                    name,
+                    count
+                ),
+            );
+        }
+    }
+}
+
+/// Quotes arguments for trace output the way a shell would need them
+pub fn quote(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_=./:@%+,".contains(c));
+            match plain {
+                true => arg.clone(),
+                false => format!("'{}'", arg.replace('\'', "'\\''")),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Reads where the trace `key` turns on goes, once
+fn destination(
+    cell: &'static OnceLock<Option<Destination>>,
+    key: &str,
+) -> Option<&'static Destination> {
+    cell.get_or_init(|| {
+        let value = env::var(key).ok()?;
+        match value.to_lowercase().as_str() {
+            "" | "0" | "false" => None,
+            "1" | "2" | "true" => Some(Destination::Stderr),
+            _ if value.starts_with('/') => Some(Destination::File(PathBuf::from(value))),
+            _ => {
+                eprintln!(
+                    "warning: unknown trace value for '{}': {}\n         \
+                     If you want to trace into a file, then please set {} to an absolute \
+                     pathname (starting with /)",
+                    key, value, key
+                );
+                None
+            }
+        }
+    })
+    .as_ref()
+}
+
+fn write(destination: &Destination, line: &str) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let time = seconds.as_secs() % 86400;
+    let line = format!(
+        "{:02}:{:02}:{:02}.{:06} {}\n",
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        seconds.subsec_micros(),
+        line
+    );
+    // Tracing is best effort, so a destination that can't be written to is ignored
+    let _ = match destination {
+        Destination::Stderr => std::io::stderr().write_all(line.as_bytes()),
+        Destination::File(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes())),
+    };
+}
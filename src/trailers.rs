@@ -0,0 +1,436 @@
+// Parses and edits the trailers at the end of commit messages, eg. `Signed-off-by: A U Thor <a@b>`.
+// The trailer block is the last paragraph of the message (ignoring comments), and only counts as
+// one if every line in it is a trailer or the continuation of one. The subject line is never part
+// of a trailer block.
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailer {
+    pub token: String,
+    // Continuation lines are kept as is, with their newline and leading whitespace
+    pub value: String,
+}
+
+/// Where new trailers are inserted
+#[derive(Debug, Clone, Copy)]
+pub enum Where {
+    End,
+    Start,
+    // After the last, or before the first, trailer with the same token
+    After,
+    Before,
+}
+
+/// What to do when a trailer with the same token already exists
+#[derive(Debug, Clone, Copy)]
+pub enum IfExists {
+    AddIfDifferentNeighbor,
+    AddIfDifferent,
+    Add,
+    Replace,
+    DoNothing,
+}
+
+/// What to do when no trailer with the same token exists
+#[derive(Debug, Clone, Copy)]
+pub enum IfMissing {
+    Add,
+    DoNothing,
+}
+
+pub struct Placement {
+    pub position: Where,
+    pub if_exists: IfExists,
+    pub if_missing: IfMissing,
+}
+
+pub struct Message {
+    // Everything before the trailer block
+    head: String,
+    trailers: Vec<Trailer>,
+    // Comments and blank lines after the trailer block
+    tail: String,
+    // Whether the message already ended with a trailer block
+    has_block: bool,
+}
+
+impl Trailer {
+    /// Parses a `token: value` line, returning None if the line isn't a trailer
+    pub fn parse(line: &str) -> Option<Self> {
+        let (token, value) = line.split_once(':')?;
+        let token = token.trim_end();
+        if token.is_empty() || !token.chars().all(|c| c.is_alphanumeric() || c == '-') {
+            return None;
+        }
+
+        Some(Self {
+            token: token.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    /// Parses a trailer given on the command line, where the value may also follow a `=` and can be
+    /// omitted entirely
+    pub fn parse_arg(arg: &str) -> Result<Self, String> {
+        let (token, value) = match arg.find([':', '=']) {
+            Some(idx) => (&arg[..idx], &arg[idx + 1..]),
+            None => (arg, ""),
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(format!("Empty trailer token in '{}'", arg));
+        }
+
+        Ok(Self {
+            token: token.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    pub fn has_token(&self, token: &str) -> bool {
+        self.token.eq_ignore_ascii_case(token)
+    }
+
+    fn same_as(&self, other: &Trailer) -> bool {
+        self.has_token(&other.token) && self.value == other.value
+    }
+}
+
+impl fmt::Display for Trailer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.token, self.value)
+    }
+}
+
+impl Message {
+    pub fn parse(message: &str) -> Self {
+        let lines: Vec<&str> = message.lines().collect();
+        let is_comment = |line: &str| line.starts_with('#');
+
+        // Skip trailing blank lines and comments
+        let mut end = lines.len();
+        while end > 0 && (lines[end - 1].trim().is_empty() || is_comment(lines[end - 1])) {
+            end -= 1;
+        }
+
+        let mut start = end;
+        while start > 0 && !lines[start - 1].trim().is_empty() {
+            start -= 1;
+        }
+
+        let trailers = (start > 0)
+            .then(|| Self::parse_block(&lines[start..end]))
+            .flatten();
+        let join = |lines: &[&str]| lines.iter().map(|line| format!("{}\n", line)).collect();
+
+        match trailers {
+            Some(trailers) => Self {
+                head: join(&lines[..start]),
+                trailers,
+                tail: join(&lines[end..]),
+                has_block: true,
+            },
+            None => Self {
+                head: join(&lines[..end]),
+                trailers: Vec::new(),
+                tail: join(&lines[end..]),
+                has_block: false,
+            },
+        }
+    }
+
+    // Parses the lines of a paragraph as trailers, returning None if any line isn't one
+    fn parse_block(lines: &[&str]) -> Option<Vec<Trailer>> {
+        let mut trailers: Vec<Trailer> = Vec::new();
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            if line.starts_with([' ', '\t']) {
+                let last = trailers.last_mut()?;
+                last.value.push('\n');
+                last.value.push_str(line.trim_end());
+            } else {
+                trailers.push(Trailer::parse(line)?);
+            }
+        }
+        Some(trailers)
+    }
+
+    pub fn trailers(&self) -> &[Trailer] {
+        &self.trailers
+    }
+
+    /// Adds `trailer`, subject to the rules in `placement`
+    pub fn add(&mut self, trailer: Trailer, placement: &Placement) {
+        let exists = self.trailers.iter().any(|t| t.has_token(&trailer.token));
+        let position = self.insert_position(&trailer.token, placement.position);
+
+        if !exists {
+            if let IfMissing::Add = placement.if_missing {
+                self.trailers.insert(position, trailer);
+            }
+            return;
+        }
+
+        match placement.if_exists {
+            IfExists::AddIfDifferentNeighbor => {
+                let neighbor = match placement.position {
+                    Where::End | Where::After => position.checked_sub(1),
+                    Where::Start | Where::Before => Some(position),
+                };
+                let duplicate = neighbor
+                    .and_then(|idx| self.trailers.get(idx))
+                    .is_some_and(|t| t.same_as(&trailer));
+                if !duplicate {
+                    self.trailers.insert(position, trailer);
+                }
+            }
+            IfExists::AddIfDifferent => {
+                if !self.trailers.iter().any(|t| t.same_as(&trailer)) {
+                    self.trailers.insert(position, trailer);
+                }
+            }
+            IfExists::Add => self.trailers.insert(position, trailer),
+            IfExists::Replace => {
+                self.trailers.retain(|t| !t.has_token(&trailer.token));
+                let position = self.insert_position(&trailer.token, placement.position);
+                self.trailers.insert(position, trailer);
+            }
+            IfExists::DoNothing => {}
+        }
+    }
+
+    fn insert_position(&self, token: &str, position: Where) -> usize {
+        match position {
+            Where::End => self.trailers.len(),
+            Where::Start => 0,
+            Where::After => self
+                .trailers
+                .iter()
+                .rposition(|t| t.has_token(token))
+                .map_or(self.trailers.len(), |idx| idx + 1),
+            Where::Before => self
+                .trailers
+                .iter()
+                .position(|t| t.has_token(token))
+                .unwrap_or(0),
+        }
+    }
+
+    /// Removes trailers with an empty value
+    pub fn trim_empty(&mut self) {
+        self.trailers.retain(|t| !t.value.trim().is_empty());
+    }
+
+    /// Joins continuation lines into a single line
+    pub fn unfold(&mut self) {
+        for trailer in &mut self.trailers {
+            trailer.value = trailer
+                .value
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.head)?;
+        if !self.has_block && !self.trailers.is_empty() {
+            writeln!(f)?;
+        }
+        for trailer in &self.trailers {
+            writeln!(f, "{}", trailer)?;
+        }
+        write!(f, "{}", self.tail)
+    }
+}
+
+impl FromStr for Where {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "end" => Ok(Where::End),
+            "start" => Ok(Where::Start),
+            "after" => Ok(Where::After),
+            "before" => Ok(Where::Before),
+            _ => Err(format!("Unknown value for --where: {}", s)),
+        }
+    }
+}
+
+impl FromStr for IfExists {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "addifdifferentneighbor" => Ok(IfExists::AddIfDifferentNeighbor),
+            "addifdifferent" => Ok(IfExists::AddIfDifferent),
+            "add" => Ok(IfExists::Add),
+            "replace" => Ok(IfExists::Replace),
+            "donothing" => Ok(IfExists::DoNothing),
+            _ => Err(format!("Unknown value for --if-exists: {}", s)),
+        }
+    }
+}
+
+impl FromStr for IfMissing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "add" => Ok(IfMissing::Add),
+            "donothing" => Ok(IfMissing::DoNothing),
+            _ => Err(format!("Unknown value for --if-missing: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "subject\n\nbody text\n\nSigned-off-by: A <a@x>\nAcked-by: B\n";
+
+    // Adds `trailer` to `message` like git interpret-trailers --trailer does
+    fn add(message: &str, trailer: &str, placement: Placement) -> String {
+        let mut message = Message::parse(message);
+        message.add(Trailer::parse_arg(trailer).unwrap(), &placement);
+        message.to_string()
+    }
+
+    fn placement() -> Placement {
+        Placement {
+            position: Where::End,
+            if_exists: IfExists::AddIfDifferentNeighbor,
+            if_missing: IfMissing::Add,
+        }
+    }
+
+    // The expected messages are what git 2.39 interpret-trailers gives
+    #[test]
+    fn placements() {
+        let block = |trailers: &str| format!("subject\n\nbody text\n\n{}", trailers);
+        let unchanged = block("Signed-off-by: A <a@x>\nAcked-by: B\n");
+        for (trailer, placement, expected) in [
+            (
+                "Acked-by: C",
+                placement(),
+                block("Signed-off-by: A <a@x>\nAcked-by: B\nAcked-by: C\n"),
+            ),
+            ("Acked-by: B", placement(), unchanged.clone()),
+            (
+                "Acked-by: C",
+                Placement {
+                    position: Where::Start,
+                    ..placement()
+                },
+                block("Acked-by: C\nSigned-off-by: A <a@x>\nAcked-by: B\n"),
+            ),
+            (
+                "signed-off-by: D",
+                Placement {
+                    position: Where::After,
+                    ..placement()
+                },
+                block("Signed-off-by: A <a@x>\nsigned-off-by: D\nAcked-by: B\n"),
+            ),
+            (
+                "Acked-by: E",
+                Placement {
+                    position: Where::Before,
+                    ..placement()
+                },
+                block("Signed-off-by: A <a@x>\nAcked-by: E\nAcked-by: B\n"),
+            ),
+            (
+                "Acked-by: F",
+                Placement {
+                    if_exists: IfExists::Replace,
+                    ..placement()
+                },
+                block("Signed-off-by: A <a@x>\nAcked-by: F\n"),
+            ),
+            (
+                "Signed-off-by: A <a@x>",
+                Placement {
+                    if_exists: IfExists::AddIfDifferent,
+                    ..placement()
+                },
+                unchanged.clone(),
+            ),
+            (
+                "Signed-off-by: A <a@x>",
+                Placement {
+                    position: Where::Start,
+                    if_exists: IfExists::Add,
+                    ..placement()
+                },
+                block("Signed-off-by: A <a@x>\nSigned-off-by: A <a@x>\nAcked-by: B\n"),
+            ),
+            (
+                "Acked-by: G",
+                Placement {
+                    if_exists: IfExists::DoNothing,
+                    ..placement()
+                },
+                unchanged.clone(),
+            ),
+            (
+                "Reviewed-by: H",
+                Placement {
+                    if_missing: IfMissing::DoNothing,
+                    ..placement()
+                },
+                unchanged.clone(),
+            ),
+        ] {
+            assert_eq!(add(MESSAGE, trailer, placement), expected, "{}", trailer);
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        let reviewed = |message| add(message, "Reviewed-by: H", placement());
+        assert_eq!(
+            reviewed("subject\n\nbody\n"),
+            "subject\n\nbody\n\nReviewed-by: H\n"
+        );
+        assert_eq!(
+            reviewed("subject\n\nnot: a trailer\nplain line\n"),
+            "subject\n\nnot: a trailer\nplain line\n\nReviewed-by: H\n"
+        );
+        assert_eq!(
+            reviewed("subject\n\nFixes: 123\n  continued here\nAcked-by: B\n# comment\n\n"),
+            "subject\n\nFixes: 123\n  continued here\nAcked-by: B\nReviewed-by: H\n# comment\n\n"
+        );
+        // The subject is never a trailer
+        assert_eq!(
+            reviewed("Fixes: subject only\n"),
+            "Fixes: subject only\n\nReviewed-by: H\n"
+        );
+    }
+
+    #[test]
+    fn unfold() {
+        let mut message = Message::parse("subject\n\nFixes: 123\n  continued here\nAcked-by:\n");
+        message.unfold();
+        let trailers: Vec<String> = message.trailers().iter().map(Trailer::to_string).collect();
+        assert_eq!(trailers, ["Fixes: 123 continued here", "Acked-by: "]);
+        message.trim_empty();
+        assert_eq!(message.trailers().len(), 1);
+    }
+
+    #[test]
+    fn arguments() {
+        let trailer = Trailer::parse_arg("Acked-by = B ").unwrap();
+        assert_eq!(
+            (trailer.token.as_str(), trailer.value.as_str()),
+            ("Acked-by", "B")
+        );
+        assert_eq!(Trailer::parse_arg("Acked-by").unwrap().value, "");
+        assert!(Trailer::parse_arg(": B").is_err());
+        assert!(Trailer::parse("not a: trailer").is_none());
+    }
+}
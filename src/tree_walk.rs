@@ -0,0 +1,147 @@
+// Walks one or more trees side by side in git's tree order, pairing up the entries found at the
+// same path in each. Walking recursively enters subtrees instead of reporting them; either way,
+// subtrees that are identical on every side, or that can't hold any of the paths selected by the
+// pathspec, are skipped without being read.
+use anyhow::bail;
+
+use crate::object::tree::{Leaf, Tree};
+use crate::object::{GitrsObject, ObjectType};
+use crate::pathspec::Pathspec;
+use crate::repository::Repository;
+
+#[derive(Default)]
+pub struct TreeWalkOptions {
+    /// Enter subtrees, reporting the entries within them rather than the subtrees themselves
+    pub recursive: bool,
+    /// Leave out entries (including whole subtrees) that are the same in every tree
+    pub skip_identical: bool,
+    /// Only walk the paths it selects, skipping subtrees that can't contain any
+    pub pathspec: Pathspec,
+}
+
+pub struct WalkEntry {
+    /// Path relative to the walked trees, `/` separated
+    pub path: String,
+    /// The mode and hash found at the path in each tree, or None where it is missing
+    pub entries: Vec<Option<(String, String)>>,
+}
+
+pub struct TreeWalk<'a> {
+    repository: &'a Repository,
+    options: TreeWalkOptions,
+    // The directories being walked, innermost last, with the records each tree has left in them
+    // in reverse tree order
+    stack: Vec<(String, Vec<Vec<Leaf>>)>,
+}
+
+impl<'a> TreeWalk<'a> {
+    pub fn new(repository: &'a Repository, trees: &[&Tree], options: TreeWalkOptions) -> Self {
+        let sides = trees
+            .iter()
+            .map(|tree| sorted(tree.records.clone()))
+            .collect();
+        Self {
+            repository,
+            options,
+            stack: vec![(String::new(), sides)],
+        }
+    }
+
+    // Returns true if `path` is selected by the pathspec. Subtrees are also wanted when something
+    // below them could be, which is how they are skipped without being read.
+    fn wanted(&self, path: &str, is_tree: bool) -> bool {
+        let pathspec = &self.options.pathspec;
+        match (is_tree, self.options.recursive) {
+            (false, _) => pathspec.matches(path, false),
+            (true, true) => pathspec.matches(path, true) || pathspec.could_match_below(path),
+            (true, false) => pathspec.matches(path, true) || pathspec.leads_below(path),
+        }
+    }
+
+    fn next_entry(&mut self) -> anyhow::Result<Option<WalkEntry>> {
+        loop {
+            let Some((prefix, sides)) = self.stack.last_mut() else {
+                return Ok(None);
+            };
+            let Some(key) = sides
+                .iter()
+                .filter_map(|records| records.last().map(tree_order_key))
+                .min()
+            else {
+                self.stack.pop();
+                continue;
+            };
+
+            let leaves: Vec<Option<Leaf>> = sides
+                .iter_mut()
+                .map(|records| records.pop_if(|leaf| tree_order_key(leaf) == key))
+                .collect();
+            let path = format!("{}{}", prefix, key.trim_end_matches('/'));
+            let is_tree = key.ends_with('/');
+
+            if !self.wanted(&path, is_tree) {
+                continue;
+            }
+            let identical = leaves.len() > 1
+                && leaves.windows(2).all(|pair| match pair {
+                    [Some(a), Some(b)] => a.file_mode == b.file_mode && a.hash == b.hash,
+                    _ => false,
+                });
+            if self.options.skip_identical && identical {
+                continue;
+            }
+
+            if is_tree && self.options.recursive {
+                let subtrees = leaves
+                    .iter()
+                    .map(|leaf| match leaf {
+                        Some(leaf) => {
+                            read_tree(self.repository, leaf).map(|tree| sorted(tree.records))
+                        }
+                        None => Ok(Vec::new()),
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                self.stack.push((format!("{}/", path), subtrees));
+                continue;
+            }
+
+            return Ok(Some(WalkEntry {
+                path,
+                entries: leaves
+                    .into_iter()
+                    .map(|leaf| leaf.map(|leaf| (leaf.file_mode, leaf.hash)))
+                    .collect(),
+            }));
+        }
+    }
+}
+
+impl Iterator for TreeWalk<'_> {
+    type Item = anyhow::Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+// The records in reverse tree order, so that the next one can be popped off the end
+fn sorted(mut records: Vec<Leaf>) -> Vec<Leaf> {
+    records.sort_by_key(|leaf| std::cmp::Reverse(tree_order_key(leaf)));
+    records
+}
+
+// Git orders tree entries as if directory names ended with a `/`
+fn tree_order_key(leaf: &Leaf) -> String {
+    let mut key = leaf.path.to_string_lossy().into_owned();
+    if Leaf::get_type_from_mode(&leaf.file_mode) == ObjectType::Tree {
+        key.push('/');
+    }
+    key
+}
+
+fn read_tree(repository: &Repository, leaf: &Leaf) -> anyhow::Result<Tree> {
+    match GitrsObject::read(repository, &leaf.hash)? {
+        GitrsObject::TreeObject(tree) => Ok(tree),
+        _ => bail!("Expected a tree object: {}", leaf.hash),
+    }
+}
@@ -0,0 +1,94 @@
+// Unpacks a pack into loose objects, like git unpack-objects. A pack received whole can be kept as
+// it is, but one with only a few objects is better unpacked, which is what git does with packs of
+// fewer objects than transfer.unpackLimit. Objects the repository has already are left alone.
+// Checking strictly, every object must be well-formed and everything it links to must be in the
+// pack or the repository, with the type the link gives it, and nothing is written unless all of
+// the pack passes.
+use std::collections::HashMap;
+
+use anyhow::{Context, anyhow, ensure};
+
+use crate::fsck;
+use crate::object::{GitrsObject, ObjectType};
+use crate::pack::{self, PackObject};
+use crate::repository::Repository;
+
+#[derive(Default)]
+pub struct UnpackOptions {
+    /// Check the pack without writing anything
+    pub dry_run: bool,
+    /// Check that the objects are well-formed and that what they link to exists
+    pub strict: bool,
+}
+
+pub struct Unpacked {
+    /// How many objects the pack has
+    pub objects: usize,
+    /// How many of them were written, those the repository didn't have
+    pub written: usize,
+}
+
+/// Unpacks `pack`, a whole pack as it is sent, into loose objects as `options` says
+pub fn unpack(
+    repository: &Repository,
+    pack: &[u8],
+    options: &UnpackOptions,
+) -> anyhow::Result<Unpacked> {
+    let objects = pack::parse(repository, pack)?;
+    if options.strict {
+        check(repository, &objects)?;
+    }
+
+    let mut written = 0;
+    for object in &objects {
+        if options.dry_run || GitrsObject::exists(repository, &object.hash)? {
+            continue;
+        }
+        GitrsObject::write_data(repository, object.object_type.clone(), &object.data)?;
+        written += 1;
+    }
+    Ok(Unpacked {
+        objects: objects.len(),
+        written,
+    })
+}
+
+// Checks each object, and that what it links to is in the pack or the repository
+fn check(repository: &Repository, objects: &[PackObject]) -> anyhow::Result<()> {
+    let packed: HashMap<&str, &ObjectType> = objects
+        .iter()
+        .map(|object| (object.hash.as_str(), &object.object_type))
+        .collect();
+    // The types of the objects outside the pack linked to so far
+    let mut outside: HashMap<String, ObjectType> = HashMap::new();
+    for object in objects {
+        let links = fsck::check(&object.object_type, &object.data)
+            .map_err(|e| anyhow!("Bad {} {}: {}", object.object_type, object.hash, e))?;
+        for (hash, expected) in links {
+            let actual = match (packed.get(hash.as_str()), outside.get(&hash)) {
+                (Some(&object_type), _) | (None, Some(object_type)) => object_type.clone(),
+                (None, None) => {
+                    let (object_type, _) =
+                        GitrsObject::read_data(repository, &hash).with_context(|| {
+                            format!(
+                                "{} {} links to missing object {}",
+                                object.object_type, object.hash, hash
+                            )
+                        })?;
+                    outside.insert(hash.clone(), object_type.clone());
+                    object_type
+                }
+            };
+            ensure!(
+                actual == expected,
+                "{} {} links to {} as a {}, but it is a {}",
+                object.object_type,
+                object.hash,
+                hash,
+                expected,
+                actual
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+// Finds the diff driver for a path, which the path's `diff` attribute names, and reads the
+// driver's settings from the diff.<driver>.* config, over those of the builtin driver of that
+// name if there is one. A driver can have files converted to text before they are compared, and
+// have an external program show their changes instead. Either program is run through the shell
+// from the top of the worktree and given each side as a file: the worktree's own copy where that
+// is what's compared, or else a temporary one. Its funcname patterns pick the line a hunk header
+// shows: the first of them to match a line decides, a match of a pattern starting with `!`
+// meaning it isn't one, and otherwise what the pattern's first group matched, or all of its
+// match, is shown.
+mod builtin;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, bail};
+
+use crate::attributes::{AttrValue, Attributes};
+use crate::config::Config;
+use crate::notes::Notes;
+use crate::object::tree::SYMLINK_MODE;
+use crate::refs::ZERO_HASH;
+use crate::regex::Regex;
+use crate::repository::Repository;
+use crate::temporary::TempDir;
+
+/// The settings of a diff driver
+pub struct Driver {
+    pub name: String,
+    /// What counts as a word for --word-diff (diff.<driver>.wordRegex)
+    pub word_regex: Option<Vec<u8>>,
+    /// The patterns finding the line hunk headers show, one to a line
+    /// (diff.<driver>.xfuncname), and whether they ignore case
+    pub funcname: Option<(Vec<u8>, bool)>,
+    /// The program converting files to the text compared (diff.<driver>.textconv)
+    pub textconv: Option<String>,
+    /// Whether text converted from content in the repository is kept in
+    /// refs/notes/textconv/<driver> (diff.<driver>.cachetextconv)
+    pub cache_textconv: bool,
+    /// The external program showing the changes (diff.<driver>.command)
+    pub command: Option<String>,
+}
+
+/// One side of a change, as the programs run for it see it
+pub struct Side<'a> {
+    pub path: &'a str,
+    pub mode: &'a str,
+    /// The hash of the content, or ZERO_HASH for the worktree's
+    pub hash: &'a str,
+    /// The content, or None if the file is missing on this side
+    pub content: Option<&'a [u8]>,
+}
+
+/// Compiled funcname patterns, each with whether it is negated
+pub struct Funcname(Vec<(Regex, bool)>);
+
+// A side of a change as a file a program can read
+struct DiffFile {
+    path: PathBuf,
+    hash: String,
+    mode: String,
+    // The directory a temporary file is in
+    directory: Option<TempDir>,
+}
+
+/// Looks up the driver the `diff` attribute of `path` names, if it names one
+pub fn driver(
+    config: &Config,
+    attributes: &mut Attributes,
+    path: &str,
+) -> anyhow::Result<Option<Driver>> {
+    let Some(AttrValue::Value(name)) = attributes.check(path).get("diff").cloned() else {
+        return Ok(None);
+    };
+    let get = |setting: &str| {
+        config
+            .get(&format!("diff.{}.{}", name, setting))
+            .map(String::from)
+    };
+    let builtin = builtin::find(&name);
+    Ok(Some(Driver {
+        word_regex: get("wordRegex")
+            .map(String::into_bytes)
+            .or_else(|| builtin.map(|builtin| builtin.word_regex())),
+        funcname: get("xfuncname")
+            .map(|patterns| (patterns.into_bytes(), false))
+            .or_else(|| {
+                builtin.map(|builtin| (builtin.funcname.join(&b'\n'), builtin.ignore_case))
+            }),
+        textconv: get("textconv"),
+        cache_textconv: config
+            .get_bool(&format!("diff.{}.cachetextconv", name))?
+            .unwrap_or(false),
+        command: get("command"),
+        name,
+    }))
+}
+
+impl Driver {
+    /// Compiles the driver's funcname patterns, if it has any
+    pub fn funcname(&self) -> anyhow::Result<Option<Funcname>> {
+        self.funcname
+            .as_ref()
+            .map(|(patterns, ignore_case)| Funcname::new(patterns, *ignore_case))
+            .transpose()
+    }
+
+    /// Converts `side` to text with the driver's textconv program, or returns None if it has none
+    /// or the file is missing
+    pub fn convert(
+        &self,
+        repository: &Repository,
+        config: &Config,
+        side: &Side,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let (Some(program), Some(_)) = (&self.textconv, side.content) else {
+            return Ok(None);
+        };
+        // Conversions are only cached for content the repository has, under the content's hash
+        let mut cache = match self.cache_textconv && side.hash != ZERO_HASH {
+            true => {
+                let ref_name = format!("refs/notes/textconv/{}", self.name);
+                Some(Notes::load_cache(repository, config, &ref_name, program)?)
+            }
+            false => None,
+        };
+        if let Some(cache) = &cache
+            && let Some(text) = cache.get_data(side.hash)?
+        {
+            return Ok(Some(text));
+        }
+
+        let file = DiffFile::new(side)?;
+        let Some(text) = run(repository, program, &[&file.path], &[])? else {
+            bail!("unable to read files to diff");
+        };
+        if let Some(cache) = &mut cache {
+            cache.set_data(side.hash, &text)?;
+            cache.commit_cache(config, program)?;
+        }
+        Ok(Some(text))
+    }
+}
+
+impl Funcname {
+    /// Compiles funcname patterns, one to a line
+    pub fn new(patterns: &[u8], ignore_case: bool) -> anyhow::Result<Self> {
+        let patterns = patterns
+            .split(|&b| b == b'\n')
+            .map(|pattern| {
+                let (pattern, negated) = match pattern.strip_prefix(b"!") {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                let regex = Regex::new(pattern, ignore_case).with_context(|| {
+                    format!(
+                        "Invalid regexp to look for hunk header: {}",
+                        String::from_utf8_lossy(pattern)
+                    )
+                })?;
+                Ok((regex, negated))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Funcname(patterns))
+    }
+
+    /// The part of `line` a hunk header shows, if it is a function line
+    pub fn find<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let (regex, negated) = self
+            .0
+            .iter()
+            .find(|(regex, _)| regex.find(line).is_some())?;
+        if *negated {
+            return None;
+        }
+        let groups = regex.captures(line)?;
+        let found = groups
+            .get(1)
+            .cloned()
+            .flatten()
+            .or_else(|| groups[0].clone())?;
+        Some(&line[found])
+    }
+}
+
+/// Shows the change to `path` with the external diff program `program`, which is given the path
+/// and the file, hash and mode of each side, returning what it printed. It is told this is change
+/// `counter` (from 1) of `total` through GIT_DIFF_PATH_COUNTER and GIT_DIFF_PATH_TOTAL.
+pub fn external_diff(
+    repository: &Repository,
+    program: &str,
+    path: &str,
+    [old, new]: [&Side; 2],
+    counter: usize,
+    total: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let (old, new) = (DiffFile::new(old)?, DiffFile::new(new)?);
+    let args = [
+        Path::new(path),
+        &old.path,
+        Path::new(&old.hash),
+        Path::new(&old.mode),
+        &new.path,
+        Path::new(&new.hash),
+        Path::new(&new.mode),
+    ];
+    let env = [
+        ("GIT_DIFF_PATH_COUNTER", counter.to_string()),
+        ("GIT_DIFF_PATH_TOTAL", total.to_string()),
+    ];
+    run(repository, program, &args, &env)?
+        .with_context(|| format!("external diff died, stopping at {}", path))
+}
+
+// Runs `program` through the shell with `args` from the top of the worktree, returning what it
+// printed, or None if it failed
+fn run(
+    repository: &Repository,
+    program: &str,
+    args: &[&Path],
+    env: &[(&str, String)],
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", program))
+        .arg(program)
+        .args(args)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .current_dir(&repository.worktree)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Couldn't run {}", program))?;
+    Ok(output.status.success().then_some(output.stdout))
+}
+
+impl DiffFile {
+    // A missing file is /dev/null, with "." for its hash and mode. Worktree files other than
+    // symlinks are read where they are; anything else is written to a temporary file named after
+    // it in a directory of its own, so that programs can tell its type from its extension.
+    fn new(side: &Side) -> anyhow::Result<Self> {
+        let Some(content) = side.content else {
+            return Ok(Self {
+                path: PathBuf::from("/dev/null"),
+                hash: ".".to_string(),
+                mode: ".".to_string(),
+                directory: None,
+            });
+        };
+        let mut file = Self {
+            path: PathBuf::from(side.path),
+            hash: side.hash.to_string(),
+            mode: side.mode.to_string(),
+            directory: None,
+        };
+        if side.hash == ZERO_HASH && side.mode != SYMLINK_MODE {
+            return Ok(file);
+        }
+
+        let name = Path::new(side.path)
+            .file_name()
+            .map_or_else(Default::default, |name| name.to_string_lossy());
+        let directory = TempDir::new("blob")?;
+        file.path = directory.path().join(&*name);
+        fs::write(&file.path, content)
+            .with_context(|| format!("Couldn't write {}", file.path.display()))?;
+        file.directory = Some(directory);
+        Ok(file)
+    }
+}
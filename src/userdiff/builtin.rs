@@ -0,0 +1,261 @@
+// The diff drivers git has built in for common languages, which a `diff` attribute can name
+// without any configuration: the patterns that find the line a hunk header shows, and what counts
+// as a word in the language. These are git's own patterns.
+
+/// A builtin driver's patterns
+pub struct Builtin {
+    pub name: &'static str,
+    /// The funcname patterns, each a regex of its own
+    pub funcname: &'static [&'static [u8]],
+    pub ignore_case: bool,
+    // What counts as a word, besides any other non-space or UTF-8 character
+    words: &'static [u8],
+}
+
+/// Finds the builtin driver called `name`
+pub fn find(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
+
+impl Builtin {
+    pub fn word_regex(&self) -> Vec<u8> {
+        [self.words, b"|[^[:space:]]|[\xc0-\xff][\x80-\xbf]+"].concat()
+    }
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "ada",
+        funcname: &[
+            b"!^(.*[ \t])?(is[ \t]+new|renames|is[ \t]+separate)([ \t].*)?$",
+            b"!^[ \t]*with[ \t].*$",
+            b"^[ \t]*((procedure|function)[ \t]+.*)$",
+            b"^[ \t]*((package|protected|task)[ \t]+.*)$",
+        ],
+        ignore_case: true,
+        words: b"[a-zA-Z][a-zA-Z0-9_]*\
+            |[-+]?[0-9][0-9#_.aAbBcCdDeEfF]*([eE][+-]?[0-9_]+)?|=>|\\.\\.|\\*\\*|:=|/=|>=|<=|<<\
+            |>>|<>",
+    },
+    Builtin {
+        name: "bash",
+        funcname: &[b"^[ \t]*(([a-zA-Z_][a-zA-Z0-9_]*[ \t]*\\([ \t]*\\))\
+            |(function[ \t]+[a-zA-Z_][a-zA-Z0-9_]*(([ \t]*\\([ \t]*\\))|([ \t]+)))[ \t]*(\\{\
+            |\\(\\(?|\\[\\[))"],
+        ignore_case: false,
+        words: b"[^ \t]+",
+    },
+    Builtin {
+        name: "bibtex",
+        funcname: &[b"(@[a-zA-Z]{1,}[ \t]*\\{{0,1}[ \t]*[^ \t\"@',\\#}{~%]*).*$"],
+        ignore_case: false,
+        words: b"[={}\"]|[^={}\" \t]+",
+    },
+    Builtin {
+        name: "cpp",
+        funcname: &[
+            b"!^[ \t]*[A-Za-z_][A-Za-z_0-9]*:[[:space:]]*($|/[/*])",
+            b"^((::[[:space:]]*)?[A-Za-z_].*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[0-9][0-9.]*([Ee][-+]?[0-9]+)?[fFlLuU]*\
+            |0[xXbB][0-9a-fA-F]+[lLuU]*|\\.[0-9][0-9]*([Ee][-+]?[0-9]+)?[fFlL]?|[-+*/<>%&^|=!]=\
+            |--|\\+\\+|<<=?|>>=?|&&|\\|\\||::|->\\*?|\\.\\*|<=>",
+    },
+    Builtin {
+        name: "csharp",
+        funcname: &[
+            b"!^[ \t]*(do|while|for|if|else|instanceof|new|return|switch|case|throw|catch\
+            |using)",
+            b"^[ \t]*(((static|public|internal|private|protected|new|virtual|sealed|override\
+            |unsafe\
+            |async)[ \t]+)*[][<>@.~_[:alnum:]]+[ \t]+[<>@._[:alnum:]]+[ \t]*\\(.*\\))[ \t]*$",
+            b"^[ \t]*(((static|public|internal|private|protected|new|virtual|sealed|override\
+            |unsafe)[ \t]+)*[][<>@.~_[:alnum:]]+[ \t]+[@._[:alnum:]]+)[ \t]*$",
+            b"^[ \t]*(((static|public|internal|private|protected|new|unsafe|sealed|abstract\
+            |partial)[ \t]+)*(class|enum|interface|struct|record)[ \t]+.*)$",
+            b"^[ \t]*(namespace[ \t]+.*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+[fFlL]?|0[xXbB]?[0-9a-fA-F]+[lL]?\
+            |[-+*/<>%&^|=!]=|--|\\+\\+|<<=?|>>=?|&&|\\|\\||::|->",
+    },
+    Builtin {
+        name: "css",
+        funcname: &[b"![:;][[:space:]]*$", b"^[:[@.#]?[_a-z0-9].*$"],
+        ignore_case: true,
+        words: b"-?[_a-zA-Z][-_a-zA-Z0-9]*|-?[0-9]+|\\#[0-9a-fA-F]+",
+    },
+    Builtin {
+        name: "dts",
+        funcname: &[b"!;", b"!=", b"^[ \t]*((/[ \t]*\\{|&?[a-zA-Z_]).*)"],
+        ignore_case: false,
+        words: b"[a-zA-Z0-9,._+?#-]+|[-+*/%&^|!~]|>>|<<|&&|\\|\\|",
+    },
+    Builtin {
+        name: "elixir",
+        funcname: &[b"^[ \t]*((def(macro|module|impl|protocol|p)?|test)[ \t].*)$"],
+        ignore_case: false,
+        words: b"[@:]?[a-zA-Z0-9@_?!]+|[-+]?0[xob][0-9a-fA-F]+\
+            |[-+]?[0-9][0-9_.]*([eE][-+]?[0-9_]+)?|:?(\\+\\+|--|\\.\\.|~~~|<>|\\^\\^\\^|<?\\|>\
+            |<<<?|>?>>|<<?~|~>?>|<~>|<=|>=|===?|!==?|=~|&&&?|\\|\\|\\|?|=>|<-|\\\\\\\\|->)\
+            |:?%[A-Za-z0-9_.]\\{\\}?",
+    },
+    Builtin {
+        name: "fortran",
+        funcname: &[
+            b"!^([C*]|[ \t]*!)",
+            b"!^[ \t]*MODULE[ \t]+PROCEDURE[ \t]",
+            b"^[ \t]*((END[ \t]+)?(PROGRAM|MODULE|BLOCK[ \t]+DATA\
+            |([^!'\" \t]+[ \t]+)*(SUBROUTINE|FUNCTION))[ \t]+[A-Z].*)$",
+        ],
+        ignore_case: true,
+        words: b"[a-zA-Z][a-zA-Z0-9_]*|\\.([Ee][Qq]|[Nn][Ee]|[Gg][TtEe]|[Ll][TtEe]\
+            |[Tt][Rr][Uu][Ee]|[Ff][Aa][Ll][Ss][Ee]|[Aa][Nn][Dd]|[Oo][Rr]|[Nn]?[Ee][Qq][Vv]\
+            |[Nn][Oo][Tt])\\.\
+            |[-+]?[0-9.]+([AaIiDdEeFfLlTtXx][Ss]?[-+]?[0-9.]*)?(_[a-zA-Z0-9][a-zA-Z0-9_]*)?|//\
+            |\\*\\*|::|[/<>=]=",
+    },
+    Builtin {
+        name: "fountain",
+        funcname: &[b"^((\\.[^.]|(int|ext|est|int\\.?/ext|i/e)[. ]).*)$"],
+        ignore_case: true,
+        words: b"[^ \t-]+",
+    },
+    Builtin {
+        name: "golang",
+        funcname: &[
+            b"^[ \t]*(func[ \t]*.*(\\{[ \t]*)?)",
+            b"^[ \t]*(type[ \t].*(struct|interface)[ \t]*(\\{[ \t]*)?)",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.eE]+i?|0[xX]?[0-9a-fA-F]+i?|[-+*/<>%&^\
+            |=!:]=|--|\\+\\+|<<=?|>>=?|&\\^=?|&&|\\|\\||<-|\\.{3}",
+    },
+    Builtin {
+        name: "html",
+        funcname: &[b"^[ \t]*(<[Hh][1-6]([ \t].*)?>.*)$"],
+        ignore_case: false,
+        words: b"[^<>= \t]+",
+    },
+    Builtin {
+        name: "java",
+        funcname: &[
+            b"!^[ \t]*(catch|do|for|if|instanceof|new|return|switch|throw|while)",
+            b"^[ \t]*(([a-z]+[ \t]+)*(class|enum\
+            |interface)[ \t]+[A-Za-z][A-Za-z0-9_$]*[ \t]+.*)$",
+            b"^[ \t]*(([A-Za-z_<>&][][?&<>.,A-Za-z_0-9]*[ \t]+)+[A-Za-z_][A-Za-z_0-9]*[ \t]*\\\
+            ([^;]*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+[fFlL]?|0[xXbB]?[0-9a-fA-F]+[lL]?\
+            |[-+*/<>%&^|=!]=|--|\\+\\+|<<=?|>>>?=?|&&|\\|\\|",
+    },
+    Builtin {
+        name: "kotlin",
+        funcname: &[b"^[ \t]*(([a-z]+[ \t]+)*(fun|class|interface)[ \t]+.*)$"],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|0[xXbB][0-9a-fA-F_]+[lLuU]*\
+            |[0-9][0-9_]*([.][0-9_]*)?([Ee][-+]?[0-9]+)?[fFlLuU]*\
+            |[.][0-9][0-9_]*([Ee][-+]?[0-9]+)?[fFlLuU]?|[-+*/<>%&^|=!]==?|--|\\+\\+|<<=|>>=|&&\
+            |\\|\\||->|\\.\\*|!!|[?:.][.:]",
+    },
+    Builtin {
+        name: "markdown",
+        funcname: &[b"^ {0,3}#{1,6}[ \t].*"],
+        ignore_case: false,
+        words: b"[^<>= \t]+",
+    },
+    Builtin {
+        name: "matlab",
+        funcname: &[b"^[[:space:]]*((classdef|function)[[:space:]].*)$|^(%%%?|##)[[:space:]].*$"],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+|[=~<>]=|\\.[*/\\^']|\\|\\||&&",
+    },
+    Builtin {
+        name: "objc",
+        funcname: &[
+            b"!^[ \t]*(do|for|if|else|return|switch|while)",
+            b"^[ \t]*([-+][ \t]*\\([ \t]*[A-Za-z_][A-Za-z_0-9* \t]*\\)[ \t]*[A-Za-z_].*)$",
+            b"^[ \t]*(([A-Za-z_][A-Za-z_0-9]*[ \t]+)+[A-Za-z_][A-Za-z_0-9]*[ \t]*\\([^;]*)$",
+            b"^(@(implementation|interface|protocol)[ \t].*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+[fFlL]?|0[xXbB]?[0-9a-fA-F]+[lL]?\
+            |[-+*/<>%&^|=!]=|--|\\+\\+|<<=?|>>=?|&&|\\|\\||::|->",
+    },
+    Builtin {
+        name: "pascal",
+        funcname: &[
+            b"^(((class[ \t]+)?(procedure|function)|constructor|destructor|interface\
+            |implementation|initialization|finalization)[ \t]*.*)$",
+            b"^(.*=[ \t]*(class|record).*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+|0[xXbB]?[0-9a-fA-F]+|<>|<=|>=|:=\
+            |\\.\\.",
+    },
+    Builtin {
+        name: "perl",
+        funcname: &[
+            b"^package .*",
+            b"^sub [[:alnum:]_':]+[ \t]*(\\([^)]*\\)[ \t]*)?(:[^;#]*)?(\\{[ \t]*)?(#.*)?$",
+            b"^(BEGIN|END|INIT|CHECK|UNITCHECK|AUTOLOAD|DESTROY)[ \t]*(\\{[ \t]*)?(#.*)?$",
+            b"^=head[0-9] .*",
+        ],
+        ignore_case: false,
+        words: b"[[:alpha:]_'][[:alnum:]_']*|0[xb]?[0-9a-fA-F_]*\
+            |[0-9a-fA-F_]+(\\.[0-9a-fA-F_]+)?([eE][-+]?[0-9_]+)?|=>\
+            |-[rwxoRWXOezsfdlpSugkbctTBMAC>]|~~|::|&&=|\\|\\|=|//=|\\*\\*=|&&|\\|\\||//|\\+\\+\
+            |--|\\*\\*|\\.\\.\\.?|[-+*/%.^&<>=!|]=|=~|!~|<<|<>|<=>|>>",
+    },
+    Builtin {
+        name: "php",
+        funcname: &[
+            b"^[\t ]*(((public|protected|private|static|abstract|final)[\t ]+)*function.*)$",
+            b"^[\t ]*((((final|abstract)[\t ]+)?class|enum|interface|trait).*)$",
+        ],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+|0[xXbB]?[0-9a-fA-F]+|[-+*/<>%&^\
+            |=!.]=|--|\\+\\+|<<=?|>>=?|===|&&|\\|\\||::|->",
+    },
+    Builtin {
+        name: "python",
+        funcname: &[b"^[ \t]*((class|(async[ \t]+)?def)[ \t].*)$"],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+[jJlL]?|0[xX]?[0-9a-fA-F]+[lL]?\
+            |[-+*/<>%&^|=!]=|//=?|<<=?|>>=?|\\*\\*=?",
+    },
+    Builtin {
+        name: "ruby",
+        funcname: &[b"^[ \t]*((class|module|def)[ \t].*)$"],
+        ignore_case: false,
+        words: b"(@|@@|\\$)?[a-zA-Z_][a-zA-Z0-9_]*|[-+0-9.e]+|0[xXbB]?[0-9a-fA-F]+\
+            |\\?(\\\\C-)?(\\\\M-)?.|//=?|[-+*/<>%&^|=!]=|<<=?|>>=?|===|\\.{1,3}|::|[!=]~",
+    },
+    Builtin {
+        name: "rust",
+        funcname: &[b"^[\t ]*((pub(\\([^\\)]+\\))?[\t ]+)?((async|const|unsafe\
+            |extern([\t ]+\"[^\"]+\"))[\t ]+)?(struct|enum|union|mod|trait|fn|impl\
+            |macro_rules!)[< \t]+[^;]*)$"],
+        ignore_case: false,
+        words: b"[a-zA-Z_][a-zA-Z0-9_]*\
+            |[0-9][0-9_a-fA-Fiosuxz]*(\\.([0-9]*[eE][+-]?)?[0-9_fF]*)?|[-+*\\/<>%&^|=!:]=|<<=?\
+            |>>=?|&&|\\|\\||->|=>|\\.{2}=|\\.{3}|::",
+    },
+    Builtin {
+        name: "scheme",
+        funcname: &[
+            b"^[\t ]*(\\(((define|def(struct|syntax|class|method|rules|record|proto\
+            |alias)?)[-*/ \t]|(library|module|struct|class)[*+ \t]).*)$",
+        ],
+        ignore_case: false,
+        words: b"\\|([^\\\\]*)\\||([^][)(}{[ \t])+",
+    },
+    Builtin {
+        name: "tex",
+        funcname: &[b"^(\\\\((sub)*section|chapter|part)\\*{0,1}\\{.*)$"],
+        ignore_case: false,
+        words: b"\\\\[a-zA-Z@]+|\\\\.|[a-zA-Z0-9\x80-\xff]+",
+    },
+];
@@ -0,0 +1,46 @@
+// Logical variables, as printed by var: the identities new commits would be recorded with, and the
+// editor and pager commands to run, each resolved from the environment and config the way git does
+use std::env;
+
+use crate::config::Config;
+use crate::ident::Ident;
+
+/// Every variable, in the order var -l lists them
+pub const VARIABLES: [&str; 5] = [
+    "GIT_COMMITTER_IDENT",
+    "GIT_AUTHOR_IDENT",
+    "GIT_EDITOR",
+    "GIT_SEQUENCE_EDITOR",
+    "GIT_PAGER",
+];
+
+/// Returns the value of the variable `name`, or None if there is no such variable
+pub fn read(config: &Config, name: &str) -> anyhow::Result<Option<String>> {
+    Ok(Some(match name {
+        "GIT_COMMITTER_IDENT" => Ident::committer(config)?.to_string(),
+        "GIT_AUTHOR_IDENT" => Ident::author(config)?.to_string(),
+        "GIT_EDITOR" => editor(config),
+        "GIT_SEQUENCE_EDITOR" => env::var("GIT_SEQUENCE_EDITOR")
+            .ok()
+            .or_else(|| config.get("sequence.editor").map(str::to_string))
+            .unwrap_or_else(|| editor(config)),
+        "GIT_PAGER" => env::var("GIT_PAGER")
+            .ok()
+            .or_else(|| config.get("core.pager").map(str::to_string))
+            .or_else(|| env::var("PAGER").ok())
+            .unwrap_or_else(|| "less".to_string()),
+        _ => return Ok(None),
+    }))
+}
+
+/// The command to edit messages with
+pub fn editor(config: &Config) -> String {
+    // Full screen editors are no use on a dumb terminal
+    let dumb = env::var("TERM").map_or(true, |term| term == "dumb");
+    env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| env::var("VISUAL").ok().filter(|_| !dumb))
+        .or_else(|| env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_string())
+}
@@ -0,0 +1,245 @@
+// Glob matching following git's wildmatch rules, shared by everything that accepts path or ref
+// patterns
+
+/// Matches `text` against the glob `pattern`. `*` and `?` never match a `/`, `**` matches across
+/// directories (and `**/` also matches no directory at all), `[...]` matches a character class and
+/// `\` escapes the next character.
+pub fn wildmatch(pattern: &str, text: &str) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes(), true, true)
+}
+
+/// Matches like `wildmatch`, except that `*`, `?` and `[...]` also match a `/`, the way git
+/// matches pathspecs without the `glob` magic
+pub fn fnmatch(pattern: &str, text: &str) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes(), false, true)
+}
+
+/// Matches a worktree `path` against a pattern read from a gitignore-style file in the `base`
+/// directory (empty for the worktree root). A pattern without a slash matches the file name at any
+/// depth below `base`, otherwise it is anchored to `base`.
+pub fn path_matches(pattern: &str, base: &str, path: &str) -> bool {
+    let relative = if base.is_empty() {
+        path
+    } else {
+        match path
+            .strip_prefix(base)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(relative) => relative,
+            None => return false,
+        }
+    };
+
+    match pattern.strip_prefix('/') {
+        Some(anchored) => wildmatch(anchored, relative),
+        None if pattern.contains('/') => wildmatch(pattern, relative),
+        None => wildmatch(pattern, relative.rsplit('/').next().unwrap_or(relative)),
+    }
+}
+
+// Unless `pathname` is set, wildcards match slashes like any other character. `boundary` is
+// whether `pattern` starts a path component, which `**` has to for it to match across them.
+fn matches(pattern: &[u8], text: &[u8], pathname: bool, boundary: bool) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) if pathname && rest.first() == Some(&b'*') => {
+            let rest = &rest[rest.iter().take_while(|&&b| b == b'*').count()..];
+            // Elsewhere `**` is just `*`
+            if !boundary || !matches!(rest.first(), None | Some(b'/')) {
+                return matches(&pattern[1..], text, pathname, false);
+            }
+            if rest.first() == Some(&b'/') && matches(&rest[1..], text, pathname, true) {
+                return true;
+            }
+            (0..=text.len()).any(|i| matches(rest, &text[i..], pathname, false))
+        }
+        Some((b'*', rest)) => {
+            let rest = &rest[rest.iter().take_while(|&&b| b == b'*').count()..];
+            (0..=text.len())
+                .take_while(|&i| i == 0 || !pathname || text[i - 1] != b'/')
+                .any(|i| matches(rest, &text[i..], pathname, false))
+        }
+        Some((b'?', rest)) => match text.split_first() {
+            Some((&ch, text_rest)) if !pathname || ch != b'/' => {
+                matches(rest, text_rest, pathname, false)
+            }
+            _ => false,
+        },
+        Some((b'[', rest)) => match text.split_first() {
+            Some((&ch, text_rest)) if !pathname || ch != b'/' => match match_class(rest, ch) {
+                Some((true, after)) => matches(after, text_rest, pathname, false),
+                _ => false,
+            },
+            _ => false,
+        },
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && matches(&rest[1..], &text[1..], pathname, false)
+        }
+        Some((&expected, rest)) => {
+            text.first() == Some(&expected) && matches(rest, &text[1..], pathname, expected == b'/')
+        }
+    }
+}
+
+// Matches `ch` against the character class at the start of `pattern` (just after the `[`),
+// returning whether it matched along with the rest of the pattern after the closing `]`. Returns
+// None if the class is never closed.
+fn match_class(pattern: &[u8], ch: u8) -> Option<(bool, &[u8])> {
+    let (negated, mut pos) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(pos)?;
+        // A `]` right after the opening bracket is a literal
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if start == b'\\' {
+            pos += 1;
+            start = *pattern.get(pos)?;
+        } else if start == b'[' && pattern.get(pos + 1) == Some(&b':') {
+            let name = &pattern[pos + 2..];
+            if let Some(end) = name.windows(2).position(|pair| pair == b":]") {
+                matched |= class(&name[..end])?(&ch);
+                pos += end + 4;
+                continue;
+            }
+        }
+
+        match (pattern.get(pos + 1), pattern.get(pos + 2)) {
+            (Some(b'-'), Some(&end)) if end != b']' => {
+                matched |= (start..=end).contains(&ch);
+                pos += 3;
+            }
+            _ => {
+                matched |= start == ch;
+                pos += 1;
+            }
+        }
+    }
+
+    Some((matched != negated, &pattern[pos + 1..]))
+}
+
+// The test for a named class like `[:alpha:]`, or None if there is no such class, in which case
+// nothing matches the pattern
+fn class(name: &[u8]) -> Option<fn(&u8) -> bool> {
+    Some(match name {
+        b"alnum" => u8::is_ascii_alphanumeric,
+        b"alpha" => u8::is_ascii_alphabetic,
+        b"blank" => |ch| *ch == b' ' || *ch == b'\t',
+        b"cntrl" => u8::is_ascii_control,
+        b"digit" => u8::is_ascii_digit,
+        b"graph" => u8::is_ascii_graphic,
+        b"lower" => u8::is_ascii_lowercase,
+        b"print" => |ch| (0x20..0x7f).contains(ch),
+        b"punct" => u8::is_ascii_punctuation,
+        b"space" => |ch| b" \t\n\r\x0b\x0c".contains(ch),
+        b"upper" => u8::is_ascii_uppercase,
+        b"xdigit" => u8::is_ascii_hexdigit,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cases from git's t3070-wildmatch.sh
+    #[test]
+    fn wildcards() {
+        for (pattern, text, expected) in [
+            ("foo", "foo", true),
+            ("bar", "foo", false),
+            ("", "", true),
+            ("???", "foo", true),
+            ("??", "foo", false),
+            ("*", "foo", true),
+            ("f*", "foo", true),
+            ("*f", "foo", false),
+            ("*foo*", "foo", true),
+            ("*ob*a*r*", "foobar", true),
+            ("*ab", "aaaaaaabababab", true),
+            ("foo\\*", "foo*", true),
+            ("foo\\*bar", "foobar", false),
+            ("f\\\\oo", "f\\oo", true),
+            ("*X*i", "abcXdefXghi", true),
+        ] {
+            assert_eq!(wildmatch(pattern, text), expected, "{} {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        for (pattern, text, expected) in [
+            ("*[al]?", "ball", true),
+            ("[ten]", "ten", false),
+            ("**[!te]", "ten", true),
+            ("**[!ten]", "ten", false),
+            ("t[a-g]n", "ten", true),
+            ("t[!a-g]n", "ten", false),
+            ("t[!a-g]n", "ton", true),
+            ("t[^a-g]n", "ton", true),
+            ("a[]]b", "a]b", true),
+            ("a[]-]b", "a-b", true),
+            ("a[]-]b", "a]b", true),
+            ("a[]-]b", "aab", false),
+            ("a[]a-]b", "aab", true),
+            ("]", "]", true),
+            ("[[:alpha:]][[:digit:]][[:upper:]]", "a1B", true),
+            ("[[:digit:][:upper:][:space:]]", "a", false),
+            ("[[:xdigit:]]", "5", true),
+            ("[a-c", "a", false),
+        ] {
+            assert_eq!(wildmatch(pattern, text), expected, "{} {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn slashes() {
+        for (pattern, text, expected) in [
+            ("foo*bar", "foo/baz/bar", false),
+            ("foo**bar", "foo/baz/bar", false),
+            ("foo/**/bar", "foo/baz/bar", true),
+            ("foo/**/**/bar", "foo/baz/bar", true),
+            ("foo/**/bar", "foo/b/a/z/bar", true),
+            ("foo/**/bar", "foo/bar", true),
+            ("foo?bar", "foo/bar", false),
+            ("foo[/]bar", "foo/bar", false),
+            ("**/foo", "foo", true),
+            ("**/foo", "XXX/foo", true),
+            ("**/foo", "bar/baz/foo", true),
+            ("*/foo", "bar/baz/foo", false),
+            ("**/bar*", "foo/bar/baz", false),
+            ("**/bar/*", "deep/foo/bar/baz", true),
+            ("**/bar/*", "deep/foo/bar/baz/", false),
+            ("**/bar/**", "deep/foo/bar/baz/", true),
+            ("**/bar/*", "deep/foo/bar", false),
+            ("**/bar/**", "deep/foo/bar/", true),
+            ("*/bar/**", "foo/bar/baz/x", true),
+            ("*/*/*", "foo/bb/aa/rr", false),
+            ("**/**/**", "foo/bb/aa/rr", true),
+            ("*/*X*/*/*i", "ab/cXd/efXg/hi", true),
+        ] {
+            assert_eq!(wildmatch(pattern, text), expected, "{} {}", pattern, text);
+        }
+        assert!(fnmatch("foo*bar", "foo/baz/bar"));
+        assert!(fnmatch("foo?bar", "foo/bar"));
+    }
+
+    #[test]
+    fn paths() {
+        assert!(path_matches("foo", "", "a/b/foo"));
+        assert!(path_matches("/foo", "", "foo"));
+        assert!(!path_matches("/foo", "", "a/foo"));
+        assert!(path_matches("b/*.c", "a", "a/b/x.c"));
+        assert!(!path_matches("b/*.c", "a", "a/c/b/x.c"));
+        assert!(!path_matches("x.c", "a", "b/x.c"));
+    }
+}
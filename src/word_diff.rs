@@ -0,0 +1,233 @@
+// Shows the changes in a hunk word by word, as git's --word-diff does. The lines removed and
+// added between two unchanged lines are collected, split into words (the matches of a word regex,
+// or else runs of non-whitespace, never spanning lines) and the words compared as if each were
+// a line. The text in common is then shown from the new side, with the words in between marked
+// up as removed or added: in brackets, in color, or on lines of their own for porcelain.
+use std::ops::Range;
+
+use crate::line_diff::{self, DiffOptions};
+use crate::patch::{NEW_COLOR, OLD_COLOR, RESET};
+use crate::regex::Regex;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    /// [-removed-]{+added+}
+    Plain,
+    /// Removed words in red, added ones in green
+    Color,
+    /// A line for each run of words, starting with `-`, `+` or a space, and `~` for newlines
+    Porcelain,
+}
+
+// How one kind of text is marked up
+struct Markup {
+    prefix: &'static str,
+    suffix: &'static str,
+    color: &'static str,
+}
+
+impl Style {
+    // The markup for removed, added and unchanged text, and what is written for newlines
+    fn markup(self) -> ([Markup; 3], &'static str) {
+        let markup = |prefix, suffix, color| Markup {
+            prefix,
+            suffix,
+            color,
+        };
+        match self {
+            Style::Plain => (
+                [
+                    markup("[-", "-]", ""),
+                    markup("{+", "+}", ""),
+                    markup("", "", ""),
+                ],
+                "\n",
+            ),
+            Style::Color => (
+                [
+                    markup("", "", OLD_COLOR),
+                    markup("", "", NEW_COLOR),
+                    markup("", "", ""),
+                ],
+                "\n",
+            ),
+            Style::Porcelain => (
+                [
+                    markup("-", "\n", ""),
+                    markup("+", "\n", ""),
+                    markup(" ", "\n", ""),
+                ],
+                "~\n",
+            ),
+        }
+    }
+}
+
+/// Writes the lines of a hunk word by word, holding on to removed and added lines until the
+/// next unchanged one or the end of the hunk
+pub struct WordDiff<'a> {
+    style: Style,
+    regex: Option<&'a Regex>,
+    removed: Vec<u8>,
+    added: Vec<u8>,
+}
+
+impl<'a> WordDiff<'a> {
+    pub fn new(style: Style, regex: Option<&'a Regex>) -> Self {
+        Self {
+            style,
+            regex,
+            removed: Vec::new(),
+            added: Vec::new(),
+        }
+    }
+
+    pub fn remove(&mut self, line: &[u8]) {
+        push_line(&mut self.removed, line);
+    }
+
+    pub fn add(&mut self, line: &[u8]) {
+        push_line(&mut self.added, line);
+    }
+
+    /// Writes an unchanged line, after the changes before it
+    pub fn context(&mut self, out: &mut Vec<u8>, line: &[u8]) {
+        self.flush(out);
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        match self.style {
+            Style::Plain => out.extend_from_slice(line),
+            Style::Color => {
+                let content = line.strip_suffix(b"\r").unwrap_or(line);
+                if !content.is_empty() {
+                    out.extend_from_slice(content);
+                    out.extend_from_slice(RESET.as_bytes());
+                }
+                out.extend_from_slice(&line[content.len()..]);
+            }
+            Style::Porcelain => {
+                out.push(b' ');
+                out.extend_from_slice(line);
+                out.extend_from_slice(b"\n~");
+            }
+        }
+        out.push(b'\n');
+    }
+
+    /// Writes the changes collected since the last unchanged line
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        if self.removed.is_empty() && self.added.is_empty() {
+            return;
+        }
+        let ([old, new, context], newline) = self.style.markup();
+        let (removed, added) = (
+            std::mem::take(&mut self.removed),
+            std::mem::take(&mut self.added),
+        );
+        if added.is_empty() {
+            write(out, &old, newline, &removed);
+            return;
+        }
+
+        let (old_words, new_words) = (words(&removed, self.regex), words(&added, self.regex));
+        let old_tokens: Vec<&[u8]> = old_words
+            .iter()
+            .map(|word| &removed[word.clone()])
+            .collect();
+        let new_tokens: Vec<&[u8]> = new_words.iter().map(|word| &added[word.clone()]).collect();
+        let regions = line_diff::diff_lines(&old_tokens, &new_tokens, &DiffOptions::default());
+        // Where the words of a region start and end in the text, an empty region being at the
+        // end of the word before it
+        let span = |words: &[Range<usize>], region: &Range<usize>| match region.clone() {
+            empty if empty.is_empty() => {
+                let end = empty.start.checked_sub(1).map_or(0, |i| words[i].end);
+                end..end
+            }
+            region => words[region.start].start..words[region.end - 1].end,
+        };
+        let mut position = 0;
+        for region in regions {
+            let (old_span, new_span) =
+                (span(&old_words, &region.old), span(&new_words, &region.new));
+            if position != new_span.start {
+                write(out, &context, newline, &added[position..new_span.start]);
+            }
+            if !old_span.is_empty() {
+                write(out, &old, newline, &removed[old_span]);
+            }
+            if !new_span.is_empty() {
+                write(out, &new, newline, &added[new_span.clone()]);
+            }
+            position = new_span.end;
+        }
+        if position != added.len() {
+            write(out, &context, newline, &added[position..]);
+        }
+    }
+}
+
+// Every line in a patch ends with a newline, even the last one in a file without it
+fn push_line(text: &mut Vec<u8>, line: &[u8]) {
+    text.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        text.push(b'\n');
+    }
+}
+
+// Writes `text` with each line's content marked up, and its newlines as `newline`
+fn write(out: &mut Vec<u8>, markup: &Markup, newline: &str, text: &[u8]) {
+    for (i, line) in text.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            out.extend_from_slice(newline.as_bytes());
+        }
+        if line.is_empty() {
+            continue;
+        }
+        out.extend_from_slice(markup.color.as_bytes());
+        out.extend_from_slice(markup.prefix.as_bytes());
+        out.extend_from_slice(line);
+        out.extend_from_slice(markup.suffix.as_bytes());
+        if !markup.color.is_empty() {
+            out.extend_from_slice(RESET.as_bytes());
+        }
+    }
+}
+
+// Splits `text` into its words
+fn words(text: &[u8], regex: Option<&Regex>) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut position = 0;
+    while let Some(word) = next_word(text, position, regex) {
+        position = word.end;
+        words.push(word);
+    }
+    words
+}
+
+// Finds the first word at or after `start`. A regex matching nothing, or only a newline, is
+// tried again a byte further on.
+fn next_word(text: &[u8], mut start: usize, regex: Option<&Regex>) -> Option<Range<usize>> {
+    if let Some(regex) = regex {
+        while start < text.len() {
+            let found = regex.find(&text[start..])?;
+            let begin = start + found.start;
+            let end = text[begin..start + found.end]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(start + found.end, |newline| begin + newline);
+            if begin != end {
+                return Some(begin..end);
+            }
+            start = begin + 1;
+        }
+    }
+    let begin = start
+        + text
+            .get(start..)?
+            .iter()
+            .position(|b| !line_diff::is_space(b))?;
+    let end = text[begin..]
+        .iter()
+        .position(line_diff::is_space)
+        .map_or(text.len(), |length| begin + length);
+    Some(begin..end)
+}
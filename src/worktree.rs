@@ -0,0 +1,239 @@
+// Manages linked worktrees, which let several branches of the same repository be checked out at
+// once. Each linked worktree has a private gitdir under `.gitrs/worktrees/<name>` holding its
+// HEAD, and shares everything else with the main worktree through the `commondir` pointer.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, anyhow, bail, ensure};
+
+use crate::branch;
+use crate::object::GitrsObject;
+use crate::object::tree::Tree;
+use crate::refs::Ref;
+use crate::repository::{self, Repository};
+
+pub struct Worktree {
+    // `None` for the main worktree
+    pub name: Option<String>,
+    pub path: PathBuf,
+    // Raw contents of the worktree's HEAD file
+    pub head: String,
+}
+
+impl Worktree {
+    /// Create a new worktree at `path` with `commit` checked out. If `commit` names a local branch,
+    /// the worktree's HEAD is attached to it, otherwise it is detached.
+    pub fn add(
+        repository: &Repository,
+        path: &Path,
+        commit: &str,
+        new_branch: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        ensure!(
+            !path.exists() || repository::is_empty_dir(path),
+            "'{}' already exists",
+            path.display()
+        );
+
+        let hash = GitrsObject::find(repository, commit)?;
+        let head = match new_branch {
+            Some(branch) => {
+                branch::verify_name(branch)?;
+                ensure!(
+                    Ref::try_resolve(repository, &format!("refs/heads/{}", branch))?.is_none(),
+                    "A branch named '{}' already exists",
+                    branch
+                );
+                Ref::create_at(repository, &hash, &["refs", "heads", branch])?;
+                format!("ref: refs/heads/{}", branch)
+            }
+            None if Ref::try_resolve(repository, &format!("refs/heads/{}", commit))?.is_some() => {
+                format!("ref: refs/heads/{}", commit)
+            }
+            None => hash.clone(),
+        };
+
+        if let Some(target) = head.strip_prefix("ref: ")
+            && let Some(other) = Self::list(repository)?.iter().find(|wt| wt.head == head)
+        {
+            bail!(
+                "'{}' is already checked out at '{}'",
+                target,
+                other.path.display()
+            );
+        }
+
+        fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create the path {}", path.display()))?;
+        let path = fs::canonicalize(path)?;
+        let name = Self::unique_name(repository, &path)?;
+        let admin_dir = fs::canonicalize(&repository.commondir)?
+            .join("worktrees")
+            .join(&name);
+
+        for (file, content) in [
+            ("HEAD", head.as_str()),
+            ("commondir", "../.."),
+            ("gitdir", &path.join(".gitrs").to_string_lossy()),
+        ] {
+            let file_path = repository
+                .create_file(&["worktrees", &name, file])
+                .with_context(|| format!("Couldn't create file at worktrees/{}/{}", name, file))?;
+            fs::write(file_path, format!("{}\n", content))?;
+        }
+        fs::write(
+            path.join(".gitrs"),
+            format!("gitdir: {}\n", admin_dir.display()),
+        )?;
+
+        Tree::of_commit(repository, &hash)?.checkout(repository, &path)?;
+
+        Ok(Self {
+            name: Some(name),
+            path,
+            head,
+        })
+    }
+
+    /// List the main worktree followed by all linked worktrees
+    pub fn list(repository: &Repository) -> anyhow::Result<Vec<Self>> {
+        let commondir = fs::canonicalize(&repository.commondir)?;
+        let main_path = commondir
+            .parent()
+            .ok_or_else(|| anyhow!("Repository has no worktree"))?
+            .to_path_buf();
+
+        let mut worktrees = vec![Self {
+            name: None,
+            head: read_trimmed(&commondir.join("HEAD"))?,
+            path: main_path,
+        }];
+
+        let mut names = Self::linked_names(repository)?;
+        names.sort();
+        for name in names {
+            let admin_dir = commondir.join("worktrees").join(&name);
+            let gitdir = PathBuf::from(read_trimmed(&admin_dir.join("gitdir"))?);
+            worktrees.push(Self {
+                name: Some(name),
+                head: read_trimmed(&admin_dir.join("HEAD"))?,
+                path: gitdir.parent().map(Path::to_path_buf).unwrap_or(gitdir),
+            });
+        }
+
+        Ok(worktrees)
+    }
+
+    /// Delete a linked worktree and its administrative files. Unless `force` is set, the worktree
+    /// must match the commit it has checked out.
+    pub fn remove(repository: &Repository, path: &Path, force: bool) -> anyhow::Result<()> {
+        let path = fs::canonicalize(path)
+            .with_context(|| format!("'{}' is not a worktree", path.display()))?;
+        let worktree = Self::list(repository)?
+            .into_iter()
+            .find(|wt| wt.path == path)
+            .ok_or_else(|| anyhow!("'{}' is not a worktree", path.display()))?;
+        let Some(name) = worktree.name.as_deref() else {
+            bail!("'{}' is a main worktree", path.display());
+        };
+
+        if !force {
+            let tree_obj = Tree::of_commit(repository, &worktree.resolve_head(repository)?)?;
+            ensure!(
+                tree_obj.matches_dir(repository, &path)?,
+                "'{}' contains modified or untracked files, use --force to delete it",
+                path.display()
+            );
+        }
+
+        fs::remove_dir_all(&path)
+            .with_context(|| format!("Failed to delete {}", path.display()))?;
+        Self::remove_admin_dir(repository, name)
+    }
+
+    /// Remove the administrative files of linked worktrees whose directory no longer exists,
+    /// returning the names of the pruned worktrees along with why they were pruned. Locked
+    /// worktrees are kept, and with `dry_run` nothing is removed.
+    pub fn prune(repository: &Repository, dry_run: bool) -> anyhow::Result<Vec<(String, String)>> {
+        let mut pruned = Vec::new();
+        for name in Self::linked_names(repository)? {
+            if repository
+                .get_path_to_file(&["worktrees", &name, "locked"])
+                .is_some()
+            {
+                continue;
+            }
+            let gitdir_file = repository
+                .get_path_to_file(&["worktrees", &name, "gitdir"])
+                .map(|path| read_trimmed(&path))
+                .transpose()?;
+
+            let reason = match gitdir_file {
+                None => "gitdir file does not exist",
+                Some(gitdir) if gitdir.is_empty() => "invalid gitdir file",
+                Some(gitdir) if !Path::new(&gitdir).exists() => {
+                    "gitdir file points to non-existent location"
+                }
+                Some(_) => continue,
+            };
+            if !dry_run {
+                Self::remove_admin_dir(repository, &name)?;
+            }
+            pruned.push((name, reason.to_string()));
+        }
+
+        Ok(pruned)
+    }
+
+    /// Resolves the worktree's HEAD to a commit hash
+    pub fn resolve_head(&self, repository: &Repository) -> anyhow::Result<String> {
+        match self.head.strip_prefix("ref: ") {
+            Some(target) => Ref::resolve(repository, &target.split('/').collect::<Vec<_>>()),
+            None => Ok(self.head.clone()),
+        }
+    }
+
+    // Names of the worktrees with administrative files under `worktrees`
+    fn linked_names(repository: &Repository) -> anyhow::Result<Vec<String>> {
+        let Some(dir) = repository.get_path_to_dir(&["worktrees"]) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    // Derives the worktree name from the last component of its path, appending a number if it is
+    // already taken
+    fn unique_name(repository: &Repository, path: &Path) -> anyhow::Result<String> {
+        let base = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid worktree path: {}", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+
+        let taken = Self::linked_names(repository)?;
+        Ok(std::iter::once(base.clone())
+            .chain((1..).map(|i| format!("{}{}", base, i)))
+            .find(|name| !taken.contains(name))
+            .expect("Expected an unused worktree name"))
+    }
+
+    fn remove_admin_dir(repository: &Repository, name: &str) -> anyhow::Result<()> {
+        if let Some(dir) = repository.get_path_to_dir(&["worktrees", name]) {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to delete {}", dir.display()))?;
+        }
+        Ok(())
+    }
+}
+
+fn read_trimmed(path: &Path) -> anyhow::Result<String> {
+    Ok(fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?
+        .trim()
+        .to_string())
+}